@@ -0,0 +1,328 @@
+//! Attachments uploaded to a room's server.
+//!
+//! An attachment is encrypted before it leaves the client, with a key of its
+//! own: a fresh seed drives a [`SymmetricRatchet`], as for sender keys, and
+//! each chunk of [`ATTACHMENT_CHUNK_SIZE`] bytes is sealed with the next
+//! message key. Every key seals one chunk, so the nonce needs no randomness
+//! and the ciphertext is a pure function of the seed and the plaintext.
+//!
+//! The server stores the ciphertext under its SHA-256 hash and never sees the
+//! key. Once the upload is complete the application shares the
+//! [`AttachmentKey`] with the room in an ordinary encrypted message; members
+//! download the chunks a window at a time and open them with
+//! [`decrypt_attachment`].
+//!
+//! Uploads are resumed after a reconnect: the client announces each
+//! unfinished upload again and continues from the chunk the server asks for.
+
+use std::collections::HashMap;
+
+use lockframe_crypto::{
+    CIPHER_SUITE_XCHACHA20_POLY1305, EncryptedMessage as CryptoEncryptedMessage, PADDING_NONE,
+    SymmetricRatchet, decrypt_message, encrypt_message,
+};
+use lockframe_proto::payloads::{
+    app::EncryptedMessage,
+    attachment::{AttachmentChunk, AttachmentFetch, AttachmentInit},
+};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::error::ClientError;
+
+/// Plaintext bytes sealed per chunk.
+pub const ATTACHMENT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Everything a member needs to open an uploaded attachment.
+///
+/// The seed is zeroized when the key is dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct AttachmentKey {
+    /// SHA-256 hash of the ciphertext, under which the server stores it
+    #[zeroize(skip)]
+    pub content_hash: [u8; 32],
+    /// Seed of the ratchet that sealed the chunks
+    pub seed: [u8; 32],
+    /// Size of every sealed chunk but the last
+    #[zeroize(skip)]
+    pub chunk_size: u32,
+}
+
+/// Seal `plaintext` chunk by chunk under `seed`.
+fn encrypt_chunks(plaintext: &[u8], seed: &[u8; 32]) -> Result<Vec<Vec<u8>>, ClientError> {
+    let mut ratchet = SymmetricRatchet::new(seed);
+    plaintext
+        .chunks(ATTACHMENT_CHUNK_SIZE)
+        .map(|chunk| {
+            let key = ratchet.advance()?;
+            Ok(encrypt_message(chunk, &key, 0, 0, [0; 8]).ciphertext)
+        })
+        .collect()
+}
+
+/// Open an attachment sealed under `key`.
+///
+/// # Errors
+///
+/// - `InvalidFrame`: the ciphertext doesn't match the key's hash
+/// - `SenderKey`: a chunk fails authentication
+pub fn decrypt_attachment(key: &AttachmentKey, ciphertext: &[u8]) -> Result<Vec<u8>, ClientError> {
+    if Sha256::digest(ciphertext).as_slice() != key.content_hash {
+        return Err(ClientError::InvalidFrame {
+            reason: "attachment does not match its content hash".to_string(),
+        });
+    }
+
+    let mut ratchet = SymmetricRatchet::new(&key.seed);
+    let chunk_size = usize::try_from(key.chunk_size).unwrap_or(usize::MAX);
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+    for chunk in ciphertext.chunks(chunk_size) {
+        let message_key = ratchet.advance()?;
+        let sealed = CryptoEncryptedMessage {
+            epoch: 0,
+            sender_index: 0,
+            generation: message_key.generation(),
+            padding: PADDING_NONE,
+            cipher_suite: CIPHER_SUITE_XCHACHA20_POLY1305,
+            nonce: chunk_nonce(message_key.generation()),
+            ciphertext: chunk.to_vec(),
+        };
+        plaintext.extend(decrypt_message(&sealed, &message_key)?);
+    }
+    Ok(plaintext)
+}
+
+/// Nonce `encrypt_message` builds for the chunk sealed at `generation`.
+fn chunk_nonce(generation: u32) -> [u8; 24] {
+    let mut nonce = [0; 24];
+    if let Some(field) = nonce.get_mut(12..16) {
+        field.copy_from_slice(&generation.to_be_bytes());
+    }
+    nonce
+}
+
+/// An unfinished upload.
+#[derive(Debug)]
+struct Upload {
+    key: AttachmentKey,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl Upload {
+    fn init(&self) -> AttachmentInit {
+        let total_size =
+            self.chunks.iter().map(|chunk| u64::try_from(chunk.len()).unwrap_or(u64::MAX)).sum();
+        AttachmentInit {
+            content_hash: self.key.content_hash,
+            total_size,
+            chunk_size: self.key.chunk_size,
+        }
+    }
+}
+
+/// Our unfinished uploads to one room.
+#[derive(Debug, Default)]
+pub struct Uploads {
+    /// Content hash → upload
+    pending: HashMap<[u8; 32], Upload>,
+}
+
+impl Uploads {
+    /// Seal `plaintext` under `seed` and keep it until the server has it.
+    ///
+    /// Returns the frame payload announcing the upload.
+    pub fn start(
+        &mut self,
+        plaintext: &[u8],
+        seed: [u8; 32],
+    ) -> Result<AttachmentInit, ClientError> {
+        let chunks = encrypt_chunks(plaintext, &seed)?;
+        let mut hasher = Sha256::new();
+        for chunk in &chunks {
+            hasher.update(chunk);
+        }
+
+        let chunk_size = ATTACHMENT_CHUNK_SIZE.saturating_add(EncryptedMessage::TAG_SIZE);
+        let key = AttachmentKey {
+            content_hash: hasher.finalize().into(),
+            seed,
+            chunk_size: u32::try_from(chunk_size).unwrap_or(u32::MAX),
+        };
+        let content_hash = key.content_hash;
+        let upload = Upload { key, chunks };
+        let init = upload.init();
+        self.pending.insert(content_hash, upload);
+        Ok(init)
+    }
+
+    /// Chunks of an upload from `next_chunk` on, `None` if it isn't ours.
+    pub fn chunks_from(
+        &self,
+        content_hash: &[u8; 32],
+        next_chunk: u32,
+    ) -> Option<Vec<AttachmentChunk>> {
+        let upload = self.pending.get(content_hash)?;
+        let skip = usize::try_from(next_chunk).unwrap_or(usize::MAX);
+        Some(
+            (next_chunk..)
+                .zip(upload.chunks.iter().skip(skip))
+                .map(|(index, data)| AttachmentChunk {
+                    content_hash: *content_hash,
+                    index,
+                    data: data.clone(),
+                })
+                .collect(),
+        )
+    }
+
+    /// Forget a finished upload, returning its key.
+    pub fn finish(&mut self, content_hash: &[u8; 32]) -> Option<AttachmentKey> {
+        self.pending.remove(content_hash).map(|upload| upload.key)
+    }
+
+    /// Announcements of every unfinished upload, to resume them.
+    pub fn resume(&self) -> impl Iterator<Item = AttachmentInit> + '_ {
+        self.pending.values().map(Upload::init)
+    }
+}
+
+/// An unfinished download.
+#[derive(Debug)]
+struct Download {
+    key: AttachmentKey,
+    ciphertext: Vec<u8>,
+    next_chunk: u32,
+}
+
+/// Where a download stands once a window of chunks has arrived.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DownloadProgress {
+    /// Not one of our downloads
+    Unknown,
+    /// Chunks are still missing and this asks for the next window
+    More(AttachmentFetch),
+    /// Every chunk arrived; the opened attachment
+    Done(Vec<u8>),
+}
+
+/// Our unfinished downloads from one room.
+#[derive(Debug, Default)]
+pub struct Downloads {
+    /// Content hash → download
+    pending: HashMap<[u8; 32], Download>,
+}
+
+impl Downloads {
+    /// Start downloading the attachment sealed under `key`.
+    ///
+    /// Returns the frame payload asking for the first window of chunks.
+    pub fn start(&mut self, key: AttachmentKey) -> AttachmentFetch {
+        let content_hash = key.content_hash;
+        let download = Download { key, ciphertext: Vec::new(), next_chunk: 0 };
+        self.pending.insert(content_hash, download);
+        AttachmentFetch { content_hash, from_chunk: 0 }
+    }
+
+    /// Append a downloaded chunk, ignoring chunks we didn't ask for or
+    /// already have.
+    pub fn chunk(&mut self, chunk: &AttachmentChunk) {
+        let Some(download) = self.pending.get_mut(&chunk.content_hash) else {
+            return;
+        };
+        if chunk.index == download.next_chunk {
+            download.ciphertext.extend_from_slice(&chunk.data);
+            download.next_chunk = download.next_chunk.saturating_add(1);
+        }
+    }
+
+    /// Handle the status ending a window of chunks, `chunk_count` being the
+    /// number of chunks the attachment is stored as.
+    ///
+    /// A download that fails to open is dropped.
+    pub fn window_end(
+        &mut self,
+        content_hash: &[u8; 32],
+        chunk_count: u32,
+    ) -> Result<DownloadProgress, ClientError> {
+        let Some(download) = self.pending.get(content_hash) else {
+            return Ok(DownloadProgress::Unknown);
+        };
+        if download.next_chunk < chunk_count {
+            let from_chunk = download.next_chunk;
+            return Ok(DownloadProgress::More(AttachmentFetch {
+                content_hash: *content_hash,
+                from_chunk,
+            }));
+        }
+
+        let Some(download) = self.pending.remove(content_hash) else {
+            return Ok(DownloadProgress::Unknown);
+        };
+        decrypt_attachment(&download.key, &download.ciphertext).map(DownloadProgress::Done)
+    }
+
+    /// Requests for every unfinished download, to resume them.
+    pub fn resume(&self) -> impl Iterator<Item = AttachmentFetch> + '_ {
+        self.pending.iter().map(|(content_hash, download)| AttachmentFetch {
+            content_hash: *content_hash,
+            from_chunk: download.next_chunk,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_chunks_open_with_the_key() {
+        let plaintext: Vec<u8> = (0..ATTACHMENT_CHUNK_SIZE * 2 + 5).map(|i| i as u8).collect();
+        let mut uploads = Uploads::default();
+        let init = uploads.start(&plaintext, [7; 32]).unwrap();
+
+        let chunks = uploads.chunks_from(&init.content_hash, 1).unwrap();
+        assert_eq!(chunks.iter().map(|chunk| chunk.index).collect::<Vec<_>>(), vec![1, 2]);
+
+        let ciphertext: Vec<u8> = uploads
+            .chunks_from(&init.content_hash, 0)
+            .unwrap()
+            .into_iter()
+            .flat_map(|chunk| chunk.data)
+            .collect();
+        assert_eq!(ciphertext.len() as u64, init.total_size);
+
+        let key = uploads.finish(&init.content_hash).unwrap();
+        assert_eq!(decrypt_attachment(&key, &ciphertext).unwrap(), plaintext);
+
+        let mut tampered = ciphertext;
+        tampered[0] ^= 1;
+        assert!(decrypt_attachment(&key, &tampered).is_err());
+        assert_eq!(uploads.resume().count(), 0);
+    }
+
+    #[test]
+    fn downloads_continue_until_every_chunk_arrived() {
+        let plaintext: Vec<u8> = (0..ATTACHMENT_CHUNK_SIZE + 5).map(|i| i as u8).collect();
+        let mut uploads = Uploads::default();
+        let init = uploads.start(&plaintext, [3; 32]).unwrap();
+        let chunks = uploads.chunks_from(&init.content_hash, 0).unwrap();
+        let key = uploads.finish(&init.content_hash).unwrap();
+
+        let mut downloads = Downloads::default();
+        assert_eq!(downloads.start(key.clone()).from_chunk, 0);
+
+        // A window of one chunk, then one repeated
+        downloads.chunk(&chunks[0]);
+        downloads.chunk(&chunks[0]);
+        let progress = downloads.window_end(&key.content_hash, 2).unwrap();
+        assert!(matches!(progress, DownloadProgress::More(fetch) if fetch.from_chunk == 1));
+        assert_eq!(downloads.resume().next().map(|fetch| fetch.from_chunk), Some(1));
+
+        downloads.chunk(&chunks[1]);
+        let progress = downloads.window_end(&key.content_hash, 2).unwrap();
+        assert_eq!(progress, DownloadProgress::Done(plaintext));
+        assert_eq!(downloads.resume().count(), 0);
+        let progress = downloads.window_end(&key.content_hash, 2).unwrap();
+        assert_eq!(progress, DownloadProgress::Unknown);
+    }
+}
@@ -0,0 +1,321 @@
+//! Epoch fast-forward and lazy backfill.
+//!
+//! A client many epochs behind syncs in [`SyncMode::EpochChanges`] and
+//! applies only the handshake frames until it reaches the server's epoch.
+//! The application messages it skipped are fetched afterwards, one page per
+//! [`ClientEvent::Backfill`]. Opening them needs the sender keys and roster
+//! of the epoch they were sent in, so the keys of the epochs passed during
+//! a fast-forward are kept until the backfill is done.
+//!
+//! Kept keys weaken forward secrecy: whoever takes them can read the
+//! messages they open. Only the last [`MAX_RETAINED_EPOCHS`] epochs are
+//! kept, for at most [`RETAINED_KEYS_TTL`]; a backfill not done by then is
+//! abandoned and its keys wiped. Retained keys are exported with the client,
+//! so a client restarted mid-backfill carries on where it stopped; the time
+//! spent stopped counts towards the TTL.
+//!
+//! [`SyncMode::EpochChanges`]: lockframe_proto::payloads::session::SyncMode::EpochChanges
+//! [`ClientEvent::Backfill`]: crate::ClientEvent::Backfill
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use lockframe_core::mls::MlsGroupState;
+
+use crate::{
+    error::ClientError,
+    persistence::{PersistedBackfill, PersistedEpochKeys},
+    sender_key_store::SenderKeyStore,
+};
+
+/// Epochs a client must be behind before it fast-forwards.
+pub const FAST_FORWARD_EPOCHS: u64 = 4;
+
+/// Most past epochs whose keys are kept for backfill. Older epochs are
+/// wiped first; their messages can no longer be opened.
+pub const MAX_RETAINED_EPOCHS: usize = 8;
+
+/// How long keys are kept for a backfill, from the first epoch retained.
+pub const RETAINED_KEYS_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// What it takes to open application messages of a past epoch.
+pub struct EpochKeys {
+    /// Sender key ratchets of the epoch.
+    pub sender_keys: SenderKeyStore,
+    /// Member signature keys of the epoch, for header validation.
+    pub validation: MlsGroupState,
+    /// Member ID by leaf index in the epoch.
+    pub members: HashMap<u32, u64>,
+}
+
+/// Fast-forward and backfill progress of one room.
+#[derive(Default)]
+pub struct Backfill {
+    /// Log index the running fast-forward started at.
+    fast_forward_from: Option<u64>,
+    /// Skipped log range not yet backfilled.
+    pending: Option<Range<u64>>,
+    /// Whether a backfill page was requested and has not arrived yet.
+    in_flight: bool,
+    /// Keys of epochs passed during fast-forwards, by epoch.
+    epochs: BTreeMap<u64, EpochKeys>,
+    /// When the oldest keys still kept were retained.
+    retained_at: Option<Instant>,
+}
+
+impl Backfill {
+    /// Start fast-forwarding from `from_log_index`.
+    pub fn start(&mut self, from_log_index: u64) {
+        self.fast_forward_from.get_or_insert(from_log_index);
+    }
+
+    /// Whether a fast-forward is running.
+    pub fn is_fast_forwarding(&self) -> bool {
+        self.fast_forward_from.is_some()
+    }
+
+    /// Keep the keys of `epoch`, which the room is moving past at `now`.
+    pub fn retain(&mut self, epoch: u64, keys: EpochKeys, now: Instant) {
+        self.retained_at.get_or_insert(now);
+        self.epochs.insert(epoch, keys);
+        while self.epochs.len() > MAX_RETAINED_EPOCHS {
+            if let Some((_, mut keys)) = self.epochs.pop_first() {
+                keys.sender_keys.purge();
+            }
+        }
+    }
+
+    /// Abandon a backfill whose keys were kept longer than
+    /// [`RETAINED_KEYS_TTL`] by `now`, wiping them.
+    ///
+    /// Returns whether it was abandoned.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let expired = self.retained_at.is_some_and(|retained_at| {
+            now.saturating_duration_since(retained_at) > RETAINED_KEYS_TTL
+        });
+        if expired {
+            self.abandon();
+        }
+        expired
+    }
+
+    /// Stop the fast-forward and backfill, wiping the retained keys.
+    fn abandon(&mut self) {
+        self.fast_forward_from = None;
+        self.pending = None;
+        self.in_flight = false;
+        self.purge();
+    }
+
+    /// Finish the fast-forward at `next_log_index`.
+    ///
+    /// Returns the whole range still to backfill.
+    pub fn finish(&mut self, next_log_index: u64) -> Option<Range<u64>> {
+        let from = self.fast_forward_from.take()?;
+        let start = self.pending.as_ref().map_or(from, |pending| pending.start.min(from));
+        let pending = start..next_log_index.max(start);
+        self.pending = Some(pending.clone());
+        Some(pending)
+    }
+
+    /// Log index of the next backfill page, marking it requested.
+    pub fn next_page(&mut self) -> Option<u64> {
+        let start = self.pending.as_ref()?.start;
+        self.in_flight = true;
+        Some(start)
+    }
+
+    /// Claim an arriving backfill sync response as the requested page.
+    ///
+    /// Returns the range that was pending, or `None` if no page was
+    /// requested.
+    pub fn take_page(&mut self) -> Option<Range<u64>> {
+        if !std::mem::take(&mut self.in_flight) {
+            return None;
+        }
+        self.pending.clone()
+    }
+
+    /// Record that frames before `next_log_index` were backfilled.
+    ///
+    /// Returns the range still to backfill, or `None` once the backfill is
+    /// complete, in which case the retained keys are wiped.
+    pub fn advance(&mut self, next_log_index: u64) -> Option<Range<u64>> {
+        let pending = self.pending.as_mut()?;
+        pending.start = pending.start.max(next_log_index);
+        if pending.start < pending.end {
+            return Some(pending.clone());
+        }
+
+        self.pending = None;
+        if !self.is_fast_forwarding() {
+            self.purge();
+        }
+        None
+    }
+
+    /// Wipe the sender keys of every retained epoch, e.g. on leaving the
+    /// room.
+    pub fn purge(&mut self) {
+        for keys in self.epochs.values_mut() {
+            keys.sender_keys.purge();
+        }
+        self.epochs.clear();
+        self.retained_at = None;
+    }
+
+    /// Retained keys of `epoch`.
+    pub fn keys_mut(&mut self, epoch: u64) -> Option<&mut EpochKeys> {
+        self.epochs.get_mut(&epoch)
+    }
+
+    /// Progress and retained keys as of `now`, for persisting.
+    pub fn export(&self, now: Instant) -> PersistedBackfill {
+        let epochs = self
+            .epochs
+            .iter()
+            .map(|(&epoch, keys)| {
+                let mut members: Vec<_> = keys.members.iter().map(|(&l, &m)| (l, m)).collect();
+                members.sort_unstable();
+                PersistedEpochKeys {
+                    epoch,
+                    sender_keys: keys.sender_keys.export_state(),
+                    validation: keys.validation.clone(),
+                    members,
+                }
+            })
+            .collect();
+
+        PersistedBackfill {
+            fast_forward_from: self.fast_forward_from,
+            pending: self.pending.as_ref().map(|pending| (pending.start, pending.end)),
+            epochs,
+            retained_for_millis: self.retained_at.map(|retained_at| {
+                u64::try_from(now.saturating_duration_since(retained_at).as_millis())
+                    .unwrap_or(u64::MAX)
+            }),
+        }
+    }
+
+    /// Restore progress written by [`Self::export`], resuming at `now`.
+    ///
+    /// A page requested before the export is requested again.
+    pub fn import(persisted: PersistedBackfill, now: Instant) -> Result<Self, ClientError> {
+        let mut epochs = BTreeMap::new();
+        for keys in persisted.epochs {
+            let keys_epoch = keys.epoch;
+            let epoch_keys = EpochKeys {
+                sender_keys: SenderKeyStore::import_state(keys.sender_keys)?,
+                validation: keys.validation,
+                members: keys.members.into_iter().collect(),
+            };
+            epochs.insert(keys_epoch, epoch_keys);
+        }
+
+        let retained_for = persisted.retained_for_millis.map(Duration::from_millis);
+        let mut backfill = Self {
+            fast_forward_from: persisted.fast_forward_from,
+            pending: persisted.pending.map(|(start, end)| start..end),
+            in_flight: false,
+            epochs,
+            retained_at: retained_for
+                .map(|retained_for| now.checked_sub(retained_for).unwrap_or(now)),
+        };
+        if retained_for.is_some_and(|retained_for| retained_for > RETAINED_KEYS_TTL) {
+            backfill.abandon();
+        }
+        Ok(backfill)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(epoch: u64) -> EpochKeys {
+        EpochKeys {
+            sender_keys: SenderKeyStore::initialize_epoch(&[0; 32], epoch, &[0]),
+            validation: MlsGroupState::new(1, epoch, [0; 32], vec![], vec![]),
+            members: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn exported_backfill_resumes_with_its_keys() {
+        let now = Instant::now();
+        let mut backfill = Backfill::default();
+        backfill.start(10);
+        backfill.retain(3, keys(3), now);
+        backfill.finish(50);
+        backfill.next_page();
+
+        let later = now + RETAINED_KEYS_TTL / 2;
+        let mut restored = Backfill::import(backfill.export(later), later).unwrap();
+        assert!(restored.keys_mut(3).is_some());
+        // The page in flight is requested again
+        assert_eq!(restored.next_page(), Some(10));
+        // Time retained before the export still counts
+        assert!(restored.expire(later + RETAINED_KEYS_TTL / 2 + Duration::from_secs(1)));
+
+        let too_late = now + RETAINED_KEYS_TTL * 2;
+        let mut restored = Backfill::import(backfill.export(too_late), too_late).unwrap();
+        assert!(restored.keys_mut(3).is_none());
+        assert_eq!(restored.next_page(), None);
+    }
+
+    #[test]
+    fn backfill_pages_through_skipped_range() {
+        let mut backfill = Backfill::default();
+        assert!(backfill.next_page().is_none());
+
+        backfill.start(10);
+        backfill.retain(3, keys(3), Instant::now());
+        assert!(backfill.is_fast_forwarding());
+        assert_eq!(backfill.finish(50), Some(10..50));
+        assert!(!backfill.is_fast_forwarding());
+
+        // Responses nobody asked for as a backfill page are not claimed
+        assert_eq!(backfill.take_page(), None);
+
+        assert_eq!(backfill.next_page(), Some(10));
+        assert_eq!(backfill.take_page(), Some(10..50));
+        assert_eq!(backfill.advance(30), Some(30..50));
+        assert!(backfill.keys_mut(3).is_some());
+
+        assert_eq!(backfill.next_page(), Some(30));
+        assert_eq!(backfill.take_page(), Some(30..50));
+        assert_eq!(backfill.advance(60), None);
+        assert!(backfill.keys_mut(3).is_none());
+        assert!(backfill.next_page().is_none());
+    }
+
+    #[test]
+    fn retained_epochs_are_bounded() {
+        let mut backfill = Backfill::default();
+        let now = Instant::now();
+        for epoch in 0..(MAX_RETAINED_EPOCHS as u64 + 2) {
+            backfill.retain(epoch, keys(epoch), now);
+        }
+        assert!(backfill.keys_mut(1).is_none());
+        assert!(backfill.keys_mut(2).is_some());
+    }
+
+    #[test]
+    fn backfill_is_abandoned_once_its_keys_expire() {
+        let mut backfill = Backfill::default();
+        let now = Instant::now();
+        backfill.start(10);
+        backfill.retain(3, keys(3), now);
+        backfill.finish(50);
+
+        assert!(!backfill.expire(now + RETAINED_KEYS_TTL));
+        assert!(backfill.keys_mut(3).is_some());
+
+        assert!(backfill.expire(now + RETAINED_KEYS_TTL + Duration::from_secs(1)));
+        assert!(backfill.keys_mut(3).is_none());
+        assert!(backfill.next_page().is_none());
+    }
+}
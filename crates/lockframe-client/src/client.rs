@@ -3,22 +3,68 @@
 //! The `Client` is the top-level state machine that manages multiple room
 //! memberships and orchestrates MLS operations with sender key encryption.
 
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
 
+use ed25519_dalek::{SigningKey, VerifyingKey};
 use lockframe_core::{
+    backoff::Backoff,
+    checkpoint::verify_checkpoint,
+    connection::{
+        Connection as Session, ConnectionAction, ConnectionConfig, DEFAULT_HEARTBEAT_INTERVAL,
+    },
     env::Environment,
-    mls::{MlsAction, MlsGroup, PendingJoinState, RoomId},
+    error::ConnectionError,
+    hlc::{HlcTimestamp, HybridClock},
+    ids::IdAllocator,
+    mls::{
+        DEFAULT_CIPHERSUITE, MemberId, MlsAction, MlsError, MlsGroup, MlsGroupState, MlsValidator,
+        RoomId, ValidationResult, welcome_key_package_refs,
+    },
+    rtt::RttEstimator,
+};
+use lockframe_crypto::{
+    CipherSuite, DEFAULT_MAX_GENERATION_JUMP, EncryptedMessage as CryptoEncryptedMessage,
+    NONCE_RANDOM_SIZE, Padding, PeerIdentity, fingerprint, safety_number, short_auth_string,
+    verification_payload,
 };
-use lockframe_crypto::{EncryptedMessage as CryptoEncryptedMessage, NONCE_RANDOM_SIZE};
 use lockframe_proto::{
-    Frame, FrameHeader, Opcode,
-    payloads::{app::EncryptedMessage, session::SyncResponse},
+    Capabilities, Frame, FrameFlags, FrameHeader, FrameTiming, Opcode, Payload, compression,
+    payloads::{
+        ErrorPayload,
+        app::{EncryptedMessage, ReadReceipt, Typing},
+        attachment::{AttachmentComplete, AttachmentStatus},
+        session::{
+            Checkpoint, ListRooms, ProofResponse, RevokeSessions, SyncMode, SyncResponse, TimeSync,
+        },
+    },
 };
+use zeroize::Zeroizing;
 
 use crate::{
+    attachments::{AttachmentKey, DownloadProgress, Downloads, Uploads},
+    backfill::{Backfill, EpochKeys, FAST_FORWARD_EPOCHS},
+    dedup::{MessageId, SeenMessages},
+    delivery::{DeliveryState, SequencedMessages},
+    drafts::{Drafts, Fragment, MAX_OPEN_DRAFTS},
     error::ClientError,
-    event::{ClientAction, ClientEvent, RoomStateSnapshot},
-    sender_key_store::SenderKeyStore,
+    escrow::KeyEscrow,
+    event::{ClientAction, ClientEvent, IntentOutcome, RoomStateSnapshot},
+    expiry::Expiry,
+    intents::{Intent, IntentQueue},
+    key_packages::KeyPackages,
+    latency::FrameLatency,
+    outbox::{MAX_UNSEQUENCED_MESSAGES, Outbox, Outgoing},
+    persistence::{ClientState, PersistedRoom, decode_frame, encode_frame},
+    read_state::ReadState,
+    recovery::{CommitTimeouts, Recovery, Retries},
+    sender_key_store::{DEFAULT_MAX_SKIPPED_KEYS, SenderKeyStore},
+    servers::{HOME_SERVER, Liveness, ServerId, Servers},
+    transcript::Transcript,
+    verification::{PeerVerification, VerifiedPeers},
 };
 
 /// Label for MLS secret export (domain separation).
@@ -30,9 +76,45 @@ const SENDER_KEY_CONTEXT: &[u8] = b"";
 /// Size of the sender key secret in bytes.
 const SENDER_KEY_SECRET_SIZE: usize = 32;
 
-/// Timeout for pending commits before requesting sync (30 seconds).
+/// Timeout for pending commits before requesting sync, used until an RTT
+/// sample is available (30 seconds).
 const COMMIT_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// Bounds on the RTT-derived commit timeout.
+const MIN_COMMIT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_COMMIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Commit timeout as a multiple of the RTT retry timeout. A commit needs a
+/// round trip through the sequencer plus fanout, so allow generous slack.
+const COMMIT_TIMEOUT_RTT_FACTOR: u32 = 8;
+
+/// How long the home connection may stay silent before a tick probes it
+/// with a heartbeat. Well inside the server's idle timeout, so the probe
+/// also keeps an otherwise quiet session open.
+const HEARTBEAT_INTERVAL: Duration = DEFAULT_HEARTBEAT_INTERVAL;
+
+/// Time allowed for a heartbeat's ack before the connection is taken for
+/// dead, used until an RTT sample is available (10 seconds).
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bounds on the RTT-derived heartbeat timeout.
+const MIN_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Heartbeat timeout as a multiple of the RTT retry timeout. An ack needs
+/// only one round trip, but a busy connection may queue it behind other
+/// frames.
+const HEARTBEAT_TIMEOUT_RTT_FACTOR: u32 = 4;
+
+/// Delays between attempts to reconnect to a lost server: 1 second doubling
+/// up to a minute, jittered so clients dropped together spread out.
+const RECONNECT_BACKOFF: Backoff =
+    Backoff::new(Duration::from_secs(1), Duration::from_secs(60)).jittered();
+
+/// Capabilities the client offers in its Hello: the ones it implements.
+/// Delivery receipts are not among them, so `RECEIPTS` is not offered.
+const CAPABILITIES: Capabilities = Capabilities::COMPRESSION;
+
 /// Client identity.
 ///
 /// Owns the persistent cryptographic material that identifies this client
@@ -43,12 +125,23 @@ const COMMIT_TIMEOUT: Duration = Duration::from_secs(30);
 pub struct ClientIdentity {
     /// Stable sender ID used in frame headers.
     pub sender_id: u64,
+
+    /// Key that answers a server's identity challenge during the handshake.
+    /// Servers that require identity refuse a client without one.
+    pub identity_key: Option<SigningKey>,
 }
 
 impl ClientIdentity {
     /// Create a new client identity with the given sender ID.
     pub fn new(sender_id: u64) -> Self {
-        Self { sender_id }
+        Self { sender_id, identity_key: None }
+    }
+
+    /// Prove `identity_key` to servers that challenge for it.
+    #[must_use]
+    pub fn with_identity_key(mut self, identity_key: SigningKey) -> Self {
+        self.identity_key = Some(identity_key);
+        self
     }
 }
 
@@ -62,10 +155,43 @@ struct RoomState<E: Environment> {
 
     /// Our leaf index in the MLS tree.
     my_leaf_index: u32,
-}
 
-/// State stored between KeyPackage generation and Welcome receipt.
-type PendingJoin<E> = PendingJoinState<E>;
+    /// What the server has shown us of the room's log.
+    transcript: Transcript,
+
+    /// Epoch fast-forward and backfill of skipped messages.
+    backfill: Backfill,
+
+    /// Recently delivered application messages.
+    seen: SeenMessages,
+
+    /// Messages being composed or received a chunk at a time.
+    drafts: Drafts,
+
+    /// How far each member has read.
+    read_state: ReadState,
+
+    /// Where our recent messages were sequenced, for read receipts.
+    sequenced: SequencedMessages,
+
+    /// Our attachments not yet fully uploaded.
+    uploads: Uploads,
+
+    /// Attachments we are downloading.
+    downloads: Downloads,
+
+    /// Delivered messages due to disappear.
+    expiry: Expiry,
+
+    /// Restored from a snapshot whose sending ratchet may since have run
+    /// on, so nothing is sent until a commit of ours moves the room to
+    /// fresh sender keys.
+    needs_rekey: bool,
+
+    /// Our pending commit as sent, to tell errors about it from errors about
+    /// other frames and to resend it after a backoff.
+    commit: Option<Frame>,
+}
 
 /// Client state machine.
 ///
@@ -77,9 +203,59 @@ pub struct Client<E: Environment> {
     /// Active room memberships.
     rooms: HashMap<RoomId, RoomState<E>>,
 
-    /// Pending join attempts (KeyPackage generated, waiting for Welcome).
-    /// Each entry contains the crypto state needed to decrypt a Welcome.
-    pending_joins: Vec<PendingJoin<E>>,
+    /// Pending join attempts (`KeyPackage` generated, waiting for Welcome).
+    /// Each entry contains the crypto state needed to decrypt a Welcome made
+    /// for that `KeyPackage`.
+    pending_joins: KeyPackages<E>,
+
+    /// Heartbeats and traffic per server connection.
+    liveness: HashMap<ServerId, Liveness>,
+
+    /// Hybrid logical clock for stamping outgoing frames.
+    clock: HybridClock,
+
+    /// Latency of frames that arrived with a server timing trailer.
+    latency: FrameLatency,
+
+    /// Server key that signs log checkpoints. Transcript verification is off
+    /// until one is set.
+    checkpoint_key: Option<VerifyingKey>,
+
+    /// Request IDs for frames that expect a response.
+    ids: IdAllocator,
+
+    /// Server connections and the server each room is homed on.
+    servers: Servers,
+
+    /// Intents made while offline, waiting to be replayed.
+    intents: IntentQueue,
+
+    /// Messages sent but not yet seen sequenced.
+    outbox: Outbox,
+
+    /// Refused frames to resend once their backoff is over.
+    retries: Retries,
+
+    /// Rooms to sync instead if the server never sequences our commit.
+    commit_timeouts: CommitTimeouts,
+
+    /// Peers the user verified out of band.
+    verified: VerifiedPeers,
+
+    /// Escrow for room secrets, if the deployment requires one.
+    escrow: Option<Box<dyn KeyEscrow>>,
+
+    /// MLS ciphersuite our rooms and KeyPackages are created on.
+    ciphersuite: u16,
+
+    /// Padding applied to application messages before encryption.
+    padding: Padding,
+
+    /// Skipped message keys each room keeps for late messages.
+    max_skipped_keys: usize,
+
+    /// Generations a sender's ratchet may skip to reach one message.
+    max_generation_jump: u32,
 
     /// Environment for time/randomness.
     env: E,
@@ -88,7 +264,29 @@ pub struct Client<E: Environment> {
 impl<E: Environment> Client<E> {
     /// Create a new client with the given identity.
     pub fn new(env: E, identity: ClientIdentity) -> Self {
-        Self { identity, rooms: HashMap::new(), pending_joins: Vec::new(), env }
+        let now = env.now();
+        Self {
+            identity,
+            rooms: HashMap::new(),
+            pending_joins: KeyPackages::default(),
+            liveness: HashMap::from([(HOME_SERVER, Liveness::new(now))]),
+            clock: HybridClock::new(),
+            latency: FrameLatency::default(),
+            checkpoint_key: None,
+            ids: IdAllocator::new(),
+            servers: Servers::default(),
+            intents: IntentQueue::default(),
+            outbox: Outbox::default(),
+            retries: Retries::new(now),
+            commit_timeouts: CommitTimeouts::new(now),
+            verified: VerifiedPeers::default(),
+            escrow: None,
+            ciphersuite: DEFAULT_CIPHERSUITE,
+            padding: Padding::default(),
+            max_skipped_keys: DEFAULT_MAX_SKIPPED_KEYS,
+            max_generation_jump: DEFAULT_MAX_GENERATION_JUMP,
+            env,
+        }
     }
 
     /// Client's stable sender ID used in frame headers.
@@ -111,711 +309,5055 @@ impl<E: Environment> Client<E> {
         self.rooms.get(&room_id).map(|r| r.mls_group.epoch())
     }
 
-    /// Generate a KeyPackage for this client to join a room.
-    ///
-    /// The returned KeyPackage should be sent to the room creator who will
-    /// add this client via `AddMembers`. The client stores the cryptographic
-    /// state internally and uses it when the Welcome message arrives.
-    ///
-    /// Returns (serialized KeyPackage bytes, KeyPackage hash ref).
-    pub fn generate_key_package(&mut self) -> Result<(Vec<u8>, Vec<u8>), ClientError> {
-        let (kp_bytes, hash_ref, pending_state) =
-            MlsGroup::generate_key_package(self.env.clone(), self.identity.sender_id)
-                .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
-
-        self.pending_joins.push(pending_state);
-
-        Ok((kp_bytes, hash_ref))
+    /// How far each member of a room has read, as far as the receipts seen
+    /// so far tell. `None` if not a member.
+    pub fn read_state(&self, room_id: RoomId) -> Option<&ReadState> {
+        self.rooms.get(&room_id).map(|r| &r.read_state)
     }
 
-    /// Process an event and return resulting actions.
-    pub fn handle(&mut self, event: ClientEvent) -> Result<Vec<ClientAction>, ClientError> {
-        match event {
-            ClientEvent::CreateRoom { room_id } => self.handle_create_room(room_id),
-            ClientEvent::SendMessage { room_id, plaintext } => {
-                self.handle_send_message(room_id, &plaintext)
-            },
-            ClientEvent::FrameReceived(frame) => self.handle_frame(frame),
-            ClientEvent::Tick { now } => self.handle_tick(now),
-            ClientEvent::LeaveRoom { room_id } => self.handle_leave_room(room_id),
-            ClientEvent::JoinRoom { room_id, welcome } => self.handle_join_room(room_id, &welcome),
-            ClientEvent::AddMembers { room_id, key_packages } => {
-                self.handle_add_members(room_id, key_packages)
-            },
-        }
+    /// How long messages in a room are kept. `None` if not a member or
+    /// messages are kept indefinitely.
+    pub fn message_ttl(&self, room_id: RoomId) -> Option<Duration> {
+        self.rooms.get(&room_id).and_then(|r| r.expiry.ttl())
     }
 
-    fn handle_create_room(&mut self, room_id: RoomId) -> Result<Vec<ClientAction>, ClientError> {
-        if self.rooms.contains_key(&room_id) {
-            return Err(ClientError::RoomAlreadyExists { room_id });
-        }
-
-        let member_id = self.identity.sender_id;
-
-        let (mls_group, mls_actions) = MlsGroup::new(self.env.clone(), room_id, member_id)
-            .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
-
-        let sender_keys = self.initialize_sender_keys(&mls_group)?;
-        let my_leaf_index = mls_group.own_leaf_index();
+    /// Whether the client believes it is connected to the home server.
+    pub fn is_online(&self) -> bool {
+        self.servers.is_online(HOME_SERVER)
+    }
 
-        let initial_state =
-            mls_group.export_state().map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+    /// Whether the client believes it is connected to `server`.
+    pub fn is_server_online(&self, server: ServerId) -> bool {
+        self.servers.is_online(server)
+    }
 
-        let room_state = RoomState { mls_group, sender_keys, my_leaf_index };
-        self.rooms.insert(room_id, room_state);
+    /// Start the handshake on a new connection to `server`.
+    ///
+    /// Returns the Hello to send before anything else, offering the
+    /// capabilities the client implements and `auth_token` if the server
+    /// wants one. The server's `HelloReply` completes the handshake; a
+    /// challenge in it is answered with [`ClientIdentity::identity_key`].
+    pub fn hello(
+        &mut self,
+        server: ServerId,
+        auth_token: Option<Vec<u8>>,
+    ) -> Result<Frame, ClientError> {
+        let config = ConnectionConfig {
+            capabilities: CAPABILITIES,
+            identity_key: self.identity.identity_key.clone(),
+            auth_token,
+            ..ConnectionConfig::default()
+        };
+        let now = self.env.now();
+        let mut session = Session::new(now, config);
+        let actions = session.send_hello(now).map_err(|e| handshake_error(&e))?;
+        self.servers.start_session(server, session);
 
-        let mut actions = self.convert_mls_actions(room_id, mls_actions);
+        actions
+            .into_iter()
+            .find_map(|action| match action {
+                ConnectionAction::SendFrame(frame) => Some(frame),
+                ConnectionAction::Close { .. } => None,
+            })
+            .ok_or_else(|| ClientError::InvalidState { reason: "no Hello to send".to_string() })
+    }
 
-        actions.push(ClientAction::PersistRoom(RoomStateSnapshot {
-            room_id,
-            epoch: 0,
-            mls_state: initial_state,
-            my_leaf_index,
-        }));
+    /// Whether the handshake [`Client::hello`] started with `server`
+    /// completed.
+    pub fn is_authenticated(&self, server: ServerId) -> bool {
+        self.servers.is_authenticated(server)
+    }
 
-        actions.push(ClientAction::Log { message: format!("Created room {room_id:x} at epoch 0") });
+    /// Server that sequences `room_id`.
+    pub fn home_server(&self, room_id: RoomId) -> ServerId {
+        self.servers.home(room_id)
+    }
 
-        Ok(actions)
+    /// Number of intents waiting to be replayed.
+    pub fn queued_intents(&self) -> usize {
+        self.intents.len()
     }
 
-    /// Initialize sender keys from MLS group state.
-    fn initialize_sender_keys(
-        &self,
-        mls_group: &MlsGroup<E>,
-    ) -> Result<SenderKeyStore, ClientError> {
-        let epoch_secret = mls_group
-            .export_secret(SENDER_KEY_LABEL, SENDER_KEY_CONTEXT, SENDER_KEY_SECRET_SIZE)
-            .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+    /// Round-trip time estimate to the home server, sampled from heartbeat
+    /// acks.
+    pub fn rtt(&self) -> RttEstimator {
+        self.server_rtt(HOME_SERVER)
+    }
 
-        let member_indices = mls_group.member_leaf_indices();
+    /// Round-trip time estimate to `server`, sampled from heartbeat acks.
+    pub fn server_rtt(&self, server: ServerId) -> RttEstimator {
+        self.liveness
+            .get(&server)
+            .map_or_else(RttEstimator::default, |liveness| *liveness.heartbeats.rtt())
+    }
 
-        Ok(SenderKeyStore::initialize_epoch(&epoch_secret, mls_group.epoch(), &member_indices))
+    /// How long a pending commit to the home server may wait before the
+    /// client requests sync.
+    ///
+    /// Derived from the RTT estimate once a sample exists, otherwise a fixed
+    /// 30 second default.
+    pub fn commit_timeout(&self) -> Duration {
+        commit_timeout(&self.rtt())
     }
 
-    fn handle_send_message(
-        &mut self,
-        room_id: RoomId,
-        plaintext: &[u8],
-    ) -> Result<Vec<ClientAction>, ClientError> {
-        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+    /// How long a heartbeat to the home server may go unacked before the
+    /// client gives up on the connection.
+    ///
+    /// Derived from the RTT estimate once a sample exists, otherwise a fixed
+    /// 10 second default.
+    pub fn heartbeat_timeout(&self) -> Duration {
+        heartbeat_timeout(&self.rtt())
+    }
 
-        let mut random_bytes = [0u8; NONCE_RANDOM_SIZE];
-        self.env.random_bytes(&mut random_bytes);
+    /// How long to wait before reconnection attempt `attempt`, counting
+    /// from 1, after losing a server.
+    pub fn reconnect_delay(&self, attempt: u32) -> Duration {
+        RECONNECT_BACKOFF.delay(attempt, &self.env)
+    }
 
-        let crypto_encrypted =
-            room.sender_keys.encrypt(room.my_leaf_index, plaintext, random_bytes)?;
+    /// Latency distributions of frames received with server timestamps.
+    pub fn latency(&self) -> &FrameLatency {
+        &self.latency
+    }
 
-        let encrypted = crypto_to_proto_encrypted(&crypto_encrypted);
-        let payload = serialize_encrypted_message(&encrypted);
+    /// Latest hybrid logical clock timestamp issued or observed.
+    pub fn hlc(&self) -> HlcTimestamp {
+        self.clock.last()
+    }
 
-        let mut header = FrameHeader::new(Opcode::AppMessage);
-        header.set_room_id(room_id);
-        header.set_sender_id(self.identity.sender_id);
-        header.set_epoch(room.mls_group.epoch());
+    /// Estimated server wall-clock time in Unix milliseconds.
+    ///
+    /// Local wall clock corrected by the offset from the home server's latest
+    /// `TimeSync`, so displayed timestamps agree across clients with skewed
+    /// clocks. Falls back to the local clock before the first `TimeSync`.
+    pub fn server_time_millis(&self) -> u64 {
+        self.server_time_millis_for(HOME_SERVER)
+    }
 
-        room.mls_group.sign_frame_header(&mut header);
+    /// Estimated wall-clock time of `server` in Unix milliseconds.
+    fn server_time_millis_for(&self, server: ServerId) -> u64 {
+        let local = self.env.wall_clock_millis();
+        self.servers
+            .clock_offset_millis(server)
+            .map_or(local, |offset| local.saturating_add_signed(offset))
+    }
 
-        let frame = Frame::new(header, payload);
+    /// Heartbeats and traffic on the connection to `server`, tracked from
+    /// now if it had none yet.
+    fn liveness_mut(&mut self, server: ServerId) -> &mut Liveness {
+        let now = self.env.now();
+        self.liveness.entry(server).or_insert_with(|| Liveness::new(now))
+    }
 
-        Ok(vec![ClientAction::Send(frame)])
+    /// Create rooms and KeyPackages on the MLS ciphersuite with IANA
+    /// identifier `ciphersuite` (RFC 9420 §17.1), [`DEFAULT_CIPHERSUITE`]
+    /// unless set otherwise.
+    ///
+    /// A room keeps the ciphersuite it was created on, and encrypts messages
+    /// with the AEAD matching it. Joining takes a KeyPackage on the room's
+    /// ciphersuite, so members of a room should be set alike. Unsupported
+    /// ciphersuites fail when the room or KeyPackage is created.
+    pub fn set_ciphersuite(&mut self, ciphersuite: u16) {
+        self.ciphersuite = ciphersuite;
     }
 
-    fn handle_frame(&mut self, frame: Frame) -> Result<Vec<ClientAction>, ClientError> {
-        let room_id = frame.header.room_id();
+    /// Pad application messages with `padding` before encrypting them.
+    ///
+    /// Messages are padded to [`Padding::default`] buckets unless set
+    /// otherwise. Padding is applied after compression, so the ciphertext
+    /// shows only which bucket the compressed message fell in.
+    pub fn set_padding(&mut self, padding: Padding) {
+        self.padding = padding;
+    }
 
-        let opcode = frame.header.opcode_enum().ok_or(ClientError::InvalidFrame {
-            reason: format!("Unknown opcode: {}", frame.header.opcode()),
-        })?;
+    /// Keep at most `max` skipped message keys per sender, room, and epoch.
+    ///
+    /// Bounds the memory spent decrypting messages that arrive out of order;
+    /// a late message whose key was dropped can no longer be read. Defaults
+    /// to [`DEFAULT_MAX_SKIPPED_KEYS`].
+    pub fn set_max_skipped_keys(&mut self, max: usize) {
+        self.max_skipped_keys = max;
+        for room in self.rooms.values_mut() {
+            room.sender_keys.set_max_skipped_keys(max);
+        }
+    }
 
-        match opcode {
-            Opcode::AppMessage => self.handle_app_message(room_id, frame),
-            Opcode::Commit => self.handle_commit(room_id, frame),
-            Opcode::Welcome => self.handle_welcome(room_id, frame),
-            Opcode::SyncResponse => self.handle_sync_response(room_id, frame),
-            _ => {
-                // MLS
-                let room =
-                    self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+    /// Reject messages claiming a generation more than `max` ahead of their
+    /// sender's ratchet.
+    ///
+    /// Bounds the key derivations a single message can make us do; a member
+    /// whose messages are lost in greater numbers can no longer be read until
+    /// the next epoch. Defaults to [`DEFAULT_MAX_GENERATION_JUMP`].
+    pub fn set_max_generation_jump(&mut self, max: u32) {
+        self.max_generation_jump = max;
+        for room in self.rooms.values_mut() {
+            room.sender_keys.set_max_generation_jump(max);
+        }
+    }
 
-                let mls_actions = room
+    /// Capture the identity, every room, the sender key ratchets and the
+    /// `KeyPackage`s awaiting a Welcome so the client can be restarted with
+    /// [`Self::import_state`].
+    ///
+    /// Verified peers, room homes, transcripts and backfills are kept too.
+    /// Messages sent but not yet sequenced are kept as resends queued ahead
+    /// of the intents, as if the connection had dropped.
+    pub fn export_state(&self) -> Result<Vec<u8>, ClientError> {
+        let now = self.env.now();
+        let mut by_id: Vec<_> = self.rooms.iter().collect();
+        by_id.sort_unstable_by_key(|&(room_id, _)| *room_id);
+
+        let mut rooms = Vec::with_capacity(by_id.len());
+        for (&room_id, room) in by_id {
+            rooms.push(PersistedRoom {
+                room_id,
+                mls_state: room
                     .mls_group
-                    .process_message(frame)
-                    .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
-
-                Ok(self.convert_mls_actions(room_id, mls_actions))
-            },
+                    .export_state()
+                    .map_err(|e| ClientError::Mls { reason: e.to_string() })?,
+                sender_keys: room.sender_keys.export_state(),
+                transcript: room.transcript.clone(),
+                backfill: room.backfill.export(now),
+                commit: room.commit.as_ref().map(encode_frame).transpose()?,
+                needs_rekey: room.needs_rekey,
+            });
         }
-    }
 
-    /// Handle application message (encrypted content).
-    fn handle_app_message(
-        &mut self,
-        room_id: RoomId,
-        frame: Frame,
-    ) -> Result<Vec<ClientAction>, ClientError> {
-        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let mut intents = self.intents.clone();
+        intents.requeue(self.outbox.iter().cloned().collect());
 
-        let frame_epoch = frame.header.epoch();
-        let room_epoch = room.mls_group.epoch();
-        if frame_epoch != room_epoch {
-            return Err(ClientError::EpochMismatch { expected: room_epoch, actual: frame_epoch });
+        ClientState {
+            sender_id: self.identity.sender_id,
+            rooms,
+            key_packages: self.pending_joins.export()?,
+            verified: self.verified.export(),
+            homes: self.servers.homes(),
+            intents: intents.export(),
         }
+        .encode()
+    }
 
-        let validation_state = room.mls_group.export_validation_state();
-        room.mls_group
-            .validate_frame(&frame, Some(&validation_state))
-            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
-
-        let proto_encrypted = deserialize_encrypted_message(&frame.payload)
-            .map_err(|e| ClientError::InvalidFrame { reason: e })?;
+    /// Restart a client from state written by [`Self::export_state`].
+    ///
+    /// Rooms resume at the epoch and ratchet generations they were exported
+    /// at, so messages sent since can be decrypted without a full resync.
+    /// Queued intents replay once the client reconnects and has synced their
+    /// rooms, and a backfill left pending resumes on [`ClientEvent::Backfill`].
+    pub fn import_state(env: E, state: &[u8]) -> Result<Self, ClientError> {
+        let state = ClientState::decode(state)?;
+        let now = env.now();
+        let mut client = Self::new(env, ClientIdentity::new(state.sender_id));
+        client.pending_joins = KeyPackages::import(&client.env, state.key_packages)?;
+        client.intents = IntentQueue::import(state.intents);
+        for (peer_id, fingerprint) in state.verified {
+            client.verified.insert(peer_id, fingerprint);
+        }
 
-        // Verify sender_id in header matches the sender_index from the encrypted
-        // payload. This prevents forgery where an attacker repackages a message
-        // with a different header.
-        let header_sender_id = frame.header.sender_id();
-        let verified_sender_id = room
-            .mls_group
-            .member_id_by_leaf_index(proto_encrypted.sender_index)
-            .ok_or_else(|| ClientError::InvalidFrame {
-                reason: format!(
-                    "unknown sender_index {} in encrypted payload",
-                    proto_encrypted.sender_index
-                ),
-            })?;
+        for persisted in state.rooms {
+            let room_id = persisted.room_id;
+            let mls_group = MlsGroup::import_state(client.env.clone(), &persisted.mls_state)
+                .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+            let sender_keys = SenderKeyStore::import_state(persisted.sender_keys)?;
+            if mls_group.room_id() != room_id || sender_keys.epoch() != mls_group.epoch() {
+                return Err(ClientError::InvalidState {
+                    reason: format!("persisted room {room_id:x} is inconsistent"),
+                });
+            }
 
-        if header_sender_id != verified_sender_id {
-            return Err(ClientError::InvalidFrame {
-                reason: format!(
-                    "sender_id mismatch: header claims {}, but sender_index {} belongs to {}",
-                    header_sender_id, proto_encrypted.sender_index, verified_sender_id
-                ),
-            });
+            let mut expiry = Expiry::default();
+            expiry.set_ttl(mls_group.message_ttl());
+
+            let room_state = RoomState {
+                my_leaf_index: mls_group.own_leaf_index(),
+                mls_group,
+                sender_keys,
+                transcript: persisted.transcript,
+                backfill: Backfill::import(persisted.backfill, now)?,
+                seen: SeenMessages::default(),
+                drafts: Drafts::default(),
+                read_state: ReadState::default(),
+                sequenced: SequencedMessages::default(),
+                uploads: Uploads::default(),
+                downloads: Downloads::default(),
+                expiry,
+                needs_rekey: persisted.needs_rekey,
+                commit: persisted.commit.as_deref().map(decode_frame).transpose()?,
+            };
+            client.rooms.insert(room_id, room_state);
         }
 
-        let encrypted = proto_to_crypto_encrypted(&proto_encrypted);
-        let plaintext = room.sender_keys.decrypt(&encrypted)?;
+        for (room_id, server) in state.homes {
+            if client.rooms.contains_key(&room_id) {
+                client.servers.set_home(room_id, server);
+            }
+        }
 
-        Ok(vec![ClientAction::DeliverMessage {
-            room_id,
-            sender_id: verified_sender_id,
-            plaintext,
-            log_index: frame.header.log_index(),
-            timestamp: frame.header.hlc_timestamp(),
-        }])
+        Ok(client)
     }
 
-    /// Handle MLS commit (epoch transition).
-    fn handle_commit(
+    /// Resume a room from a [`ClientAction::PersistRoom`] snapshot, e.g.
+    /// after a restart.
+    ///
+    /// The room comes back at the snapshot's epoch and asks the server for
+    /// whatever it missed since. Snapshots are only taken on epoch changes,
+    /// so the messages we sent after it used sender key generations the
+    /// snapshot doesn't know about. Reusing them would reuse nonces, so the
+    /// room updates our leaf and refuses to send until that commit lands.
+    pub fn restore_room(
         &mut self,
-        room_id: RoomId,
-        frame: Frame,
+        snapshot: &RoomStateSnapshot,
     ) -> Result<Vec<ClientAction>, ClientError> {
-        let mls_actions = {
-            let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
-            room.mls_group
-                .process_message(frame)
-                .map_err(|e| ClientError::Mls { reason: e.to_string() })?
-        };
-
-        let mut actions = self.convert_mls_actions(room_id, mls_actions);
+        let room_id = snapshot.room_id;
+        if self.rooms.contains_key(&room_id) {
+            return Err(ClientError::RoomAlreadyExists { room_id });
+        }
 
-        // Re-derive sender keys for new epoch from MLS state
-        // We need to export the secret while holding only an immutable borrow,
-        // then update the room state afterward
-        let (new_sender_keys, new_leaf_index, epoch, my_leaf_index) = {
-            let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
-            let sender_keys = self.initialize_sender_keys(&room.mls_group)?;
-            let leaf_index = room.mls_group.own_leaf_index();
-            let epoch = room.mls_group.epoch();
-            (sender_keys, leaf_index, epoch, leaf_index)
-        };
+        let mls_group = MlsGroup::import_state(self.env.clone(), &snapshot.mls_state)
+            .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+        if mls_group.room_id() != room_id {
+            return Err(ClientError::InvalidState {
+                reason: format!("snapshot for room {room_id:x} holds another room"),
+            });
+        }
+        if mls_group.epoch() != snapshot.epoch {
+            return Err(ClientError::EpochMismatch {
+                expected: snapshot.epoch,
+                actual: mls_group.epoch(),
+            });
+        }
 
-        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
-        room.sender_keys = new_sender_keys;
-        room.my_leaf_index = new_leaf_index;
+        let sender_keys = self.initialize_sender_keys(&mls_group)?;
+        let my_leaf_index = mls_group.own_leaf_index();
+        let epoch = mls_group.epoch();
+        let mut expiry = Expiry::default();
+        expiry.set_ttl(mls_group.message_ttl());
 
-        actions.push(ClientAction::PersistRoom(RoomStateSnapshot {
-            room_id,
-            epoch,
-            mls_state: room
-                .mls_group
-                .export_state()
-                .map_err(|e| ClientError::Mls { reason: e.to_string() })?,
+        let room_state = RoomState {
+            mls_group,
+            sender_keys,
             my_leaf_index,
-        }));
+            transcript: Transcript::default(),
+            backfill: Backfill::default(),
+            seen: SeenMessages::default(),
+            drafts: Drafts::default(),
+            read_state: ReadState::default(),
+            sequenced: SequencedMessages::default(),
+            uploads: Uploads::default(),
+            downloads: Downloads::default(),
+            expiry,
+            needs_rekey: true,
+            commit: None,
+        };
+        self.rooms.insert(room_id, room_state);
 
+        let mut actions = vec![ClientAction::Log {
+            message: format!("Restored room {room_id:x} at epoch {epoch}"),
+        }];
+        actions.extend(self.sync_request(room_id));
+        actions.extend(self.handle_update_self(room_id)?);
         Ok(actions)
     }
 
-    /// Try to join a room using a pending KeyPackage state.
+    /// Trust `key` for server checkpoint signatures and start verifying the
+    /// transcript of every room.
     ///
-    /// Uses the most recently generated KeyPackage state. On success, the state
-    /// is consumed. On failure, the state is also consumed (caller should
-    /// generate a new KeyPackage if needed).
-    fn try_join_from_welcome(
-        &mut self,
-        room_id: RoomId,
-        welcome_bytes: &[u8],
-    ) -> Result<(MlsGroup<E>, Vec<MlsAction>), ClientError> {
-        let pending_state = self.pending_joins.pop().ok_or_else(|| ClientError::Mls {
-            reason: "No pending KeyPackage state - call generate_key_package first".to_string(),
-        })?;
+    /// Sequenced frames, checkpoints, and Merkle proofs are checked against
+    /// each other from then on; any inconsistency is reported as
+    /// [`ClientAction::TranscriptViolation`].
+    pub fn set_checkpoint_key(&mut self, key: VerifyingKey) {
+        self.checkpoint_key = Some(key);
+    }
 
-        MlsGroup::join_from_welcome(room_id, self.identity.sender_id, welcome_bytes, pending_state)
-            .map_err(|e| ClientError::Mls { reason: e.to_string() })
+    /// Material for verifying `peer_id` out of band, derived from the keys
+    /// `room_id` holds for both of us.
+    ///
+    /// The user compares the safety number or short authentication string
+    /// with the peer, or has them scan the QR payload, and confirms a match
+    /// with [`Client::mark_peer_verified`].
+    pub fn peer_verification(
+        &self,
+        room_id: RoomId,
+        peer_id: MemberId,
+    ) -> Result<PeerVerification, ClientError> {
+        let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let group = &room.mls_group;
+        let our_key = Self::signature_key(group, group.member_id())?;
+        let peer_key = Self::signature_key(group, peer_id)?;
+        let ours = PeerIdentity { member_id: group.member_id(), signature_key: &our_key };
+        let theirs = PeerIdentity { member_id: peer_id, signature_key: &peer_key };
+
+        Ok(PeerVerification {
+            peer_id,
+            epoch: group.epoch(),
+            safety_number: safety_number(ours, theirs),
+            short_auth_string: short_auth_string(group.epoch_authenticator(), ours, theirs),
+            qr_payload: verification_payload(ours, theirs),
+        })
     }
 
-    /// Handle incoming Welcome frame.
+    /// Record that the user verified `peer_id`'s key as `room_id` holds it.
     ///
-    /// When we receive a Welcome message from another member (who added us),
-    /// we process it to join the group.
-    fn handle_welcome(
+    /// Messages from the peer are delivered with `peer_verified` set, in
+    /// any room, until their key changes.
+    pub fn mark_peer_verified(
         &mut self,
         room_id: RoomId,
-        frame: Frame,
-    ) -> Result<Vec<ClientAction>, ClientError> {
-        if self.rooms.contains_key(&room_id) {
-            return Err(ClientError::RoomAlreadyExists { room_id });
+        peer_id: MemberId,
+    ) -> Result<(), ClientError> {
+        let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let key = Self::signature_key(&room.mls_group, peer_id)?;
+        self.verified
+            .insert(peer_id, fingerprint(PeerIdentity { member_id: peer_id, signature_key: &key }));
+        Ok(())
+    }
+
+    /// Check a QR payload scanned from `peer_id`'s device against our own,
+    /// marking the peer verified if they match.
+    pub fn verify_scanned_payload(
+        &mut self,
+        room_id: RoomId,
+        peer_id: MemberId,
+        scanned: &[u8],
+    ) -> Result<bool, ClientError> {
+        let matched = self.peer_verification(room_id, peer_id)?.qr_payload == scanned;
+        if matched {
+            self.mark_peer_verified(room_id, peer_id)?;
         }
+        Ok(matched)
+    }
 
-        let (mls_group, mls_actions) = self.try_join_from_welcome(room_id, &frame.payload)?;
+    /// Forget that `peer_id` was verified. Returns whether they were.
+    pub fn unverify_peer(&mut self, peer_id: MemberId) -> bool {
+        self.verified.remove(peer_id)
+    }
 
-        let sender_keys = self.initialize_sender_keys(&mls_group)?;
-        let my_leaf_index = mls_group.own_leaf_index();
+    /// Escrow room secrets to an organization recovery key.
+    ///
+    /// Off by default. Rooms created from now on name the recovery key in
+    /// their group context, and the secret of every epoch this client enters
+    /// in a room naming it is wrapped by `escrow`. See [`KeyEscrow`].
+    pub fn set_key_escrow(&mut self, escrow: impl KeyEscrow + 'static) {
+        self.escrow = Some(Box::new(escrow));
+    }
 
-        let room_state = RoomState { mls_group, sender_keys, my_leaf_index };
-        self.rooms.insert(room_id, room_state);
+    /// Whether `peer_id`'s key in `room_id` is the one the user verified.
+    pub fn is_peer_verified(&self, room_id: RoomId, peer_id: MemberId) -> bool {
+        self.verified.get(peer_id).is_some_and(|verified| {
+            self.rooms
+                .get(&room_id)
+                .and_then(|room| room.mls_group.member_signature_key(peer_id))
+                .is_some_and(|key| {
+                    fingerprint(PeerIdentity { member_id: peer_id, signature_key: &key })
+                        == *verified
+                })
+        })
+    }
 
-        let mut actions = self.convert_mls_actions(room_id, mls_actions);
-        actions.push(ClientAction::Log { message: format!("Joined room {room_id:x} via Welcome") });
+    fn signature_key(group: &MlsGroup<E>, member_id: MemberId) -> Result<Vec<u8>, ClientError> {
+        group.member_signature_key(member_id).ok_or_else(|| ClientError::InvalidState {
+            reason: format!("member {member_id} is not in room {:x}", group.room_id()),
+        })
+    }
 
-        Ok(actions)
+    /// Generate a KeyPackage for this client to join a room.
+    ///
+    /// The returned KeyPackage should be sent to the room creator who will
+    /// add this client via `AddMembers`. The client stores the cryptographic
+    /// state internally and uses it when the Welcome message arrives, for up
+    /// to [`KEY_PACKAGE_TTL_MILLIS`](crate::KEY_PACKAGE_TTL_MILLIS).
+    ///
+    /// Returns (serialized KeyPackage bytes, KeyPackage hash ref).
+    pub fn generate_key_package(&mut self) -> Result<(Vec<u8>, Vec<u8>), ClientError> {
+        let (kp_bytes, hash_ref, pending_state) = MlsGroup::generate_key_package_for(
+            self.env.clone(),
+            self.identity.sender_id,
+            self.ciphersuite,
+        )
+        .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+
+        let now_millis = self.server_time_millis();
+        self.pending_joins.insert(hash_ref.clone(), pending_state, now_millis);
+
+        Ok((kp_bytes, hash_ref))
     }
 
-    /// Handle join room request via Welcome message.
+    /// Number of generated `KeyPackage`s no Welcome has used yet.
+    pub fn pending_key_packages(&self) -> usize {
+        self.pending_joins.len()
+    }
+
+    /// Number of sent messages not yet seen sequenced, not counting those
+    /// queued for resending.
+    pub fn unsequenced_messages(&self) -> usize {
+        self.outbox.len()
+    }
+
+    fn handle_generate_key_package(&mut self) -> Result<Vec<ClientAction>, ClientError> {
+        let (key_package, _hash_ref) = self.generate_key_package()?;
+        Ok(vec![ClientAction::PublishKeyPackage(key_package)])
+    }
+
+    /// Process an event and return resulting actions.
     ///
-    /// This is the application-initiated join (via ClientEvent::JoinRoom).
-    /// The Welcome message should have been received out-of-band.
-    fn handle_join_room(
+    /// Frames are sent to the home server of their room, or back to the
+    /// server that sent the event's frame if they are for no room we're in.
+    pub fn handle(&mut self, event: ClientEvent) -> Result<Vec<ClientAction>, ClientError> {
+        let source = match &event {
+            ClientEvent::FrameReceivedFrom { server, .. }
+            | ClientEvent::TimedFrameReceivedFrom { server, .. } => *server,
+            _ => HOME_SERVER,
+        };
+        if matches!(
+            event,
+            ClientEvent::FrameReceived(_)
+                | ClientEvent::FrameReceivedFrom { .. }
+                | ClientEvent::TimedFrameReceived { .. }
+                | ClientEvent::TimedFrameReceivedFrom { .. }
+        ) {
+            let now = self.env.now();
+            self.liveness_mut(source).last_received = now;
+        }
+        let actions = self.handle_event(event)?;
+        Ok(actions.into_iter().map(|action| self.route(action, source)).collect())
+    }
+
+    fn handle_event(&mut self, event: ClientEvent) -> Result<Vec<ClientAction>, ClientError> {
+        match event {
+            ClientEvent::CreateRoom { room_id } => self.handle_create_room(room_id),
+            ClientEvent::GenerateKeyPackage => self.handle_generate_key_package(),
+            ClientEvent::SendMessage { room_id, plaintext } if self.should_queue(room_id) => {
+                self.queue_intent(Intent::SendMessage { room_id, plaintext })
+            },
+            ClientEvent::SendMessage { room_id, plaintext } => {
+                let message_id = self.intents.allocate_id();
+                let mut actions = self.handle_send_message(room_id, &plaintext, message_id)?;
+                actions.push(ClientAction::DeliveryUpdate {
+                    message_id,
+                    room_id,
+                    state: DeliveryState::Sent,
+                });
+                Ok(actions)
+            },
+            // Stale by the time the server is back, so never queued
+            ClientEvent::SetTyping { room_id, .. } if self.should_queue(room_id) => Ok(Vec::new()),
+            ClientEvent::SetTyping { room_id, typing } => self.handle_set_typing(room_id, typing),
+            ClientEvent::MarkRead { room_id, up_to_log_index } if self.should_queue(room_id) => {
+                self.queue_intent(Intent::MarkRead { room_id, up_to_log_index })
+            },
+            ClientEvent::MarkRead { room_id, up_to_log_index } => {
+                self.handle_mark_read(room_id, up_to_log_index)
+            },
+            ClientEvent::UploadAttachment { room_id, plaintext } => {
+                self.handle_upload_attachment(room_id, &plaintext)
+            },
+            ClientEvent::FetchAttachment { room_id, key } => {
+                self.handle_fetch_attachment(room_id, key)
+            },
+            ClientEvent::StartDraft { room_id } => self.handle_start_draft(room_id),
+            ClientEvent::AppendDraft { room_id, draft_id, chunk } => {
+                self.handle_draft_chunk(room_id, draft_id, &chunk, false)
+            },
+            ClientEvent::FinishDraft { room_id, draft_id, chunk } => {
+                self.handle_draft_chunk(room_id, draft_id, &chunk, true)
+            },
+            ClientEvent::FrameReceived(frame) => self.handle_frame_from(HOME_SERVER, frame),
+            ClientEvent::FrameReceivedFrom { server, frame } => {
+                self.handle_frame_from(server, frame)
+            },
+            ClientEvent::TimedFrameReceived { frame, timing } => {
+                self.handle_timed_frame(HOME_SERVER, frame, &timing)
+            },
+            ClientEvent::TimedFrameReceivedFrom { server, frame, timing } => {
+                self.handle_timed_frame(server, frame, &timing)
+            },
+            ClientEvent::Tick { now } => self.handle_tick(now),
+            ClientEvent::SendHeartbeat => self.handle_send_heartbeat(HOME_SERVER, self.env.now()),
+            ClientEvent::LeaveRoom { room_id } => self.handle_leave_room(room_id),
+            ClientEvent::JoinRoom { room_id, welcome } => self.handle_join_room(room_id, &welcome),
+            ClientEvent::AddMembers { room_id, key_packages } if self.should_queue(room_id) => {
+                self.queue_intent(Intent::AddMembers { room_id, key_packages })
+            },
+            ClientEvent::AddMembers { room_id, key_packages } => {
+                self.handle_add_members(room_id, key_packages)
+            },
+            ClientEvent::RemoveMembers { room_id, member_ids } if self.should_queue(room_id) => {
+                self.queue_intent(Intent::RemoveMembers { room_id, member_ids })
+            },
+            ClientEvent::RemoveMembers { room_id, member_ids } => {
+                self.handle_remove_members(room_id, &member_ids)
+            },
+            ClientEvent::UpdateSelf { room_id } if self.should_queue(room_id) => {
+                self.queue_intent(Intent::UpdateSelf { room_id })
+            },
+            ClientEvent::UpdateSelf { room_id } => self.handle_update_self(room_id),
+            ClientEvent::SetMessageTtl { room_id, ttl } => {
+                self.handle_set_message_ttl(room_id, ttl)
+            },
+            ClientEvent::RevokeSessions { member_ids } => self.handle_revoke_sessions(member_ids),
+            ClientEvent::ListRooms { after, limit } => list_rooms(after, limit),
+            ClientEvent::Backfill { room_id } => self.handle_backfill(room_id),
+            ClientEvent::Disconnected => Ok(self.handle_disconnected(HOME_SERVER)),
+            ClientEvent::Reconnected => Ok(self.handle_connected(HOME_SERVER)),
+            ClientEvent::ServerDisconnected { server } => Ok(self.handle_disconnected(server)),
+            ClientEvent::ServerConnected { server } => Ok(self.handle_connected(server)),
+            ClientEvent::MoveRoom { room_id, server } => self.handle_move_room(room_id, server),
+        }
+    }
+
+    /// Address a frame to the home server of its room, or to `source` if it
+    /// is for no room we're in.
+    fn route(&self, action: ClientAction, source: ServerId) -> ClientAction {
+        let ClientAction::Send(frame) = action else {
+            return action;
+        };
+        let room_id = frame.header.room_id();
+        let server =
+            if self.rooms.contains_key(&room_id) { self.servers.home(room_id) } else { source };
+
+        if server == HOME_SERVER {
+            ClientAction::Send(frame)
+        } else {
+            ClientAction::SendTo { server, frame }
+        }
+    }
+
+    /// Intents queue while the room's home server is offline, and while
+    /// earlier ones for the room wait to replay so that order is kept.
+    fn should_queue(&self, room_id: RoomId) -> bool {
+        !self.servers.is_online(self.servers.home(room_id)) || self.intents.contains_room(room_id)
+    }
+
+    /// Whether `room_id` finishing its sync lets its intents replay.
+    fn intents_synced(&mut self, room_id: RoomId) -> bool {
+        self.intents.sync_complete(room_id) && self.servers.is_online(self.servers.home(room_id))
+    }
+
+    fn queue_intent(&mut self, intent: Intent) -> Result<Vec<ClientAction>, ClientError> {
+        let room_id = intent.room_id();
+        let queued_epoch = self.epoch(room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let intent_id = self.intents.push(intent, queued_epoch);
+
+        Ok(vec![ClientAction::IntentQueued { intent_id, room_id }])
+    }
+
+    fn handle_disconnected(&mut self, server: ServerId) -> Vec<ClientAction> {
+        self.servers.set_online(server, false);
+        // Its ack would come over the connection that is gone
+        self.liveness_mut(server).heartbeats.abandon();
+        let servers = &self.servers;
+        self.intents.cancel_sync(|room_id| servers.home(room_id) == server);
+
+        // Messages the server may not have received go out again after the
+        // reconnect sync, ahead of intents made since
+        let interrupted = self.outbox.take(|room_id| servers.home(room_id) == server);
+        self.intents.requeue(interrupted);
+
+        let message = if server == HOME_SERVER {
+            "Disconnected, queueing intents".to_string()
+        } else {
+            format!("Disconnected from server {server}, queueing its intents")
+        };
+        vec![ClientAction::Log { message }]
+    }
+
+    /// Request sync for rooms moved to `server` while it was offline and for
+    /// its rooms with queued intents, or replay right away if none of those
+    /// are still joined.
+    fn handle_connected(&mut self, server: ServerId) -> Vec<ClientAction> {
+        let moved = self.servers.set_online(server, true);
+        let now = self.env.now();
+        self.liveness_mut(server).last_received = now;
+        let mut actions: Vec<ClientAction> =
+            moved.iter().filter_map(|&room_id| self.sync_request(room_id)).collect();
+        actions.extend(self.resume_transfers(server));
+        if self.intents.is_empty() {
+            return actions;
+        }
+
+        let servers = &self.servers;
+        for room_id in self.intents.await_sync(|room_id| servers.home(room_id) == server) {
+            match self.sync_request(room_id) {
+                Some(_) if moved.contains(&room_id) => {},
+                Some(request) => actions.push(request),
+                None => {
+                    if self.intents_synced(room_id) {
+                        actions.extend(self.replay_intents());
+                    }
+                },
+            }
+        }
+
+        actions.push(ClientAction::Log {
+            message: format!("Reconnected with {} queued intents", self.intents.len()),
+        });
+        actions
+    }
+
+    /// Home a room on `server` and sync it from there once connected.
+    fn handle_move_room(
         &mut self,
         room_id: RoomId,
-        welcome: &[u8],
+        server: ServerId,
     ) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        room.mls_group.clear_pending_commit();
+
+        let mut actions = Vec::new();
+        if self.servers.set_home(room_id, server) {
+            actions.extend(self.sync_request(room_id));
+        }
+        actions.push(ClientAction::Log {
+            message: format!("Moved room {room_id:x} to server {server}"),
+        });
+        Ok(actions)
+    }
+
+    /// Sync request for the next epoch of a room we're in.
+    fn sync_request(&self, room_id: RoomId) -> Option<ClientAction> {
+        let epoch = self.epoch(room_id)?;
+        Some(ClientAction::RequestSync {
+            room_id,
+            from_epoch: epoch,
+            to_epoch: epoch.saturating_add(1),
+            from_log_index: None,
+            mode: SyncMode::Full,
+        })
+    }
+
+    /// Replay, in order, the queued intents of every room that is not
+    /// waiting for its server or a sync, reporting an outcome for each.
+    fn replay_intents(&mut self) -> Vec<ClientAction> {
+        let mut actions = Vec::new();
+
+        let (rooms, servers) = (&self.rooms, &self.servers);
+        let ready = self.intents.drain_ready(|room_id| {
+            !rooms.contains_key(&room_id) || servers.is_online(servers.home(room_id))
+        });
+        for queued in ready {
+            let room_id = queued.intent.room_id();
+            let outcome = if self.rooms.contains_key(&room_id) {
+                let replayed = match queued.intent {
+                    Intent::SendMessage { plaintext, .. } => {
+                        self.handle_send_message(room_id, &plaintext, queued.id)
+                    },
+                    Intent::Resend(outgoing) => self.resend_message(outgoing),
+                    Intent::MarkRead { up_to_log_index, .. } => {
+                        self.handle_mark_read(room_id, up_to_log_index)
+                    },
+                    Intent::AddMembers { key_packages, .. } => {
+                        self.handle_add_members(room_id, key_packages)
+                    },
+                    Intent::RemoveMembers { member_ids, .. } => {
+                        self.handle_remove_members(room_id, &member_ids)
+                    },
+                    Intent::UpdateSelf { .. } => self.handle_update_self(room_id),
+                };
+                match replayed {
+                    Ok(replay_actions) => {
+                        actions.extend(replay_actions);
+                        IntentOutcome::Sent { epoch: self.epoch(room_id).unwrap_or_default() }
+                    },
+                    Err(e) => {
+                        IntentOutcome::Failed { reason: e.to_string(), retryable: !e.is_fatal() }
+                    },
+                }
+            } else {
+                IntentOutcome::RoomGone
+            };
+
+            actions.push(ClientAction::IntentResolved {
+                intent_id: queued.id,
+                room_id,
+                queued_epoch: queued.queued_epoch,
+                outcome,
+            });
+        }
+
+        actions
+    }
+
+    fn handle_create_room(&mut self, room_id: RoomId) -> Result<Vec<ClientAction>, ClientError> {
         if self.rooms.contains_key(&room_id) {
             return Err(ClientError::RoomAlreadyExists { room_id });
         }
 
-        let (mls_group, mls_actions) = self.try_join_from_welcome(room_id, welcome)?;
+        let member_id = self.identity.sender_id;
+
+        let created = match &self.escrow {
+            Some(escrow) => MlsGroup::new_escrowed(
+                self.env.clone(),
+                room_id,
+                member_id,
+                self.ciphersuite,
+                &escrow.recovery_key_id(),
+            ),
+            None => MlsGroup::new_with_ciphersuite(
+                self.env.clone(),
+                room_id,
+                member_id,
+                self.ciphersuite,
+            ),
+        };
+        let (mls_group, mls_actions) =
+            created.map_err(|e| ClientError::Mls { reason: e.to_string() })?;
 
         let sender_keys = self.initialize_sender_keys(&mls_group)?;
         let my_leaf_index = mls_group.own_leaf_index();
 
-        let room_state = RoomState { mls_group, sender_keys, my_leaf_index };
+        let room_state = RoomState {
+            mls_group,
+            sender_keys,
+            my_leaf_index,
+            transcript: Transcript::genesis(),
+            backfill: Backfill::default(),
+            seen: SeenMessages::default(),
+            drafts: Drafts::default(),
+            read_state: ReadState::default(),
+            sequenced: SequencedMessages::default(),
+            uploads: Uploads::default(),
+            downloads: Downloads::default(),
+            expiry: Expiry::default(),
+            needs_rekey: false,
+            commit: None,
+        };
         self.rooms.insert(room_id, room_state);
 
         let mut actions = self.convert_mls_actions(room_id, mls_actions);
-        actions.push(ClientAction::Log {
-            message: format!("Joined room {room_id:x} via JoinRoom event"),
-        });
+        actions.push(self.persist_room(room_id)?);
+
+        actions.push(ClientAction::Log { message: format!("Created room {room_id:x} at epoch 0") });
+        actions.extend(self.escrow_notice(room_id));
+        actions.extend(self.escrow_epoch(room_id)?);
 
         Ok(actions)
     }
 
-    /// Handle sync response from server.
-    ///
-    /// Processes frames from the sync response in order to catch up
-    /// to the server's epoch. Each frame is decoded and processed
-    /// sequentially. If `has_more` is true, emits another RequestSync action.
-    fn handle_sync_response(
-        &mut self,
-        room_id: RoomId,
-        frame: Frame,
-    ) -> Result<Vec<ClientAction>, ClientError> {
-        let sync_response: SyncResponse =
-            ciborium::de::from_reader(&frame.payload[..]).map_err(|e| {
-                ClientError::InvalidFrame { reason: format!("Failed to decode SyncResponse: {e}") }
-            })?;
+    /// Initialize sender keys from MLS group state.
+    fn initialize_sender_keys(
+        &self,
+        mls_group: &MlsGroup<E>,
+    ) -> Result<SenderKeyStore, ClientError> {
+        let epoch_secret = epoch_secret(mls_group)?;
 
-        let mut all_actions = Vec::new();
+        let member_indices = mls_group.member_leaf_indices();
 
-        all_actions.push(ClientAction::Log {
-            message: format!(
-                "Processing sync response for room {room_id:x}: {} frames, has_more={}, server_epoch={}",
-                sync_response.frames.len(),
-                sync_response.has_more,
-                sync_response.server_epoch
-            ),
-        });
+        let mut sender_keys =
+            SenderKeyStore::initialize_epoch(&epoch_secret, mls_group.epoch(), &member_indices);
+        sender_keys.set_max_skipped_keys(self.max_skipped_keys);
+        sender_keys.set_max_generation_jump(self.max_generation_jump);
+        sender_keys.set_cipher_suite(CipherSuite::for_mls_ciphersuite(mls_group.ciphersuite()));
+        Ok(sender_keys)
+    }
 
-        for (i, frame_bytes) in sync_response.frames.iter().enumerate() {
-            let sync_frame = Frame::decode(frame_bytes).map_err(|e| ClientError::InvalidFrame {
-                reason: format!("Failed to decode sync frame {i}: {e}"),
-            })?;
+    /// [`ClientAction::RoomEscrowed`] if `room_id` names a recovery key.
+    fn escrow_notice(&self, room_id: RoomId) -> Option<ClientAction> {
+        let recovery_key_id = self.rooms.get(&room_id)?.mls_group.escrow_key_id()?.to_vec();
+        Some(ClientAction::RoomEscrowed { room_id, recovery_key_id })
+    }
 
-            match self.handle_frame(sync_frame) {
-                Ok(actions) => all_actions.extend(actions),
-                Err(e) => {
-                    // Log error but continue processing remaining frames
-                    // Some frames might be from epochs we already have
-                    all_actions.push(ClientAction::Log {
-                        message: format!("Sync frame {i} processing error (may be expected): {e}"),
-                    });
-                },
-            }
+    /// Hand the current epoch's secret of `room_id` to the installed escrow,
+    /// if the room names its recovery key.
+    fn escrow_epoch(&mut self, room_id: RoomId) -> Result<Vec<ClientAction>, ClientError> {
+        let (Some(escrow), Some(room)) = (self.escrow.as_mut(), self.rooms.get(&room_id)) else {
+            return Ok(Vec::new());
+        };
+        let recovery_key_id = escrow.recovery_key_id();
+        if room.mls_group.escrow_key_id() != Some(recovery_key_id.as_slice()) {
+            return Ok(Vec::new());
         }
 
-        if sync_response.has_more {
-            // More frames avaliable
-            let current_epoch = self.rooms.get(&room_id).map(|r| r.mls_group.epoch()).unwrap_or(0);
-
-            all_actions.push(ClientAction::RequestSync {
-                room_id,
-                from_epoch: current_epoch,
-                to_epoch: sync_response.server_epoch,
-            });
-
-            all_actions.push(ClientAction::Log {
-                message: format!(
-                    "Sync incomplete, requesting more frames for room {room_id:x} (current epoch: {current_epoch}, target: {})",
-                    sync_response.server_epoch
-                ),
-            });
-        } else {
-            all_actions.push(ClientAction::Log {
+        let epoch = room.mls_group.epoch();
+        let secret = epoch_secret(&room.mls_group)?;
+        let key = recovery_key_id.iter().fold(String::new(), |mut key, byte| {
+            let _ = write!(key, "{byte:02x}");
+            key
+        });
+        Ok(match escrow.wrap(room_id, epoch, &secret) {
+            Ok(wrapped) => vec![
+                ClientAction::Log {
+                    message: format!(
+                        "KEY ESCROW: room {room_id:x} epoch {epoch} secret wrapped to recovery key {key}"
+                    ),
+                },
+                ClientAction::EscrowRoomKey { room_id, epoch, wrapped },
+            ],
+            Err(reason) => vec![ClientAction::Log {
                 message: format!(
-                    "Sync complete for room {room_id:x}, now at epoch {}",
-                    self.rooms.get(&room_id).map(|r| r.mls_group.epoch()).unwrap_or(0)
+                    "KEY ESCROW: failed to wrap room {room_id:x} epoch {epoch} to recovery key {key}: {reason}"
                 ),
-            });
-        }
-
-        Ok(all_actions)
+            }],
+        })
     }
 
-    /// Handle add members request.
-    ///
-    /// Adds members to a room using their serialized KeyPackages.
-    /// Delegates to MlsGroup::add_members_from_bytes.
-    fn handle_add_members(
+    fn handle_send_message(
         &mut self,
         room_id: RoomId,
-        key_packages_bytes: Vec<Vec<u8>>,
+        plaintext: &[u8],
+        message_id: u64,
     ) -> Result<Vec<ClientAction>, ClientError> {
-        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
-        let mls_actions = room
-            .mls_group
-            .add_members_from_bytes(&key_packages_bytes)
-            .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+        let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        if self.outbox.is_full() {
+            return Err(ClientError::OutboxFull { max: MAX_UNSEQUENCED_MESSAGES });
+        }
+        let epoch = room.mls_group.epoch();
+        let generation = room.sender_keys.generation(room.my_leaf_index).unwrap_or_default();
 
-        Ok(self.convert_mls_actions(room_id, mls_actions))
+        let frame = self.encrypt_app_message(room_id, plaintext, FrameFlags::empty())?;
+        self.outbox.push(Outgoing {
+            id: message_id,
+            room_id,
+            epoch,
+            generation,
+            plaintext: Zeroizing::new(plaintext.to_vec()),
+            frame: frame.clone(),
+        });
+        Ok(vec![ClientAction::Send(frame)])
     }
 
-    /// Handle tick (timeout processing).
+    /// Resend a message interrupted by a dropped connection.
     ///
-    /// Checks all rooms for pending commits that have timed out.
-    /// For rooms with timed-out commits, clears the pending state and emits
-    /// `RequestSync` actions.
-    fn handle_tick(&mut self, now: std::time::Instant) -> Result<Vec<ClientAction>, ClientError> {
-        let mut actions = Vec::new();
+    /// Sent unchanged while its epoch is current, so the server can tell it
+    /// from a new message; re-encrypted once the epoch has moved on.
+    fn resend_message(&mut self, outgoing: Outgoing) -> Result<Vec<ClientAction>, ClientError> {
+        let room_id = outgoing.room_id;
+        if self.epoch(room_id) != Some(outgoing.epoch) {
+            return self.handle_send_message(room_id, &outgoing.plaintext, outgoing.id);
+        }
 
-        for (&room_id, room) in &mut self.rooms {
-            if room.mls_group.is_commit_timeout(now, COMMIT_TIMEOUT) {
-                let current_epoch = room.mls_group.epoch();
-                room.mls_group.clear_pending_commit();
+        let frame = outgoing.frame.clone();
+        self.outbox.push(outgoing);
+        Ok(vec![ClientAction::Send(frame)])
+    }
 
-                actions.push(ClientAction::RequestSync {
-                    room_id,
-                    from_epoch: current_epoch,
-                    to_epoch: current_epoch.saturating_add(1), // next commit
-                });
-                actions.push(ClientAction::Log {
-                    message: format!(
-                        "Commit timeout in room {room_id:x}, requesting sync from epoch {current_epoch}"
-                    ),
-                });
-            }
+    /// Our own application message coming back sequenced.
+    ///
+    /// Settles the message in the outbox, or its queued resend. `None` if the
+    /// frame is not one of ours.
+    fn own_message_sequenced(&mut self, room_id: RoomId, frame: &Frame) -> Option<ClientAction> {
+        if frame.header.sender_id() != self.identity.sender_id {
+            return None;
+        }
+        let room = self.rooms.get_mut(&room_id)?;
+        let encrypted = EncryptedMessage::decode(&frame.payload).ok()?;
+        if encrypted.sender_index != room.my_leaf_index {
+            return None;
         }
 
-        Ok(actions)
+        // Our own messages disappear along with everyone else's
+        let log_index = frame.header.log_index();
+        let sent_millis = HlcTimestamp::from_u64(frame.header.hlc_timestamp()).physical_millis();
+        room.expiry.track(sent_millis, log_index);
+
+        let (epoch, generation) = (encrypted.epoch, encrypted.generation);
+        let message_id = self
+            .outbox
+            .acknowledge(room_id, epoch, generation)
+            .or_else(|| self.intents.remove_resend(room_id, epoch, generation));
+        let Some(message_id) = message_id else {
+            return Some(ClientAction::Log {
+                message: format!(
+                    "Own message at log index {log_index} in room {room_id:x} already settled"
+                ),
+            });
+        };
+
+        room.sequenced.track(log_index, message_id);
+        Some(ClientAction::DeliveryUpdate {
+            message_id,
+            room_id,
+            state: DeliveryState::Sequenced { log_index },
+        })
     }
 
-    fn handle_leave_room(&mut self, room_id: RoomId) -> Result<Vec<ClientAction>, ClientError> {
-        if self.rooms.remove(&room_id).is_none() {
-            return Err(ClientError::RoomNotFound { room_id });
-        }
+    fn handle_set_typing(
+        &mut self,
+        room_id: RoomId,
+        typing: bool,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+
+        let mut header = FrameHeader::new(Opcode::Typing);
+        header.set_room_id(room_id);
+        header.set_sender_id(self.identity.sender_id);
+        header.set_epoch(room.mls_group.epoch());
+        header.set_hlc_timestamp(self.clock.now(self.env.wall_clock_millis()).as_u64());
+        header.set_flags(FrameFlags::EPHEMERAL);
+
+        let mut frame = Payload::Typing(Typing { typing })
+            .into_frame(header)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+        room.mls_group.sign_frame_header(&mut frame.header);
 
-        Ok(vec![ClientAction::RoomRemoved { room_id, reason: "Left room".to_string() }])
+        Ok(vec![ClientAction::Send(frame)])
     }
 
-    /// Convert MLS actions to client actions.
-    fn convert_mls_actions(
-        &self,
+    /// Send a read receipt, signed but not encrypted so the server can keep
+    /// the read position.
+    fn handle_mark_read(
+        &mut self,
+        room_id: RoomId,
+        up_to_log_index: u64,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+
+        let mut header = FrameHeader::new(Opcode::ReadReceipt);
+        header.set_room_id(room_id);
+        header.set_sender_id(self.identity.sender_id);
+        header.set_epoch(room.mls_group.epoch());
+        header.set_hlc_timestamp(self.clock.now(self.env.wall_clock_millis()).as_u64());
+
+        let mut frame = Payload::ReadReceipt(ReadReceipt { room_id, up_to_log_index })
+            .into_frame(header)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+        room.mls_group.sign_frame_header(&mut frame.header);
+
+        Ok(vec![ClientAction::Send(frame)])
+    }
+
+    /// Encrypt an attachment under a key of its own and announce the upload.
+    ///
+    /// While the room's server is offline the upload waits for the reconnect,
+    /// when every unfinished upload is announced again.
+    fn handle_upload_attachment(
+        &mut self,
+        room_id: RoomId,
+        plaintext: &[u8],
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let mut seed = Zeroizing::new([0u8; 32]);
+        self.env.random_bytes(seed.as_mut());
+
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let init = room.uploads.start(plaintext, *seed)?;
+        if self.should_queue(room_id) {
+            return Ok(Vec::new());
+        }
+
+        let frame = self.attachment_frame(room_id, Payload::AttachmentInit(init))?;
+        Ok(vec![ClientAction::Send(frame)])
+    }
+
+    /// Download the attachment sealed under `key` from a room's server.
+    ///
+    /// While the room's server is offline the download waits for the
+    /// reconnect, when every unfinished download is requested again.
+    fn handle_fetch_attachment(
+        &mut self,
+        room_id: RoomId,
+        key: AttachmentKey,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let fetch = room.downloads.start(key);
+        if self.should_queue(room_id) {
+            return Ok(Vec::new());
+        }
+
+        let frame = self.attachment_frame(room_id, Payload::AttachmentFetch(fetch))?;
+        Ok(vec![ClientAction::Send(frame)])
+    }
+
+    /// Announce again the unfinished uploads to rooms homed on `server`, and
+    /// request the rest of the unfinished downloads from them.
+    fn resume_transfers(&mut self, server: ServerId) -> Vec<ClientAction> {
+        let payloads: Vec<_> = self
+            .rooms
+            .iter()
+            .filter(|&(&room_id, _)| self.servers.home(room_id) == server)
+            .flat_map(|(&room_id, room)| {
+                let uploads = room.uploads.resume().map(Payload::AttachmentInit);
+                let downloads = room.downloads.resume().map(Payload::AttachmentFetch);
+                uploads.chain(downloads).map(move |payload| (room_id, payload))
+            })
+            .collect();
+
+        payloads
+            .into_iter()
+            .map(|(room_id, payload)| match self.attachment_frame(room_id, payload) {
+                Ok(frame) => ClientAction::Send(frame),
+                Err(e) => ClientAction::Log {
+                    message: format!("Failed to resume attachment in room {room_id:x}: {e}"),
+                },
+            })
+            .collect()
+    }
+
+    /// Continue an upload from the chunk the server needs next, or hand the
+    /// key of a finished one to the application.
+    ///
+    /// For a download the status ends a window of chunks: the next window is
+    /// requested, or the opened attachment handed to the application.
+    fn handle_attachment_status(
+        &mut self,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let Payload::AttachmentStatus(AttachmentStatus { content_hash, next_chunk, complete }) =
+            Payload::from_frame(frame)
+                .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        else {
+            return Err(ClientError::InvalidFrame {
+                reason: "expected AttachmentStatus payload".to_string(),
+            });
+        };
+
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        if complete {
+            let mut actions: Vec<ClientAction> = room
+                .uploads
+                .finish(&content_hash)
+                .map(|key| ClientAction::AttachmentUploaded { room_id, key })
+                .into_iter()
+                .collect();
+            match room.downloads.window_end(&content_hash, next_chunk)? {
+                DownloadProgress::Unknown => {},
+                DownloadProgress::More(fetch) => actions.push(ClientAction::Send(
+                    self.attachment_frame(room_id, Payload::AttachmentFetch(fetch))?,
+                )),
+                DownloadProgress::Done(plaintext) => {
+                    actions.push(ClientAction::AttachmentFetched {
+                        room_id,
+                        content_hash,
+                        plaintext,
+                    });
+                },
+            }
+            return Ok(actions);
+        }
+
+        let Some(chunks) = room.uploads.chunks_from(&content_hash, next_chunk) else {
+            return Ok(vec![ClientAction::Log {
+                message: format!("Ignoring status of unknown upload to room {room_id:x}"),
+            }]);
+        };
+        let mut actions = Vec::with_capacity(chunks.len().saturating_add(1));
+        for chunk in chunks {
+            actions.push(ClientAction::Send(
+                self.attachment_frame(room_id, Payload::AttachmentChunk(chunk))?,
+            ));
+        }
+        let complete = Payload::AttachmentComplete(AttachmentComplete { content_hash });
+        actions.push(ClientAction::Send(self.attachment_frame(room_id, complete)?));
+        Ok(actions)
+    }
+
+    /// Keep a downloaded chunk until its window ends.
+    fn handle_attachment_chunk(
+        &mut self,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let Payload::AttachmentChunk(chunk) = Payload::from_frame(frame)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        else {
+            return Err(ClientError::InvalidFrame {
+                reason: "expected AttachmentChunk payload".to_string(),
+            });
+        };
+
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        room.downloads.chunk(&chunk);
+        Ok(Vec::new())
+    }
+
+    /// Sign an upload or download step, which the server only accepts from
+    /// members.
+    fn attachment_frame(
+        &mut self,
+        room_id: RoomId,
+        payload: Payload,
+    ) -> Result<Frame, ClientError> {
+        let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+
+        let mut header = FrameHeader::new(payload.opcode());
+        header.set_room_id(room_id);
+        header.set_sender_id(self.identity.sender_id);
+        header.set_epoch(room.mls_group.epoch());
+        header.set_hlc_timestamp(self.clock.now(self.env.wall_clock_millis()).as_u64());
+
+        let mut frame = payload
+            .into_frame(header)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+        room.mls_group.sign_frame_header(&mut frame.header);
+        Ok(frame)
+    }
+
+    fn handle_start_draft(&mut self, room_id: RoomId) -> Result<Vec<ClientAction>, ClientError> {
+        let now = self.env.now();
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let draft_id = room.drafts.start(now).ok_or_else(|| ClientError::InvalidState {
+            reason: format!("room {room_id:x} already has {MAX_OPEN_DRAFTS} open drafts"),
+        })?;
+        Ok(vec![ClientAction::DraftStarted { room_id, draft_id }])
+    }
+
+    /// Send the next chunk of a draft as soon as it is produced.
+    ///
+    /// Unlike whole messages, chunks are not queued while offline: a stream
+    /// that stalls until reconnect is better restarted by the application.
+    fn handle_draft_chunk(
+        &mut self,
+        room_id: RoomId,
+        draft_id: u64,
+        chunk: &[u8],
+        last: bool,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        if self.should_queue(room_id) {
+            return Err(ClientError::InvalidState {
+                reason: format!("cannot stream draft {draft_id} to room {room_id:x} while offline"),
+            });
+        }
+
+        let now = self.env.now();
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let fragment = room.drafts.next_fragment(draft_id, last, now).ok_or_else(|| {
+            ClientError::InvalidState { reason: format!("draft {draft_id} is not open") }
+        })?;
+
+        let frame =
+            self.encrypt_app_message(room_id, &fragment.seal(chunk), FrameFlags::FRAGMENTED)?;
+        Ok(vec![ClientAction::Send(frame)])
+    }
+
+    /// Encrypt `plaintext` to the room and sign it into an `AppMessage` frame.
+    fn encrypt_app_message(
+        &mut self,
+        room_id: RoomId,
+        plaintext: &[u8],
+        flags: FrameFlags,
+    ) -> Result<Frame, ClientError> {
+        // Compressed before sealing, as ciphertext doesn't compress. Only if
+        // the server and every member of the room can handle it
+        let compressible = plaintext.len() > compression::COMPRESSION_THRESHOLD
+            && self.rooms.get(&room_id).is_some_and(|room| room.mls_group.supports_compression())
+            && self
+                .servers
+                .capabilities(self.servers.home(room_id))
+                .contains(Capabilities::COMPRESSION);
+        let compressed = if compressible {
+            compression::compress(plaintext)
+                .ok()
+                .filter(|compressed| compressed.len() < plaintext.len())
+        } else {
+            None
+        };
+        let flags = if compressed.is_some() { flags | FrameFlags::COMPRESSED } else { flags };
+        let plaintext = compressed.as_deref().unwrap_or(plaintext);
+
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        if room.needs_rekey {
+            return Err(ClientError::InvalidState {
+                reason: format!("room {room_id:x} is rekeying after a restore"),
+            });
+        }
+
+        let mut random_bytes = [0u8; NONCE_RANDOM_SIZE];
+        self.env.random_bytes(&mut random_bytes);
+
+        let crypto_encrypted = room.sender_keys.encrypt_padded(
+            room.my_leaf_index,
+            plaintext,
+            self.padding,
+            random_bytes,
+        )?;
+
+        let encrypted = crypto_to_proto_encrypted(&crypto_encrypted);
+        let payload =
+            encrypted.encode().map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_sender_id(self.identity.sender_id);
+        header.set_epoch(room.mls_group.epoch());
+        header.set_hlc_timestamp(self.clock.now(self.env.wall_clock_millis()).as_u64());
+        header.set_flags(flags);
+        // Lets a server error name the message, and a streamed payload find
+        // its header
+        header.set_request_id(self.ids.next_request_id());
+
+        // Large payloads go on a stream of their own, matched by request ID
+        if payload.len() > Frame::STREAM_THRESHOLD {
+            header.set_flags(flags | FrameFlags::STREAMED);
+        }
+
+        // Signed once the payload size is set, so receivers can verify it
+        let mut frame = Frame::new(header, payload);
+        room.mls_group.sign_frame_header(&mut frame.header);
+
+        Ok(frame)
+    }
+
+    /// Sample the latency of a frame from `server` against that server's
+    /// clock, then handle it.
+    fn handle_timed_frame(
+        &mut self,
+        server: ServerId,
+        frame: Frame,
+        timing: &FrameTiming,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let arrived_at = self.server_time_millis_for(server);
+        self.latency.record(frame.header.hlc_timestamp(), timing, arrived_at);
+        self.handle_frame_from(server, frame)
+    }
+
+    /// Handle a frame from `server` if it may speak for the frame's room.
+    fn handle_frame_from(
+        &mut self,
+        server: ServerId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let room_id = frame.header.room_id();
+        let joined = self.rooms.contains_key(&room_id);
+        let home = self.servers.home(room_id);
+        if joined && home != server {
+            return Ok(vec![ClientAction::Log {
+                message: format!(
+                    "Ignoring frame for room {room_id:x} from server {server}, homed on server {home}"
+                ),
+            }]);
+        }
+
+        let actions = self.handle_frame(server, frame)?;
+        if !joined && server != HOME_SERVER && self.rooms.contains_key(&room_id) {
+            self.servers.set_home(room_id, server);
+        }
+        Ok(actions)
+    }
+
+    fn handle_frame(
+        &mut self,
+        server: ServerId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let room_id = frame.header.room_id();
+
+        let opcode = frame.header.opcode_enum().ok_or(ClientError::InvalidFrame {
+            reason: format!("Unknown opcode: {}", frame.header.opcode()),
+        })?;
+
+        if is_sequenced(opcode) && opcode != Opcode::Checkpoint {
+            if let Some(transcript) = self.transcript_mut(room_id) {
+                if let Err(reason) = transcript.observe_frame(&frame) {
+                    return Ok(vec![ClientAction::TranscriptViolation { room_id, reason }]);
+                }
+            }
+        }
+
+        match opcode {
+            Opcode::AppMessage => self.handle_app_message(room_id, frame),
+            Opcode::Commit => self.handle_commit(room_id, frame),
+            Opcode::Welcome => self.handle_welcome(room_id, frame),
+            Opcode::SyncResponse => self.handle_sync_response(server, room_id, frame),
+            Opcode::Checkpoint => self.handle_checkpoint(room_id, &frame),
+            Opcode::ProofResponse => self.handle_proof_response(room_id, &frame),
+            Opcode::Heartbeat | Opcode::HeartbeatAck => self.handle_heartbeat_frame(server, frame),
+            Opcode::HelloReply => self.handle_hello_reply(server, &frame),
+            Opcode::TimeSync => self.handle_time_sync_frame(server, frame),
+            Opcode::Error => self.handle_error_frame(room_id, frame),
+            Opcode::SessionsRevoked => self.handle_sessions_revoked(frame),
+            Opcode::RoomMoved => self.handle_room_moved(room_id, frame),
+            Opcode::Maintenance => handle_maintenance(server, frame),
+            Opcode::ListRoomsReply => handle_list_rooms_reply(frame),
+            Opcode::Typing => self.handle_typing(room_id, frame),
+            Opcode::ReadReceipt => self.handle_read_receipt(room_id, frame),
+            Opcode::AttachmentStatus => self.handle_attachment_status(room_id, frame),
+            Opcode::AttachmentChunk => self.handle_attachment_chunk(room_id, frame),
+            _ => {
+                // MLS
+                let room =
+                    self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+
+                let mls_actions = room
+                    .mls_group
+                    .process_message(frame)
+                    .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+
+                Ok(self.convert_mls_actions(room_id, mls_actions))
+            },
+        }
+    }
+
+    /// Handle application message (encrypted content).
+    fn handle_app_message(
+        &mut self,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        if let Some(action) = self.own_message_sequenced(room_id, &frame) {
+            return Ok(vec![action]);
+        }
+
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+
+        let frame_epoch = frame.header.epoch();
+        let room_epoch = room.mls_group.epoch();
+        let opened = if frame_epoch == room_epoch {
+            let validation_state = room.mls_group.export_validation_state();
+            let group = &room.mls_group;
+            open_app_message(
+                &frame,
+                &validation_state,
+                |leaf_index| group.member_id_by_leaf_index(leaf_index),
+                &mut room.sender_keys,
+                &mut room.seen,
+            )?
+        } else if let Some(keys) = room.backfill.keys_mut(frame_epoch) {
+            // Skipped by a fast-forward; opened with the keys of its epoch
+            let EpochKeys { sender_keys, validation, members } = keys;
+            open_app_message(
+                &frame,
+                validation,
+                |leaf_index| members.get(&leaf_index).copied(),
+                sender_keys,
+                &mut room.seen,
+            )?
+        } else {
+            return Err(ClientError::EpochMismatch { expected: room_epoch, actual: frame_epoch });
+        };
+
+        let (verified_sender_id, plaintext) = match opened {
+            Opened::Message { sender_id, plaintext } => (sender_id, plaintext),
+            Opened::Duplicate(MessageId { sender_index, epoch, generation }) => {
+                return Ok(vec![ClientAction::DuplicateSuppressed {
+                    room_id,
+                    sender_index,
+                    epoch,
+                    generation,
+                }]);
+            },
+        };
+
+        let plaintext = if frame.header.flags().contains(FrameFlags::COMPRESSED) {
+            let max_size = usize::try_from(FrameHeader::MAX_PAYLOAD_SIZE).unwrap_or(usize::MAX);
+            compression::decompress(&plaintext, max_size)
+                .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        } else {
+            plaintext
+        };
+
+        let timestamp = frame.header.hlc_timestamp();
+        self.clock.observe(HlcTimestamp::from_u64(timestamp), self.env.wall_clock_millis());
+
+        if frame.header.flags().contains(FrameFlags::FRAGMENTED) {
+            return Ok(vec![self.receive_draft_chunk(
+                room_id,
+                verified_sender_id,
+                &plaintext,
+                frame.header.log_index(),
+                timestamp,
+            )]);
+        }
+
+        let log_index = frame.header.log_index();
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            room.expiry.track(HlcTimestamp::from_u64(timestamp).physical_millis(), log_index);
+        }
+
+        Ok(vec![ClientAction::DeliverMessage {
+            room_id,
+            sender_id: verified_sender_id,
+            plaintext,
+            log_index,
+            timestamp,
+            peer_verified: self.is_peer_verified(room_id, verified_sender_id),
+        }])
+    }
+
+    /// Handle a member's read receipt, ours included.
+    ///
+    /// Receipts from an epoch we hold no keys for, e.g. from before we
+    /// joined, cannot be verified and are dropped.
+    fn handle_read_receipt(
+        &mut self,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+
+        let frame_epoch = frame.header.epoch();
+        let current;
+        let validation = if frame_epoch == room.mls_group.epoch() {
+            current = room.mls_group.export_validation_state();
+            &current
+        } else if let Some(keys) = room.backfill.keys_mut(frame_epoch) {
+            &keys.validation
+        } else {
+            return Ok(vec![ClientAction::Log {
+                message: format!(
+                    "Dropping read receipt for room {room_id:x} from epoch {frame_epoch} without keys"
+                ),
+            }]);
+        };
+
+        let validation_result = MlsValidator::validate_frame(&frame, frame_epoch, validation)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+        if let ValidationResult::Reject { reason } = validation_result {
+            let reason = MlsError::ValidationFailed(reason).to_string();
+            return Err(ClientError::InvalidFrame { reason });
+        }
+
+        let sender_id = frame.header.sender_id();
+        let Payload::ReadReceipt(receipt) = Payload::from_frame(frame)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        else {
+            return Err(ClientError::InvalidFrame {
+                reason: "expected ReadReceipt payload".to_string(),
+            });
+        };
+        if receipt.room_id != room_id {
+            return Err(ClientError::InvalidFrame {
+                reason: format!("read receipt for room {:x} sent to {room_id:x}", receipt.room_id),
+            });
+        }
+
+        let read_before = room.read_state.read_up_to(sender_id);
+        if !room.read_state.record(sender_id, receipt.up_to_log_index) {
+            return Ok(Vec::new());
+        }
+
+        let mut actions = vec![ClientAction::ReadReceipt {
+            room_id,
+            sender_id,
+            up_to_log_index: receipt.up_to_log_index,
+        }];
+        if sender_id != self.identity.sender_id {
+            let read = room.sequenced.newly_read(read_before, receipt.up_to_log_index);
+            actions.extend(read.map(|message_id| ClientAction::DeliveryUpdate {
+                message_id,
+                room_id,
+                state: DeliveryState::Read { member_id: sender_id },
+            }));
+        }
+        Ok(actions)
+    }
+
+    /// Handle a member's typing indicator.
+    ///
+    /// Indicators are not sequenced, so one from another epoch than ours is
+    /// dropped rather than synced for.
+    fn handle_typing(
+        &self,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+
+        let epoch = room.mls_group.epoch();
+        if frame.header.epoch() != epoch {
+            return Ok(vec![ClientAction::Log {
+                message: format!(
+                    "Dropping typing indicator for room {room_id:x} from epoch {}, at epoch {epoch}",
+                    frame.header.epoch()
+                ),
+            }]);
+        }
+
+        let validation = room.mls_group.export_validation_state();
+        let validation_result = MlsValidator::validate_frame(&frame, epoch, &validation)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+        if let ValidationResult::Reject { reason } = validation_result {
+            let reason = MlsError::ValidationFailed(reason).to_string();
+            return Err(ClientError::InvalidFrame { reason });
+        }
+
+        let sender_id = frame.header.sender_id();
+        let Payload::Typing(Typing { typing }) = Payload::from_frame(frame)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        else {
+            return Err(ClientError::InvalidFrame {
+                reason: "expected Typing payload".to_string(),
+            });
+        };
+
+        Ok(vec![ClientAction::Typing { room_id, sender_id, typing }])
+    }
+
+    /// Hand a chunk of another member's draft to the application, if it
+    /// continues the draft.
+    fn receive_draft_chunk(
+        &mut self,
+        room_id: RoomId,
+        sender_id: u64,
+        plaintext: &[u8],
+        log_index: u64,
+        timestamp: u64,
+    ) -> ClientAction {
+        let now = self.env.now();
+        let Some(room) = self.rooms.get_mut(&room_id) else {
+            return ClientAction::Log {
+                message: format!("Draft chunk for unknown room {room_id:x}"),
+            };
+        };
+        let (fragment, chunk) = match Fragment::open(plaintext).and_then(|(fragment, chunk)| {
+            room.drafts.receive(sender_id, fragment, now).map(|()| (fragment, chunk))
+        }) {
+            Ok(received) => received,
+            Err(reason) => {
+                return ClientAction::Log {
+                    message: format!("Dropping draft chunk in room {room_id:x}: {reason}"),
+                };
+            },
+        };
+
+        ClientAction::DraftChunk {
+            room_id,
+            sender_id,
+            draft_id: fragment.draft_id,
+            index: fragment.index,
+            chunk: chunk.to_vec(),
+            last: fragment.last,
+            log_index,
+            timestamp,
+        }
+    }
+
+    /// Handle MLS commit (epoch transition).
+    fn handle_commit(
+        &mut self,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let own_commit = frame.header.sender_id() == self.identity.sender_id;
+        let (mls_actions, past_epoch) = {
+            let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+
+            // Messages of the epoch being left are backfilled after a
+            // fast-forward, so keep what it takes to open them
+            let past_epoch = room.backfill.is_fast_forwarding().then(|| {
+                let group = &room.mls_group;
+                let members = group
+                    .member_leaf_indices()
+                    .into_iter()
+                    .filter_map(|leaf| group.member_id_by_leaf_index(leaf).map(|id| (leaf, id)))
+                    .collect();
+                (group.epoch(), group.export_validation_state(), members)
+            });
+
+            // Our own commit is merged from its pending state; MLS cannot
+            // process a commit its own member sent
+            let mls_actions = if own_commit && room.mls_group.has_pending_commit() {
+                room.mls_group.merge_pending_commit().map(|()| Vec::new())
+            } else {
+                room.mls_group.process_message(frame)
+            }
+            .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+            (mls_actions, past_epoch)
+        };
+
+        let removed =
+            mls_actions.iter().any(|action| matches!(action, MlsAction::RemoveGroup { .. }));
+        let mut actions = self.convert_mls_actions(room_id, mls_actions);
+        if removed {
+            // The commit removed us; there is no epoch left to take up
+            actions.extend(self.forget_room(room_id));
+        } else {
+            actions.extend(self.enter_epoch(room_id, past_epoch)?);
+        }
+        Ok(actions)
+    }
+
+    /// Take up the epoch a commit just moved the room to.
+    ///
+    /// Sender keys are re-derived from the new epoch, and those of the epoch
+    /// left behind kept for backfill if `past_epoch` is given. A commit that
+    /// changed the message TTL in the group context takes effect here.
+    fn enter_epoch(
+        &mut self,
+        room_id: RoomId,
+        past_epoch: Option<(u64, MlsGroupState, HashMap<u32, MemberId>)>,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        // Re-derive sender keys for new epoch from MLS state
+        // We need to export the secret while holding only an immutable borrow,
+        // then update the room state afterward
+        let (new_sender_keys, new_leaf_index) = {
+            let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+            let sender_keys = self.initialize_sender_keys(&room.mls_group)?;
+            (sender_keys, room.mls_group.own_leaf_index())
+        };
+
+        let now = self.env.now();
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let sender_keys = std::mem::replace(&mut room.sender_keys, new_sender_keys);
+        room.my_leaf_index = new_leaf_index;
+        room.needs_rekey = false;
+        if let Some((past_epoch, validation, members)) = past_epoch {
+            let keys = EpochKeys { sender_keys, validation, members };
+            room.backfill.retain(past_epoch, keys, now);
+        }
+
+        let mut actions = Vec::new();
+        let ttl = room.mls_group.message_ttl();
+        if room.expiry.ttl() != ttl {
+            room.expiry.set_ttl(ttl);
+            actions.push(ClientAction::MessageTtlChanged { room_id, ttl });
+        }
+
+        actions.push(self.persist_room(room_id)?);
+        actions.extend(self.escrow_epoch(room_id)?);
+
+        Ok(actions)
+    }
+
+    /// Room state for the application to persist.
+    fn persist_room(&self, room_id: RoomId) -> Result<ClientAction, ClientError> {
+        let room = self.rooms.get(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        Ok(ClientAction::PersistRoom(RoomStateSnapshot {
+            room_id,
+            epoch: room.mls_group.epoch(),
+            mls_state: room
+                .mls_group
+                .export_state()
+                .map_err(|e| ClientError::Mls { reason: e.to_string() })?,
+            my_leaf_index: room.my_leaf_index,
+        }))
+    }
+
+    /// Try to join a room using a pending KeyPackage state.
+    ///
+    /// Uses the state of the `KeyPackage` the Welcome was made for. On success,
+    /// the state is consumed. On failure, the state is also consumed (caller
+    /// should generate a new `KeyPackage` if needed). States of other
+    /// `KeyPackage`s are kept for their own Welcomes.
+    fn try_join_from_welcome(
+        &mut self,
+        room_id: RoomId,
+        welcome_bytes: &[u8],
+    ) -> Result<(MlsGroup<E>, Vec<MlsAction>), ClientError> {
+        let refs = welcome_key_package_refs(welcome_bytes)
+            .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+        let pending_state = self.pending_joins.take(&refs).ok_or_else(|| ClientError::Mls {
+            reason: "No pending KeyPackage state for this Welcome - call \
+                         generate_key_package first"
+                .to_string(),
+        })?;
+
+        MlsGroup::join_from_welcome(room_id, self.identity.sender_id, welcome_bytes, pending_state)
+            .map_err(|e| ClientError::Mls { reason: e.to_string() })
+    }
+
+    /// Handle incoming Welcome frame.
+    ///
+    /// When we receive a Welcome message from another member (who added us),
+    /// we process it to join the group.
+    fn handle_welcome(
+        &mut self,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        self.join_room(room_id, &frame.payload, "Welcome")
+    }
+
+    /// Handle join room request via Welcome message.
+    ///
+    /// This is the application-initiated join (via ClientEvent::JoinRoom).
+    /// The Welcome message should have been received out-of-band.
+    fn handle_join_room(
+        &mut self,
+        room_id: RoomId,
+        welcome: &[u8],
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        self.join_room(room_id, welcome, "JoinRoom event")
+    }
+
+    /// Join a room with the `KeyPackage` state a Welcome was made for.
+    ///
+    /// The group starts at the epoch the Welcome admits us to, with sender
+    /// keys derived from it, ready to open the next message sent there.
+    fn join_room(
+        &mut self,
+        room_id: RoomId,
+        welcome: &[u8],
+        via: &str,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        if self.rooms.contains_key(&room_id) {
+            return Err(ClientError::RoomAlreadyExists { room_id });
+        }
+
+        let (mls_group, mls_actions) = self.try_join_from_welcome(room_id, welcome)?;
+
+        let sender_keys = self.initialize_sender_keys(&mls_group)?;
+        let my_leaf_index = mls_group.own_leaf_index();
+        let epoch = mls_group.epoch();
+
+        // Joiners learn the TTL from the group context they join with
+        let ttl = mls_group.message_ttl();
+        let mut expiry = Expiry::default();
+        expiry.set_ttl(ttl);
+
+        let room_state = RoomState {
+            mls_group,
+            sender_keys,
+            my_leaf_index,
+            transcript: Transcript::default(),
+            backfill: Backfill::default(),
+            seen: SeenMessages::default(),
+            drafts: Drafts::default(),
+            read_state: ReadState::default(),
+            sequenced: SequencedMessages::default(),
+            uploads: Uploads::default(),
+            downloads: Downloads::default(),
+            expiry,
+            needs_rekey: false,
+            commit: None,
+        };
+        self.rooms.insert(room_id, room_state);
+
+        let mut actions = self.convert_mls_actions(room_id, mls_actions);
+        actions.push(ClientAction::RoomJoined { room_id, epoch });
+        if ttl.is_some() {
+            actions.push(ClientAction::MessageTtlChanged { room_id, ttl });
+        }
+        actions.push(self.persist_room(room_id)?);
+        actions.push(ClientAction::Log {
+            message: format!("Joined room {room_id:x} at epoch {epoch} via {via}"),
+        });
+        actions.extend(self.escrow_notice(room_id));
+        actions.extend(self.escrow_epoch(room_id)?);
+
+        Ok(actions)
+    }
+
+    /// Handle sync response from server.
+    ///
+    /// Processes frames from the sync response in order to catch up
+    /// to the server's epoch. Each frame is decoded and processed
+    /// sequentially. If `has_more` is true, emits another RequestSync action.
+    ///
+    /// A client more than [`FAST_FORWARD_EPOCHS`] behind switches to
+    /// [`SyncMode::EpochChanges`] and reports the messages it skipped with
+    /// [`ClientAction::BackfillPending`] once it has caught up. A response
+    /// to [`ClientEvent::Backfill`] delivers one page of those, and reports
+    /// what is left with another [`ClientAction::BackfillPending`].
+    #[allow(clippy::too_many_lines)]
+    fn handle_sync_response(
+        &mut self,
+        server: ServerId,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let sync_response: SyncResponse =
+            ciborium::de::from_reader(&frame.payload[..]).map_err(|e| {
+                ClientError::InvalidFrame { reason: format!("Failed to decode SyncResponse: {e}") }
+            })?;
+
+        let backfill_page = match sync_response.mode {
+            SyncMode::Backfill => self.rooms.get_mut(&room_id).and_then(|r| r.backfill.take_page()),
+            SyncMode::Full | SyncMode::EpochChanges => None,
+        };
+
+        let mut all_actions = Vec::new();
+
+        all_actions.push(ClientAction::Log {
+            message: format!(
+                "Processing sync response for room {room_id:x}: {} frames, has_more={}, server_epoch={}",
+                sync_response.frames.len(),
+                sync_response.has_more,
+                sync_response.server_epoch
+            ),
+        });
+
+        let mut next_log_index = sync_response.next_log_index;
+        for (i, frame_bytes) in sync_response.frames.iter().enumerate() {
+            let sync_frame = Frame::decode(frame_bytes).map_err(|e| ClientError::InvalidFrame {
+                reason: format!("Failed to decode sync frame {i}: {e}"),
+            })?;
+
+            let log_index = sync_frame.header.log_index();
+            if sync_response.next_log_index.is_none() {
+                next_log_index = Some(log_index.saturating_add(1));
+            }
+            // Frames past the skipped range were delivered live
+            if backfill_page.as_ref().is_some_and(|range| log_index >= range.end) {
+                continue;
+            }
+
+            match self.handle_frame(server, sync_frame) {
+                Ok(actions) => all_actions.extend(actions),
+                Err(e) => {
+                    // Log error but continue processing remaining frames
+                    // Some frames might be from epochs we already have
+                    all_actions.push(ClientAction::Log {
+                        message: format!("Sync frame {i} processing error (may be expected): {e}"),
+                    });
+                },
+            }
+        }
+
+        let Some(room) = self.rooms.get_mut(&room_id) else {
+            return Ok(all_actions);
+        };
+        let current_epoch = room.mls_group.epoch();
+
+        if sync_response.mode == SyncMode::Backfill {
+            // E.g. answering a request from before the backfill was abandoned
+            if backfill_page.is_none() {
+                all_actions.push(ClientAction::Log {
+                    message: format!("Unrequested backfill page for room {room_id:x}"),
+                });
+                return Ok(all_actions);
+            }
+            match next_log_index.and_then(|next| room.backfill.advance(next)) {
+                Some(remaining) => all_actions.push(ClientAction::BackfillPending {
+                    room_id,
+                    from_log_index: remaining.start,
+                    to_log_index: remaining.end,
+                }),
+                None => all_actions.push(ClientAction::Log {
+                    message: format!("Backfill complete for room {room_id:x}"),
+                }),
+            }
+            return Ok(all_actions);
+        }
+
+        if sync_response.has_more {
+            // Far behind: catch up on epoch changes first, backfill later
+            let far_behind =
+                sync_response.server_epoch.saturating_sub(current_epoch) > FAST_FORWARD_EPOCHS;
+            let mode = match next_log_index {
+                Some(next) if far_behind => {
+                    room.backfill.start(next);
+                    SyncMode::EpochChanges
+                },
+                _ => sync_response.mode,
+            };
+
+            // More frames avaliable
+            all_actions.push(ClientAction::RequestSync {
+                room_id,
+                from_epoch: current_epoch,
+                to_epoch: sync_response.server_epoch,
+                from_log_index: next_log_index,
+                mode,
+            });
+
+            all_actions.push(ClientAction::Log {
+                message: format!(
+                    "Sync incomplete, requesting more frames for room {room_id:x} (current epoch: {current_epoch}, target: {})",
+                    sync_response.server_epoch
+                ),
+            });
+        } else {
+            if let Some(skipped) = next_log_index.and_then(|next| room.backfill.finish(next)) {
+                all_actions.push(ClientAction::BackfillPending {
+                    room_id,
+                    from_log_index: skipped.start,
+                    to_log_index: skipped.end,
+                });
+            }
+
+            all_actions.push(ClientAction::Log {
+                message: format!(
+                    "Sync complete for room {room_id:x}, now at epoch {current_epoch}"
+                ),
+            });
+
+            if self.intents_synced(room_id) {
+                all_actions.extend(self.replay_intents());
+            }
+        }
+
+        Ok(all_actions)
+    }
+
+    /// Request the next page of messages skipped by a fast-forward.
+    fn handle_backfill(&mut self, room_id: RoomId) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let Some(from_log_index) = room.backfill.next_page() else {
+            return Ok(vec![]);
+        };
+
+        let epoch = room.mls_group.epoch();
+        Ok(vec![ClientAction::RequestSync {
+            room_id,
+            from_epoch: epoch,
+            to_epoch: epoch,
+            from_log_index: Some(from_log_index),
+            mode: SyncMode::Backfill,
+        }])
+    }
+
+    /// Verify a signed checkpoint and ask the server to prove its Merkle
+    /// tree agrees with what we've seen so far.
+    fn handle_checkpoint(
+        &mut self,
+        room_id: RoomId,
+        frame: &Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let Some(key) = self.checkpoint_key else {
+            return Ok(vec![]);
+        };
+        let Some(room) = self.rooms.get_mut(&room_id) else {
+            return Ok(vec![]);
+        };
+
+        let checkpoint: Checkpoint =
+            ciborium::de::from_reader(&frame.payload[..]).map_err(|e| {
+                ClientError::InvalidFrame { reason: format!("Failed to decode Checkpoint: {e}") }
+            })?;
+
+        let frame_index = frame.header.log_index();
+        let verified = if verify_checkpoint(&key, room_id, &checkpoint) {
+            room.transcript
+                .observe_checkpoint(frame_index, &checkpoint)
+                .and_then(|()| room.transcript.observe_frame(frame))
+        } else {
+            Err(format!("checkpoint for log index {} has a bad signature", checkpoint.log_index))
+        };
+        if let Err(reason) = verified {
+            return Ok(vec![ClientAction::TranscriptViolation { room_id, reason }]);
+        }
+
+        let request = Payload::ProofRequest(room.transcript.next_proof_request(frame_index));
+        let mut header = FrameHeader::new(Opcode::ProofRequest);
+        header.set_room_id(room_id);
+        header.set_sender_id(self.identity.sender_id);
+        header.set_request_id(self.ids.next_request_id());
+        let frame = request
+            .into_frame(header)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+
+        Ok(vec![ClientAction::Send(frame)])
+    }
+
+    /// Check a Merkle proof from the server against the room transcript.
+    fn handle_proof_response(
+        &mut self,
+        room_id: RoomId,
+        frame: &Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let Some(transcript) = self.transcript_mut(room_id) else {
+            return Ok(vec![]);
+        };
+
+        let response: ProofResponse =
+            ciborium::de::from_reader(&frame.payload[..]).map_err(|e| {
+                ClientError::InvalidFrame { reason: format!("Failed to decode ProofResponse: {e}") }
+            })?;
+
+        match transcript.observe_proof(&response) {
+            Ok(()) => Ok(vec![]),
+            Err(reason) => Ok(vec![ClientAction::TranscriptViolation { room_id, reason }]),
+        }
+    }
+
+    /// Transcript for a room, if we're a member and verification is enabled.
+    fn transcript_mut(&mut self, room_id: RoomId) -> Option<&mut Transcript> {
+        self.checkpoint_key?;
+        self.rooms.get_mut(&room_id).map(|room| &mut room.transcript)
+    }
+
+    /// Handle add members request.
+    ///
+    /// Adds members to a room using their serialized KeyPackages.
+    /// Delegates to MlsGroup::add_members_from_bytes.
+    fn handle_add_members(
+        &mut self,
+        room_id: RoomId,
+        key_packages_bytes: Vec<Vec<u8>>,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let mls_actions = room
+            .mls_group
+            .add_members_from_bytes(&key_packages_bytes)
+            .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+
+        Ok(self.convert_mls_actions(room_id, mls_actions))
+    }
+
+    /// Commit the removal of members from a room.
+    fn handle_remove_members(
+        &mut self,
+        room_id: RoomId,
+        member_ids: &[u64],
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let mls_actions = room
+            .mls_group
+            .remove_members(member_ids)
+            .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+
+        Ok(self.convert_mls_actions(room_id, mls_actions))
+    }
+
+    /// Commit an update of our own leaf.
+    fn handle_update_self(&mut self, room_id: RoomId) -> Result<Vec<ClientAction>, ClientError> {
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let mls_actions =
+            room.mls_group.self_update().map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+
+        Ok(self.convert_mls_actions(room_id, mls_actions))
+    }
+
+    /// Change the room's message TTL with a commit setting it in the group
+    /// context.
+    ///
+    /// Nothing changes until the server sequences the commit and it comes
+    /// back to us.
+    fn handle_set_message_ttl(
+        &mut self,
+        room_id: RoomId,
+        ttl: Option<Duration>,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        if ttl.is_some_and(|ttl| ttl.as_secs() == 0) {
+            return Err(ClientError::InvalidState {
+                reason: "message TTL must be at least a second".to_string(),
+            });
+        }
+
+        let room = self.rooms.get_mut(&room_id).ok_or(ClientError::RoomNotFound { room_id })?;
+        let mls_actions = room
+            .mls_group
+            .set_message_ttl(ttl)
+            .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+
+        Ok(self.convert_mls_actions(room_id, mls_actions))
+    }
+
+    fn handle_revoke_sessions(
+        &self,
+        member_ids: Vec<u64>,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let mut header = FrameHeader::new(Opcode::RevokeSessions);
+        header.set_sender_id(self.identity.sender_id);
+        let frame = Payload::RevokeSessions(RevokeSessions { member_ids })
+            .into_frame(header)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+
+        Ok(vec![ClientAction::Send(frame)])
+    }
+
+    /// Commit revoked devices out of every room that still has them.
+    fn handle_sessions_revoked(&mut self, frame: Frame) -> Result<Vec<ClientAction>, ClientError> {
+        let Payload::SessionsRevoked(revoked) = Payload::from_frame(frame)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        else {
+            return Err(ClientError::InvalidFrame {
+                reason: "expected SessionsRevoked payload".to_string(),
+            });
+        };
+
+        let mut room_ids: Vec<RoomId> = self.rooms.keys().copied().collect();
+        room_ids.sort_unstable();
+
+        let mut actions = Vec::new();
+        for room_id in room_ids {
+            let Some(room) = self.rooms.get_mut(&room_id) else {
+                continue;
+            };
+            let group = &mut room.mls_group;
+            let present: Vec<u64> = group
+                .member_leaf_indices()
+                .into_iter()
+                .filter_map(|leaf_index| group.member_id_by_leaf_index(leaf_index))
+                .filter(|member_id| revoked.member_ids.contains(member_id))
+                .collect();
+            if present.is_empty() {
+                continue;
+            }
+
+            match group.remove_members(&present) {
+                Ok(mls_actions) => actions.extend(self.convert_mls_actions(room_id, mls_actions)),
+                Err(e) => actions.push(ClientAction::Log {
+                    message: format!("failed to remove revoked members from room {room_id:x}: {e}"),
+                }),
+            }
+        }
+
+        actions.push(ClientAction::SessionsRevoked {
+            member_ids: revoked.member_ids,
+            sessions_closed: revoked.sessions_closed,
+        });
+        Ok(actions)
+    }
+
+    /// Drop what the old server will never sequence and report where the
+    /// room went.
+    fn handle_room_moved(
+        &mut self,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let Payload::RoomMoved(moved) = Payload::from_frame(frame)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        else {
+            return Err(ClientError::InvalidFrame {
+                reason: "expected RoomMoved payload".to_string(),
+            });
+        };
+
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            room.mls_group.clear_pending_commit();
+        }
+        Ok(vec![ClientAction::RoomMoved {
+            room_id,
+            target: moved.target,
+            cutover_log_index: moved.cutover_log_index,
+        }])
+    }
+
+    fn handle_send_heartbeat(
+        &mut self,
+        server: ServerId,
+        now: Instant,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let heartbeat = Payload::Heartbeat(self.liveness_mut(server).heartbeats.start(now));
+        let frame = heartbeat
+            .into_frame(FrameHeader::new(Opcode::Heartbeat))
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+
+        if server == HOME_SERVER {
+            Ok(vec![ClientAction::Send(frame)])
+        } else {
+            Ok(vec![ClientAction::SendTo { server, frame }])
+        }
+    }
+
+    /// Echo server heartbeats and sample RTT to `server` from acks of our
+    /// own.
+    fn handle_heartbeat_frame(
+        &mut self,
+        server: ServerId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let payload = Payload::from_frame(frame)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+
+        match payload {
+            Payload::Heartbeat(heartbeat) => {
+                let ack = Payload::HeartbeatAck(heartbeat)
+                    .into_frame(FrameHeader::new(Opcode::HeartbeatAck))
+                    .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+                Ok(vec![ClientAction::Send(ack)])
+            },
+            Payload::HeartbeatAck(ack) => {
+                let now = self.env.now();
+                self.liveness_mut(server).heartbeats.on_ack(&ack, now);
+                Ok(vec![])
+            },
+            _ => {
+                Err(ClientError::InvalidFrame { reason: "expected heartbeat payload".to_string() })
+            },
+        }
+    }
+
+    /// Surface an error the server sent about one of our requests.
+    ///
+    /// A commit rejected for exceeding the room's member limit is dropped, so
+    /// the group stays at its current epoch.
+    fn handle_error_frame(
+        &mut self,
+        room_id: RoomId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let request_id = frame.header.request_id();
+        let Payload::Error(error) = Payload::from_frame(frame)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        else {
+            return Err(ClientError::InvalidFrame { reason: "expected error payload".to_string() });
+        };
+
+        // An error about another frame says nothing about the commit
+        let refused_commit = self.rooms.get_mut(&room_id).filter(|room| {
+            room.mls_group.has_pending_commit()
+                && room.commit.as_ref().is_some_and(|c| c.header.request_id() == request_id)
+        });
+
+        match error.room_full {
+            Some(room_full) if error.code == ErrorPayload::ROOM_FULL => {
+                if let Some(room) = refused_commit {
+                    room.mls_group.clear_pending_commit();
+                }
+                Ok(vec![ClientAction::RoomFull {
+                    room_id,
+                    max_members: room_full.max_members,
+                    member_count: room_full.member_count,
+                }])
+            },
+            _ => {
+                let recovery = Recovery::for_error(&error);
+                // A commit resent after the backoff stays pending meanwhile
+                if let Some(room) = refused_commit
+                    && !matches!(recovery, Recovery::Backoff { .. })
+                {
+                    room.mls_group.clear_pending_commit();
+                }
+
+                // A room's intents hold back everything else for it while it
+                // syncs, so the error is about the sync. Unless another sync
+                // is requested below, it will never complete.
+                let abandoned =
+                    if self.intents.awaiting_sync(room_id) && !matches!(recovery, Recovery::Sync) {
+                        self.intents.take_room(room_id)
+                    } else {
+                        Vec::new()
+                    };
+                let resolved: Vec<ClientAction> = abandoned
+                    .into_iter()
+                    .map(|queued| ClientAction::IntentResolved {
+                        intent_id: queued.id,
+                        room_id,
+                        queued_epoch: queued.queued_epoch,
+                        outcome: IntentOutcome::Failed {
+                            reason: format!("sync failed: {}", error.message),
+                            retryable: error.retryable,
+                        },
+                    })
+                    .collect();
+
+                let mut actions = vec![ClientAction::ServerError {
+                    room_id,
+                    request_id,
+                    code: error.code,
+                    message: error.message,
+                    recovery,
+                }];
+                match recovery {
+                    // A stale view of the room is why the frame was refused,
+                    // so catch up before anything is resent
+                    Recovery::Sync => {
+                        if let Some(epoch) = self.epoch(room_id) {
+                            actions.push(ClientAction::RequestSync {
+                                room_id,
+                                from_epoch: epoch,
+                                to_epoch: epoch.saturating_add(1),
+                                from_log_index: None,
+                                mode: SyncMode::Full,
+                            });
+                        }
+                    },
+                    Recovery::Backoff { retry_after } if request_id != 0 => {
+                        let at = self.env.now() + retry_after;
+                        self.retries.schedule(at, (room_id, request_id));
+                    },
+                    Recovery::Backoff { .. } | Recovery::GiveUp => {},
+                }
+                actions.extend(resolved);
+                Ok(actions)
+            },
+        }
+    }
+
+    /// Complete the handshake with `server`, answering its identity
+    /// challenge if it sent one, and adopt the server clock and the
+    /// capabilities both sides support.
+    fn handle_hello_reply(
+        &mut self,
+        server: ServerId,
+        frame: &Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let Payload::HelloReply(reply) = Payload::from_frame(frame.clone())
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        else {
+            return Err(ClientError::InvalidFrame {
+                reason: "expected HelloReply payload".to_string(),
+            });
+        };
+
+        let mut actions = Vec::new();
+        let capabilities = match self.servers.session_mut(server) {
+            Some(session) => {
+                for action in
+                    session.handle_frame(frame, self.env.now()).map_err(|e| handshake_error(&e))?
+                {
+                    match action {
+                        ConnectionAction::SendFrame(frame) => {
+                            actions.push(ClientAction::Send(frame));
+                        },
+                        ConnectionAction::Close { reason } => {
+                            return Err(ClientError::InvalidState { reason });
+                        },
+                    }
+                }
+                session.capabilities()
+            },
+            // The caller said Hello itself
+            None => CAPABILITIES & Capabilities::from_names(&reply.capabilities),
+        };
+        self.servers.set_capabilities(server, capabilities);
+
+        if let Some(time_sync) = reply.time_sync {
+            self.apply_time_sync(server, time_sync);
+        }
+
+        Ok(actions)
+    }
+
+    /// Adopt the server clock from a `TimeSync` frame.
+    fn handle_time_sync_frame(
+        &mut self,
+        server: ServerId,
+        frame: Frame,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let Payload::TimeSync(time_sync) = Payload::from_frame(frame)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+        else {
+            return Err(ClientError::InvalidFrame {
+                reason: "expected time sync payload".to_string(),
+            });
+        };
+
+        self.apply_time_sync(server, time_sync);
+        Ok(vec![])
+    }
+
+    /// Record the server clock offset and merge the server HLC.
+    ///
+    /// The reading is assumed to be half a round trip old.
+    fn apply_time_sync(&mut self, server: ServerId, time_sync: TimeSync) {
+        let local = self.env.wall_clock_millis();
+        let half_rtt = self
+            .server_rtt(server)
+            .smoothed()
+            .map_or(0, |rtt| u64::try_from(rtt.as_millis()).unwrap_or(u64::MAX) / 2);
+        let server_now = time_sync.wall_clock_millis.saturating_add(half_rtt);
+
+        let offset = i128::from(server_now).saturating_sub(i128::from(local));
+        let offset = i64::try_from(offset).unwrap_or(if offset < 0 { i64::MIN } else { i64::MAX });
+        self.servers.set_clock_offset_millis(server, offset);
+
+        self.clock.observe(HlcTimestamp::from_u64(time_sync.hlc), local);
+    }
+
+    /// Handle tick (timeout processing).
+    ///
+    /// For rooms whose pending commit timed out, clears the pending state and
+    /// emits `RequestSync` actions.
+    fn handle_tick(&mut self, now: Instant) -> Result<Vec<ClientAction>, ClientError> {
+        let mut actions = Vec::new();
+
+        for room_id in self.commit_timeouts.due(now) {
+            // The commit may have been sequenced or dropped since
+            let Some(room) = self.rooms.get_mut(&room_id) else {
+                continue;
+            };
+            if !room.mls_group.has_pending_commit() {
+                continue;
+            }
+
+            let current_epoch = room.mls_group.epoch();
+            room.mls_group.clear_pending_commit();
+
+            actions.push(ClientAction::RequestSync {
+                room_id,
+                from_epoch: current_epoch,
+                to_epoch: current_epoch.saturating_add(1), // next commit
+                from_log_index: None,
+                mode: SyncMode::Full,
+            });
+            actions.push(ClientAction::Log {
+                message: format!(
+                    "Commit timeout in room {room_id:x}, requesting sync from epoch {current_epoch}"
+                ),
+            });
+        }
+
+        let now_millis = self.server_time_millis();
+        let mut rekey = Vec::new();
+
+        let expired = self.pending_joins.expire(now_millis);
+        if expired > 0 {
+            actions.push(ClientAction::Log {
+                message: format!("Dropped {expired} KeyPackages no Welcome used in time"),
+            });
+        }
+
+        for (&room_id, room) in &mut self.rooms {
+            if room.backfill.expire(now) {
+                actions.push(ClientAction::Log {
+                    message: format!(
+                        "Backfill of room {room_id:x} abandoned, its retained keys expired"
+                    ),
+                });
+            }
+
+            let log_indices = room.expiry.sweep(now_millis);
+            if !log_indices.is_empty() {
+                actions.push(ClientAction::MessagesExpired { room_id, log_indices });
+            }
+
+            let idle_drafts = room.drafts.expire(now);
+            if idle_drafts > 0 {
+                actions.push(ClientAction::Log {
+                    message: format!("Dropped {idle_drafts} idle drafts in room {room_id:x}"),
+                });
+            }
+
+            if room.needs_rekey && !room.mls_group.has_pending_commit() {
+                rekey.push(room_id);
+            }
+        }
+
+        // A rekey commit that was lost or rejected is tried again
+        for room_id in rekey {
+            if self.servers.is_online(self.servers.home(room_id)) {
+                actions.extend(self.handle_update_self(room_id)?);
+            }
+        }
+
+        for (room_id, request_id) in self.retries.due(now) {
+            actions.extend(self.resend_refused(room_id, request_id)?);
+        }
+
+        actions.extend(self.check_connections(now)?);
+        Ok(actions)
+    }
+
+    /// Resend a frame the server refused with a backoff: our pending commit,
+    /// or a message still in the outbox. Nothing if the frame has since been
+    /// settled or its connection dropped, which requeues messages anyway.
+    fn resend_refused(
+        &mut self,
+        room_id: RoomId,
+        request_id: u32,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        if let Some(outgoing) = self.outbox.remove(room_id, request_id) {
+            return self.resend_message(outgoing);
+        }
+
+        let online = self.servers.is_online(self.servers.home(room_id));
+        let commit = self
+            .rooms
+            .get(&room_id)
+            .filter(|room| online && room.mls_group.has_pending_commit())
+            .and_then(|room| room.commit.as_ref())
+            .filter(|commit| commit.header.request_id() == request_id);
+        Ok(commit.map(|commit| ClientAction::Send(commit.clone())).into_iter().collect())
+    }
+
+    /// Probe silent server connections with a heartbeat, and give one up
+    /// once its heartbeat goes unacked for longer than its heartbeat timeout.
+    fn check_connections(&mut self, now: Instant) -> Result<Vec<ClientAction>, ClientError> {
+        let mut actions = Vec::new();
+        for server in self.servers.online() {
+            actions.extend(self.check_connection(server, now)?);
+        }
+        Ok(actions)
+    }
+
+    fn check_connection(
+        &mut self,
+        server: ServerId,
+        now: Instant,
+    ) -> Result<Vec<ClientAction>, ClientError> {
+        let liveness = self.liveness_mut(server);
+        if let Some(sent_at) = liveness.heartbeats.outstanding_since() {
+            let waited = now.saturating_duration_since(sent_at);
+            if waited < heartbeat_timeout(liveness.heartbeats.rtt()) {
+                return Ok(Vec::new());
+            }
+
+            let mut actions = self.handle_disconnected(server);
+            actions.push(ClientAction::Reconnect {
+                server,
+                reason: format!("heartbeat unacked after {}ms", waited.as_millis()),
+            });
+            return Ok(actions);
+        }
+
+        if now.saturating_duration_since(liveness.last_received) < HEARTBEAT_INTERVAL {
+            return Ok(Vec::new());
+        }
+        self.handle_send_heartbeat(server, now)
+    }
+
+    fn handle_leave_room(&mut self, room_id: RoomId) -> Result<Vec<ClientAction>, ClientError> {
+        if !self.rooms.contains_key(&room_id) {
+            return Err(ClientError::RoomNotFound { room_id });
+        }
+
+        let mut actions =
+            vec![ClientAction::RoomRemoved { room_id, reason: "Left room".to_string() }];
+        actions.extend(self.forget_room(room_id));
+        Ok(actions)
+    }
+
+    /// Drop everything kept for a room we are no longer a member of, wiping
+    /// its sender keys.
+    fn forget_room(&mut self, room_id: RoomId) -> Vec<ClientAction> {
+        if let Some(mut room) = self.rooms.remove(&room_id) {
+            room.sender_keys.purge();
+            room.backfill.purge();
+            room.mls_group.purge();
+        }
+        self.servers.forget_room(room_id);
+        self.outbox.take(|id| id == room_id);
+
+        // No sync will arrive for this room; don't hold up the queue on it
+        if self.intents_synced(room_id) { self.replay_intents() } else { Vec::new() }
+    }
+
+    /// Address a commit or proposal to its room, from us at the current
+    /// epoch, so the sequencer can place it and we recognize its echo.
+    fn stamp_handshake(&self, room_id: RoomId, mut frame: Frame, request_id: u32) -> Frame {
+        frame.header.set_room_id(room_id);
+        frame.header.set_request_id(request_id);
+        frame.header.set_sender_id(self.identity.sender_id);
+        if let Some(room) = self.rooms.get(&room_id) {
+            frame.header.set_epoch(room.mls_group.epoch());
+            room.mls_group.sign_frame_header(&mut frame.header);
+        }
+        frame
+    }
+
+    /// Convert MLS actions to client actions.
+    fn convert_mls_actions(
+        &mut self,
         room_id: RoomId,
         mls_actions: Vec<MlsAction>,
     ) -> Vec<ClientAction> {
-        mls_actions
-            .into_iter()
+        let mut actions = Vec::with_capacity(mls_actions.len());
+        for action in mls_actions {
+            actions.push(match action {
+                MlsAction::SendCommit(frame) => {
+                    let request_id = self.ids.next_request_id();
+                    let frame = self.stamp_handshake(room_id, frame, request_id);
+                    if let Some(room) = self.rooms.get_mut(&room_id) {
+                        room.commit = Some(frame.clone());
+                    }
+                    let timeout = commit_timeout(&self.server_rtt(self.servers.home(room_id)));
+                    self.commit_timeouts.schedule(self.env.now() + timeout, room_id);
+                    ClientAction::Send(frame)
+                },
+                MlsAction::SendProposal(frame) => {
+                    ClientAction::Send(self.stamp_handshake(room_id, frame, 0))
+                },
+                MlsAction::SendMessage(frame) => ClientAction::Send(frame),
+                MlsAction::SendWelcome { frame, .. } => ClientAction::Send(frame),
+                MlsAction::DeliverMessage { sender, plaintext } => {
+                    // MLS-decrypted message don't use sender keys path
+                    ClientAction::DeliverMessage {
+                        room_id,
+                        sender_id: sender,
+                        plaintext,
+                        log_index: 0,
+                        timestamp: 0,
+                        peer_verified: self.is_peer_verified(room_id, sender),
+                    }
+                },
+                MlsAction::RemoveGroup { reason } => ClientAction::RoomRemoved { room_id, reason },
+                MlsAction::Log { message } => ClientAction::Log { message },
+            });
+        }
+        actions
+    }
+}
+
+/// An application message after validation.
+enum Opened {
+    /// Decrypted for the first time.
+    Message {
+        /// Verified sender ID.
+        sender_id: u64,
+        /// Decrypted plaintext.
+        plaintext: Vec<u8>,
+    },
+    /// Already delivered; not decrypted again.
+    Duplicate(MessageId),
+}
+
+/// Secret every sender key of the group's current epoch derives from,
+/// zeroized when dropped.
+fn epoch_secret<E: Environment>(
+    mls_group: &MlsGroup<E>,
+) -> Result<Zeroizing<Vec<u8>>, ClientError> {
+    mls_group
+        .export_secret(SENDER_KEY_LABEL, SENDER_KEY_CONTEXT, SENDER_KEY_SECRET_SIZE)
+        .map(Zeroizing::new)
+        .map_err(|e| ClientError::Mls { reason: e.to_string() })
+}
+
+/// Validate an application message against `validation` and decrypt it.
+///
+/// Messages recorded in `seen` are reported as duplicates without touching
+/// the sender keys; newly decrypted ones are recorded.
+fn open_app_message(
+    frame: &Frame,
+    validation: &MlsGroupState,
+    member_id: impl Fn(u32) -> Option<u64>,
+    sender_keys: &mut SenderKeyStore,
+    seen: &mut SeenMessages,
+) -> Result<Opened, ClientError> {
+    let validation_result = MlsValidator::validate_frame(frame, validation.epoch, validation)
+        .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+    if let ValidationResult::Reject { reason } = validation_result {
+        let reason = MlsError::ValidationFailed(reason).to_string();
+        return Err(ClientError::InvalidFrame { reason });
+    }
+
+    let proto_encrypted = EncryptedMessage::decode(&frame.payload)
+        .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+
+    // Verify sender_id in header matches the sender_index from the encrypted
+    // payload. This prevents forgery where an attacker repackages a message
+    // with a different header.
+    let header_sender_id = frame.header.sender_id();
+    let verified_sender_id =
+        member_id(proto_encrypted.sender_index).ok_or_else(|| ClientError::InvalidFrame {
+            reason: format!(
+                "unknown sender_index {} in encrypted payload",
+                proto_encrypted.sender_index
+            ),
+        })?;
+
+    if header_sender_id != verified_sender_id {
+        return Err(ClientError::InvalidFrame {
+            reason: format!(
+                "sender_id mismatch: header claims {}, but sender_index {} belongs to {}",
+                header_sender_id, proto_encrypted.sender_index, verified_sender_id
+            ),
+        });
+    }
+
+    let id = MessageId {
+        sender_index: proto_encrypted.sender_index,
+        epoch: proto_encrypted.epoch,
+        generation: proto_encrypted.generation,
+    };
+    if seen.contains(&id) {
+        return Ok(Opened::Duplicate(id));
+    }
+
+    let encrypted = proto_to_crypto_encrypted(&proto_encrypted);
+    let plaintext = sender_keys.decrypt(&encrypted)?;
+    seen.insert(id);
+    Ok(Opened::Message { sender_id: verified_sender_id, plaintext })
+}
+
+/// A handshake frame we can't make or a `HelloReply` we can't accept.
+fn handshake_error(error: &ConnectionError) -> ClientError {
+    ClientError::InvalidFrame { reason: format!("handshake failed: {error}") }
+}
+
+/// Commit timeout for a connection with round-trip estimate `rtt`.
+fn commit_timeout(rtt: &RttEstimator) -> Duration {
+    if rtt.smoothed().is_none() {
+        return COMMIT_TIMEOUT;
+    }
+
+    rtt.retry_timeout()
+        .saturating_mul(COMMIT_TIMEOUT_RTT_FACTOR)
+        .clamp(MIN_COMMIT_TIMEOUT, MAX_COMMIT_TIMEOUT)
+}
+
+/// Heartbeat timeout for a connection with round-trip estimate `rtt`.
+fn heartbeat_timeout(rtt: &RttEstimator) -> Duration {
+    if rtt.smoothed().is_none() {
+        return HEARTBEAT_TIMEOUT;
+    }
+
+    rtt.retry_timeout()
+        .saturating_mul(HEARTBEAT_TIMEOUT_RTT_FACTOR)
+        .clamp(MIN_HEARTBEAT_TIMEOUT, MAX_HEARTBEAT_TIMEOUT)
+}
+
+/// Report a server going into maintenance.
+fn handle_maintenance(server: ServerId, frame: Frame) -> Result<Vec<ClientAction>, ClientError> {
+    let Payload::Maintenance(notice) = Payload::from_frame(frame)
+        .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+    else {
+        return Err(ClientError::InvalidFrame {
+            reason: "expected Maintenance payload".to_string(),
+        });
+    };
+
+    Ok(vec![ClientAction::ServerMaintenance {
+        server,
+        reconnect_after: Duration::from_secs(notice.reconnect_after_secs),
+        reason: notice.reason,
+    }])
+}
+
+/// Ask the home server for a page of its room directory.
+fn list_rooms(after: Option<RoomId>, limit: u32) -> Result<Vec<ClientAction>, ClientError> {
+    let frame = Payload::ListRooms(ListRooms { after, limit })
+        .into_frame(FrameHeader::new(Opcode::ListRooms))
+        .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?;
+
+    Ok(vec![ClientAction::Send(frame)])
+}
+
+/// Report a page of the room directory.
+fn handle_list_rooms_reply(frame: Frame) -> Result<Vec<ClientAction>, ClientError> {
+    let Payload::ListRoomsReply(reply) = Payload::from_frame(frame)
+        .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })?
+    else {
+        return Err(ClientError::InvalidFrame {
+            reason: "expected ListRoomsReply payload".to_string(),
+        });
+    };
+
+    Ok(vec![ClientAction::RoomDirectory { rooms: reply.rooms, more: reply.more }])
+}
+
+/// Whether frames with this opcode are sequenced into a room's log.
+fn is_sequenced(opcode: Opcode) -> bool {
+    !matches!(
+        opcode,
+        Opcode::Hello
+            | Opcode::HelloReply
+            | Opcode::Goodbye
+            | Opcode::Ping
+            | Opcode::Pong
+            | Opcode::SyncRequest
+            | Opcode::SyncResponse
+            | Opcode::Heartbeat
+            | Opcode::HeartbeatAck
+            | Opcode::TimeSync
+            | Opcode::ProofRequest
+            | Opcode::ProofResponse
+            | Opcode::RevokeSessions
+            | Opcode::SessionsRevoked
+            | Opcode::RoomMoved
+            | Opcode::ChallengeResponse
+            | Opcode::Maintenance
+            | Opcode::ListRooms
+            | Opcode::ListRoomsReply
+            | Opcode::Error
+            | Opcode::Welcome
+            | Opcode::Typing
+            | Opcode::AttachmentInit
+            | Opcode::AttachmentChunk
+            | Opcode::AttachmentComplete
+            | Opcode::AttachmentStatus
+            | Opcode::AttachmentFetch
+    )
+}
+
+fn crypto_to_proto_encrypted(crypto: &CryptoEncryptedMessage) -> EncryptedMessage {
+    EncryptedMessage {
+        epoch: crypto.epoch,
+        sender_index: crypto.sender_index,
+        generation: crypto.generation,
+        padding: crypto.padding,
+        cipher_suite: crypto.cipher_suite,
+        nonce: crypto.nonce,
+        ciphertext: crypto.ciphertext.clone(),
+        push_keys: None, // Not implemented yet
+    }
+}
+
+fn proto_to_crypto_encrypted(proto: &EncryptedMessage) -> CryptoEncryptedMessage {
+    CryptoEncryptedMessage {
+        epoch: proto.epoch,
+        sender_index: proto.sender_index,
+        generation: proto.generation,
+        padding: proto.padding,
+        cipher_suite: proto.cipher_suite,
+        nonce: proto.nonce,
+        ciphertext: proto.ciphertext.clone(),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    };
+
+    use lockframe_proto::FrameTiming;
+
+    use super::*;
+    use crate::key_packages::MAX_PENDING_KEY_PACKAGES;
+
+    struct ImmediateFuture;
+
+    impl Future for ImmediateFuture {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+            Poll::Ready(())
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestEnv;
+
+    impl Environment for TestEnv {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn sleep(&self, _duration: Duration) -> impl Future<Output = ()> + Send {
+            ImmediateFuture
+        }
+
+        fn random_bytes(&self, buffer: &mut [u8]) {
+            // Deterministic for tests
+            for (i, byte) in buffer.iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+        }
+    }
+
+    /// Environment whose random bytes differ on every call, for tests that
+    /// need real key material.
+    #[derive(Clone, Default)]
+    struct CountingEnv(std::sync::Arc<std::sync::atomic::AtomicU64>);
+
+    impl Environment for CountingEnv {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn sleep(&self, _duration: Duration) -> impl Future<Output = ()> + Send {
+            ImmediateFuture
+        }
+
+        fn random_bytes(&self, buffer: &mut [u8]) {
+            for chunk in buffer.chunks_mut(8) {
+                let n = self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                // splitmix64
+                let mut z = n.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^= z >> 31;
+                chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+            }
+        }
+    }
+
+    /// Environment whose clock only moves when the test advances it, with
+    /// random bytes as [`CountingEnv`]'s.
+    #[derive(Clone)]
+    struct ManualClock {
+        origin: Instant,
+        elapsed_nanos: std::sync::Arc<std::sync::atomic::AtomicU64>,
+        random: CountingEnv,
+    }
+
+    impl ManualClock {
+        fn new() -> Self {
+            Self {
+                origin: Instant::now(),
+                elapsed_nanos: std::sync::Arc::default(),
+                random: CountingEnv::default(),
+            }
+        }
+
+        /// Move the clock forward and return the new time.
+        fn advance(&self, by: Duration) -> Instant {
+            let by = u64::try_from(by.as_nanos()).unwrap();
+            self.elapsed_nanos.fetch_add(by, std::sync::atomic::Ordering::Relaxed);
+            self.now()
+        }
+    }
+
+    impl Environment for ManualClock {
+        fn now(&self) -> Instant {
+            let elapsed = self.elapsed_nanos.load(std::sync::atomic::Ordering::Relaxed);
+            self.origin + Duration::from_nanos(elapsed)
+        }
+
+        fn sleep(&self, _duration: Duration) -> impl Future<Output = ()> + Send {
+            ImmediateFuture
+        }
+
+        fn random_bytes(&self, buffer: &mut [u8]) {
+            self.random.random_bytes(buffer);
+        }
+    }
+
+    #[test]
+    fn create_client() {
+        let env = TestEnv;
+        let identity = ClientIdentity::new(42);
+        let client: Client<TestEnv> = Client::new(env, identity);
+
+        assert_eq!(client.sender_id(), 42);
+        assert_eq!(client.room_count(), 0);
+    }
+
+    #[test]
+    fn reconnect_delay_backs_off_with_jitter() {
+        let client = Client::new(CountingEnv::default(), ClientIdentity::new(1));
+
+        let first = client.reconnect_delay(1);
+        assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+
+        let fourth = client.reconnect_delay(4);
+        assert!(fourth >= Duration::from_secs(4) && fourth <= Duration::from_secs(8));
+
+        let capped = client.reconnect_delay(30);
+        assert!(capped >= Duration::from_secs(30) && capped <= Duration::from_secs(60));
+        assert_ne!(capped, client.reconnect_delay(30));
+    }
+
+    #[test]
+    fn heartbeat_ack_updates_rtt_and_commit_timeout() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        assert_eq!(client.commit_timeout(), COMMIT_TIMEOUT);
+
+        let actions = client.handle(ClientEvent::SendHeartbeat).unwrap();
+        let [ClientAction::Send(heartbeat)] = actions.as_slice() else {
+            panic!("expected heartbeat frame, got {actions:?}");
+        };
+        assert_eq!(heartbeat.header.opcode_enum(), Some(Opcode::Heartbeat));
+
+        // Server echoes the payload back under HeartbeatAck
+        let mut ack = heartbeat.clone();
+        ack.header = FrameHeader::new(Opcode::HeartbeatAck);
+        let actions = client.handle(ClientEvent::FrameReceived(ack)).unwrap();
+        assert!(actions.is_empty());
+
+        assert_eq!(client.rtt().sample_count(), 1);
+        assert!(client.commit_timeout() >= MIN_COMMIT_TIMEOUT);
+        assert!(client.commit_timeout() <= MAX_COMMIT_TIMEOUT);
+    }
+
+    #[test]
+    fn silent_connection_is_probed_with_heartbeats() {
+        let clock = ManualClock::new();
+        let mut client = Client::new(clock.clone(), ClientIdentity::new(42));
+
+        let now = clock.advance(HEARTBEAT_INTERVAL - Duration::from_secs(1));
+        assert!(client.handle(ClientEvent::Tick { now }).unwrap().is_empty());
+
+        let now = clock.advance(Duration::from_secs(1));
+        let actions = client.handle(ClientEvent::Tick { now }).unwrap();
+        let [ClientAction::Send(heartbeat)] = actions.as_slice() else {
+            panic!("expected heartbeat frame, got {actions:?}");
+        };
+        assert_eq!(heartbeat.header.opcode_enum(), Some(Opcode::Heartbeat));
+
+        // One probe at a time
+        let now = clock.advance(Duration::from_secs(1));
+        assert!(client.handle(ClientEvent::Tick { now }).unwrap().is_empty());
+
+        let mut ack = heartbeat.clone();
+        ack.header = FrameHeader::new(Opcode::HeartbeatAck);
+        client.handle(ClientEvent::FrameReceived(ack)).unwrap();
+        assert_eq!(client.rtt().latest(), Some(Duration::from_secs(1)));
+
+        // The ack counts as traffic, so the next probe waits a full interval
+        let now = clock.advance(HEARTBEAT_INTERVAL - Duration::from_secs(1));
+        assert!(client.handle(ClientEvent::Tick { now }).unwrap().is_empty());
+        let now = clock.advance(Duration::from_secs(1));
+        assert!(matches!(
+            client.handle(ClientEvent::Tick { now }).unwrap().as_slice(),
+            [ClientAction::Send(frame)] if frame.header.opcode_enum() == Some(Opcode::Heartbeat)
+        ));
+    }
+
+    #[test]
+    fn unacked_heartbeat_gives_up_the_connection() {
+        let clock = ManualClock::new();
+        let mut client = Client::new(clock.clone(), ClientIdentity::new(42));
+
+        let now = clock.advance(HEARTBEAT_INTERVAL);
+        assert_eq!(client.handle(ClientEvent::Tick { now }).unwrap().len(), 1);
+
+        let now = clock.advance(client.heartbeat_timeout() - Duration::from_millis(1));
+        assert!(client.handle(ClientEvent::Tick { now }).unwrap().is_empty());
+
+        let now = clock.advance(Duration::from_millis(1));
+        let actions = client.handle(ClientEvent::Tick { now }).unwrap();
+        assert!(
+            matches!(actions.last(), Some(ClientAction::Reconnect { server: HOME_SERVER, .. })),
+            "got {actions:?}"
+        );
+
+        // Offline: intents queue and ticks leave the connection alone
+        let room_id = 0x1234;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+        client.handle(ClientEvent::SendMessage { room_id, plaintext: b"hi".to_vec() }).unwrap();
+        assert_eq!(client.queued_intents(), 1);
+        let now = clock.advance(HEARTBEAT_INTERVAL);
+        assert!(client.handle(ClientEvent::Tick { now }).unwrap().is_empty());
+
+        // A fresh connection starts a fresh idle interval
+        client.handle(ClientEvent::Reconnected).unwrap();
+        let now = clock.advance(HEARTBEAT_INTERVAL - Duration::from_secs(1));
+        assert!(
+            !client
+                .handle(ClientEvent::Tick { now })
+                .unwrap()
+                .iter()
+                .any(|action| matches!(action, ClientAction::Reconnect { .. }))
+        );
+    }
+
+    #[test]
+    fn other_servers_are_probed_on_their_own_connection() {
+        let clock = ManualClock::new();
+        let mut client = Client::new(clock.clone(), ClientIdentity::new(42));
+        client.handle(ClientEvent::Disconnected).unwrap();
+        client.handle(ClientEvent::ServerConnected { server: 1 }).unwrap();
+
+        let now = clock.advance(HEARTBEAT_INTERVAL);
+        let actions = client.handle(ClientEvent::Tick { now }).unwrap();
+        let [ClientAction::SendTo { server: 1, frame: heartbeat }] = actions.as_slice() else {
+            panic!("expected heartbeat to server 1, got {actions:?}");
+        };
+        assert_eq!(heartbeat.header.opcode_enum(), Some(Opcode::Heartbeat));
+
+        // The ack samples that server's RTT, not the home server's
+        clock.advance(Duration::from_secs(1));
+        let mut ack = heartbeat.clone();
+        ack.header = FrameHeader::new(Opcode::HeartbeatAck);
+        client.handle(ClientEvent::FrameReceivedFrom { server: 1, frame: ack }).unwrap();
+        assert_eq!(client.server_rtt(1).latest(), Some(Duration::from_secs(1)));
+        assert_eq!(client.rtt().sample_count(), 0);
+
+        let now = clock.advance(HEARTBEAT_INTERVAL);
+        assert_eq!(client.handle(ClientEvent::Tick { now }).unwrap().len(), 1);
+        let now = clock.advance(heartbeat_timeout(&client.server_rtt(1)));
+        let actions = client.handle(ClientEvent::Tick { now }).unwrap();
+        assert!(
+            matches!(actions.last(), Some(ClientAction::Reconnect { server: 1, .. })),
+            "got {actions:?}"
+        );
+    }
+
+    #[test]
+    fn unsequenced_commit_times_out_into_a_sync() {
+        use lockframe_proto::payloads::session::Heartbeat;
+
+        let clock = ManualClock::new();
+        let mut alice = Client::new(clock.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(clock.clone(), ClientIdentity::new(2));
+        let room_id = 0x1234;
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        alice.handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] }).unwrap();
+        assert!(alice.rooms[&room_id].mls_group.has_pending_commit());
+
+        // Keep the connection busy so only the commit can time out
+        let tick = |client: &mut Client<ManualClock>, by: Duration| {
+            let now = clock.advance(by);
+            let ack = Payload::HeartbeatAck(Heartbeat { timestamp_micros: 0 })
+                .into_frame(FrameHeader::new(Opcode::HeartbeatAck))
+                .unwrap();
+            client.handle(ClientEvent::FrameReceived(ack)).unwrap();
+            client.handle(ClientEvent::Tick { now }).unwrap()
+        };
+
+        let timeout = alice.commit_timeout();
+        assert!(tick(&mut alice, timeout - Duration::from_secs(1)).is_empty());
+        let actions = tick(&mut alice, Duration::from_secs(1));
+        assert!(
+            matches!(actions.as_slice(), [
+                ClientAction::RequestSync { room_id: 0x1234, from_epoch: 0, to_epoch: 1, .. },
+                ClientAction::Log { .. }
+            ]),
+            "got {actions:?}"
+        );
+        assert!(!alice.rooms[&room_id].mls_group.has_pending_commit());
+        assert!(tick(&mut alice, timeout).is_empty());
+    }
+
+    #[test]
+    fn timed_frames_feed_latency_distributions() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        let now = client.server_time_millis();
+
+        let mut header = FrameHeader::new(Opcode::Heartbeat);
+        header.set_hlc_timestamp(HlcTimestamp::new(now.saturating_sub(100), 0).as_u64());
+        let frame = Payload::Heartbeat(lockframe_proto::payloads::session::Heartbeat {
+            timestamp_micros: 7,
+        })
+        .into_frame(header)
+        .unwrap();
+        let timing =
+            FrameTiming { received_at_millis: now.saturating_sub(60), processing_micros: 5_000 };
+
+        // Still handled like any other frame
+        let actions = client.handle(ClientEvent::TimedFrameReceived { frame, timing }).unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::Send(_)]));
+
+        let latency = client.latency();
+        assert_eq!(latency.server.max(), Some(Duration::from_millis(5)));
+        assert!(latency.upload.max().unwrap() >= Duration::from_millis(40));
+        assert!(latency.download.max().unwrap() >= Duration::from_millis(55));
+        assert!(latency.end_to_end.max().unwrap() >= Duration::from_millis(100));
+
+        // Untimed frames are not sampled
+        let plain = Payload::Heartbeat(lockframe_proto::payloads::session::Heartbeat {
+            timestamp_micros: 8,
+        })
+        .into_frame(FrameHeader::new(Opcode::Heartbeat))
+        .unwrap();
+        client.handle(ClientEvent::FrameReceived(plain)).unwrap();
+        assert_eq!(client.latency().end_to_end.count(), 1);
+    }
+
+    #[test]
+    fn server_heartbeat_is_echoed() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+
+        let heartbeat = Payload::Heartbeat(lockframe_proto::payloads::session::Heartbeat {
+            timestamp_micros: 7,
+        })
+        .into_frame(FrameHeader::new(Opcode::Heartbeat))
+        .unwrap();
+
+        let actions = client.handle(ClientEvent::FrameReceived(heartbeat.clone())).unwrap();
+        let [ClientAction::Send(ack)] = actions.as_slice() else {
+            panic!("expected heartbeat ack, got {actions:?}");
+        };
+        assert_eq!(ack.header.opcode_enum(), Some(Opcode::HeartbeatAck));
+        assert_eq!(ack.payload, heartbeat.payload);
+        assert_eq!(client.rtt().sample_count(), 0);
+    }
+
+    #[test]
+    fn time_sync_sets_server_offset_and_seeds_hlc() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+
+        let local = TestEnv.wall_clock_millis();
+        let server_hlc = HlcTimestamp::new(local + 60_000, 3);
+        let time_sync = TimeSync { wall_clock_millis: local + 60_000, hlc: server_hlc.as_u64() };
+        let frame =
+            Payload::TimeSync(time_sync).into_frame(FrameHeader::new(Opcode::TimeSync)).unwrap();
+
+        let actions = client.handle(ClientEvent::FrameReceived(frame)).unwrap();
+        assert!(actions.is_empty());
+
+        // Server runs a minute ahead; allow for test execution time
+        let skew = client.server_time_millis().abs_diff(TestEnv.wall_clock_millis() + 60_000);
+        assert!(skew < 1_000, "server time off by {skew}ms");
+        assert!(client.hlc() > server_hlc);
+
+        // Outgoing messages are stamped after the server clock
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+        let actions =
+            client.handle(ClientEvent::SendMessage { room_id, plaintext: b"hi".to_vec() }).unwrap();
+        let [ClientAction::Send(frame), ClientAction::DeliveryUpdate { .. }] = actions.as_slice()
+        else {
+            panic!("expected message frame, got {actions:?}");
+        };
+        assert!(HlcTimestamp::from_u64(frame.header.hlc_timestamp()) > server_hlc);
+    }
+
+    #[test]
+    fn checkpoint_is_verified_and_triggers_proof_request() {
+        use ed25519_dalek::SigningKey;
+        use lockframe_core::checkpoint::{LogHash, sign_checkpoint};
+
+        let room_id = 0x1234_u128;
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        client.set_checkpoint_key(key.verifying_key());
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        let message = Frame::new(header, b"sealed".to_vec());
+        // Undecryptable, but still part of the log
+        assert!(client.handle(ClientEvent::FrameReceived(message.clone())).is_err());
+
+        let checkpoint_frame = |checkpoint| {
+            let mut header = FrameHeader::new(Opcode::Checkpoint);
+            header.set_room_id(room_id);
+            header.set_log_index(1);
+            Payload::Checkpoint(checkpoint).into_frame(header).unwrap()
+        };
+
+        let forged =
+            sign_checkpoint(&SigningKey::from_bytes(&[8; 32]), room_id, 0, LogHash::GENESIS);
+        let actions = client.handle(ClientEvent::FrameReceived(checkpoint_frame(forged))).unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::TranscriptViolation { .. }]));
+
+        let log_hash = LogHash::GENESIS.chain(&message);
+        let checkpoint = sign_checkpoint(&key, room_id, 0, log_hash);
+        let actions =
+            client.handle(ClientEvent::FrameReceived(checkpoint_frame(checkpoint))).unwrap();
+        let [ClientAction::Send(request)] = actions.as_slice() else {
+            panic!("expected proof request, got {actions:?}");
+        };
+        assert_eq!(request.header.opcode_enum(), Some(Opcode::ProofRequest));
+        assert_ne!(request.header.request_id(), 0);
+        assert_eq!(request.header.room_id(), room_id);
+    }
+
+    #[test]
+    fn create_room() {
+        let env = TestEnv;
+        let identity = ClientIdentity::new(42);
+        let mut client = Client::new(env, identity);
+
+        let room_id = 0x1234_5678_9abc_def0_u128;
+        let actions = client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        assert!(client.is_member(room_id));
+        assert_eq!(client.epoch(room_id), Some(0));
+        assert!(!actions.is_empty());
+    }
+
+    #[test]
+    fn create_duplicate_room_fails() {
+        let env = TestEnv;
+        let identity = ClientIdentity::new(42);
+        let mut client = Client::new(env, identity);
+
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let result = client.handle(ClientEvent::CreateRoom { room_id });
+        assert!(matches!(result, Err(ClientError::RoomAlreadyExists { .. })));
+    }
+
+    #[test]
+    fn send_message_to_unknown_room_fails() {
+        let env = TestEnv;
+        let identity = ClientIdentity::new(42);
+        let mut client = Client::new(env, identity);
+
+        let result = client.handle(ClientEvent::SendMessage {
+            room_id: 0x9999_u128,
+            plaintext: b"hello".to_vec(),
+        });
+
+        assert!(matches!(result, Err(ClientError::RoomNotFound { .. })));
+    }
+
+    #[test]
+    fn leave_room() {
+        let env = TestEnv;
+        let identity = ClientIdentity::new(42);
+        let mut client = Client::new(env, identity);
+
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+        assert!(client.is_member(room_id));
+
+        let actions = client.handle(ClientEvent::LeaveRoom { room_id }).unwrap();
+        assert!(!client.is_member(room_id));
+        assert!(matches!(actions[0], ClientAction::RoomRemoved { .. }));
+    }
+
+    #[test]
+    fn leave_unknown_room_fails() {
+        let env = TestEnv;
+        let identity = ClientIdentity::new(42);
+        let mut client = Client::new(env, identity);
+
+        let result = client.handle(ClientEvent::LeaveRoom { room_id: 0x9999_u128 });
+        assert!(matches!(result, Err(ClientError::RoomNotFound { .. })));
+    }
+
+    #[test]
+    fn send_message_produces_encrypted_frame() {
+        let env = TestEnv;
+        let identity = ClientIdentity::new(42);
+        let mut client = Client::new(env, identity);
+
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let actions = client
+            .handle(ClientEvent::SendMessage { room_id, plaintext: b"Hello, World!".to_vec() })
+            .unwrap();
+
+        // Should produce a Send action with encrypted frame, then report it sent
+        assert_eq!(actions.len(), 2);
+        assert!(matches!(actions[1], ClientAction::DeliveryUpdate {
+            message_id: 0,
+            state: DeliveryState::Sent,
+            ..
+        }));
+        match &actions[0] {
+            ClientAction::Send(frame) => {
+                assert_eq!(frame.header.opcode_enum(), Some(Opcode::AppMessage));
+                assert_eq!(frame.header.room_id(), room_id);
+                // Payload should be encrypted (not plaintext)
+                assert!(!frame.payload.is_empty());
+                assert_ne!(frame.payload.as_ref(), b"Hello, World!");
+
+                // The room's MLS ciphersuite uses AES-GCM, so messages do too
+                let encrypted = EncryptedMessage::decode(&frame.payload).unwrap();
+                assert_eq!(encrypted.cipher_suite, CipherSuite::Aes256Gcm.id());
+            },
+            _ => panic!("Expected Send action"),
+        }
+    }
+
+    #[test]
+    fn rooms_encrypt_with_the_aead_of_their_ciphersuite() {
+        let room_id = 0x1234;
+        let chacha = 0x0003;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.set_ciphersuite(chacha);
+        bob.set_ciphersuite(chacha);
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        let mut message = sent(
+            &alice
+                .handle(ClientEvent::SendMessage { room_id, plaintext: b"chacha".to_vec() })
+                .unwrap(),
+            Opcode::AppMessage,
+        )
+        .remove(0);
+        let encrypted = EncryptedMessage::decode(&message.payload).unwrap();
+        assert_eq!(encrypted.cipher_suite, CipherSuite::XChaCha20Poly1305.id());
+
+        message.header.set_log_index(2);
+        let actions = bob.handle(ClientEvent::FrameReceived(message)).unwrap();
+        assert!(
+            matches!(
+                actions.as_slice(),
+                [ClientAction::DeliverMessage { plaintext, .. }] if plaintext == b"chacha"
+            ),
+            "got {actions:?}"
+        );
+
+        // A ciphersuite the provider lacks fails at room creation
+        alice.set_ciphersuite(0x0006);
+        assert!(matches!(
+            alice.handle(ClientEvent::CreateRoom { room_id: 0x5678 }),
+            Err(ClientError::Mls { .. })
+        ));
+    }
+
+    #[test]
+    fn app_message_with_invalid_signature_is_rejected() {
+        let env = TestEnv;
+        let identity = ClientIdentity::new(42);
+        let mut client = Client::new(env, identity);
+
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_sender_id(42);
+        header.set_epoch(0);
+        let frame = Frame::new(header, Vec::<u8>::new());
+
+        let result = client.handle(ClientEvent::FrameReceived(frame));
+        assert!(matches!(result, Err(ClientError::InvalidFrame { .. })));
+    }
+
+    fn sync_complete_frame(room_id: RoomId) -> Frame {
+        let response = SyncResponse {
+            frames: vec![],
+            has_more: false,
+            server_epoch: 0,
+            mode: SyncMode::Full,
+            next_log_index: None,
+        };
+        let mut payload = Vec::new();
+        ciborium::ser::into_writer(&response, &mut payload).unwrap();
+
+        let mut header = FrameHeader::new(Opcode::SyncResponse);
+        header.set_room_id(room_id);
+        Frame::new(header, payload)
+    }
+
+    #[test]
+    fn offline_intents_replay_in_order_after_sync() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        client.handle(ClientEvent::Disconnected).unwrap();
+        assert!(!client.is_online());
+
+        for (expected_id, text) in [b"first", b"later"].into_iter().enumerate() {
+            let actions = client
+                .handle(ClientEvent::SendMessage { room_id, plaintext: text.to_vec() })
+                .unwrap();
+            let [ClientAction::IntentQueued { intent_id, .. }] = actions.as_slice() else {
+                panic!("expected queued intent, got {actions:?}");
+            };
+            assert_eq!(*intent_id, expected_id as u64);
+        }
+
+        // Unknown rooms are still rejected up front
+        let result =
+            client.handle(ClientEvent::SendMessage { room_id: 0x9999, plaintext: b"x".to_vec() });
+        assert!(matches!(result, Err(ClientError::RoomNotFound { .. })));
+
+        let actions = client.handle(ClientEvent::Reconnected).unwrap();
+        assert!(matches!(actions[0], ClientAction::RequestSync { room_id: r, .. } if r == room_id));
+        assert_eq!(client.queued_intents(), 2);
+
+        // Sent while the queue waits for sync: goes behind the queued intents
+        let actions = client
+            .handle(ClientEvent::SendMessage { room_id, plaintext: b"last".to_vec() })
+            .unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::IntentQueued { intent_id: 2, .. }]));
+
+        let actions =
+            client.handle(ClientEvent::FrameReceived(sync_complete_frame(room_id))).unwrap();
+        let generations: Vec<u32> = actions
+            .iter()
             .filter_map(|action| match action {
-                MlsAction::SendCommit(frame)
-                | MlsAction::SendProposal(frame)
-                | MlsAction::SendMessage(frame) => Some(ClientAction::Send(frame)),
-                MlsAction::SendWelcome { frame, .. } => Some(ClientAction::Send(frame)),
-                MlsAction::DeliverMessage { sender, plaintext } => {
-                    // MLS-decrypted message don't use sender keys path
-                    Some(ClientAction::DeliverMessage {
-                        room_id,
-                        sender_id: sender,
-                        plaintext,
-                        log_index: 0,
-                        timestamp: 0,
-                    })
+                ClientAction::Send(frame) => {
+                    Some(EncryptedMessage::decode(&frame.payload).unwrap().generation)
                 },
-                MlsAction::RemoveGroup { reason } => {
-                    Some(ClientAction::RoomRemoved { room_id, reason })
+                _ => None,
+            })
+            .collect();
+        assert_eq!(generations, vec![0, 1, 2]);
+
+        let resolved: Vec<(u64, IntentOutcome)> = actions
+            .into_iter()
+            .filter_map(|action| match action {
+                ClientAction::IntentResolved { intent_id, outcome, .. } => {
+                    Some((intent_id, outcome))
                 },
-                MlsAction::Log { message } => Some(ClientAction::Log { message }),
+                _ => None,
             })
-            .collect()
+            .collect();
+        assert_eq!(resolved, vec![
+            (0, IntentOutcome::Sent { epoch: 0 }),
+            (1, IntentOutcome::Sent { epoch: 0 }),
+            (2, IntentOutcome::Sent { epoch: 0 }),
+        ]);
+        assert_eq!(client.queued_intents(), 0);
     }
-}
 
-fn crypto_to_proto_encrypted(crypto: &CryptoEncryptedMessage) -> EncryptedMessage {
-    EncryptedMessage {
-        epoch: crypto.epoch,
-        sender_index: crypto.sender_index,
-        generation: crypto.generation,
-        nonce: crypto.nonce,
-        ciphertext: crypto.ciphertext.clone(),
-        push_keys: None, // Not implemented yet
+    #[test]
+    fn intents_fail_when_their_sync_is_refused() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        client.handle(ClientEvent::Disconnected).unwrap();
+        client.handle(ClientEvent::SendMessage { room_id, plaintext: b"hi".to_vec() }).unwrap();
+        client.handle(ClientEvent::Reconnected).unwrap();
+
+        let mut header = FrameHeader::new(Opcode::Error);
+        header.set_room_id(room_id);
+        let refused = Payload::Error(ErrorPayload::room_not_found(room_id)).into_frame(header);
+        let actions = client.handle(ClientEvent::FrameReceived(refused.unwrap())).unwrap();
+        assert!(
+            actions.iter().any(|action| matches!(action, ClientAction::IntentResolved {
+                intent_id: 0,
+                outcome: IntentOutcome::Failed { retryable: false, .. },
+                ..
+            })),
+            "got {actions:?}"
+        );
+        assert_eq!(client.queued_intents(), 0);
+
+        // Nothing holds the room's intents back any more
+        let actions =
+            client.handle(ClientEvent::SendMessage { room_id, plaintext: b"again".to_vec() });
+        assert_eq!(sent(&actions.unwrap(), Opcode::AppMessage).len(), 1);
+    }
+
+    #[test]
+    fn rooms_replay_without_waiting_for_other_servers() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        let (here, elsewhere) = (0x1234_u128, 0x5678_u128);
+        client.handle(ClientEvent::CreateRoom { room_id: here }).unwrap();
+        client.handle(ClientEvent::CreateRoom { room_id: elsewhere }).unwrap();
+        client.handle(ClientEvent::MoveRoom { room_id: elsewhere, server: 1 }).unwrap();
+
+        // The other server never connects, yet rooms homed here don't queue
+        let actions = client
+            .handle(ClientEvent::SendMessage { room_id: elsewhere, plaintext: b"x".to_vec() })
+            .unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::IntentQueued { .. }]));
+        let actions =
+            client.handle(ClientEvent::SendMessage { room_id: here, plaintext: b"y".to_vec() });
+        assert_eq!(sent(&actions.unwrap(), Opcode::AppMessage).len(), 1);
+
+        // Nor do they wait for its rooms to replay after a reconnect
+        client.handle(ClientEvent::Disconnected).unwrap();
+        client
+            .handle(ClientEvent::SendMessage { room_id: here, plaintext: b"z".to_vec() })
+            .unwrap();
+        client.handle(ClientEvent::Reconnected).unwrap();
+        let actions = client.handle(ClientEvent::FrameReceived(sync_complete_frame(here))).unwrap();
+        // The message in flight is resent, then the one queued
+        assert_eq!(sent(&actions, Opcode::AppMessage).len(), 2);
+        assert_eq!(client.queued_intents(), 1);
+    }
+
+    #[test]
+    fn interrupted_messages_resend_unchanged_after_reconnect() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let actions = client
+            .handle(ClientEvent::SendMessage { room_id, plaintext: b"hello".to_vec() })
+            .unwrap();
+        assert!(matches!(
+            actions.last(),
+            Some(ClientAction::DeliveryUpdate { message_id: 0, state: DeliveryState::Sent, .. })
+        ));
+        let original = sent(&actions, Opcode::AppMessage).remove(0);
+        assert_eq!(client.unsequenced_messages(), 1);
+
+        // Lost with the connection, as far as the client can tell
+        client.handle(ClientEvent::Disconnected).unwrap();
+        assert_eq!(client.unsequenced_messages(), 0);
+        assert_eq!(client.queued_intents(), 1);
+
+        client.handle(ClientEvent::Reconnected).unwrap();
+        let actions =
+            client.handle(ClientEvent::FrameReceived(sync_complete_frame(room_id))).unwrap();
+        let resent = sent(&actions, Opcode::AppMessage);
+        assert_eq!(resent.len(), 1);
+        assert_eq!(resent[0].payload, original.payload);
+        assert_eq!(client.unsequenced_messages(), 1);
+
+        // The sequenced copy settles it, and a second copy is only logged
+        let mut sequenced = original;
+        sequenced.header.set_log_index(3);
+        let actions = client.handle(ClientEvent::FrameReceived(sequenced.clone())).unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::DeliveryUpdate {
+            message_id: 0,
+            state: DeliveryState::Sequenced { log_index: 3 },
+            ..
+        }]));
+        assert_eq!(client.unsequenced_messages(), 0);
+
+        let actions = client.handle(ClientEvent::FrameReceived(sequenced)).unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::Log { .. }]));
+    }
+
+    #[test]
+    fn offline_intent_for_left_room_resolves_as_gone() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        client.handle(ClientEvent::Disconnected).unwrap();
+        client.handle(ClientEvent::SendMessage { room_id, plaintext: b"hi".to_vec() }).unwrap();
+        client.handle(ClientEvent::LeaveRoom { room_id }).unwrap();
+
+        let actions = client.handle(ClientEvent::Reconnected).unwrap();
+        assert!(actions.iter().any(|action| matches!(action, ClientAction::IntentResolved {
+            intent_id: 0,
+            outcome: IntentOutcome::RoomGone,
+            ..
+        })));
+        assert!(!actions.iter().any(|action| matches!(action, ClientAction::RequestSync { .. })));
+    }
+
+    #[test]
+    fn rooms_homed_on_another_server_are_routed_there() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        // Not connected to the new home yet: nothing to sync from
+        let actions = client.handle(ClientEvent::MoveRoom { room_id, server: 1 }).unwrap();
+        assert!(!actions.iter().any(|action| matches!(action, ClientAction::RequestSync { .. })));
+        assert_eq!(client.home_server(room_id), 1);
+        assert!(client.is_online());
+
+        let actions =
+            client.handle(ClientEvent::SendMessage { room_id, plaintext: b"hi".to_vec() }).unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::IntentQueued { intent_id: 0, .. }]));
+
+        // Synced once, though both the move and the queued intent need it
+        let actions = client.handle(ClientEvent::ServerConnected { server: 1 }).unwrap();
+        let syncs = actions
+            .iter()
+            .filter(|action| matches!(action, ClientAction::RequestSync { .. }))
+            .count();
+        assert_eq!(syncs, 1);
+
+        // The old server no longer speaks for the room
+        let actions =
+            client.handle(ClientEvent::FrameReceived(sync_complete_frame(room_id))).unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::Log { .. }]));
+        assert_eq!(client.queued_intents(), 1);
+
+        let actions = client
+            .handle(ClientEvent::FrameReceivedFrom {
+                server: 1,
+                frame: sync_complete_frame(room_id),
+            })
+            .unwrap();
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            ClientAction::SendTo { server: 1, frame }
+                if frame.header.opcode_enum() == Some(Opcode::AppMessage)
+        )));
+        assert_eq!(client.queued_intents(), 0);
+
+        // Frames for no room are answered on the connection they came from
+        let heartbeat = Payload::Heartbeat(lockframe_proto::payloads::session::Heartbeat {
+            timestamp_micros: 7,
+        })
+        .into_frame(FrameHeader::new(Opcode::Heartbeat))
+        .unwrap();
+        let actions = client
+            .handle(ClientEvent::FrameReceivedFrom { server: 1, frame: heartbeat.clone() })
+            .unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::SendTo { server: 1, .. }]));
+        let actions = client.handle(ClientEvent::FrameReceived(heartbeat)).unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::Send(_)]));
+
+        // Losing the new home leaves the home server connected
+        client.handle(ClientEvent::ServerDisconnected { server: 1 }).unwrap();
+        assert!(client.is_online());
+        assert!(!client.is_server_online(1));
+    }
+
+    #[test]
+    fn encrypt_decrypt_roundtrip_same_client() {
+        // NOTE: This test demonstrates a known limitation.
+        // In the current sender key design, sender and receiver share the same ratchet.
+        // When you send (encrypt), your ratchet advances. When you receive your own
+        // message back, decryption fails because the ratchet already moved forward.
+        //
+        // In production:
+        // 1. Servers shouldn't echo your own messages back to you
+        // 2. Or: We need separate send/receive ratchets per member
+        //
+        // For now, we test that encryption works and produces valid output,
+        // and that the serialization roundtrips correctly.
+
+        let env = TestEnv;
+        let identity = ClientIdentity::new(42);
+        let mut client = Client::new(env, identity);
+
+        let room_id = 0x1234_u128;
+        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        // Send a message
+        let plaintext = b"Secret message";
+        let actions = client
+            .handle(ClientEvent::SendMessage { room_id, plaintext: plaintext.to_vec() })
+            .unwrap();
+
+        let frame = match &actions[0] {
+            ClientAction::Send(f) => f.clone(),
+            _ => panic!("Expected Send action"),
+        };
+
+        // Verify the encrypted payload can be deserialized
+        let encrypted = EncryptedMessage::decode(&frame.payload).unwrap();
+        assert_eq!(encrypted.epoch, 0);
+        assert_eq!(encrypted.sender_index, 0); // Creator is leaf 0
+        assert_eq!(encrypted.generation, 0); // First message
+        assert!(!encrypted.ciphertext.is_empty());
+
+        // Verify sender's ratchet advanced
+        let room = client.rooms.get(&room_id).unwrap();
+        assert_eq!(room.sender_keys.generation(0), Some(1)); // Now at gen 1
+    }
+
+    #[test]
+    fn room_full_error_drops_pending_commit() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        let [commit] = sent(&actions, Opcode::Commit).try_into().unwrap();
+        assert!(alice.rooms[&room_id].mls_group.has_pending_commit());
+
+        // An error about some other frame leaves the commit alone
+        let mut header = FrameHeader::new(Opcode::Error);
+        header.set_room_id(room_id);
+        header.set_request_id(commit.header.request_id().wrapping_add(1));
+        let error = Payload::Error(ErrorPayload::room_full(1, 2)).into_frame(header).unwrap();
+        alice.handle(ClientEvent::FrameReceived(error)).unwrap();
+        assert!(alice.rooms[&room_id].mls_group.has_pending_commit());
+
+        header.set_request_id(commit.header.request_id());
+        let error = Payload::Error(ErrorPayload::room_full(1, 2)).into_frame(header).unwrap();
+        let actions = alice.handle(ClientEvent::FrameReceived(error)).unwrap();
+        assert!(
+            matches!(actions.as_slice(), [ClientAction::RoomFull {
+                room_id: 0x1234,
+                max_members: 1,
+                member_count: 2
+            }]),
+            "got {actions:?}"
+        );
+        assert!(!alice.rooms[&room_id].mls_group.has_pending_commit());
+        assert_eq!(alice.epoch(room_id), Some(0));
+    }
+
+    #[test]
+    fn sequencer_error_requests_sync() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        let [commit] = sent(&actions, Opcode::Commit).try_into().unwrap();
+        let request_id = commit.header.request_id();
+        assert_ne!(request_id, 0);
+
+        let mut header = FrameHeader::new(Opcode::Error);
+        header.set_room_id(room_id);
+        header.set_request_id(request_id);
+        let error = Payload::Error(ErrorPayload::sequencer_error("epoch mismatch"))
+            .into_frame(header)
+            .unwrap();
+
+        let actions = alice.handle(ClientEvent::FrameReceived(error)).unwrap();
+        assert!(
+            matches!(actions.as_slice(), [
+                ClientAction::ServerError { recovery: Recovery::Sync, request_id: id, .. },
+                ClientAction::RequestSync { from_epoch: 0, to_epoch: 1, .. }
+            ] if *id == request_id),
+            "got {actions:?}"
+        );
+        assert!(!alice.rooms[&room_id].mls_group.has_pending_commit());
+
+        // An error naming no frame of ours leaves nothing to resend
+        let mut header = FrameHeader::new(Opcode::Error);
+        header.set_room_id(room_id);
+        let error =
+            Payload::Error(ErrorPayload::rate_limited("slow down", 2)).into_frame(header).unwrap();
+        let actions = alice.handle(ClientEvent::FrameReceived(error)).unwrap();
+        assert!(
+            matches!(actions.as_slice(), [ClientAction::ServerError {
+                code: ErrorPayload::RATE_LIMITED,
+                recovery: Recovery::Backoff { .. },
+                ..
+            }]),
+            "got {actions:?}"
+        );
+    }
+
+    #[test]
+    fn backed_off_frames_are_resent() {
+        let room_id = 0x1234;
+        let clock = ManualClock::new();
+        let mut alice = Client::new(clock.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(clock.clone(), ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let rate_limited = |refused: &Frame| {
+            let mut header = FrameHeader::new(Opcode::Error);
+            header.set_room_id(room_id);
+            header.set_request_id(refused.header.request_id());
+            let error = ErrorPayload::rate_limited("slow down", 2);
+            ClientEvent::FrameReceived(Payload::Error(error).into_frame(header).unwrap())
+        };
+
+        let plaintext = b"hello".to_vec();
+        let actions = alice.handle(ClientEvent::SendMessage { room_id, plaintext }).unwrap();
+        let [message] = sent(&actions, Opcode::AppMessage).try_into().unwrap();
+        alice.handle(rate_limited(&message)).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        let [commit] = sent(&actions, Opcode::Commit).try_into().unwrap();
+        alice.handle(rate_limited(&commit)).unwrap();
+        assert!(alice.rooms[&room_id].mls_group.has_pending_commit());
+
+        let now = clock.advance(Duration::from_secs(1));
+        assert!(alice.handle(ClientEvent::Tick { now }).unwrap().is_empty());
+
+        // Sent unchanged, so a server that did sequence them recognises them
+        let now = clock.advance(Duration::from_secs(1));
+        let actions = alice.handle(ClientEvent::Tick { now }).unwrap();
+        assert_eq!(sent(&actions, Opcode::AppMessage), vec![message]);
+        assert_eq!(sent(&actions, Opcode::Commit), vec![commit]);
+
+        let now = clock.advance(Duration::from_secs(2));
+        assert!(alice.handle(ClientEvent::Tick { now }).unwrap().is_empty());
+    }
+
+    #[test]
+    fn room_moved_drops_pending_commit() {
+        use lockframe_proto::payloads::session::RoomMoved;
+
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        alice.handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] }).unwrap();
+
+        let mut header = FrameHeader::new(Opcode::RoomMoved);
+        header.set_room_id(room_id);
+        let moved = Payload::RoomMoved(RoomMoved {
+            target: "lockframe-2.example:4433".to_string(),
+            cutover_log_index: 7,
+        })
+        .into_frame(header)
+        .unwrap();
+
+        let actions = alice.handle(ClientEvent::FrameReceived(moved)).unwrap();
+        assert!(
+            matches!(actions.as_slice(), [ClientAction::RoomMoved {
+                room_id: 0x1234,
+                target,
+                cutover_log_index: 7,
+            }] if target == "lockframe-2.example:4433"),
+            "got {actions:?}"
+        );
+        assert!(!alice.rooms[&room_id].mls_group.has_pending_commit());
     }
-}
 
-fn proto_to_crypto_encrypted(proto: &EncryptedMessage) -> CryptoEncryptedMessage {
-    CryptoEncryptedMessage {
-        epoch: proto.epoch,
-        sender_index: proto.sender_index,
-        generation: proto.generation,
-        nonce: proto.nonce,
-        ciphertext: proto.ciphertext.clone(),
+    #[test]
+    fn maintenance_notice_names_the_server() {
+        use lockframe_proto::payloads::session::Maintenance;
+
+        let mut client = Client::new(TestEnv, ClientIdentity::new(42));
+        let frame = Payload::Maintenance(Maintenance {
+            reconnect_after_secs: 120,
+            reason: "upgrade".to_string(),
+        })
+        .into_frame(FrameHeader::new(Opcode::Maintenance))
+        .unwrap();
+
+        let actions = client.handle(ClientEvent::FrameReceivedFrom { server: 3, frame }).unwrap();
+        assert!(
+            matches!(actions.as_slice(), [ClientAction::ServerMaintenance {
+                server: 3,
+                reconnect_after,
+                reason,
+            }] if *reconnect_after == Duration::from_secs(120) && reason == "upgrade"),
+            "got {actions:?}"
+        );
     }
-}
 
-fn serialize_encrypted_message(encrypted: &EncryptedMessage) -> Vec<u8> {
-    let mut data = Vec::new();
-    ciborium::ser::into_writer(encrypted, &mut data)
-        .expect("CBOR serialization of EncryptedMessage should not fail");
-    data
-}
+    #[test]
+    fn sessions_revoked_removes_revoked_devices() {
+        use lockframe_proto::payloads::session::SessionsRevoked;
+
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut lost = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = lost.generate_key_package().unwrap();
+        alice.handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] }).unwrap();
+        alice.rooms.get_mut(&room_id).unwrap().mls_group.merge_pending_commit().unwrap();
+
+        let actions = alice.handle(ClientEvent::RevokeSessions { member_ids: vec![] }).unwrap();
+        let [ClientAction::Send(request)] = actions.as_slice() else {
+            panic!("expected a revocation request, got {actions:?}");
+        };
+        assert_eq!(request.header.opcode_enum(), Some(Opcode::RevokeSessions));
+        assert_eq!(request.header.sender_id(), 1);
+
+        let reply =
+            Payload::SessionsRevoked(SessionsRevoked { member_ids: vec![2], sessions_closed: 1 })
+                .into_frame(FrameHeader::new(Opcode::SessionsRevoked))
+                .unwrap();
+        let actions = alice.handle(ClientEvent::FrameReceived(reply)).unwrap();
+
+        assert!(
+            actions.iter().any(|action| matches!(
+                action,
+                ClientAction::Send(frame) if frame.header.opcode_enum() == Some(Opcode::Commit)
+            )),
+            "got {actions:?}"
+        );
+        assert!(matches!(
+            actions.last(),
+            Some(ClientAction::SessionsRevoked { sessions_closed: 1, .. })
+        ));
+        assert!(alice.rooms[&room_id].mls_group.has_pending_commit());
+    }
 
-fn deserialize_encrypted_message(data: &[u8]) -> Result<EncryptedMessage, String> {
-    ciborium::de::from_reader(data).map_err(|e| format!("CBOR decode failed: {e}"))
-}
+    #[test]
+    fn update_self_and_remove_members_take_effect_when_sequenced() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        // Both take up the sequenced commit; alice's sender keys follow her
+        // new leaf
+        let actions = alice.handle(ClientEvent::UpdateSelf { room_id }).unwrap();
+        let [mut commit] = sent(&actions, Opcode::Commit).try_into().unwrap();
+        commit.header.set_log_index(1);
+        assert_eq!(alice.epoch(room_id), Some(1));
+        alice.handle(ClientEvent::FrameReceived(commit.clone())).unwrap();
+        bob.handle(ClientEvent::FrameReceived(commit)).unwrap();
+        assert_eq!(alice.epoch(room_id), Some(2));
+        assert_eq!(bob.epoch(room_id), Some(2));
+
+        let actions = alice
+            .handle(ClientEvent::SendMessage { room_id, plaintext: b"rotated".to_vec() })
+            .unwrap();
+        let [mut message] = sent(&actions, Opcode::AppMessage).try_into().unwrap();
+        message.header.set_log_index(2);
+        alice.handle(ClientEvent::FrameReceived(message.clone())).unwrap();
+        let actions = bob.handle(ClientEvent::FrameReceived(message)).unwrap();
+        assert!(
+            actions.iter().any(|action| matches!(
+                action,
+                ClientAction::DeliverMessage { sender_id: 1, plaintext, .. } if plaintext == b"rotated"
+            )),
+            "got {actions:?}"
+        );
+
+        let actions =
+            alice.handle(ClientEvent::RemoveMembers { room_id, member_ids: vec![2] }).unwrap();
+        let [mut commit] = sent(&actions, Opcode::Commit).try_into().unwrap();
+        commit.header.set_log_index(3);
+        alice.handle(ClientEvent::FrameReceived(commit.clone())).unwrap();
+        assert_eq!(alice.epoch(room_id), Some(3));
+        assert_eq!(alice.rooms[&room_id].mls_group.member_leaf_indices().len(), 1);
+
+        let actions = bob.handle(ClientEvent::FrameReceived(commit)).unwrap();
+        assert!(
+            actions.iter().any(|action| matches!(action, ClientAction::RoomRemoved { .. })),
+            "got {actions:?}"
+        );
+        assert_eq!(bob.room_count(), 0);
+
+        // Offline, both wait to replay
+        alice.handle(ClientEvent::Disconnected).unwrap();
+        alice.handle(ClientEvent::UpdateSelf { room_id }).unwrap();
+        alice.handle(ClientEvent::RemoveMembers { room_id, member_ids: vec![3] }).unwrap();
+        assert_eq!(alice.queued_intents(), 2);
+    }
 
-#[cfg(test)]
-#[allow(clippy::unwrap_used)]
-mod tests {
-    use std::{
-        future::Future,
-        pin::Pin,
-        task::{Context, Poll},
-        time::{Duration, Instant},
-    };
+    #[test]
+    fn commit_removing_us_reports_the_room_removed() {
+        use lockframe_proto::payloads::session::SessionsRevoked;
+
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        let reply =
+            Payload::SessionsRevoked(SessionsRevoked { member_ids: vec![2], sessions_closed: 1 })
+                .into_frame(FrameHeader::new(Opcode::SessionsRevoked))
+                .unwrap();
+        let actions = alice.handle(ClientEvent::FrameReceived(reply)).unwrap();
+        let [mut commit] = sent(&actions, Opcode::Commit).try_into().unwrap();
+        commit.header.set_log_index(1);
+
+        let actions = bob.handle(ClientEvent::FrameReceived(commit)).unwrap();
+
+        assert!(
+            actions.iter().any(|action| matches!(
+                action,
+                ClientAction::RoomRemoved { room_id: removed, .. } if *removed == room_id
+            )),
+            "got {actions:?}"
+        );
+        assert!(!bob.rooms.contains_key(&room_id));
+    }
 
-    use super::*;
+    /// Merge our own pending commit and move to the new epoch's sender keys,
+    /// as seeing the commit come back from the server would.
+    fn merge_own_commit(client: &mut Client<CountingEnv>, room_id: RoomId) {
+        client.rooms.get_mut(&room_id).unwrap().mls_group.merge_pending_commit().unwrap();
+        let sender_keys = client.initialize_sender_keys(&client.rooms[&room_id].mls_group).unwrap();
+        client.rooms.get_mut(&room_id).unwrap().sender_keys = sender_keys;
+    }
 
-    struct ImmediateFuture;
+    /// Frames of `opcode` among `actions`, framed as the server would relay
+    /// them.
+    fn sent(actions: &[ClientAction], opcode: Opcode) -> Vec<Frame> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                ClientAction::Send(frame) if frame.header.opcode_enum() == Some(opcode) => {
+                    Some(Frame::new(frame.header, frame.payload.clone()))
+                },
+                _ => None,
+            })
+            .collect()
+    }
 
-    impl Future for ImmediateFuture {
-        type Output = ();
+    fn sync_response(
+        room_id: RoomId,
+        frames: &[&Frame],
+        has_more: bool,
+        server_epoch: u64,
+        mode: SyncMode,
+        next_log_index: u64,
+    ) -> Frame {
+        let frames = frames
+            .iter()
+            .map(|frame| {
+                let mut bytes = Vec::new();
+                frame.encode(&mut bytes).unwrap();
+                bytes
+            })
+            .collect();
+        let response = SyncResponse {
+            frames,
+            has_more,
+            server_epoch,
+            mode,
+            next_log_index: Some(next_log_index),
+        };
+        let mut header = FrameHeader::new(Opcode::SyncResponse);
+        header.set_room_id(room_id);
+        Payload::SyncResponse(response).into_frame(header).unwrap()
+    }
 
-        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-            Poll::Ready(())
+    #[test]
+    fn far_behind_client_fast_forwards_then_backfills() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env.clone(), ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+        let bob_epoch = bob.epoch(room_id).unwrap();
+
+        // While bob is away, a message and a commit in each of six epochs
+        let mut log = Vec::new();
+        for i in 0..6 {
+            let plaintext = format!("message {i}").into_bytes();
+            log.extend(sent(
+                &alice.handle(ClientEvent::SendMessage { room_id, plaintext }).unwrap(),
+                Opcode::AppMessage,
+            ));
+
+            let (key_package, _) = Client::new(env.clone(), ClientIdentity::new(10 + i))
+                .generate_key_package()
+                .unwrap();
+            log.extend(sent(
+                &alice
+                    .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+                    .unwrap(),
+                Opcode::Commit,
+            ));
+            merge_own_commit(&mut alice, room_id);
+        }
+        for (log_index, frame) in log.iter_mut().enumerate() {
+            frame.header.set_room_id(room_id);
+            frame.header.set_log_index(log_index as u64);
+        }
+        let server_epoch = alice.epoch(room_id).unwrap();
+        let end = log.len() as u64;
+
+        // A first full page shows how far behind bob is
+        let page = sync_response(room_id, &[&log[0]], true, server_epoch, SyncMode::Full, 1);
+        let actions = bob.handle(ClientEvent::FrameReceived(page)).unwrap();
+        assert!(
+            actions
+                .iter()
+                .any(|action| matches!(action, ClientAction::DeliverMessage { log_index: 0, .. }))
+        );
+        assert!(actions.iter().any(|action| matches!(action, ClientAction::RequestSync {
+            mode: SyncMode::EpochChanges,
+            from_log_index: Some(1),
+            ..
+        })));
+
+        // Only the commits are needed to reach the current epoch
+        let commits: Vec<&Frame> =
+            log.iter().filter(|frame| frame.header.opcode_enum() == Some(Opcode::Commit)).collect();
+        let page =
+            sync_response(room_id, &commits, false, server_epoch, SyncMode::EpochChanges, end);
+        let actions = bob.handle(ClientEvent::FrameReceived(page)).unwrap();
+        assert_eq!(bob.epoch(room_id), Some(server_epoch));
+        assert_eq!(server_epoch, bob_epoch + 6);
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            ClientAction::BackfillPending { from_log_index: 1, to_log_index, .. } if *to_log_index == end
+        )));
+
+        // A full sync is not mistaken for a backfill page
+        let actions = bob.handle(ClientEvent::Backfill { room_id }).unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::RequestSync {
+            mode: SyncMode::Backfill,
+            from_log_index: Some(1),
+            ..
+        }]));
+        let page = sync_response(room_id, &[], false, server_epoch, SyncMode::Full, end);
+        bob.handle(ClientEvent::FrameReceived(page)).unwrap();
+
+        // Skipped messages are opened with the keys of their epoch, a page
+        // at a time
+        let mut delivered: Vec<Vec<u8>> = Vec::new();
+        let pages = [(&log[1..5], true, 5), (&log[5..], false, end)];
+        for (i, (frames, has_more, next)) in pages.into_iter().enumerate() {
+            let frames: Vec<&Frame> = frames.iter().collect();
+            let page =
+                sync_response(room_id, &frames, has_more, server_epoch, SyncMode::Backfill, next);
+            let actions = bob.handle(ClientEvent::FrameReceived(page)).unwrap();
+            let pending = actions.iter().any(|action| matches!(
+                action,
+                ClientAction::BackfillPending { from_log_index: 5, to_log_index, .. } if *to_log_index == end
+            ));
+            assert_eq!(pending, i == 0);
+            delivered.extend(actions.into_iter().filter_map(|action| match action {
+                ClientAction::DeliverMessage { plaintext, .. } => Some(plaintext),
+                _ => None,
+            }));
+            if i == 0 {
+                bob.handle(ClientEvent::Backfill { room_id }).unwrap();
+            }
         }
+        let expected: Vec<Vec<u8>> = (1..6).map(|i| format!("message {i}").into_bytes()).collect();
+        assert_eq!(delivered, expected);
+
+        // Nothing left to backfill, and the old keys are gone
+        assert!(bob.handle(ClientEvent::Backfill { room_id }).unwrap().is_empty());
+        assert!(matches!(
+            bob.handle(ClientEvent::FrameReceived(log[2].clone())),
+            Err(ClientError::EpochMismatch { .. })
+        ));
     }
 
-    #[derive(Clone)]
-    struct TestEnv;
-
-    impl Environment for TestEnv {
-        fn now(&self) -> Instant {
-            Instant::now()
+    #[test]
+    fn relayed_twice_app_message_delivered_once() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        let plaintext = b"once".to_vec();
+        let actions = alice.handle(ClientEvent::SendMessage { room_id, plaintext }).unwrap();
+        let [mut message] = sent(&actions, Opcode::AppMessage).try_into().unwrap();
+        message.header.set_room_id(room_id);
+        message.header.set_log_index(1);
+
+        let actions = bob.handle(ClientEvent::FrameReceived(message.clone())).unwrap();
+        assert!(matches!(actions.as_slice(), [ClientAction::DeliverMessage { log_index: 1, .. }]));
+
+        // Same ciphertext again, as relayed and as resequenced by the server
+        let mut resequenced = message.clone();
+        resequenced.header.set_log_index(2);
+        for frame in [message, resequenced] {
+            let actions = bob.handle(ClientEvent::FrameReceived(frame)).unwrap();
+            assert!(matches!(actions.as_slice(), [ClientAction::DuplicateSuppressed {
+                sender_index: 0,
+                generation: 0,
+                ..
+            }]));
         }
+    }
 
-        fn sleep(&self, _duration: Duration) -> impl Future<Output = ()> + Send {
-            ImmediateFuture
-        }
+    #[test]
+    fn draft_chunks_are_delivered_as_they_are_sent() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
 
-        fn random_bytes(&self, buffer: &mut [u8]) {
-            // Deterministic for tests
-            for (i, byte) in buffer.iter_mut().enumerate() {
-                *byte = i as u8;
+        let actions = alice.handle(ClientEvent::StartDraft { room_id }).unwrap();
+        let [ClientAction::DraftStarted { draft_id, .. }] = actions.as_slice() else {
+            panic!("expected DraftStarted, got {actions:?}");
+        };
+        let draft_id = *draft_id;
+
+        let events = [
+            ClientEvent::AppendDraft { room_id, draft_id, chunk: b"voice ".to_vec() },
+            ClientEvent::AppendDraft { room_id, draft_id, chunk: b"note".to_vec() },
+            ClientEvent::FinishDraft { room_id, draft_id, chunk: Vec::new() },
+        ];
+        let mut received = Vec::new();
+        for (log_index, event) in (1..).zip(events) {
+            let actions = alice.handle(event).unwrap();
+            let [mut frame] = sent(&actions, Opcode::AppMessage).try_into().unwrap();
+            assert!(frame.header.flags().contains(FrameFlags::FRAGMENTED));
+            frame.header.set_log_index(log_index);
+
+            match bob.handle(ClientEvent::FrameReceived(frame)).unwrap().as_slice() {
+                [ClientAction::DraftChunk { sender_id: 1, index, chunk, last, .. }] => {
+                    received.push((*index, chunk.clone(), *last));
+                },
+                actions => panic!("expected DraftChunk, got {actions:?}"),
             }
         }
+        assert_eq!(received, vec![
+            (0, b"voice ".to_vec(), false),
+            (1, b"note".to_vec(), false),
+            (2, Vec::new(), true),
+        ]);
+
+        // The draft is closed once finished
+        let result =
+            alice.handle(ClientEvent::AppendDraft { room_id, draft_id, chunk: b"more".to_vec() });
+        assert!(matches!(result, Err(ClientError::InvalidState { .. })));
     }
 
     #[test]
-    fn create_client() {
-        let env = TestEnv;
-        let identity = ClientIdentity::new(42);
-        let client: Client<TestEnv> = Client::new(env, identity);
+    fn typing_reaches_members_unsequenced() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        let actions = alice.handle(ClientEvent::SetTyping { room_id, typing: true }).unwrap();
+        let [frame] = sent(&actions, Opcode::Typing).try_into().unwrap();
+        assert!(frame.header.flags().contains(FrameFlags::EPHEMERAL));
+
+        let actions = bob.handle(ClientEvent::FrameReceived(frame)).unwrap();
+        assert!(
+            matches!(actions.as_slice(), [ClientAction::Typing { sender_id: 1, typing: true, .. }]),
+            "got {actions:?}"
+        );
+
+        // Nothing is queued while offline
+        alice.handle(ClientEvent::Disconnected).unwrap();
+        let actions = alice.handle(ClientEvent::SetTyping { room_id, typing: false }).unwrap();
+        assert!(actions.is_empty());
+    }
 
-        assert_eq!(client.sender_id(), 42);
-        assert_eq!(client.room_count(), 0);
+    #[test]
+    fn read_receipts_aggregate_per_room() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        let mut receipts = Vec::new();
+        for (log_index, up_to_log_index) in (1..).zip([0, 2, 1]) {
+            let actions = alice.handle(ClientEvent::MarkRead { room_id, up_to_log_index }).unwrap();
+            let [mut frame] = sent(&actions, Opcode::ReadReceipt).try_into().unwrap();
+            frame.header.set_log_index(log_index);
+            receipts.extend(bob.handle(ClientEvent::FrameReceived(frame)).unwrap());
+        }
+
+        // The receipt sequenced last is older, so it reports nothing
+        assert!(
+            matches!(receipts.as_slice(), [
+                ClientAction::ReadReceipt { sender_id: 1, up_to_log_index: 0, .. },
+                ClientAction::ReadReceipt { sender_id: 1, up_to_log_index: 2, .. },
+            ]),
+            "got {receipts:?}"
+        );
+        let state = bob.read_state(room_id).unwrap();
+        assert_eq!(state.read_up_to(1), Some(2));
+        assert_eq!(state.read_up_to(2), None);
+
+        // Receipts wait for the server like messages do
+        alice.handle(ClientEvent::Disconnected).unwrap();
+        alice.handle(ClientEvent::MarkRead { room_id, up_to_log_index: 3 }).unwrap();
+        assert_eq!(alice.queued_intents(), 1);
     }
 
     #[test]
-    fn create_room() {
-        let env = TestEnv;
-        let identity = ClientIdentity::new(42);
-        let mut client = Client::new(env, identity);
+    fn delivery_updates_follow_a_message_until_it_is_read() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        let mut states = Vec::new();
+        let mut record = |actions: Vec<ClientAction>| {
+            states.extend(actions.into_iter().filter_map(|action| match action {
+                ClientAction::DeliveryUpdate { message_id, state, .. } => Some((message_id, state)),
+                _ => None,
+            }));
+        };
 
-        let room_id = 0x1234_5678_9abc_def0_u128;
-        let actions = client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+        let actions =
+            alice.handle(ClientEvent::SendMessage { room_id, plaintext: b"hi".to_vec() }).unwrap();
+        let [mut message] = sent(&actions, Opcode::AppMessage).try_into().unwrap();
+        record(actions);
+        message.header.set_log_index(1);
+        record(alice.handle(ClientEvent::FrameReceived(message.clone())).unwrap());
+        bob.handle(ClientEvent::FrameReceived(message)).unwrap();
+
+        // Receipts that don't newly cover the message report nothing more
+        for (log_index, up_to_log_index) in (2..).zip([0, 1, 1]) {
+            let actions = bob.handle(ClientEvent::MarkRead { room_id, up_to_log_index }).unwrap();
+            let [mut receipt] = sent(&actions, Opcode::ReadReceipt).try_into().unwrap();
+            receipt.header.set_log_index(log_index);
+            record(alice.handle(ClientEvent::FrameReceived(receipt.clone())).unwrap());
+            // Our own receipts move our own position only
+            bob.handle(ClientEvent::FrameReceived(receipt)).unwrap();
+        }
 
-        assert!(client.is_member(room_id));
-        assert_eq!(client.epoch(room_id), Some(0));
-        assert!(!actions.is_empty());
+        assert_eq!(states, vec![
+            (0, DeliveryState::Sent),
+            (0, DeliveryState::Sequenced { log_index: 1 }),
+            (0, DeliveryState::Read { member_id: 2 }),
+        ]);
     }
 
     #[test]
-    fn create_duplicate_room_fails() {
-        let env = TestEnv;
-        let identity = ClientIdentity::new(42);
-        let mut client = Client::new(env, identity);
+    fn large_messages_are_streamed() {
+        let room_id = 0x1234;
+        let mut alice = Client::new(CountingEnv::default(), ClientIdentity::new(1));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let mut send = |len: usize| {
+            let plaintext = vec![0; len];
+            let actions = alice.handle(ClientEvent::SendMessage { room_id, plaintext }).unwrap();
+            let [frame] = sent(&actions, Opcode::AppMessage).try_into().unwrap();
+            frame
+        };
 
-        let room_id = 0x1234_u128;
-        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+        let small = send(16);
+        assert!(!small.is_streamed());
+        assert_ne!(small.header.request_id(), 0);
 
-        let result = client.handle(ClientEvent::CreateRoom { room_id });
-        assert!(matches!(result, Err(ClientError::RoomAlreadyExists { .. })));
+        let large = send(Frame::STREAM_THRESHOLD);
+        assert!(large.is_streamed());
+        assert_ne!(large.header.request_id(), 0);
+        let next = send(Frame::STREAM_THRESHOLD);
+        assert_ne!(next.header.request_id(), large.header.request_id());
     }
 
     #[test]
-    fn send_message_to_unknown_room_fails() {
-        let env = TestEnv;
-        let identity = ClientIdentity::new(42);
-        let mut client = Client::new(env, identity);
+    fn large_messages_compress_once_negotiated() {
+        use lockframe_proto::payloads::session::HelloReply;
+
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        let plaintext = b"compressible ".repeat(200);
+        let send = |alice: &mut Client<CountingEnv>| {
+            let event = ClientEvent::SendMessage { room_id, plaintext: plaintext.clone() };
+            let actions = alice.handle(event).unwrap();
+            let [frame] = sent(&actions, Opcode::AppMessage).try_into().unwrap();
+            frame
+        };
 
-        let result = client.handle(ClientEvent::SendMessage {
-            room_id: 0x9999_u128,
-            plaintext: b"hello".to_vec(),
-        });
+        // Not before the server agrees to it
+        assert!(!send(&mut alice).header.flags().contains(FrameFlags::COMPRESSED));
 
-        assert!(matches!(result, Err(ClientError::RoomNotFound { .. })));
+        let reply = HelloReply {
+            session_id: 1,
+            capabilities: Capabilities::COMPRESSION.to_names(),
+            challenge: None,
+            time_sync: None,
+        };
+        let reply = Payload::HelloReply(reply).into_frame(FrameHeader::new(Opcode::HelloReply));
+        alice.handle(ClientEvent::FrameReceived(reply.unwrap())).unwrap();
+
+        let mut message = send(&mut alice);
+        assert!(message.header.flags().contains(FrameFlags::COMPRESSED));
+        assert!(message.payload.len() < plaintext.len());
+
+        message.header.set_log_index(2);
+        let actions = bob.handle(ClientEvent::FrameReceived(message)).unwrap();
+        assert!(matches!(
+            actions.as_slice(),
+            [ClientAction::DeliverMessage { plaintext: delivered, .. }] if *delivered == plaintext
+        ));
     }
 
     #[test]
-    fn leave_room() {
-        let env = TestEnv;
-        let identity = ClientIdentity::new(42);
-        let mut client = Client::new(env, identity);
+    fn short_messages_pad_to_the_same_length() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        let send = |alice: &mut Client<CountingEnv>, plaintext: &[u8]| {
+            let event = ClientEvent::SendMessage { room_id, plaintext: plaintext.to_vec() };
+            let actions = alice.handle(event).unwrap();
+            let [frame] = sent(&actions, Opcode::AppMessage).try_into().unwrap();
+            frame
+        };
 
-        let room_id = 0x1234_u128;
-        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
-        assert!(client.is_member(room_id));
+        let mut yes = send(&mut alice, b"yes");
+        let no_way = send(&mut alice, b"no way, not today");
+        assert_eq!(yes.payload.len(), no_way.payload.len());
 
-        let actions = client.handle(ClientEvent::LeaveRoom { room_id }).unwrap();
-        assert!(!client.is_member(room_id));
-        assert!(matches!(actions[0], ClientAction::RoomRemoved { .. }));
+        yes.header.set_log_index(2);
+        let actions = bob.handle(ClientEvent::FrameReceived(yes)).unwrap();
+        assert!(matches!(
+            actions.as_slice(),
+            [ClientAction::DeliverMessage { plaintext, .. }] if plaintext == b"yes"
+        ));
+
+        alice.set_padding(Padding::None);
+        assert!(send(&mut alice, b"yes").payload.len() < no_way.payload.len());
     }
 
     #[test]
-    fn leave_unknown_room_fails() {
-        let env = TestEnv;
-        let identity = ClientIdentity::new(42);
-        let mut client = Client::new(env, identity);
+    fn welcome_joins_with_the_key_package_it_was_made_for() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let mut published = Vec::new();
+        for _ in 0..2 {
+            match bob.handle(ClientEvent::GenerateKeyPackage).unwrap().as_slice() {
+                [ClientAction::PublishKeyPackage(key_package)] => {
+                    published.push(key_package.clone());
+                },
+                actions => panic!("got {actions:?}"),
+            }
+        }
+        assert_eq!(bob.pending_key_packages(), 2);
 
-        let result = client.handle(ClientEvent::LeaveRoom { room_id: 0x9999_u128 });
-        assert!(matches!(result, Err(ClientError::RoomNotFound { .. })));
+        // Alice picks the older of the two
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![published.remove(0)] })
+            .unwrap();
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        assert!(bob.is_member(room_id));
+        assert_eq!(bob.pending_key_packages(), 1);
     }
 
     #[test]
-    fn send_message_produces_encrypted_frame() {
-        let env = TestEnv;
-        let identity = ClientIdentity::new(42);
-        let mut client = Client::new(env, identity);
+    fn pending_key_packages_are_bounded() {
+        let mut bob = Client::new(CountingEnv::default(), ClientIdentity::new(2));
+        for _ in 0..MAX_PENDING_KEY_PACKAGES + 3 {
+            bob.generate_key_package().unwrap();
+        }
+        assert_eq!(bob.pending_key_packages(), MAX_PENDING_KEY_PACKAGES);
+    }
 
-        let room_id = 0x1234_u128;
-        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+    #[test]
+    fn restored_client_joins_with_a_key_package_from_before_export() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env.clone(), ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let mut bob = Client::import_state(env, &bob.export_state().unwrap()).unwrap();
+        assert_eq!(bob.pending_key_packages(), 1);
+
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
 
-        let actions = client
-            .handle(ClientEvent::SendMessage { room_id, plaintext: b"Hello, World!".to_vec() })
+        assert!(bob.is_member(room_id));
+        assert_eq!(bob.pending_key_packages(), 0);
+    }
+
+    #[test]
+    fn restored_room_resumes_at_its_epoch() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env.clone(), ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
             .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
 
-        // Should produce a Send action with encrypted frame
-        assert_eq!(actions.len(), 1);
-        match &actions[0] {
-            ClientAction::Send(frame) => {
-                assert_eq!(frame.header.opcode_enum(), Some(Opcode::AppMessage));
-                assert_eq!(frame.header.room_id(), room_id);
-                // Payload should be encrypted (not plaintext)
-                assert!(!frame.payload.is_empty());
-                assert_ne!(frame.payload.as_ref(), b"Hello, World!");
-            },
-            _ => panic!("Expected Send action"),
+        let snapshot = match alice.persist_room(room_id).unwrap() {
+            ClientAction::PersistRoom(snapshot) => snapshot,
+            action => panic!("got {action:?}"),
+        };
+        assert_eq!(snapshot.epoch, 1);
+
+        // Alice restarts with only the snapshot
+        let mut alice = Client::new(env, ClientIdentity::new(1));
+        let actions = alice.restore_room(&snapshot).unwrap();
+        assert_eq!(alice.epoch(room_id), Some(1));
+        assert!(
+            actions
+                .iter()
+                .any(|action| matches!(action, ClientAction::RequestSync { from_epoch: 1, .. })),
+            "got {actions:?}"
+        );
+        assert!(matches!(
+            alice.restore_room(&snapshot),
+            Err(ClientError::RoomAlreadyExists { .. })
+        ));
+
+        // Her sending ratchet may have run past the snapshot, so she rekeys
+        // before sending anything
+        let [mut commit] = sent(&actions, Opcode::Commit).try_into().unwrap();
+        assert!(matches!(
+            alice.handle(ClientEvent::SendMessage { room_id, plaintext: b"early".to_vec() }),
+            Err(ClientError::InvalidState { .. })
+        ));
+        commit.header.set_log_index(2);
+        alice.handle(ClientEvent::FrameReceived(commit.clone())).unwrap();
+        bob.handle(ClientEvent::FrameReceived(commit)).unwrap();
+        assert_eq!(alice.epoch(room_id), Some(2));
+        assert_eq!(bob.epoch(room_id), Some(2));
+
+        // Bob still reads what she sends
+        let mut message = sent(
+            &alice
+                .handle(ClientEvent::SendMessage { room_id, plaintext: b"back".to_vec() })
+                .unwrap(),
+            Opcode::AppMessage,
+        )
+        .remove(0);
+        message.header.set_log_index(3);
+        let actions = bob.handle(ClientEvent::FrameReceived(message)).unwrap();
+        assert!(
+            matches!(
+                actions.as_slice(),
+                [ClientAction::DeliverMessage { plaintext, .. }] if plaintext == b"back"
+            ),
+            "got {actions:?}"
+        );
+    }
+
+    #[test]
+    fn imported_client_keeps_decrypting() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env.clone(), ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        let mut messages: Vec<_> = (0..4u8)
+            .map(|i| {
+                let actions =
+                    alice.handle(ClientEvent::SendMessage { room_id, plaintext: vec![i] }).unwrap();
+                let mut message = sent(&actions, Opcode::AppMessage).remove(0);
+                message.header.set_log_index(u64::from(i) + 2);
+                message
+            })
+            .collect();
+
+        // Bob sees the third message before restarting
+        bob.handle(ClientEvent::FrameReceived(messages.remove(2))).unwrap();
+        let state = bob.export_state().unwrap();
+        drop(bob);
+
+        let mut bob = Client::import_state(env, &state).unwrap();
+        assert_eq!(bob.sender_id(), 2);
+        assert_eq!(bob.epoch(room_id), Some(1));
+
+        // The two skipped messages and the one after still decrypt
+        for (message, plaintext) in messages.into_iter().zip([0, 1, 3]) {
+            let actions = bob.handle(ClientEvent::FrameReceived(message)).unwrap();
+            assert!(
+                matches!(
+                    actions.as_slice(),
+                    [ClientAction::DeliverMessage { plaintext: delivered, .. }]
+                        if *delivered == [plaintext]
+                ),
+                "got {actions:?}"
+            );
         }
+
+        assert!(Client::import_state(CountingEnv::default(), b"garbage").is_err());
     }
 
     #[test]
-    fn app_message_with_invalid_signature_is_rejected() {
-        let env = TestEnv;
-        let identity = ClientIdentity::new(42);
-        let mut client = Client::new(env, identity);
+    fn sending_is_refused_while_the_outbox_is_full() {
+        let room_id = 0x1234;
+        let mut alice = Client::new(CountingEnv::default(), ClientIdentity::new(1));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let send = || ClientEvent::SendMessage { room_id, plaintext: b"hi".to_vec() };
+        for _ in 0..MAX_UNSEQUENCED_MESSAGES {
+            alice.handle(send()).unwrap();
+        }
+        assert!(matches!(alice.handle(send()), Err(ClientError::OutboxFull { .. })));
+        assert_eq!(alice.unsequenced_messages(), MAX_UNSEQUENCED_MESSAGES);
+    }
 
-        let room_id = 0x1234_u128;
-        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+    #[test]
+    fn imported_client_keeps_what_the_server_cannot_give_back() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env.clone(), ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        alice.handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] }).unwrap();
+        merge_own_commit(&mut alice, room_id);
+        alice.mark_peer_verified(room_id, 2).unwrap();
+
+        // One message is in flight when the room moves to a server we are
+        // not connected to, so the next one is queued
+        let send = ClientEvent::SendMessage { room_id, plaintext: b"in flight".to_vec() };
+        alice.handle(send).unwrap();
+        alice.handle(ClientEvent::MoveRoom { room_id, server: 7 }).unwrap();
+        let send = ClientEvent::SendMessage { room_id, plaintext: b"queued".to_vec() };
+        alice.handle(send).unwrap();
+        assert_eq!((alice.unsequenced_messages(), alice.queued_intents()), (1, 1));
+
+        let mut alice = Client::import_state(env, &alice.export_state().unwrap()).unwrap();
+        assert!(alice.is_peer_verified(room_id, 2));
+        assert_eq!(alice.home_server(room_id), 7);
+        // The message in flight is resent ahead of the queued one
+        assert_eq!((alice.unsequenced_messages(), alice.queued_intents()), (0, 2));
+        let queued: Vec<_> =
+            alice.intents.drain_ready(|_| true).into_iter().map(|q| q.intent).collect();
+        assert!(matches!(
+            queued.as_slice(),
+            [Intent::Resend(outgoing), Intent::SendMessage { plaintext, .. }]
+                if outgoing.plaintext.as_slice() == b"in flight" && plaintext == b"queued"
+        ));
+    }
 
-        let mut header = FrameHeader::new(Opcode::AppMessage);
-        header.set_room_id(room_id);
-        header.set_sender_id(42);
-        header.set_epoch(0);
-        let frame = Frame::new(header, Vec::<u8>::new());
+    #[test]
+    fn messages_disappear_after_room_ttl() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+        let epoch = alice.epoch(room_id).unwrap();
+
+        // Nothing changes until the server sequences the change
+        let ttl = Some(Duration::from_secs(60));
+        let actions = alice.handle(ClientEvent::SetMessageTtl { room_id, ttl }).unwrap();
+        let [mut change] = sent(&actions, Opcode::Commit).try_into().unwrap();
+        assert_eq!(alice.message_ttl(room_id), None);
+
+        change.header.set_log_index(2);
+        for client in [&mut alice, &mut bob] {
+            let actions = client.handle(ClientEvent::FrameReceived(change.clone())).unwrap();
+            assert!(
+                actions.iter().any(|action| matches!(
+                    action,
+                    ClientAction::MessageTtlChanged { ttl: changed, .. } if *changed == ttl
+                )),
+                "got {actions:?}"
+            );
+            assert_eq!(client.message_ttl(room_id), ttl);
+            assert_eq!(client.epoch(room_id), Some(epoch + 1));
+        }
 
-        let result = client.handle(ClientEvent::FrameReceived(frame));
-        assert!(matches!(result, Err(ClientError::InvalidFrame { .. })));
+        let mut message = sent(
+            &alice
+                .handle(ClientEvent::SendMessage { room_id, plaintext: b"bye".to_vec() })
+                .unwrap(),
+            Opcode::AppMessage,
+        )
+        .remove(0);
+        message.header.set_log_index(3);
+        bob.handle(ClientEvent::FrameReceived(message)).unwrap();
+
+        let now = Instant::now();
+        assert!(bob.handle(ClientEvent::Tick { now }).unwrap().is_empty());
+
+        // An hour later by the server's clock
+        let later = CountingEnv::default().wall_clock_millis() + 3_600_000;
+        let time_sync =
+            TimeSync { wall_clock_millis: later, hlc: HlcTimestamp::new(later, 0).as_u64() };
+        let frame =
+            Payload::TimeSync(time_sync).into_frame(FrameHeader::new(Opcode::TimeSync)).unwrap();
+        bob.handle(ClientEvent::FrameReceived(frame)).unwrap();
+
+        let actions = bob.handle(ClientEvent::Tick { now }).unwrap();
+        assert!(
+            matches!(
+                actions.as_slice(),
+                [ClientAction::MessagesExpired { log_indices, .. }] if *log_indices == [3]
+            ),
+            "got {actions:?}"
+        );
+        assert!(bob.handle(ClientEvent::Tick { now }).unwrap().is_empty());
     }
 
     #[test]
-    fn encrypt_decrypt_roundtrip_same_client() {
-        // NOTE: This test demonstrates a known limitation.
-        // In the current sender key design, sender and receiver share the same ratchet.
-        // When you send (encrypt), your ratchet advances. When you receive your own
-        // message back, decryption fails because the ratchet already moved forward.
-        //
-        // In production:
-        // 1. Servers shouldn't echo your own messages back to you
-        // 2. Or: We need separate send/receive ratchets per member
-        //
-        // For now, we test that encryption works and produces valid output,
-        // and that the serialization roundtrips correctly.
+    fn attachments_upload_resumably_and_download_by_window() {
+        use lockframe_proto::payloads::attachment::{
+            AttachmentChunk, AttachmentFetch, AttachmentInit,
+        };
 
-        let env = TestEnv;
-        let identity = ClientIdentity::new(42);
-        let mut client = Client::new(env, identity);
+        use crate::attachments::{ATTACHMENT_CHUNK_SIZE, decrypt_attachment};
 
-        let room_id = 0x1234_u128;
-        client.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+        let room_id = 0x1234;
+        let mut alice = Client::new(CountingEnv::default(), ClientIdentity::new(1));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
 
-        // Send a message
-        let plaintext = b"Secret message";
-        let actions = client
-            .handle(ClientEvent::SendMessage { room_id, plaintext: plaintext.to_vec() })
+        let status = |content_hash, next_chunk, complete| {
+            let mut header = FrameHeader::new(Opcode::AttachmentStatus);
+            header.set_room_id(room_id);
+            let status = AttachmentStatus { content_hash, next_chunk, complete };
+            ClientEvent::FrameReceived(
+                Payload::AttachmentStatus(status).into_frame(header).unwrap(),
+            )
+        };
+
+        let plaintext = vec![7; ATTACHMENT_CHUNK_SIZE * 2 + 5];
+        let actions = alice
+            .handle(ClientEvent::UploadAttachment { room_id, plaintext: plaintext.clone() })
             .unwrap();
+        let [init] = sent(&actions, Opcode::AttachmentInit).try_into().unwrap();
+        let Ok(Payload::AttachmentInit(AttachmentInit { content_hash, .. })) =
+            Payload::from_frame(init)
+        else {
+            panic!("expected AttachmentInit");
+        };
 
-        let frame = match &actions[0] {
-            ClientAction::Send(f) => f.clone(),
-            _ => panic!("Expected Send action"),
+        // The server already has the first chunk
+        let actions = alice.handle(status(content_hash, 1, false)).unwrap();
+        let chunks = sent(&actions, Opcode::AttachmentChunk);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(sent(&actions, Opcode::AttachmentComplete).len(), 1);
+
+        // Unfinished uploads are announced again after a reconnect
+        alice.handle(ClientEvent::Disconnected).unwrap();
+        let actions = alice.handle(ClientEvent::Reconnected).unwrap();
+        assert_eq!(sent(&actions, Opcode::AttachmentInit).len(), 1);
+
+        let actions = alice.handle(status(content_hash, 3, true)).unwrap();
+        let [ClientAction::AttachmentUploaded { key, .. }] = actions.as_slice() else {
+            panic!("expected AttachmentUploaded, got {actions:?}");
+        };
+        assert_eq!(key.content_hash, content_hash);
+
+        let actions = alice.handle(ClientEvent::Reconnected).unwrap();
+        assert!(sent(&actions, Opcode::AttachmentInit).is_empty());
+
+        // Members holding the key open what was uploaded
+        let mut ciphertext = Vec::new();
+        let mut uploads = crate::attachments::Uploads::default();
+        let init = uploads.start(&plaintext, key.seed).unwrap();
+        assert_eq!(init.content_hash, content_hash);
+        for chunk in uploads.chunks_from(&content_hash, 0).unwrap() {
+            ciphertext.extend(chunk.data);
+        }
+        assert_eq!(decrypt_attachment(key, &ciphertext).unwrap(), plaintext);
+
+        // and download it a window at a time
+        let key = key.clone();
+        let actions = alice.handle(ClientEvent::FetchAttachment { room_id, key }).unwrap();
+        assert_eq!(sent(&actions, Opcode::AttachmentFetch).len(), 1);
+        let chunks = uploads.chunks_from(&content_hash, 0).unwrap();
+        let received = |chunk: &AttachmentChunk| {
+            let mut header = FrameHeader::new(Opcode::AttachmentChunk);
+            header.set_room_id(room_id);
+            let frame = Payload::AttachmentChunk(chunk.clone()).into_frame(header).unwrap();
+            ClientEvent::FrameReceived(frame)
         };
+        for chunk in &chunks[..2] {
+            assert!(alice.handle(received(chunk)).unwrap().is_empty());
+        }
+        let actions = alice.handle(status(content_hash, 3, true)).unwrap();
+        let [fetch] = sent(&actions, Opcode::AttachmentFetch).try_into().unwrap();
+        assert!(matches!(
+            Payload::from_frame(fetch),
+            Ok(Payload::AttachmentFetch(AttachmentFetch { from_chunk: 2, .. }))
+        ));
+
+        alice.handle(received(&chunks[2])).unwrap();
+        let actions = alice.handle(status(content_hash, 3, true)).unwrap();
+        assert!(matches!(
+            actions.as_slice(),
+            [ClientAction::AttachmentFetched { plaintext: fetched, .. }] if *fetched == plaintext
+        ));
+    }
 
-        // Verify the encrypted payload can be deserialized
-        let encrypted = deserialize_encrypted_message(&frame.payload).unwrap();
-        assert_eq!(encrypted.epoch, 0);
-        assert_eq!(encrypted.sender_index, 0); // Creator is leaf 0
-        assert_eq!(encrypted.generation, 0); // First message
-        assert!(!encrypted.ciphertext.is_empty());
+    /// Escrow that "wraps" by prefixing the key ID.
+    struct PrefixEscrow(&'static [u8]);
 
-        // Verify sender's ratchet advanced
-        let room = client.rooms.get(&room_id).unwrap();
-        assert_eq!(room.sender_keys.generation(0), Some(1)); // Now at gen 1
+    impl KeyEscrow for PrefixEscrow {
+        fn recovery_key_id(&self) -> Vec<u8> {
+            self.0.to_vec()
+        }
+
+        fn wrap(
+            &mut self,
+            _room_id: RoomId,
+            _epoch: u64,
+            secret: &[u8],
+        ) -> Result<Vec<u8>, String> {
+            Ok([self.0, secret].concat())
+        }
+    }
+
+    #[test]
+    fn escrow_is_advertised_and_only_used_where_advertised() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.set_key_escrow(PrefixEscrow(b"org"));
+        bob.set_key_escrow(PrefixEscrow(b"other-org"));
+
+        let actions = alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            ClientAction::RoomEscrowed { room_id: 0x1234, recovery_key_id } if recovery_key_id == b"org"
+        )));
+        let secret = epoch_secret(&alice.rooms[&room_id].mls_group).unwrap();
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            ClientAction::EscrowRoomKey { room_id: 0x1234, epoch: 0, wrapped }
+                if *wrapped == [&b"org"[..], &secret[..]].concat()
+        )));
+
+        // Bob is told the room is escrowed, but his escrow is for another key
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        let actions = bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            ClientAction::RoomEscrowed { recovery_key_id, .. } if recovery_key_id == b"org"
+        )));
+        assert!(!actions.iter().any(|action| matches!(action, ClientAction::EscrowRoomKey { .. })));
+
+        // Rooms created without escrow are never escrowed
+        let mut carol = Client::new(CountingEnv::default(), ClientIdentity::new(3));
+        let actions = carol.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+        carol.set_key_escrow(PrefixEscrow(b"org"));
+        assert!(carol.escrow_epoch(room_id).unwrap().is_empty());
+        assert!(!actions.iter().any(|action| matches!(action, ClientAction::RoomEscrowed { .. })));
+    }
+
+    #[test]
+    fn verified_peers_are_flagged_on_delivery() {
+        let room_id = 0x1234;
+        let env = CountingEnv::default();
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env, ClientIdentity::new(2));
+        alice.handle(ClientEvent::CreateRoom { room_id }).unwrap();
+
+        let (key_package, _) = bob.generate_key_package().unwrap();
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id, key_packages: vec![key_package] })
+            .unwrap();
+        merge_own_commit(&mut alice, room_id);
+        let [welcome] = sent(&actions, Opcode::Welcome).try_into().unwrap();
+        bob.handle(ClientEvent::FrameReceived(welcome)).unwrap();
+
+        // Both sides derive the same material, so a scan of Alice's code works
+        let alice_view = alice.peer_verification(room_id, 2).unwrap();
+        let bob_view = bob.peer_verification(room_id, 1).unwrap();
+        assert_eq!(alice_view.safety_number, bob_view.safety_number);
+        assert_eq!(alice_view.short_auth_string, bob_view.short_auth_string);
+        assert!(!bob.verify_scanned_payload(room_id, 1, b"forged").unwrap());
+        assert!(bob.verify_scanned_payload(room_id, 1, &alice_view.qr_payload).unwrap());
+        assert!(bob.peer_verification(room_id, 3).is_err());
+
+        let mut deliver = |log_index| {
+            let plaintext = b"hi".to_vec();
+            let actions = alice.handle(ClientEvent::SendMessage { room_id, plaintext }).unwrap();
+            let [mut message] = sent(&actions, Opcode::AppMessage).try_into().unwrap();
+            message.header.set_room_id(room_id);
+            message.header.set_log_index(log_index);
+            match bob.handle(ClientEvent::FrameReceived(message)).unwrap().as_slice() {
+                [ClientAction::DeliverMessage { peer_verified, .. }] => *peer_verified,
+                actions => panic!("expected a delivery, got {actions:?}"),
+            }
+        };
+        assert!(deliver(1));
+
+        bob.unverify_peer(1);
+        assert!(!bob.is_peer_verified(room_id, 1));
     }
 }
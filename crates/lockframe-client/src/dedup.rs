@@ -0,0 +1,88 @@
+//! Duplicate suppression for application messages.
+//!
+//! A sender key message is identified by who sent it, in which epoch, and at
+//! which ratchet generation. Each room remembers the most recent identities
+//! it delivered, so a ciphertext relayed twice (a retried broadcast, an
+//! overlapping sync page, a server replaying frames) reaches the application
+//! once. The window is bounded: the oldest identities are forgotten first,
+//! and a duplicate older than the window is left to the sender key ratchet,
+//! which no longer holds its key.
+
+use std::collections::{HashSet, VecDeque};
+
+/// Most message identities remembered per room.
+pub const DEDUP_WINDOW: usize = 4096;
+
+/// Identity of one sender key message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageId {
+    /// Sender's leaf index.
+    pub sender_index: u32,
+    /// Epoch the message was encrypted in.
+    pub epoch: u64,
+    /// Sender ratchet generation.
+    pub generation: u32,
+}
+
+/// Recently delivered message identities of one room.
+#[derive(Debug)]
+pub struct SeenMessages {
+    ids: HashSet<MessageId>,
+    order: VecDeque<MessageId>,
+    capacity: usize,
+}
+
+impl Default for SeenMessages {
+    fn default() -> Self {
+        Self::with_capacity(DEDUP_WINDOW)
+    }
+}
+
+impl SeenMessages {
+    /// Window remembering at most `capacity` identities.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { ids: HashSet::new(), order: VecDeque::new(), capacity: capacity.max(1) }
+    }
+
+    /// Whether a message was delivered recently.
+    pub fn contains(&self, id: &MessageId) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// Remember a delivered message, forgetting the oldest if full.
+    pub fn insert(&mut self, id: MessageId) {
+        if !self.ids.insert(id) {
+            return;
+        }
+
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(generation: u32) -> MessageId {
+        MessageId { sender_index: 1, epoch: 3, generation }
+    }
+
+    #[test]
+    fn window_forgets_oldest_first() {
+        let mut seen = SeenMessages::with_capacity(2);
+        seen.insert(id(0));
+        seen.insert(id(1));
+        seen.insert(id(1));
+        assert!(seen.contains(&id(0)));
+
+        seen.insert(id(2));
+        assert!(!seen.contains(&id(0)));
+        assert!(seen.contains(&id(1)));
+        assert!(seen.contains(&id(2)));
+    }
+}
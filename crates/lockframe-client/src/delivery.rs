@@ -0,0 +1,80 @@
+//! Delivery state of our own messages.
+//!
+//! A message we send moves through states an application can render as
+//! ticks: handed to the server, sequenced into the room's log, then read by
+//! the other members. The first two follow from the outbox (see
+//! [`crate::outbox`]). Reads come from members' read receipts, each of which
+//! covers every message up to a log index, so every room remembers where its
+//! recent messages from us were sequenced and a receipt reports each of them
+//! it covers for the first time.
+//!
+//! Only the most recent messages are remembered; a receipt reaching further
+//! back reports nothing for the older ones.
+
+use std::collections::BTreeMap;
+
+/// Most of our sequenced messages remembered per room.
+pub const DELIVERY_WINDOW: usize = 1024;
+
+/// How far one of our messages got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryState {
+    /// Handed to the server, not yet sequenced.
+    Sent,
+    /// Sequenced into the room's log.
+    Sequenced {
+        /// Log index the message was sequenced at.
+        log_index: u64,
+    },
+    /// A member's read receipt covers the message.
+    Read {
+        /// Member who read it.
+        member_id: u64,
+    },
+}
+
+/// Where a room sequenced our recent messages.
+#[derive(Debug, Clone, Default)]
+pub struct SequencedMessages {
+    /// Log index → client-assigned message ID
+    messages: BTreeMap<u64, u64>,
+}
+
+impl SequencedMessages {
+    /// Remember that our message `message_id` was sequenced at `log_index`.
+    pub fn track(&mut self, log_index: u64, message_id: u64) {
+        self.messages.insert(log_index, message_id);
+        while self.messages.len() > DELIVERY_WINDOW {
+            self.messages.pop_first();
+        }
+    }
+
+    /// IDs of our messages a member reading up to `up_to` reads for the
+    /// first time, having read up to `before` until now, oldest first.
+    pub fn newly_read(&self, before: Option<u64>, up_to: u64) -> impl Iterator<Item = u64> + '_ {
+        let from = before.map_or(0, |before| before.saturating_add(1));
+        self.messages.range(from..=up_to).map(|(_, &message_id)| message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receipts_report_messages_they_newly_cover() {
+        let mut sequenced = SequencedMessages::default();
+        sequenced.track(2, 10);
+        sequenced.track(5, 11);
+        sequenced.track(9, 12);
+
+        assert_eq!(sequenced.newly_read(None, 5).collect::<Vec<_>>(), vec![10, 11]);
+        assert_eq!(sequenced.newly_read(Some(5), 8).count(), 0);
+        assert_eq!(sequenced.newly_read(Some(5), 9).collect::<Vec<_>>(), vec![12]);
+
+        for log_index in 10..=10 + DELIVERY_WINDOW as u64 {
+            sequenced.track(log_index, log_index);
+        }
+        assert_eq!(sequenced.newly_read(None, 9).count(), 0);
+    }
+}
@@ -0,0 +1,244 @@
+//! Messages composed and delivered a chunk at a time.
+//!
+//! A draft is a message the application produces incrementally, such as a
+//! voice note being recorded. Each chunk is sent as soon as it is appended,
+//! as its own application message with the `FRAGMENTED` frame flag, so
+//! receivers can play or render the draft while it is still being composed.
+//!
+//! The `FRAGMENTED` flag is in the cleartext frame header, so the server can
+//! tell a chunk from a whole message. The fragment header travels inside the
+//! plaintext, so it learns neither which chunks belong to one draft nor
+//! where a draft ends. Fragments are sequenced in the room's log like any
+//! message and so arrive in the order they were sent. Receivers follow each
+//! sender's drafts and refuse a chunk that does not continue its draft, e.g.
+//! after joining the room halfway through one.
+//!
+//! At most [`MAX_OPEN_DRAFTS`] drafts are followed each way per room, and a
+//! draft that sees no chunk for [`DRAFT_IDLE_TIMEOUT`] is dropped, so a
+//! sender that never finishes its drafts cannot grow a receiver's state.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Bytes of fragment header before each chunk.
+pub const FRAGMENT_HEADER_SIZE: usize = 13;
+
+/// Most drafts of one room followed at once, ours and other members' each.
+pub const MAX_OPEN_DRAFTS: usize = 64;
+
+/// How long a draft may go without a chunk before it is dropped.
+pub const DRAFT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Position of a chunk in its draft.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragment {
+    /// Draft the chunk belongs to, unique per sender.
+    pub draft_id: u64,
+    /// Position of the chunk in the draft, from 0.
+    pub index: u32,
+    /// Whether this is the draft's last chunk.
+    pub last: bool,
+}
+
+impl Fragment {
+    /// Plaintext carrying `chunk` at this position.
+    pub fn seal(&self, chunk: &[u8]) -> Vec<u8> {
+        let mut plaintext = Vec::with_capacity(FRAGMENT_HEADER_SIZE.saturating_add(chunk.len()));
+        plaintext.extend_from_slice(&self.draft_id.to_be_bytes());
+        plaintext.extend_from_slice(&self.index.to_be_bytes());
+        plaintext.push(u8::from(self.last));
+        plaintext.extend_from_slice(chunk);
+        plaintext
+    }
+
+    /// Split a fragment plaintext into its position and chunk.
+    pub fn open(plaintext: &[u8]) -> Result<(Self, &[u8]), String> {
+        let (Some(header), Some(chunk)) =
+            (plaintext.get(..FRAGMENT_HEADER_SIZE), plaintext.get(FRAGMENT_HEADER_SIZE..))
+        else {
+            return Err(format!("fragment of {} bytes has no header", plaintext.len()));
+        };
+        let (draft_id, rest) = header.split_at(8);
+        let (index, last) = rest.split_at(4);
+        let last = match last {
+            [0] => false,
+            [1] => true,
+            _ => return Err("fragment has an invalid last flag".to_string()),
+        };
+        let (Ok(draft_id), Ok(index)) = (draft_id.try_into(), index.try_into()) else {
+            return Err("fragment header is truncated".to_string());
+        };
+
+        let fragment =
+            Self { draft_id: u64::from_be_bytes(draft_id), index: u32::from_be_bytes(index), last };
+        Ok((fragment, chunk))
+    }
+}
+
+/// An open draft: the index of its next chunk and when it last had one.
+#[derive(Debug, Clone, Copy)]
+struct OpenDraft {
+    next: u32,
+    active: Instant,
+}
+
+/// Drafts of one room, sent and received.
+#[derive(Debug, Default)]
+pub struct Drafts {
+    /// Our open drafts by draft ID
+    outgoing: HashMap<u64, OpenDraft>,
+    /// Next draft ID to hand out
+    next_draft_id: u64,
+    /// Other members' open drafts by (sender, draft ID)
+    incoming: HashMap<(u64, u64), OpenDraft>,
+}
+
+impl Drafts {
+    /// Open a draft and return its ID.
+    ///
+    /// `None` if [`MAX_OPEN_DRAFTS`] of ours are already open.
+    pub fn start(&mut self, now: Instant) -> Option<u64> {
+        if self.outgoing.len() >= MAX_OPEN_DRAFTS {
+            return None;
+        }
+        let draft_id = self.next_draft_id;
+        self.next_draft_id = self.next_draft_id.wrapping_add(1);
+        self.outgoing.insert(draft_id, OpenDraft { next: 0, active: now });
+        Some(draft_id)
+    }
+
+    /// Position of the next chunk of `draft_id`, closing the draft if `last`.
+    ///
+    /// `None` if the draft is not open.
+    pub fn next_fragment(&mut self, draft_id: u64, last: bool, now: Instant) -> Option<Fragment> {
+        let draft = self.outgoing.get_mut(&draft_id)?;
+        let fragment = Fragment { draft_id, index: draft.next, last };
+        if last {
+            self.outgoing.remove(&draft_id);
+        } else {
+            draft.next = draft.next.checked_add(1)?;
+            draft.active = now;
+        }
+        Some(fragment)
+    }
+
+    /// Record a chunk `sender_id` sent, checking it continues its draft.
+    ///
+    /// A chunk at index 0 starts a draft, replacing any the sender left open
+    /// under the same ID. With [`MAX_OPEN_DRAFTS`] already followed, the
+    /// longest idle one is dropped to make room.
+    pub fn receive(
+        &mut self,
+        sender_id: u64,
+        fragment: Fragment,
+        now: Instant,
+    ) -> Result<(), String> {
+        let key = (sender_id, fragment.draft_id);
+        let expected = if fragment.index == 0 {
+            0
+        } else {
+            self.incoming.remove(&key).map_or(0, |draft| draft.next)
+        };
+        if fragment.index != expected {
+            self.incoming.remove(&key);
+            return Err(format!(
+                "chunk {} of draft {} from {sender_id} arrived, expected chunk {expected}",
+                fragment.index, fragment.draft_id
+            ));
+        }
+
+        match fragment.index.checked_add(1) {
+            Some(next) if !fragment.last => {
+                if !self.incoming.contains_key(&key) && self.incoming.len() >= MAX_OPEN_DRAFTS {
+                    let idlest = self.incoming.iter().min_by_key(|(_, draft)| draft.active);
+                    if let Some(idlest) = idlest.map(|(key, _)| *key) {
+                        self.incoming.remove(&idlest);
+                    }
+                }
+                self.incoming.insert(key, OpenDraft { next, active: now });
+            },
+            _ => {
+                self.incoming.remove(&key);
+            },
+        }
+        Ok(())
+    }
+
+    /// Drop drafts, ours and others', with no chunk for
+    /// [`DRAFT_IDLE_TIMEOUT`]. Returns how many were dropped.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.outgoing.len().saturating_add(self.incoming.len());
+        let live = |draft: &OpenDraft| now.duration_since(draft.active) < DRAFT_IDLE_TIMEOUT;
+        self.outgoing.retain(|_, draft| live(draft));
+        self.incoming.retain(|_, draft| live(draft));
+        before.saturating_sub(self.outgoing.len().saturating_add(self.incoming.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fragment_roundtrip() {
+        let fragment = Fragment { draft_id: 7, index: 3, last: true };
+        let plaintext = fragment.seal(b"chunk");
+        assert_eq!(plaintext.len(), FRAGMENT_HEADER_SIZE + 5);
+
+        let (opened, chunk) = Fragment::open(&plaintext).unwrap();
+        assert_eq!(opened, fragment);
+        assert_eq!(chunk, b"chunk");
+
+        assert!(Fragment::open(&plaintext[..FRAGMENT_HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn received_chunks_must_continue_their_draft() {
+        let mut drafts = Drafts::default();
+        let now = Instant::now();
+        let chunk = |index, last| Fragment { draft_id: 1, index, last };
+
+        drafts.receive(5, chunk(0, false), now).unwrap();
+        drafts.receive(5, chunk(1, false), now).unwrap();
+        assert!(drafts.receive(5, chunk(3, false), now).is_err());
+
+        // The draft was dropped, so its later chunks are refused too
+        assert!(drafts.receive(5, chunk(4, true), now).is_err());
+
+        // Chunk 0 starts over, and the last chunk closes the draft
+        drafts.receive(5, chunk(0, false), now).unwrap();
+        drafts.receive(5, chunk(1, true), now).unwrap();
+        assert!(drafts.receive(5, chunk(2, false), now).is_err());
+    }
+
+    #[test]
+    fn open_drafts_are_bounded_and_expire() {
+        let mut drafts = Drafts::default();
+        let start = Instant::now();
+        let later = start + Duration::from_secs(1);
+        let first = |draft_id| Fragment { draft_id, index: 0, last: false };
+
+        // The longest idle draft makes room for a new one
+        for draft_id in 0..MAX_OPEN_DRAFTS as u64 {
+            drafts.receive(5, first(draft_id), start).unwrap();
+        }
+        drafts.receive(5, Fragment { draft_id: 1, index: 1, last: false }, later).unwrap();
+        drafts.receive(6, first(0), later).unwrap();
+        assert_eq!(drafts.incoming.len(), MAX_OPEN_DRAFTS);
+        assert!(drafts.receive(5, Fragment { draft_id: 1, index: 2, last: false }, later).is_ok());
+
+        // Our own drafts are refused past the cap
+        let ours: Vec<u64> = (0..MAX_OPEN_DRAFTS).filter_map(|_| drafts.start(start)).collect();
+        assert_eq!(ours.len(), MAX_OPEN_DRAFTS);
+        assert_eq!(drafts.start(start), None);
+
+        // Everything idle past the timeout is dropped, ours included
+        drafts.next_fragment(ours[0], false, later).unwrap();
+        let expired = drafts.expire(start + DRAFT_IDLE_TIMEOUT);
+        assert_eq!(expired, 2 * MAX_OPEN_DRAFTS - 3);
+        assert!(drafts.next_fragment(ours[1], false, later).is_none());
+        assert!(drafts.start(later).is_some());
+    }
+}
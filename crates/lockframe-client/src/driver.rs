@@ -0,0 +1,631 @@
+//! Async runtime around the client, behind the `tokio` feature.
+//!
+//! [`Client`] does no I/O of its own. [`ClientDriver`] is the glue most
+//! applications want: it owns a QUIC connection to the home server, feeds
+//! the frames it receives into [`Client::handle`], executes the actions that
+//! need the network, ticks the client on an interval and hands delivered
+//! messages to the application over a channel. Actions it has no use for
+//! (room snapshots to persist, send states, membership changes, ...) go to a
+//! second channel, in the order the client produced them.
+//!
+//! Every connection starts with the handshake [`Client::hello`] begins: the
+//! driver waits for it to complete, answering the server's identity
+//! challenge with the key in the client's
+//! [`ClientIdentity`](crate::ClientIdentity), before the client uses the
+//! connection.
+//!
+//! Frames travel the way the server reads them: the client writes its frames
+//! back to back on one bidirectional stream, with the payload of a streamed
+//! frame on a unidirectional stream of its own, and the server sends each
+//! frame on a unidirectional stream, optionally followed by a timing
+//! trailer.
+//!
+//! When the connection drops the client goes offline, queueing what the
+//! application sends meanwhile, and the driver reconnects after
+//! [`Client::reconnect_delay`], or once a maintenance window is over.
+//!
+//! Messages a room skipped to fast-forward are backfilled in the background,
+//! one page per tick, after the [`ClientAction::BackfillPending`] announcing
+//! them is passed on.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use lockframe_core::{connection::DEFAULT_HANDSHAKE_TIMEOUT, env::Environment, mls::RoomId};
+use lockframe_proto::{
+    Frame, FrameHeader, FrameTiming, Opcode, Payload,
+    payloads::session::{SyncMode, SyncRequest},
+};
+use quinn::{Endpoint, RecvStream, SendStream};
+use rustls::RootCertStore;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{Client, ClientAction, ClientError, ClientEvent, DeliveredMessage, HOME_SERVER};
+
+/// How often the client is ticked by default.
+pub const DEFAULT_TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Frames asked for per sync request by default.
+pub const DEFAULT_SYNC_LIMIT: u64 = 100;
+
+/// Events, messages and actions buffered between the driver and the
+/// application.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Largest frame the server can send, trailer included.
+const MAX_INBOUND_SIZE: usize =
+    FrameHeader::SIZE + FrameHeader::MAX_PAYLOAD_SIZE as usize + FrameTiming::SIZE;
+
+/// Where and how a [`ClientDriver`] connects.
+///
+/// The key that answers identity challenges belongs to the client, see
+/// [`ClientIdentity::identity_key`](crate::ClientIdentity::identity_key).
+#[derive(Debug, Clone)]
+pub struct ClientDriverConfig {
+    /// Address of the home server
+    pub server_addr: SocketAddr,
+    /// Name the server's certificate is issued to
+    pub server_name: String,
+    /// CA certificates (PEM format) the server's certificate must chain to
+    pub ca_path: String,
+    /// Authentication token sent in the Hello, if the server wants one
+    pub auth_token: Option<Vec<u8>>,
+    /// How often the client is ticked
+    pub tick_interval: Duration,
+    /// Frames asked for per sync request
+    pub sync_limit: u64,
+}
+
+impl ClientDriverConfig {
+    /// Connect to `server_addr`, trusting the CAs in `ca_path` to vouch for
+    /// `server_name`.
+    pub fn new(
+        server_addr: SocketAddr,
+        server_name: impl Into<String>,
+        ca_path: impl Into<String>,
+    ) -> Self {
+        Self {
+            server_addr,
+            server_name: server_name.into(),
+            ca_path: ca_path.into(),
+            auth_token: None,
+            tick_interval: DEFAULT_TICK_INTERVAL,
+            sync_limit: DEFAULT_SYNC_LIMIT,
+        }
+    }
+}
+
+/// Feeds events to a running [`ClientDriver`].
+///
+/// The driver stops once every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct ClientHandle {
+    events: mpsc::Sender<Request>,
+}
+
+impl ClientHandle {
+    /// Have the client handle `event`, e.g. [`ClientEvent::SendMessage`].
+    ///
+    /// Resolves once the client handled it and the frames it produced were
+    /// written, with the error the client returned, if any.
+    pub async fn send(&self, event: ClientEvent) -> Result<(), ClientError> {
+        let (reply, handled) = oneshot::channel();
+        self.events.send(Request { event, reply }).await.map_err(|_| stopped())?;
+        handled.await.map_err(|_| stopped())?
+    }
+}
+
+/// What a [`ClientDriver`] hands to the application.
+#[derive(Debug)]
+pub struct ClientChannels {
+    /// Feeds events to the driver
+    pub handle: ClientHandle,
+    /// Messages the client decrypted, in delivery order
+    pub messages: mpsc::Receiver<DeliveredMessage>,
+    /// Every other action the driver does not execute itself
+    pub actions: mpsc::Receiver<ClientAction>,
+}
+
+/// An event from a [`ClientHandle`] and where its outcome goes.
+#[derive(Debug)]
+struct Request {
+    event: ClientEvent,
+    reply: oneshot::Sender<Result<(), ClientError>>,
+}
+
+/// A frame from the server, with its timing trailer if it had one.
+type Inbound = (Frame, Option<FrameTiming>);
+
+/// The connection to the home server while it is up.
+struct Connection {
+    quic: quinn::Connection,
+    /// Stream the client's frames are written to
+    control: SendStream,
+    /// Frames read from the server's streams; closes with the connection
+    inbound: mpsc::Receiver<Inbound>,
+}
+
+/// Runs a [`Client`] over a QUIC connection to its home server.
+///
+/// Connect with [`ClientDriver::connect`], then drive it with
+/// [`ClientDriver::run`] on a task of its own. The application talks to the
+/// client through the [`ClientChannels`] and must keep reading both
+/// receivers: the driver waits while either is full.
+pub struct ClientDriver<E: Environment> {
+    client: Client<E>,
+    config: ClientDriverConfig,
+    endpoint: Endpoint,
+    tls: quinn::ClientConfig,
+    connection: Option<Connection>,
+    events: mpsc::Receiver<Request>,
+    messages: mpsc::Sender<DeliveredMessage>,
+    actions: mpsc::Sender<ClientAction>,
+    /// Log index after the last frame received per room, where a sync with
+    /// no starting point of its own continues
+    next_log_index: HashMap<RoomId, u64>,
+    /// Earliest reconnect the server asked for before going away
+    reconnect_after: Option<Duration>,
+    /// Rooms with skipped messages to fetch the next page of
+    backfills: VecDeque<RoomId>,
+}
+
+impl<E: Environment> ClientDriver<E> {
+    /// Connect `client` to its home server and complete the handshake.
+    pub async fn connect(
+        client: Client<E>,
+        config: ClientDriverConfig,
+    ) -> Result<(Self, ClientChannels), ClientError> {
+        let tls = client_config(&config.ca_path)?;
+        let local: SocketAddr = if config.server_addr.is_ipv6() {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        };
+        let endpoint = Endpoint::client(local)
+            .map_err(|e| transport(format!("failed to create endpoint: {e}")))?;
+
+        let (events_tx, events) = mpsc::channel(CHANNEL_CAPACITY);
+        let (messages_tx, messages) = mpsc::channel(CHANNEL_CAPACITY);
+        let (actions_tx, actions) = mpsc::channel(CHANNEL_CAPACITY);
+        let mut driver = Self {
+            client,
+            config,
+            endpoint,
+            tls,
+            connection: None,
+            events,
+            messages: messages_tx,
+            actions: actions_tx,
+            next_log_index: HashMap::new(),
+            reconnect_after: None,
+            backfills: VecDeque::new(),
+        };
+        driver.connection = Some(open(&driver.endpoint, &driver.tls, &driver.config).await?);
+        driver.handshake().await?;
+
+        let channels =
+            ClientChannels { handle: ClientHandle { events: events_tx }, messages, actions };
+        Ok((driver, channels))
+    }
+
+    /// Drive the client until every [`ClientHandle`] is dropped, then close
+    /// the connection and hand the client back, e.g. to export its state.
+    pub async fn run(mut self) -> Result<Client<E>, ClientError> {
+        let mut ticks = tokio::time::interval(self.config.tick_interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            let Some(connection) = self.connection.as_mut() else {
+                if !self.reconnect().await {
+                    break;
+                }
+                continue;
+            };
+
+            tokio::select! {
+                request = self.events.recv() => {
+                    let Some(Request { event, reply }) = request else {
+                        break;
+                    };
+                    let result = self.handle(event).await;
+                    let _ = reply.send(result);
+                },
+                inbound = connection.inbound.recv() => match inbound {
+                    Some((frame, timing)) => self.receive(frame, timing).await,
+                    None => self.disconnected().await,
+                },
+                tick = ticks.tick() => {
+                    let event = ClientEvent::Tick { now: tick.into_std() };
+                    if let Err(e) = self.handle(event).await {
+                        tracing::debug!("Tick failed: {}", e);
+                    }
+                    self.backfill().await;
+                },
+            }
+        }
+
+        if let Some(connection) = self.connection.take() {
+            connection.quic.close(0u32.into(), b"client stopped");
+        }
+        self.endpoint.wait_idle().await;
+        Ok(self.client)
+    }
+
+    /// Have the client handle `event` and execute the actions.
+    async fn handle(&mut self, event: ClientEvent) -> Result<(), ClientError> {
+        let actions = self.client.handle(event)?;
+        self.execute(actions).await;
+        Ok(())
+    }
+
+    /// Request the next page of skipped messages for one room, if any.
+    ///
+    /// The page's response announces what is left, queueing the room again.
+    async fn backfill(&mut self) {
+        if self.connection.is_none() {
+            return;
+        }
+        let Some(room_id) = self.backfills.pop_front() else {
+            return;
+        };
+        if let Err(e) = self.handle(ClientEvent::Backfill { room_id }).await {
+            tracing::debug!("Backfill of room {:x} failed: {}", room_id, e);
+        }
+    }
+
+    /// Hand a frame from the server to the client.
+    async fn receive(&mut self, frame: Frame, timing: Option<FrameTiming>) {
+        if let Some(next) = sequenced_through(&frame) {
+            let room_next = self.next_log_index.entry(frame.header.room_id()).or_default();
+            *room_next = (*room_next).max(next);
+        }
+
+        let opcode = frame.header.opcode();
+        let event = match timing {
+            Some(timing) => ClientEvent::TimedFrameReceived { frame, timing },
+            None => ClientEvent::FrameReceived(frame),
+        };
+        if let Err(e) = self.handle(event).await {
+            tracing::debug!("Frame with opcode {:#06x} not handled: {}", opcode, e);
+        }
+    }
+
+    async fn execute(&mut self, actions: Vec<ClientAction>) {
+        for action in actions {
+            match action {
+                ClientAction::Send(frame) => self.send(&frame).await,
+                ClientAction::RequestSync { room_id, from_log_index, mode, .. } => {
+                    let from_log_index = from_log_index.unwrap_or_else(|| {
+                        self.next_log_index.get(&room_id).copied().unwrap_or_default()
+                    });
+                    match self.sync_request(room_id, from_log_index, mode) {
+                        Ok(frame) => self.send(&frame).await,
+                        Err(e) => tracing::warn!("Sync request for room {:x}: {}", room_id, e),
+                    }
+                },
+                ClientAction::DeliverMessage {
+                    room_id,
+                    sender_id,
+                    plaintext,
+                    log_index,
+                    timestamp,
+                    peer_verified,
+                } => {
+                    let message = DeliveredMessage {
+                        room_id,
+                        sender_id,
+                        plaintext,
+                        log_index,
+                        timestamp,
+                        peer_verified,
+                    };
+                    // Nobody is listening any more; the handles stop the driver
+                    let _ = self.messages.send(message).await;
+                },
+                ClientAction::Log { message } => tracing::debug!("{}", message),
+                ClientAction::Reconnect { server: HOME_SERVER, reason } => {
+                    // The client is already offline; the run loop reconnects
+                    if let Some(connection) = self.connection.take() {
+                        tracing::info!("Dropping connection: {}", reason);
+                        connection.quic.close(0u32.into(), b"heartbeat timeout");
+                    }
+                },
+                action => {
+                    if let ClientAction::BackfillPending { room_id, .. } = &action {
+                        if !self.backfills.contains(room_id) {
+                            self.backfills.push_back(*room_id);
+                        }
+                    }
+                    if let ClientAction::ServerMaintenance { server, reconnect_after, .. } = &action
+                    {
+                        if *server == HOME_SERVER {
+                            self.reconnect_after = Some(*reconnect_after);
+                        }
+                    }
+                    let _ = self.actions.send(action).await;
+                },
+            }
+        }
+    }
+
+    /// Write a frame to the server. A failed write is not retried: the
+    /// connection is going away, and the client resends what matters after
+    /// reconnecting.
+    async fn send(&mut self, frame: &Frame) {
+        let Some(connection) = self.connection.as_mut() else {
+            tracing::debug!("Offline, dropping frame with opcode {:#06x}", frame.header.opcode());
+            return;
+        };
+        if let Err(e) = write_frame(connection, frame).await {
+            tracing::debug!("Failed to send frame: {}", e);
+        }
+    }
+
+    fn sync_request(
+        &self,
+        room_id: RoomId,
+        from_log_index: u64,
+        mode: SyncMode,
+    ) -> Result<Frame, ClientError> {
+        let mut header = FrameHeader::new(Opcode::SyncRequest);
+        header.set_room_id(room_id);
+        header.set_sender_id(self.client.sender_id());
+        let request = SyncRequest { from_log_index, limit: self.config.sync_limit, mode };
+        Payload::SyncRequest(request)
+            .into_frame(header)
+            .map_err(|e| ClientError::InvalidFrame { reason: e.to_string() })
+    }
+
+    /// The connection dropped: take the client offline.
+    async fn disconnected(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            let reason = connection.quic.close_reason();
+            tracing::info!("Disconnected from server: {:?}", reason);
+        }
+        if let Err(e) = self.handle(ClientEvent::Disconnected).await {
+            tracing::warn!("Failed to go offline: {}", e);
+        }
+    }
+
+    /// Try to reconnect until it works, backing off between attempts.
+    ///
+    /// Returns `false` if every handle was dropped meanwhile.
+    async fn reconnect(&mut self) -> bool {
+        let mut attempt: u32 = 1;
+        loop {
+            let delay = self.client.reconnect_delay(attempt);
+            let delay = self.reconnect_after.take().map_or(delay, |after| after.max(delay));
+            if !self.wait_offline(delay).await {
+                return false;
+            }
+
+            let opened = match open(&self.endpoint, &self.tls, &self.config).await {
+                Ok(connection) => {
+                    self.connection = Some(connection);
+                    self.handshake().await
+                },
+                Err(e) => Err(e),
+            };
+            match opened {
+                Ok(()) => {
+                    if let Err(e) = self.handle(ClientEvent::Reconnected).await {
+                        tracing::warn!("Failed to come back online: {}", e);
+                    }
+                    return true;
+                },
+                Err(e) => {
+                    tracing::debug!("Reconnect attempt {} failed: {}", attempt, e);
+                    attempt = attempt.saturating_add(1);
+                },
+            }
+        }
+    }
+
+    /// Say Hello on the new connection and wait until the handshake
+    /// completes. On failure the connection is closed.
+    async fn handshake(&mut self) -> Result<(), ClientError> {
+        let result = tokio::time::timeout(DEFAULT_HANDSHAKE_TIMEOUT, self.exchange_hello())
+            .await
+            .unwrap_or_else(|_| Err(transport("handshake timed out".to_string())));
+        if let Err(e) = &result {
+            if let Some(connection) = self.connection.take() {
+                connection.quic.close(0u32.into(), e.to_string().as_bytes());
+            }
+        }
+        result
+    }
+
+    /// Send the client's Hello and hand it what the server answers until
+    /// the client considers the session authenticated.
+    async fn exchange_hello(&mut self) -> Result<(), ClientError> {
+        let hello = self.client.hello(HOME_SERVER, self.config.auth_token.clone())?;
+        self.send(&hello).await;
+
+        while !self.client.is_authenticated(HOME_SERVER) {
+            let inbound = match self.connection.as_mut() {
+                Some(connection) => connection.inbound.recv().await,
+                None => None,
+            };
+            let Some((frame, timing)) = inbound else {
+                return Err(transport("connection closed during handshake".to_string()));
+            };
+            let event = match timing {
+                Some(timing) => ClientEvent::TimedFrameReceived { frame, timing },
+                None => ClientEvent::FrameReceived(frame),
+            };
+            self.handle(event).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait `delay` while offline, still handing events to the client so
+    /// the application can queue messages. Returns `false` if every handle
+    /// was dropped.
+    async fn wait_offline(&mut self, delay: Duration) -> bool {
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                () = &mut sleep => return true,
+                request = self.events.recv() => {
+                    let Some(Request { event, reply }) = request else {
+                        return false;
+                    };
+                    let result = self.handle(event).await;
+                    let _ = reply.send(result);
+                },
+            }
+        }
+    }
+}
+
+/// Open a connection to the server and start reading from it.
+async fn open(
+    endpoint: &Endpoint,
+    tls: &quinn::ClientConfig,
+    config: &ClientDriverConfig,
+) -> Result<Connection, ClientError> {
+    let connecting = endpoint
+        .connect_with(tls.clone(), config.server_addr, &config.server_name)
+        .map_err(|e| transport(format!("failed to connect: {e}")))?;
+    let connection = connecting.await.map_err(|e| transport(format!("connection failed: {e}")))?;
+    let (control, recv) =
+        connection.open_bi().await.map_err(|e| transport(format!("open_bi failed: {e}")))?;
+    // The server answers on streams of its own
+    drop(recv);
+
+    let (inbound_tx, inbound) = mpsc::channel(CHANNEL_CAPACITY);
+    tokio::spawn(read_connection(connection.clone(), inbound_tx));
+
+    Ok(Connection { quic: connection, control, inbound })
+}
+
+/// Write a frame to the control stream, and the payload of a streamed frame
+/// to a stream of its own.
+async fn write_frame(connection: &mut Connection, frame: &Frame) -> Result<(), String> {
+    if frame.is_streamed() {
+        let (header, payload) = frame.encode_streamed().map_err(|e| e.to_string())?;
+        connection.control.write_all(&header).await.map_err(|e| e.to_string())?;
+        let mut stream = connection.quic.open_uni().await.map_err(|e| e.to_string())?;
+        stream.write_all(&payload).await.map_err(|e| e.to_string())?;
+        return stream.finish().map_err(|e| e.to_string());
+    }
+
+    let mut buf = Vec::new();
+    frame.encode(&mut buf).map_err(|e| e.to_string())?;
+    connection.control.write_all(&buf).await.map_err(|e| e.to_string())
+}
+
+/// Read the frames the server sends, one per unidirectional stream, until
+/// the connection closes or the driver stops listening.
+async fn read_connection(connection: quinn::Connection, inbound: mpsc::Sender<Inbound>) {
+    loop {
+        let recv = match connection.accept_uni().await {
+            Ok(recv) => recv,
+            Err(e) => {
+                tracing::debug!("Connection closed: {}", e);
+                return;
+            },
+        };
+        match read_frame(recv).await {
+            Ok(frame) => {
+                if inbound.send(frame).await.is_err() {
+                    return;
+                }
+            },
+            Err(e) => tracing::debug!("Dropping unreadable frame: {}", e),
+        }
+    }
+}
+
+async fn read_frame(mut recv: RecvStream) -> Result<Inbound, String> {
+    let bytes = recv.read_to_end(MAX_INBOUND_SIZE).await.map_err(|e| e.to_string())?;
+    Frame::decode_with_timing(&bytes).map_err(|e| e.to_string())
+}
+
+/// Log index after `frame`, if it is part of a room's log.
+///
+/// A sync response continues where its page ends.
+fn sequenced_through(frame: &Frame) -> Option<u64> {
+    match frame.header.opcode_enum()? {
+        Opcode::AppMessage
+        | Opcode::Commit
+        | Opcode::Proposal
+        | Opcode::ReadReceipt
+        | Opcode::Checkpoint => frame.header.log_index().checked_add(1),
+        Opcode::SyncResponse => match Payload::from_frame(frame.clone()) {
+            Ok(Payload::SyncResponse(response)) => response.next_log_index,
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// TLS configuration trusting the CAs in `ca_path`.
+fn client_config(ca_path: &str) -> Result<quinn::ClientConfig, ClientError> {
+    let ca_pem = std::fs::read(ca_path)
+        .map_err(|e| transport(format!("failed to read CA '{ca_path}': {e}")))?;
+
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut ca_pem.as_slice()) {
+        let cert = cert.map_err(|e| transport(format!("failed to parse CA: {e}")))?;
+        roots.add(cert).map_err(|e| transport(format!("invalid CA certificate: {e}")))?;
+    }
+    if roots.is_empty() {
+        return Err(transport(format!("no certificates in CA '{ca_path}'")));
+    }
+
+    let mut tls_config =
+        rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+    tls_config.alpn_protocols = vec![b"lockframe".to_vec()];
+
+    let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(tls_config)
+        .map_err(|e| transport(format!("QUIC config error: {e}")))?;
+    Ok(quinn::ClientConfig::new(Arc::new(crypto)))
+}
+
+fn transport(reason: String) -> ClientError {
+    ClientError::Transport { reason }
+}
+
+fn stopped() -> ClientError {
+    transport("client driver stopped".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use lockframe_proto::payloads::session::SyncResponse;
+
+    use super::*;
+
+    fn frame(opcode: Opcode, log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(opcode);
+        header.set_room_id(1);
+        header.set_log_index(log_index);
+        Frame::new(header, Vec::new())
+    }
+
+    #[test]
+    fn syncs_continue_after_the_last_logged_frame() {
+        assert_eq!(sequenced_through(&frame(Opcode::AppMessage, 4)), Some(5));
+        assert_eq!(sequenced_through(&frame(Opcode::Commit, 0)), Some(1));
+        // Relayed, never logged
+        assert_eq!(sequenced_through(&frame(Opcode::Typing, 0)), None);
+
+        let response = SyncResponse {
+            frames: Vec::new(),
+            has_more: true,
+            server_epoch: 2,
+            mode: SyncMode::Full,
+            next_log_index: Some(100),
+        };
+        let response = Payload::SyncResponse(response)
+            .into_frame(FrameHeader::new(Opcode::SyncResponse))
+            .unwrap();
+        assert_eq!(sequenced_through(&response), Some(100));
+    }
+}
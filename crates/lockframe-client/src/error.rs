@@ -55,6 +55,13 @@ pub enum ClientError {
         reason: String,
     },
 
+    /// Too many sent messages are still awaiting their sequenced copy.
+    #[error("outbox full: {max} messages await sequencing")]
+    OutboxFull {
+        /// Most messages awaiting sequencing at once.
+        max: usize,
+    },
+
     /// Sync required to process frame.
     #[error("sync required: room {room_id:x} needs epoch {target_epoch}")]
     SyncRequired {
@@ -63,6 +70,20 @@ pub enum ClientError {
         /// Target epoch to sync to.
         target_epoch: u64,
     },
+
+    /// The connection to the server could not be opened or used.
+    #[error("transport error: {reason}")]
+    Transport {
+        /// Description of the transport failure.
+        reason: String,
+    },
+
+    /// Message history could not be read or written.
+    #[error("storage error: {reason}")]
+    Storage {
+        /// Description of the storage failure.
+        reason: String,
+    },
 }
 
 impl ClientError {
@@ -82,7 +103,10 @@ impl ClientError {
             Self::RoomNotFound { .. }
             | Self::RoomAlreadyExists { .. }
             | Self::EpochMismatch { .. }
-            | Self::SyncRequired { .. } => false,
+            | Self::OutboxFull { .. }
+            | Self::SyncRequired { .. }
+            | Self::Transport { .. }
+            | Self::Storage { .. } => false,
         }
     }
 }
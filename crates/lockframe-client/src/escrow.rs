@@ -0,0 +1,31 @@
+//! Room key escrow.
+//!
+//! Regulated deployments may have to recover room contents without a
+//! member's device. A client with a [`KeyEscrow`] installed
+//! ([`Client::set_key_escrow`](crate::Client::set_key_escrow)) creates rooms
+//! whose group context names the organization's recovery key, and hands each
+//! epoch's room secret to the escrow to be wrapped to that key. The wrapped
+//! secret is returned as [`ClientAction::EscrowRoomKey`](crate::ClientAction)
+//! for the application to store.
+//!
+//! Escrow is off unless installed, and never silent: a room is only escrowed
+//! if its group context names the installed recovery key, every member
+//! creating or joining such a room is told with
+//! [`ClientAction::RoomEscrowed`](crate::ClientAction), and every secret
+//! handed over is logged.
+
+use crate::RoomId;
+
+/// Wraps room secrets to an organization recovery key.
+pub trait KeyEscrow: Send {
+    /// Identifier of the recovery key, advertised in the group context of
+    /// rooms this client creates.
+    fn recovery_key_id(&self) -> Vec<u8>;
+
+    /// Wrap the secret all message keys of `room_id` in `epoch` derive from.
+    ///
+    /// # Errors
+    ///
+    /// A description of why wrapping failed. The epoch is then not escrowed.
+    fn wrap(&mut self, room_id: RoomId, epoch: u64, secret: &[u8]) -> Result<Vec<u8>, String>;
+}
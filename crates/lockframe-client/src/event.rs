@@ -1,9 +1,16 @@
 //! Client events and actions.
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use lockframe_core::mls::RoomId;
-use lockframe_proto::Frame;
+use lockframe_proto::{
+    Frame, FrameTiming,
+    payloads::session::{DirectoryEntry, SyncMode},
+};
+
+use crate::{
+    attachments::AttachmentKey, delivery::DeliveryState, recovery::Recovery, servers::ServerId,
+};
 
 /// Events the caller feeds into the client.
 ///
@@ -14,17 +21,119 @@ use lockframe_proto::Frame;
 #[derive(Debug, Clone)]
 pub enum ClientEvent {
     /// Frame received from server.
+    ///
+    /// Same as [`ClientEvent::FrameReceivedFrom`] the
+    /// [`HOME_SERVER`](crate::HOME_SERVER).
     FrameReceived(Frame),
 
+    /// Frame received from one of several servers.
+    ///
+    /// Frames for a room are ignored unless they come from the room's home
+    /// server. A room joined through a Welcome from `server` is homed there.
+    FrameReceivedFrom {
+        /// Server the frame came from.
+        server: ServerId,
+        /// Frame received.
+        frame: Frame,
+    },
+
+    /// Frame received from server with the server timing trailer after it.
+    ///
+    /// Handled like [`ClientEvent::FrameReceived`], and additionally sampled
+    /// into [`Client::latency`](crate::Client::latency).
+    TimedFrameReceived {
+        /// Frame received.
+        frame: Frame,
+        /// Server timestamps from the trailer.
+        timing: FrameTiming,
+    },
+
+    /// Frame with a server timing trailer received from one of several
+    /// servers.
+    ///
+    /// Handled like [`ClientEvent::FrameReceivedFrom`], and sampled into
+    /// [`Client::latency`](crate::Client::latency) against that server's
+    /// clock.
+    TimedFrameReceivedFrom {
+        /// Server the frame came from.
+        server: ServerId,
+        /// Frame received.
+        frame: Frame,
+        /// Server timestamps from the trailer.
+        timing: FrameTiming,
+    },
+
     /// Time tick for timeout processing.
     ///
     /// The caller should send ticks periodically to allow the client
-    /// to detect timeouts and perform housekeeping.
+    /// to detect timeouts and perform housekeeping. Commits left
+    /// unsequenced are abandoned for a sync, a silent home connection is
+    /// probed with a heartbeat, and one whose heartbeat goes unacked is
+    /// reported with [`ClientAction::Reconnect`].
     Tick {
         /// Current time from the environment.
         now: Instant,
     },
 
+    /// Send a timestamped heartbeat to the home server to sample round-trip
+    /// time.
+    ///
+    /// The server echoes it back; the matching ack updates
+    /// [`Client::rtt`](crate::Client::rtt). Silent connections to other
+    /// servers are probed on [`ClientEvent::Tick`], see
+    /// [`Client::server_rtt`](crate::Client::server_rtt).
+    SendHeartbeat,
+
+    /// Connection to the server was lost.
+    ///
+    /// Until [`ClientEvent::Reconnected`], `SendMessage`, `AddMembers`,
+    /// `RemoveMembers` and `UpdateSelf` are queued instead of producing
+    /// frames.
+    ///
+    /// Same as [`ClientEvent::ServerDisconnected`] for the
+    /// [`HOME_SERVER`](crate::HOME_SERVER).
+    Disconnected,
+
+    /// Connection to the server was re-established.
+    ///
+    /// The client requests sync for every room with queued intents and
+    /// replays each room's intents, in order, once it has synced.
+    ///
+    /// Same as [`ClientEvent::ServerConnected`] for the
+    /// [`HOME_SERVER`](crate::HOME_SERVER).
+    Reconnected,
+
+    /// Connection to one of several servers was lost.
+    ///
+    /// Intents for rooms homed on `server` are queued until it reconnects.
+    ServerDisconnected {
+        /// Server whose connection dropped.
+        server: ServerId,
+    },
+
+    /// Connection to one of several servers was established.
+    ///
+    /// Servers other than the home server start out offline. The client
+    /// requests sync for rooms homed on `server` that have queued intents
+    /// or were moved there while it was offline. A room's queued intents
+    /// replay once it has synced; rooms on other servers don't hold them up.
+    ServerConnected {
+        /// Server now connected.
+        server: ServerId,
+    },
+
+    /// Application wants a room sequenced by another server, e.g. after
+    /// [`ClientAction::RoomMoved`].
+    ///
+    /// Any pending commit is dropped and the room is synced from `server`,
+    /// right away or once it is connected.
+    MoveRoom {
+        /// Room to move.
+        room_id: RoomId,
+        /// Server now hosting the room.
+        server: ServerId,
+    },
+
     /// Application wants to send a message.
     SendMessage {
         /// Target room.
@@ -33,6 +142,89 @@ pub enum ClientEvent {
         plaintext: Vec<u8>,
     },
 
+    /// The user started or stopped typing in a room.
+    ///
+    /// Relayed to the members connected now; nothing is sent while the
+    /// room's server is offline.
+    SetTyping {
+        /// Room the user is typing in.
+        room_id: RoomId,
+        /// Whether the user is typing.
+        typing: bool,
+    },
+
+    /// The user has read a room up to and including a message.
+    ///
+    /// Sent as a read receipt that the server sequences and keeps, so other
+    /// members and the user's other devices learn it. Queued while the
+    /// room's server is offline.
+    MarkRead {
+        /// Room the user has read.
+        room_id: RoomId,
+        /// Log index of the last message read.
+        up_to_log_index: u64,
+    },
+
+    /// Application wants to upload an attachment to a room's server.
+    ///
+    /// The attachment is encrypted under a key of its own and uploaded in
+    /// chunks, resuming after a reconnect. Answered with
+    /// [`ClientAction::AttachmentUploaded`] once the server has all of it.
+    UploadAttachment {
+        /// Room the attachment is for.
+        room_id: RoomId,
+        /// Attachment contents.
+        plaintext: Vec<u8>,
+    },
+
+    /// Application wants to download an attachment a member shared.
+    ///
+    /// The chunks are downloaded a window at a time, resuming after a
+    /// reconnect. Answered with [`ClientAction::AttachmentFetched`] once
+    /// every chunk has arrived and opened with `key`.
+    FetchAttachment {
+        /// Room the attachment was uploaded to.
+        room_id: RoomId,
+        /// Key the attachment is encrypted and stored under.
+        key: AttachmentKey,
+    },
+
+    /// Application wants to compose a message a chunk at a time, e.g. a
+    /// voice note while it is recorded.
+    ///
+    /// Answered with [`ClientAction::DraftStarted`]; send the chunks with
+    /// [`ClientEvent::AppendDraft`] and [`ClientEvent::FinishDraft`]. Fails
+    /// with [`MAX_OPEN_DRAFTS`](crate::MAX_OPEN_DRAFTS) drafts already open
+    /// in the room; a draft without a chunk for
+    /// [`DRAFT_IDLE_TIMEOUT`](crate::DRAFT_IDLE_TIMEOUT) is closed.
+    StartDraft {
+        /// Target room.
+        room_id: RoomId,
+    },
+
+    /// Application produced the next chunk of a draft.
+    ///
+    /// The chunk is encrypted and sent right away. Fails while the room's
+    /// server is offline rather than queueing.
+    AppendDraft {
+        /// Target room.
+        room_id: RoomId,
+        /// Draft from [`ClientAction::DraftStarted`].
+        draft_id: u64,
+        /// Next chunk of the message.
+        chunk: Vec<u8>,
+    },
+
+    /// Application produced the last chunk of a draft, which may be empty.
+    FinishDraft {
+        /// Target room.
+        room_id: RoomId,
+        /// Draft from [`ClientAction::DraftStarted`].
+        draft_id: u64,
+        /// Last chunk of the message.
+        chunk: Vec<u8>,
+    },
+
     /// Application wants to create a new room.
     CreateRoom {
         /// Room ID to create.
@@ -53,6 +245,42 @@ pub enum ClientEvent {
         room_id: RoomId,
     },
 
+    /// Application wants to revoke this account's other devices.
+    ///
+    /// The server closes their sessions and confirms with the revoked
+    /// members, which this client then commits out of every room it shares
+    /// with them.
+    RevokeSessions {
+        /// Members (devices) to revoke; empty revokes every other device.
+        member_ids: Vec<u64>,
+    },
+
+    /// Application wants a page of the server's room directory.
+    ///
+    /// Answered with [`ClientAction::RoomDirectory`].
+    ListRooms {
+        /// List rooms after this one (`None` starts from the beginning).
+        after: Option<RoomId>,
+        /// Maximum number of rooms to list.
+        limit: u32,
+    },
+
+    /// Application wants older messages skipped by an epoch fast-forward.
+    ///
+    /// Requests the next page of the range announced by
+    /// [`ClientAction::BackfillPending`]; the skipped messages are delivered
+    /// as the page arrives. Does nothing once the range is backfilled.
+    Backfill {
+        /// Room to backfill.
+        room_id: RoomId,
+    },
+
+    /// Application wants a `KeyPackage` others can add this client with.
+    ///
+    /// Produces [`ClientAction::PublishKeyPackage`]. The private state stays
+    /// with the client until a Welcome made for the `KeyPackage` arrives.
+    GenerateKeyPackage,
+
     /// Application wants to add members to a room.
     AddMembers {
         /// Target room.
@@ -60,6 +288,40 @@ pub enum ClientEvent {
         /// MLS `KeyPackage` messages (TLS-serialized).
         key_packages: Vec<Vec<u8>>,
     },
+
+    /// Application wants to remove members from a room.
+    ///
+    /// Produces a commit; the members are gone once the server sequences it
+    /// and it comes back to us. To leave a room use
+    /// [`ClientEvent::LeaveRoom`] instead.
+    RemoveMembers {
+        /// Target room.
+        room_id: RoomId,
+        /// Members to remove.
+        member_ids: Vec<u64>,
+    },
+
+    /// Application wants to rotate our own key material in a room.
+    ///
+    /// Produces a commit updating our leaf. Sender keys are re-derived from
+    /// the new epoch once the server sequences it and it comes back to us.
+    UpdateSelf {
+        /// Target room.
+        room_id: RoomId,
+    },
+
+    /// Application wants messages in a room to disappear after `ttl`.
+    ///
+    /// Set in the group context with a commit, so the change takes effect
+    /// for every member at the epoch it starts.
+    /// [`ClientAction::MessageTtlChanged`] follows once the server has
+    /// sequenced it.
+    SetMessageTtl {
+        /// Target room.
+        room_id: RoomId,
+        /// How long messages are kept (`None` keeps them indefinitely).
+        ttl: Option<Duration>,
+    },
 }
 
 /// Serializable snapshot of room state for persistence.
@@ -69,7 +331,7 @@ pub struct RoomStateSnapshot {
     pub room_id: RoomId,
     /// Current epoch.
     pub epoch: u64,
-    /// Serialized MLS group state.
+    /// Serialized MLS group state, including our private keys.
     pub mls_state: Vec<u8>,
     /// Our leaf index in the tree.
     pub my_leaf_index: u32,
@@ -79,8 +341,22 @@ pub struct RoomStateSnapshot {
 #[derive(Debug, Clone)]
 pub enum ClientAction {
     /// Send a frame to the server.
+    ///
+    /// Only used for the [`HOME_SERVER`](crate::HOME_SERVER); frames for
+    /// other servers are [`ClientAction::SendTo`]. Frames with the
+    /// `STREAMED` flag are written with
+    /// [`Frame::encode_streamed`](lockframe_proto::Frame::encode_streamed),
+    /// their payload on a stream of its own.
     Send(Frame),
 
+    /// Send a frame to a server other than the home server.
+    SendTo {
+        /// Server to send to.
+        server: ServerId,
+        /// Frame to send.
+        frame: Frame,
+    },
+
     /// Deliver decrypted message to application layer.
     DeliverMessage {
         /// Room the message is from.
@@ -93,12 +369,109 @@ pub enum ClientAction {
         log_index: u64,
         /// Message timestamp (HLC).
         timestamp: u64,
+        /// Sender's current key is the one the user verified, see
+        /// [`Client::mark_peer_verified`](crate::Client::mark_peer_verified).
+        peer_verified: bool,
+    },
+
+    /// A member started or stopped typing.
+    ///
+    /// Indicators are best effort: one may never arrive, so applications
+    /// should stop showing a member as typing after a while without news.
+    Typing {
+        /// Room the member is typing in.
+        room_id: RoomId,
+        /// Member's stable ID.
+        sender_id: u64,
+        /// Whether the member is typing.
+        typing: bool,
+    },
+
+    /// A member's read position moved forward, ours included.
+    ///
+    /// The room's positions are kept in [`crate::Client::read_state`].
+    ReadReceipt {
+        /// Room the receipt is for.
+        room_id: RoomId,
+        /// Member's stable ID.
+        sender_id: u64,
+        /// Log index of the last message the member has read.
+        up_to_log_index: u64,
+    },
+
+    /// The server holds the whole of an attachment we uploaded.
+    ///
+    /// Share `key` with the room in a message so members can fetch the
+    /// attachment and open it with [`crate::decrypt_attachment`].
+    AttachmentUploaded {
+        /// Room the attachment was uploaded to.
+        room_id: RoomId,
+        /// Key the attachment is encrypted and stored under.
+        key: AttachmentKey,
+    },
+
+    /// An attachment requested with [`ClientEvent::FetchAttachment`] was
+    /// downloaded and opened.
+    AttachmentFetched {
+        /// Room the attachment was downloaded from.
+        room_id: RoomId,
+        /// SHA-256 hash of the attachment's ciphertext.
+        content_hash: [u8; 32],
+        /// Attachment contents.
+        plaintext: Vec<u8>,
+    },
+
+    /// A draft was opened for [`ClientEvent::AppendDraft`].
+    DraftStarted {
+        /// Room the draft is for.
+        room_id: RoomId,
+        /// Draft identifier, unique among ours.
+        draft_id: u64,
+    },
+
+    /// Deliver the next chunk of a message another member is composing.
+    ///
+    /// Chunks of a draft arrive in order, starting at index 0; the one with
+    /// `last` set completes it. A chunk that does not continue its draft is
+    /// dropped and logged, as is the rest of that draft.
+    DraftChunk {
+        /// Room the draft is in.
+        room_id: RoomId,
+        /// Sender's stable ID.
+        sender_id: u64,
+        /// Draft identifier, unique among the sender's.
+        draft_id: u64,
+        /// Position of the chunk in the draft.
+        index: u32,
+        /// Decrypted chunk.
+        chunk: Vec<u8>,
+        /// Whether the draft is complete.
+        last: bool,
+        /// Log index in the room.
+        log_index: u64,
+        /// Chunk timestamp (HLC).
+        timestamp: u64,
+    },
+
+    /// An application message already delivered was received again.
+    ///
+    /// Nothing is delivered; reported so callers can count duplicates.
+    DuplicateSuppressed {
+        /// Room the message is from.
+        room_id: RoomId,
+        /// Sender's leaf index.
+        sender_index: u32,
+        /// Epoch the message was encrypted in.
+        epoch: u64,
+        /// Sender ratchet generation.
+        generation: u32,
     },
 
     /// Request missing commits for epoch sync.
     ///
-    /// The caller should fetch commits from the server and feed
-    /// them back as `FrameReceived` events.
+    /// The caller should fetch commits from the room's home server
+    /// ([`Client::home_server`](crate::Client::home_server)) and feed them
+    /// back as `FrameReceived` or `FrameReceivedFrom` events.
     RequestSync {
         /// Room that needs syncing.
         room_id: RoomId,
@@ -106,13 +479,49 @@ pub enum ClientAction {
         from_epoch: u64,
         /// Target epoch we need.
         to_epoch: u64,
+        /// Log index to sync from, if the client knows it. Otherwise the
+        /// caller continues after the last frame it delivered.
+        from_log_index: Option<u64>,
+        /// Which frames to request.
+        mode: SyncMode,
+    },
+
+    /// The room fast-forwarded past application messages.
+    ///
+    /// Messages in `from_log_index..to_log_index` were skipped to reach the
+    /// current epoch quickly. Send [`ClientEvent::Backfill`] to fetch them.
+    BackfillPending {
+        /// Room with skipped messages.
+        room_id: RoomId,
+        /// First skipped log index.
+        from_log_index: u64,
+        /// Log index after the last skipped frame.
+        to_log_index: u64,
     },
 
     /// Persist room state.
     ///
-    /// The caller decides the storage backend.
+    /// The caller decides the storage backend. Pass the latest snapshot of a
+    /// room to [`Client::restore_room`](crate::Client::restore_room) to
+    /// resume it.
     PersistRoom(RoomStateSnapshot),
 
+    /// Publish a `KeyPackage` (TLS-serialized) where members adding this
+    /// client can fetch it, e.g. a directory service.
+    ///
+    /// Each `KeyPackage` should be used for one Welcome only.
+    PublishKeyPackage(Vec<u8>),
+
+    /// Joined a room through a Welcome.
+    ///
+    /// Messages sent to the room from `epoch` on can be decrypted.
+    RoomJoined {
+        /// Room that was joined.
+        room_id: RoomId,
+        /// Epoch the Welcome admitted us to.
+        epoch: u64,
+    },
+
     /// Room was removed (left, kicked, or error).
     RoomRemoved {
         /// Room that was removed.
@@ -121,9 +530,225 @@ pub enum ClientAction {
         reason: String,
     },
 
+    /// The server presented inconsistent views of a room's log.
+    ///
+    /// Raised for rewritten frames, conflicting or mismatched checkpoints,
+    /// and Merkle proofs that don't verify. The offending frame is not
+    /// processed; what to do about the server is up to the caller.
+    TranscriptViolation {
+        /// Room whose log is inconsistent.
+        room_id: RoomId,
+        /// What was inconsistent.
+        reason: String,
+    },
+
+    /// The server rejected our commit because the room would exceed its
+    /// member limit.
+    ///
+    /// The pending commit is dropped and the room stays at its current
+    /// epoch.
+    RoomFull {
+        /// Room the commit was for.
+        room_id: RoomId,
+        /// The room's member limit.
+        max_members: u32,
+        /// Members the room would have had after the commit.
+        member_count: u32,
+    },
+
+    /// The room's message TTL changed.
+    ///
+    /// Messages delivered from now on are reported in
+    /// [`ClientAction::MessagesExpired`] once they are older than `ttl`.
+    MessageTtlChanged {
+        /// Room whose TTL changed.
+        room_id: RoomId,
+        /// How long messages are kept (`None` keeps them indefinitely).
+        ttl: Option<Duration>,
+    },
+
+    /// Delivered messages outlived the room's message TTL.
+    ///
+    /// Reported on [`ClientEvent::Tick`]. The application should delete
+    /// them; the server has pruned them or is about to.
+    MessagesExpired {
+        /// Room the messages were in.
+        room_id: RoomId,
+        /// Log indices of the expired messages, oldest first.
+        log_indices: Vec<u64>,
+    },
+
+    /// The server refused a frame for a room.
+    ///
+    /// `recovery` says what to do about it. For [`Recovery::Sync`] a
+    /// [`ClientAction::RequestSync`] follows. For [`Recovery::Backoff`] the
+    /// refused frame is resent on the first tick after the delay. A pending
+    /// commit the error names is dropped unless it is to be resent.
+    ServerError {
+        /// Room the refused frame was for.
+        room_id: RoomId,
+        /// Request ID of the refused frame, zero if it had none.
+        request_id: u32,
+        /// Error code, one of the `ErrorPayload` constants.
+        code: u16,
+        /// Human-readable error message.
+        message: String,
+        /// What to do about it.
+        recovery: Recovery,
+    },
+
+    /// The room moved to another server.
+    ///
+    /// Any pending commit was dropped, as the old server no longer sequences
+    /// the room. Reconnect to `target` and sync the room from there.
+    RoomMoved {
+        /// Room that moved.
+        room_id: RoomId,
+        /// Address of the server now hosting the room.
+        target: String,
+        /// First log index sequenced by the new server.
+        cutover_log_index: u64,
+    },
+
+    /// The server is going into maintenance and closing the connection.
+    ///
+    /// Frames it had queued were delivered first. Reconnect no sooner than
+    /// `reconnect_after`.
+    ServerMaintenance {
+        /// Server going into maintenance.
+        server: ServerId,
+        /// How long until the server accepts connections again.
+        reconnect_after: Duration,
+        /// Why, for display.
+        reason: String,
+    },
+
+    /// The connection to a server stopped acking heartbeats and is taken
+    /// for dead.
+    ///
+    /// The client already went offline for the server, as on
+    /// [`ClientEvent::ServerDisconnected`]. Close the connection, open a new
+    /// one and report [`ClientEvent::ServerConnected`].
+    Reconnect {
+        /// Server whose connection is dead.
+        server: ServerId,
+        /// Why, for display.
+        reason: String,
+    },
+
+    /// A page of the server's room directory arrived.
+    ///
+    /// Ask for the next page with [`ClientEvent::ListRooms`] after the last
+    /// room ID if `more` is set.
+    RoomDirectory {
+        /// Listed rooms, ordered by room ID.
+        rooms: Vec<DirectoryEntry>,
+        /// Whether more rooms follow the last one.
+        more: bool,
+    },
+
+    /// The server revoked other devices of this account.
+    ///
+    /// Commits removing them were sent for every room that had them.
+    SessionsRevoked {
+        /// Members that were revoked.
+        member_ids: Vec<u64>,
+        /// Connections the server closed.
+        sessions_closed: u32,
+    },
+
+    /// One of our application messages got further.
+    ///
+    /// Reported as [`DeliveryState::Sent`] when the message is handed to the
+    /// server, [`DeliveryState::Sequenced`] once the server sequenced it, and
+    /// [`DeliveryState::Read`] for every member whose read receipt covers it.
+    /// A message still unsequenced when the connection drops is resent after
+    /// reconnecting.
+    DeliveryUpdate {
+        /// Client-assigned message ID, or the [`ClientAction::IntentQueued`]
+        /// ID of a message sent offline.
+        message_id: u64,
+        /// Room the message was sent to.
+        room_id: RoomId,
+        /// How far the message got.
+        state: DeliveryState,
+    },
+
+    /// An intent was queued while offline.
+    ///
+    /// Its outcome is reported as [`ClientAction::IntentResolved`] after the
+    /// client reconnects and syncs the room, or once the server refuses the
+    /// sync.
+    IntentQueued {
+        /// Identifier of the queued intent.
+        intent_id: u64,
+        /// Room the intent targets.
+        room_id: RoomId,
+    },
+
+    /// A queued intent was replayed, or given up on because its room could
+    /// not be synced.
+    ///
+    /// Any frames it produced precede this action.
+    IntentResolved {
+        /// Identifier from [`ClientAction::IntentQueued`].
+        intent_id: u64,
+        /// Room the intent targets.
+        room_id: RoomId,
+        /// Room epoch when the intent was queued.
+        queued_epoch: u64,
+        /// What happened on replay.
+        outcome: IntentOutcome,
+    },
+
+    /// The room's secrets are escrowed to an organization recovery key.
+    ///
+    /// Reported when the client creates or joins the room, so the user can be
+    /// told, whether or not this client escrows them itself.
+    RoomEscrowed {
+        /// Room identifier.
+        room_id: RoomId,
+        /// Recovery key named in the room's group context.
+        recovery_key_id: Vec<u8>,
+    },
+
+    /// An epoch's room secret, wrapped by the installed
+    /// [`KeyEscrow`](crate::KeyEscrow), for the application to store with
+    /// the organization.
+    EscrowRoomKey {
+        /// Room identifier.
+        room_id: RoomId,
+        /// Epoch the secret belongs to.
+        epoch: u64,
+        /// Secret wrapped to the recovery key.
+        wrapped: Vec<u8>,
+    },
+
     /// Log message for debugging.
     Log {
         /// Log message.
         message: String,
     },
 }
+
+/// Result of replaying a queued intent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntentOutcome {
+    /// Frames were produced for the server.
+    Sent {
+        /// Room epoch the frames were produced at. Differs from the queued
+        /// epoch if the group moved on while the client was offline.
+        epoch: u64,
+    },
+
+    /// The client is no longer a member of the room.
+    RoomGone,
+
+    /// Replay failed; nothing was sent.
+    Failed {
+        /// Description of the failure.
+        reason: String,
+        /// Whether making the same intent again may succeed.
+        retryable: bool,
+    },
+}
@@ -0,0 +1,87 @@
+//! Disappearing messages.
+//!
+//! A room may set a message TTL, agreed by its members with a commit. The
+//! server prunes older frames from its log, but copies already delivered stay
+//! with the clients, so each client also forgets them: delivered messages are
+//! tracked by the time they were sent, and every tick reports the ones whose
+//! TTL has run out for the application to delete.
+//!
+//! Age is measured from the HLC physical time the sender stamped, the same
+//! clock the server prunes by, so both sides expire a message together.
+
+use std::{collections::BTreeSet, time::Duration};
+
+/// Delivered messages of one room that are due to expire.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Expiry {
+    /// How long messages are kept (`None` keeps them indefinitely)
+    ttl: Option<Duration>,
+    /// (sent at in Unix milliseconds, log index) of tracked messages
+    pending: BTreeSet<(u64, u64)>,
+}
+
+impl Expiry {
+    /// The room's message TTL.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Change the room's message TTL.
+    ///
+    /// Clearing it keeps every message delivered so far.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        if ttl.is_none() {
+            self.pending.clear();
+        }
+        self.ttl = ttl;
+    }
+
+    /// Track a delivered message sent at `sent_millis`, if the room has a TTL.
+    pub fn track(&mut self, sent_millis: u64, log_index: u64) {
+        if self.ttl.is_some() {
+            self.pending.insert((sent_millis, log_index));
+        }
+    }
+
+    /// Stop tracking messages that expired by `now_millis`, returning their
+    /// log indices in the order they were sent.
+    pub fn sweep(&mut self, now_millis: u64) -> Vec<u64> {
+        let Some(ttl) = self.ttl else {
+            return Vec::new();
+        };
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let cutoff = now_millis.saturating_sub(ttl);
+
+        let retained = self.pending.split_off(&(cutoff, 0));
+        let expired = std::mem::replace(&mut self.pending, retained);
+        expired.into_iter().map(|(_, log_index)| log_index).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_messages_older_than_ttl() {
+        let mut expiry = Expiry::default();
+
+        // Untracked without a TTL
+        expiry.track(1_000, 1);
+        assert!(expiry.sweep(u64::MAX).is_empty());
+
+        expiry.set_ttl(Some(Duration::from_secs(10)));
+        expiry.track(5_000, 3);
+        expiry.track(2_000, 2);
+        expiry.track(20_000, 4);
+
+        assert!(expiry.sweep(11_999).is_empty());
+        assert_eq!(expiry.sweep(15_001), vec![2, 3]);
+        assert!(expiry.sweep(15_001).is_empty());
+
+        // Clearing the TTL keeps what was delivered
+        expiry.set_ttl(None);
+        expiry.set_ttl(Some(Duration::from_secs(10)));
+        assert!(expiry.sweep(60_000).is_empty());
+    }
+}
@@ -0,0 +1,424 @@
+//! Local message history.
+//!
+//! The client delivers each message once and keeps no plaintext. An
+//! application that wants to show past messages passes the client's actions
+//! through a [`MessageHistory`], which records every
+//! [`ClientAction::DeliverMessage`] per room and pages through them newest
+//! first. Messages the room's TTL expires are deleted from it as well, so
+//! disappearing messages don't outlive the server's copy.
+//!
+//! Where the messages are kept is up to a [`ClientStorage`]:
+//! [`MemoryClientStorage`] for tests and ephemeral sessions,
+//! [`FileClientStorage`] for a history that survives restarts. The history
+//! holds plaintext, so the application must store it as securely as the
+//! messages themselves deserve.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use lockframe_core::mls::RoomId;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ClientError, event::ClientAction};
+
+/// File extension of a room's history file.
+const HISTORY_EXTENSION: &str = "history";
+
+/// Size of the length prefix of each record in a history file.
+const RECORD_LEN_SIZE: usize = 4;
+
+/// A delivered message as kept in the history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredMessage {
+    /// Room the message is from.
+    pub room_id: RoomId,
+    /// Log index in the room.
+    pub log_index: u64,
+    /// Sender's stable ID.
+    pub sender_id: u64,
+    /// Message timestamp (HLC).
+    pub timestamp: u64,
+    /// Decrypted plaintext.
+    pub plaintext: Vec<u8>,
+}
+
+/// Where a [`MessageHistory`] keeps its messages.
+///
+/// Messages of a room are keyed by log index; storing one at an index that
+/// is taken replaces it, so recording a redelivered message is harmless.
+pub trait ClientStorage {
+    /// Store a message, replacing any at the same room and log index.
+    fn store_message(&mut self, message: &StoredMessage) -> Result<(), ClientError>;
+
+    /// Up to `limit` of a room's messages with a log index below `before`,
+    /// or its latest if `before` is `None`, oldest first.
+    fn load_messages(
+        &self,
+        room_id: RoomId,
+        before: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, ClientError>;
+
+    /// Delete a room's messages at `log_indices`. Indices with no message
+    /// are ignored.
+    fn delete_messages(&mut self, room_id: RoomId, log_indices: &[u64]) -> Result<(), ClientError>;
+
+    /// Delete every message of a room.
+    fn delete_room(&mut self, room_id: RoomId) -> Result<(), ClientError>;
+}
+
+/// History kept in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryClientStorage {
+    rooms: HashMap<RoomId, BTreeMap<u64, StoredMessage>>,
+}
+
+impl MemoryClientStorage {
+    /// Create an empty storage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every message of a room, oldest first.
+    fn room_messages(&self, room_id: RoomId) -> impl Iterator<Item = &StoredMessage> {
+        self.rooms.get(&room_id).into_iter().flat_map(BTreeMap::values)
+    }
+}
+
+impl ClientStorage for MemoryClientStorage {
+    fn store_message(&mut self, message: &StoredMessage) -> Result<(), ClientError> {
+        self.rooms.entry(message.room_id).or_default().insert(message.log_index, message.clone());
+        Ok(())
+    }
+
+    fn load_messages(
+        &self,
+        room_id: RoomId,
+        before: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, ClientError> {
+        let Some(messages) = self.rooms.get(&room_id) else {
+            return Ok(Vec::new());
+        };
+
+        let range = before.map_or_else(|| messages.range(..), |before| messages.range(..before));
+        let mut page: Vec<StoredMessage> =
+            range.rev().take(limit).map(|(_, m)| m.clone()).collect();
+        page.reverse();
+        Ok(page)
+    }
+
+    fn delete_messages(&mut self, room_id: RoomId, log_indices: &[u64]) -> Result<(), ClientError> {
+        if let Some(messages) = self.rooms.get_mut(&room_id) {
+            for log_index in log_indices {
+                messages.remove(log_index);
+            }
+            if messages.is_empty() {
+                self.rooms.remove(&room_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_room(&mut self, room_id: RoomId) -> Result<(), ClientError> {
+        self.rooms.remove(&room_id);
+        Ok(())
+    }
+}
+
+/// History kept in a directory, one file per room.
+///
+/// A room's file is a sequence of length-prefixed CBOR records, appended to
+/// as messages arrive. Deleting messages rewrites the file. Every file is
+/// read into memory when the storage is opened, and queries are answered
+/// from there.
+#[derive(Debug)]
+pub struct FileClientStorage {
+    root: PathBuf,
+    cache: MemoryClientStorage,
+}
+
+impl FileClientStorage {
+    /// Open the history in `root`, creating the directory if missing.
+    ///
+    /// A record cut short at the end of a file, as a crash mid-write leaves
+    /// it, is dropped from the file.
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root).map_err(|e| storage_error(&root, &e))?;
+
+        let mut cache = MemoryClientStorage::new();
+        let entries = fs::read_dir(&root).map_err(|e| storage_error(&root, &e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| storage_error(&root, &e))?.path();
+            if path.extension().is_some_and(|extension| extension == HISTORY_EXTENSION) {
+                let bytes = fs::read(&path).map_err(|e| storage_error(&path, &e))?;
+                let (messages, intact) = decode_records(&bytes)?;
+                if intact < bytes.len() {
+                    // Later appends would land behind the torn record
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .open(&path)
+                        .and_then(|file| file.set_len(intact as u64))
+                        .map_err(|e| storage_error(&path, &e))?;
+                }
+                for message in messages {
+                    cache.store_message(&message)?;
+                }
+            }
+        }
+
+        Ok(Self { root, cache })
+    }
+
+    fn path(&self, room_id: RoomId) -> PathBuf {
+        self.root.join(format!("{room_id:032x}.{HISTORY_EXTENSION}"))
+    }
+
+    /// Replace a room's file with its messages in the cache.
+    fn rewrite_room(&self, room_id: RoomId) -> Result<(), ClientError> {
+        let path = self.path(room_id);
+        let mut bytes = Vec::new();
+        for message in self.cache.room_messages(room_id) {
+            bytes.extend(encode_record(message)?);
+        }
+        if bytes.is_empty() {
+            return remove_file(&path);
+        }
+
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bytes).map_err(|e| storage_error(&tmp, &e))?;
+        fs::File::open(&tmp)
+            .and_then(|file| file.sync_all())
+            .map_err(|e| storage_error(&tmp, &e))?;
+        fs::rename(&tmp, &path).map_err(|e| storage_error(&path, &e))
+    }
+}
+
+impl ClientStorage for FileClientStorage {
+    fn store_message(&mut self, message: &StoredMessage) -> Result<(), ClientError> {
+        let path = self.path(message.room_id);
+        let record = encode_record(message)?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| storage_error(&path, &e))?;
+        file.write_all(&record)
+            .and_then(|()| file.sync_data())
+            .map_err(|e| storage_error(&path, &e))?;
+
+        self.cache.store_message(message)
+    }
+
+    fn load_messages(
+        &self,
+        room_id: RoomId,
+        before: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, ClientError> {
+        self.cache.load_messages(room_id, before, limit)
+    }
+
+    fn delete_messages(&mut self, room_id: RoomId, log_indices: &[u64]) -> Result<(), ClientError> {
+        self.cache.delete_messages(room_id, log_indices)?;
+        self.rewrite_room(room_id)
+    }
+
+    fn delete_room(&mut self, room_id: RoomId) -> Result<(), ClientError> {
+        self.cache.delete_room(room_id)?;
+        remove_file(&self.path(room_id))
+    }
+}
+
+/// Delivered messages of every room, kept in a [`ClientStorage`].
+#[derive(Debug)]
+pub struct MessageHistory<S> {
+    storage: S,
+}
+
+impl<S: ClientStorage> MessageHistory<S> {
+    /// Create a history kept in `storage`.
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Record delivered messages among `actions` and delete expired ones.
+    /// Other actions are ignored.
+    pub fn record(&mut self, actions: &[ClientAction]) -> Result<(), ClientError> {
+        for action in actions {
+            match action {
+                ClientAction::DeliverMessage {
+                    room_id,
+                    sender_id,
+                    plaintext,
+                    log_index,
+                    timestamp,
+                    ..
+                } => self.storage.store_message(&StoredMessage {
+                    room_id: *room_id,
+                    log_index: *log_index,
+                    sender_id: *sender_id,
+                    timestamp: *timestamp,
+                    plaintext: plaintext.clone(),
+                })?,
+                ClientAction::MessagesExpired { room_id, log_indices } => {
+                    self.storage.delete_messages(*room_id, log_indices)?;
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    /// Up to `limit` of a room's messages before log index `before_index`,
+    /// or its latest if `None`, oldest first. Page further back by passing
+    /// the first message's log index as the next `before_index`.
+    pub fn history(
+        &self,
+        room_id: RoomId,
+        before_index: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, ClientError> {
+        self.storage.load_messages(room_id, before_index, limit)
+    }
+
+    /// Delete a room's history, e.g. after leaving it.
+    pub fn forget_room(&mut self, room_id: RoomId) -> Result<(), ClientError> {
+        self.storage.delete_room(room_id)
+    }
+
+    /// The storage the history is kept in.
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+}
+
+/// Length-prefixed CBOR record of `message`.
+fn encode_record(message: &StoredMessage) -> Result<Vec<u8>, ClientError> {
+    let mut body = Vec::new();
+    ciborium::ser::into_writer(message, &mut body)
+        .map_err(|e| ClientError::Storage { reason: format!("failed to encode message: {e}") })?;
+    let len = u32::try_from(body.len())
+        .map_err(|_| ClientError::Storage { reason: "message too large to store".to_string() })?;
+
+    let mut record = len.to_le_bytes().to_vec();
+    record.extend(body);
+    Ok(record)
+}
+
+/// Messages of a history file, stopping at a record cut short, and the
+/// length of the records before it.
+fn decode_records(file: &[u8]) -> Result<(Vec<StoredMessage>, usize), ClientError> {
+    let mut messages = Vec::new();
+    let mut bytes = file;
+    while let Some(Ok(len)) = bytes.get(..RECORD_LEN_SIZE).map(<[u8; RECORD_LEN_SIZE]>::try_from) {
+        let rest = bytes.get(RECORD_LEN_SIZE..).unwrap_or_default();
+        let len = usize::try_from(u32::from_le_bytes(len)).unwrap_or(usize::MAX);
+        let (Some(body), Some(rest)) = (rest.get(..len), rest.get(len..)) else {
+            break;
+        };
+        let message = ciborium::de::from_reader(body)
+            .map_err(|e| ClientError::Storage { reason: format!("invalid history record: {e}") })?;
+        messages.push(message);
+        bytes = rest;
+    }
+    Ok((messages, file.len().saturating_sub(bytes.len())))
+}
+
+fn remove_file(path: &Path) -> Result<(), ClientError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(storage_error(path, &e)),
+        _ => Ok(()),
+    }
+}
+
+fn storage_error(path: &Path, error: &std::io::Error) -> ClientError {
+    ClientError::Storage { reason: format!("{}: {error}", path.display()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delivered(room_id: RoomId, log_index: u64) -> ClientAction {
+        ClientAction::DeliverMessage {
+            room_id,
+            sender_id: 7,
+            plaintext: format!("message {log_index}").into_bytes(),
+            log_index,
+            timestamp: log_index * 1000,
+            peer_verified: false,
+        }
+    }
+
+    fn log_indices(page: &[StoredMessage]) -> Vec<u64> {
+        page.iter().map(|message| message.log_index).collect()
+    }
+
+    #[test]
+    fn history_pages_back_from_the_latest_message() {
+        let mut history = MessageHistory::new(MemoryClientStorage::new());
+        let actions: Vec<ClientAction> = (0..5).map(|i| delivered(1, i)).collect();
+        history.record(&actions).unwrap();
+        history.record(&[delivered(2, 9), delivered(1, 4)]).unwrap();
+
+        let page = history.history(1, None, 2).unwrap();
+        assert_eq!(log_indices(&page), vec![3, 4]);
+        let page = history.history(1, Some(page[0].log_index), 2).unwrap();
+        assert_eq!(log_indices(&page), vec![1, 2]);
+        let page = history.history(1, Some(1), 2).unwrap();
+        assert_eq!(log_indices(&page), vec![0]);
+        assert!(history.history(1, Some(0), 2).unwrap().is_empty());
+        assert!(history.history(3, None, 2).unwrap().is_empty());
+
+        history
+            .record(&[ClientAction::MessagesExpired { room_id: 1, log_indices: vec![0, 1, 7] }])
+            .unwrap();
+        assert_eq!(log_indices(&history.history(1, None, 10).unwrap()), vec![2, 3, 4]);
+
+        history.forget_room(1).unwrap();
+        assert!(history.history(1, None, 10).unwrap().is_empty());
+        assert_eq!(log_indices(&history.history(2, None, 10).unwrap()), vec![9]);
+    }
+
+    #[test]
+    fn file_history_survives_reopening() {
+        let dir =
+            std::env::temp_dir().join(format!("lockframe-client-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut history = MessageHistory::new(FileClientStorage::open(&dir).unwrap());
+        let actions: Vec<ClientAction> = (0..4).map(|i| delivered(1, i)).collect();
+        history.record(&actions).unwrap();
+        history.record(&[delivered(2, 0)]).unwrap();
+        history
+            .record(&[ClientAction::MessagesExpired { room_id: 1, log_indices: vec![1] }])
+            .unwrap();
+        history.forget_room(2).unwrap();
+        drop(history);
+
+        // A crash cut the last record short
+        let path = dir.join(format!("{:032x}.{HISTORY_EXTENSION}", 1));
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[200, 0, 0, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let mut history = MessageHistory::new(FileClientStorage::open(&dir).unwrap());
+        history.record(&[delivered(1, 4)]).unwrap();
+        drop(history);
+
+        let history = MessageHistory::new(FileClientStorage::open(&dir).unwrap());
+        let page = history.history(1, None, 10).unwrap();
+        assert_eq!(log_indices(&page), vec![0, 2, 3, 4]);
+        assert_eq!(page[2].plaintext, b"message 3");
+        assert_eq!(page[2].timestamp, 3000);
+        assert!(history.history(2, None, 10).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+//! Offline intent queue.
+//!
+//! While the client is disconnected, application intents that need the server
+//! (sending a message or read receipt, changing membership) are queued instead
+//! of being turned into frames. Encrypting or committing against a stale epoch
+//! would only produce frames the server rejects, so a room's intents wait
+//! until its server is back and the client has caught up on the room, then
+//! replay in the order they were made. Rooms replay independently: one whose
+//! server stays away holds up only its own intents.
+//!
+//! Messages that were sent but not yet sequenced when the connection dropped
+//! are put back at the front of the queue, see [`crate::outbox`]. Intent and
+//! message IDs are allocated from the same counter, so a message keeps its ID
+//! whether it was sent, queued or resent.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use lockframe_core::mls::RoomId;
+use serde::{Deserialize, Serialize};
+
+use crate::{outbox::Outgoing, persistence::PersistedIntents};
+
+/// Application intent deferred until the client is back online.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Intent {
+    /// Send an application message.
+    SendMessage {
+        /// Target room.
+        room_id: RoomId,
+        /// Message plaintext.
+        plaintext: Vec<u8>,
+    },
+
+    /// Mark a room as read up to a message.
+    MarkRead {
+        /// Target room.
+        room_id: RoomId,
+        /// Log index of the last message read.
+        up_to_log_index: u64,
+    },
+
+    /// Add members to a room.
+    AddMembers {
+        /// Target room.
+        room_id: RoomId,
+        /// MLS `KeyPackage` messages (TLS-serialized).
+        key_packages: Vec<Vec<u8>>,
+    },
+
+    /// Remove members from a room.
+    RemoveMembers {
+        /// Target room.
+        room_id: RoomId,
+        /// Members to remove.
+        member_ids: Vec<u64>,
+    },
+
+    /// Rotate our own key material in a room.
+    UpdateSelf {
+        /// Target room.
+        room_id: RoomId,
+    },
+
+    /// Resend a message whose sequenced copy never came back.
+    Resend(Outgoing),
+}
+
+impl Intent {
+    /// Room the intent targets.
+    pub fn room_id(&self) -> RoomId {
+        match self {
+            Self::SendMessage { room_id, .. }
+            | Self::MarkRead { room_id, .. }
+            | Self::AddMembers { room_id, .. }
+            | Self::RemoveMembers { room_id, .. }
+            | Self::UpdateSelf { room_id } => *room_id,
+            Self::Resend(outgoing) => outgoing.room_id,
+        }
+    }
+}
+
+/// An intent waiting in the queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedIntent {
+    /// Identifier reported back in the intent's outcome.
+    pub id: u64,
+    /// Room epoch when the intent was queued.
+    pub queued_epoch: u64,
+    /// The deferred intent.
+    pub intent: Intent,
+}
+
+/// Intents queued while offline, in the order they were made.
+#[derive(Debug, Default, Clone)]
+pub struct IntentQueue {
+    /// Queued intents, oldest first.
+    queue: VecDeque<QueuedIntent>,
+
+    /// Rooms that must finish syncing before their intents can replay.
+    awaiting_sync: BTreeSet<RoomId>,
+
+    /// Identifier for the next queued intent.
+    next_id: u64,
+}
+
+impl IntentQueue {
+    /// Allocate an identifier, e.g. for a message sent right away.
+    pub fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    /// Queue an intent and return its identifier.
+    pub fn push(&mut self, intent: Intent, queued_epoch: u64) -> u64 {
+        let id = self.allocate_id();
+        self.queue.push_back(QueuedIntent { id, queued_epoch, intent });
+        id
+    }
+
+    /// Queue interrupted messages for resending, ahead of every queued
+    /// intent and in the order they were sent.
+    pub fn requeue(&mut self, interrupted: Vec<Outgoing>) {
+        for outgoing in interrupted.into_iter().rev() {
+            let queued = QueuedIntent {
+                id: outgoing.id,
+                queued_epoch: outgoing.epoch,
+                intent: Intent::Resend(outgoing),
+            };
+            self.queue.push_front(queued);
+        }
+    }
+
+    /// Drop the queued resend of the message `(room_id, epoch, generation)`
+    /// identifies, now that it turned out to be sequenced. Returns its ID.
+    pub fn remove_resend(&mut self, room_id: RoomId, epoch: u64, generation: u32) -> Option<u64> {
+        let position = self.queue.iter().position(|queued| {
+            matches!(&queued.intent, Intent::Resend(outgoing) if outgoing.is(room_id, epoch, generation))
+        })?;
+        self.queue.remove(position).map(|queued| queued.id)
+    }
+
+    /// Number of queued intents.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether no intents are queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Start waiting for every room with a queued intent that `include`
+    /// selects to sync, e.g. the rooms of a server that just reconnected.
+    ///
+    /// Returns those rooms. Waits on other rooms are kept.
+    pub fn await_sync(&mut self, include: impl Fn(RoomId) -> bool) -> Vec<RoomId> {
+        let rooms: BTreeSet<RoomId> = self.rooms().filter(|room_id| include(*room_id)).collect();
+        self.awaiting_sync.extend(&rooms);
+        rooms.into_iter().collect()
+    }
+
+    /// Stop waiting on `room_id`, e.g. because its sync completed or the room
+    /// no longer exists.
+    ///
+    /// Returns true if the room was being waited on.
+    pub fn sync_complete(&mut self, room_id: RoomId) -> bool {
+        self.awaiting_sync.remove(&room_id)
+    }
+
+    /// Whether `room_id` is waiting for its sync.
+    pub fn awaiting_sync(&self, room_id: RoomId) -> bool {
+        self.awaiting_sync.contains(&room_id)
+    }
+
+    /// Whether any intent for `room_id` is queued.
+    pub fn contains_room(&self, room_id: RoomId) -> bool {
+        self.rooms().any(|queued| queued == room_id)
+    }
+
+    /// Forget the rooms `include` selects being waited on, e.g. after their
+    /// server's connection dropped again.
+    pub fn cancel_sync(&mut self, include: impl Fn(RoomId) -> bool) {
+        self.awaiting_sync.retain(|room_id| !include(*room_id));
+    }
+
+    /// Rooms targeted by queued intents, with repeats.
+    pub fn rooms(&self) -> impl Iterator<Item = RoomId> + '_ {
+        self.queue.iter().map(|queued| queued.intent.room_id())
+    }
+
+    /// Take the intents for rooms that `ready` selects and that are not
+    /// waiting for a sync, oldest first. Other rooms' intents stay queued.
+    pub fn drain_ready(&mut self, ready: impl Fn(RoomId) -> bool) -> Vec<QueuedIntent> {
+        let (taken, kept): (VecDeque<_>, VecDeque<_>) =
+            std::mem::take(&mut self.queue).into_iter().partition(|queued| {
+                let room_id = queued.intent.room_id();
+                !self.awaiting_sync.contains(&room_id) && ready(room_id)
+            });
+        self.queue = kept;
+        taken.into()
+    }
+
+    /// Take every intent for `room_id` and stop waiting on it, e.g. because
+    /// it can't be synced.
+    pub fn take_room(&mut self, room_id: RoomId) -> Vec<QueuedIntent> {
+        self.awaiting_sync.remove(&room_id);
+        self.drain_ready(|queued| queued == room_id)
+    }
+
+    /// Queued intents and the next identifier, for persisting. Waits on
+    /// syncs are not kept; they start over on reconnecting.
+    pub fn export(&self) -> PersistedIntents {
+        PersistedIntents { queued: self.queue.iter().cloned().collect(), next_id: self.next_id }
+    }
+
+    /// Restore a queue written by [`Self::export`].
+    pub fn import(persisted: PersistedIntents) -> Self {
+        Self {
+            queue: persisted.queued.into(),
+            awaiting_sync: BTreeSet::new(),
+            next_id: persisted.next_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lockframe_proto::{Frame, FrameHeader, Opcode};
+
+    use super::*;
+
+    fn message(room_id: RoomId) -> Intent {
+        Intent::SendMessage { room_id, plaintext: b"hi".to_vec() }
+    }
+
+    #[test]
+    fn rooms_replay_once_synced() {
+        let mut queue = IntentQueue::default();
+        queue.push(message(1), 0);
+        queue.push(message(2), 3);
+        queue.push(message(1), 0);
+
+        assert_eq!(queue.await_sync(|_| true), vec![1, 2]);
+        assert!(queue.drain_ready(|_| true).is_empty());
+        assert!(queue.sync_complete(1));
+        // Repeated completions for a room don't count twice
+        assert!(!queue.sync_complete(1));
+
+        // Room 2 still waiting doesn't hold up room 1
+        let ids: Vec<u64> = queue.drain_ready(|_| true).iter().map(|queued| queued.id).collect();
+        assert_eq!(ids, vec![0, 2]);
+        assert!(queue.contains_room(2) && !queue.contains_room(1));
+
+        assert!(queue.sync_complete(2));
+        assert!(queue.drain_ready(|room_id| room_id != 2).is_empty());
+        assert_eq!(queue.drain_ready(|_| true).len(), 1);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn taken_rooms_stop_waiting() {
+        let mut queue = IntentQueue::default();
+        queue.push(message(1), 0);
+        queue.push(message(2), 0);
+        queue.await_sync(|_| true);
+
+        assert_eq!(queue.take_room(1).len(), 1);
+        assert!(!queue.awaiting_sync(1));
+        assert!(queue.awaiting_sync(2));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn interrupted_messages_replay_first() {
+        let mut queue = IntentQueue::default();
+        let sent: Vec<Outgoing> = (0..2)
+            .map(|generation| Outgoing {
+                id: queue.allocate_id(),
+                room_id: 1,
+                epoch: 0,
+                generation,
+                plaintext: b"hi".to_vec().into(),
+                frame: Frame::new(FrameHeader::new(Opcode::AppMessage), Vec::new()),
+            })
+            .collect();
+        queue.push(message(1), 0);
+
+        queue.requeue(sent);
+        assert_eq!(queue.remove_resend(1, 0, 1), Some(1));
+        assert_eq!(queue.remove_resend(1, 0, 1), None);
+
+        let ids: Vec<u64> = queue.drain_ready(|_| true).iter().map(|queued| queued.id).collect();
+        assert_eq!(ids, vec![0, 2]);
+    }
+
+    #[test]
+    fn cancelled_sync_never_completes() {
+        let mut queue = IntentQueue::default();
+        queue.push(message(1), 0);
+
+        queue.await_sync(|_| true);
+        queue.cancel_sync(|_| true);
+        assert!(!queue.sync_complete(1));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn waits_are_scoped_to_the_selected_rooms() {
+        let mut queue = IntentQueue::default();
+        queue.push(message(1), 0);
+        queue.push(message(2), 0);
+
+        assert_eq!(queue.await_sync(|room_id| room_id == 1), vec![1]);
+        assert_eq!(queue.await_sync(|room_id| room_id == 2), vec![2]);
+        // Dropping room 2's wait leaves room 1's in place
+        queue.cancel_sync(|room_id| room_id == 2);
+        assert!(queue.sync_complete(1));
+    }
+}
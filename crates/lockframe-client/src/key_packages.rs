@@ -0,0 +1,110 @@
+//! `KeyPackage`s handed out for joining rooms, awaiting their Welcome.
+//!
+//! Every `KeyPackage` the client generates has private state that a Welcome
+//! made for it needs. It is kept here under the `KeyPackage`'s hash ref until
+//! a Welcome uses it: at most [`MAX_PENDING_KEY_PACKAGES`] of them, for at
+//! most [`KEY_PACKAGE_TTL_MILLIS`] each. The oldest are dropped first; a
+//! Welcome made for a dropped `KeyPackage` can no longer be joined.
+//!
+//! The state is exported with the client, so a client restarted between
+//! publishing a `KeyPackage` and receiving its Welcome can still join.
+
+use std::collections::HashMap;
+
+use lockframe_core::{env::Environment, mls::PendingJoinState};
+
+use crate::{error::ClientError, persistence::PersistedKeyPackage};
+
+/// Most `KeyPackage`s awaiting a Welcome at once.
+pub const MAX_PENDING_KEY_PACKAGES: usize = 32;
+
+/// How long a `KeyPackage` can be joined with after it was generated.
+pub const KEY_PACKAGE_TTL_MILLIS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Join state of one generated `KeyPackage`.
+struct Pending<E: Environment> {
+    state: PendingJoinState<E>,
+    generated_at_millis: u64,
+}
+
+/// Join state of the `KeyPackage`s awaiting a Welcome, by hash ref.
+pub struct KeyPackages<E: Environment> {
+    pending: HashMap<Vec<u8>, Pending<E>>,
+}
+
+impl<E: Environment> Default for KeyPackages<E> {
+    fn default() -> Self {
+        Self { pending: HashMap::new() }
+    }
+}
+
+impl<E: Environment> KeyPackages<E> {
+    /// Keep the join state of a `KeyPackage` generated at `now_millis`,
+    /// dropping the oldest once over [`MAX_PENDING_KEY_PACKAGES`].
+    pub fn insert(&mut self, hash_ref: Vec<u8>, state: PendingJoinState<E>, now_millis: u64) {
+        self.pending.insert(hash_ref, Pending { state, generated_at_millis: now_millis });
+        while self.pending.len() > MAX_PENDING_KEY_PACKAGES {
+            let oldest = self
+                .pending
+                .iter()
+                .min_by_key(|(_, pending)| pending.generated_at_millis)
+                .map(|(hash_ref, _)| hash_ref.clone());
+            if let Some(oldest) = oldest {
+                self.pending.remove(&oldest);
+            }
+        }
+    }
+
+    /// Take the join state of the first of `hash_refs` still pending.
+    pub fn take<'a>(
+        &mut self,
+        hash_refs: impl IntoIterator<Item = &'a Vec<u8>>,
+    ) -> Option<PendingJoinState<E>> {
+        hash_refs.into_iter().find_map(|hash_ref| self.pending.remove(hash_ref)).map(|p| p.state)
+    }
+
+    /// Drop `KeyPackage`s older than [`KEY_PACKAGE_TTL_MILLIS`] at
+    /// `now_millis`. Returns how many were dropped.
+    pub fn expire(&mut self, now_millis: u64) -> usize {
+        let before = self.pending.len();
+        self.pending.retain(|_, pending| {
+            now_millis.saturating_sub(pending.generated_at_millis) <= KEY_PACKAGE_TTL_MILLIS
+        });
+        before.saturating_sub(self.pending.len())
+    }
+
+    /// Number of `KeyPackage`s awaiting a Welcome.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Export every pending join state, oldest first.
+    pub fn export(&self) -> Result<Vec<PersistedKeyPackage>, ClientError> {
+        let mut exported = Vec::with_capacity(self.pending.len());
+        for (hash_ref, pending) in &self.pending {
+            exported.push(PersistedKeyPackage {
+                hash_ref: hash_ref.clone(),
+                state: pending
+                    .state
+                    .export_state()
+                    .map_err(|e| ClientError::Mls { reason: e.to_string() })?,
+                generated_at_millis: pending.generated_at_millis,
+            });
+        }
+        exported.sort_unstable_by(|a, b| {
+            (a.generated_at_millis, &a.hash_ref).cmp(&(b.generated_at_millis, &b.hash_ref))
+        });
+        Ok(exported)
+    }
+
+    /// Restore join states written by [`Self::export`].
+    pub fn import(env: &E, persisted: Vec<PersistedKeyPackage>) -> Result<Self, ClientError> {
+        let mut key_packages = Self::default();
+        for PersistedKeyPackage { hash_ref, state, generated_at_millis } in persisted {
+            let state = PendingJoinState::import_state(env.clone(), &state)
+                .map_err(|e| ClientError::Mls { reason: e.to_string() })?;
+            key_packages.insert(hash_ref, state, generated_at_millis);
+        }
+        Ok(key_packages)
+    }
+}
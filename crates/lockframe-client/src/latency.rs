@@ -0,0 +1,53 @@
+//! End-to-end frame latency as seen by a client.
+//!
+//! A frame relayed with a [`FrameTiming`] trailer splits its delivery into
+//! three legs, all on the server's clock: the sender's HLC timestamp to
+//! server arrival, server processing, and server broadcast to local arrival.
+//! Local arrival uses [`Client::server_time_millis`], so client clock skew
+//! cancels out once a `TimeSync` has been applied; the sender's own skew
+//! still shows up in the upload leg.
+//!
+//! [`Client::server_time_millis`]: crate::Client::server_time_millis
+
+use std::time::Duration;
+
+use lockframe_core::{hlc::HlcTimestamp, latency::LatencyHistogram};
+use lockframe_proto::FrameTiming;
+
+/// Latency distributions of frames delivered to this client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameLatency {
+    /// Sender HLC timestamp to local arrival
+    pub end_to_end: LatencyHistogram,
+    /// Sender HLC timestamp to server arrival
+    pub upload: LatencyHistogram,
+    /// Server arrival to broadcast
+    pub server: LatencyHistogram,
+    /// Server broadcast to local arrival
+    pub download: LatencyHistogram,
+}
+
+impl FrameLatency {
+    /// Record a frame with header timestamp `hlc` that arrived at
+    /// `arrived_at_millis` (server clock).
+    ///
+    /// Frames without an HLC timestamp only count towards server time. Legs
+    /// that come out negative because of clock skew are recorded as zero.
+    pub fn record(&mut self, hlc: u64, timing: &FrameTiming, arrived_at_millis: u64) {
+        let processing = Duration::from_micros(u64::from(timing.processing_micros));
+        self.server.record(processing);
+
+        let broadcast_at = timing
+            .received_at_millis
+            .saturating_add(u64::try_from(processing.as_millis()).unwrap_or(u64::MAX));
+        self.download.record(Duration::from_millis(arrived_at_millis.saturating_sub(broadcast_at)));
+
+        let sent_at = HlcTimestamp::from_u64(hlc).physical_millis();
+        if sent_at > 0 {
+            let upload = timing.received_at_millis.saturating_sub(sent_at);
+            self.upload.record(Duration::from_millis(upload));
+            self.end_to_end
+                .record(Duration::from_millis(arrived_at_millis.saturating_sub(sent_at)));
+        }
+    }
+}
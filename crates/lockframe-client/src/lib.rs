@@ -16,20 +16,73 @@
 //! - [`SenderKeyStore`]: Per-room sender key ratchet management
 //! - [`ClientEvent`]: Events fed into the client
 //! - [`ClientAction`]: Actions produced by the client
+//! - [`ClientObserver`]: Callbacks for embedders that don't dispatch actions
+//! - [`FrameLatency`]: End-to-end latency of delivered frames
+//! - [`MessageHistory`]: Delivered messages kept per room in a
+//!   [`ClientStorage`], paged newest first
+//! - [`ServerId`]: Server a room is homed on when talking to several
+//! - [`PeerVerification`]: Material for verifying a peer's key out of band
+//! - [`KeyEscrow`]: Opt-in escrow of room secrets to a recovery key
+//! - [`AttachmentKey`]: Key of an attachment uploaded in encrypted chunks
+//! - [`Recovery`]: What to do about an error the server sent
+//! - [`ClientDriver`]: Async QUIC runtime around the client (`tokio` feature)
 
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
 
+mod attachments;
+mod backfill;
 mod client;
+mod dedup;
+mod delivery;
+mod drafts;
+#[cfg(feature = "tokio")]
+mod driver;
 mod error;
+mod escrow;
 mod event;
+mod expiry;
+mod history;
+mod intents;
+mod key_packages;
+mod latency;
+mod observer;
+mod outbox;
+mod persistence;
+mod read_state;
+mod recovery;
 mod sender_key_store;
+mod servers;
+mod transcript;
+mod verification;
 
+pub use attachments::{ATTACHMENT_CHUNK_SIZE, AttachmentKey, decrypt_attachment};
 pub use client::{Client, ClientIdentity};
+pub use delivery::{DELIVERY_WINDOW, DeliveryState};
+pub use drafts::{DRAFT_IDLE_TIMEOUT, MAX_OPEN_DRAFTS};
+#[cfg(feature = "tokio")]
+pub use driver::{
+    ClientChannels, ClientDriver, ClientDriverConfig, ClientHandle, DEFAULT_SYNC_LIMIT,
+    DEFAULT_TICK_INTERVAL,
+};
 pub use error::ClientError;
-pub use event::{ClientAction, ClientEvent, RoomStateSnapshot};
+pub use escrow::KeyEscrow;
+pub use event::{ClientAction, ClientEvent, IntentOutcome, RoomStateSnapshot};
+pub use history::{
+    ClientStorage, FileClientStorage, MemoryClientStorage, MessageHistory, StoredMessage,
+};
+pub use key_packages::{KEY_PACKAGE_TTL_MILLIS, MAX_PENDING_KEY_PACKAGES};
+pub use latency::FrameLatency;
 pub use lockframe_core::{
     env::Environment,
     mls::{MemberId, RoomId},
 };
-pub use sender_key_store::SenderKeyStore;
+pub use lockframe_crypto::{DEFAULT_MAX_GENERATION_JUMP, Padding};
+pub use observer::{
+    ClientObserver, DeliveredMessage, MembershipChange, ObservedError, SendState, dispatch,
+};
+pub use read_state::ReadState;
+pub use recovery::{DEFAULT_RETRY_AFTER, Recovery};
+pub use sender_key_store::{DEFAULT_MAX_SKIPPED_KEYS, SenderKeyStore};
+pub use servers::{HOME_SERVER, ServerId};
+pub use verification::PeerVerification;
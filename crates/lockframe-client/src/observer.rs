@@ -0,0 +1,417 @@
+//! Callback interface over the action API.
+//!
+//! [`Client::handle`] returns actions and leaves dispatching them to the
+//! caller, which keeps the client pure and lets simulations inspect every
+//! action. Embedders that only want to react to what happened can implement
+//! [`ClientObserver`] instead and feed events through
+//! [`Client::handle_with`]: actions are handed to the matching callback in
+//! the order the client produced them, and errors from `handle` arrive at
+//! [`ClientObserver::on_error`] alongside the ones reported as actions.
+
+use lockframe_core::{env::Environment, mls::RoomId};
+use lockframe_proto::Frame;
+
+use crate::{
+    Client, ClientAction, ClientError, ClientEvent, IntentOutcome, RoomStateSnapshot,
+    delivery::DeliveryState, recovery::Recovery, servers::ServerId,
+};
+
+/// Receives the outcome of [`Client::handle_with`].
+///
+/// Only [`send`](Self::send) and [`persist`](Self::persist) are required;
+/// the client does not work unless frames reach the server and room state
+/// is stored. Actions without a callback of their own (sync requests,
+/// backfill and maintenance notices, suppressed duplicates and log lines) go
+/// to [`on_action`](Self::on_action).
+pub trait ClientObserver {
+    /// Send a frame to the server.
+    fn send(&mut self, frame: Frame);
+
+    /// Send a frame to a server other than the home server. Only embedders
+    /// that connect to several servers get these; by default they go to
+    /// [`on_action`](Self::on_action).
+    fn send_to(&mut self, server: ServerId, frame: Frame) {
+        self.on_action(ClientAction::SendTo { server, frame });
+    }
+
+    /// Store a room's state.
+    fn persist(&mut self, snapshot: RoomStateSnapshot);
+
+    /// A message was decrypted.
+    fn on_message(&mut self, _message: DeliveredMessage) {}
+
+    /// The client joined, left or lost a room, or a room's membership moved
+    /// to a new epoch.
+    fn on_membership_change(&mut self, _change: MembershipChange) {}
+
+    /// Something went wrong.
+    fn on_error(&mut self, _error: ObservedError) {}
+
+    /// A message was sent or sequenced, or an intent made offline was queued
+    /// or replayed.
+    fn on_send_state(&mut self, _state: SendState) {}
+
+    /// Any other action.
+    fn on_action(&mut self, _action: ClientAction) {}
+}
+
+/// A decrypted application message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveredMessage {
+    /// Room the message is from.
+    pub room_id: RoomId,
+    /// Sender's stable ID.
+    pub sender_id: u64,
+    /// Decrypted plaintext.
+    pub plaintext: Vec<u8>,
+    /// Log index in the room.
+    pub log_index: u64,
+    /// Message timestamp (HLC).
+    pub timestamp: u64,
+    /// Sender's current key is the one the user verified.
+    pub peer_verified: bool,
+}
+
+/// A change to the rooms the client is in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MembershipChange {
+    /// A Welcome admitted the client to the room.
+    Joined {
+        /// Room that was joined.
+        room_id: RoomId,
+        /// Epoch the room was joined at.
+        epoch: u64,
+    },
+    /// The room was created or joined, or a commit moved it to a new epoch.
+    Updated {
+        /// Room that changed.
+        room_id: RoomId,
+        /// The room's epoch now.
+        epoch: u64,
+    },
+    /// The client is no longer in the room.
+    Removed {
+        /// Room that was removed.
+        room_id: RoomId,
+        /// Reason for removal.
+        reason: String,
+    },
+    /// The room moved to another server.
+    Moved {
+        /// Room that moved.
+        room_id: RoomId,
+        /// Address of the server now hosting the room.
+        target: String,
+        /// First log index sequenced by the new server.
+        cutover_log_index: u64,
+    },
+    /// Other devices of this account were revoked.
+    Revoked {
+        /// Members that were revoked.
+        member_ids: Vec<u64>,
+        /// Connections the server closed.
+        sessions_closed: u32,
+    },
+}
+
+/// A failure reported to [`ClientObserver::on_error`].
+#[derive(Debug)]
+pub enum ObservedError {
+    /// [`Client::handle`] returned an error.
+    Failed(ClientError),
+    /// The server presented inconsistent views of a room's log.
+    TranscriptViolation {
+        /// Room whose log is inconsistent.
+        room_id: RoomId,
+        /// What was inconsistent.
+        reason: String,
+    },
+    /// The server rejected our commit because the room is full.
+    RoomFull {
+        /// Room the commit was for.
+        room_id: RoomId,
+        /// The room's member limit.
+        max_members: u32,
+        /// Members the room would have had after the commit.
+        member_count: u32,
+    },
+    /// The server refused a frame.
+    Server {
+        /// Room the refused frame was for.
+        room_id: RoomId,
+        /// Request ID of the refused frame, zero if it had none.
+        request_id: u32,
+        /// Error code, one of the `ErrorPayload` constants.
+        code: u16,
+        /// Human-readable error message.
+        message: String,
+        /// What to do about it.
+        recovery: Recovery,
+    },
+}
+
+/// Progress of a sent message or an intent made while offline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendState {
+    /// Message handed to the server, not yet sequenced.
+    Sent {
+        /// Client-assigned message ID.
+        message_id: u64,
+        /// Room the message was sent to.
+        room_id: RoomId,
+    },
+    /// The server sequenced the message.
+    Sequenced {
+        /// Message ID from [`SendState::Sent`] or [`SendState::Queued`].
+        message_id: u64,
+        /// Room the message was sent to.
+        room_id: RoomId,
+        /// Log index the message was sequenced at.
+        log_index: u64,
+    },
+    /// A member's read receipt covers the message.
+    Read {
+        /// Message ID from [`SendState::Sent`] or [`SendState::Queued`].
+        message_id: u64,
+        /// Room the message was sent to.
+        room_id: RoomId,
+        /// Member who read it.
+        member_id: u64,
+    },
+    /// Queued until the client reconnects and syncs.
+    Queued {
+        /// Identifier of the queued intent.
+        intent_id: u64,
+        /// Room the intent targets.
+        room_id: RoomId,
+    },
+    /// Replayed after reconnecting.
+    Resolved {
+        /// Identifier from [`SendState::Queued`].
+        intent_id: u64,
+        /// Room the intent targets.
+        room_id: RoomId,
+        /// Room epoch when the intent was queued.
+        queued_epoch: u64,
+        /// What happened on replay.
+        outcome: IntentOutcome,
+    },
+}
+
+impl<E: Environment> Client<E> {
+    /// Process an event and hand the result to `observer`.
+    ///
+    /// Equivalent to [`handle`](Self::handle) followed by [`dispatch`].
+    pub fn handle_with(&mut self, event: ClientEvent, observer: &mut impl ClientObserver) {
+        match self.handle(event) {
+            Ok(actions) => dispatch(actions, observer),
+            Err(error) => observer.on_error(ObservedError::Failed(error)),
+        }
+    }
+}
+
+/// Hand each action to the matching callback of `observer`, in order.
+#[allow(clippy::too_many_lines)]
+pub fn dispatch(actions: Vec<ClientAction>, observer: &mut impl ClientObserver) {
+    for action in actions {
+        match action {
+            ClientAction::Send(frame) => observer.send(frame),
+            ClientAction::SendTo { server, frame } => observer.send_to(server, frame),
+            ClientAction::PersistRoom(snapshot) => {
+                let change =
+                    MembershipChange::Updated { room_id: snapshot.room_id, epoch: snapshot.epoch };
+                observer.persist(snapshot);
+                observer.on_membership_change(change);
+            },
+            ClientAction::DeliverMessage {
+                room_id,
+                sender_id,
+                plaintext,
+                log_index,
+                timestamp,
+                peer_verified,
+            } => {
+                observer.on_message(DeliveredMessage {
+                    room_id,
+                    sender_id,
+                    plaintext,
+                    log_index,
+                    timestamp,
+                    peer_verified,
+                });
+            },
+            ClientAction::RoomJoined { room_id, epoch } => {
+                observer.on_membership_change(MembershipChange::Joined { room_id, epoch });
+            },
+            ClientAction::RoomRemoved { room_id, reason } => {
+                observer.on_membership_change(MembershipChange::Removed { room_id, reason });
+            },
+            ClientAction::RoomMoved { room_id, target, cutover_log_index } => {
+                observer.on_membership_change(MembershipChange::Moved {
+                    room_id,
+                    target,
+                    cutover_log_index,
+                });
+            },
+            ClientAction::SessionsRevoked { member_ids, sessions_closed } => {
+                observer.on_membership_change(MembershipChange::Revoked {
+                    member_ids,
+                    sessions_closed,
+                });
+            },
+            ClientAction::TranscriptViolation { room_id, reason } => {
+                observer.on_error(ObservedError::TranscriptViolation { room_id, reason });
+            },
+            ClientAction::RoomFull { room_id, max_members, member_count } => {
+                observer.on_error(ObservedError::RoomFull { room_id, max_members, member_count });
+            },
+            ClientAction::ServerError { room_id, request_id, code, message, recovery } => {
+                observer.on_error(ObservedError::Server {
+                    room_id,
+                    request_id,
+                    code,
+                    message,
+                    recovery,
+                });
+            },
+            ClientAction::DeliveryUpdate { message_id, room_id, state } => {
+                observer.on_send_state(match state {
+                    DeliveryState::Sent => SendState::Sent { message_id, room_id },
+                    DeliveryState::Sequenced { log_index } => {
+                        SendState::Sequenced { message_id, room_id, log_index }
+                    },
+                    DeliveryState::Read { member_id } => {
+                        SendState::Read { message_id, room_id, member_id }
+                    },
+                });
+            },
+            ClientAction::IntentQueued { intent_id, room_id } => {
+                observer.on_send_state(SendState::Queued { intent_id, room_id });
+            },
+            ClientAction::IntentResolved { intent_id, room_id, queued_epoch, outcome } => {
+                observer.on_send_state(SendState::Resolved {
+                    intent_id,
+                    room_id,
+                    queued_epoch,
+                    outcome,
+                });
+            },
+            action @ (ClientAction::Typing { .. }
+            | ClientAction::ReadReceipt { .. }
+            | ClientAction::AttachmentUploaded { .. }
+            | ClientAction::AttachmentFetched { .. }
+            | ClientAction::DraftStarted { .. }
+            | ClientAction::DraftChunk { .. }
+            | ClientAction::DuplicateSuppressed { .. }
+            | ClientAction::RequestSync { .. }
+            | ClientAction::BackfillPending { .. }
+            | ClientAction::PublishKeyPackage(_)
+            | ClientAction::MessageTtlChanged { .. }
+            | ClientAction::MessagesExpired { .. }
+            | ClientAction::ServerMaintenance { .. }
+            | ClientAction::Reconnect { .. }
+            | ClientAction::RoomDirectory { .. }
+            | ClientAction::RoomEscrowed { .. }
+            | ClientAction::EscrowRoomKey { .. }
+            | ClientAction::Log { .. }) => observer.on_action(action),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::ClientIdentity;
+
+    #[derive(Clone)]
+    struct TestEnv;
+
+    impl Environment for TestEnv {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn sleep(&self, _duration: Duration) -> impl Future<Output = ()> + Send {
+            std::future::ready(())
+        }
+
+        fn random_bytes(&self, buffer: &mut [u8]) {
+            // Deterministic for tests
+            for (i, byte) in buffer.iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        sent: usize,
+        persisted: Vec<RoomId>,
+        changes: Vec<MembershipChange>,
+        errors: Vec<ObservedError>,
+        send_states: Vec<SendState>,
+    }
+
+    impl ClientObserver for Recorder {
+        fn send(&mut self, _frame: Frame) {
+            self.sent += 1;
+        }
+
+        fn persist(&mut self, snapshot: RoomStateSnapshot) {
+            self.persisted.push(snapshot.room_id);
+        }
+
+        fn on_membership_change(&mut self, change: MembershipChange) {
+            self.changes.push(change);
+        }
+
+        fn on_error(&mut self, error: ObservedError) {
+            self.errors.push(error);
+        }
+
+        fn on_send_state(&mut self, state: SendState) {
+            self.send_states.push(state);
+        }
+    }
+
+    #[test]
+    fn events_are_dispatched_to_callbacks() {
+        let mut client = Client::new(TestEnv, ClientIdentity::new(1));
+        let mut recorder = Recorder::default();
+
+        client.handle_with(ClientEvent::CreateRoom { room_id: 0x42 }, &mut recorder);
+        assert_eq!(recorder.persisted, vec![0x42]);
+        assert_eq!(recorder.changes, vec![MembershipChange::Updated { room_id: 0x42, epoch: 0 }]);
+
+        client.handle_with(ClientEvent::CreateRoom { room_id: 0x42 }, &mut recorder);
+        assert!(matches!(recorder.errors.as_slice(), [ObservedError::Failed(
+            ClientError::RoomAlreadyExists { room_id: 0x42 }
+        )]));
+
+        client.handle_with(ClientEvent::Disconnected, &mut recorder);
+        let sent = recorder.sent;
+        let message = ClientEvent::SendMessage { room_id: 0x42, plaintext: b"hi".to_vec() };
+        client.handle_with(message, &mut recorder);
+        assert_eq!(recorder.sent, sent);
+        assert!(matches!(recorder.send_states.as_slice(), [SendState::Queued {
+            room_id: 0x42,
+            ..
+        }]));
+    }
+
+    #[test]
+    fn action_reported_failures_reach_on_error() {
+        let mut recorder = Recorder::default();
+        dispatch(
+            vec![
+                ClientAction::RoomFull { room_id: 7, max_members: 2, member_count: 3 },
+                ClientAction::Log { message: "ignored".to_string() },
+            ],
+            &mut recorder,
+        );
+
+        assert!(matches!(recorder.errors.as_slice(), [ObservedError::RoomFull { room_id: 7, .. }]));
+    }
+}
@@ -0,0 +1,139 @@
+//! Application messages sent but not yet sequenced.
+//!
+//! A frame handed to the transport can be lost with the connection before the
+//! server sequences it. Every message sent is kept here under its
+//! client-assigned ID until the server's sequenced copy comes back. When the
+//! connection drops, whatever is still outstanding is queued for replay after
+//! the reconnect sync, ahead of intents made later.
+//!
+//! A replayed message is resent unchanged while its epoch is current, so a
+//! server that did sequence the original recognises the copy and answers with
+//! the sequenced frame instead of sequencing it twice. Once the epoch has
+//! moved on the original can no longer be sequenced, and had it been, the
+//! sync would have returned it, so the plaintext is encrypted afresh.
+//!
+//! At most [`MAX_UNSEQUENCED_MESSAGES`] are kept; sending more is refused
+//! until the server catches up. Plaintexts are wiped once their message
+//! leaves the outbox.
+
+use std::collections::VecDeque;
+
+use lockframe_core::mls::RoomId;
+use lockframe_proto::Frame;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+/// Most messages awaiting their sequenced copy at once.
+pub const MAX_UNSEQUENCED_MESSAGES: usize = 256;
+
+/// A sent application message awaiting its sequenced copy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Outgoing {
+    /// Client-assigned message ID.
+    pub id: u64,
+    /// Room the message was sent to.
+    pub room_id: RoomId,
+    /// Epoch the message was encrypted in.
+    pub epoch: u64,
+    /// Our sender ratchet generation the message was encrypted at.
+    pub generation: u32,
+    /// Message plaintext, to re-encrypt if the epoch moves on.
+    pub plaintext: Zeroizing<Vec<u8>>,
+    /// The encrypted frame as sent.
+    #[serde(with = "crate::persistence::frame_bytes")]
+    pub frame: Frame,
+}
+
+impl Outgoing {
+    /// Whether this is the message `(room_id, epoch, generation)` identifies.
+    pub fn is(&self, room_id: RoomId, epoch: u64, generation: u32) -> bool {
+        self.room_id == room_id && self.epoch == epoch && self.generation == generation
+    }
+}
+
+/// Sent messages not yet seen sequenced, oldest first.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    sent: VecDeque<Outgoing>,
+}
+
+impl Outbox {
+    /// Track a message that was just sent.
+    pub fn push(&mut self, outgoing: Outgoing) {
+        self.sent.push_back(outgoing);
+    }
+
+    /// Number of messages awaiting their sequenced copy.
+    pub fn len(&self) -> usize {
+        self.sent.len()
+    }
+
+    /// Whether no more messages can be sent until some are sequenced.
+    pub fn is_full(&self) -> bool {
+        self.sent.len() >= MAX_UNSEQUENCED_MESSAGES
+    }
+
+    /// Messages awaiting their sequenced copy, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Outgoing> {
+        self.sent.iter()
+    }
+
+    /// Stop tracking the message `(room_id, epoch, generation)` identifies,
+    /// now that it was sequenced. Returns its ID.
+    pub fn acknowledge(&mut self, room_id: RoomId, epoch: u64, generation: u32) -> Option<u64> {
+        let position = self.sent.iter().position(|sent| sent.is(room_id, epoch, generation))?;
+        self.sent.remove(position).map(|sent| sent.id)
+    }
+
+    /// Take the message sent in `room_id` under `request_id`, e.g. to resend
+    /// it after the server refused it.
+    pub fn remove(&mut self, room_id: RoomId, request_id: u32) -> Option<Outgoing> {
+        let position = self.sent.iter().position(|sent| {
+            sent.room_id == room_id && sent.frame.header.request_id() == request_id
+        })?;
+        self.sent.remove(position)
+    }
+
+    /// Take the messages of the rooms `include` selects, oldest first, e.g.
+    /// the rooms of a server whose connection dropped.
+    pub fn take(&mut self, include: impl Fn(RoomId) -> bool) -> Vec<Outgoing> {
+        let (taken, kept): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.sent).into_iter().partition(|sent| include(sent.room_id));
+        self.sent = kept.into();
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+
+    fn outgoing(id: u64, room_id: RoomId, generation: u32) -> Outgoing {
+        Outgoing {
+            id,
+            room_id,
+            epoch: 0,
+            generation,
+            plaintext: b"hi".to_vec().into(),
+            frame: Frame::new(FrameHeader::new(Opcode::AppMessage), Vec::new()),
+        }
+    }
+
+    #[test]
+    fn acknowledged_and_taken_messages_leave_the_outbox() {
+        let mut outbox = Outbox::default();
+        outbox.push(outgoing(0, 1, 0));
+        outbox.push(outgoing(1, 2, 0));
+        outbox.push(outgoing(2, 1, 1));
+
+        assert_eq!(outbox.acknowledge(1, 0, 0), Some(0));
+        assert_eq!(outbox.acknowledge(1, 0, 0), None);
+        assert_eq!(outbox.acknowledge(1, 1, 1), None);
+
+        let taken: Vec<u64> = outbox.take(|room_id| room_id == 1).iter().map(|s| s.id).collect();
+        assert_eq!(taken, vec![2]);
+        assert_eq!(outbox.len(), 1);
+    }
+}
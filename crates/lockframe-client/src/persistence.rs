@@ -0,0 +1,169 @@
+//! Persisted client state.
+//!
+//! [`Client::export_state`](crate::Client::export_state) captures what a
+//! client needs to keep decrypting after a restart: its identity, the MLS
+//! group of every room and the sender key ratchets of the current epoch,
+//! including messages skipped over but not yet received, and the join state
+//! of `KeyPackage`s still awaiting a Welcome.
+//!
+//! It also keeps what the server can't give back: the peers the user
+//! verified, the server each room is homed on, what every room's transcript
+//! has seen, the keys of past epochs kept for a backfill, and the messages
+//! and intents not yet sequenced. Drafts, read state and transfers are
+//! rebuilt from the server or started over.
+//!
+//! The encoding is CBOR. It holds private keys, so the application must store
+//! it as securely as its identity key.
+
+use lockframe_core::mls::{MlsGroupState, RoomId};
+use lockframe_proto::Frame;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ClientError, intents::QueuedIntent, sender_key_store::SenderKeysState,
+    servers::ServerId, transcript::Transcript,
+};
+
+/// Everything [`Client::import_state`](crate::Client::import_state) restores.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientState {
+    /// Stable sender ID of the client's identity.
+    pub sender_id: u64,
+    /// Every room the client is a member of, by ascending room ID.
+    pub rooms: Vec<PersistedRoom>,
+    /// `KeyPackage`s awaiting a Welcome, oldest first.
+    #[serde(default)]
+    pub key_packages: Vec<PersistedKeyPackage>,
+    /// Verified peers and their fingerprints, by ascending member ID.
+    #[serde(default)]
+    pub verified: Vec<(u64, [u8; 32])>,
+    /// Rooms homed somewhere other than the home server, by ascending room
+    /// ID.
+    #[serde(default)]
+    pub homes: Vec<(RoomId, ServerId)>,
+    /// Intents not yet replayed, behind the messages sent but not yet
+    /// sequenced.
+    #[serde(default)]
+    pub intents: PersistedIntents,
+}
+
+/// Persisted state of one room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedRoom {
+    /// Room identifier.
+    pub room_id: RoomId,
+    /// MLS group state, as exported by the group.
+    pub mls_state: Vec<u8>,
+    /// Sender key ratchets of the current epoch.
+    pub sender_keys: SenderKeysState,
+    /// What the server has shown of the room's log.
+    #[serde(default)]
+    pub transcript: Transcript,
+    /// Fast-forward and backfill progress, with the keys it retains.
+    #[serde(default)]
+    pub backfill: PersistedBackfill,
+    /// Our pending commit as sent, encoded.
+    #[serde(default)]
+    pub commit: Option<Vec<u8>>,
+    /// Whether nothing may be sent until a commit of ours lands.
+    #[serde(default)]
+    pub needs_rekey: bool,
+}
+
+/// Persisted fast-forward and backfill progress of one room.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PersistedBackfill {
+    /// Log index the running fast-forward started at.
+    pub fast_forward_from: Option<u64>,
+    /// Skipped log range not yet backfilled, as `(start, end)`.
+    pub pending: Option<(u64, u64)>,
+    /// Keys of the epochs retained for the backfill, by ascending epoch.
+    pub epochs: Vec<PersistedEpochKeys>,
+    /// How long the oldest keys had been retained when exported.
+    pub retained_for_millis: Option<u64>,
+}
+
+/// Persisted keys of a past epoch, retained for a backfill.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedEpochKeys {
+    /// Epoch the keys belong to.
+    pub epoch: u64,
+    /// Sender key ratchets of the epoch.
+    pub sender_keys: SenderKeysState,
+    /// Member signature keys of the epoch.
+    pub validation: MlsGroupState,
+    /// `(leaf index, member ID)` of every member of the epoch.
+    pub members: Vec<(u32, u64)>,
+}
+
+/// Persisted intent queue.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PersistedIntents {
+    /// Queued intents, oldest first.
+    pub queued: Vec<QueuedIntent>,
+    /// Identifier for the next intent or message.
+    pub next_id: u64,
+}
+
+/// Persisted join state of a `KeyPackage` awaiting its Welcome.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedKeyPackage {
+    /// Hash ref of the `KeyPackage`.
+    pub hash_ref: Vec<u8>,
+    /// Join state, as exported by the pending join.
+    pub state: Vec<u8>,
+    /// Server time the `KeyPackage` was generated at, in milliseconds.
+    pub generated_at_millis: u64,
+}
+
+impl ClientState {
+    /// Encode to CBOR.
+    pub fn encode(&self) -> Result<Vec<u8>, ClientError> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(self, &mut bytes).map_err(|e| ClientError::InvalidState {
+            reason: format!("failed to encode client state: {e}"),
+        })?;
+        Ok(bytes)
+    }
+
+    /// Decode from CBOR written by [`Self::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Self, ClientError> {
+        ciborium::de::from_reader(bytes)
+            .map_err(|e| ClientError::InvalidState { reason: format!("invalid client state: {e}") })
+    }
+}
+
+/// Frames in persisted state, in their wire encoding.
+pub mod frame_bytes {
+    use lockframe_proto::Frame;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de, ser};
+
+    /// Serialize `frame` as its wire encoding.
+    pub fn serialize<S: Serializer>(frame: &Frame, serializer: S) -> Result<S::Ok, S::Error> {
+        super::encode_frame(frame).map_err(ser::Error::custom)?.serialize(serializer)
+    }
+
+    /// Deserialize a frame from its wire encoding.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Frame, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        Frame::decode(&bytes).map_err(de::Error::custom)
+    }
+}
+
+/// Wire encoding of a frame kept in persisted state.
+///
+/// Frames built by the MLS group leave the header's payload size for the
+/// transport to fill in, so the frame is re-framed around its payload first.
+pub fn encode_frame(frame: &Frame) -> Result<Vec<u8>, ClientError> {
+    let mut bytes = Vec::new();
+    Frame::new(frame.header, frame.payload.clone()).encode(&mut bytes).map_err(|e| {
+        ClientError::InvalidState { reason: format!("failed to encode persisted frame: {e}") }
+    })?;
+    Ok(bytes)
+}
+
+/// Frame from its persisted wire encoding.
+pub fn decode_frame(bytes: &[u8]) -> Result<Frame, ClientError> {
+    Frame::decode(bytes)
+        .map_err(|e| ClientError::InvalidState { reason: format!("invalid persisted frame: {e}") })
+}
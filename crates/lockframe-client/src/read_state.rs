@@ -0,0 +1,67 @@
+//! Read positions of a room's members.
+//!
+//! Members mark messages as read with a read receipt, which the server
+//! sequences into the room's log like any other frame. A receipt covers every
+//! message up to and including its log index, so only the highest one a
+//! member has sent matters: receipts arriving out of order, e.g. while syncing
+//! history, never move a member's position back.
+
+use std::collections::BTreeMap;
+
+/// How far each member of one room has read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadState {
+    /// Member ID → log index of the last message read
+    markers: BTreeMap<u64, u64>,
+}
+
+impl ReadState {
+    /// Record that `member_id` has read up to `up_to_log_index`.
+    ///
+    /// Returns whether the member's position moved forward.
+    pub fn record(&mut self, member_id: u64, up_to_log_index: u64) -> bool {
+        if self.markers.get(&member_id).is_some_and(|&current| current >= up_to_log_index) {
+            return false;
+        }
+        self.markers.insert(member_id, up_to_log_index);
+        true
+    }
+
+    /// Log index of the last message `member_id` has read.
+    pub fn read_up_to(&self, member_id: u64) -> Option<u64> {
+        self.markers.get(&member_id).copied()
+    }
+
+    /// Members who have read the message at `log_index`, in ID order.
+    pub fn read_by(&self, log_index: u64) -> impl Iterator<Item = u64> + '_ {
+        self.markers
+            .iter()
+            .filter(move |&(_, &up_to)| up_to >= log_index)
+            .map(|(&member_id, _)| member_id)
+    }
+
+    /// Every member's position, member ID → last log index read.
+    pub fn markers(&self) -> &BTreeMap<u64, u64> {
+        &self.markers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_only_move_forward() {
+        let mut state = ReadState::default();
+
+        assert!(state.record(1, 5));
+        assert!(state.record(2, 3));
+        assert!(!state.record(1, 4));
+        assert!(state.record(1, 8));
+
+        assert_eq!(state.read_up_to(1), Some(8));
+        assert_eq!(state.read_up_to(3), None);
+        assert_eq!(state.read_by(4).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(state.read_by(3).collect::<Vec<_>>(), vec![1, 2]);
+    }
+}
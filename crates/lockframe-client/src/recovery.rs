@@ -0,0 +1,131 @@
+//! Recovery from server errors.
+//!
+//! The server answers a frame it cannot accept with an [`ErrorPayload`]. Its
+//! code says what went wrong and its `retryable` flag whether the same frame
+//! may succeed later. [`Recovery::for_error`] turns the two into what the
+//! client should do next: catch up with the room when our view of it is
+//! stale, retry after a delay when the failure is transient, and otherwise
+//! give up and surface the error.
+//!
+//! The error echoes the request ID of the refused frame. Frames to be resent
+//! after a delay wait in [`Retries`] under that ID until they are due.
+//!
+//! A commit the server never answers at all is given up on once its room's
+//! entry in [`CommitTimeouts`] comes due, and the room is synced instead.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use lockframe_core::{
+    mls::RoomId,
+    timer::{TimerId, TimerWheel},
+};
+use lockframe_proto::payloads::ErrorPayload;
+
+/// Delay before retrying when the server gives none.
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// What to do about a server error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Our view of the room is stale. Sync it, then try again.
+    Sync,
+    /// The failure is transient. Resend after `retry_after`.
+    Backoff {
+        /// How long to wait before resending.
+        retry_after: Duration,
+    },
+    /// Resending will not help.
+    GiveUp,
+}
+
+impl Recovery {
+    /// Recovery strategy for `error`.
+    pub fn for_error(error: &ErrorPayload) -> Self {
+        match error.code {
+            ErrorPayload::SEQUENCER_ERROR | ErrorPayload::MLS_ERROR => Self::Sync,
+            _ if error.retryable => Self::Backoff {
+                retry_after: error.retry_after.map_or(DEFAULT_RETRY_AFTER, Duration::from_secs),
+            },
+            _ => Self::GiveUp,
+        }
+    }
+}
+
+/// One pending deadline per key, on a [`TimerWheel`].
+#[derive(Debug)]
+pub struct Deadlines<K> {
+    wheel: TimerWheel<K>,
+    /// Timer of each waiting key
+    timers: HashMap<K, TimerId>,
+}
+
+/// Refused frames waiting out their backoff, by `(room_id, request_id)`.
+pub type Retries = Deadlines<(RoomId, u32)>;
+
+/// Rooms waiting for the server to sequence our pending commit.
+pub type CommitTimeouts = Deadlines<RoomId>;
+
+impl<K: Copy + Eq + Hash> Deadlines<K> {
+    /// No deadlines pending, with time counted from `origin`.
+    pub fn new(origin: Instant) -> Self {
+        Self { wheel: TimerWheel::new(origin), timers: HashMap::new() }
+    }
+
+    /// Make `key` due at `at`. A key scheduled again while waiting keeps
+    /// only the later deadline.
+    pub fn schedule(&mut self, at: Instant, key: K) {
+        if let Some(timer) = self.timers.remove(&key) {
+            self.wheel.cancel(timer);
+        }
+        self.timers.insert(key, self.wheel.schedule(at, key));
+    }
+
+    /// Take the keys due by `now`, ordered by when they were due.
+    pub fn due(&mut self, now: Instant) -> Vec<K> {
+        let due = self.wheel.advance(now);
+        for key in &due {
+            self.timers.remove(key);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_map_to_recovery() {
+        assert_eq!(
+            Recovery::for_error(&ErrorPayload::sequencer_error("stale epoch")),
+            Recovery::Sync
+        );
+        assert_eq!(Recovery::for_error(&ErrorPayload::mls_error("bad commit")), Recovery::Sync);
+        assert_eq!(
+            Recovery::for_error(&ErrorPayload::storage_error("disk full")),
+            Recovery::Backoff { retry_after: DEFAULT_RETRY_AFTER }
+        );
+        assert_eq!(
+            Recovery::for_error(&ErrorPayload::rate_limited("slow down", 5)),
+            Recovery::Backoff { retry_after: Duration::from_secs(5) }
+        );
+        assert_eq!(Recovery::for_error(&ErrorPayload::room_not_found(7)), Recovery::GiveUp);
+    }
+
+    #[test]
+    fn retries_come_due_once() {
+        let start = Instant::now();
+        let mut retries = Retries::new(start);
+        retries.schedule(start + Duration::from_secs(2), (1, 7));
+        retries.schedule(start + Duration::from_secs(1), (1, 8));
+        retries.schedule(start + Duration::from_secs(3), (1, 8));
+
+        assert!(retries.due(start + Duration::from_secs(1)).is_empty());
+        assert_eq!(retries.due(start + Duration::from_secs(3)), vec![(1, 7), (1, 8)]);
+        assert!(retries.due(start + Duration::from_secs(9)).is_empty());
+    }
+}
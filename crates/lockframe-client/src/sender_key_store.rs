@@ -3,13 +3,64 @@
 //! Each room member has their own symmetric ratchet for message encryption.
 //! Keys are derived from the MLS epoch secret and re-initialized on each
 //! epoch transition.
+//!
+//! Messages can arrive out of generation order. When a sender's ratchet skips
+//! ahead, the keys of the generations it passed over are kept so the late
+//! messages still decrypt. The cache is bounded per sender: past the cap the
+//! sender's oldest skipped keys are dropped and their messages can no longer
+//! be read, so one sender skipping far ahead cannot evict anyone else's keys.
+//!
+//! Every message is encrypted with the room's AEAD suite, and a message in
+//! any other suite is rejected.
+//!
+//! How far a ratchet skips is bounded too. Reaching generation `n` takes `n`
+//! ratchet steps, so a message claiming a generation far ahead is rejected
+//! rather than letting a sender make us derive millions of keys.
+//!
+//! Chain keys and message keys are zeroized when dropped, and
+//! [`SenderKeyStore::purge`] wipes them all at once when we leave a room.
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+};
 
 use lockframe_crypto::{
-    EncryptedMessage, NONCE_RANDOM_SIZE, SenderKeyError, SymmetricRatchet, decrypt_message,
-    derive_sender_key_seed, encrypt_message,
+    CipherSuite, DEFAULT_MAX_GENERATION_JUMP, EncryptedMessage, MessageKey, NONCE_RANDOM_SIZE,
+    Padding, SenderKeyError, SymmetricRatchet, decrypt_message, derive_sender_key_seed,
+    encrypt_padded,
 };
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Default number of skipped message keys kept per sender and epoch.
+pub const DEFAULT_MAX_SKIPPED_KEYS: usize = 1000;
+
+/// Persisted form of a [`SenderKeyStore`].
+///
+/// Holds chain keys and skipped message keys, so it must be stored as
+/// securely as the MLS group state. The keys are zeroized when dropped.
+#[derive(Debug, Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct SenderKeysState {
+    /// Epoch the keys are valid for.
+    epoch: u64,
+    /// (`sender_index`, chain key, generation) of every ratchet.
+    ratchets: Vec<(u32, [u8; 32], u32)>,
+    /// (`sender_index`, generation, key) of skipped keys, oldest first.
+    skipped: Vec<(u32, u32, [u8; 32])>,
+    /// Most skipped keys held per sender.
+    max_skipped_keys: usize,
+    /// Most generations a ratchet skips over.
+    #[serde(default = "default_max_generation_jump")]
+    max_generation_jump: u32,
+    /// Identifier of the AEAD suite messages are encrypted with.
+    #[serde(default)]
+    cipher_suite: u8,
+}
+
+fn default_max_generation_jump() -> u32 {
+    DEFAULT_MAX_GENERATION_JUMP
+}
 
 /// Manages sender key ratchets for all members in a room.
 ///
@@ -20,13 +71,35 @@ use lockframe_crypto::{
 ///
 /// - All ratchets are for the same epoch
 /// - Ratchet generations only increase (forward secrecy)
-/// - Store is immutable after creation (new epoch = new store)
+/// - Skipped keys are below their sender's ratchet generation and each is used
+///   for at most one message
+/// - At most `max_skipped_keys` skipped keys are held per sender
+/// - Every ratchet skips at most `max_generation_jump` generations at once
+/// - A new epoch gets a new store
+/// - A purged store holds no keys
 pub struct SenderKeyStore {
     /// Current epoch these keys are valid for.
     epoch: u64,
 
     /// Ratchet state per member (`sender_index` -> ratchet).
     ratchets: HashMap<u32, SymmetricRatchet>,
+
+    /// Keys of generations skipped over (`(sender_index, generation)` ->
+    /// key).
+    skipped: HashMap<(u32, u32), MessageKey>,
+
+    /// Generations of each sender's skipped keys in the order they were
+    /// cached, oldest first (`sender_index` -> generations).
+    skipped_order: HashMap<u32, VecDeque<u32>>,
+
+    /// Most skipped keys held per sender before its oldest are dropped.
+    max_skipped_keys: usize,
+
+    /// Most generations a ratchet skips over to reach a message.
+    max_generation_jump: u32,
+
+    /// AEAD suite messages are encrypted with.
+    cipher_suite: CipherSuite,
 }
 
 impl SenderKeyStore {
@@ -42,7 +115,124 @@ impl SenderKeyStore {
             ratchets.insert(sender_index, SymmetricRatchet::new(&seed));
         }
 
-        Self { epoch, ratchets }
+        Self {
+            epoch,
+            ratchets,
+            skipped: HashMap::new(),
+            skipped_order: HashMap::new(),
+            max_skipped_keys: DEFAULT_MAX_SKIPPED_KEYS,
+            max_generation_jump: DEFAULT_MAX_GENERATION_JUMP,
+            cipher_suite: CipherSuite::default(),
+        }
+    }
+
+    /// Capture the ratchets and skipped keys so they can be restored with
+    /// [`Self::import_state`].
+    pub fn export_state(&self) -> SenderKeysState {
+        let mut ratchets: Vec<_> = self
+            .ratchets
+            .iter()
+            .map(|(&sender_index, ratchet)| {
+                (sender_index, *ratchet.chain_key(), ratchet.generation())
+            })
+            .collect();
+        ratchets.sort_unstable_by_key(|&(sender_index, ..)| sender_index);
+
+        let mut senders: Vec<_> = self.skipped_order.keys().copied().collect();
+        senders.sort_unstable();
+        let skipped = senders
+            .into_iter()
+            .flat_map(|sender_index| {
+                let generations = self.skipped_order.get(&sender_index).into_iter().flatten();
+                generations.map(move |&generation| (sender_index, generation))
+            })
+            .filter_map(|(sender_index, generation)| {
+                let key = self.skipped.get(&(sender_index, generation))?;
+                Some((sender_index, generation, *key.key()))
+            })
+            .collect();
+
+        SenderKeysState {
+            epoch: self.epoch,
+            ratchets,
+            skipped,
+            max_skipped_keys: self.max_skipped_keys,
+            max_generation_jump: self.max_generation_jump,
+            cipher_suite: self.cipher_suite.id(),
+        }
+    }
+
+    /// Restore a store captured by [`Self::export_state`], resuming every
+    /// ratchet at its generation.
+    ///
+    /// # Errors
+    ///
+    /// - `SenderKeyError::UnsupportedCipherSuite` if the state names an AEAD
+    ///   suite we don't implement
+    pub fn import_state(mut state: SenderKeysState) -> Result<Self, SenderKeyError> {
+        let cipher_suite = CipherSuite::from_id(state.cipher_suite)?;
+        let ratchets = mem::take(&mut state.ratchets)
+            .into_iter()
+            .map(|(sender_index, mut chain_key, generation)| {
+                let mut ratchet = SymmetricRatchet::restore(chain_key, generation);
+                ratchet.set_max_generation_jump(state.max_generation_jump);
+                chain_key.zeroize();
+                (sender_index, ratchet)
+            })
+            .collect();
+
+        let mut skipped = HashMap::with_capacity(state.skipped.len());
+        let mut skipped_order: HashMap<u32, VecDeque<u32>> = HashMap::new();
+        for (sender_index, generation, mut key) in mem::take(&mut state.skipped) {
+            skipped.insert((sender_index, generation), MessageKey::restore(key, generation));
+            skipped_order.entry(sender_index).or_default().push_back(generation);
+            key.zeroize();
+        }
+
+        let mut store = Self {
+            epoch: state.epoch,
+            ratchets,
+            skipped,
+            skipped_order,
+            max_skipped_keys: state.max_skipped_keys,
+            max_generation_jump: state.max_generation_jump,
+            cipher_suite,
+        };
+        store.evict_skipped();
+        Ok(store)
+    }
+
+    /// Keep at most `max` skipped message keys per sender, dropping each
+    /// sender's oldest beyond that. Zero disables out-of-order decryption.
+    pub fn set_max_skipped_keys(&mut self, max: usize) {
+        self.max_skipped_keys = max;
+        self.evict_skipped();
+    }
+
+    /// Reject messages more than `max` generations ahead of their sender's
+    /// ratchet. Defaults to [`DEFAULT_MAX_GENERATION_JUMP`].
+    pub fn set_max_generation_jump(&mut self, max: u32) {
+        self.max_generation_jump = max;
+        for ratchet in self.ratchets.values_mut() {
+            ratchet.set_max_generation_jump(max);
+        }
+    }
+
+    /// Encrypt messages with `suite` and accept only messages encrypted with
+    /// it. Defaults to XChaCha20-Poly1305.
+    pub fn set_cipher_suite(&mut self, suite: CipherSuite) {
+        self.cipher_suite = suite;
+    }
+
+    /// AEAD suite messages are encrypted with.
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.cipher_suite
+    }
+
+    /// Number of skipped message keys held for late messages, across all
+    /// senders.
+    pub fn skipped_key_count(&self) -> usize {
+        self.skipped.len()
     }
 
     /// Current MLS epoch for this room.
@@ -60,7 +250,7 @@ impl SenderKeyStore {
         self.ratchets.contains_key(&sender_index)
     }
 
-    /// Encrypt a message as a specific sender.
+    /// Encrypt a message as a specific sender, without padding.
     ///
     /// Advances the sender's ratchet and returns the encrypted message.
     pub fn encrypt(
@@ -68,6 +258,19 @@ impl SenderKeyStore {
         sender_index: u32,
         plaintext: &[u8],
         random_bytes: [u8; NONCE_RANDOM_SIZE],
+    ) -> Result<EncryptedMessage, SenderKeyError> {
+        self.encrypt_padded(sender_index, plaintext, Padding::None, random_bytes)
+    }
+
+    /// Encrypt a message as a specific sender, padded with `padding`.
+    ///
+    /// Advances the sender's ratchet and returns the encrypted message.
+    pub fn encrypt_padded(
+        &mut self,
+        sender_index: u32,
+        plaintext: &[u8],
+        padding: Padding,
+        random_bytes: [u8; NONCE_RANDOM_SIZE],
     ) -> Result<EncryptedMessage, SenderKeyError> {
         let ratchet = self
             .ratchets
@@ -75,19 +278,33 @@ impl SenderKeyStore {
             .ok_or(SenderKeyError::UnknownSender { sender_index })?;
 
         let message_key = ratchet.advance()?;
-        Ok(encrypt_message(plaintext, &message_key, self.epoch, sender_index, random_bytes))
+        Ok(encrypt_padded(
+            plaintext,
+            padding,
+            self.cipher_suite,
+            &message_key,
+            self.epoch,
+            sender_index,
+            random_bytes,
+        ))
     }
 
     /// Decrypt a message from any member.
     ///
-    /// Advances the sender's ratchet to match the message generation.
+    /// Advances the sender's ratchet to match the message generation, caching
+    /// the keys of skipped generations. A message behind the ratchet is
+    /// decrypted with its cached key, which is then discarded.
     ///
     /// # Errors
     ///
     /// - `SenderKeyError::EpochMismatch` if message is for a different epoch
+    /// - `SenderKeyError::CipherSuiteMismatch` if message is not encrypted with
+    ///   the room's suite
     /// - `SenderKeyError::UnknownSender` if sender not in this store
-    /// - `SenderKeyError::RatchetTooFarBehind` if message generation too far
-    ///   ahead
+    /// - `SenderKeyError::RatchetTooFarBehind` if message generation is behind
+    ///   the ratchet with no cached key (already used or evicted)
+    /// - `SenderKeyError::GenerationJumpTooLarge` if message generation is more
+    ///   than `max_generation_jump` ahead of the ratchet
     /// - `SenderKeyError::DecryptionFailed` if authentication failed (tampering
     ///   or wrong key)
     pub fn decrypt(&mut self, encrypted: &EncryptedMessage) -> Result<Vec<u8>, SenderKeyError> {
@@ -97,14 +314,72 @@ impl SenderKeyStore {
                 actual: encrypted.epoch,
             });
         }
+        if encrypted.cipher_suite != self.cipher_suite.id() {
+            return Err(SenderKeyError::CipherSuiteMismatch {
+                expected: self.cipher_suite.id(),
+                actual: encrypted.cipher_suite,
+            });
+        }
+
+        let slot = (encrypted.sender_index, encrypted.generation);
+        if let Some(message_key) = self.skipped.get(&slot) {
+            // Consume the key only once it authenticated, so a forged message
+            // cannot burn the key of one still in flight
+            let plaintext = decrypt_message(encrypted, message_key)?;
+            self.skipped.remove(&slot);
+            if let Some(order) = self.skipped_order.get_mut(&encrypted.sender_index) {
+                order.retain(|&generation| generation != encrypted.generation);
+            }
+            return Ok(plaintext);
+        }
 
         let ratchet = self
             .ratchets
             .get_mut(&encrypted.sender_index)
             .ok_or(SenderKeyError::UnknownSender { sender_index: encrypted.sender_index })?;
 
-        let message_key = ratchet.advance_to(encrypted.generation)?;
-        decrypt_message(encrypted, &message_key)
+        // Advance a copy and keep it only once the message authenticated, so a
+        // forged future generation cannot burn real keys or evict cached ones
+        let mut advanced = ratchet.clone();
+        let mut newly_skipped = Vec::new();
+        let message_key =
+            advanced.advance_to_with(encrypted.generation, |key| newly_skipped.push(key))?;
+        let plaintext = decrypt_message(encrypted, &message_key)?;
+
+        *ratchet = advanced;
+        let sender_index = encrypted.sender_index;
+        let order = self.skipped_order.entry(sender_index).or_default();
+        for key in newly_skipped {
+            order.push_back(key.generation());
+            self.skipped.insert((sender_index, key.generation()), key);
+        }
+        self.evict_skipped();
+
+        Ok(plaintext)
+    }
+
+    /// Wipe every chain key and skipped message key, e.g. on leaving the
+    /// room.
+    ///
+    /// Afterwards the store knows no sender: encrypting or decrypting fails
+    /// with `SenderKeyError::UnknownSender`.
+    pub fn purge(&mut self) {
+        // Ratchets and message keys zeroize themselves when dropped
+        self.ratchets.clear();
+        self.skipped.clear();
+        self.skipped_order.clear();
+    }
+
+    /// Drop each sender's oldest skipped keys until the cap is respected.
+    fn evict_skipped(&mut self) {
+        for (&sender_index, order) in &mut self.skipped_order {
+            while order.len() > self.max_skipped_keys {
+                if let Some(generation) = order.pop_front() {
+                    self.skipped.remove(&(sender_index, generation));
+                }
+            }
+        }
+        self.skipped_order.retain(|_, order| !order.is_empty());
     }
 
     /// Current generation for a sender's ratchet. `None` if sender not
@@ -189,6 +464,8 @@ mod tests {
             epoch: 1,
             sender_index: 5, // not in store
             generation: 0,
+            padding: 0,
+            cipher_suite: 0,
             nonce: [0; 24],
             ciphertext: vec![0; 32],
         };
@@ -206,6 +483,8 @@ mod tests {
             epoch: 2, // wrong!
             sender_index: 0,
             generation: 0,
+            padding: 0,
+            cipher_suite: 0,
             nonce: [0; 24],
             ciphertext: vec![0; 32],
         };
@@ -222,21 +501,226 @@ mod tests {
         // Sender encrypts messages 0, 1, 2
         let mut sender_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
         let msg0 = sender_store.encrypt(0, b"msg0", [0; NONCE_RANDOM_SIZE]).unwrap();
-        let _msg1 = sender_store.encrypt(0, b"msg1", [1; NONCE_RANDOM_SIZE]).unwrap();
+        let msg1 = sender_store.encrypt(0, b"msg1", [1; NONCE_RANDOM_SIZE]).unwrap();
         let msg2 = sender_store.encrypt(0, b"msg2", [2; NONCE_RANDOM_SIZE]).unwrap();
 
         // Receiver gets them out of order: 2, 0, 1
         let mut receiver_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
 
-        // Receive msg2 first (skips to generation 2)
+        // Receive msg2 first (skips to generation 2, caching 0 and 1)
         let decrypted = receiver_store.decrypt(&msg2).unwrap();
         assert_eq!(decrypted, b"msg2");
+        assert_eq!(receiver_store.skipped_key_count(), 2);
+
+        // A forged late message does not burn the cached key
+        let mut forged = msg0.clone();
+        forged.ciphertext[0] ^= 0xFF;
+        let result = receiver_store.decrypt(&forged);
+        assert!(matches!(result, Err(SenderKeyError::DecryptionFailed { .. })));
 
-        // msg0 and msg1 are now behind the ratchet - should fail
+        // Late messages decrypt with their cached keys
+        assert_eq!(receiver_store.decrypt(&msg0).unwrap(), b"msg0");
+        assert_eq!(receiver_store.decrypt(&msg1).unwrap(), b"msg1");
+        assert_eq!(receiver_store.skipped_key_count(), 0);
+
+        // Each cached key is used once
         let result = receiver_store.decrypt(&msg0);
         assert!(matches!(result, Err(SenderKeyError::RatchetTooFarBehind { .. })));
     }
 
+    #[test]
+    fn forged_future_message_leaves_ratchet_and_cache_intact() {
+        let members = vec![0, 1];
+        let epoch_secret = test_epoch_secret();
+
+        let mut sender_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        let messages: Vec<_> = (0..6u8)
+            .map(|i| sender_store.encrypt(0, &[i], [i; NONCE_RANDOM_SIZE]).unwrap())
+            .collect();
+
+        let mut receiver_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        receiver_store.set_max_skipped_keys(2);
+        assert_eq!(receiver_store.decrypt(&messages[2]).unwrap(), [2]);
+        assert_eq!(receiver_store.skipped_key_count(), 2);
+
+        // Had the ratchet moved, this would burn generation 5's key and evict
+        // the cached keys for 0 and 1
+        let mut forged = messages[5].clone();
+        forged.ciphertext[0] ^= 0xFF;
+        let result = receiver_store.decrypt(&forged);
+        assert!(matches!(result, Err(SenderKeyError::DecryptionFailed { .. })));
+        assert_eq!(receiver_store.generation(0), Some(3));
+        assert_eq!(receiver_store.skipped_key_count(), 2);
+
+        assert_eq!(receiver_store.decrypt(&messages[0]).unwrap(), [0]);
+        assert_eq!(receiver_store.decrypt(&messages[1]).unwrap(), [1]);
+        assert_eq!(receiver_store.decrypt(&messages[5]).unwrap(), [5]);
+    }
+
+    #[test]
+    fn exported_state_keeps_generations_and_skipped_keys() {
+        let members = vec![0, 1];
+        let epoch_secret = test_epoch_secret();
+
+        let mut sender_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        let msg0 = sender_store.encrypt(0, b"msg0", [0; NONCE_RANDOM_SIZE]).unwrap();
+        let msg1 = sender_store.encrypt(0, b"msg1", [1; NONCE_RANDOM_SIZE]).unwrap();
+        let msg2 = sender_store.encrypt(0, b"msg2", [2; NONCE_RANDOM_SIZE]).unwrap();
+
+        let mut receiver_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        receiver_store.decrypt(&msg1).unwrap();
+
+        let mut restored = SenderKeyStore::import_state(receiver_store.export_state()).unwrap();
+        drop(receiver_store);
+
+        assert_eq!(restored.epoch(), 1);
+        assert_eq!(restored.generation(0), Some(2));
+        assert_eq!(restored.generation(1), Some(0));
+        assert_eq!(restored.skipped_key_count(), 1);
+
+        // Both the late message and the next one decrypt
+        assert_eq!(restored.decrypt(&msg0).unwrap(), b"msg0");
+        assert_eq!(restored.decrypt(&msg2).unwrap(), b"msg2");
+    }
+
+    #[test]
+    fn skipped_keys_are_capped() {
+        let members = vec![0, 1];
+        let epoch_secret = test_epoch_secret();
+
+        let mut sender_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        let messages: Vec<_> = (0..5u8)
+            .map(|i| sender_store.encrypt(0, &[i], [i; NONCE_RANDOM_SIZE]).unwrap())
+            .collect();
+
+        let mut receiver_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        receiver_store.set_max_skipped_keys(2);
+
+        // Skipping generations 0..4 keeps only the newest two
+        receiver_store.decrypt(&messages[4]).unwrap();
+        assert_eq!(receiver_store.skipped_key_count(), 2);
+
+        let result = receiver_store.decrypt(&messages[1]);
+        assert!(matches!(result, Err(SenderKeyError::RatchetTooFarBehind { .. })));
+        assert_eq!(receiver_store.decrypt(&messages[2]).unwrap(), [2]);
+        assert_eq!(receiver_store.decrypt(&messages[3]).unwrap(), [3]);
+
+        // Lowering the cap drops what no longer fits
+        let more: Vec<_> = (5..8u8)
+            .map(|i| sender_store.encrypt(0, &[i], [i; NONCE_RANDOM_SIZE]).unwrap())
+            .collect();
+        receiver_store.decrypt(&more[2]).unwrap();
+        receiver_store.set_max_skipped_keys(0);
+        assert_eq!(receiver_store.skipped_key_count(), 0);
+    }
+
+    #[test]
+    fn one_sender_skipping_ahead_keeps_other_senders_keys() {
+        let members = vec![0, 1, 2];
+        let epoch_secret = test_epoch_secret();
+
+        let mut sender_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        let quiet: Vec<_> = (0..3u8)
+            .map(|i| sender_store.encrypt(0, &[i], [i; NONCE_RANDOM_SIZE]).unwrap())
+            .collect();
+        let noisy: Vec<_> = (0..10u8)
+            .map(|i| sender_store.encrypt(1, &[i], [i; NONCE_RANDOM_SIZE]).unwrap())
+            .collect();
+
+        let mut receiver_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        receiver_store.set_max_skipped_keys(2);
+
+        receiver_store.decrypt(&quiet[2]).unwrap();
+        receiver_store.decrypt(&noisy[9]).unwrap();
+        assert_eq!(receiver_store.skipped_key_count(), 4);
+
+        assert_eq!(receiver_store.decrypt(&quiet[0]).unwrap(), [0]);
+        assert_eq!(receiver_store.decrypt(&quiet[1]).unwrap(), [1]);
+        assert_eq!(receiver_store.decrypt(&noisy[8]).unwrap(), [8]);
+    }
+
+    #[test]
+    fn purged_store_holds_no_keys() {
+        let members = vec![0, 1];
+        let epoch_secret = test_epoch_secret();
+
+        let mut sender_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        let messages: Vec<_> = (0..3u8)
+            .map(|i| sender_store.encrypt(0, &[i], [i; NONCE_RANDOM_SIZE]).unwrap())
+            .collect();
+
+        let mut receiver_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        receiver_store.decrypt(&messages[2]).unwrap();
+        assert_eq!(receiver_store.skipped_key_count(), 2);
+
+        receiver_store.purge();
+        assert_eq!(receiver_store.member_count(), 0);
+        assert_eq!(receiver_store.skipped_key_count(), 0);
+        assert!(receiver_store.export_state().ratchets.is_empty());
+
+        // Neither skipped nor future messages open any more
+        let result = receiver_store.decrypt(&messages[0]);
+        assert!(matches!(result, Err(SenderKeyError::UnknownSender { sender_index: 0 })));
+        let result = receiver_store.encrypt(1, b"late", [0; NONCE_RANDOM_SIZE]);
+        assert!(matches!(result, Err(SenderKeyError::UnknownSender { sender_index: 1 })));
+    }
+
+    #[test]
+    fn generations_beyond_the_jump_are_rejected() {
+        let members = vec![0, 1];
+        let epoch_secret = test_epoch_secret();
+
+        let mut sender_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        let messages: Vec<_> = (0..4u8)
+            .map(|i| sender_store.encrypt(0, &[i], [i; NONCE_RANDOM_SIZE]).unwrap())
+            .collect();
+
+        let mut receiver_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        let forged = EncryptedMessage { generation: u32::MAX, ..messages[0].clone() };
+        let result = receiver_store.decrypt(&forged);
+        assert!(matches!(result, Err(SenderKeyError::GenerationJumpTooLarge { .. })));
+        assert_eq!(receiver_store.generation(0), Some(0));
+        assert_eq!(receiver_store.skipped_key_count(), 0);
+
+        // The bound survives a restart
+        receiver_store.set_max_generation_jump(2);
+        let mut receiver_store =
+            SenderKeyStore::import_state(receiver_store.export_state()).unwrap();
+        let result = receiver_store.decrypt(&messages[3]);
+        assert!(matches!(
+            result,
+            Err(SenderKeyError::GenerationJumpTooLarge { current: 0, requested: 3, max_jump: 2 })
+        ));
+        assert_eq!(receiver_store.decrypt(&messages[2]).unwrap(), [2]);
+        assert_eq!(receiver_store.decrypt(&messages[3]).unwrap(), [3]);
+    }
+
+    #[test]
+    fn messages_in_another_suite_are_rejected() {
+        let members = vec![0, 1];
+        let epoch_secret = test_epoch_secret();
+
+        let mut sender_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        sender_store.set_cipher_suite(CipherSuite::Aes256Gcm);
+        let message = sender_store.encrypt(0, b"aes", [0; NONCE_RANDOM_SIZE]).unwrap();
+        assert_eq!(message.cipher_suite, CipherSuite::Aes256Gcm.id());
+
+        let mut receiver_store = SenderKeyStore::initialize_epoch(&epoch_secret, 1, &members);
+        let result = receiver_store.decrypt(&message);
+        assert!(matches!(
+            result,
+            Err(SenderKeyError::CipherSuiteMismatch { expected: 0, actual: 1 })
+        ));
+        assert_eq!(receiver_store.generation(0), Some(0));
+
+        // The suite survives a restart
+        receiver_store.set_cipher_suite(CipherSuite::Aes256Gcm);
+        let mut receiver_store =
+            SenderKeyStore::import_state(receiver_store.export_state()).unwrap();
+        assert_eq!(receiver_store.cipher_suite(), CipherSuite::Aes256Gcm);
+        assert_eq!(receiver_store.decrypt(&message).unwrap(), b"aes");
+    }
+
     #[test]
     fn different_epochs_produce_different_keys() {
         let members = vec![0];
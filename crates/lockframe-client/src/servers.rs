@@ -0,0 +1,231 @@
+//! Server connections.
+//!
+//! A client may hold sessions with several servers at once, for instance
+//! while a room migrates or when rooms are federated across servers. Each
+//! room has a home server that sequences it: frames for the room are sent
+//! there, and frames for it arriving from any other server are ignored.
+//! Rooms are homed on [`HOME_SERVER`] unless moved.
+//!
+//! Every server has its own connection state, so one dropping offline only
+//! holds back intents for the rooms it homes, and its clock readings don't
+//! skew the others. Each connection is also probed with its own heartbeats,
+//! see [`Liveness`].
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Instant,
+};
+
+use lockframe_core::{
+    connection::{Connection as Session, ConnectionState},
+    mls::RoomId,
+    rtt::HeartbeatTracker,
+};
+use lockframe_proto::Capabilities;
+
+/// Identifies a server connection. The caller picks the values and maps
+/// them to addresses.
+pub type ServerId = u32;
+
+/// Server rooms are homed on unless moved, and the one the single-server
+/// events and [`ClientAction::Send`](crate::ClientAction::Send) refer to.
+pub const HOME_SERVER: ServerId = 0;
+
+/// Heartbeats and traffic on one server connection.
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    /// Outstanding heartbeat and RTT estimate.
+    pub heartbeats: HeartbeatTracker<Instant>,
+
+    /// When a frame last arrived from the server, or the connection to it
+    /// came up.
+    pub last_received: Instant,
+}
+
+impl Liveness {
+    /// Liveness of a connection that came up at `now`.
+    pub fn new(now: Instant) -> Self {
+        Self { heartbeats: HeartbeatTracker::new(now), last_received: now }
+    }
+}
+
+/// State of one server connection.
+#[derive(Debug, Default)]
+struct Connection {
+    /// Whether the caller reports the connection as live.
+    online: bool,
+
+    /// Server wall clock minus local wall clock, from the latest `TimeSync`.
+    clock_offset_millis: Option<i64>,
+
+    /// Rooms moved here that sync once the connection is live.
+    unsynced: BTreeSet<RoomId>,
+
+    /// Capabilities negotiated in the latest `HelloReply`.
+    capabilities: Capabilities,
+
+    /// Handshake of the current session, once the client said Hello.
+    session: Option<Session>,
+}
+
+/// Server connections and where each room is homed.
+#[derive(Debug)]
+pub struct Servers {
+    /// Connections by server; a server without an entry is offline.
+    connections: HashMap<ServerId, Connection>,
+
+    /// Rooms homed somewhere other than [`HOME_SERVER`].
+    homes: HashMap<RoomId, ServerId>,
+}
+
+impl Default for Servers {
+    fn default() -> Self {
+        let home = Connection { online: true, ..Connection::default() };
+        Self { connections: HashMap::from([(HOME_SERVER, home)]), homes: HashMap::new() }
+    }
+}
+
+impl Servers {
+    /// Server that sequences `room_id`.
+    pub fn home(&self, room_id: RoomId) -> ServerId {
+        self.homes.get(&room_id).copied().unwrap_or(HOME_SERVER)
+    }
+
+    /// Home `room_id` on `server`.
+    ///
+    /// Returns whether the connection is live; if not, the room is synced
+    /// when it comes up, see [`Servers::set_online`].
+    pub fn set_home(&mut self, room_id: RoomId, server: ServerId) -> bool {
+        self.forget_room(room_id);
+        if server != HOME_SERVER {
+            self.homes.insert(room_id, server);
+        }
+
+        let connection = self.connections.entry(server).or_default();
+        if !connection.online {
+            connection.unsynced.insert(room_id);
+        }
+        connection.online
+    }
+
+    /// Rooms homed somewhere other than [`HOME_SERVER`], by ascending room
+    /// ID.
+    pub fn homes(&self) -> Vec<(RoomId, ServerId)> {
+        let mut homes: Vec<_> = self.homes.iter().map(|(&room, &server)| (room, server)).collect();
+        homes.sort_unstable_by_key(|&(room_id, _)| room_id);
+        homes
+    }
+
+    /// The client is no longer in `room_id`.
+    pub fn forget_room(&mut self, room_id: RoomId) {
+        if let Some(server) = self.homes.remove(&room_id) {
+            if let Some(connection) = self.connections.get_mut(&server) {
+                connection.unsynced.remove(&room_id);
+            }
+        }
+        if let Some(connection) = self.connections.get_mut(&HOME_SERVER) {
+            connection.unsynced.remove(&room_id);
+        }
+    }
+
+    /// Whether the connection to `server` is live.
+    pub fn is_online(&self, server: ServerId) -> bool {
+        self.connections.get(&server).is_some_and(|connection| connection.online)
+    }
+
+    /// Servers whose connection is live, by ascending server ID.
+    pub fn online(&self) -> Vec<ServerId> {
+        let mut online: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.online)
+            .map(|(&server, _)| server)
+            .collect();
+        online.sort_unstable();
+        online
+    }
+
+    /// Record whether the connection to `server` is live.
+    ///
+    /// Coming online returns the rooms moved to `server` while it was
+    /// offline, which need syncing. Going offline forgets the session and
+    /// the capabilities negotiated with it; the next session negotiates them
+    /// afresh.
+    pub fn set_online(&mut self, server: ServerId, online: bool) -> BTreeSet<RoomId> {
+        let connection = self.connections.entry(server).or_default();
+        connection.online = online;
+        if online {
+            std::mem::take(&mut connection.unsynced)
+        } else {
+            connection.capabilities = Capabilities::empty();
+            connection.session = None;
+            BTreeSet::new()
+        }
+    }
+
+    /// Server clock offset from the latest `TimeSync` sent by `server`.
+    pub fn clock_offset_millis(&self, server: ServerId) -> Option<i64> {
+        self.connections.get(&server).and_then(|connection| connection.clock_offset_millis)
+    }
+
+    /// Record the clock offset measured from `server`.
+    pub fn set_clock_offset_millis(&mut self, server: ServerId, offset: i64) {
+        self.connections.entry(server).or_default().clock_offset_millis = Some(offset);
+    }
+
+    /// Capabilities negotiated with `server`, empty before its `HelloReply`.
+    pub fn capabilities(&self, server: ServerId) -> Capabilities {
+        self.connections
+            .get(&server)
+            .map_or(Capabilities::empty(), |connection| connection.capabilities)
+    }
+
+    /// Record the capabilities `server` agreed to in its `HelloReply`.
+    pub fn set_capabilities(&mut self, server: ServerId, capabilities: Capabilities) {
+        self.connections.entry(server).or_default().capabilities = capabilities;
+    }
+
+    /// Start a new session with `server`, replacing the handshake of any
+    /// earlier one.
+    pub fn start_session(&mut self, server: ServerId, session: Session) {
+        self.connections.entry(server).or_default().session = Some(session);
+    }
+
+    /// Handshake with `server`, if the client said Hello to it.
+    pub fn session_mut(&mut self, server: ServerId) -> Option<&mut Session> {
+        self.connections.get_mut(&server)?.session.as_mut()
+    }
+
+    /// Whether the handshake with `server` completed.
+    pub fn is_authenticated(&self, server: ServerId) -> bool {
+        self.connections
+            .get(&server)
+            .and_then(|connection| connection.session.as_ref())
+            .is_some_and(|session| session.state() == ConnectionState::Authenticated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rooms_moved_to_offline_servers_sync_on_connect() {
+        let mut servers = Servers::default();
+        assert!(servers.is_online(HOME_SERVER));
+        assert!(!servers.is_online(1));
+
+        assert!(!servers.set_home(7, 1));
+        assert!(!servers.set_home(8, 1));
+        servers.forget_room(8);
+        assert_eq!(servers.home(7), 1);
+        assert_eq!(servers.home(8), HOME_SERVER);
+
+        assert_eq!(servers.set_online(1, true), BTreeSet::from([7]));
+        assert!(servers.set_online(1, true).is_empty());
+
+        // Moving back home needs no connection
+        assert!(servers.set_home(7, HOME_SERVER));
+        assert_eq!(servers.home(7), HOME_SERVER);
+    }
+}
@@ -0,0 +1,258 @@
+//! Per-room transcript verification.
+//!
+//! Tracks what the server has shown this client about a room's log, so that
+//! two inconsistent views are caught even though the server is untrusted:
+//!
+//! - the same log index carrying two different frames
+//! - two signed checkpoints for the same log index with different hashes
+//! - a checkpoint whose hash disagrees with the log the client replayed
+//! - two Merkle roots for the same tree size, or a proof that doesn't verify
+//!
+//! Checkpoint signatures are checked by the client before they reach the
+//! transcript. A newly joined member hasn't seen the log prefix, so it can't
+//! check checkpoint hashes itself and relies on the Merkle proofs instead.
+
+use std::collections::BTreeMap;
+
+use lockframe_core::{
+    checkpoint::LogHash,
+    merkle::{MerkleHash, leaf_hash, verify_consistency, verify_inclusion},
+};
+use lockframe_proto::{
+    Frame,
+    payloads::session::{Checkpoint, ProofRequest, ProofResponse},
+};
+use serde::{Deserialize, Serialize};
+
+/// Maximum entries kept per history map. Older entries are dropped first.
+const MAX_TRACKED: usize = 1024;
+
+/// What the server has presented about one room's log.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Transcript {
+    /// Next log index and rolling hash, while every frame since index 0 has
+    /// been seen in order.
+    head: Option<(u64, LogHash)>,
+
+    /// Leaf hashes of recently seen frames, by log index.
+    leaves: BTreeMap<u64, MerkleHash>,
+
+    /// Signed log hashes, by the last log index they cover.
+    checkpoints: BTreeMap<u64, [u8; 32]>,
+
+    /// Merkle roots the server has presented, by tree size.
+    roots: BTreeMap<u64, MerkleHash>,
+
+    /// Largest tree size whose root is proven to extend every earlier
+    /// trusted root.
+    trusted_size: Option<u64>,
+}
+
+impl Transcript {
+    /// Transcript for a room this client created and has seen from index 0.
+    pub fn genesis() -> Self {
+        Self { head: Some((0, LogHash::GENESIS)), ..Self::default() }
+    }
+
+    /// Record a sequenced frame.
+    ///
+    /// Fails if a different frame was already seen at the same log index.
+    pub fn observe_frame(&mut self, frame: &Frame) -> Result<(), String> {
+        let log_index = frame.header.log_index();
+        let leaf = leaf_hash(frame);
+
+        if self.leaves.get(&log_index).is_some_and(|seen| *seen != leaf) {
+            return Err(format!("log index {log_index} presented with two different frames"));
+        }
+        insert_bounded(&mut self.leaves, log_index, leaf);
+
+        self.head = match self.head {
+            Some((next, hash)) if log_index == next => {
+                Some((next.saturating_add(1), hash.chain(frame)))
+            },
+            Some((next, _)) if log_index > next => None,
+            head => head,
+        };
+
+        Ok(())
+    }
+
+    /// Record a checkpoint sequenced at `frame_index`, before the checkpoint
+    /// frame itself is observed.
+    ///
+    /// The signature must already be verified.
+    pub fn observe_checkpoint(
+        &mut self,
+        frame_index: u64,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), String> {
+        let log_index = checkpoint.log_index;
+
+        if log_index.checked_add(1) != Some(frame_index) {
+            return Err(format!(
+                "checkpoint at log index {frame_index} covers log index {log_index}"
+            ));
+        }
+
+        if self.checkpoints.get(&log_index).is_some_and(|seen| *seen != checkpoint.log_hash) {
+            return Err(format!("two checkpoints for log index {log_index}"));
+        }
+
+        if let Some((next, hash)) = self.head {
+            if next == frame_index && *hash.as_bytes() != checkpoint.log_hash {
+                return Err(format!("checkpoint for log index {log_index} does not match the log"));
+            }
+        }
+
+        insert_bounded(&mut self.checkpoints, log_index, checkpoint.log_hash);
+        Ok(())
+    }
+
+    /// Check a Merkle proof against everything seen so far.
+    ///
+    /// Inclusion proofs are checked when the frame was seen, consistency
+    /// proofs when the older root was seen. A verified proof that links back
+    /// to the trusted root advances it.
+    pub fn observe_proof(&mut self, response: &ProofResponse) -> Result<(), String> {
+        let ProofResponse { request, tree_size, root, path } = response;
+        let tree_size = *tree_size;
+
+        if self.roots.get(&tree_size).is_some_and(|seen| seen != root) {
+            return Err(format!("two Merkle roots for tree size {tree_size}"));
+        }
+
+        let extends_trusted = match *request {
+            ProofRequest::Inclusion { leaf_index, .. } => {
+                let Some(leaf) = self.leaves.get(&leaf_index) else {
+                    insert_bounded(&mut self.roots, tree_size, *root);
+                    return Ok(());
+                };
+                if !verify_inclusion(leaf, leaf_index, tree_size, path, root) {
+                    return Err(format!(
+                        "inclusion proof for log index {leaf_index} in tree size {tree_size} \
+                         does not verify"
+                    ));
+                }
+                self.trusted_size.is_none()
+            },
+            ProofRequest::Consistency { old_size, .. } => {
+                let Some(old_root) = self.roots.get(&old_size) else {
+                    insert_bounded(&mut self.roots, tree_size, *root);
+                    return Ok(());
+                };
+                if !verify_consistency(old_size, tree_size, old_root, root, path) {
+                    return Err(format!(
+                        "tree size {tree_size} is not consistent with tree size {old_size}"
+                    ));
+                }
+                self.trusted_size == Some(old_size)
+            },
+        };
+
+        insert_bounded(&mut self.roots, tree_size, *root);
+        if extends_trusted && self.trusted_size.map_or(true, |trusted| tree_size >= trusted) {
+            self.trusted_size = Some(tree_size);
+        }
+
+        Ok(())
+    }
+
+    /// Proof to request after a checkpoint at `frame_index`: consistency with
+    /// the trusted root once there is one, otherwise inclusion of the
+    /// checkpoint frame to anchor a first root.
+    pub fn next_proof_request(&self, frame_index: u64) -> ProofRequest {
+        self.trusted_size.map_or(
+            ProofRequest::Inclusion { leaf_index: frame_index, tree_size: None },
+            |old_size| ProofRequest::Consistency { old_size, new_size: None },
+        )
+    }
+}
+
+fn insert_bounded<V>(map: &mut BTreeMap<u64, V>, key: u64, value: V) {
+    map.insert(key, value);
+    if map.len() > MAX_TRACKED {
+        map.pop_first();
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use lockframe_core::merkle::MerkleLog;
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+
+    fn frame(log_index: u64, body: &str) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(1);
+        header.set_log_index(log_index);
+        Frame::new(header, body.as_bytes().to_vec())
+    }
+
+    fn proof(log: &MerkleLog, request: ProofRequest) -> ProofResponse {
+        let (tree_size, path) = match request {
+            ProofRequest::Inclusion { leaf_index, .. } => {
+                (log.len(), log.inclusion_proof(leaf_index, log.len()).unwrap())
+            },
+            ProofRequest::Consistency { old_size, .. } => {
+                (log.len(), log.consistency_proof(old_size, log.len()).unwrap())
+            },
+        };
+        ProofResponse { request, tree_size, root: log.root(), path }
+    }
+
+    #[test]
+    fn rewritten_frame_is_a_violation() {
+        let mut transcript = Transcript::genesis();
+        transcript.observe_frame(&frame(0, "a")).unwrap();
+        transcript.observe_frame(&frame(0, "a")).unwrap();
+        assert!(transcript.observe_frame(&frame(0, "b")).is_err());
+    }
+
+    #[test]
+    fn checkpoint_must_match_replayed_log() {
+        let mut transcript = Transcript::genesis();
+        let frames = [frame(0, "a"), frame(1, "b")];
+        for frame in &frames {
+            transcript.observe_frame(frame).unwrap();
+        }
+        let hash = LogHash::GENESIS.chain(&frames[0]).chain(&frames[1]);
+
+        let good = Checkpoint { log_index: 1, log_hash: *hash.as_bytes(), signature: vec![] };
+        let bad = Checkpoint { log_hash: [0xAA; 32], ..good.clone() };
+
+        assert!(transcript.clone().observe_checkpoint(2, &bad).is_err());
+        transcript.observe_checkpoint(2, &good).unwrap();
+        assert!(transcript.observe_checkpoint(2, &bad).is_err());
+    }
+
+    #[test]
+    fn forked_tree_fails_consistency() {
+        let honest: Vec<Frame> = (0..6).map(|i| frame(i, "honest")).collect();
+        let mut log = MerkleLog::new();
+        let mut transcript = Transcript::default();
+
+        for frame in &honest[..4] {
+            log.push(frame);
+            transcript.observe_frame(frame).unwrap();
+        }
+        let request = transcript.next_proof_request(3);
+        transcript.observe_proof(&proof(&log, request)).unwrap();
+        assert_eq!(transcript.trusted_size, Some(4));
+
+        // Same server, but answering from a log where index 1 was rewritten
+        let mut forked = MerkleLog::new();
+        for (i, frame) in honest.iter().enumerate() {
+            forked.push(if i == 1 { &honest[0] } else { frame });
+        }
+        let request = transcript.next_proof_request(5);
+        assert!(transcript.observe_proof(&proof(&forked, request)).is_err());
+
+        for frame in &honest[4..] {
+            log.push(frame);
+        }
+        transcript.observe_proof(&proof(&log, request)).unwrap();
+        assert_eq!(transcript.trusted_size, Some(6));
+    }
+}
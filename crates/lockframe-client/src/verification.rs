@@ -0,0 +1,55 @@
+//! Peer verification.
+//!
+//! Members verify each other by comparing material derived from the keys
+//! their client sees for the other, see [`lockframe_crypto::verification`].
+//! Once the user confirms a match, the peer's fingerprint is remembered and
+//! messages from them are delivered as verified until their key changes.
+
+use std::collections::HashMap;
+
+use lockframe_core::mls::MemberId;
+
+/// Material to compare with a peer, from one room's view of both members.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerVerification {
+    /// Member being verified.
+    pub peer_id: MemberId,
+    /// Epoch the short authentication string was derived in.
+    pub epoch: u64,
+    /// 60 digit safety number, stable while neither key changes.
+    pub safety_number: String,
+    /// 6 digit code, only valid in `epoch`.
+    pub short_auth_string: String,
+    /// Bytes to encode in a QR code for the peer to scan.
+    pub qr_payload: Vec<u8>,
+}
+
+/// Fingerprints of the peers the user has verified.
+#[derive(Debug, Default)]
+pub struct VerifiedPeers {
+    fingerprints: HashMap<MemberId, [u8; 32]>,
+}
+
+impl VerifiedPeers {
+    /// Remember `fingerprint` as the verified key of `peer_id`.
+    pub fn insert(&mut self, peer_id: MemberId, fingerprint: [u8; 32]) {
+        self.fingerprints.insert(peer_id, fingerprint);
+    }
+
+    /// Forget the verification of `peer_id`. Returns whether it was verified.
+    pub fn remove(&mut self, peer_id: MemberId) -> bool {
+        self.fingerprints.remove(&peer_id).is_some()
+    }
+
+    /// Fingerprint verified for `peer_id`, if any.
+    pub fn get(&self, peer_id: MemberId) -> Option<&[u8; 32]> {
+        self.fingerprints.get(&peer_id)
+    }
+
+    /// Every verified peer and its fingerprint, by ascending peer ID.
+    pub fn export(&self) -> Vec<(MemberId, [u8; 32])> {
+        let mut peers: Vec<_> = self.fingerprints.iter().map(|(&id, &fp)| (id, fp)).collect();
+        peers.sort_unstable_by_key(|&(peer_id, _)| peer_id);
+        peers
+    }
+}
@@ -0,0 +1,148 @@
+//! Exponential backoff for retries.
+//!
+//! [`Backoff`] doubles the delay with each consecutive attempt, starting at
+//! `initial` and capped at `max`. A jittered backoff draws each delay
+//! uniformly from the upper half of that range ("equal jitter"), so peers
+//! that failed together don't all retry at the same instant. Randomness
+//! comes from the [`Environment`], which keeps delays reproducible under a
+//! seeded simulation RNG.
+
+use std::time::Duration;
+
+use crate::env::Environment;
+
+/// Nanoseconds per second, for rebuilding a [`Duration`] from `u128` nanos.
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Exponential backoff schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay before the first retry
+    pub initial: Duration,
+    /// Cap on the delay between retries
+    pub max: Duration,
+    /// Whether delays are randomized within the upper half of the schedule
+    pub jitter: bool,
+}
+
+impl Backoff {
+    /// Backoff from `initial` doubling up to `max`, without jitter.
+    #[must_use]
+    pub const fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max, jitter: false }
+    }
+
+    /// The same schedule with jitter.
+    #[must_use]
+    pub const fn jittered(self) -> Self {
+        Self { jitter: true, ..self }
+    }
+
+    /// Delay before the given attempt, counting from 1, ignoring jitter.
+    ///
+    /// `initial * 2^(attempt - 1)`, saturating at `max`.
+    #[must_use]
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let max = self.max.as_nanos();
+        let nanos = 1u128
+            .checked_shl(attempt.saturating_sub(1))
+            .and_then(|factor| self.initial.as_nanos().checked_mul(factor))
+            .map_or(max, |nanos| nanos.min(max));
+
+        from_nanos(nanos)
+    }
+
+    /// Delay before the given attempt, counting from 1.
+    ///
+    /// With jitter, between half of [`base_delay`](Self::base_delay) and all
+    /// of it; the random part comes from `env`. Without jitter, `env` is not
+    /// consulted.
+    pub fn delay<E: Environment>(&self, attempt: u32, env: &E) -> Duration {
+        let base = self.base_delay(attempt);
+        if !self.jitter {
+            return base;
+        }
+
+        let half = base.as_nanos() / 2;
+        let offset = u128::from(env.random_u64()).checked_rem(half.saturating_add(1)).unwrap_or(0);
+        from_nanos(base.as_nanos().saturating_sub(half).saturating_add(offset))
+    }
+}
+
+/// [`Duration`] of `nanos`, which must not exceed [`Duration::MAX`].
+fn from_nanos(nanos: u128) -> Duration {
+    let secs = u64::try_from(nanos / NANOS_PER_SEC).unwrap_or(u64::MAX);
+    let subsec = u32::try_from(nanos % NANOS_PER_SEC).unwrap_or(0);
+    Duration::new(secs, subsec)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicU8, Ordering},
+        },
+        time::Instant,
+    };
+
+    use super::*;
+
+    /// Environment whose random bytes are all the same, settable value.
+    #[derive(Clone, Default)]
+    struct FixedRng(Arc<AtomicU8>);
+
+    impl Environment for FixedRng {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn sleep(&self, _duration: Duration) -> impl std::future::Future<Output = ()> + Send {
+            std::future::ready(())
+        }
+
+        fn random_bytes(&self, buffer: &mut [u8]) {
+            buffer.fill(self.0.load(Ordering::Relaxed));
+        }
+    }
+
+    #[test]
+    fn doubles_up_to_max() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        let env = FixedRng::default();
+
+        let delays: Vec<Duration> = (1..=6).map(|attempt| backoff.delay(attempt, &env)).collect();
+        assert_eq!(delays, vec![
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            Duration::from_millis(400),
+            Duration::from_millis(800),
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        ]);
+    }
+
+    #[test]
+    fn saturates_instead_of_overflowing() {
+        let backoff = Backoff::new(Duration::from_secs(1), Duration::MAX);
+        assert_eq!(backoff.base_delay(0), Duration::from_secs(1));
+        assert_eq!(backoff.base_delay(200), Duration::MAX);
+        assert_eq!(backoff.base_delay(u32::MAX), Duration::MAX);
+    }
+
+    #[test]
+    fn jitter_stays_in_upper_half_and_follows_env() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(10)).jittered();
+        let env = FixedRng::default();
+
+        assert_eq!(backoff.delay(3, &env), Duration::from_millis(200));
+
+        env.0.store(0xff, Ordering::Relaxed);
+        let high = backoff.delay(3, &env);
+        assert!(high >= Duration::from_millis(200) && high <= Duration::from_millis(400));
+        assert_eq!(high, backoff.delay(3, &env));
+
+        env.0.store(0x5a, Ordering::Relaxed);
+        assert_ne!(backoff.delay(3, &env), high);
+    }
+}
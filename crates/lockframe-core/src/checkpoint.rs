@@ -0,0 +1,122 @@
+//! Signed log checkpoints.
+//!
+//! The server folds every sequenced frame of a room into a rolling SHA-256
+//! hash: `hash[n] = SHA-256(hash[n-1] || header[n] || payload[n])`, starting
+//! from [`LogHash::GENESIS`]. Periodically it signs `(room_id, log_index,
+//! hash)` and sequences the result as a [`Checkpoint`] frame. A client holding
+//! the server's checkpoint key can then check that the log it replayed matches
+//! what the server committed to.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use lockframe_proto::{Frame, payloads::session::Checkpoint};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Rolling hash over a room's log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LogHash([u8; 32]);
+
+impl LogHash {
+    /// Hash of the empty log.
+    pub const GENESIS: Self = Self([0; 32]);
+
+    /// Wrap raw hash bytes (e.g. from a [`Checkpoint`]).
+    #[must_use]
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Raw hash bytes.
+    #[must_use]
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Extend the hash with the next sequenced frame.
+    ///
+    /// The frame must carry its assigned `log_index`; the header is hashed as
+    /// sent on the wire.
+    #[must_use]
+    pub fn chain(&self, frame: &Frame) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(self.0);
+        hasher.update(frame.header.to_bytes());
+        hasher.update(&frame.payload);
+        Self(hasher.finalize().into())
+    }
+}
+
+impl Default for LogHash {
+    fn default() -> Self {
+        Self::GENESIS
+    }
+}
+
+/// Sign a checkpoint covering a room's log through `log_index`.
+#[must_use]
+pub fn sign_checkpoint(
+    key: &SigningKey,
+    room_id: u128,
+    log_index: u64,
+    log_hash: LogHash,
+) -> Checkpoint {
+    let data = Checkpoint::signing_data(room_id, log_index, log_hash.as_bytes());
+    let signature = key.sign(&data);
+
+    Checkpoint { log_index, log_hash: *log_hash.as_bytes(), signature: signature.to_vec() }
+}
+
+/// Verify a checkpoint's signature for `room_id`.
+///
+/// Only proves the server signed it; comparing `log_hash` against a locally
+/// computed [`LogHash`] is up to the caller.
+#[must_use]
+pub fn verify_checkpoint(key: &VerifyingKey, room_id: u128, checkpoint: &Checkpoint) -> bool {
+    let Ok(signature) = Signature::from_slice(&checkpoint.signature) else {
+        return false;
+    };
+
+    let data = Checkpoint::signing_data(room_id, checkpoint.log_index, &checkpoint.log_hash);
+    key.verify(&data, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+
+    fn frame(log_index: u64, body: &'static [u8]) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_log_index(log_index);
+        Frame::new(header, body)
+    }
+
+    #[test]
+    fn chain_depends_on_order_and_content() {
+        let a = LogHash::GENESIS.chain(&frame(0, b"a")).chain(&frame(1, b"b"));
+        let b = LogHash::GENESIS.chain(&frame(0, b"b")).chain(&frame(1, b"a"));
+        let c = LogHash::GENESIS.chain(&frame(0, b"a")).chain(&frame(1, b"b"));
+
+        assert_ne!(a, b);
+        assert_eq!(a, c);
+        assert_ne!(a, LogHash::GENESIS);
+    }
+
+    #[test]
+    fn checkpoint_signature_binds_room_and_hash() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let hash = LogHash::GENESIS.chain(&frame(0, b"a"));
+        let checkpoint = sign_checkpoint(&key, 42, 0, hash);
+
+        assert!(verify_checkpoint(&key.verifying_key(), 42, &checkpoint));
+        assert!(!verify_checkpoint(&key.verifying_key(), 43, &checkpoint));
+
+        let mut tampered = checkpoint.clone();
+        tampered.log_hash[0] ^= 1;
+        assert!(!verify_checkpoint(&key.verifying_key(), 42, &tampered));
+
+        let other = SigningKey::from_bytes(&[8; 32]);
+        assert!(!verify_checkpoint(&other.verifying_key(), 42, &checkpoint));
+    }
+}
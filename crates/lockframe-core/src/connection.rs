@@ -18,18 +18,41 @@
 //!                   │ Closed │<─────────────────────│ Closed │
 //!                   └────────┘                      └────────┘
 //! ```
+//!
+//! A server configured with [`ConnectionConfig::require_identity`] challenges
+//! the client in its `HelloReply` and waits until the client signs the
+//! challenge with its identity key ([`ConnectionConfig::identity_key`]):
+//!
+//! ```text
+//! ┌──────┐  Hello   ┌────────────┐  ChallengeResponse  ┌───────────────┐
+//! │ Init │─────────>│ Challenged │────────────────────>│ Authenticated │
+//! └──────┘          └────────────┘                     └───────────────┘
+//!                         │
+//!                         │ Bad signature/Timeout
+//!                         ↓
+//!                    ┌────────┐
+//!                    │ Closed │
+//!                    └────────┘
+//! ```
 
 use std::{
     ops::Sub,
     time::{Duration, Instant},
 };
 
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 use lockframe_proto::{
-    Frame, FrameHeader, Opcode, Payload,
-    payloads::session::{Goodbye, Hello, HelloReply},
+    Capabilities, Frame, FrameHeader, Opcode, Payload,
+    payloads::{
+        ErrorPayload,
+        session::{ChallengeResponse, Goodbye, Hello, HelloReply, TimeSync},
+    },
 };
 
-use crate::error::ConnectionError;
+use crate::{
+    error::ConnectionError,
+    rtt::{HeartbeatTracker, RttEstimator},
+};
 
 /// Time allowed to complete the Hello/HelloReply handshake.
 pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
@@ -37,9 +60,15 @@ pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
 /// Maximum time allowed without any activity before the connection is closed.
 pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
-/// Interval at which the connection sends Ping frames while authenticated.
+/// Interval at which the connection sends Heartbeat frames while authenticated.
 pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
 
+/// Length of the random challenge a server sends in `HelloReply`.
+pub const CHALLENGE_LEN: usize = 32;
+
+/// Domain separation prefix for challenge signatures.
+const CHALLENGE_CONTEXT: &[u8] = b"lockframe-challenge-v1";
+
 /// Actions returned by the connection state machine.
 ///
 /// The driver (test harness or production server) executes these actions:
@@ -64,6 +93,9 @@ pub enum ConnectionState {
     Init,
     /// Hello sent, waiting for HelloReply
     Pending,
+    /// HelloReply with a challenge sent, waiting for ChallengeResponse
+    /// (server only)
+    Challenged,
     /// HelloReply received, connection authenticated
     Authenticated,
     /// Connection closed (graceful or error)
@@ -79,6 +111,17 @@ pub struct ConnectionConfig {
     pub idle_timeout: Duration,
     /// Heartbeat interval (should be < idle_timeout / 2)
     pub heartbeat_interval: Duration,
+    /// Capabilities offered in the handshake. The session uses the
+    /// intersection with the peer's.
+    pub capabilities: Capabilities,
+    /// Key the client signs server challenges with. A client without one
+    /// closes the connection when challenged.
+    pub identity_key: Option<SigningKey>,
+    /// Token the client sends in its Hello, for servers that want one
+    pub auth_token: Option<Vec<u8>>,
+    /// Challenge clients to prove their identity key before authenticating
+    /// them (server use)
+    pub require_identity: bool,
 }
 
 impl Default for ConnectionConfig {
@@ -87,6 +130,10 @@ impl Default for ConnectionConfig {
             handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
             idle_timeout: DEFAULT_IDLE_TIMEOUT,
             heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            capabilities: Capabilities::all(),
+            identity_key: None,
+            auth_token: None,
+            require_identity: false,
         }
     }
 }
@@ -113,8 +160,21 @@ where
     last_activity: I,
     /// Last heartbeat sent timestamp
     last_heartbeat: Option<I>,
+    /// Outstanding heartbeat and RTT estimate
+    heartbeats: HeartbeatTracker<I>,
     /// Session ID (assigned by server)
     session_id: Option<u64>,
+    /// Server clock: advertised in `HelloReply` (server) or last received
+    /// (client)
+    time_sync: Option<TimeSync>,
+    /// Capabilities negotiated during the handshake
+    capabilities: Capabilities,
+    /// Protocol version negotiated during the handshake
+    version: Option<u8>,
+    /// Challenge sent in `HelloReply` (server)
+    challenge: Option<Vec<u8>>,
+    /// Identity key the client proved it holds (server)
+    peer_identity: Option<VerifyingKey>,
 }
 
 impl<I> Connection<I>
@@ -128,7 +188,13 @@ where
             config,
             last_activity: now,
             last_heartbeat: None,
+            heartbeats: HeartbeatTracker::new(now),
             session_id: None,
+            time_sync: None,
+            capabilities: Capabilities::empty(),
+            version: None,
+            challenge: None,
+            peer_identity: None,
         }
     }
 
@@ -144,6 +210,12 @@ where
         self.session_id
     }
 
+    /// Round-trip time estimate sampled from heartbeat acks.
+    #[must_use]
+    pub fn rtt(&self) -> &RttEstimator {
+        self.heartbeats.rtt()
+    }
+
     /// Maximum time allowed for handshake completion.
     #[must_use]
     pub fn handshake_timeout(&self) -> Duration {
@@ -159,6 +231,51 @@ where
         self.session_id = Some(session_id);
     }
 
+    /// Capabilities negotiated with the peer. Empty until the handshake
+    /// completes.
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Protocol version negotiated with the peer. `None` until the handshake
+    /// completes.
+    #[must_use]
+    pub fn version(&self) -> Option<u8> {
+        self.version
+    }
+
+    /// Latest server clock reading. On the client this is the one carried by
+    /// `HelloReply` or the most recent `TimeSync` frame.
+    #[must_use]
+    pub fn time_sync(&self) -> Option<TimeSync> {
+        self.time_sync
+    }
+
+    /// Set the server clock reading to advertise in `HelloReply` (server use,
+    /// before handling Hello).
+    pub fn set_time_sync(&mut self, time_sync: TimeSync) {
+        self.time_sync = Some(time_sync);
+    }
+
+    /// Draw the challenge to send in `HelloReply` (server use, before
+    /// handling Hello). Does nothing unless
+    /// [`ConnectionConfig::require_identity`] is set.
+    pub fn issue_challenge<E: crate::env::Environment>(&mut self, env: &E) {
+        if self.config.require_identity {
+            let mut challenge = vec![0; CHALLENGE_LEN];
+            env.random_bytes(&mut challenge);
+            self.challenge = Some(challenge);
+        }
+    }
+
+    /// Identity key the client proved it holds. `None` unless the server
+    /// requires identity and the client answered its challenge.
+    #[must_use]
+    pub fn peer_identity(&self) -> Option<&VerifyingKey> {
+        self.peer_identity.as_ref()
+    }
+
     /// Initiate handshake (client use).
     ///
     /// Transitions to Pending state and returns SendFrame(Hello) action.
@@ -177,20 +294,29 @@ where
         self.state = ConnectionState::Pending;
         self.last_activity = now;
 
-        let hello = Payload::Hello(Hello { version: 1, capabilities: vec![], auth_token: None });
-        let frame = hello.into_frame(FrameHeader::new(Opcode::Hello))?;
+        // The header carries the oldest version we speak and the payload the
+        // newest, so the server can pick one both sides understand
+        let hello = Payload::Hello(Hello {
+            version: FrameHeader::VERSION,
+            capabilities: self.config.capabilities.to_names(),
+            auth_token: self.config.auth_token.clone(),
+        });
+        let mut header = FrameHeader::new(Opcode::Hello);
+        header.set_version(FrameHeader::MIN_VERSION);
+        let frame = hello.into_frame(header)?;
 
         Ok(vec![ConnectionAction::SendFrame(frame)])
     }
 
     /// Process incoming Hello and generate session ID (server use).
     ///
-    /// Transitions to Authenticated and returns SendFrame(HelloReply).
+    /// Transitions to Authenticated and returns SendFrame(HelloReply). Without
+    /// its frame header a Hello offers only `hello.version`; if we don't speak
+    /// it, returns an `UNSUPPORTED_VERSION` error frame and closes.
     ///
     /// # Errors
     ///
     /// - `ConnectionError::InvalidState` if not in Init state
-    /// - `ConnectionError::UnsupportedVersion` if version ≠ 1
     pub fn handle_hello<E: crate::env::Environment>(
         &mut self,
         hello: &Hello,
@@ -204,23 +330,17 @@ where
             });
         }
 
-        if hello.version != 1 {
-            return Err(ConnectionError::UnsupportedVersion(hello.version));
-        }
+        let Some(version) = FrameHeader::negotiate_version(hello.version, hello.version) else {
+            return self.reject_version(hello.version, hello.version);
+        };
 
         let session_id = env.random_u64();
         debug_assert_ne!(session_id, 0);
 
+        self.issue_challenge(env);
         self.session_id = Some(session_id);
-        self.state = ConnectionState::Authenticated;
         self.last_activity = now;
-
-        let reply =
-            Payload::HelloReply(HelloReply { session_id, capabilities: vec![], challenge: None });
-
-        let frame = reply.into_frame(FrameHeader::new(Opcode::HelloReply))?;
-
-        Ok(vec![ConnectionAction::SendFrame(frame)])
+        self.reply_to_hello(session_id, hello, version)
     }
 
     /// Mark connection as closed.
@@ -239,7 +359,7 @@ where
         let elapsed = now - self.last_activity;
 
         let timeout = match self.state {
-            ConnectionState::Pending => self.config.handshake_timeout,
+            ConnectionState::Pending | ConnectionState::Challenged => self.config.handshake_timeout,
             ConnectionState::Authenticated => self.config.idle_timeout,
             _ => return None,
         };
@@ -257,7 +377,9 @@ where
         // Check for timeout
         if let Some(elapsed) = self.check_timeout(now) {
             let reason = match self.state {
-                ConnectionState::Pending => format!("handshake timeout after {:?}", elapsed),
+                ConnectionState::Pending | ConnectionState::Challenged => {
+                    format!("handshake timeout after {:?}", elapsed)
+                },
                 ConnectionState::Authenticated => format!("idle timeout after {:?}", elapsed),
                 _ => "timeout".to_string(),
             };
@@ -277,10 +399,12 @@ where
             };
 
             if should_send {
-                let ping_header = FrameHeader::new(lockframe_proto::Opcode::Ping);
-                let ping_frame = Frame::new(ping_header, Vec::new());
+                // Encoding a bare timestamp cannot fail
+                let heartbeat = Payload::Heartbeat(self.heartbeats.start(now));
+                if let Ok(frame) = heartbeat.into_frame(self.header(Opcode::Heartbeat)) {
+                    actions.push(ConnectionAction::SendFrame(frame));
+                }
 
-                actions.push(ConnectionAction::SendFrame(ping_frame));
                 self.last_heartbeat = Some(now);
                 self.last_activity = now;
             }
@@ -295,7 +419,8 @@ where
     ///
     /// - `ConnectionError::UnexpectedFrame` if opcode invalid for current state
     /// - `ConnectionError::InvalidPayload` if CBOR deserialization fails
-    /// - `ConnectionError::UnsupportedVersion` if Hello version ≠ 1
+    /// - `ConnectionError::UnsupportedVersion` if `HelloReply` picks a version
+    ///   we don't speak
     /// - `ConnectionError::Protocol` if server session_id not set
     pub fn handle_frame(
         &mut self,
@@ -318,9 +443,11 @@ where
 
                 match payload {
                     Payload::Hello(hello) => {
-                        if hello.version != 1 {
-                            return Err(ConnectionError::UnsupportedVersion(hello.version));
-                        }
+                        let oldest = frame.header.version();
+                        let Some(version) = FrameHeader::negotiate_version(oldest, hello.version)
+                        else {
+                            return self.reject_version(oldest, hello.version);
+                        };
 
                         let Some(session_id) = self.session_id else {
                             return Err(ConnectionError::Protocol(
@@ -330,17 +457,7 @@ where
 
                         debug_assert_ne!(session_id, 0);
 
-                        self.state = ConnectionState::Authenticated;
-
-                        let reply = Payload::HelloReply(HelloReply {
-                            session_id,
-                            capabilities: vec![],
-                            challenge: None,
-                        });
-
-                        let frame = reply.into_frame(FrameHeader::new(Opcode::HelloReply))?;
-
-                        Ok(vec![ConnectionAction::SendFrame(frame)])
+                        self.reply_to_hello(session_id, &hello, version)
                     },
                     _ => Err(ConnectionError::InvalidPayload {
                         expected: "Hello",
@@ -355,9 +472,21 @@ where
 
                 match payload {
                     Payload::HelloReply(reply) => {
-                        self.state = ConnectionState::Authenticated;
+                        // The server answers in the version it picked
+                        let version = frame.header.version();
+                        if !FrameHeader::is_supported_version(version) {
+                            return Err(ConnectionError::UnsupportedVersion(version));
+                        }
+                        self.version = Some(version);
                         self.session_id = Some(reply.session_id);
+                        self.time_sync = reply.time_sync.or(self.time_sync);
+                        self.capabilities = self.negotiate(&reply.capabilities);
+
+                        if let Some(challenge) = &reply.challenge {
+                            return self.answer_challenge(reply.session_id, challenge);
+                        }
 
+                        self.state = ConnectionState::Authenticated;
                         Ok(vec![]) // No response needed
                     },
                     _ => Err(ConnectionError::InvalidPayload {
@@ -367,9 +496,14 @@ where
                 }
             },
 
+            // Server: client proves its identity key
+            (ConnectionState::Challenged, Opcode::ChallengeResponse) => {
+                self.handle_challenge_response(frame)
+            },
+
             // Both: Ping when Authenticated
             (ConnectionState::Authenticated, Opcode::Ping) => {
-                let pong_header = FrameHeader::new(Opcode::Pong);
+                let pong_header = self.header(Opcode::Pong);
                 let pong_frame = Frame::new(pong_header, Vec::new());
                 Ok(vec![ConnectionAction::SendFrame(pong_frame)])
             },
@@ -380,6 +514,14 @@ where
                 Ok(vec![])
             },
 
+            // Both: Heartbeat and its ack when Authenticated
+            (ConnectionState::Authenticated, Opcode::Heartbeat | Opcode::HeartbeatAck) => {
+                self.handle_heartbeat(frame, now)
+            },
+
+            // Client: periodic server clock refresh
+            (ConnectionState::Authenticated, Opcode::TimeSync) => self.handle_time_sync(frame),
+
             // Both: Goodbye (any state except Closed)
             (state, Opcode::Goodbye) if state != ConnectionState::Closed => {
                 let payload = Payload::from_frame(frame.clone())?;
@@ -397,7 +539,7 @@ where
                 self.state = ConnectionState::Closed;
 
                 let reply = Payload::Goodbye(Goodbye { reason: "ack".to_string() });
-                let frame = reply.into_frame(FrameHeader::new(Opcode::Goodbye))?;
+                let frame = reply.into_frame(self.header(Opcode::Goodbye))?;
 
                 Ok(vec![ConnectionAction::SendFrame(frame), ConnectionAction::Close {
                     reason: format!("peer goodbye: {}", reason),
@@ -417,6 +559,186 @@ where
             },
         }
     }
+
+    /// Build the `HelloReply` for an accepted Hello, in the negotiated
+    /// `version` and with a challenge if identity is required.
+    fn reply_to_hello(
+        &mut self,
+        session_id: u64,
+        hello: &Hello,
+        version: u8,
+    ) -> Result<Vec<ConnectionAction>, ConnectionError> {
+        let challenge = if self.config.require_identity {
+            let Some(challenge) = self.challenge.clone() else {
+                return Err(ConnectionError::Protocol(
+                    "server must set a challenge before handling Hello".to_string(),
+                ));
+            };
+            self.state = ConnectionState::Challenged;
+            Some(challenge)
+        } else {
+            self.state = ConnectionState::Authenticated;
+            None
+        };
+        self.capabilities = self.negotiate(&hello.capabilities);
+        self.version = Some(version);
+
+        let reply = Payload::HelloReply(HelloReply {
+            session_id,
+            capabilities: self.capabilities.to_names(),
+            challenge,
+            time_sync: self.time_sync,
+        });
+
+        let frame = reply.into_frame(self.header(Opcode::HelloReply))?;
+
+        Ok(vec![ConnectionAction::SendFrame(frame)])
+    }
+
+    /// Refuse a Hello offering no version we speak: answer with an error in
+    /// the client's oldest version, which it can decode, and close.
+    fn reject_version(
+        &mut self,
+        oldest: u8,
+        newest: u8,
+    ) -> Result<Vec<ConnectionAction>, ConnectionError> {
+        self.state = ConnectionState::Closed;
+
+        let error = ErrorPayload::unsupported_version(oldest, newest);
+        let reason = error.message.clone();
+        let mut header = FrameHeader::new(Opcode::Error);
+        header.set_version(oldest);
+        let frame = Payload::Error(error).into_frame(header)?;
+
+        Ok(vec![ConnectionAction::SendFrame(frame), ConnectionAction::Close { reason }])
+    }
+
+    /// Header for a frame we send, in the negotiated version.
+    fn header(&self, opcode: Opcode) -> FrameHeader {
+        let mut header = FrameHeader::new(opcode);
+        if let Some(version) = self.version {
+            header.set_version(version);
+        }
+        header
+    }
+
+    /// Sign a server challenge with our identity key.
+    fn answer_challenge(
+        &mut self,
+        session_id: u64,
+        challenge: &[u8],
+    ) -> Result<Vec<ConnectionAction>, ConnectionError> {
+        let Some(key) = &self.config.identity_key else {
+            self.state = ConnectionState::Closed;
+            return Ok(vec![ConnectionAction::Close {
+                reason: "server requires an identity key".to_string(),
+            }]);
+        };
+
+        let signature = key.sign(&challenge_message(session_id, challenge));
+        let response = Payload::ChallengeResponse(ChallengeResponse {
+            identity_key: key.verifying_key().to_bytes().to_vec(),
+            signature: signature.to_bytes().to_vec(),
+        });
+        let frame = response.into_frame(self.header(Opcode::ChallengeResponse))?;
+        self.state = ConnectionState::Authenticated;
+
+        Ok(vec![ConnectionAction::SendFrame(frame)])
+    }
+
+    /// Check the client's signature over our challenge.
+    fn handle_challenge_response(
+        &mut self,
+        frame: &Frame,
+    ) -> Result<Vec<ConnectionAction>, ConnectionError> {
+        let Payload::ChallengeResponse(response) = Payload::from_frame(frame.clone())? else {
+            return Err(ConnectionError::InvalidPayload {
+                expected: "ChallengeResponse",
+                opcode: Opcode::ChallengeResponse.to_u16(),
+            });
+        };
+
+        let verified = match (self.session_id, &self.challenge) {
+            (Some(session_id), Some(challenge)) => {
+                verify_challenge(session_id, challenge, &response)
+            },
+            _ => None,
+        };
+        let Some(identity) = verified else {
+            self.state = ConnectionState::Closed;
+            return Ok(vec![ConnectionAction::Close {
+                reason: "challenge response has a bad signature".to_string(),
+            }]);
+        };
+
+        self.peer_identity = Some(identity);
+        self.challenge = None;
+        self.state = ConnectionState::Authenticated;
+        Ok(vec![])
+    }
+
+    /// Intersect the peer's advertised capabilities with our own.
+    fn negotiate(&self, peer: &[String]) -> Capabilities {
+        self.config.capabilities & Capabilities::from_names(peer)
+    }
+
+    /// Record a refreshed server clock reading.
+    fn handle_time_sync(
+        &mut self,
+        frame: &Frame,
+    ) -> Result<Vec<ConnectionAction>, ConnectionError> {
+        match Payload::from_frame(frame.clone())? {
+            Payload::TimeSync(time_sync) => {
+                self.time_sync = Some(time_sync);
+                Ok(vec![])
+            },
+            _ => Err(ConnectionError::InvalidPayload {
+                expected: "TimeSync",
+                opcode: Opcode::TimeSync.to_u16(),
+            }),
+        }
+    }
+
+    /// Echo a peer heartbeat, or sample RTT from the ack of our own.
+    fn handle_heartbeat(
+        &mut self,
+        frame: &Frame,
+        now: I,
+    ) -> Result<Vec<ConnectionAction>, ConnectionError> {
+        match Payload::from_frame(frame.clone())? {
+            Payload::Heartbeat(heartbeat) => {
+                let ack = Payload::HeartbeatAck(heartbeat)
+                    .into_frame(self.header(Opcode::HeartbeatAck))?;
+                Ok(vec![ConnectionAction::SendFrame(ack)])
+            },
+            Payload::HeartbeatAck(ack) => {
+                self.heartbeats.on_ack(&ack, now);
+                Ok(vec![])
+            },
+            _ => Err(ConnectionError::InvalidPayload {
+                expected: "Heartbeat",
+                opcode: frame.header.opcode(),
+            }),
+        }
+    }
+}
+
+/// Bytes a client signs to answer `challenge` on `session_id`.
+fn challenge_message(session_id: u64, challenge: &[u8]) -> Vec<u8> {
+    [CHALLENGE_CONTEXT, &session_id.to_be_bytes(), challenge].concat()
+}
+
+/// Identity key of a valid response to `challenge`, `None` if the key or
+/// signature is malformed or does not verify.
+fn verify_challenge(
+    session_id: u64,
+    challenge: &[u8],
+    response: &ChallengeResponse,
+) -> Option<VerifyingKey> {
+    let key = VerifyingKey::from_bytes(response.identity_key.as_slice().try_into().ok()?).ok()?;
+    let signature = Signature::from_slice(&response.signature).ok()?;
+    key.verify_strict(&challenge_message(session_id, challenge), &signature).ok()?;
+    Some(key)
 }
 
 #[cfg(test)]
@@ -465,6 +787,7 @@ mod tests {
             session_id: 12345,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let reply_frame = reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         let actions = conn.handle_frame(&reply_frame, t0).unwrap();
@@ -489,6 +812,7 @@ mod tests {
             session_id: 12345,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let reply_frame = reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         conn.handle_frame(&reply_frame, t0).unwrap();
@@ -522,6 +846,7 @@ mod tests {
             session_id: 12345,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let reply_frame = reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         conn.handle_frame(&reply_frame, t0).unwrap();
@@ -540,6 +865,97 @@ mod tests {
         assert!(conn.check_timeout(t2).is_none());
     }
 
+    #[test]
+    fn heartbeat_round_trip_samples_rtt() {
+        let env = TestEnv;
+        let t0 = env.now();
+        let mut client = Connection::new(t0, ConnectionConfig::default());
+        let mut server = Connection::new(t0, ConnectionConfig::default());
+
+        // Move both sides to authenticated
+        server.set_session_id(12345);
+        let hello = client.send_hello(t0).unwrap();
+        let ConnectionAction::SendFrame(hello) = &hello[0] else { panic!("expected Hello") };
+        let reply = server.handle_frame(hello, t0).unwrap();
+        let ConnectionAction::SendFrame(reply) = &reply[0] else { panic!("expected HelloReply") };
+        client.handle_frame(reply, t0).unwrap();
+
+        // Client tick emits a timestamped heartbeat
+        let t1 = t0 + Duration::from_secs(1);
+        let actions = client.tick(t1);
+        let [ConnectionAction::SendFrame(heartbeat)] = actions.as_slice() else {
+            panic!("expected a single heartbeat, got {actions:?}");
+        };
+        assert_eq!(heartbeat.header.opcode_enum(), Some(Opcode::Heartbeat));
+
+        // Server echoes it unchanged
+        let actions = server.handle_frame(heartbeat, t1).unwrap();
+        let [ConnectionAction::SendFrame(ack)] = actions.as_slice() else {
+            panic!("expected HeartbeatAck, got {actions:?}");
+        };
+        assert_eq!(ack.header.opcode_enum(), Some(Opcode::HeartbeatAck));
+        assert_eq!(ack.payload, heartbeat.payload);
+
+        // Client samples RTT from its own send time
+        assert_eq!(client.rtt().smoothed(), None);
+        let actions = client.handle_frame(ack, t1 + Duration::from_millis(80)).unwrap();
+        assert!(actions.is_empty());
+        assert_eq!(client.rtt().latest(), Some(Duration::from_millis(80)));
+        assert_eq!(client.rtt().sample_count(), 1);
+    }
+
+    #[test]
+    fn time_sync_carried_in_hello_reply_and_refreshed() {
+        let env = TestEnv;
+        let t0 = env.now();
+        let mut client = Connection::new(t0, ConnectionConfig::default());
+        let mut server = Connection::new(t0, ConnectionConfig::default());
+
+        let initial = TimeSync { wall_clock_millis: 1_000, hlc: 1_000 << 16 };
+        server.set_session_id(12345);
+        server.set_time_sync(initial);
+
+        let hello = client.send_hello(t0).unwrap();
+        let ConnectionAction::SendFrame(hello) = &hello[0] else { panic!("expected Hello") };
+        let reply = server.handle_frame(hello, t0).unwrap();
+        let ConnectionAction::SendFrame(reply) = &reply[0] else { panic!("expected HelloReply") };
+        client.handle_frame(reply, t0).unwrap();
+        assert_eq!(client.time_sync(), Some(initial));
+
+        let refreshed = TimeSync { wall_clock_millis: 2_000, hlc: 2_000 << 16 };
+        let frame =
+            Payload::TimeSync(refreshed).into_frame(FrameHeader::new(Opcode::TimeSync)).unwrap();
+        let actions = client.handle_frame(&frame, t0).unwrap();
+        assert!(actions.is_empty());
+        assert_eq!(client.time_sync(), Some(refreshed));
+    }
+
+    #[test]
+    fn handshake_negotiates_capability_intersection() {
+        let env = TestEnv;
+        let t0 = env.now();
+        let client_config = ConnectionConfig {
+            capabilities: Capabilities::RECEIPTS | Capabilities::DATAGRAMS,
+            ..ConnectionConfig::default()
+        };
+        let server_config = ConnectionConfig {
+            capabilities: Capabilities::RECEIPTS | Capabilities::COMPRESSION,
+            ..ConnectionConfig::default()
+        };
+        let mut client = Connection::new(t0, client_config);
+        let mut server = Connection::new(t0, server_config);
+        server.set_session_id(12345);
+
+        let hello = client.send_hello(t0).unwrap();
+        let ConnectionAction::SendFrame(hello) = &hello[0] else { panic!("expected Hello") };
+        let reply = server.handle_frame(hello, t0).unwrap();
+        let ConnectionAction::SendFrame(reply) = &reply[0] else { panic!("expected HelloReply") };
+        client.handle_frame(reply, t0).unwrap();
+
+        assert_eq!(server.capabilities(), Capabilities::RECEIPTS);
+        assert_eq!(client.capabilities(), Capabilities::RECEIPTS);
+    }
+
     #[test]
     fn handle_ping_before_authenticated() {
         let env = TestEnv;
@@ -618,10 +1034,57 @@ mod tests {
             capabilities: vec![],
             auth_token: None,
         });
-        let hello_frame = hello.into_frame(FrameHeader::new(Opcode::Hello)).unwrap();
+        let mut header = FrameHeader::new(Opcode::Hello);
+        header.set_version(99); // Speaks nothing older either
+        let hello_frame = hello.into_frame(header).unwrap();
 
-        let result = conn.handle_frame(&hello_frame, t0);
-        assert!(matches!(result, Err(ConnectionError::UnsupportedVersion(99))));
+        let actions = conn.handle_frame(&hello_frame, t0).unwrap();
+        assert_unsupported_version(&actions);
+        assert_eq!(conn.state(), ConnectionState::Closed);
+    }
+
+    /// Assert `actions` refuse the Hello with an `UNSUPPORTED_VERSION` error.
+    fn assert_unsupported_version(actions: &[ConnectionAction]) {
+        let [ConnectionAction::SendFrame(frame), ConnectionAction::Close { .. }] = actions else {
+            panic!("expected an error frame and close, got {actions:?}");
+        };
+        match Payload::from_frame(frame.clone()).unwrap() {
+            Payload::Error(error) => assert_eq!(error.code, ErrorPayload::UNSUPPORTED_VERSION),
+            other => panic!("expected Error payload, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn version_is_negotiated_from_the_hello_range() {
+        let env = TestEnv;
+        let t0 = env.now();
+        let mut client = Connection::new(t0, ConnectionConfig::default());
+        let mut server = Connection::new(t0, ConnectionConfig::default());
+        server.set_session_id(12345);
+
+        let actions = client.send_hello(t0).unwrap();
+        let [ConnectionAction::SendFrame(hello)] = actions.as_slice() else {
+            panic!("expected Hello");
+        };
+        assert_eq!(hello.header.version(), FrameHeader::MIN_VERSION);
+
+        let actions = server.handle_frame(hello, t0).unwrap();
+        let [ConnectionAction::SendFrame(reply)] = actions.as_slice() else {
+            panic!("expected HelloReply");
+        };
+        assert_eq!(reply.header.version(), FrameHeader::VERSION);
+        assert_eq!(server.version(), Some(FrameHeader::VERSION));
+
+        client.handle_frame(reply, t0).unwrap();
+        assert_eq!(client.version(), Some(FrameHeader::VERSION));
+
+        // A client whose oldest version is newer than ours is refused
+        let mut server = Connection::new(t0, ConnectionConfig::default());
+        server.set_session_id(12345);
+        let mut too_new = hello.clone();
+        too_new.header.set_version(FrameHeader::VERSION + 1);
+        assert_unsupported_version(&server.handle_frame(&too_new, t0).unwrap());
+        assert_eq!(server.version(), None);
     }
 
     #[test]
@@ -666,8 +1129,9 @@ mod tests {
 
         let hello = Hello { version: 99, capabilities: vec![], auth_token: None };
 
-        let result = conn.handle_hello(&hello, &env, t0);
-        assert!(matches!(result, Err(ConnectionError::UnsupportedVersion(99))));
+        let actions = conn.handle_hello(&hello, &env, t0).unwrap();
+        assert_unsupported_version(&actions);
+        assert_eq!(conn.session_id(), None);
     }
 
     #[test]
@@ -682,6 +1146,7 @@ mod tests {
             session_id: 12345,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let reply_frame = reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         conn.handle_frame(&reply_frame, t0).unwrap();
@@ -706,6 +1171,7 @@ mod tests {
             session_id: 12345,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let reply_frame = reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         conn.handle_frame(&reply_frame, t0).unwrap();
@@ -753,6 +1219,7 @@ mod tests {
             session_id: 12345,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let reply_frame = reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         conn.handle_frame(&reply_frame, t0).unwrap();
@@ -766,4 +1233,73 @@ mod tests {
         assert_eq!(actions.len(), 1);
         assert!(matches!(actions[0], ConnectionAction::Close { .. }));
     }
+
+    fn challenged_pair(identity_key: Option<SigningKey>) -> (Connection, Connection, Frame) {
+        let t0 = TestEnv.now();
+        let client_config = ConnectionConfig { identity_key, ..ConnectionConfig::default() };
+        let server_config = ConnectionConfig { require_identity: true, ..Default::default() };
+        let mut client = Connection::new(t0, client_config);
+        let mut server = Connection::new(t0, server_config);
+        server.set_session_id(12345);
+        server.issue_challenge(&TestEnv);
+
+        let hello = client.send_hello(t0).unwrap();
+        let ConnectionAction::SendFrame(hello) = &hello[0] else { panic!("expected Hello") };
+        let reply = server.handle_frame(hello, t0).unwrap();
+        let ConnectionAction::SendFrame(reply) = &reply[0] else { panic!("expected HelloReply") };
+        assert_eq!(server.state(), ConnectionState::Challenged);
+        (client, server, reply.clone())
+    }
+
+    #[test]
+    fn challenge_response_proves_identity_key() {
+        let t0 = TestEnv.now();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let (mut client, mut server, reply) = challenged_pair(Some(key.clone()));
+
+        let actions = client.handle_frame(&reply, t0).unwrap();
+        let [ConnectionAction::SendFrame(response)] = actions.as_slice() else {
+            panic!("expected ChallengeResponse, got {actions:?}");
+        };
+        assert_eq!(client.state(), ConnectionState::Authenticated);
+
+        // Nothing but the proof is accepted while challenged
+        let ping = Frame::new(FrameHeader::new(Opcode::Ping), Vec::new());
+        assert!(server.handle_frame(&ping, t0).is_err());
+
+        assert!(server.handle_frame(response, t0).unwrap().is_empty());
+        assert_eq!(server.state(), ConnectionState::Authenticated);
+        assert_eq!(server.peer_identity(), Some(&key.verifying_key()));
+    }
+
+    #[test]
+    fn challenge_signed_by_another_key_closes_connection() {
+        let t0 = TestEnv.now();
+        let (_, mut server, _) = challenged_pair(None);
+
+        // Signed over a different challenge
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let signature = key.sign(&challenge_message(12345, &[0; CHALLENGE_LEN]));
+        let response = Payload::ChallengeResponse(ChallengeResponse {
+            identity_key: key.verifying_key().to_bytes().to_vec(),
+            signature: signature.to_bytes().to_vec(),
+        })
+        .into_frame(FrameHeader::new(Opcode::ChallengeResponse))
+        .unwrap();
+
+        let actions = server.handle_frame(&response, t0).unwrap();
+        assert!(matches!(actions.as_slice(), [ConnectionAction::Close { .. }]));
+        assert_eq!(server.state(), ConnectionState::Closed);
+        assert_eq!(server.peer_identity(), None);
+    }
+
+    #[test]
+    fn client_without_identity_key_gives_up_when_challenged() {
+        let t0 = TestEnv.now();
+        let (mut client, _, reply) = challenged_pair(None);
+
+        let actions = client.handle_frame(&reply, t0).unwrap();
+        assert!(matches!(actions.as_slice(), [ConnectionAction::Close { .. }]));
+        assert_eq!(client.state(), ConnectionState::Closed);
+    }
 }
@@ -4,7 +4,7 @@
 //! deterministic simulation with Turmoil (virtual clock, seeded RNG) and
 //! production use with real system resources.
 
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Abstract environment providing time, randomness, and async primitives.
 ///
@@ -26,6 +26,18 @@ pub trait Environment: Clone + Send + Sync + 'static {
     ///   calls.
     fn now(&self) -> Instant;
 
+    /// Current wall-clock time in Unix milliseconds.
+    ///
+    /// Unlike [`now`](Self::now) this may jump backwards (NTP adjustments), so
+    /// it is only used for display timestamps and seeding hybrid logical
+    /// clocks, never for timeouts. The default reads the system clock;
+    /// simulation environments override it to stay deterministic.
+    fn wall_clock_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX))
+    }
+
     /// Sleeps for the specified duration.
     ///
     /// This is the ONLY async method in the trait, and it should only be used
@@ -0,0 +1,154 @@
+//! Hybrid logical clock.
+//!
+//! An HLC timestamp pairs a physical wall-clock reading with a logical counter
+//! so that timestamps stay close to real time while still respecting
+//! causality: any event that observes another gets a strictly larger
+//! timestamp, even if the local wall clock lags behind.
+//!
+//! Timestamps pack into the `u64` carried in [`FrameHeader`]: the upper 48
+//! bits hold Unix milliseconds, the lower 16 bits the logical counter.
+//!
+//! [`FrameHeader`]: lockframe_proto::FrameHeader
+
+/// Number of bits reserved for the logical counter.
+const LOGICAL_BITS: u32 = 16;
+
+/// Largest physical component representable in 48 bits.
+const MAX_PHYSICAL_MILLIS: u64 = u64::MAX >> LOGICAL_BITS;
+
+/// Packed hybrid logical clock timestamp.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HlcTimestamp(u64);
+
+impl HlcTimestamp {
+    /// Build a timestamp from its components.
+    ///
+    /// `physical_millis` is truncated to 48 bits.
+    #[must_use]
+    pub const fn new(physical_millis: u64, logical: u16) -> Self {
+        Self(((physical_millis & MAX_PHYSICAL_MILLIS) << LOGICAL_BITS) | logical as u64)
+    }
+
+    /// Reinterpret a packed `u64` (e.g. from a frame header).
+    #[must_use]
+    pub const fn from_u64(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    /// Packed representation.
+    #[must_use]
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// Physical component in Unix milliseconds.
+    #[must_use]
+    pub const fn physical_millis(self) -> u64 {
+        self.0 >> LOGICAL_BITS
+    }
+
+    /// Logical counter component.
+    #[must_use]
+    pub const fn logical(self) -> u16 {
+        (self.0 & 0xFFFF) as u16
+    }
+
+    /// Next timestamp at the same physical time, carrying into the physical
+    /// component if the counter is exhausted.
+    const fn successor(self) -> Self {
+        match self.logical().checked_add(1) {
+            Some(logical) => Self::new(self.physical_millis(), logical),
+            None => Self::new(self.physical_millis().saturating_add(1), 0),
+        }
+    }
+}
+
+/// Hybrid logical clock state.
+///
+/// Wall-clock readings are passed in by the caller, keeping the clock pure.
+/// Every timestamp returned is strictly greater than any previously returned
+/// or observed.
+#[derive(Debug, Clone, Default)]
+pub struct HybridClock {
+    last: HlcTimestamp,
+}
+
+impl HybridClock {
+    /// Create a clock that has not issued any timestamps.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Latest timestamp issued or observed.
+    #[must_use]
+    pub fn last(&self) -> HlcTimestamp {
+        self.last
+    }
+
+    /// Timestamp a local or send event.
+    pub fn now(&mut self, wall_millis: u64) -> HlcTimestamp {
+        let wall = HlcTimestamp::new(wall_millis, 0);
+
+        self.last = if wall > self.last { wall } else { self.last.successor() };
+        self.last
+    }
+
+    /// Merge a timestamp received from a peer and timestamp the receive event.
+    pub fn observe(&mut self, remote: HlcTimestamp, wall_millis: u64) -> HlcTimestamp {
+        let wall = HlcTimestamp::new(wall_millis, 0);
+        let latest = self.last.max(remote);
+
+        self.last = if wall > latest { wall } else { latest.successor() };
+        self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packing_round_trip() {
+        let ts = HlcTimestamp::new(1_700_000_000_000, 42);
+        assert_eq!(ts.physical_millis(), 1_700_000_000_000);
+        assert_eq!(ts.logical(), 42);
+        assert_eq!(HlcTimestamp::from_u64(ts.as_u64()), ts);
+    }
+
+    #[test]
+    fn now_tracks_wall_clock_and_stays_monotonic() {
+        let mut clock = HybridClock::new();
+
+        assert_eq!(clock.now(1_000), HlcTimestamp::new(1_000, 0));
+        assert_eq!(clock.now(2_000), HlcTimestamp::new(2_000, 0));
+
+        // Wall clock stalls or steps backwards: logical counter advances
+        assert_eq!(clock.now(2_000), HlcTimestamp::new(2_000, 1));
+        assert_eq!(clock.now(1_500), HlcTimestamp::new(2_000, 2));
+    }
+
+    #[test]
+    fn observe_orders_after_remote() {
+        let mut clock = HybridClock::new();
+        clock.now(1_000);
+
+        // Remote is ahead of our wall clock
+        let remote = HlcTimestamp::new(5_000, 7);
+        let ts = clock.observe(remote, 1_100);
+        assert_eq!(ts, HlcTimestamp::new(5_000, 8));
+        assert!(clock.now(1_200) > remote);
+
+        // Wall clock catches up and takes over
+        assert_eq!(clock.observe(remote, 6_000), HlcTimestamp::new(6_000, 0));
+    }
+
+    #[test]
+    fn logical_overflow_carries() {
+        let mut clock = HybridClock::new();
+        let ts = clock.observe(HlcTimestamp::new(1_000, u16::MAX), 0);
+
+        assert_eq!(ts, HlcTimestamp::new(1_001, 0));
+        assert_eq!(clock.now(0), HlcTimestamp::new(1_001, 1));
+    }
+}
@@ -0,0 +1,161 @@
+//! Identifier allocation.
+//!
+//! Connection and session IDs are drawn from the
+//! [`Environment`](crate::env::Environment) RNG, so production IDs are
+//! unpredictable while simulation runs produce the same IDs for the same seed.
+//! The allocator tracks which IDs are live, never hands out zero or a live ID
+//! twice, and counts every allocation for auditing.
+//!
+//! Request IDs only need to be unique among a peer's outstanding requests,
+//! so they come from a counter.
+
+use std::collections::HashSet;
+
+use crate::env::Environment;
+
+/// Allocator for connection, session, and request IDs.
+#[derive(Debug, Clone, Default)]
+pub struct IdAllocator {
+    /// IDs handed out or reserved and not yet released
+    live: HashSet<u64>,
+    /// Last request ID handed out
+    last_request_id: u32,
+    /// Total IDs allocated or reserved
+    issued: u64,
+}
+
+impl IdAllocator {
+    /// Create an allocator with no live IDs.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate a fresh non-zero ID that is not currently live.
+    pub fn allocate<E: Environment>(&mut self, env: &E) -> u64 {
+        loop {
+            let id = env.random_u64();
+            if id != 0 && self.live.insert(id) {
+                self.issued = self.issued.saturating_add(1);
+                return id;
+            }
+        }
+    }
+
+    /// Mark an externally chosen ID as live.
+    ///
+    /// Returns `false` if the ID is zero or already live.
+    pub fn reserve(&mut self, id: u64) -> bool {
+        if id == 0 || !self.live.insert(id) {
+            return false;
+        }
+        self.issued = self.issued.saturating_add(1);
+        true
+    }
+
+    /// Release a live ID so it may be allocated again.
+    ///
+    /// Returns `false` if the ID was not live.
+    pub fn release(&mut self, id: u64) -> bool {
+        self.live.remove(&id)
+    }
+
+    /// Whether the ID is currently live.
+    #[must_use]
+    pub fn is_live(&self, id: u64) -> bool {
+        self.live.contains(&id)
+    }
+
+    /// Number of live IDs.
+    #[must_use]
+    pub fn live_count(&self) -> usize {
+        self.live.len()
+    }
+
+    /// Total IDs allocated or reserved since creation.
+    #[must_use]
+    pub fn issued(&self) -> u64 {
+        self.issued
+    }
+
+    /// Next request ID. Never zero, which marks frames that are not
+    /// requests; wraps after `u32::MAX`.
+    pub fn next_request_id(&mut self) -> u32 {
+        self.last_request_id = self.last_request_id.checked_add(1).unwrap_or(1);
+        self.last_request_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicU64, Ordering},
+        },
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    /// Environment whose RNG replays a fixed sequence.
+    #[derive(Clone)]
+    struct ScriptedEnv {
+        values: Arc<[u64]>,
+        next: Arc<AtomicU64>,
+    }
+
+    impl ScriptedEnv {
+        fn new(values: &[u64]) -> Self {
+            Self { values: values.into(), next: Arc::new(AtomicU64::new(0)) }
+        }
+    }
+
+    impl Environment for ScriptedEnv {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        async fn sleep(&self, _duration: Duration) {}
+
+        fn random_bytes(&self, buffer: &mut [u8]) {
+            let i = self.next.fetch_add(1, Ordering::Relaxed) as usize;
+            let value = self.values[i % self.values.len()];
+            buffer.copy_from_slice(&value.to_be_bytes()[..buffer.len()]);
+        }
+    }
+
+    #[test]
+    fn allocate_skips_zero_and_live_ids() {
+        let env = ScriptedEnv::new(&[7, 0, 7, 9]);
+        let mut ids = IdAllocator::new();
+
+        assert_eq!(ids.allocate(&env), 7);
+        assert_eq!(ids.allocate(&env), 9);
+        assert_eq!(ids.issued(), 2);
+
+        assert!(ids.release(7));
+        assert!(!ids.release(7));
+        assert_eq!(ids.allocate(&env), 7);
+    }
+
+    #[test]
+    fn reserve_rejects_duplicates() {
+        let mut ids = IdAllocator::new();
+
+        assert!(ids.reserve(42));
+        assert!(!ids.reserve(42));
+        assert!(!ids.reserve(0));
+        assert!(ids.is_live(42));
+        assert_eq!(ids.live_count(), 1);
+    }
+
+    #[test]
+    fn request_ids_skip_zero_on_wrap() {
+        let mut ids = IdAllocator::new();
+        assert_eq!(ids.next_request_id(), 1);
+
+        ids.last_request_id = u32::MAX;
+        assert_eq!(ids.next_request_id(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+//! Latency distributions.
+//!
+//! A fixed-size log-scale histogram: bucket `i` counts samples of up to
+//! `2^i` microseconds, so memory stays constant however many frames are
+//! measured, and quantiles are exact to within a factor of two. Both ends
+//! use it: the server for frame processing time, clients for end-to-end
+//! delivery latency.
+
+use std::time::Duration;
+
+/// Number of buckets. The last one also holds every sample of more than
+/// `2^(BUCKETS - 1)` microseconds (about 36 minutes).
+const BUCKETS: usize = 32;
+
+/// Log-scale latency histogram with count, sum, and maximum.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum_micros: u64,
+    max_micros: u64,
+}
+
+impl LatencyHistogram {
+    /// Create an empty histogram.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one sample.
+    pub fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let index =
+            usize::try_from(u64::BITS.saturating_sub(micros.saturating_sub(1).leading_zeros()))
+                .unwrap_or(BUCKETS)
+                .min(BUCKETS.saturating_sub(1));
+
+        if let Some(bucket) = self.buckets.get_mut(index) {
+            *bucket = bucket.saturating_add(1);
+        }
+        self.count = self.count.saturating_add(1);
+        self.sum_micros = self.sum_micros.saturating_add(micros);
+        self.max_micros = self.max_micros.max(micros);
+    }
+
+    /// Number of samples recorded.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Mean latency. `None` until the first sample.
+    #[must_use]
+    pub fn mean(&self) -> Option<Duration> {
+        self.sum_micros.checked_div(self.count).map(Duration::from_micros)
+    }
+
+    /// Largest sample. `None` until the first sample.
+    #[must_use]
+    pub fn max(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.max_micros))
+    }
+
+    /// Upper bound on the `percentile` (0-100) latency.
+    ///
+    /// Reports the upper edge of the bucket holding the sample, capped at the
+    /// maximum seen. `None` until the first sample.
+    #[must_use]
+    pub fn percentile(&self, percentile: u8) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        // Rank of the sample, 1-based, rounded up
+        let rank = self.count.saturating_mul(u64::from(percentile.min(100))).div_ceil(100).max(1);
+
+        let mut seen = 0u64;
+        let index = self.buckets.iter().position(|&bucket| {
+            seen = seen.saturating_add(bucket);
+            seen >= rank
+        })?;
+
+        let upper = 1u64.checked_shl(u32::try_from(index).ok()?).unwrap_or(u64::MAX);
+        Some(Duration::from_micros(upper.min(self.max_micros)))
+    }
+
+    /// Add every sample of `other` to this histogram.
+    pub fn merge(&mut self, other: &Self) {
+        for (bucket, theirs) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket = bucket.saturating_add(theirs);
+        }
+        self.count = self.count.saturating_add(other.count);
+        self.sum_micros = self.sum_micros.saturating_add(other.sum_micros);
+        self.max_micros = self.max_micros.max(other.max_micros);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_histogram_reports_nothing() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.mean(), None);
+        assert_eq!(histogram.max(), None);
+        assert_eq!(histogram.percentile(50), None);
+    }
+
+    #[test]
+    fn percentiles_are_bucket_upper_bounds() {
+        let mut histogram = LatencyHistogram::new();
+        for millis in 1..=100 {
+            histogram.record(Duration::from_millis(millis));
+        }
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.mean(), Some(Duration::from_micros(50_500)));
+        assert_eq!(histogram.max(), Some(Duration::from_millis(100)));
+
+        // The 50th sample is 50ms, in the bucket up to 2^16us
+        assert_eq!(histogram.percentile(50), Some(Duration::from_micros(65_536)));
+        // Capped at the maximum rather than the bucket edge
+        assert_eq!(histogram.percentile(99), Some(Duration::from_millis(100)));
+        assert_eq!(histogram.percentile(0), Some(Duration::from_micros(1_024)));
+    }
+
+    #[test]
+    fn merge_combines_samples() {
+        let mut a = LatencyHistogram::new();
+        let mut b = LatencyHistogram::new();
+        a.record(Duration::from_micros(3));
+        b.record(Duration::ZERO);
+        b.record(Duration::from_secs(u64::MAX));
+
+        a.merge(&b);
+        assert_eq!(a.count(), 3);
+        assert_eq!(a.percentile(1), Some(Duration::from_micros(1)));
+        assert_eq!(a.max(), Some(Duration::from_micros(u64::MAX)));
+    }
+}
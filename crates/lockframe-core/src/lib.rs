@@ -20,17 +20,31 @@
 //!
 //! # Components
 //!
+//! - [`backoff`]: Exponential backoff with optional jitter for retries
 //! - [`connection`]: Connection state machine (handshake, heartbeat, timeout)
 //! - [`mls`]: MLS group state machine (proposals, commits, messages)
 //! - [`env`]: Environment abstraction (time, RNG)
+//! - [`hlc`]: Hybrid logical clock timestamps
+//! - [`ids`]: Connection, session, and request ID allocation
+//! - [`latency`]: Latency histograms for frame delivery
+//! - [`rtt`]: Round-trip time estimation from heartbeats
+//! - [`timer`]: Timer wheel for retries and timeouts
 //! - [`transport`]: Transport abstraction (streams)
 //! - [`error`]: Connection error types
 
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
 
+pub mod backoff;
+pub mod checkpoint;
 pub mod connection;
 pub mod env;
 pub mod error;
+pub mod hlc;
+pub mod ids;
+pub mod latency;
+pub mod merkle;
 pub mod mls;
+pub mod rtt;
+pub mod timer;
 pub mod transport;
@@ -0,0 +1,383 @@
+//! Merkle tree over a room's log.
+//!
+//! Follows the RFC 6962 / RFC 9162 construction: leaves are
+//! `SHA-256(0x00 || frame)`, interior nodes `SHA-256(0x01 || left || right)`,
+//! and a tree of `n` leaves splits at the largest power of two below `n`.
+//!
+//! The server appends every sequenced frame to a [`MerkleLog`] and answers
+//! proof requests from it. Clients check the proofs with [`verify_inclusion`]
+//! (a frame is at a given position under a root) and [`verify_consistency`]
+//! (a later root extends an earlier one), so a server that forks or rewrites
+//! history is caught as soon as two views of the log are compared.
+
+use lockframe_proto::Frame;
+use sha2::{Digest, Sha256};
+
+/// Hash of a leaf, interior node, or tree root.
+pub type MerkleHash = [u8; 32];
+
+/// Domain separation prefix for leaf hashes.
+const LEAF_PREFIX: u8 = 0x00;
+
+/// Domain separation prefix for interior node hashes.
+const NODE_PREFIX: u8 = 0x01;
+
+/// Leaf hash for a sequenced frame, as sent on the wire.
+#[must_use]
+pub fn leaf_hash(frame: &Frame) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(frame.header.to_bytes());
+    hasher.update(&frame.payload);
+    hasher.finalize().into()
+}
+
+/// Root of the empty tree.
+#[must_use]
+pub fn empty_root() -> MerkleHash {
+    Sha256::digest([]).into()
+}
+
+fn node_hash(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Largest power of two strictly less than `n` (`n >= 2`).
+fn split_point(n: usize) -> usize {
+    debug_assert!(n >= 2);
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+/// Append-only Merkle tree over a room's log.
+///
+/// Keeps every leaf hash so proofs can be produced for any earlier tree size.
+/// The current root is maintained incrementally from the perfect subtrees on
+/// the right edge of the tree.
+#[derive(Debug, Clone, Default)]
+pub struct MerkleLog {
+    leaves: Vec<MerkleHash>,
+    /// Roots of the perfect subtrees making up the tree, largest first,
+    /// with their leaf counts.
+    frontier: Vec<(usize, MerkleHash)>,
+}
+
+impl MerkleLog {
+    /// Create an empty tree.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of leaves.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+
+    /// Whether the tree has no leaves.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Append the next sequenced frame.
+    pub fn push(&mut self, frame: &Frame) {
+        self.push_leaf(leaf_hash(frame));
+    }
+
+    /// Append a precomputed leaf hash.
+    pub fn push_leaf(&mut self, leaf: MerkleHash) {
+        self.leaves.push(leaf);
+
+        let mut carry = (1, leaf);
+        while let Some(&(size, hash)) = self.frontier.last() {
+            if size != carry.0 {
+                break;
+            }
+            self.frontier.pop();
+            carry = (size * 2, node_hash(&hash, &carry.1));
+        }
+        self.frontier.push(carry);
+    }
+
+    /// Root of the current tree.
+    #[must_use]
+    pub fn root(&self) -> MerkleHash {
+        let mut subtrees = self.frontier.iter().rev().map(|(_, hash)| *hash);
+        let Some(last) = subtrees.next() else {
+            return empty_root();
+        };
+        subtrees.fold(last, |acc, left| node_hash(&left, &acc))
+    }
+
+    /// Root of the tree as it was with `size` leaves.
+    ///
+    /// `None` if `size` exceeds the current size.
+    #[must_use]
+    pub fn root_at(&self, size: u64) -> Option<MerkleHash> {
+        let leaves = self.prefix(size)?;
+        Some(subtree_root(leaves))
+    }
+
+    /// Audit path proving leaf `index` is in the tree of `size` leaves.
+    ///
+    /// `None` unless `index < size <= len()`.
+    #[must_use]
+    pub fn inclusion_proof(&self, index: u64, size: u64) -> Option<Vec<MerkleHash>> {
+        let leaves = self.prefix(size)?;
+        let index = usize::try_from(index).ok().filter(|&i| i < leaves.len())?;
+
+        let mut proof = Vec::new();
+        audit_path(index, leaves, &mut proof);
+        Some(proof)
+    }
+
+    /// Proof that the tree of `old_size` leaves is a prefix of the tree of
+    /// `new_size` leaves.
+    ///
+    /// `None` unless `old_size <= new_size <= len()`. Empty when the sizes are
+    /// equal or `old_size` is zero.
+    #[must_use]
+    pub fn consistency_proof(&self, old_size: u64, new_size: u64) -> Option<Vec<MerkleHash>> {
+        let leaves = self.prefix(new_size)?;
+        let old = usize::try_from(old_size).ok().filter(|&old| old <= leaves.len())?;
+
+        let mut proof = Vec::new();
+        if old > 0 && old < leaves.len() {
+            subproof(old, leaves, true, &mut proof);
+        }
+        Some(proof)
+    }
+
+    fn prefix(&self, size: u64) -> Option<&[MerkleHash]> {
+        let size = usize::try_from(size).ok()?;
+        self.leaves.get(..size)
+    }
+}
+
+/// `MTH(D[n])` from RFC 6962.
+fn subtree_root(leaves: &[MerkleHash]) -> MerkleHash {
+    match leaves {
+        [] => empty_root(),
+        [leaf] => *leaf,
+        _ => {
+            let (left, right) = leaves.split_at(split_point(leaves.len()));
+            node_hash(&subtree_root(left), &subtree_root(right))
+        },
+    }
+}
+
+/// `PATH(m, D[n])` from RFC 6962, appended leaf-first.
+fn audit_path(index: usize, leaves: &[MerkleHash], proof: &mut Vec<MerkleHash>) {
+    if leaves.len() <= 1 {
+        return;
+    }
+
+    let (left, right) = leaves.split_at(split_point(leaves.len()));
+    if index < left.len() {
+        audit_path(index, left, proof);
+        proof.push(subtree_root(right));
+    } else {
+        audit_path(index - left.len(), right, proof);
+        proof.push(subtree_root(left));
+    }
+}
+
+/// `SUBPROOF(m, D[n], b)` from RFC 6962, appended bottom-up.
+fn subproof(old: usize, leaves: &[MerkleHash], complete: bool, proof: &mut Vec<MerkleHash>) {
+    if old == leaves.len() {
+        if !complete {
+            proof.push(subtree_root(leaves));
+        }
+        return;
+    }
+
+    let (left, right) = leaves.split_at(split_point(leaves.len()));
+    if old <= left.len() {
+        subproof(old, left, complete, proof);
+        proof.push(subtree_root(right));
+    } else {
+        subproof(old - left.len(), right, false, proof);
+        proof.push(subtree_root(left));
+    }
+}
+
+/// Check that `leaf` is at `index` in the tree of `size` leaves with `root`.
+#[must_use]
+pub fn verify_inclusion(
+    leaf: &MerkleHash,
+    index: u64,
+    size: u64,
+    proof: &[MerkleHash],
+    root: &MerkleHash,
+) -> bool {
+    if index >= size {
+        return false;
+    }
+
+    let mut node = index;
+    let mut last = size - 1;
+    let mut hash = *leaf;
+
+    for sibling in proof {
+        if last == 0 {
+            return false;
+        }
+
+        if node & 1 == 1 || node == last {
+            hash = node_hash(sibling, &hash);
+            while node & 1 == 0 && node != 0 {
+                node >>= 1;
+                last >>= 1;
+            }
+        } else {
+            hash = node_hash(&hash, sibling);
+        }
+
+        node >>= 1;
+        last >>= 1;
+    }
+
+    last == 0 && hash == *root
+}
+
+/// Check that the tree of `new_size` leaves with `new_root` extends the tree
+/// of `old_size` leaves with `old_root`.
+#[must_use]
+pub fn verify_consistency(
+    old_size: u64,
+    new_size: u64,
+    old_root: &MerkleHash,
+    new_root: &MerkleHash,
+    proof: &[MerkleHash],
+) -> bool {
+    if old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && old_root == new_root;
+    }
+    if old_size == 0 {
+        return proof.is_empty();
+    }
+
+    // A power-of-two old tree is a complete subtree and its root is implied
+    let mut nodes = Vec::with_capacity(proof.len() + 1);
+    if old_size.is_power_of_two() {
+        nodes.push(*old_root);
+    }
+    nodes.extend_from_slice(proof);
+
+    let Some((first, rest)) = nodes.split_first() else {
+        return false;
+    };
+
+    let mut node = old_size - 1;
+    let mut last = new_size - 1;
+    while node & 1 == 1 {
+        node >>= 1;
+        last >>= 1;
+    }
+
+    let mut old_hash = *first;
+    let mut new_hash = *first;
+
+    for sibling in rest {
+        if last == 0 {
+            return false;
+        }
+
+        if node & 1 == 1 || node == last {
+            old_hash = node_hash(sibling, &old_hash);
+            new_hash = node_hash(sibling, &new_hash);
+            while node & 1 == 0 && node != 0 {
+                node >>= 1;
+                last >>= 1;
+            }
+        } else {
+            new_hash = node_hash(&new_hash, sibling);
+        }
+
+        node >>= 1;
+        last >>= 1;
+    }
+
+    last == 0 && old_hash == *old_root && new_hash == *new_root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(size: u8) -> MerkleLog {
+        let mut log = MerkleLog::new();
+        for i in 0..size {
+            log.push_leaf(Sha256::digest([i]).into());
+        }
+        log
+    }
+
+    #[test]
+    fn incremental_root_matches_recursive() {
+        let log = log(33);
+        for size in 0..=log.len() {
+            let mut prefix = MerkleLog::new();
+            for leaf in &log.leaves[..size as usize] {
+                prefix.push_leaf(*leaf);
+            }
+            assert_eq!(Some(prefix.root()), log.root_at(size), "size {size}");
+        }
+        assert_eq!(MerkleLog::new().root(), empty_root());
+    }
+
+    #[test]
+    fn inclusion_proofs_verify() {
+        let log = log(17);
+        for size in 1..=log.len() {
+            let root = log.root_at(size).unwrap();
+            for index in 0..size {
+                let proof = log.inclusion_proof(index, size).unwrap();
+                let leaf = log.leaves[index as usize];
+                assert!(verify_inclusion(&leaf, index, size, &proof, &root), "{index}/{size}");
+
+                let wrong = log.leaves[((index + 1) % size) as usize];
+                assert!(size == 1 || !verify_inclusion(&wrong, index, size, &proof, &root));
+            }
+        }
+        assert!(log.inclusion_proof(17, 17).is_none());
+        assert!(log.inclusion_proof(0, 18).is_none());
+    }
+
+    #[test]
+    fn consistency_proofs_verify() {
+        let log = log(17);
+        for new_size in 1..=log.len() {
+            let new_root = log.root_at(new_size).unwrap();
+            for old_size in 1..=new_size {
+                let old_root = log.root_at(old_size).unwrap();
+                let proof = log.consistency_proof(old_size, new_size).unwrap();
+                assert!(
+                    verify_consistency(old_size, new_size, &old_root, &new_root, &proof),
+                    "{old_size} -> {new_size}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rewritten_history_fails_consistency() {
+        let honest = log(10);
+        let mut forked = log(6);
+        for i in 100..104 {
+            forked.push_leaf(Sha256::digest([i]).into());
+        }
+
+        let old_root = honest.root_at(8).unwrap();
+        let proof = forked.consistency_proof(8, 10).unwrap();
+        assert!(!verify_consistency(8, 10, &old_root, &forked.root(), &proof));
+    }
+}
@@ -13,6 +13,33 @@
 /// Real deployments will likely rotate groups much more frequently.
 pub const MAX_EPOCH: u64 = 1_000_000;
 
+/// Group context extension naming the recovery key a room's secrets are
+/// escrowed to, from the private use range (RFC 9420 §17.3).
+///
+/// Every leaf advertises support for it, so any member can join a room that
+/// carries it, and every member can see that the room is escrowed.
+pub const ESCROW_EXTENSION_TYPE: u16 = 0xff0a;
+
+/// Group context extension holding a room's message TTL in seconds (a
+/// big-endian `u64`), from the private use range (RFC 9420 §17.3).
+///
+/// Set and cleared with a commit, so every member moves to a new TTL at the
+/// same epoch, and joiners read it from the group they join. Rooms without it
+/// keep messages indefinitely.
+pub const MESSAGE_TTL_EXTENSION_TYPE: u16 = 0xff0b;
+
+/// Group context extension marking a room whose members can all read
+/// zstd-compressed messages, from the private use range (RFC 9420 §17.3).
+///
+/// Rooms are created with it and list it as a required capability, so MLS
+/// refuses any member whose leaf does not support it. Rooms created before it
+/// existed lack it, and their messages are never compressed.
+pub const COMPRESSION_EXTENSION_TYPE: u16 = 0xff0c;
+
+/// MLS ciphersuite rooms are created on unless asked otherwise:
+/// `MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519` (RFC 9420 §17.1).
+pub const DEFAULT_CIPHERSUITE: u16 = 0x0001;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -10,6 +10,10 @@ use tls_codec::{Deserialize, Serialize};
 
 use super::{
     MlsGroupState,
+    constants::{
+        COMPRESSION_EXTENSION_TYPE, DEFAULT_CIPHERSUITE, ESCROW_EXTENSION_TYPE,
+        MESSAGE_TTL_EXTENSION_TYPE,
+    },
     error::MlsError,
     provider::MlsProvider,
     validator::{MlsValidator, ValidationResult},
@@ -32,6 +36,66 @@ pub struct PendingJoinState<E: Environment> {
     signer: SignatureKeyPair,
 }
 
+impl<E: Environment> PendingJoinState<E> {
+    /// Export the state for storage, e.g. to join with the `KeyPackage`
+    /// after a restart.
+    ///
+    /// The bytes contain private key material and must be stored as such.
+    pub fn export_state(&self) -> Result<Vec<u8>, MlsError> {
+        let exported = ExportedPendingJoin {
+            signature_scheme: self.signer.signature_scheme(),
+            signature_private: self.signer.private().to_vec(),
+            signature_public: self.signer.public().to_vec(),
+            storage: self.provider.storage_entries(),
+        };
+
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&exported, &mut bytes)
+            .map_err(|e| MlsError::Serialization(format!("Failed to export join state: {e}")))?;
+        Ok(bytes)
+    }
+
+    /// Restore state written by [`Self::export_state`].
+    pub fn import_state(env: E, state: &[u8]) -> Result<Self, MlsError> {
+        let exported: ExportedPendingJoin = ciborium::de::from_reader(state)
+            .map_err(|e| MlsError::Serialization(format!("Invalid join state: {e}")))?;
+
+        let provider = MlsProvider::with_storage_entries(env, exported.storage);
+        let signer = SignatureKeyPair::from_raw(
+            exported.signature_scheme,
+            exported.signature_private,
+            exported.signature_public,
+        );
+        Ok(Self { provider, signer })
+    }
+}
+
+/// Serialized KeyPackage, its hash ref, and the state needed to join with it.
+type GeneratedKeyPackage<E> = (Vec<u8>, Vec<u8>, PendingJoinState<E>);
+
+/// A peer commit that has been verified but not yet applied.
+///
+/// Returned by [`MlsGroup::stage_commit`].
+pub struct StagedPeerCommit {
+    staged: Box<StagedCommit>,
+    member_count: usize,
+}
+
+impl StagedPeerCommit {
+    /// Number of members the group has once the commit is merged.
+    pub fn member_count(&self) -> usize {
+        self.member_count
+    }
+}
+
+impl std::fmt::Debug for StagedPeerCommit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StagedPeerCommit")
+            .field("member_count", &self.member_count)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Actions that MLS group operations can produce.
 ///
 /// The application layer is responsible for executing these actions.
@@ -75,6 +139,52 @@ pub enum MlsAction {
     },
 }
 
+/// Capabilities of our leaf nodes: the defaults, plus the extensions rooms
+/// may carry in their group context.
+fn leaf_capabilities() -> Capabilities {
+    Capabilities::new(
+        None,
+        None,
+        Some(&[
+            ExtensionType::Unknown(ESCROW_EXTENSION_TYPE),
+            ExtensionType::Unknown(MESSAGE_TTL_EXTENSION_TYPE),
+            ExtensionType::Unknown(COMPRESSION_EXTENSION_TYPE),
+        ]),
+        None,
+        None,
+    )
+}
+
+/// Require `required` of every member, on top of what `extensions` already
+/// requires.
+fn require_extensions(extensions: &mut Extensions, required: &[ExtensionType]) {
+    let mut types = extensions
+        .required_capabilities()
+        .map(|existing| existing.extension_types().to_vec())
+        .unwrap_or_default();
+    for extension_type in required {
+        if !types.contains(extension_type) {
+            types.push(*extension_type);
+        }
+    }
+    let required = RequiredCapabilitiesExtension::new(&types, &[], &[]);
+    extensions.add_or_replace(Extension::RequiredCapabilities(required));
+}
+
+/// Ciphersuite with IANA identifier `id`, if `provider` implements it.
+fn supported_ciphersuite<E: Environment>(
+    provider: &MlsProvider<E>,
+    id: u16,
+) -> Result<Ciphersuite, MlsError> {
+    let ciphersuite = Ciphersuite::try_from(id)
+        .map_err(|_| MlsError::Crypto(format!("Unknown ciphersuite {id:#06x}")))?;
+    provider
+        .crypto()
+        .supports(ciphersuite)
+        .map_err(|_| MlsError::Crypto(format!("Unsupported ciphersuite {id:#06x}")))?;
+    Ok(ciphersuite)
+}
+
 /// Extract member_id from an MLS credential.
 ///
 /// Our credentials store the member_id as little-endian u64 bytes.
@@ -92,6 +202,27 @@ fn extract_member_id_from_credential(credential: &Credential) -> Result<MemberId
     Ok(u64::from_le_bytes(member_id_bytes))
 }
 
+/// Decode a serialized Welcome message.
+fn decode_welcome(welcome_bytes: &[u8]) -> Result<Welcome, MlsError> {
+    let mls_message = MlsMessageIn::tls_deserialize(&mut welcome_bytes.as_ref())
+        .map_err(|e| MlsError::Serialization(format!("Failed to deserialize Welcome: {}", e)))?;
+
+    match mls_message.extract() {
+        MlsMessageBodyIn::Welcome(welcome) => Ok(welcome),
+        _ => Err(MlsError::Serialization("Message is not a Welcome".to_string())),
+    }
+}
+
+/// Hash refs of the `KeyPackage`s a Welcome message admits.
+///
+/// Matches the hash refs returned by [`MlsGroup::generate_key_package`], so
+/// a client holding several `KeyPackage`s can pick the state the Welcome
+/// needs.
+pub fn welcome_key_package_refs(welcome_bytes: &[u8]) -> Result<Vec<Vec<u8>>, MlsError> {
+    let welcome = decode_welcome(welcome_bytes)?;
+    Ok(welcome.secrets().iter().map(|secrets| secrets.new_member().as_slice().to_vec()).collect())
+}
+
 /// Client-side MLS group state.
 ///
 /// Represents participation in a single MLS group (room). Clients can be
@@ -133,22 +264,113 @@ struct PendingCommit {
     sent_at: std::time::Instant,
 }
 
+/// Join state, as written by [`PendingJoinState::export_state`].
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ExportedPendingJoin {
+    /// Scheme of the signature keypair the `KeyPackage` was signed with
+    signature_scheme: SignatureScheme,
+
+    /// Private signature key
+    signature_private: Vec<u8>,
+
+    /// Public signature key
+    signature_public: Vec<u8>,
+
+    /// Provider storage (the `KeyPackage`'s private init and encryption keys)
+    storage: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Complete group state, as written by [`MlsGroup::export_state`].
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ExportedGroup {
+    /// Room identifier
+    room_id: RoomId,
+
+    /// Our member ID in this group
+    member_id: MemberId,
+
+    /// MLS group ID the state is stored under
+    group_id: Vec<u8>,
+
+    /// Scheme of our signature keypair
+    signature_scheme: SignatureScheme,
+
+    /// Our private signature key
+    signature_private: Vec<u8>,
+
+    /// Our public signature key
+    signature_public: Vec<u8>,
+
+    /// Provider storage (key schedule, ratchet tree, secret tree, ...)
+    storage: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
 impl<E: Environment> MlsGroup<E> {
     /// Create a new MLS group.
     ///
-    /// This initializes a new group at epoch 0. The creator becomes the first
-    /// member and can add other members via proposals + commits.
+    /// This initializes a new group at epoch 0 on [`DEFAULT_CIPHERSUITE`].
+    /// The creator becomes the first member and can add other members via
+    /// proposals + commits.
     ///
     /// Returns a tuple containing a new `MlsGroup` instance and any actions to
     /// execute.
-    #[allow(clippy::too_many_lines)]
     pub fn new(
         env: E,
         room_id: RoomId,
         member_id: MemberId,
+    ) -> Result<(Self, Vec<MlsAction>), MlsError> {
+        Self::create(env, room_id, member_id, DEFAULT_CIPHERSUITE, Extensions::empty())
+    }
+
+    /// Create a new MLS group on the ciphersuite with IANA identifier
+    /// `ciphersuite` (RFC 9420 §17.1).
+    ///
+    /// Members can only join with a KeyPackage on the same ciphersuite; see
+    /// [`Self::generate_key_package_for`].
+    pub fn new_with_ciphersuite(
+        env: E,
+        room_id: RoomId,
+        member_id: MemberId,
+        ciphersuite: u16,
+    ) -> Result<(Self, Vec<MlsAction>), MlsError> {
+        Self::create(env, room_id, member_id, ciphersuite, Extensions::empty())
+    }
+
+    /// Create a new MLS group on `ciphersuite` whose secrets are escrowed to
+    /// `recovery_key_id`.
+    ///
+    /// The key ID is carried in the group context
+    /// ([`ESCROW_EXTENSION_TYPE`]), so everyone who joins sees it.
+    pub fn new_escrowed(
+        env: E,
+        room_id: RoomId,
+        member_id: MemberId,
+        ciphersuite: u16,
+        recovery_key_id: &[u8],
+    ) -> Result<(Self, Vec<MlsAction>), MlsError> {
+        let escrow =
+            Extension::Unknown(ESCROW_EXTENSION_TYPE, UnknownExtension(recovery_key_id.to_vec()));
+        Self::create(env, room_id, member_id, ciphersuite, Extensions::single(escrow))
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn create(
+        env: E,
+        room_id: RoomId,
+        member_id: MemberId,
+        ciphersuite: u16,
+        mut group_context_extensions: Extensions,
     ) -> Result<(Self, Vec<MlsAction>), MlsError> {
         let provider = MlsProvider::new(env);
-        let ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+        let ciphersuite = supported_ciphersuite(&provider, ciphersuite)?;
+
+        // Every room created now can carry compressed messages
+        let compression = UnknownExtension(Vec::new());
+        group_context_extensions
+            .add_or_replace(Extension::Unknown(COMPRESSION_EXTENSION_TYPE, compression));
+        require_extensions(&mut group_context_extensions, &[ExtensionType::Unknown(
+            COMPRESSION_EXTENSION_TYPE,
+        )]);
 
         let signer = SignatureKeyPair::new(ciphersuite.signature_algorithm())
             .map_err(|e| MlsError::Crypto(format!("Failed to generate keypair: {}", e)))?;
@@ -162,6 +384,9 @@ impl<E: Environment> MlsGroup<E> {
         let group_config = MlsGroupCreateConfig::builder()
             .ciphersuite(ciphersuite)
             .use_ratchet_tree_extension(true)
+            .capabilities(leaf_capabilities())
+            .with_group_context_extensions(group_context_extensions)
+            .map_err(|e| MlsError::Crypto(format!("Invalid group context extensions: {e}")))?
             .build();
         let mls_group =
             openmls::group::MlsGroup::new(&provider, &signer, &group_config, credential_with_key)
@@ -196,6 +421,13 @@ impl<E: Environment> MlsGroup<E> {
         self.mls_group.group_id()
     }
 
+    /// IANA identifier of the group's MLS ciphersuite (RFC 9420 §17.1).
+    ///
+    /// Fixed when the group is created, so every member sees the same one.
+    pub fn ciphersuite(&self) -> u16 {
+        self.mls_group.ciphersuite() as u16
+    }
+
     /// Our position in the ratchet tree.
     pub fn own_leaf_index(&self) -> u32 {
         self.mls_group.own_leaf_index().u32()
@@ -203,14 +435,14 @@ impl<E: Environment> MlsGroup<E> {
 
     /// Sign a frame header using this group's MLS signature key.
     ///
-    /// The signature is computed over the first 64 bytes of the header
-    /// (the routing/sequencing data, excluding the signature field itself).
-    /// The signature is set directly on the header.
+    /// The signature covers [`FrameHeader::signing_data`]: the routing data,
+    /// excluding the log index the sequencer assigns and the signature field
+    /// itself. The signature is set directly on the header.
     pub fn sign_frame_header(&self, header: &mut FrameHeader) {
-        let header_bytes = header.to_bytes();
-        let signed_data = &header_bytes[..64];
+        // Same bytes the validator checks: the log index is assigned later
+        let signed_data = header.signing_data();
 
-        if let Ok(signature) = self.signer.sign(signed_data) {
+        if let Ok(signature) = self.signer.sign(&signed_data) {
             if signature.len() == 64 {
                 let mut sig_bytes = [0u8; 64];
                 sig_bytes.copy_from_slice(&signature);
@@ -238,6 +470,46 @@ impl<E: Environment> MlsGroup<E> {
         })
     }
 
+    /// Public signature key of a member. `None` if not in the group.
+    ///
+    /// Together with the member ID this is what peers compare when verifying
+    /// each other out of band.
+    pub fn member_signature_key(&self, member_id: MemberId) -> Option<Vec<u8>> {
+        self.mls_group.members().find_map(|m| {
+            (extract_member_id_from_credential(&m.credential).ok() == Some(member_id))
+                .then_some(m.signature_key)
+        })
+    }
+
+    /// Current epoch's authenticator (RFC 9420 §8.7).
+    ///
+    /// Equal for every member in the same epoch with the same group state, so
+    /// codes derived from it only match if nobody in between forked the group.
+    pub fn epoch_authenticator(&self) -> &[u8] {
+        self.mls_group.epoch_authenticator().as_slice()
+    }
+
+    /// Recovery key the group's secrets are escrowed to, as named in the
+    /// group context. `None` if the group is not escrowed.
+    pub fn escrow_key_id(&self) -> Option<&[u8]> {
+        self.mls_group.extensions().unknown(ESCROW_EXTENSION_TYPE).map(|escrow| escrow.0.as_slice())
+    }
+
+    /// How long the room keeps messages, as set in the group context. `None`
+    /// if messages are kept indefinitely, which a malformed or zero TTL is
+    /// taken to mean.
+    pub fn message_ttl(&self) -> Option<Duration> {
+        let ttl = self.mls_group.extensions().unknown(MESSAGE_TTL_EXTENSION_TYPE)?;
+        let secs = u64::from_be_bytes(ttl.0.as_slice().try_into().ok()?);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// Whether every member of the room can read compressed messages, as
+    /// marked in the group context ([`COMPRESSION_EXTENSION_TYPE`]).
+    pub fn supports_compression(&self) -> bool {
+        self.mls_group.extensions().unknown(COMPRESSION_EXTENSION_TYPE).is_some()
+    }
+
     /// Derive secret from current epoch's key schedule (for sender keys).
     pub fn export_secret(
         &self,
@@ -275,6 +547,15 @@ impl<E: Environment> MlsGroup<E> {
         let _ = self.mls_group.clear_pending_commit(self.provider.storage()); // best-effort cleanup
     }
 
+    /// Wipe the group's stored state (key schedule, secret tree, ratchet
+    /// tree, ...), e.g. on leaving the room.
+    ///
+    /// The group is unusable afterwards; drop it.
+    pub fn purge(&mut self) {
+        self.pending_commit = None;
+        self.provider.wipe_storage();
+    }
+
     /// Merge the pending commit after it has been confirmed by the sequencer.
     ///
     /// This is called when we created a commit (e.g., via add_members) and
@@ -374,30 +655,76 @@ impl<E: Environment> MlsGroup<E> {
                 });
             },
             ProcessedMessageContent::StagedCommitMessage(staged_commit) => {
-                let old_epoch = self.epoch();
+                actions.extend(self.merge_staged(*staged_commit)?);
+            },
+        }
+
+        Ok(actions)
+    }
+
+    /// Verify a peer's commit without applying it.
+    ///
+    /// Lets the caller inspect the commit's effect on the group, e.g. the
+    /// resulting member count, before deciding to accept it. Apply it with
+    /// [`merge_staged_commit`](Self::merge_staged_commit); dropping it leaves
+    /// the group unchanged.
+    pub fn stage_commit(&mut self, frame: &Frame) -> Result<StagedPeerCommit, MlsError> {
+        let mls_message =
+            MlsMessageIn::tls_deserialize_exact(frame.payload.as_ref()).map_err(|e| {
+                MlsError::Serialization(format!("Failed to deserialize MLS message: {e}"))
+            })?;
 
-                self.mls_group
-                    .merge_staged_commit(&self.provider, *staged_commit)
-                    .map_err(|e| MlsError::Crypto(format!("Failed to merge commit: {}", e)))?;
+        let protocol_message: ProtocolMessage = mls_message
+            .try_into()
+            .map_err(|e| MlsError::Serialization(format!("Invalid MLS message type: {e:?}")))?;
 
-                let new_epoch = self.epoch();
-                debug_assert!(
-                    new_epoch > old_epoch,
-                    "invariant: epoch must increase after commit ({} -> {})",
-                    old_epoch,
-                    new_epoch
-                );
+        let processed = self
+            .mls_group
+            .process_message(&self.provider, protocol_message)
+            .map_err(|e| MlsError::Crypto(format!("Failed to process message: {e}")))?;
 
-                actions.push(MlsAction::Log {
-                    message: format!("Advanced to epoch {}", self.epoch()),
-                });
+        let ProcessedMessageContent::StagedCommitMessage(staged) = processed.into_content() else {
+            return Err(MlsError::ValidationFailed("expected a commit".to_string()));
+        };
 
-                if !self.mls_group.is_active() {
-                    actions.push(MlsAction::RemoveGroup {
-                        reason: "Removed from group by commit".to_string(),
-                    });
-                }
-            },
+        let added = staged.add_proposals().count();
+        let removed = staged.remove_proposals().count();
+        let member_count =
+            self.mls_group.members().count().saturating_add(added).saturating_sub(removed);
+
+        Ok(StagedPeerCommit { staged, member_count })
+    }
+
+    /// Apply a commit verified by [`stage_commit`](Self::stage_commit).
+    pub fn merge_staged_commit(
+        &mut self,
+        commit: StagedPeerCommit,
+    ) -> Result<Vec<MlsAction>, MlsError> {
+        self.merge_staged(*commit.staged)
+    }
+
+    fn merge_staged(&mut self, staged_commit: StagedCommit) -> Result<Vec<MlsAction>, MlsError> {
+        let old_epoch = self.epoch();
+
+        self.mls_group
+            .merge_staged_commit(&self.provider, staged_commit)
+            .map_err(|e| MlsError::Crypto(format!("Failed to merge commit: {}", e)))?;
+
+        let new_epoch = self.epoch();
+        debug_assert!(
+            new_epoch > old_epoch,
+            "invariant: epoch must increase after commit ({} -> {})",
+            old_epoch,
+            new_epoch
+        );
+
+        let mut actions =
+            vec![MlsAction::Log { message: format!("Advanced to epoch {}", self.epoch()) }];
+
+        if !self.mls_group.is_active() {
+            actions.push(MlsAction::RemoveGroup {
+                reason: "Removed from group by commit".to_string(),
+            });
         }
 
         Ok(actions)
@@ -447,16 +774,63 @@ impl<E: Environment> MlsGroup<E> {
 
     /// Export the current group state for storage.
     ///
-    /// Returns the serialized OpenMLS group state that can be stored
-    /// and later used to restore the group.
+    /// Serializes everything stored for the group (key schedule,
+    /// ratchet tree, secret tree) together with our signature keypair, so
+    /// [`Self::import_state`] can resume at the same epoch.
     ///
-    /// Returns serialized group state bytes.
+    /// The bytes contain private key material and must be stored as such.
     pub fn export_state(&self) -> Result<Vec<u8>, MlsError> {
-        // For now, we export the group secret as a proxy for the full state.
-        // In a full implementation, OpenMLS would provide a way to serialize
-        // the entire group state including key schedule, tree, etc.
-        self.export_secret("group_state", b"", 64)
-            .map_err(|e| MlsError::Crypto(format!("Failed to export group state: {}", e)))
+        let exported = ExportedGroup {
+            room_id: self.room_id,
+            member_id: self.member_id,
+            group_id: self.group_id().as_slice().to_vec(),
+            signature_scheme: self.signer.signature_scheme(),
+            signature_private: self.signer.private().to_vec(),
+            signature_public: self.signer.public().to_vec(),
+            storage: self.provider.storage_entries(),
+        };
+
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&exported, &mut bytes)
+            .map_err(|e| MlsError::Serialization(format!("Failed to export group state: {e}")))?;
+        Ok(bytes)
+    }
+
+    /// Restore a group from state written by [`Self::export_state`].
+    ///
+    /// The group resumes at the epoch it was exported in. A commit we had
+    /// sent but not yet seen sequenced is no longer tracked; it either comes
+    /// back from the server or times out like any other.
+    pub fn import_state(env: E, state: &[u8]) -> Result<Self, MlsError> {
+        let exported: ExportedGroup = ciborium::de::from_reader(state)
+            .map_err(|e| MlsError::Serialization(format!("Invalid group state: {e}")))?;
+
+        let provider = MlsProvider::with_storage_entries(env, exported.storage);
+        let group_id = GroupId::from_slice(&exported.group_id);
+        let mls_group = openmls::group::MlsGroup::load(provider.storage(), &group_id)
+            .map_err(|e| MlsError::Serialization(format!("Failed to load group state: {e}")))?
+            .ok_or_else(|| MlsError::Serialization("group state holds no group".to_string()))?;
+
+        let signer = SignatureKeyPair::from_raw(
+            exported.signature_scheme,
+            exported.signature_private,
+            exported.signature_public,
+        );
+        let own_key = mls_group.own_leaf_node().map(|leaf| leaf.signature_key().as_slice());
+        if own_key != Some(signer.public()) {
+            return Err(MlsError::Serialization(
+                "group state signer does not match our leaf".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            room_id: exported.room_id,
+            member_id: exported.member_id,
+            mls_group,
+            signer,
+            provider,
+            pending_commit: None,
+        })
     }
 
     /// Export the current group state needed for frame validation.
@@ -551,7 +925,7 @@ impl<E: Environment> MlsGroup<E> {
         ))
     }
 
-    /// Generate a KeyPackage for joining groups.
+    /// Generate a KeyPackage for joining groups on [`DEFAULT_CIPHERSUITE`].
     ///
     /// Creates a KeyPackage that can be shared with group members who want to
     /// add this client to their group. The KeyPackage is signed with this
@@ -563,9 +937,19 @@ impl<E: Environment> MlsGroup<E> {
     pub fn generate_key_package(
         env: E,
         member_id: MemberId,
-    ) -> Result<(Vec<u8>, Vec<u8>, PendingJoinState<E>), MlsError> {
+    ) -> Result<GeneratedKeyPackage<E>, MlsError> {
+        Self::generate_key_package_for(env, member_id, DEFAULT_CIPHERSUITE)
+    }
+
+    /// Generate a KeyPackage for joining groups on the ciphersuite with IANA
+    /// identifier `ciphersuite`, like [`Self::generate_key_package`].
+    pub fn generate_key_package_for(
+        env: E,
+        member_id: MemberId,
+        ciphersuite: u16,
+    ) -> Result<GeneratedKeyPackage<E>, MlsError> {
         let provider = MlsProvider::new(env);
-        let ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+        let ciphersuite = supported_ciphersuite(&provider, ciphersuite)?;
 
         let signer = SignatureKeyPair::new(ciphersuite.signature_algorithm())
             .map_err(|e| MlsError::Crypto(format!("Failed to generate keypair: {}", e)))?;
@@ -577,6 +961,7 @@ impl<E: Environment> MlsGroup<E> {
         };
 
         let key_package_bundle = KeyPackage::builder()
+            .leaf_node_capabilities(leaf_capabilities())
             .build(ciphersuite, &provider, &signer, credential_with_key)
             .map_err(|e| MlsError::Crypto(format!("Failed to build KeyPackage: {}", e)))?;
 
@@ -610,16 +995,7 @@ impl<E: Environment> MlsGroup<E> {
         pending_state: PendingJoinState<E>,
     ) -> Result<(Self, Vec<MlsAction>), MlsError> {
         let PendingJoinState { provider, signer } = pending_state;
-
-        let mls_message =
-            MlsMessageIn::tls_deserialize(&mut welcome_bytes.as_ref()).map_err(|e| {
-                MlsError::Serialization(format!("Failed to deserialize Welcome: {}", e))
-            })?;
-
-        let welcome = match mls_message.extract() {
-            MlsMessageBodyIn::Welcome(w) => w,
-            _ => return Err(MlsError::Serialization("Message is not a Welcome".to_string())),
-        };
+        let welcome = decode_welcome(welcome_bytes)?;
 
         let group_config = MlsGroupJoinConfig::builder().build();
 
@@ -735,6 +1111,84 @@ impl<E: Environment> MlsGroup<E> {
         Ok(actions)
     }
 
+    /// Refresh our leaf with an empty commit.
+    ///
+    /// Creates a commit that changes nothing but our own key material, for
+    /// when a change to the room must be bound to an epoch of its own. The
+    /// commit must be sent to the sequencer and will advance the epoch when
+    /// accepted.
+    pub fn self_update(&mut self) -> Result<Vec<MlsAction>, MlsError> {
+        let target_epoch = self.epoch() + 1;
+        let now = self.provider.now();
+
+        let bundle = self
+            .mls_group
+            .self_update(&self.provider, &self.signer, LeafNodeParameters::default())
+            .map_err(|e| MlsError::Crypto(format!("Failed to update own leaf: {e}")))?;
+
+        self.pending_commit = Some(PendingCommit { target_epoch, sent_at: now });
+
+        let commit_payload = bundle
+            .into_commit()
+            .tls_serialize_detached()
+            .map_err(|e| MlsError::Serialization(format!("Failed to serialize commit: {e}")))?;
+
+        let commit_frame =
+            Frame { header: FrameHeader::new(Opcode::Commit), payload: commit_payload.into() };
+
+        Ok(vec![MlsAction::SendCommit(commit_frame), MlsAction::Log {
+            message: format!("Updating own leaf for epoch {target_epoch}"),
+        }])
+    }
+
+    /// Change the room's message TTL with a commit.
+    ///
+    /// The commit replaces the group context extensions with the current ones
+    /// carrying `ttl` (or none, for `None`), so the TTL changes for everyone
+    /// at the epoch it creates. Our own extensions are also added to the
+    /// required capabilities, which MLS asks of any extension changed by a
+    /// commit. The
+    /// commit must be sent to the sequencer and will advance the epoch when
+    /// accepted.
+    pub fn set_message_ttl(&mut self, ttl: Option<Duration>) -> Result<Vec<MlsAction>, MlsError> {
+        let target_epoch = self.epoch() + 1;
+        let now = self.provider.now();
+
+        let mut extensions = self.mls_group.extensions().clone();
+        let ttl_type = ExtensionType::Unknown(MESSAGE_TTL_EXTENSION_TYPE);
+        require_extensions(&mut extensions, &[
+            ExtensionType::Unknown(ESCROW_EXTENSION_TYPE),
+            ttl_type,
+        ]);
+        match ttl {
+            Some(ttl) => {
+                let secs = UnknownExtension(ttl.as_secs().to_be_bytes().to_vec());
+                extensions.add_or_replace(Extension::Unknown(MESSAGE_TTL_EXTENSION_TYPE, secs));
+            },
+            None => {
+                let _ = extensions.remove(ttl_type);
+            },
+        }
+
+        let (commit, _, _) = self
+            .mls_group
+            .update_group_context_extensions(&self.provider, extensions, &self.signer)
+            .map_err(|e| MlsError::Crypto(format!("Failed to change message TTL: {e}")))?;
+
+        self.pending_commit = Some(PendingCommit { target_epoch, sent_at: now });
+
+        let commit_payload = commit
+            .tls_serialize_detached()
+            .map_err(|e| MlsError::Serialization(format!("Failed to serialize commit: {e}")))?;
+
+        let commit_frame =
+            Frame { header: FrameHeader::new(Opcode::Commit), payload: commit_payload.into() };
+
+        Ok(vec![MlsAction::SendCommit(commit_frame), MlsAction::Log {
+            message: format!("Changing message TTL to {ttl:?} for epoch {target_epoch}"),
+        }])
+    }
+
     /// Leave the group voluntarily.
     ///
     /// Creates a Remove proposal for this member. The proposal must be sent
@@ -848,6 +1302,37 @@ mod tests {
         assert!(matches!(actions[0], MlsAction::Log { .. }));
     }
 
+    #[test]
+    fn signed_headers_validate_once_sequenced() {
+        let room_id = 0x1234_5678_9abc_def0_1234_5678_9abc_def0;
+        let (group, _) = MlsGroup::new(TestEnv, room_id, 1).unwrap();
+
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_sender_id(1);
+        group.sign_frame_header(&mut header);
+
+        // The sequencer assigns the log index after the sender signed
+        header.set_log_index(42);
+        let frame = Frame::new(header, Vec::new());
+        let state = group.export_validation_state();
+        assert_eq!(
+            MlsValidator::validate_frame(&frame, group.epoch(), &state).unwrap(),
+            ValidationResult::Accept
+        );
+    }
+
+    #[test]
+    fn purged_group_holds_no_state() {
+        let room_id = 0x1234_5678_9abc_def0_1234_5678_9abc_def0;
+        let (mut group, _) = MlsGroup::new(TestEnv, room_id, 1).unwrap();
+        assert!(!group.provider.storage_entries().is_empty());
+
+        group.purge();
+        assert!(group.provider.storage_entries().is_empty());
+        assert!(!group.has_pending_commit());
+    }
+
     #[test]
     fn commit_timeout_detection() {
         let env = TestEnv;
@@ -889,7 +1374,7 @@ mod tests {
 
         // Bob generates a KeyPackage (keeping provider state for later)
         let bob_id = 100u64;
-        let (bob_kp_bytes, _, bob_pending) =
+        let (bob_kp_bytes, bob_hash_ref, bob_pending) =
             MlsGroup::generate_key_package(env.clone(), bob_id).expect("bob generate key package");
 
         // Alice adds Bob - creates Commit and Welcome
@@ -908,6 +1393,11 @@ mod tests {
         // Alice merges her pending commit
         alice_group.merge_pending_commit().expect("alice merge commit");
 
+        // The Welcome names the KeyPackage it was made for
+        assert_eq!(welcome_key_package_refs(&welcome_frame.payload).expect("welcome refs"), vec![
+            bob_hash_ref
+        ]);
+
         // Bob joins via Welcome (using his stored provider state)
         let (mut bob_group, _) =
             MlsGroup::join_from_welcome(room_id, bob_id, &welcome_frame.payload, bob_pending)
@@ -948,6 +1438,65 @@ mod tests {
         assert_eq!(delivered.1, b"Hello from Alice");
     }
 
+    #[test]
+    fn imported_state_resumes_the_group() {
+        let env = TestEnv;
+        let room_id = 0x1234_5678_9abc_def0_1234_5678_9abc_def0;
+
+        let alice_id = 42u64;
+        let (mut alice_group, _) =
+            MlsGroup::new(env.clone(), room_id, alice_id).expect("alice create group");
+
+        let bob_id = 100u64;
+        let (bob_kp_bytes, _, bob_pending) =
+            MlsGroup::generate_key_package(env.clone(), bob_id).expect("bob generate key package");
+        let add_actions =
+            alice_group.add_members_from_bytes(&[bob_kp_bytes]).expect("alice add bob");
+        let welcome_frame = add_actions
+            .iter()
+            .find_map(|a| match a {
+                MlsAction::SendWelcome { frame, .. } => Some(frame.clone()),
+                _ => None,
+            })
+            .expect("should have welcome");
+        alice_group.merge_pending_commit().expect("alice merge commit");
+        let (mut bob_group, _) =
+            MlsGroup::join_from_welcome(room_id, bob_id, &welcome_frame.payload, bob_pending)
+                .expect("bob join via welcome");
+
+        // Alice restarts from her exported state
+        let state = alice_group.export_state().expect("export state");
+        drop(alice_group);
+        let mut alice_group = MlsGroup::import_state(env, &state).expect("import state");
+
+        assert_eq!(alice_group.room_id(), room_id);
+        assert_eq!(alice_group.member_id(), alice_id);
+        assert_eq!(alice_group.epoch(), 1);
+        assert_eq!(alice_group.epoch_authenticator(), bob_group.epoch_authenticator());
+
+        // She can still send to Bob
+        let message_frame = alice_group
+            .create_message(b"after restart")
+            .expect("alice create message")
+            .into_iter()
+            .find_map(|a| match a {
+                MlsAction::SendMessage(frame) => Some(frame),
+                _ => None,
+            })
+            .expect("should have message frame");
+        let delivered = bob_group
+            .process_message(message_frame)
+            .expect("bob process message")
+            .into_iter()
+            .find_map(|a| match a {
+                MlsAction::DeliverMessage { plaintext, .. } => Some(plaintext),
+                _ => None,
+            });
+        assert_eq!(delivered.as_deref(), Some(&b"after restart"[..]));
+
+        assert!(MlsGroup::<TestEnv>::import_state(TestEnv, b"garbage").is_err());
+    }
+
     /// Test that add_members returns the correct recipient in SendWelcome.
     #[test]
     fn add_members_returns_correct_welcome_recipient() {
@@ -983,6 +1532,149 @@ mod tests {
         assert_eq!(welcome_frame.header.sender_id(), alice_id);
     }
 
+    #[test]
+    fn escrow_key_is_visible_to_joiners() {
+        let env = TestEnv;
+        let room_id = 0x1234_5678_9abc_def0_1234_5678_9abc_def0;
+
+        let (plain, _) = MlsGroup::new(env.clone(), room_id, 1).unwrap();
+        assert_eq!(plain.escrow_key_id(), None);
+
+        let (mut alice_group, _) = MlsGroup::new_escrowed(
+            env.clone(),
+            room_id,
+            42,
+            DEFAULT_CIPHERSUITE,
+            b"org-recovery-1",
+        )
+        .unwrap();
+        assert_eq!(alice_group.escrow_key_id(), Some(&b"org-recovery-1"[..]));
+
+        let (bob_kp_bytes, _, bob_pending) = MlsGroup::generate_key_package(env, 100).unwrap();
+        let add_actions = alice_group.add_members_from_bytes(&[bob_kp_bytes]).unwrap();
+        let welcome = add_actions
+            .iter()
+            .find_map(|a| match a {
+                MlsAction::SendWelcome { frame, .. } => Some(frame.payload.clone()),
+                _ => None,
+            })
+            .unwrap();
+
+        let (bob_group, _) =
+            MlsGroup::join_from_welcome(room_id, 100, &welcome, bob_pending).unwrap();
+        assert_eq!(bob_group.escrow_key_id(), Some(&b"org-recovery-1"[..]));
+    }
+
+    #[test]
+    fn message_ttl_travels_in_the_group_context() {
+        let env = TestEnv;
+        let room_id = 0x1234;
+
+        let (mut alice_group, _) =
+            MlsGroup::new_escrowed(env.clone(), room_id, 1, DEFAULT_CIPHERSUITE, b"recovery")
+                .unwrap();
+        let (bob_kp, _, bob_pending) = MlsGroup::generate_key_package(env, 2).unwrap();
+        let welcome = alice_group
+            .add_members_from_bytes(&[bob_kp])
+            .unwrap()
+            .into_iter()
+            .find_map(|a| match a {
+                MlsAction::SendWelcome { frame, .. } => Some(frame.payload),
+                _ => None,
+            })
+            .unwrap();
+        alice_group.merge_pending_commit().unwrap();
+        let (mut bob_group, _) =
+            MlsGroup::join_from_welcome(room_id, 2, &welcome, bob_pending).unwrap();
+        assert_eq!(bob_group.message_ttl(), None);
+
+        let ttl = Duration::from_secs(3600);
+        for change in [Some(ttl), None] {
+            let commit = alice_group
+                .set_message_ttl(change)
+                .unwrap()
+                .into_iter()
+                .find_map(|a| match a {
+                    MlsAction::SendCommit(frame) => Some(frame),
+                    _ => None,
+                })
+                .unwrap();
+            assert_eq!(alice_group.message_ttl(), if change.is_some() { None } else { Some(ttl) });
+
+            alice_group.merge_pending_commit().unwrap();
+            let staged = bob_group.stage_commit(&commit).unwrap();
+            bob_group.merge_staged_commit(staged).unwrap();
+            assert_eq!(alice_group.message_ttl(), change);
+            assert_eq!(bob_group.message_ttl(), change);
+
+            // Other extensions are kept
+            assert_eq!(bob_group.escrow_key_id(), Some(&b"recovery"[..]));
+            assert!(bob_group.supports_compression());
+        }
+    }
+
+    #[test]
+    fn new_rooms_require_compression_of_every_member() {
+        let env = TestEnv;
+        let room_id = 0x1234;
+
+        let (mut alice_group, _) = MlsGroup::new(env.clone(), room_id, 1).unwrap();
+        assert!(alice_group.supports_compression());
+        let required = alice_group.mls_group.extensions().required_capabilities().unwrap();
+        let compression = ExtensionType::Unknown(COMPRESSION_EXTENSION_TYPE);
+        assert!(required.extension_types().contains(&compression));
+
+        // Joiners advertise it, so they can be added and see it too
+        let (bob_kp, _, bob_pending) = MlsGroup::generate_key_package(env, 2).unwrap();
+        let welcome = alice_group
+            .add_members_from_bytes(&[bob_kp])
+            .unwrap()
+            .into_iter()
+            .find_map(|a| match a {
+                MlsAction::SendWelcome { frame, .. } => Some(frame.payload),
+                _ => None,
+            })
+            .unwrap();
+        alice_group.merge_pending_commit().unwrap();
+        let (bob_group, _) =
+            MlsGroup::join_from_welcome(room_id, 2, &welcome, bob_pending).unwrap();
+        assert!(bob_group.supports_compression());
+    }
+
+    #[test]
+    fn groups_are_created_on_the_chosen_ciphersuite() {
+        let env = TestEnv;
+        let room_id = 0x1234;
+        let chacha = 0x0003;
+
+        let (mut alice_group, _) =
+            MlsGroup::new_with_ciphersuite(env.clone(), room_id, 1, chacha).unwrap();
+        assert_eq!(alice_group.ciphersuite(), chacha);
+
+        // A KeyPackage on another ciphersuite can't join
+        let (default_kp, ..) = MlsGroup::generate_key_package(env.clone(), 2).unwrap();
+        assert!(alice_group.add_members_from_bytes(&[default_kp]).is_err());
+
+        let (bob_kp, _, bob_pending) =
+            MlsGroup::generate_key_package_for(env.clone(), 2, chacha).unwrap();
+        let welcome = alice_group
+            .add_members_from_bytes(&[bob_kp])
+            .unwrap()
+            .into_iter()
+            .find_map(|a| match a {
+                MlsAction::SendWelcome { frame, .. } => Some(frame.payload),
+                _ => None,
+            })
+            .unwrap();
+        let (bob_group, _) =
+            MlsGroup::join_from_welcome(room_id, 2, &welcome, bob_pending).unwrap();
+        assert_eq!(bob_group.ciphersuite(), chacha);
+
+        // Ciphersuites the provider doesn't implement are refused
+        assert!(MlsGroup::new_with_ciphersuite(env.clone(), room_id, 1, 0x0006).is_err());
+        assert!(MlsGroup::generate_key_package_for(env, 1, 0xf000).is_err());
+    }
+
     /// Test that remove_members produces a Commit and removes the correct
     /// member.
     #[test]
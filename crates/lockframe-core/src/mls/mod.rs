@@ -20,9 +20,15 @@ pub mod provider;
 pub mod state;
 pub mod validator;
 
-pub use constants::MAX_EPOCH;
+pub use constants::{
+    COMPRESSION_EXTENSION_TYPE, DEFAULT_CIPHERSUITE, ESCROW_EXTENSION_TYPE, MAX_EPOCH,
+    MESSAGE_TTL_EXTENSION_TYPE,
+};
 pub use error::MlsError;
-pub use group::{MemberId, MlsAction, MlsGroup, PendingJoinState, RoomId};
+pub use group::{
+    MemberId, MlsAction, MlsGroup, PendingJoinState, RoomId, StagedPeerCommit,
+    welcome_key_package_refs,
+};
 pub use provider::MlsProvider;
 pub use state::MlsGroupState;
 pub use validator::{MlsValidator, ValidationResult};
@@ -3,9 +3,12 @@
 //! Bridges OpenMLS's provider pattern with our deterministic Environment trait,
 //! enabling deterministic testing with Turmoil.
 
+use std::sync::PoisonError;
+
 use openmls_memory_storage::MemoryStorage;
 use openmls_rust_crypto::RustCrypto;
 use openmls_traits::{OpenMlsProvider, random::OpenMlsRand};
+use zeroize::Zeroize;
 
 use crate::env::Environment;
 
@@ -33,6 +36,42 @@ impl<E: Environment> MlsProvider<E> {
         }
     }
 
+    /// Initialize a provider whose storage holds `entries`, as returned by
+    /// [`Self::storage_entries`].
+    pub fn with_storage_entries(env: E, entries: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        let provider = Self::new(env);
+        provider.storage.values.write().unwrap_or_else(PoisonError::into_inner).extend(entries);
+        provider
+    }
+
+    /// Every key/value pair written to storage, sorted by key.
+    ///
+    /// Together with the signer this is the complete state of the groups
+    /// using this provider.
+    pub fn storage_entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries: Vec<_> = self
+            .storage
+            .values
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        entries.sort_unstable();
+        entries
+    }
+
+    /// Zeroize every key and value in storage and drop them.
+    ///
+    /// Afterwards no group using this provider can be loaded or advanced.
+    pub fn wipe_storage(&self) {
+        let mut values = self.storage.values.write().unwrap_or_else(PoisonError::into_inner);
+        for (mut key, mut value) in values.drain() {
+            key.zeroize();
+            value.zeroize();
+        }
+    }
+
     /// Current time from the environment.
     ///
     /// Used for tracking when commits are sent for timeout detection.
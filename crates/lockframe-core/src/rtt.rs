@@ -0,0 +1,243 @@
+//! Round-trip time estimation.
+//!
+//! Both ends of a session send [`Heartbeat`] frames stamped with their own
+//! monotonic clock and get the same timestamp echoed back. Each echo yields an
+//! RTT sample, which feeds an RFC 6298 style estimator (smoothed RTT plus
+//! variance). Callers use the resulting retry timeout instead of a fixed
+//! constant, so slow links back off and fast links recover quickly.
+
+use std::{ops::Sub, time::Duration};
+
+use lockframe_proto::payloads::session::Heartbeat;
+
+/// Lower bound on [`RttEstimator::retry_timeout`].
+pub const MIN_RETRY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Upper bound on [`RttEstimator::retry_timeout`].
+pub const MAX_RETRY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Retry timeout used before any RTT sample has been taken.
+pub const INITIAL_RETRY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Smoothed RTT estimator (RFC 6298).
+///
+/// `SRTT` and `RTTVAR` are updated with gains of 1/8 and 1/4. The retry
+/// timeout is `SRTT + 4 * RTTVAR`, clamped to
+/// [`MIN_RETRY_TIMEOUT`]..=[`MAX_RETRY_TIMEOUT`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RttEstimator {
+    smoothed: Option<Duration>,
+    variance: Duration,
+    latest: Option<Duration>,
+    samples: u64,
+}
+
+impl RttEstimator {
+    /// Create an estimator with no samples.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Incorporate one round-trip measurement.
+    pub fn on_sample(&mut self, rtt: Duration) {
+        match self.smoothed {
+            None => {
+                self.smoothed = Some(rtt);
+                self.variance = rtt.checked_div(2).unwrap_or_default();
+            },
+            Some(srtt) => {
+                let deviation = srtt.max(rtt).saturating_sub(srtt.min(rtt));
+                self.variance = self
+                    .variance
+                    .saturating_mul(3)
+                    .saturating_add(deviation)
+                    .checked_div(4)
+                    .unwrap_or_default();
+                self.smoothed =
+                    srtt.saturating_mul(7).saturating_add(rtt).checked_div(8).or(Some(srtt));
+            },
+        }
+
+        self.latest = Some(rtt);
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    /// Smoothed RTT. `None` until the first sample.
+    #[must_use]
+    pub fn smoothed(&self) -> Option<Duration> {
+        self.smoothed
+    }
+
+    /// RTT variance estimate.
+    #[must_use]
+    pub fn variance(&self) -> Duration {
+        self.variance
+    }
+
+    /// Most recent raw sample. `None` until the first sample.
+    #[must_use]
+    pub fn latest(&self) -> Option<Duration> {
+        self.latest
+    }
+
+    /// Number of samples taken.
+    #[must_use]
+    pub fn sample_count(&self) -> u64 {
+        self.samples
+    }
+
+    /// How long to wait for a response before retrying.
+    ///
+    /// Returns [`INITIAL_RETRY_TIMEOUT`] until the first sample.
+    #[must_use]
+    pub fn retry_timeout(&self) -> Duration {
+        let Some(srtt) = self.smoothed else {
+            return INITIAL_RETRY_TIMEOUT;
+        };
+
+        srtt.saturating_add(self.variance.saturating_mul(4))
+            .clamp(MIN_RETRY_TIMEOUT, MAX_RETRY_TIMEOUT)
+    }
+}
+
+/// Heartbeat bookkeeping for one side of a session.
+///
+/// Stamps outgoing heartbeats relative to an origin instant and turns matching
+/// acks into RTT samples. Only the most recent heartbeat is tracked; an ack for
+/// an older one is ignored, which keeps samples from being inflated by
+/// heartbeats that were lost and superseded.
+#[derive(Debug, Clone)]
+pub struct HeartbeatTracker<I> {
+    origin: I,
+    outstanding: Option<(u64, I)>,
+    rtt: RttEstimator,
+}
+
+impl<I> HeartbeatTracker<I>
+where
+    I: Copy + Sub<Output = Duration>,
+{
+    /// Create a tracker whose timestamps are measured from `origin`.
+    pub fn new(origin: I) -> Self {
+        Self { origin, outstanding: None, rtt: RttEstimator::new() }
+    }
+
+    /// Stamp a new heartbeat sent at `now`, superseding any outstanding one.
+    pub fn start(&mut self, now: I) -> Heartbeat {
+        let elapsed = now - self.origin;
+        let timestamp_micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+
+        self.outstanding = Some((timestamp_micros, now));
+        Heartbeat { timestamp_micros }
+    }
+
+    /// Process an echoed heartbeat received at `now`.
+    ///
+    /// Returns the RTT sample if `ack` matches the outstanding heartbeat.
+    pub fn on_ack(&mut self, ack: &Heartbeat, now: I) -> Option<Duration> {
+        let (timestamp, sent_at) = self.outstanding?;
+        if ack.timestamp_micros != timestamp {
+            return None;
+        }
+
+        self.outstanding = None;
+        let sample = now - sent_at;
+        self.rtt.on_sample(sample);
+        Some(sample)
+    }
+
+    /// Whether a heartbeat is awaiting its ack.
+    #[must_use]
+    pub fn is_outstanding(&self) -> bool {
+        self.outstanding.is_some()
+    }
+
+    /// When the heartbeat awaiting its ack was sent.
+    #[must_use]
+    pub fn outstanding_since(&self) -> Option<I> {
+        self.outstanding.map(|(_, sent_at)| sent_at)
+    }
+
+    /// Stop waiting for the outstanding heartbeat, e.g. because the
+    /// connection it was sent on is gone. A late ack for it is ignored.
+    pub fn abandon(&mut self) {
+        self.outstanding = None;
+    }
+
+    /// Current RTT estimate.
+    #[must_use]
+    pub fn rtt(&self) -> &RttEstimator {
+        &self.rtt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn first_sample_seeds_estimate() {
+        let mut rtt = RttEstimator::new();
+        assert_eq!(rtt.smoothed(), None);
+        assert_eq!(rtt.retry_timeout(), INITIAL_RETRY_TIMEOUT);
+
+        rtt.on_sample(Duration::from_millis(100));
+        assert_eq!(rtt.smoothed(), Some(Duration::from_millis(100)));
+        assert_eq!(rtt.variance(), Duration::from_millis(50));
+        assert_eq!(rtt.retry_timeout(), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn smoothing_converges_and_clamps() {
+        let mut rtt = RttEstimator::new();
+        for _ in 0..100 {
+            rtt.on_sample(Duration::from_millis(10));
+        }
+
+        assert_eq!(rtt.smoothed(), Some(Duration::from_millis(10)));
+        assert_eq!(rtt.retry_timeout(), MIN_RETRY_TIMEOUT);
+
+        rtt.on_sample(Duration::from_secs(600));
+        assert_eq!(rtt.retry_timeout(), MAX_RETRY_TIMEOUT);
+        assert_eq!(rtt.sample_count(), 101);
+    }
+
+    #[test]
+    fn tracker_samples_matching_ack_only() {
+        let t0 = Instant::now();
+        let mut tracker = HeartbeatTracker::new(t0);
+
+        let stale = tracker.start(t0 + Duration::from_secs(1));
+        let current = tracker.start(t0 + Duration::from_secs(2));
+        assert!(tracker.is_outstanding());
+
+        assert_eq!(tracker.on_ack(&stale, t0 + Duration::from_secs(3)), None);
+        assert_eq!(
+            tracker.on_ack(&current, t0 + Duration::from_millis(2250)),
+            Some(Duration::from_millis(250))
+        );
+        assert!(!tracker.is_outstanding());
+
+        // Duplicate ack is ignored
+        assert_eq!(tracker.on_ack(&current, t0 + Duration::from_secs(4)), None);
+        assert_eq!(tracker.rtt().sample_count(), 1);
+    }
+
+    #[test]
+    fn abandoned_heartbeat_is_not_sampled() {
+        let t0 = Instant::now();
+        let mut tracker = HeartbeatTracker::new(t0);
+
+        let sent_at = t0 + Duration::from_secs(1);
+        let heartbeat = tracker.start(sent_at);
+        assert_eq!(tracker.outstanding_since(), Some(sent_at));
+
+        tracker.abandon();
+        assert_eq!(tracker.outstanding_since(), None);
+        assert_eq!(tracker.on_ack(&heartbeat, t0 + Duration::from_secs(2)), None);
+        assert_eq!(tracker.rtt().sample_count(), 0);
+    }
+}
@@ -0,0 +1,256 @@
+//! Hashed timer wheel for retries and timeouts.
+//!
+//! Drivers schedule timers against deadlines derived from
+//! [`Environment::now`](crate::env::Environment::now) and call
+//! [`TimerWheel::advance`] on every tick with the current time. The wheel
+//! never reads a clock itself, so under a simulated environment the same
+//! sequence of calls always fires the same timers in the same order.
+//!
+//! Time is quantized into ticks of a fixed resolution counted from the
+//! wheel's origin. Deadlines round up to the next tick, so a timer never
+//! fires early. Each slot holds timers for every tick congruent to it modulo
+//! the wheel size; timers more than one rotation out simply stay in their
+//! slot until their tick comes around.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Default tick length.
+pub const DEFAULT_RESOLUTION: Duration = Duration::from_millis(10);
+
+/// Default number of slots (one rotation covers about 5 seconds at the
+/// default resolution).
+pub const DEFAULT_SLOTS: usize = 512;
+
+/// Handle for cancelling a scheduled timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(u64);
+
+#[derive(Debug, Clone)]
+struct Timer<T> {
+    id: TimerId,
+    due: u64,
+    payload: T,
+}
+
+/// Timer wheel carrying a payload of type `T` per timer.
+///
+/// Timers that expire in the same [`advance`](Self::advance) call are
+/// returned ordered by tick, then by scheduling order.
+#[derive(Debug, Clone)]
+pub struct TimerWheel<T> {
+    origin: Instant,
+    resolution: Duration,
+    /// Last tick advanced to. Timers due at or before it have fired.
+    current: u64,
+    slots: Vec<Vec<Timer<T>>>,
+    /// Slot of each pending timer
+    pending: HashMap<TimerId, usize>,
+    next_id: u64,
+}
+
+impl<T> TimerWheel<T> {
+    /// Create a wheel starting at `origin` with the default resolution and
+    /// size.
+    #[must_use]
+    pub fn new(origin: Instant) -> Self {
+        Self::with_resolution(origin, DEFAULT_RESOLUTION, DEFAULT_SLOTS)
+    }
+
+    /// Create a wheel with a custom tick length and number of slots.
+    ///
+    /// A zero `resolution` is treated as one nanosecond and zero `slots` as
+    /// one slot.
+    #[must_use]
+    pub fn with_resolution(origin: Instant, resolution: Duration, slots: usize) -> Self {
+        let resolution = resolution.max(Duration::from_nanos(1));
+        let slots = (0..slots.max(1)).map(|_| Vec::new()).collect();
+
+        Self { origin, resolution, current: 0, slots, pending: HashMap::new(), next_id: 0 }
+    }
+
+    /// Number of pending timers.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether no timers are pending.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Schedule `payload` to fire at `deadline`.
+    ///
+    /// A deadline that has already passed fires on the next
+    /// [`advance`](Self::advance).
+    pub fn schedule(&mut self, deadline: Instant, payload: T) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id = self.next_id.saturating_add(1);
+
+        let due = self.tick_ceil(deadline);
+        let slot = self.slot_of(due.max(self.current));
+
+        if let Some(timers) = self.slots.get_mut(slot) {
+            timers.push(Timer { id, due, payload });
+            self.pending.insert(id, slot);
+        }
+
+        id
+    }
+
+    /// Schedule `payload` to fire `delay` after `now`.
+    pub fn schedule_after(&mut self, now: Instant, delay: Duration, payload: T) -> TimerId {
+        let deadline = now.checked_add(delay).unwrap_or(now);
+        self.schedule(deadline, payload)
+    }
+
+    /// Cancel a pending timer, returning its payload.
+    ///
+    /// `None` if the timer already fired or was cancelled.
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        let slot = self.pending.remove(&id)?;
+        let timers = self.slots.get_mut(slot)?;
+        let position = timers.iter().position(|timer| timer.id == id)?;
+        Some(timers.swap_remove(position).payload)
+    }
+
+    /// Fire every timer due at or before `now`.
+    ///
+    /// Time before the last `advance` is ignored, so a clock that stalls
+    /// never re-fires or skips timers.
+    pub fn advance(&mut self, now: Instant) -> Vec<T> {
+        let target = self.tick_floor(now).max(self.current);
+
+        // Rescan the current slot for overdue timers, then each newly
+        // reached tick, at most one full rotation
+        let steps =
+            target.saturating_sub(self.current).min((self.slots.len() as u64).saturating_sub(1));
+
+        let mut fired = Vec::new();
+        for step in 0..=steps {
+            let slot = self.slot_of(self.current.saturating_add(step));
+            let Some(timers) = self.slots.get_mut(slot) else {
+                continue;
+            };
+
+            let mut i = 0;
+            while i < timers.len() {
+                if timers.get(i).is_some_and(|timer| timer.due <= target) {
+                    let timer = timers.swap_remove(i);
+                    self.pending.remove(&timer.id);
+                    fired.push(timer);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        self.current = target;
+
+        fired.sort_by_key(|timer| (timer.due, timer.id));
+        fired.into_iter().map(|timer| timer.payload).collect()
+    }
+
+    /// Earliest time at which a pending timer fires.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let due = self.slots.iter().flatten().map(|timer| timer.due).min()?;
+        Some(self.instant_of(due.max(self.current)))
+    }
+
+    fn slot_of(&self, tick: u64) -> usize {
+        (tick % self.slots.len() as u64) as usize
+    }
+
+    fn tick_floor(&self, instant: Instant) -> u64 {
+        let elapsed = instant.saturating_duration_since(self.origin).as_nanos();
+        u64::try_from(elapsed / self.resolution.as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    fn tick_ceil(&self, instant: Instant) -> u64 {
+        let elapsed = instant.saturating_duration_since(self.origin).as_nanos();
+        u64::try_from(elapsed.div_ceil(self.resolution.as_nanos())).unwrap_or(u64::MAX)
+    }
+
+    fn instant_of(&self, tick: u64) -> Instant {
+        let nanos = self.resolution.as_nanos().saturating_mul(u128::from(tick));
+        let offset = Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX));
+        self.origin.checked_add(offset).unwrap_or(self.origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn fires_at_deadline_not_before() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::with_resolution(start, ms(10), 8);
+
+        wheel.schedule(start + ms(25), "a");
+        assert_eq!(wheel.next_deadline(), Some(start + ms(30)));
+
+        assert!(wheel.advance(start + ms(20)).is_empty());
+        assert!(wheel.advance(start + ms(29)).is_empty());
+        assert_eq!(wheel.advance(start + ms(30)), vec!["a"]);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn fires_in_deadline_then_schedule_order() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::with_resolution(start, ms(10), 8);
+
+        wheel.schedule(start + ms(50), 3);
+        wheel.schedule(start + ms(20), 1);
+        wheel.schedule(start + ms(50), 4);
+        wheel.schedule(start + ms(20), 2);
+
+        assert_eq!(wheel.advance(start + ms(100)), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn timers_beyond_one_rotation_wait() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::with_resolution(start, ms(10), 4);
+
+        // Ticks 2 and 6 share a slot
+        wheel.schedule(start + ms(20), "near");
+        wheel.schedule(start + ms(60), "far");
+
+        assert_eq!(wheel.advance(start + ms(20)), vec!["near"]);
+        assert!(wheel.advance(start + ms(50)).is_empty());
+        assert_eq!(wheel.advance(start + ms(60)), vec!["far"]);
+
+        // Jumping many rotations at once fires everything due
+        wheel.schedule(start + ms(70), "x");
+        wheel.schedule(start + ms(500), "y");
+        assert_eq!(wheel.advance(start + ms(10_000)), vec!["x", "y"]);
+    }
+
+    #[test]
+    fn cancel_and_overdue() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::with_resolution(start, ms(10), 8);
+
+        let id = wheel.schedule_after(start, ms(30), "cancelled");
+        assert_eq!(wheel.cancel(id), Some("cancelled"));
+        assert_eq!(wheel.cancel(id), None);
+
+        wheel.advance(start + ms(100));
+        wheel.schedule(start + ms(40), "overdue");
+        assert_eq!(wheel.advance(start + ms(100)), vec!["overdue"]);
+
+        // A stalled clock never re-fires
+        assert!(wheel.advance(start + ms(50)).is_empty());
+    }
+}
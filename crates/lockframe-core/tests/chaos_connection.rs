@@ -99,6 +99,7 @@ fn prop_connection_never_panics_on_invalid_frames() {
                 conn.state(),
                 ConnectionState::Init
                     | ConnectionState::Pending
+                    | ConnectionState::Challenged
                     | ConnectionState::Authenticated
                     | ConnectionState::Closed
             ),
@@ -134,7 +135,7 @@ fn prop_connection_state_transitions_valid() {
         let state_order = |s: &ConnectionState| -> u8 {
             match s {
                 ConnectionState::Init => 0,
-                ConnectionState::Pending => 1,
+                ConnectionState::Pending | ConnectionState::Challenged => 1,
                 ConnectionState::Authenticated => 2,
                 ConnectionState::Closed => 3,
             }
@@ -43,6 +43,7 @@ fn config_strategy() -> impl Strategy<Value = ConnectionConfig> {
         handshake_timeout: Duration::from_secs(handshake),
         idle_timeout: Duration::from_secs(idle),
         heartbeat_interval: Duration::from_secs(heartbeat),
+        ..ConnectionConfig::default()
     })
 }
 
@@ -93,6 +94,7 @@ fn prop_state_never_goes_backward() {
             session_id,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let frame = hello_reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         let _ = conn.handle_frame(&frame, now);
@@ -176,6 +178,7 @@ fn prop_idle_timeout_only_authenticated() {
             session_id,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let frame = hello_reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         let _ = conn.handle_frame(&frame, now);
@@ -209,7 +212,7 @@ fn prop_heartbeats_only_authenticated() {
 
         let has_heartbeat = actions.iter().any(|a| {
             matches!(a, ConnectionAction::SendFrame(frame)
-                if frame.header.opcode_enum() == Some(Opcode::Ping))
+                if frame.header.opcode_enum() == Some(Opcode::Heartbeat))
         });
         assert!(!has_heartbeat, "Init state should not send heartbeats");
 
@@ -221,7 +224,7 @@ fn prop_heartbeats_only_authenticated() {
 
         let has_heartbeat = actions.iter().any(|a| {
             matches!(a, ConnectionAction::SendFrame(frame)
-                if frame.header.opcode_enum() == Some(Opcode::Ping))
+                if frame.header.opcode_enum() == Some(Opcode::Heartbeat))
         });
         assert!(!has_heartbeat, "Pending state should not send heartbeats");
 
@@ -233,6 +236,7 @@ fn prop_heartbeats_only_authenticated() {
             session_id,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let frame = hello_reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         let _ = conn.handle_frame(&frame, now);
@@ -245,7 +249,7 @@ fn prop_heartbeats_only_authenticated() {
 
             let has_heartbeat = actions.iter().any(|a| {
                 matches!(a, ConnectionAction::SendFrame(frame)
-                    if frame.header.opcode_enum() == Some(Opcode::Ping))
+                    if frame.header.opcode_enum() == Some(Opcode::Heartbeat))
             });
             assert!(has_heartbeat, "Authenticated state should send heartbeats when heartbeat_interval < idle_timeout");
         }
@@ -270,6 +274,7 @@ fn prop_session_id_immutable() {
             session_id: session_id1,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let frame1 = hello_reply1.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         let _ = conn.handle_frame(&frame1, now);
@@ -281,6 +286,7 @@ fn prop_session_id_immutable() {
             session_id: session_id2,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let frame2 = hello_reply2.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
 
@@ -311,6 +317,7 @@ fn prop_activity_resets_timeout() {
             session_id,
             capabilities: vec![],
             challenge: None,
+            time_sync: None,
         });
         let frame = hello_reply.into_frame(FrameHeader::new(Opcode::HelloReply)).unwrap();
         let _ = conn.handle_frame(&frame, now);
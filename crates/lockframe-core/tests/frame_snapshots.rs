@@ -71,6 +71,7 @@ fn snapshot_hello_reply_frame() {
         session_id: 0x1000_0000_0000_0000,
         capabilities: vec![],
         challenge: None,
+        time_sync: None,
     });
 
     let frame = reply
@@ -86,6 +87,7 @@ fn snapshot_hello_reply_frame_with_challenge() {
         session_id: 0x1000_0000_0000_0000,
         capabilities: vec!["mls".to_string()],
         challenge: Some(vec![0x01, 0x02, 0x03, 0x04]),
+        time_sync: None,
     });
 
     let frame = reply
@@ -132,6 +134,8 @@ fn snapshot_encrypted_message_frame() {
         epoch: 1,
         sender_index: 42,
         generation: 0,
+        padding: 0,
+        cipher_suite: 0,
         nonce: [0x02; 24],
         ciphertext: vec![0xca, 0xfe, 0xba, 0xbe],
         push_keys: None,
@@ -301,7 +305,9 @@ fn snapshot_error_frame() {
     let error = Payload::Error(ErrorPayload {
         code: 400,
         message: "Invalid request".to_string(),
+        retryable: false,
         retry_after: None,
+        room_full: None,
     });
 
     let frame =
@@ -315,7 +321,9 @@ fn snapshot_error_frame_with_retry() {
     let error = Payload::Error(ErrorPayload {
         code: 429,
         message: "Rate limit exceeded".to_string(),
+        retryable: true,
         retry_after: Some(60),
+        room_full: None,
     });
 
     let frame =
@@ -22,7 +22,7 @@
 //! Symmetric Ratchet → Message Keys
 //!        │
 //!        ▼
-//! AEAD Encryption → Ciphertext
+//! Padding → AEAD Encryption → Ciphertext
 //! ```
 //!
 //! Message keys are used for exactly one encryption operation and are
@@ -42,7 +42,7 @@
 //! - MLS provides sender authentication at the control plane level
 //!
 //! Authenticity:
-//! - XChaCha20-Poly1305 AEAD provides tamper-proof encryption
+//! - AEAD (XChaCha20-Poly1305 or AES-256-GCM) provides tamper-proof encryption
 //! - Nonce structure binds message to (epoch, sender, generation)
 //! - Failed authentication tag -> reject message
 //!
@@ -50,13 +50,24 @@
 //! - MLS commit advances epoch -> new epoch secret
 //! - New epoch secret -> all sender keys re-derived from scratch
 //! - Previous compromise doesn't affect new epoch's messages
+//!
+//! # Peer Verification
+//!
+//! [`verification`] derives safety numbers and short authentication strings
+//! that members compare out of band to detect keys substituted by the server.
 
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
 
 pub mod sender_keys;
+pub mod verification;
 
 pub use sender_keys::{
-    EncryptedMessage, MessageKey, NONCE_RANDOM_SIZE, SenderKeyError, SymmetricRatchet,
-    decrypt_message, derive_sender_key_seed, encrypt_message,
+    CIPHER_SUITE_AES_256_GCM, CIPHER_SUITE_XCHACHA20_POLY1305, CipherSuite,
+    DEFAULT_MAX_GENERATION_JUMP, EncryptedMessage, MessageKey, NONCE_RANDOM_SIZE, PADDING_BUCKETS,
+    PADDING_NONE, Padding, SenderKeyError, SymmetricRatchet, decrypt_message,
+    derive_sender_key_seed, encrypt_message, encrypt_padded,
+};
+pub use verification::{
+    PeerIdentity, fingerprint, safety_number, short_auth_string, verification_payload,
 };
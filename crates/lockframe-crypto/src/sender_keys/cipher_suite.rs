@@ -0,0 +1,92 @@
+//! AEAD suites messages are encrypted with.
+//!
+//! XChaCha20-Poly1305 is fast everywhere in software; AES-256-GCM is faster
+//! on hardware with AES instructions. A room encrypts with the suite matching
+//! the AEAD of its MLS ciphersuite, so its members agree on it through the
+//! group they already share rather than a separate negotiation. The suite is
+//! recorded in every message, so a receiver never has to guess it.
+
+use super::error::SenderKeyError;
+
+/// Suite identifier of messages encrypted with XChaCha20-Poly1305.
+pub const CIPHER_SUITE_XCHACHA20_POLY1305: u8 = 0;
+
+/// Suite identifier of messages encrypted with AES-256-GCM.
+pub const CIPHER_SUITE_AES_256_GCM: u8 = 1;
+
+/// AEAD a message is encrypted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CipherSuite {
+    /// XChaCha20-Poly1305 with the full 24-byte nonce.
+    #[default]
+    XChaCha20Poly1305,
+    /// AES-256-GCM with the last 12 bytes of the nonce (generation and random
+    /// suffix). Every message key encrypts a single message, so the shorter
+    /// nonce never repeats under a key.
+    Aes256Gcm,
+}
+
+impl CipherSuite {
+    /// Suite identifier recorded with messages encrypted this way.
+    pub fn id(self) -> u8 {
+        match self {
+            Self::XChaCha20Poly1305 => CIPHER_SUITE_XCHACHA20_POLY1305,
+            Self::Aes256Gcm => CIPHER_SUITE_AES_256_GCM,
+        }
+    }
+
+    /// Suite with identifier `id`.
+    ///
+    /// # Errors
+    ///
+    /// - `UnsupportedCipherSuite` if `id` names no suite we implement
+    pub fn from_id(id: u8) -> Result<Self, SenderKeyError> {
+        match id {
+            CIPHER_SUITE_XCHACHA20_POLY1305 => Ok(Self::XChaCha20Poly1305),
+            CIPHER_SUITE_AES_256_GCM => Ok(Self::Aes256Gcm),
+            _ => Err(SenderKeyError::UnsupportedCipherSuite { id }),
+        }
+    }
+
+    /// Suite for a room whose MLS group uses ciphersuite `mls_ciphersuite`
+    /// (its IANA identifier, RFC 9420 §17.1).
+    ///
+    /// Groups on an AES-GCM ciphersuite encrypt messages with AES-256-GCM,
+    /// everything else with XChaCha20-Poly1305.
+    pub fn for_mls_ciphersuite(mls_ciphersuite: u16) -> Self {
+        match mls_ciphersuite {
+            // MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519,
+            // MLS_128_DHKEMP256_AES128GCM_SHA256_P256,
+            // MLS_256_DHKEMX448_AES256GCM_SHA512_Ed448,
+            // MLS_256_DHKEMP521_AES256GCM_SHA512_P521,
+            // MLS_256_DHKEMP384_AES256GCM_SHA384_P384
+            0x0001 | 0x0002 | 0x0004 | 0x0005 | 0x0007 => Self::Aes256Gcm,
+            _ => Self::XChaCha20Poly1305,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_round_trip() {
+        for suite in [CipherSuite::XChaCha20Poly1305, CipherSuite::Aes256Gcm] {
+            assert_eq!(CipherSuite::from_id(suite.id()).unwrap(), suite);
+        }
+        assert!(matches!(
+            CipherSuite::from_id(7),
+            Err(SenderKeyError::UnsupportedCipherSuite { id: 7 })
+        ));
+    }
+
+    #[test]
+    fn mls_ciphersuites_pick_the_matching_aead() {
+        assert_eq!(CipherSuite::for_mls_ciphersuite(0x0001), CipherSuite::Aes256Gcm);
+        assert_eq!(CipherSuite::for_mls_ciphersuite(0x0003), CipherSuite::XChaCha20Poly1305);
+        assert_eq!(CipherSuite::for_mls_ciphersuite(0x0007), CipherSuite::Aes256Gcm);
+        assert_eq!(CipherSuite::for_mls_ciphersuite(0xf000), CipherSuite::XChaCha20Poly1305);
+    }
+}
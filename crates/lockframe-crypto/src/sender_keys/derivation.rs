@@ -2,6 +2,7 @@
 
 use hkdf::Hkdf;
 use sha2::Sha256;
+use zeroize::Zeroizing;
 
 /// Label used for sender key derivation
 const SENDER_KEY_LABEL: &[u8] = b"lockframeSenderV1";
@@ -17,7 +18,12 @@ const SENDER_KEY_LABEL: &[u8] = b"lockframeSenderV1";
 ///   boundary)
 /// - Different senders produce different seeds (sender isolation)
 /// - Deterministic: same inputs always produce same output
-pub fn derive_sender_key_seed(epoch_secret: &[u8], epoch: u64, sender_index: u32) -> [u8; 32] {
+/// - The seed is zeroized when dropped
+pub fn derive_sender_key_seed(
+    epoch_secret: &[u8],
+    epoch: u64,
+    sender_index: u32,
+) -> Zeroizing<[u8; 32]> {
     // Use HKDF with the epoch secret as the PRK
     // We extract first to ensure the key material is properly distributed
     let hkdf = Hkdf::<Sha256>::new(None, epoch_secret);
@@ -29,8 +35,8 @@ pub fn derive_sender_key_seed(epoch_secret: &[u8], epoch: u64, sender_index: u32
     info.extend_from_slice(&epoch.to_be_bytes());
     info.extend_from_slice(&sender_index.to_be_bytes());
 
-    let mut seed = [0u8; 32];
-    let Ok(()) = hkdf.expand(&info, &mut seed) else {
+    let mut seed = Zeroizing::new([0u8; 32]);
+    let Ok(()) = hkdf.expand(&info, seed.as_mut_slice()) else {
         unreachable!("32 bytes is a valid HKDF-SHA256 output length");
     };
 
@@ -47,7 +53,7 @@ pub fn derive_all_sender_seeds(
     epoch_secret: &[u8],
     epoch: u64,
     member_indices: &[u32],
-) -> Vec<(u32, [u8; 32])> {
+) -> Vec<(u32, Zeroizing<[u8; 32]>)> {
     member_indices
         .iter()
         .map(|&index| (index, derive_sender_key_seed(epoch_secret, epoch, index)))
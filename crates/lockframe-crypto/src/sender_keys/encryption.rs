@@ -1,20 +1,30 @@
-//! Message encryption using `XChaCha20-Poly1305`
+//! Message encryption using `XChaCha20-Poly1305` or `AES-256-GCM`
 //!
 //! All functions are pure - random bytes must be provided by the caller.
 //! This enables deterministic testing and maintains action-based compatibility.
 
+use aes_gcm::Aes256Gcm;
 use chacha20poly1305::{
     XChaCha20Poly1305, XNonce,
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
 };
 
-use super::{error::SenderKeyError, ratchet::MessageKey};
+use super::{
+    cipher_suite::CipherSuite,
+    error::SenderKeyError,
+    padding::{PADDING_NONE, Padding, unpad},
+    ratchet::MessageKey,
+};
 
 /// Size of the random suffix in the nonce (8 bytes)
 pub const NONCE_RANDOM_SIZE: usize = 8;
 
-/// Poly1305 tag size (16 bytes)
-const POLY1305_TAG_SIZE: usize = 16;
+/// Authentication tag size, the same for every suite (16 bytes)
+const TAG_SIZE: usize = 16;
+
+/// Start of the 12-byte AES-GCM nonce within the 24-byte nonce (the
+/// generation, followed by the random suffix)
+const GCM_NONCE_OFFSET: usize = 12;
 
 /// An encrypted message with metadata for decryption.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -25,20 +35,25 @@ pub struct EncryptedMessage {
     pub sender_index: u32,
     /// The ratchet generation (for key derivation)
     pub generation: u32,
-    /// The 24-byte `XChaCha20` nonce
+    /// Padding scheme of the plaintext, authenticated as associated data
+    pub padding: u8,
+    /// AEAD suite the message is encrypted with (see [`CipherSuite::id`])
+    pub cipher_suite: u8,
+    /// The 24-byte nonce, of which `AES-256-GCM` uses the last 12 bytes
     pub nonce: [u8; 24],
-    /// The ciphertext including 16-byte Poly1305 tag
+    /// The ciphertext including the 16-byte authentication tag
     pub ciphertext: Vec<u8>,
 }
 
 impl EncryptedMessage {
-    /// Plaintext length (ciphertext length minus authentication tag).
+    /// Plaintext length including any padding (ciphertext length minus
+    /// authentication tag).
     pub fn plaintext_len(&self) -> usize {
-        self.ciphertext.len().saturating_sub(POLY1305_TAG_SIZE)
+        self.ciphertext.len().saturating_sub(TAG_SIZE)
     }
 }
 
-/// Encrypt a message using `XChaCha20-Poly1305`.
+/// Encrypt a message using `XChaCha20-Poly1305`, without padding.
 ///
 /// Returns `EncryptedMessage` containing the ciphertext and metadata.
 ///
@@ -54,30 +69,62 @@ pub fn encrypt_message(
     epoch: u64,
     sender_index: u32,
     random_suffix: [u8; NONCE_RANDOM_SIZE],
+) -> EncryptedMessage {
+    encrypt_padded(
+        plaintext,
+        Padding::None,
+        CipherSuite::XChaCha20Poly1305,
+        message_key,
+        epoch,
+        sender_index,
+        random_suffix,
+    )
+}
+
+/// Pad a message with `padding`, then encrypt it with `suite` like
+/// [`encrypt_message`].
+///
+/// The padding scheme and suite are recorded in the message, and the padding
+/// scheme is bound to the ciphertext as associated data, along with the
+/// full nonce under `AES-256-GCM`.
+pub fn encrypt_padded(
+    plaintext: &[u8],
+    padding: Padding,
+    suite: CipherSuite,
+    message_key: &MessageKey,
+    epoch: u64,
+    sender_index: u32,
+    random_suffix: [u8; NONCE_RANDOM_SIZE],
 ) -> EncryptedMessage {
     let nonce = build_nonce(epoch, sender_index, message_key.generation(), random_suffix);
-    let cipher = XChaCha20Poly1305::new(message_key.key().into());
 
-    let Ok(ciphertext) = cipher.encrypt(XNonce::from_slice(&nonce), plaintext) else {
-        unreachable!("XChaCha20-Poly1305 encryption cannot fail with valid inputs");
+    let padded = padding.pad(plaintext);
+    let aad = associated_data(suite, &nonce, padding.scheme());
+    let payload = Payload { msg: &padded, aad: &aad };
+    let Ok(ciphertext) = seal(suite, message_key, &nonce, payload) else {
+        unreachable!("AEAD encryption cannot fail with valid inputs");
     };
 
     EncryptedMessage {
         epoch,
         sender_index,
         generation: message_key.generation(),
+        padding: padding.scheme(),
+        cipher_suite: suite.id(),
         nonce,
         ciphertext,
     }
 }
 
-/// Decrypt a message using `XChaCha20-Poly1305`.
+/// Decrypt a message with the suite it names.
 ///
-/// Returns the decrypted plaintext.
+/// Returns the decrypted plaintext with its padding removed.
 ///
 /// # Errors
 ///
+/// - `UnsupportedCipherSuite`: If the message names an unknown suite
 /// - `DecryptionFailed`: If authentication tag or key is incorrect (tamper)
+/// - `InvalidPadding`: If the plaintext is not padded as its scheme says
 pub fn decrypt_message(
     encrypted: &EncryptedMessage,
     message_key: &MessageKey,
@@ -92,13 +139,70 @@ pub fn decrypt_message(
         });
     }
 
-    let cipher = XChaCha20Poly1305::new(message_key.key().into());
-    let nonce = XNonce::from_slice(&encrypted.nonce);
-    let plaintext = encrypted.ciphertext.as_slice();
+    let suite = CipherSuite::from_id(encrypted.cipher_suite)?;
+    let aad = associated_data(suite, &encrypted.nonce, encrypted.padding);
+    let payload = Payload { msg: &encrypted.ciphertext, aad: &aad };
 
-    cipher.decrypt(nonce, plaintext).map_err(|_| SenderKeyError::DecryptionFailed {
-        reason: "authentication failed".to_string(),
-    })
+    let padded = open(suite, message_key, &encrypted.nonce, payload).map_err(|_| {
+        SenderKeyError::DecryptionFailed { reason: "authentication failed".to_string() }
+    })?;
+    unpad(encrypted.padding, padded)
+}
+
+/// Encrypt `payload` with `suite`.
+fn seal(
+    suite: CipherSuite,
+    message_key: &MessageKey,
+    nonce: &[u8; 24],
+    payload: Payload<'_, '_>,
+) -> Result<Vec<u8>, aes_gcm::Error> {
+    match suite {
+        CipherSuite::XChaCha20Poly1305 => XChaCha20Poly1305::new(message_key.key().into())
+            .encrypt(XNonce::from_slice(nonce), payload),
+        CipherSuite::Aes256Gcm => {
+            Aes256Gcm::new(message_key.key().into()).encrypt(gcm_nonce(nonce), payload)
+        },
+    }
+}
+
+/// Decrypt and authenticate `payload` with `suite`.
+fn open(
+    suite: CipherSuite,
+    message_key: &MessageKey,
+    nonce: &[u8; 24],
+    payload: Payload<'_, '_>,
+) -> Result<Vec<u8>, aes_gcm::Error> {
+    match suite {
+        CipherSuite::XChaCha20Poly1305 => XChaCha20Poly1305::new(message_key.key().into())
+            .decrypt(XNonce::from_slice(nonce), payload),
+        CipherSuite::Aes256Gcm => {
+            Aes256Gcm::new(message_key.key().into()).decrypt(gcm_nonce(nonce), payload)
+        },
+    }
+}
+
+/// AES-GCM nonce: the generation and random suffix ending the full nonce.
+fn gcm_nonce(nonce: &[u8; 24]) -> &aes_gcm::Nonce<aes_gcm::aead::consts::U12> {
+    let (_, tail) = nonce.split_at(GCM_NONCE_OFFSET);
+    aes_gcm::Nonce::from_slice(tail)
+}
+
+/// Associated data binding the padding scheme to the ciphertext.
+///
+/// `AES-256-GCM` only takes the last 12 nonce bytes as its nonce, so the
+/// whole nonce is authenticated here to bind the epoch and sender index it
+/// leaves out. `XChaCha20` already takes the whole nonce; its unpadded
+/// messages have no associated data, so they decrypt as they did before
+/// padding.
+fn associated_data(suite: CipherSuite, nonce: &[u8; 24], scheme: u8) -> Vec<u8> {
+    let mut aad = match suite {
+        CipherSuite::XChaCha20Poly1305 => Vec::new(),
+        CipherSuite::Aes256Gcm => nonce.to_vec(),
+    };
+    if scheme != PADDING_NONE {
+        aad.push(scheme);
+    }
+    aad
 }
 
 /// Build a 24-byte nonce for `XChaCha20`.
@@ -218,7 +322,59 @@ mod tests {
         let encrypted = encrypt_message(plaintext, &message_key, 0, 0, random_suffix);
 
         // Ciphertext should be plaintext + 16-byte tag
-        assert_eq!(encrypted.ciphertext.len(), plaintext.len() + POLY1305_TAG_SIZE);
+        assert_eq!(encrypted.ciphertext.len(), plaintext.len() + TAG_SIZE);
+    }
+
+    #[test]
+    fn aes_256_gcm_roundtrip() {
+        let message_key = test_message_key(3);
+        let encrypted = encrypt_padded(
+            b"accelerated",
+            Padding::default(),
+            CipherSuite::Aes256Gcm,
+            &message_key,
+            1,
+            2,
+            [0x5A; NONCE_RANDOM_SIZE],
+        );
+
+        assert_eq!(encrypted.cipher_suite, CipherSuite::Aes256Gcm.id());
+        assert_eq!(decrypt_message(&encrypted, &message_key).unwrap(), b"accelerated");
+
+        // The same key and nonce under the other suite is a different cipher
+        let xchacha = encrypt_padded(
+            b"accelerated",
+            Padding::default(),
+            CipherSuite::XChaCha20Poly1305,
+            &message_key,
+            1,
+            2,
+            [0x5A; NONCE_RANDOM_SIZE],
+        );
+        assert_eq!(xchacha.nonce, encrypted.nonce);
+        assert_ne!(xchacha.ciphertext, encrypted.ciphertext);
+
+        // The epoch and sender index are outside the GCM nonce, but still
+        // authenticated
+        let mut moved = encrypted.clone();
+        moved.nonce[0] ^= 1;
+        assert!(matches!(
+            decrypt_message(&moved, &message_key),
+            Err(SenderKeyError::DecryptionFailed { .. })
+        ));
+
+        // Relabelling the suite fails authentication rather than misdecrypting
+        let mut relabelled = encrypted;
+        relabelled.cipher_suite = CipherSuite::XChaCha20Poly1305.id();
+        assert!(matches!(
+            decrypt_message(&relabelled, &message_key),
+            Err(SenderKeyError::DecryptionFailed { .. })
+        ));
+        relabelled.cipher_suite = 0xFF;
+        assert!(matches!(
+            decrypt_message(&relabelled, &message_key),
+            Err(SenderKeyError::UnsupportedCipherSuite { id: 0xFF })
+        ));
     }
 
     #[test]
@@ -294,6 +450,35 @@ mod tests {
         assert_eq!(&nonce[16..24], &[0xAB; 8]);
     }
 
+    #[test]
+    fn padded_roundtrip_hides_length() {
+        let message_key = test_message_key(0);
+        let padding = Padding::default();
+
+        let suite = CipherSuite::default();
+        let short =
+            encrypt_padded(b"yes", padding, suite, &message_key, 0, 0, [0x00; NONCE_RANDOM_SIZE]);
+        let longer = encrypt_padded(
+            b"no, never",
+            padding,
+            suite,
+            &message_key,
+            0,
+            0,
+            [0x00; NONCE_RANDOM_SIZE],
+        );
+        assert_eq!(short.ciphertext.len(), longer.ciphertext.len());
+        assert_eq!(decrypt_message(&short, &message_key).unwrap(), b"yes");
+
+        // The scheme is authenticated, so stripping it fails decryption
+        let mut stripped = short;
+        stripped.padding = PADDING_NONE;
+        assert!(matches!(
+            decrypt_message(&stripped, &message_key),
+            Err(SenderKeyError::DecryptionFailed { .. })
+        ));
+    }
+
     #[test]
     fn plaintext_len_calculation() {
         let message_key = test_message_key(0);
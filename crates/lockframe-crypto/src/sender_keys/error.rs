@@ -22,6 +22,21 @@ pub enum SenderKeyError {
         requested: u32,
     },
 
+    /// Message claims a generation further ahead of the ratchet than it is
+    /// allowed to skip, which would take unbounded work to reach
+    #[error(
+        "generation jump too large: at generation {current}, need {requested}, \
+         max jump {max_jump}"
+    )]
+    GenerationJumpTooLarge {
+        /// Current ratchet generation
+        current: u32,
+        /// Requested generation
+        requested: u32,
+        /// Most generations the ratchet skips over
+        max_jump: u32,
+    },
+
     /// Decryption failed (authentication tag mismatch)
     #[error("decryption failed: {reason}")]
     DecryptionFailed {
@@ -47,6 +62,29 @@ pub enum SenderKeyError {
         actual: usize,
     },
 
+    /// Decrypted plaintext is not padded as its scheme says
+    #[error("invalid padding: {reason}")]
+    InvalidPadding {
+        /// Reason the padding was rejected
+        reason: String,
+    },
+
+    /// Message names an AEAD suite we don't implement
+    #[error("unsupported cipher suite: {id}")]
+    UnsupportedCipherSuite {
+        /// Suite identifier in the message
+        id: u8,
+    },
+
+    /// Message is encrypted with a different AEAD suite than its room uses
+    #[error("cipher suite mismatch: expected {expected}, got {actual}")]
+    CipherSuiteMismatch {
+        /// Suite identifier of the room
+        expected: u8,
+        /// Suite identifier in the message
+        actual: u8,
+    },
+
     /// Ratchet generation would overflow
     #[error("ratchet generation overflow at {current}")]
     GenerationOverflow {
@@ -64,12 +102,16 @@ impl SenderKeyError {
         match self {
             // Protocol violations - fatal
             Self::DecryptionFailed { .. }
+            | Self::InvalidPadding { .. }
             | Self::InvalidKeyLength { .. }
+            | Self::UnsupportedCipherSuite { .. }
+            | Self::CipherSuiteMismatch { .. }
             | Self::GenerationOverflow { .. } => true,
 
             // Potentially recoverable - need state sync
             Self::UnknownSender { .. }
             | Self::RatchetTooFarBehind { .. }
+            | Self::GenerationJumpTooLarge { .. }
             | Self::EpochMismatch { .. } => false,
         }
     }
@@ -97,6 +139,13 @@ mod tests {
         assert!(!err.is_fatal());
     }
 
+    #[test]
+    fn generation_jump_too_large_is_not_fatal() {
+        let err =
+            SenderKeyError::GenerationJumpTooLarge { current: 0, requested: u32::MAX, max_jump: 1 };
+        assert!(!err.is_fatal());
+    }
+
     #[test]
     fn error_display() {
         let err = SenderKeyError::RatchetTooFarBehind { current: 10, requested: 100 };
@@ -7,7 +7,8 @@
 //!
 //! Each epoch, MLS gives us an epoch secret. We derive a unique seed for each
 //! sender (via HKDF), initialize a symmetric ratchet, and use that to generate
-//! message keys. Messages are encrypted with XChaCha20-Poly1305.
+//! message keys. Messages are padded to hide their length and encrypted with
+//! the room's AEAD suite, XChaCha20-Poly1305 or AES-256-GCM.
 //!
 //! # Security
 //!
@@ -15,12 +16,18 @@
 //! compromising one sender doesn't expose other senders' messages. AEAD
 //! prevents tampering and provides sender authentication.
 
+pub mod cipher_suite;
 pub mod derivation;
 pub mod encryption;
 pub mod error;
+pub mod padding;
 pub mod ratchet;
 
+pub use cipher_suite::{CIPHER_SUITE_AES_256_GCM, CIPHER_SUITE_XCHACHA20_POLY1305, CipherSuite};
 pub use derivation::derive_sender_key_seed;
-pub use encryption::{EncryptedMessage, NONCE_RANDOM_SIZE, decrypt_message, encrypt_message};
+pub use encryption::{
+    EncryptedMessage, NONCE_RANDOM_SIZE, decrypt_message, encrypt_message, encrypt_padded,
+};
 pub use error::SenderKeyError;
-pub use ratchet::{MessageKey, SymmetricRatchet};
+pub use padding::{PADDING_BUCKETS, PADDING_NONE, Padding};
+pub use ratchet::{DEFAULT_MAX_GENERATION_JUMP, MessageKey, SymmetricRatchet};
@@ -0,0 +1,135 @@
+//! Plaintext padding before encryption.
+//!
+//! AEAD ciphertext is exactly as long as its plaintext plus the tag, so
+//! without padding anyone relaying a message learns its length, and from the
+//! length often what it says ("yes", a reaction, a typing notice). Padding to
+//! a small set of bucket sizes leaves only the bucket visible.
+//!
+//! Padded plaintext is `plaintext || 0x80 || 0x00*` (ISO/IEC 7816-4), which
+//! strips unambiguously without knowing the bucket sizes the sender used. The
+//! scheme travels with the message and is authenticated as associated data,
+//! so it cannot be switched to make a receiver keep the padding.
+
+use super::error::SenderKeyError;
+
+/// Scheme identifier of unpadded messages.
+pub const PADDING_NONE: u8 = 0;
+
+/// Scheme identifier of messages padded to a [`Padding::Buckets`] size.
+pub const PADDING_BUCKETS: u8 = 1;
+
+/// Marker byte separating the plaintext from the zero padding.
+const PADDING_MARKER: u8 = 0x80;
+
+/// How plaintext is padded before encryption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    /// Not padded. The ciphertext length reveals the plaintext length.
+    None,
+    /// Padded to the next power of two from `min_bucket` up to `max_bucket`,
+    /// and beyond that to the next multiple of `max_bucket`.
+    Buckets {
+        /// Smallest padded size, in bytes.
+        min_bucket: usize,
+        /// Largest power-of-two bucket, in bytes.
+        max_bucket: usize,
+    },
+}
+
+impl Default for Padding {
+    /// Buckets from 256 bytes to 64 kibibytes: short messages all look
+    /// alike, and large ones pay at most 64 kibibytes of padding.
+    fn default() -> Self {
+        Self::Buckets { min_bucket: 256, max_bucket: 64 * 1024 }
+    }
+}
+
+impl Padding {
+    /// Scheme identifier recorded with messages padded this way.
+    pub fn scheme(self) -> u8 {
+        match self {
+            Self::None => PADDING_NONE,
+            Self::Buckets { .. } => PADDING_BUCKETS,
+        }
+    }
+
+    /// Length of `len` bytes of plaintext once padded.
+    pub fn padded_len(self, len: usize) -> usize {
+        match self {
+            Self::None => len,
+            Self::Buckets { min_bucket, max_bucket } => {
+                let needed = len.saturating_add(1);
+                let max_bucket = max_bucket.max(1);
+                if needed <= max_bucket {
+                    needed.next_power_of_two().clamp(min_bucket.min(max_bucket), max_bucket)
+                } else {
+                    needed.div_ceil(max_bucket).saturating_mul(max_bucket)
+                }
+            },
+        }
+    }
+
+    /// Pad `plaintext` to [`padded_len`](Self::padded_len).
+    pub fn pad(self, plaintext: &[u8]) -> Vec<u8> {
+        let mut padded = plaintext.to_vec();
+        if self != Self::None {
+            padded.push(PADDING_MARKER);
+            padded.resize(self.padded_len(plaintext.len()), 0);
+        }
+        padded
+    }
+}
+
+/// Strip the padding of `scheme` from decrypted plaintext.
+///
+/// # Errors
+///
+/// - `InvalidPadding`: unknown scheme, or no marker after the zero padding
+pub fn unpad(scheme: u8, mut padded: Vec<u8>) -> Result<Vec<u8>, SenderKeyError> {
+    match scheme {
+        PADDING_NONE => Ok(padded),
+        PADDING_BUCKETS => {
+            let marker = padded.iter().rposition(|&byte| byte != 0);
+            match marker {
+                Some(end) if padded.get(end) == Some(&PADDING_MARKER) => {
+                    padded.truncate(end);
+                    Ok(padded)
+                },
+                _ => Err(SenderKeyError::InvalidPadding { reason: "missing marker".to_string() }),
+            }
+        },
+        _ => Err(SenderKeyError::InvalidPadding { reason: format!("unknown scheme {scheme}") }),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_hide_lengths() {
+        let padding = Padding::default();
+        assert_eq!(padding.padded_len(0), 256);
+        assert_eq!(padding.padded_len(255), 256);
+        assert_eq!(padding.padded_len(256), 512);
+        assert_eq!(padding.padded_len(64 * 1024 - 1), 64 * 1024);
+        assert_eq!(padding.padded_len(64 * 1024), 128 * 1024);
+        assert_eq!(padding.padded_len(200 * 1024), 256 * 1024);
+        assert_eq!(Padding::None.padded_len(3), 3);
+
+        for plaintext in [&b""[..], b"yes", &[0u8; 300], &[0x80; 255]] {
+            let padded = padding.pad(plaintext);
+            assert_eq!(padded.len(), padding.padded_len(plaintext.len()));
+            assert_eq!(unpad(padding.scheme(), padded).unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn malformed_padding_is_rejected() {
+        assert!(unpad(PADDING_BUCKETS, vec![0; 16]).is_err());
+        assert!(unpad(PADDING_BUCKETS, b"no marker\x01\0\0".to_vec()).is_err());
+        assert!(unpad(7, b"hello".to_vec()).is_err());
+        assert_eq!(unpad(PADDING_NONE, b"hello\0".to_vec()).unwrap(), b"hello\0");
+    }
+}
@@ -8,6 +8,7 @@
 
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use super::error::SenderKeyError;
 
@@ -19,24 +20,31 @@ const CHAIN_LABEL: &[u8] = b"chain";
 /// Label for deriving a message key
 const MESSAGE_LABEL: &[u8] = b"message";
 
-/// Maximum number of generations to skip when catching up.
-/// This limits the work done when receiving out-of-order messages.
-const MAX_SKIP: u32 = 1000;
+/// Default for the most generations a ratchet skips over to reach a message.
+///
+/// Each skipped generation costs two HMACs, so without a bound a sender
+/// claiming generation `u32::MAX` could make a receiver do billions of them.
+pub const DEFAULT_MAX_GENERATION_JUMP: u32 = 1000;
 
 /// A message key derived from the ratchet.
 ///
 /// This key is used for a single message encryption/decryption.
-/// It should be used immediately and then discarded.
-#[derive(Clone)]
+/// It should be used immediately and then discarded; it is zeroized on drop.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
 pub struct MessageKey {
-    /// The 32-byte symmetric key for XChaCha20-Poly1305
+    /// The 32-byte symmetric key for the room's AEAD
     key: [u8; 32],
     /// The generation (ratchet step) this key was derived from
     generation: u32,
 }
 
 impl MessageKey {
-    /// 32-byte symmetric key for XChaCha20-Poly1305 AEAD.
+    /// Rebuild a message key kept from an earlier session.
+    pub fn restore(key: [u8; 32], generation: u32) -> Self {
+        Self { key, generation }
+    }
+
+    /// 32-byte symmetric key for the room's AEAD.
     pub fn key(&self) -> &[u8; 32] {
         &self.key
     }
@@ -47,14 +55,6 @@ impl MessageKey {
     }
 }
 
-// Implement Drop to zeroize key material
-impl Drop for MessageKey {
-    fn drop(&mut self) {
-        // Zeroize the key material
-        self.key.iter_mut().for_each(|b| *b = 0);
-    }
-}
-
 /// Forward-secure symmetric ratchet.
 ///
 /// Derives a sequence of message keys from an initial seed.
@@ -66,13 +66,17 @@ impl Drop for MessageKey {
 /// # Security
 ///
 /// - Chain keys are overwritten immediately after use
+/// - The chain key is zeroized on drop
 /// - Compromise of current state doesn't reveal past keys
 /// - Deterministic: same seed produces same sequence
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
 pub struct SymmetricRatchet {
     /// Current chain key (32 bytes)
     chain_key: [u8; 32],
     /// Current generation (number of `advance()` calls)
     generation: u32,
+    /// Most generations [`advance_to()`](Self::advance_to) skips over
+    max_generation_jump: u32,
 }
 
 impl SymmetricRatchet {
@@ -80,7 +84,32 @@ impl SymmetricRatchet {
     ///
     /// The seed becomes the initial chain key (generation 0).
     pub fn new(seed: &[u8; 32]) -> Self {
-        Self { chain_key: *seed, generation: 0 }
+        Self::restore(*seed, 0)
+    }
+
+    /// Resume a ratchet at `generation` from its chain key, as returned by
+    /// [`chain_key()`](Self::chain_key).
+    pub fn restore(chain_key: [u8; 32], generation: u32) -> Self {
+        Self { chain_key, generation, max_generation_jump: DEFAULT_MAX_GENERATION_JUMP }
+    }
+
+    /// Skip over at most `max` generations to reach a message, rejecting
+    /// anything further ahead. Defaults to [`DEFAULT_MAX_GENERATION_JUMP`].
+    pub fn set_max_generation_jump(&mut self, max: u32) {
+        self.max_generation_jump = max;
+    }
+
+    /// Most generations [`advance_to()`](Self::advance_to) skips over.
+    pub fn max_generation_jump(&self) -> u32 {
+        self.max_generation_jump
+    }
+
+    /// Current chain key, for persisting the ratchet.
+    ///
+    /// Derives every key from the current generation on, so it must be
+    /// stored as securely as the epoch secret.
+    pub fn chain_key(&self) -> &[u8; 32] {
+        &self.chain_key
     }
 
     /// Current generation number.
@@ -105,11 +134,12 @@ impl SymmetricRatchet {
         }
 
         let message_key = self.derive_message_key();
-        let next_chain_key = self.derive_next_chain_key();
+        let mut next_chain_key = self.derive_next_chain_key();
 
         // Zeroize and replace the old chain key for forward secrecy
-        self.chain_key.iter_mut().for_each(|b| *b = 0);
+        self.chain_key.zeroize();
         self.chain_key = next_chain_key;
+        next_chain_key.zeroize();
 
         let current_gen = self.generation;
         self.generation = self.generation.wrapping_add(1);
@@ -120,8 +150,25 @@ impl SymmetricRatchet {
     /// Advance the ratchet to a specific generation.
     ///
     /// Used for decrypting out-of-order messages. If the target generation
-    /// is ahead of our current position, we skip forward.
+    /// is ahead of our current position, we skip forward and discard the keys
+    /// of the generations skipped over.
+    ///
+    /// Fails with [`SenderKeyError::GenerationJumpTooLarge`] without touching
+    /// the ratchet if that means skipping more than
+    /// [`max_generation_jump()`](Self::max_generation_jump) generations.
     pub fn advance_to(&mut self, target: u32) -> Result<MessageKey, SenderKeyError> {
+        self.advance_to_with(target, drop)
+    }
+
+    /// Advance the ratchet to a specific generation, handing the key of every
+    /// generation skipped over to `on_skip`.
+    ///
+    /// Lets the caller keep skipped keys for messages that arrive late.
+    pub fn advance_to_with(
+        &mut self,
+        target: u32,
+        mut on_skip: impl FnMut(MessageKey),
+    ) -> Result<MessageKey, SenderKeyError> {
         if target < self.generation {
             return Err(SenderKeyError::RatchetTooFarBehind {
                 current: self.generation,
@@ -131,10 +178,11 @@ impl SymmetricRatchet {
 
         // We verified target >= self.generation above, so this won't underflow
         let skip_count = target.wrapping_sub(self.generation);
-        if skip_count > MAX_SKIP {
-            return Err(SenderKeyError::RatchetTooFarBehind {
+        if skip_count > self.max_generation_jump {
+            return Err(SenderKeyError::GenerationJumpTooLarge {
                 current: self.generation,
                 requested: target,
+                max_jump: self.max_generation_jump,
             });
         }
 
@@ -142,7 +190,9 @@ impl SymmetricRatchet {
         // The last advance will return the message key we want
         let mut message_key = None;
         while self.generation <= target {
-            message_key = Some(self.advance()?);
+            if let Some(skipped) = message_key.replace(self.advance()?) {
+                on_skip(skipped);
+            }
         }
 
         // We should always have a message key here since we loop at least once
@@ -159,10 +209,11 @@ impl SymmetricRatchet {
             unreachable!("HMAC-SHA256 accepts any key size");
         };
         mac.update(MESSAGE_LABEL);
-        let result = mac.finalize().into_bytes();
+        let mut result = mac.finalize().into_bytes();
 
         let mut key = [0u8; 32];
         key.copy_from_slice(&result);
+        result.as_mut_slice().zeroize();
         key
     }
 
@@ -172,21 +223,15 @@ impl SymmetricRatchet {
             unreachable!("HMAC-SHA256 accepts any key size");
         };
         mac.update(CHAIN_LABEL);
-        let result = mac.finalize().into_bytes();
+        let mut result = mac.finalize().into_bytes();
 
         let mut key = [0u8; 32];
         key.copy_from_slice(&result);
+        result.as_mut_slice().zeroize();
         key
     }
 }
 
-impl Drop for SymmetricRatchet {
-    fn drop(&mut self) {
-        // Zeroize chain key
-        self.chain_key.iter_mut().for_each(|b| *b = 0);
-    }
-}
-
 #[cfg(test)]
 #[allow(clippy::panic, clippy::unwrap_used, clippy::cast_possible_truncation)]
 mod tests {
@@ -283,6 +328,36 @@ mod tests {
         assert_eq!(ratchet.generation(), 6);
     }
 
+    #[test]
+    fn restored_ratchet_continues_the_sequence() {
+        let mut ratchet = SymmetricRatchet::new(&test_seed());
+        ratchet.advance().unwrap();
+
+        let mut restored = SymmetricRatchet::restore(*ratchet.chain_key(), ratchet.generation());
+        let key = ratchet.advance().unwrap();
+        let restored_key = restored.advance().unwrap();
+
+        assert_eq!(restored_key.generation(), 1);
+        assert_eq!(restored_key.key(), key.key());
+    }
+
+    #[test]
+    fn advance_to_with_hands_over_skipped_keys() {
+        let mut reference = SymmetricRatchet::new(&test_seed());
+        let expected: Vec<_> = (0..4).map(|_| reference.advance().unwrap()).collect();
+
+        let mut ratchet = SymmetricRatchet::new(&test_seed());
+        let mut skipped = Vec::new();
+        let key = ratchet.advance_to_with(3, |k| skipped.push(k)).unwrap();
+
+        assert_eq!(key.key(), expected[3].key());
+        assert_eq!(skipped.len(), 3);
+        for (skipped, expected) in skipped.iter().zip(&expected) {
+            assert_eq!(skipped.generation(), expected.generation());
+            assert_eq!(skipped.key(), expected.key());
+        }
+    }
+
     #[test]
     fn advance_to_matches_sequential_advance() {
         let seed = test_seed();
@@ -329,14 +404,39 @@ mod tests {
     fn advance_to_rejects_too_far_ahead() {
         let mut ratchet = SymmetricRatchet::new(&test_seed());
 
-        // Try to skip more than MAX_SKIP
-        let result = ratchet.advance_to(MAX_SKIP + 100);
+        // Try to skip more than the default jump
+        let result = ratchet.advance_to(DEFAULT_MAX_GENERATION_JUMP + 100);
         assert!(result.is_err());
 
         match result {
-            Err(SenderKeyError::RatchetTooFarBehind { .. }) => {},
-            _ => panic!("expected RatchetTooFarBehind error"),
+            Err(SenderKeyError::GenerationJumpTooLarge { current, requested, max_jump }) => {
+                assert_eq!(current, 0);
+                assert_eq!(requested, DEFAULT_MAX_GENERATION_JUMP + 100);
+                assert_eq!(max_jump, DEFAULT_MAX_GENERATION_JUMP);
+            },
+            _ => panic!("expected GenerationJumpTooLarge error"),
         }
+        assert_eq!(ratchet.generation(), 0, "a rejected jump must not move the ratchet");
+    }
+
+    #[test]
+    fn max_generation_jump_is_configurable() {
+        let mut ratchet = SymmetricRatchet::new(&test_seed());
+        ratchet.set_max_generation_jump(3);
+
+        assert!(matches!(
+            ratchet.advance_to(4),
+            Err(SenderKeyError::GenerationJumpTooLarge { max_jump: 3, .. })
+        ));
+        assert!(matches!(
+            ratchet.advance_to(u32::MAX),
+            Err(SenderKeyError::GenerationJumpTooLarge { .. })
+        ));
+
+        // Exactly at the bound is still allowed
+        let key = ratchet.advance_to(3).unwrap();
+        assert_eq!(key.generation(), 3);
+        assert_eq!(ratchet.advance_to(7).unwrap().generation(), 7);
     }
 
     #[test]
@@ -0,0 +1,163 @@
+//! Out-of-band peer verification.
+//!
+//! The server distributes members' credentials, so it could swap in keys of
+//! its own. Two members rule that out by comparing material derived from the
+//! identities they each see, over a channel the server doesn't control (in
+//! person, a call, a scanned QR code):
+//!
+//! - Safety number: 60 digits from both members' signature keys. Stable until
+//!   either key changes, so it can be compared once and remembered.
+//! - Short authentication string: 6 digits bound to the MLS epoch authenticator
+//!   as well. Easy to read out, but only valid for the epoch it was derived in.
+//! - Verification payload: the safety number's input in binary, for encoding in
+//!   a QR code and comparing byte for byte after a scan.
+//!
+//! Every derivation orders the two identities by member ID, so both sides get
+//! the same result.
+
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+
+/// Label used for identity fingerprints
+const FINGERPRINT_LABEL: &[u8] = b"lockframeFingerprintV1";
+
+/// Label used for short authentication strings
+const SAS_LABEL: &[u8] = b"lockframeSasV1";
+
+/// Leading byte of [`verification_payload`], bumped when its layout changes
+pub const VERIFICATION_PAYLOAD_VERSION: u8 = 1;
+
+/// Identity of a group member as seen by the local client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerIdentity<'a> {
+    /// Member's stable ID
+    pub member_id: u64,
+    /// Member's public MLS signature key
+    pub signature_key: &'a [u8],
+}
+
+/// Hash of a member's ID and signature key.
+pub fn fingerprint(peer: PeerIdentity<'_>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(FINGERPRINT_LABEL);
+    hasher.update(peer.member_id.to_be_bytes());
+    hasher.update(peer.signature_key);
+    hasher.finalize().into()
+}
+
+/// The two identities' (member ID, fingerprint) pairs, lowest ID first.
+fn ordered(a: PeerIdentity<'_>, b: PeerIdentity<'_>) -> [(u64, [u8; 32]); 2] {
+    let mut pair = [(a.member_id, fingerprint(a)), (b.member_id, fingerprint(b))];
+    pair.sort_unstable();
+    pair
+}
+
+/// Safety number for a pair of members: 12 groups of 5 digits.
+///
+/// Each member contributes 6 groups taken from their fingerprint.
+pub fn safety_number(a: PeerIdentity<'_>, b: PeerIdentity<'_>) -> String {
+    let groups: Vec<String> = ordered(a, b)
+        .iter()
+        .flat_map(|(_, fingerprint)| fingerprint.chunks_exact(5).take(6))
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, &byte| (acc << 8) | u64::from(byte));
+            format!("{:05}", value % 100_000)
+        })
+        .collect();
+    groups.join(" ")
+}
+
+/// Binary safety number input for encoding in a QR code.
+///
+/// Version byte, then member ID and fingerprint of each member.
+pub fn verification_payload(a: PeerIdentity<'_>, b: PeerIdentity<'_>) -> Vec<u8> {
+    // Capacity: 1 (version) + 2 * (8 (member_id) + 32 (fingerprint)) = 81
+    let mut payload = Vec::with_capacity(81);
+    payload.push(VERIFICATION_PAYLOAD_VERSION);
+    for (member_id, fingerprint) in ordered(a, b) {
+        payload.extend_from_slice(&member_id.to_be_bytes());
+        payload.extend_from_slice(&fingerprint);
+    }
+    payload
+}
+
+/// Short authentication string for a pair of members in one epoch, as two
+/// groups of 3 digits.
+///
+/// Matches only if both members see the same identities and the same
+/// epoch authenticator.
+pub fn short_auth_string(
+    epoch_authenticator: &[u8],
+    a: PeerIdentity<'_>,
+    b: PeerIdentity<'_>,
+) -> String {
+    let hkdf = Hkdf::<Sha256>::new(None, epoch_authenticator);
+
+    // Capacity: 14 (label) + 2 * (8 (member_id) + 32 (fingerprint)) = 94
+    let mut info = Vec::with_capacity(94);
+    info.extend_from_slice(SAS_LABEL);
+    for (member_id, fingerprint) in ordered(a, b) {
+        info.extend_from_slice(&member_id.to_be_bytes());
+        info.extend_from_slice(&fingerprint);
+    }
+
+    let mut code = [0u8; 4];
+    let Ok(()) = hkdf.expand(&info, &mut code) else {
+        unreachable!("4 bytes is a valid HKDF-SHA256 output length");
+    };
+
+    let value = u32::from_be_bytes(code) % 1_000_000;
+    format!("{:03} {:03}", value / 1000, value % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: PeerIdentity<'static> = PeerIdentity { member_id: 1, signature_key: b"alice" };
+    const BOB: PeerIdentity<'static> = PeerIdentity { member_id: 2, signature_key: b"bob" };
+
+    #[test]
+    fn both_sides_derive_the_same_material() {
+        assert_eq!(safety_number(ALICE, BOB), safety_number(BOB, ALICE));
+        assert_eq!(verification_payload(ALICE, BOB), verification_payload(BOB, ALICE));
+        assert_eq!(
+            short_auth_string(b"epoch", ALICE, BOB),
+            short_auth_string(b"epoch", BOB, ALICE)
+        );
+    }
+
+    #[test]
+    fn material_has_the_documented_shape() {
+        let number = safety_number(ALICE, BOB);
+        assert_eq!(number.len(), 12 * 5 + 11);
+        assert!(number.split(' ').all(|group| group.len() == 5));
+
+        let payload = verification_payload(ALICE, BOB);
+        assert_eq!(payload.len(), 81);
+        assert_eq!(payload.first(), Some(&VERIFICATION_PAYLOAD_VERSION));
+
+        let sas = short_auth_string(b"epoch", ALICE, BOB);
+        assert_eq!(sas.len(), 7);
+    }
+
+    #[test]
+    fn swapped_keys_change_the_material() {
+        let mallory = PeerIdentity { member_id: 2, signature_key: b"mallory" };
+
+        assert_ne!(safety_number(ALICE, BOB), safety_number(ALICE, mallory));
+        assert_ne!(verification_payload(ALICE, BOB), verification_payload(ALICE, mallory));
+        assert_ne!(
+            short_auth_string(b"epoch", ALICE, BOB),
+            short_auth_string(b"epoch", ALICE, mallory)
+        );
+    }
+
+    #[test]
+    fn short_auth_string_is_bound_to_the_epoch() {
+        assert_ne!(
+            short_auth_string(b"epoch 1", ALICE, BOB),
+            short_auth_string(b"epoch 2", ALICE, BOB)
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Simulated federation of servers.
+//!
+//! [`SimFederation`] runs several `ServerDriver`s side by side and carries
+//! the relays they address to each other over in-memory links. The order in
+//! which queued relays arrive is drawn from a [`DeliverySchedule`], and links
+//! can be made to deliver every relay twice or lose the next few, so the
+//! dedup, reordering and gap recovery guarantees of the federation protocol
+//! can be checked against a topology where members of one room are spread
+//! over several servers.
+
+use std::collections::{BTreeMap, HashMap};
+
+use lockframe_proto::Frame;
+use lockframe_server::{
+    DriverConfig, DriverError, MemoryStorage, Relayed, ServerAction, ServerDriver, ServerEvent,
+    ServerId, Storage,
+};
+
+use crate::{DeliverySchedule, SimEnv};
+
+/// A relay on its way between two servers.
+#[derive(Debug, Clone)]
+struct InFlight {
+    from: ServerId,
+    to: ServerId,
+    relayed: Relayed,
+}
+
+/// Several servers relaying to each other over simulated links.
+pub struct SimFederation {
+    /// Drivers by server identity
+    servers: BTreeMap<ServerId, ServerDriver<SimEnv, MemoryStorage>>,
+    /// Relays sent but not yet delivered, in the order they were sent
+    links: Vec<InFlight>,
+    /// Order in which queued relays are delivered
+    schedule: DeliverySchedule,
+    /// Whether each relay is delivered twice
+    duplicate: bool,
+    /// Relays still to be lost as they are sent
+    lose: usize,
+    /// Frames sent to each session, by server and session
+    received: HashMap<(ServerId, u64), Vec<Frame>>,
+}
+
+impl SimFederation {
+    /// Federation of one server per identity, with FIFO links.
+    pub fn new(servers: impl IntoIterator<Item = ServerId>) -> Self {
+        let servers = servers
+            .into_iter()
+            .map(|server_id| {
+                let config = DriverConfig { server_id, ..DriverConfig::default() };
+                let env = SimEnv::with_seed(server_id.0);
+                (server_id, ServerDriver::new(env, MemoryStorage::new(), config))
+            })
+            .collect();
+
+        Self {
+            servers,
+            links: Vec::new(),
+            schedule: DeliverySchedule::Fifo,
+            duplicate: false,
+            lose: 0,
+            received: HashMap::new(),
+        }
+    }
+
+    /// Deliver queued relays in the order `schedule` picks.
+    #[must_use]
+    pub fn with_schedule(mut self, schedule: DeliverySchedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Deliver every relay twice.
+    #[must_use]
+    pub fn with_duplicates(mut self) -> Self {
+        self.duplicate = true;
+        self
+    }
+
+    /// Lose the next `count` relays sent.
+    pub fn lose_relays(&mut self, count: usize) {
+        self.lose = count;
+    }
+
+    /// Driver of `server`.
+    ///
+    /// # Panics
+    ///
+    /// If `server` is not part of the federation.
+    pub fn driver(&self, server: ServerId) -> &ServerDriver<SimEnv, MemoryStorage> {
+        self.servers.get(&server).expect("server is not part of the federation")
+    }
+
+    /// Mutable driver of `server`.
+    ///
+    /// # Panics
+    ///
+    /// If `server` is not part of the federation.
+    pub fn driver_mut(&mut self, server: ServerId) -> &mut ServerDriver<SimEnv, MemoryStorage> {
+        self.servers.get_mut(&server).expect("server is not part of the federation")
+    }
+
+    /// Home `room_id` on `home`, with every other server hosting members.
+    pub fn home_room(&mut self, room_id: u128, home: ServerId) {
+        let ids: Vec<ServerId> = self.servers.keys().copied().collect();
+        for (&server_id, driver) in &mut self.servers {
+            driver.set_room_home(room_id, home);
+            if server_id == home {
+                for &peer in &ids {
+                    driver.add_room_peer(room_id, peer);
+                }
+            }
+        }
+    }
+
+    /// Feed `event` to `server` and execute its actions.
+    ///
+    /// Relays are queued until [`deliver_relays`](Self::deliver_relays).
+    pub fn process(&mut self, server: ServerId, event: ServerEvent) -> Result<(), DriverError> {
+        let actions = self.driver_mut(server).process_event(event)?;
+        self.execute(server, actions);
+        Ok(())
+    }
+
+    /// Deliver queued relays, and the relays they cause, until the links
+    /// are idle.
+    ///
+    /// Returns how many relays were delivered, counting duplicates.
+    pub fn deliver_relays(&mut self) -> Result<usize, DriverError> {
+        let mut delivered = 0usize;
+        while !self.links.is_empty() {
+            let mut queued: Vec<Option<InFlight>> =
+                std::mem::take(&mut self.links).into_iter().map(Some).collect();
+            let order = self.schedule.next_order(queued.len());
+
+            for index in order {
+                let Some(relay) = queued.get_mut(index).and_then(Option::take) else {
+                    continue;
+                };
+                let copies = if self.duplicate { 2 } else { 1 };
+                for _ in 0..copies {
+                    let event =
+                        ServerEvent::Relayed { from: relay.from, relayed: relay.relayed.clone() };
+                    self.process(relay.to, event)?;
+                    delivered = delivered.saturating_add(1);
+                }
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Frames sent to `session_id` on `server`, in the order they were sent.
+    pub fn received(&self, server: ServerId, session_id: u64) -> &[Frame] {
+        self.received.get(&(server, session_id)).map_or(&[], Vec::as_slice)
+    }
+
+    /// The copy of a room's log stored on `server`.
+    pub fn stored_log(&self, server: ServerId, room_id: u128) -> Vec<Frame> {
+        self.driver(server).storage().load_frames(room_id, 0, usize::MAX).unwrap_or_default()
+    }
+
+    /// Execute `server`'s actions: deliver frames to its sessions, persist
+    /// frames and queue relays.
+    fn execute(&mut self, server: ServerId, actions: Vec<ServerAction>) {
+        for action in actions {
+            match action {
+                ServerAction::SendToSession { session_id, frame } => {
+                    self.received.entry((server, session_id)).or_default().push(frame);
+                },
+
+                ServerAction::BroadcastToRoom { room_id, frame, exclude_session, .. } => {
+                    let recipients: Vec<u64> = self
+                        .driver(server)
+                        .sessions_in_room(room_id)
+                        .filter(|session_id| Some(*session_id) != exclude_session)
+                        .collect();
+                    for session_id in recipients {
+                        self.received.entry((server, session_id)).or_default().push(frame.clone());
+                    }
+                },
+
+                ServerAction::PersistFrame { room_id, log_index, frame } => {
+                    if let Err(e) =
+                        self.driver(server).storage().store_frame(room_id, log_index, &frame)
+                    {
+                        eprintln!("[ERROR] {server} failed to persist frame {log_index}: {e}");
+                    }
+                },
+
+                ServerAction::PersistMlsState { room_id, state } => {
+                    if let Err(e) = self.driver(server).storage().store_mls_state(room_id, &state) {
+                        eprintln!("[ERROR] {server} failed to persist MLS state: {e}");
+                    }
+                },
+
+                ServerAction::PersistCommit { room_id, log_index, frame, state } => {
+                    let storage = self.driver(server).storage();
+                    if let Err(e) = storage.store_commit(room_id, log_index, &frame, &state) {
+                        eprintln!("[ERROR] {server} failed to persist commit {log_index}: {e}");
+                    }
+                },
+
+                ServerAction::Relay { to, relayed } => match self.lose.checked_sub(1) {
+                    Some(lose) => self.lose = lose,
+                    None => self.links.push(InFlight { from: server, to, relayed }),
+                },
+
+                _ => {},
+            }
+        }
+    }
+}
@@ -8,20 +8,49 @@
 //! The `model` module provides a reference implementation for model-based
 //! testing. Operations are applied to both the model and real implementation,
 //! and their observable states are compared.
+//!
+//! # Delivery Schedules
+//!
+//! The `schedule` module makes the order of queued frame delivery an explicit
+//! input, so interleaving-dependent behaviour can be explored with seeded
+//! shuffles or enumerated exhaustively for small cases.
+//!
+//! # Incident Replay
+//!
+//! The `replay` module converts a production server's event log into a trace
+//! that replays against `ServerDriver` in simulation.
+//!
+//! # Federation
+//!
+//! The `federation` module runs several servers relaying frames for shared
+//! rooms over simulated links that can reorder and duplicate relays.
+//!
+//! # Watermark Oracle
+//!
+//! The `watermark` module checks after every operation batch that the
+//! sequencer's next log index per room matches what storage holds.
 
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
 
+pub mod federation;
 pub mod model;
+pub mod replay;
 pub mod scenario;
+pub mod schedule;
 pub mod sim_env;
 pub mod sim_server;
 pub mod sim_transport;
+pub mod watermark;
 
+pub use federation::SimFederation;
 pub use model::{
     ClientId, ErrorProperties, ModelClient, ModelMessage, ModelRoomId, ModelServer, ModelWorld,
     ObservableState, Operation, OperationError, OperationResult, PendingMessage, SmallMessage,
 };
+pub use replay::{ReplayError, ReplayStep, Trace};
+pub use schedule::DeliverySchedule;
 pub use sim_env::SimEnv;
-pub use sim_server::{SharedSimServer, SimServer, create_shared_server};
+pub use sim_server::{SharedSimServer, SimServer, SimStorage, create_shared_server};
 pub use sim_transport::SimTransport;
+pub use watermark::{WatermarkError, WatermarkOracle};
@@ -25,7 +25,7 @@ pub struct ModelMessage {
 struct ModelRoomState {
     /// Current epoch.
     epoch: u64,
-    /// Messages received (ordered by log_index).
+    /// Messages received (in delivery order).
     messages: Vec<ModelMessage>,
     /// Next expected log index for sending.
     next_send_index: u64,
@@ -74,7 +74,7 @@ impl ModelClient {
         self.rooms.keys().copied()
     }
 
-    /// Messages received in a room (ordered by log_index).
+    /// Messages received in a room (in delivery order).
     pub fn messages(&self, room_id: ModelRoomId) -> Option<&[ModelMessage]> {
         self.rooms.get(&room_id).map(|r| r.messages.as_slice())
     }
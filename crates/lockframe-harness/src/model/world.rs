@@ -9,6 +9,7 @@ use super::{
     operation::{ClientId, ModelRoomId, Operation, OperationError, OperationResult},
     server::ModelServer,
 };
+use crate::schedule::DeliverySchedule;
 
 /// Observable state for oracle comparison.
 ///
@@ -22,6 +23,12 @@ pub struct ObservableState {
     pub client_messages: Vec<Vec<(ModelRoomId, Vec<ModelMessage>)>>,
     /// Server's view of messages per room.
     pub server_messages: Vec<(ModelRoomId, Vec<ModelMessage>)>,
+    /// Per-client, per-room epochs.
+    pub client_epochs: Vec<Vec<(ModelRoomId, u64)>>,
+    /// Server's epoch per room.
+    pub server_epochs: Vec<(ModelRoomId, u64)>,
+    /// Messages sequenced by the server but not yet delivered.
+    pub pending_deliveries: usize,
 }
 
 /// Model world - the reference implementation.
@@ -34,14 +41,21 @@ pub struct ModelWorld {
     clients: Vec<ModelClient>,
     /// Model server.
     server: ModelServer,
+    /// Per-client delivery order for pending messages.
+    schedule: DeliverySchedule,
 }
 
 impl ModelWorld {
     /// Create a new model world with the given number of clients.
     pub fn new(num_clients: usize) -> Self {
+        Self::with_schedule(num_clients, DeliverySchedule::Fifo)
+    }
+
+    /// Create a model world whose pending deliveries follow `schedule`.
+    pub fn with_schedule(num_clients: usize, schedule: DeliverySchedule) -> Self {
         let clients = (0..num_clients).map(|i| ModelClient::new(i as ClientId)).collect();
 
-        Self { clients, server: ModelServer::new() }
+        Self { clients, server: ModelServer::new(), schedule }
     }
 
     /// Number of clients in the world.
@@ -95,6 +109,7 @@ impl ModelWorld {
     pub fn observable_state(&self) -> ObservableState {
         let mut client_rooms = Vec::with_capacity(self.clients.len());
         let mut client_messages = Vec::with_capacity(self.clients.len());
+        let mut client_epochs = Vec::with_capacity(self.clients.len());
 
         for client in &self.clients {
             let mut rooms: Vec<_> = client.rooms().collect();
@@ -102,15 +117,21 @@ impl ModelWorld {
             client_rooms.push(rooms.clone());
 
             let mut messages = Vec::new();
+            let mut epochs = Vec::new();
             for room_id in rooms {
                 if let Some(msgs) = client.messages(room_id) {
                     messages.push((room_id, msgs.to_vec()));
                 }
+                if let Some(epoch) = client.epoch(room_id) {
+                    epochs.push((room_id, epoch));
+                }
             }
             client_messages.push(messages);
+            client_epochs.push(epochs);
         }
 
         let mut server_messages = Vec::new();
+        let mut server_epochs = Vec::new();
         let mut room_ids: Vec<_> = self
             .clients
             .iter()
@@ -124,22 +145,34 @@ impl ModelWorld {
             if let Some(msgs) = self.server.messages(room_id) {
                 server_messages.push((room_id, msgs.to_vec()));
             }
+            if let Some(epoch) = self.server.epoch(room_id) {
+                server_epochs.push((room_id, epoch));
+            }
         }
 
-        ObservableState { client_rooms, client_messages, server_messages }
+        ObservableState {
+            client_rooms,
+            client_messages,
+            server_messages,
+            client_epochs,
+            server_epochs,
+            pending_deliveries: self.server.pending_count(),
+        }
     }
 
     /// Apply create room operation.
     ///
     /// Note: Each client can independently create a room with the same ID.
-    /// There is no centralized room registry - room creation is local.
+    /// There is no centralized room registry - room creation is local. If the
+    /// server already knows the room, the client starts at the server's epoch.
     fn apply_create_room(&mut self, client_id: ClientId, room_id: ModelRoomId) -> OperationResult {
         let client = match self.clients.get_mut(client_id as usize) {
             Some(c) => c,
             None => return OperationResult::Error(OperationError::InvalidClient),
         };
 
-        let client_result = client.create_room(room_id);
+        let epoch = self.server.epoch(room_id).unwrap_or(0);
+        let client_result = client.join_room_at_epoch(room_id, epoch);
         if client_result.is_err() {
             return client_result;
         }
@@ -175,16 +208,21 @@ impl ModelWorld {
     }
 
     /// Deliver all pending messages to their recipients.
+    ///
+    /// Each client receives the pending messages in the order chosen by the
+    /// delivery schedule, independently of other clients.
     fn apply_deliver_pending(&mut self) {
         let pending = self.server.take_pending();
 
-        for pending_msg in pending {
-            for recipient_id in pending_msg.recipients {
-                if let Some(client) = self.clients.get_mut(recipient_id as usize) {
-                    // Only deliver if still a member (may have left between send and delivery)
-                    if client.is_member(pending_msg.room_id) {
-                        client.receive_message(pending_msg.room_id, pending_msg.message.clone());
-                    }
+        for client in &mut self.clients {
+            for index in self.schedule.next_order(pending.len()) {
+                let Some(pending_msg) = pending.get(index) else { continue };
+
+                // Only deliver if still a member (may have left between send and delivery)
+                if pending_msg.recipients.contains(&client.id())
+                    && client.is_member(pending_msg.room_id)
+                {
+                    client.receive_message(pending_msg.room_id, pending_msg.message.clone());
                 }
             }
         }
@@ -0,0 +1,163 @@
+//! Deterministic replay of production event logs.
+//!
+//! A server started with its event log enabled records every event it feeds
+//! the driver (see [`EventRecord`]). [`Trace::from_log`] turns such a log into
+//! a trace, and [`Trace::replay`] feeds the same events, at the same offsets in
+//! simulated time, into a [`ServerDriver`] running on [`SimEnv`]. The driver is
+//! deterministic given its inputs, so a production incident replays the same
+//! way every time.
+
+use std::{fmt, time::Duration};
+
+use lockframe_server::{
+    DriverError, EventLogError, EventRecord, ServerAction, ServerDriver, Storage, StorageError,
+};
+
+use crate::SimEnv;
+
+/// Error converting an event log into a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// A record could not be decoded
+    Decode {
+        /// 1-based line number in the log
+        line: usize,
+        /// Why the record was rejected
+        source: EventLogError,
+    },
+
+    /// A record is timestamped before the one preceding it
+    OutOfOrder {
+        /// 1-based line number in the log
+        line: usize,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode { line, source } => write!(f, "line {line}: {source}"),
+            Self::OutOfOrder { line } => write!(f, "line {line}: record out of order"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Outcome of replaying one event.
+#[derive(Debug)]
+pub struct ReplayStep {
+    /// Offset of the event from the start of the trace
+    pub at: Duration,
+    /// What the driver returned
+    pub result: Result<Vec<ServerAction>, DriverError>,
+    /// Failures applying the event's persistence actions to storage
+    pub persist_errors: Vec<StorageError>,
+}
+
+/// Ordered server events recovered from an event log.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    events: Vec<EventRecord>,
+}
+
+impl Trace {
+    /// Build a trace from event log text.
+    ///
+    /// Lines without an event record (other log output) are skipped.
+    pub fn from_log(log: &str) -> Result<Self, ReplayError> {
+        let mut events: Vec<EventRecord> = Vec::new();
+
+        for (index, text) in log.lines().enumerate() {
+            let line = index.saturating_add(1);
+            let Some(record) =
+                EventRecord::decode(text).map_err(|source| ReplayError::Decode { line, source })?
+            else {
+                continue;
+            };
+
+            if events.last().is_some_and(|last| last.at > record.at) {
+                return Err(ReplayError::OutOfOrder { line });
+            }
+            events.push(record);
+        }
+
+        Ok(Self { events })
+    }
+
+    /// Encode the trace back into event log lines.
+    pub fn to_log(&self) -> String {
+        self.events.iter().map(|record| record.encode() + "\n").collect()
+    }
+
+    /// Events in the trace, in order.
+    pub fn events(&self) -> &[EventRecord] {
+        &self.events
+    }
+
+    /// Number of events in the trace.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Whether the trace has no events.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Replay the trace against `driver`.
+    ///
+    /// Must run inside a turmoil simulation. Each event is processed at its
+    /// recorded offset from when replay starts. Persistence actions are applied
+    /// to the driver's storage as the production runtime would, since later
+    /// events (sync requests) read back what earlier ones stored.
+    pub async fn replay<S: Storage>(
+        &self,
+        driver: &mut ServerDriver<SimEnv, S>,
+    ) -> Vec<ReplayStep> {
+        let start = tokio::time::Instant::now();
+        let mut steps = Vec::with_capacity(self.events.len());
+
+        for record in &self.events {
+            tokio::time::sleep(record.at.saturating_sub(start.elapsed())).await;
+
+            let result = driver.process_event(record.event.clone());
+            let persist_errors =
+                result.as_ref().map(|actions| persist(driver, actions)).unwrap_or_default();
+            steps.push(ReplayStep { at: record.at, result, persist_errors });
+        }
+
+        steps
+    }
+}
+
+/// Apply persistence actions to the driver's storage, returning any failures.
+fn persist<S: Storage>(
+    driver: &ServerDriver<SimEnv, S>,
+    actions: &[ServerAction],
+) -> Vec<StorageError> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            ServerAction::PersistFrame { room_id, log_index, frame } => {
+                driver.storage().store_frame(*room_id, *log_index, frame).err()
+            },
+            ServerAction::PersistMlsState { room_id, state } => {
+                driver.storage().store_mls_state(*room_id, state).err()
+            },
+            ServerAction::PersistCommit { room_id, log_index, frame, state } => {
+                driver.storage().store_commit(*room_id, *log_index, frame, state).err()
+            },
+            ServerAction::PersistProposal { room_id, proposal } => {
+                driver.storage().store_pending_proposal(*room_id, proposal).err()
+            },
+            ServerAction::ClearProposals { room_id } => {
+                driver.storage().clear_pending_proposals(*room_id).err()
+            },
+            ServerAction::PersistReadMarker { room_id, member_id, up_to_log_index } => {
+                driver.storage().store_read_marker(*room_id, *member_id, *up_to_log_index).err()
+            },
+            _ => None,
+        })
+        .collect()
+}
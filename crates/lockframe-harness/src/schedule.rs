@@ -0,0 +1,197 @@
+//! Delivery schedules for exploring frame interleavings.
+//!
+//! When several frames are queued for delivery, the order each client sees
+//! them in is a source of nondeterminism in a real deployment. A
+//! [`DeliverySchedule`] makes that order an explicit input: FIFO for the
+//! baseline, a seeded shuffle for randomized exploration, or a scripted list of
+//! orders so small cases can be enumerated exhaustively via
+//! [`DeliverySchedule::exhaustive`].
+
+use std::collections::VecDeque;
+
+use rand::{SeedableRng, seq::SliceRandom};
+use rand_chacha::ChaCha20Rng;
+
+/// Largest queue for which [`DeliverySchedule::exhaustive`] enumerates every
+/// order.
+///
+/// The schedule count is `(frames!)^clients`, so this keeps exhaustive runs in
+/// the low thousands for a handful of clients.
+pub const MAX_EXHAUSTIVE_FRAMES: usize = 4;
+
+/// Chooses the order in which queued frames reach each client.
+///
+/// Each call to [`next_order`](Self::next_order) yields the delivery order for
+/// one client during one flush. Callers ask once per client, in client order,
+/// so a schedule fully determines every interleaving of a run.
+#[derive(Debug, Clone, Default)]
+pub enum DeliverySchedule {
+    /// Deliver frames in the order they were queued.
+    #[default]
+    Fifo,
+    /// Shuffle each client's delivery order with a seeded RNG.
+    Seeded(Box<ChaCha20Rng>),
+    /// Replay explicit orders, one per call. Falls back to FIFO once the
+    /// script runs out or when an order doesn't fit the queue.
+    Scripted(VecDeque<Vec<usize>>),
+}
+
+impl DeliverySchedule {
+    /// Seeded random schedule.
+    pub fn seeded(seed: u64) -> Self {
+        Self::Seeded(Box::new(ChaCha20Rng::seed_from_u64(seed)))
+    }
+
+    /// Scripted schedule replaying `orders` in sequence.
+    pub fn scripted(orders: impl IntoIterator<Item = Vec<usize>>) -> Self {
+        Self::Scripted(orders.into_iter().collect())
+    }
+
+    /// Every schedule for a single flush of `frames` frames to `clients`
+    /// clients.
+    ///
+    /// Returns `None` if `frames` exceeds [`MAX_EXHAUSTIVE_FRAMES`].
+    pub fn exhaustive(frames: usize, clients: usize) -> Option<Vec<Self>> {
+        if frames > MAX_EXHAUSTIVE_FRAMES {
+            return None;
+        }
+
+        let perms = permutations(frames);
+        let mut schedules = Vec::new();
+        let mut digits = vec![0usize; clients];
+
+        loop {
+            let orders = digits.iter().filter_map(|&d| perms.get(d).cloned());
+            schedules.push(Self::scripted(orders));
+
+            // Mixed-radix increment over per-client permutation indices
+            let Some(pos) = digits.iter().rposition(|&d| d.saturating_add(1) < perms.len()) else {
+                break;
+            };
+            for (i, digit) in digits.iter_mut().enumerate().skip(pos) {
+                *digit = if i == pos { digit.saturating_add(1) } else { 0 };
+            }
+        }
+
+        Some(schedules)
+    }
+
+    /// Order in which `len` queued frames are delivered to the next client.
+    ///
+    /// Always returns a permutation of `0..len`.
+    pub fn next_order(&mut self, len: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..len).collect();
+
+        match self {
+            Self::Fifo => {},
+            Self::Seeded(rng) => order.shuffle(rng.as_mut()),
+            Self::Scripted(script) => {
+                if let Some(scripted) = script.pop_front() {
+                    if is_permutation(&scripted, len) {
+                        order = scripted;
+                    }
+                }
+            },
+        }
+
+        debug_assert!(is_permutation(&order, len));
+        order
+    }
+}
+
+/// All permutations of `0..n` in lexicographic order.
+pub fn permutations(n: usize) -> Vec<Vec<usize>> {
+    let mut current: Vec<usize> = (0..n).collect();
+    let mut all = vec![current.clone()];
+
+    while next_permutation(&mut current) {
+        all.push(current.clone());
+    }
+
+    all
+}
+
+/// Advance `v` to its next lexicographic permutation.
+///
+/// Returns `false` (leaving `v` untouched) if `v` is already the last one.
+fn next_permutation(v: &mut [usize]) -> bool {
+    let Some(pivot) = v.windows(2).rposition(|w| w.first() < w.last()) else {
+        return false;
+    };
+    let Some(&pivot_value) = v.get(pivot) else {
+        return false;
+    };
+    let Some(successor) = v.iter().rposition(|&x| x > pivot_value) else {
+        return false;
+    };
+
+    v.swap(pivot, successor);
+    let (_, tail) = v.split_at_mut(pivot.saturating_add(1));
+    tail.reverse();
+    true
+}
+
+fn is_permutation(order: &[usize], len: usize) -> bool {
+    let mut sorted = order.to_vec();
+    sorted.sort_unstable();
+    sorted.len() == len && sorted.iter().enumerate().all(|(i, &x)| i == x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo_preserves_queue_order() {
+        let mut schedule = DeliverySchedule::Fifo;
+        assert_eq!(schedule.next_order(4), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let mut a = DeliverySchedule::seeded(7);
+        let mut b = DeliverySchedule::seeded(7);
+
+        for len in 0..8 {
+            let order = a.next_order(len);
+            assert!(is_permutation(&order, len));
+            assert_eq!(order, b.next_order(len));
+        }
+    }
+
+    #[test]
+    fn scripted_falls_back_to_fifo() {
+        let mut schedule = DeliverySchedule::scripted([vec![2, 0, 1], vec![0, 0, 1]]);
+
+        assert_eq!(schedule.next_order(3), vec![2, 0, 1]);
+        // Not a permutation
+        assert_eq!(schedule.next_order(3), vec![0, 1, 2]);
+        // Script exhausted
+        assert_eq!(schedule.next_order(3), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn permutations_are_complete_and_unique() {
+        let perms = permutations(4);
+        assert_eq!(perms.len(), 24);
+
+        let mut deduped = perms.clone();
+        deduped.dedup();
+        assert_eq!(deduped, perms);
+        assert!(perms.iter().all(|p| is_permutation(p, 4)));
+    }
+
+    #[test]
+    fn exhaustive_covers_every_client_combination() {
+        let schedules = DeliverySchedule::exhaustive(3, 2).unwrap();
+        assert_eq!(schedules.len(), 36);
+
+        let mut seen: Vec<_> =
+            schedules.into_iter().map(|mut s| (s.next_order(3), s.next_order(3))).collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 36);
+
+        assert!(DeliverySchedule::exhaustive(MAX_EXHAUSTIVE_FRAMES + 1, 1).is_none());
+    }
+}
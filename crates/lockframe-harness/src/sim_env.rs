@@ -9,11 +9,16 @@ use lockframe_core::env::Environment;
 use rand::{RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
 
+/// Unix time (milliseconds) at which every simulation's wall clock starts:
+/// 2024-01-01T00:00:00Z.
+pub const SIM_EPOCH_MILLIS: u64 = 1_704_067_200_000;
+
 /// Simulation environment using Turmoil's virtual time and seeded RNG.
 ///
 /// now() returns Turmoil's simulated time, which can be advanced instantly via
-/// turmoil::sleep(). random_bytes() uses ChaCha20Rng seeded with a fixed value
-/// (0 by default), ensuring reproducible test runs and easier debugging.
+/// turmoil::sleep(). The wall clock starts at [`SIM_EPOCH_MILLIS`] and advances
+/// with simulated time. random_bytes() uses ChaCha20Rng seeded with a fixed
+/// value (0 by default), ensuring reproducible test runs and easier debugging.
 #[derive(Clone)]
 pub struct SimEnv {
     /// Seeded RNG for deterministic random bytes
@@ -53,6 +58,11 @@ impl Environment for SimEnv {
         tokio::time::Instant::now().into()
     }
 
+    fn wall_clock_millis(&self) -> u64 {
+        let elapsed = turmoil::sim_elapsed().unwrap_or_default();
+        SIM_EPOCH_MILLIS.saturating_add(u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX))
+    }
+
     async fn sleep(&self, duration: Duration) {
         tokio::time::sleep(duration).await;
     }
@@ -92,6 +102,25 @@ mod tests {
         sim.run().expect("simulation failed");
     }
 
+    #[test]
+    fn sim_env_wall_clock_follows_virtual_time() {
+        let mut sim = turmoil::Builder::new().build();
+
+        sim.client("test", async {
+            let env = SimEnv::new();
+
+            let start = env.wall_clock_millis();
+            assert!(start >= SIM_EPOCH_MILLIS);
+
+            env.sleep(Duration::from_secs(5)).await;
+            assert_eq!(env.wall_clock_millis() - start, 5_000);
+
+            Ok(())
+        });
+
+        sim.run().expect("simulation failed");
+    }
+
     #[test]
     fn sim_env_rng_is_deterministic() {
         // Run the same test twice with same seed, verify same output
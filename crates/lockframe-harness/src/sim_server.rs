@@ -3,6 +3,8 @@
 //! SimServer wraps ServerDriver for integration with turmoil's deterministic
 //! simulation. It uses SimEnv with MemoryStorage for the action-based core,
 //! turmoil TCP for networking, and tracks connection state in a HashMap.
+//! Storage is wrapped in `ChaoticStorage`, so a `FaultProfile` can inject
+//! storage faults; latency spikes are waited out in simulated time.
 
 use std::{
     collections::HashMap,
@@ -12,7 +14,8 @@ use std::{
 
 use lockframe_proto::Frame;
 use lockframe_server::{
-    DriverConfig, LogLevel, MemoryStorage, ServerAction, ServerDriver, ServerEvent, Storage,
+    ChaoticStorage, DriverConfig, FaultProfile, LogLevel, MemoryStorage, OutboundQueues,
+    PersistBatch, ServerAction, ServerDriver, ServerEvent, Storage,
 };
 use tokio::{
     io::{AsyncWriteExt, WriteHalf},
@@ -20,7 +23,10 @@ use tokio::{
 };
 use turmoil::net::{TcpListener, TcpStream};
 
-use crate::SimEnv;
+use crate::{SimEnv, WatermarkOracle};
+
+/// Storage backing a [`SimServer`].
+pub type SimStorage = ChaoticStorage<MemoryStorage>;
 
 /// Connection state for a simulated connection.
 struct SimConnectionState {
@@ -37,13 +43,15 @@ struct SimConnectionState {
 /// drive the server rather than having it run autonomously.
 pub struct SimServer {
     /// The action-based server driver
-    driver: ServerDriver<SimEnv, MemoryStorage>,
+    driver: ServerDriver<SimEnv, SimStorage>,
+    /// Environment the driver runs on, used to wait out storage stalls
+    env: SimEnv,
     /// TCP listener for accepting connections
     listener: TcpListener,
     /// Connection state (session_id → state)
     connections: HashMap<u64, SimConnectionState>,
-    /// Next connection ID
-    next_session_id: u64,
+    /// Storage/sequencer agreement check run after every action batch
+    watermarks: Option<WatermarkOracle>,
 }
 
 impl SimServer {
@@ -54,12 +62,48 @@ impl SimServer {
 
     /// Create and bind a new simulation server with custom config.
     pub async fn bind_with_config(address: &str, config: DriverConfig) -> io::Result<Self> {
+        Self::bind_with_storage(address, config, MemoryStorage::new()).await
+    }
+
+    /// Create and bind a new simulation server over `storage`.
+    ///
+    /// Pass a [`MemoryStorage::with_flush_tracking`] storage to restart the
+    /// server from [`MemoryStorage::after_crash`], so only what was flushed
+    /// survives.
+    pub async fn bind_with_storage(
+        address: &str,
+        config: DriverConfig,
+        storage: MemoryStorage,
+    ) -> io::Result<Self> {
+        Self::bind_with_faults(address, config, storage, FaultProfile::default()).await
+    }
+
+    /// Create and bind a new simulation server whose storage injects the
+    /// faults of `profile`.
+    ///
+    /// Latency spikes are slept off in simulated time after each action
+    /// batch.
+    pub async fn bind_with_faults(
+        address: &str,
+        config: DriverConfig,
+        storage: MemoryStorage,
+        profile: FaultProfile,
+    ) -> io::Result<Self> {
         let listener = TcpListener::bind(address).await?;
         let env = SimEnv::new();
-        let storage = MemoryStorage::new();
-        let driver = ServerDriver::new(env, storage, config);
+        let storage = ChaoticStorage::with_profile(storage, profile);
+        let driver = ServerDriver::new(env.clone(), storage, config);
 
-        Ok(Self { driver, listener, connections: HashMap::new(), next_session_id: 1 })
+        Ok(Self { driver, env, listener, connections: HashMap::new(), watermarks: None })
+    }
+
+    /// Check after every action batch that the sequencer and storage agree
+    /// on each room's log head.
+    ///
+    /// Once enabled, the call that executed a diverging batch fails with the
+    /// [`WatermarkError`](crate::WatermarkError) describing the drift.
+    pub fn check_watermarks(&mut self) {
+        self.watermarks.get_or_insert_with(WatermarkOracle::new);
     }
 
     /// Accept a new connection and return its ID.
@@ -68,8 +112,7 @@ impl SimServer {
     pub async fn accept_connection(&mut self) -> io::Result<u64> {
         let (stream, _addr) = self.listener.accept().await?;
 
-        let session_id = self.next_session_id;
-        self.next_session_id += 1;
+        let session_id = self.driver.allocate_session_id();
 
         let actions = self
             .driver
@@ -96,31 +139,39 @@ impl SimServer {
     }
 
     /// Execute server actions.
+    ///
+    /// Outgoing frames are queued per session and flushed in priority order
+    /// once the batch is done, or before a connection is closed. Consecutive
+    /// frame writes for one room are committed to storage together.
     async fn execute_actions(&mut self, actions: Vec<ServerAction>) -> io::Result<()> {
+        let mut outbound = OutboundQueues::new();
+        let mut persist = PersistBatch::new();
+
         for action in actions {
+            if !matches!(action, ServerAction::PersistFrame { .. }) {
+                self.persist_frames(persist.take());
+            }
+
             match action {
                 ServerAction::SendToSession { session_id, frame } => {
-                    self.send_frame(session_id, &frame).await?;
+                    outbound.push(session_id, frame);
                 },
 
-                ServerAction::BroadcastToRoom { room_id, frame, exclude_session } => {
-                    // Get all sessions in room and send to each
-                    let sessions: Vec<u64> = self.driver.sessions_in_room(room_id).collect();
-                    for session_id in sessions {
+                ServerAction::BroadcastToRoom { room_id, frame, exclude_session, .. } => {
+                    for session_id in self.driver.sessions_in_room(room_id) {
                         if Some(session_id) != exclude_session {
-                            self.send_frame(session_id, &frame).await?;
+                            outbound.push(session_id, frame.clone());
                         }
                     }
                 },
 
                 ServerAction::CloseConnection { session_id, reason } => {
+                    self.flush_outbound(&mut outbound).await?;
                     self.close_connection(session_id, &reason);
                 },
 
                 ServerAction::PersistFrame { room_id, log_index, frame } => {
-                    if let Err(e) = self.driver.storage().store_frame(room_id, log_index, &frame) {
-                        eprintln!("[ERROR] Failed to persist frame: {}", e);
-                    }
+                    self.persist_frames(persist.push(room_id, log_index, frame));
                 },
 
                 ServerAction::PersistMlsState { room_id, state } => {
@@ -129,12 +180,88 @@ impl SimServer {
                     }
                 },
 
+                ServerAction::PersistCommit { room_id, log_index, frame, state } => {
+                    let storage = self.driver.storage();
+                    if let Err(e) = storage.store_commit(room_id, log_index, &frame, &state) {
+                        eprintln!("[ERROR] Failed to persist commit: {}", e);
+                    }
+                },
+
+                ServerAction::PersistProposal { room_id, proposal } => {
+                    let storage = self.driver.storage();
+                    if let Err(e) = storage.store_pending_proposal(room_id, &proposal) {
+                        eprintln!("[ERROR] Failed to persist pending proposal: {}", e);
+                    }
+                },
+
+                ServerAction::ClearProposals { room_id } => {
+                    if let Err(e) = self.driver.storage().clear_pending_proposals(room_id) {
+                        eprintln!("[ERROR] Failed to clear pending proposals: {}", e);
+                    }
+                },
+
+                ServerAction::PersistReadMarker { room_id, member_id, up_to_log_index } => {
+                    let storage = self.driver.storage();
+                    if let Err(e) = storage.store_read_marker(room_id, member_id, up_to_log_index) {
+                        eprintln!("[ERROR] Failed to persist read marker: {}", e);
+                    }
+                },
+
+                // Delivered to membership hooks and stored by the driver; no archiver or
+                // push gateway in simulation
+                ServerAction::MembershipChanged(_)
+                | ServerAction::Audit(_)
+                | ServerAction::ArchiveFrames { .. }
+                | ServerAction::NotifyOffline(_) => {},
+
+                // A single server has no one to relay to
+                ServerAction::Relay { to, .. } => {
+                    self.log(LogLevel::Warn, &format!("no link to federated {to}"));
+                },
+
+                ServerAction::Rejected(record) => {
+                    let message = format!(
+                        "rejected {} frame from session {} ({} suppressed): {}",
+                        record.kind.as_str(),
+                        record.session_id,
+                        record.suppressed,
+                        record.reason
+                    );
+                    self.log(LogLevel::Warn, &message);
+                },
+
                 ServerAction::Log { level, message, .. } => {
                     self.log(level, &message);
                 },
             }
         }
 
+        self.persist_frames(persist.take());
+        self.driver.storage().wait_out_stall(&self.env).await;
+        if let Some(oracle) = &mut self.watermarks {
+            oracle
+                .check(self.driver.sequencer(), self.driver.storage())
+                .map_err(|e| io::Error::new(ErrorKind::Other, e.to_string()))?;
+        }
+        self.flush_outbound(&mut outbound).await
+    }
+
+    /// Write a coalesced batch of frames in one storage commit.
+    fn persist_frames(&self, batch: Option<(u128, Vec<Frame>)>) {
+        if let Some((room_id, frames)) = batch {
+            if let Err(e) = self.driver.storage().store_frames_batch(room_id, &frames) {
+                eprintln!("[ERROR] Failed to persist {} frames: {}", frames.len(), e);
+            }
+        }
+    }
+
+    /// Write every queued frame to its session, highest priority first.
+    async fn flush_outbound(&mut self, outbound: &mut OutboundQueues) -> io::Result<()> {
+        for session_id in outbound.pending_sessions() {
+            for frame in outbound.drain(session_id) {
+                self.send_frame(session_id, &frame).await?;
+            }
+        }
         Ok(())
     }
 
@@ -220,12 +347,12 @@ impl SimServer {
     }
 
     /// Underlying driver for test assertions.
-    pub fn driver(&self) -> &ServerDriver<SimEnv, MemoryStorage> {
+    pub fn driver(&self) -> &ServerDriver<SimEnv, SimStorage> {
         &self.driver
     }
 
     /// Mutable underlying driver for test manipulation.
-    pub fn driver_mut(&mut self) -> &mut ServerDriver<SimEnv, MemoryStorage> {
+    pub fn driver_mut(&mut self) -> &mut ServerDriver<SimEnv, SimStorage> {
         &mut self.driver
     }
 }
@@ -0,0 +1,108 @@
+//! Storage/sequencer agreement oracle.
+//!
+//! The sequencer keeps each room's next log index in memory and only reads
+//! storage when it first loads a room. If the two drift apart (a frame is
+//! sequenced but never persisted, or persisted twice), later frames are
+//! rejected or stored at the wrong index long after the bug that caused it.
+//!
+//! A [`WatermarkOracle`] compares every loaded room's watermark against
+//! storage's `latest_log_index` after each batch of simulated operations, so a
+//! test fails at the batch where the two diverged.
+
+use std::fmt;
+
+use lockframe_server::{SequencerBackend, Storage, StorageError};
+
+/// Sequencer and storage disagree about a room's log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatermarkError {
+    /// The sequencer's next log index is not one past storage's latest
+    Drift {
+        /// 1-based batch the divergence was detected after
+        batch: u64,
+        /// Room that diverged
+        room_id: u128,
+        /// Next log index the sequencer will assign
+        sequencer_next: u64,
+        /// Next log index according to storage
+        storage_next: u64,
+    },
+
+    /// Storage could not report the room's latest log index
+    Storage {
+        /// 1-based batch the check ran after
+        batch: u64,
+        /// Room being checked
+        room_id: u128,
+        /// Why the read failed
+        source: StorageError,
+    },
+}
+
+impl fmt::Display for WatermarkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Drift { batch, room_id, sequencer_next, storage_next } => write!(
+                f,
+                "batch {batch}: room {room_id:032x} sequencer next index {sequencer_next}, \
+                 storage next index {storage_next}"
+            ),
+            Self::Storage { batch, room_id, source } => {
+                write!(f, "batch {batch}: room {room_id:032x} unreadable: {source}")
+            },
+        }
+    }
+}
+
+impl std::error::Error for WatermarkError {}
+
+/// Checks storage/sequencer agreement after every operation batch.
+///
+/// Call [`check`](Self::check) once all persistence actions of a batch have
+/// been applied. The oracle counts batches so a failure names the first one
+/// that left the two out of step.
+#[derive(Debug, Clone, Default)]
+pub struct WatermarkOracle {
+    batches: u64,
+}
+
+impl WatermarkOracle {
+    /// Oracle that has checked no batches yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Batches checked so far.
+    pub fn batches_checked(&self) -> u64 {
+        self.batches
+    }
+
+    /// Check every room the sequencer has loaded against `storage`.
+    ///
+    /// Rooms are checked in ascending ID order, so the reported room is
+    /// deterministic when several diverged.
+    pub fn check(
+        &mut self,
+        sequencer: &impl SequencerBackend,
+        storage: &impl Storage,
+    ) -> Result<(), WatermarkError> {
+        self.batches = self.batches.saturating_add(1);
+        let batch = self.batches;
+
+        let mut watermarks: Vec<_> = sequencer.watermarks().collect();
+        watermarks.sort_unstable();
+
+        for (room_id, sequencer_next) in watermarks {
+            let latest = storage
+                .latest_log_index(room_id)
+                .map_err(|source| WatermarkError::Storage { batch, room_id, source })?;
+            let storage_next = latest.map_or(0, |latest| latest.saturating_add(1));
+
+            if sequencer_next != storage_next {
+                return Err(WatermarkError::Drift { batch, room_id, sequencer_next, storage_next });
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+//! Transcript verification against a Byzantine server.
+//!
+//! The server here is a test double that sequences, checkpoints, and answers
+//! proof requests from whichever copy of the room log it chooses. An honest
+//! run must never raise a violation; every way of showing the client two
+//! different histories must raise one.
+
+use ed25519_dalek::SigningKey;
+use lockframe_client::{Client, ClientAction, ClientEvent, ClientIdentity};
+use lockframe_core::{
+    checkpoint::{LogHash, sign_checkpoint},
+    merkle::MerkleLog,
+};
+use lockframe_harness::SimEnv;
+use lockframe_proto::{
+    Frame, FrameHeader, Opcode, Payload,
+    payloads::session::{ProofRequest, ProofResponse},
+};
+use turmoil::Builder;
+
+/// Test room ID
+const ROOM_ID: u128 = 0x1234_5678_9abc_def0_1234_5678_9abc_def0;
+
+/// One copy of the room log. A forking server keeps several.
+#[derive(Clone, Default)]
+struct RoomLog {
+    frames: Vec<Frame>,
+    log_hash: LogHash,
+    merkle: MerkleLog,
+}
+
+impl RoomLog {
+    /// Assign the next log index and append.
+    fn sequence(&mut self, mut frame: Frame) -> Frame {
+        frame.header.set_log_index(self.frames.len() as u64);
+        self.log_hash = self.log_hash.chain(&frame);
+        self.merkle.push(&frame);
+        self.frames.push(frame.clone());
+        frame
+    }
+
+    /// Sign the current head and sequence the checkpoint.
+    fn checkpoint(&mut self, key: &SigningKey) -> Frame {
+        let log_index = self.frames.len() as u64 - 1;
+        let checkpoint = sign_checkpoint(key, ROOM_ID, log_index, self.log_hash);
+
+        let mut header = FrameHeader::new(Opcode::Checkpoint);
+        header.set_room_id(ROOM_ID);
+        self.sequence(Payload::Checkpoint(checkpoint).into_frame(header).unwrap())
+    }
+
+    /// Answer a proof request from this copy of the log.
+    fn prove(&self, request: &Frame) -> Frame {
+        let Ok(Payload::ProofRequest(request)) = Payload::from_frame(request.clone()) else {
+            panic!("expected proof request, got {request:?}");
+        };
+
+        let (tree_size, path) = match request {
+            ProofRequest::Inclusion { leaf_index, tree_size } => {
+                let tree_size = tree_size.unwrap_or(self.merkle.len());
+                (tree_size, self.merkle.inclusion_proof(leaf_index, tree_size).unwrap())
+            },
+            ProofRequest::Consistency { old_size, new_size } => {
+                let new_size = new_size.unwrap_or(self.merkle.len());
+                (new_size, self.merkle.consistency_proof(old_size, new_size).unwrap())
+            },
+        };
+        let root = self.merkle.root_at(tree_size).unwrap();
+
+        let mut header = FrameHeader::new(Opcode::ProofResponse);
+        header.set_room_id(ROOM_ID);
+        Payload::ProofResponse(ProofResponse { request, tree_size, root, path })
+            .into_frame(header)
+            .unwrap()
+    }
+
+    /// Copy of this log with the frame at `log_index` swapped for another.
+    fn rewrite(&self, log_index: usize, replacement: Frame) -> Self {
+        let mut forked = Self::default();
+        for (i, frame) in self.frames.iter().enumerate() {
+            forked.sequence(if i == log_index { replacement.clone() } else { frame.clone() });
+        }
+        forked
+    }
+}
+
+/// Alice, the room creator, trusting `key` for checkpoints.
+fn alice(key: &SigningKey) -> Client<SimEnv> {
+    let mut alice = Client::new(SimEnv::new(), ClientIdentity::new(1));
+    alice.set_checkpoint_key(key.verifying_key());
+    alice.handle(ClientEvent::CreateRoom { room_id: ROOM_ID }).expect("create room");
+    alice
+}
+
+/// Have Alice send a message and return the frame she hands the server.
+fn send(alice: &mut Client<SimEnv>, text: &str) -> Frame {
+    let actions = alice
+        .handle(ClientEvent::SendMessage { room_id: ROOM_ID, plaintext: text.as_bytes().to_vec() })
+        .expect("send message");
+    let [ClientAction::Send(frame)] = actions.as_slice() else {
+        panic!("expected one frame, got {actions:?}");
+    };
+    frame.clone()
+}
+
+/// Deliver a frame from the server.
+///
+/// Alice can't decrypt her own echoed messages, so processing errors are
+/// expected; the transcript is updated before processing either way.
+fn deliver(alice: &mut Client<SimEnv>, frame: Frame) -> Vec<ClientAction> {
+    alice.handle(ClientEvent::FrameReceived(frame)).unwrap_or_default()
+}
+
+/// Send messages through `log`, delivering each sequenced frame to Alice.
+fn exchange(alice: &mut Client<SimEnv>, log: &mut RoomLog, count: usize) {
+    for i in 0..count {
+        let frame = log.sequence(send(alice, &format!("message {i}")));
+        assert_no_violation(&deliver(alice, frame));
+    }
+}
+
+/// The proof request Alice sends in response to a checkpoint.
+fn proof_request(actions: &[ClientAction]) -> Frame {
+    let [ClientAction::Send(request)] = actions else {
+        panic!("expected proof request, got {actions:?}");
+    };
+    assert_eq!(request.header.opcode_enum(), Some(Opcode::ProofRequest));
+    request.clone()
+}
+
+fn assert_no_violation(actions: &[ClientAction]) {
+    assert!(
+        !actions.iter().any(|a| matches!(a, ClientAction::TranscriptViolation { .. })),
+        "unexpected violation: {actions:?}"
+    );
+}
+
+fn assert_violation(actions: &[ClientAction]) {
+    assert!(
+        matches!(actions, [ClientAction::TranscriptViolation { room_id: ROOM_ID, .. }]),
+        "expected violation, got {actions:?}"
+    );
+}
+
+/// An honest server's checkpoints and proofs all verify.
+#[test]
+fn honest_server_passes_verification() {
+    let mut sim = Builder::new().build();
+
+    sim.host("test", || async {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut alice = alice(&key);
+        let mut log = RoomLog::default();
+
+        for _ in 0..3 {
+            exchange(&mut alice, &mut log, 4);
+
+            let checkpoint = log.checkpoint(&key);
+            let request = proof_request(&deliver(&mut alice, checkpoint));
+            assert_no_violation(&deliver(&mut alice, log.prove(&request)));
+        }
+
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
+
+/// A checkpoint over a rewritten prefix doesn't match the log Alice replayed.
+#[test]
+fn rewritten_history_fails_checkpoint() {
+    let mut sim = Builder::new().build();
+
+    sim.host("test", || async {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut alice = alice(&key);
+        let mut log = RoomLog::default();
+
+        exchange(&mut alice, &mut log, 3);
+
+        let replacement = send(&mut alice, "rewritten");
+        let mut forked = log.rewrite(1, replacement);
+
+        assert_violation(&deliver(&mut alice, forked.checkpoint(&key)));
+
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
+
+/// Replaying a different frame at an index Alice already saw is caught.
+#[test]
+fn replayed_frame_mismatch_is_detected() {
+    let mut sim = Builder::new().build();
+
+    sim.host("test", || async {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut alice = alice(&key);
+        let mut log = RoomLog::default();
+
+        exchange(&mut alice, &mut log, 3);
+
+        let replacement = send(&mut alice, "rewritten");
+        let forked = log.rewrite(1, replacement);
+
+        // Same frame again is fine, e.g. from an overlapping sync
+        assert_no_violation(&deliver(&mut alice, log.frames[1].clone()));
+        assert_violation(&deliver(&mut alice, forked.frames[1].clone()));
+
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
+
+/// A server that signs two different hashes for the same log index is caught.
+#[test]
+fn equivocating_checkpoints_are_detected() {
+    let mut sim = Builder::new().build();
+
+    sim.host("test", || async {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut alice = alice(&key);
+        let mut log = RoomLog::default();
+
+        exchange(&mut alice, &mut log, 3);
+        let request = proof_request(&deliver(&mut alice, log.checkpoint(&key)));
+        assert_no_violation(&deliver(&mut alice, log.prove(&request)));
+
+        let mut equivocation = log.clone();
+        equivocation.frames.truncate(3);
+        equivocation.log_hash = LogHash::from_bytes([0xEE; 32]);
+        assert_violation(&deliver(&mut alice, equivocation.checkpoint(&key)));
+
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
+
+/// A server showing Alice the honest log but proving against a forked one
+/// (the view it shows someone else) fails the consistency check.
+#[test]
+fn forked_log_fails_consistency_proof() {
+    let mut sim = Builder::new().build();
+
+    sim.host("test", || async {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut alice = alice(&key);
+        let mut log = RoomLog::default();
+
+        exchange(&mut alice, &mut log, 3);
+        let request = proof_request(&deliver(&mut alice, log.checkpoint(&key)));
+        assert_no_violation(&deliver(&mut alice, log.prove(&request)));
+
+        exchange(&mut alice, &mut log, 3);
+        let request = proof_request(&deliver(&mut alice, log.checkpoint(&key)));
+        assert!(matches!(
+            Payload::from_frame(request.clone()),
+            Ok(Payload::ProofRequest(ProofRequest::Consistency { old_size: 4, .. }))
+        ));
+
+        let replacement = send(&mut alice, "rewritten");
+        let forked = log.rewrite(1, replacement);
+        assert_violation(&deliver(&mut alice, forked.prove(&request)));
+
+        // The honest answer still verifies
+        assert_no_violation(&deliver(&mut alice, log.prove(&request)));
+
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
+
+/// Checkpoints not signed by the trusted key are rejected.
+#[test]
+fn forged_checkpoint_is_rejected() {
+    let mut sim = Builder::new().build();
+
+    sim.host("test", || async {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut alice = alice(&key);
+        let mut log = RoomLog::default();
+
+        exchange(&mut alice, &mut log, 3);
+        let impostor = SigningKey::from_bytes(&[8; 32]);
+        assert_violation(&deliver(&mut alice, log.checkpoint(&impostor)));
+
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
@@ -0,0 +1,100 @@
+//! Client driver tests
+//!
+//! Runs a client over QUIC against the full server runtime.
+
+use std::{path::Path, time::Duration};
+
+use ed25519_dalek::SigningKey;
+use lockframe_client::{
+    Client, ClientAction, ClientDriver, ClientDriverConfig, ClientEvent, ClientIdentity,
+};
+use lockframe_core::connection::ConnectionConfig;
+use lockframe_server::{DriverConfig, Server, ServerRuntimeConfig, SystemEnv};
+
+/// Runtime config for a server on localhost with a self-signed certificate
+/// written to `dir`, and the path of that certificate.
+fn localhost_server(dir: &Path) -> (ServerRuntimeConfig, String) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+    std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+
+    let config = ServerRuntimeConfig {
+        bind_address: "127.0.0.1:0".to_string(),
+        cert_path: Some(cert_path.display().to_string()),
+        key_path: Some(key_path.display().to_string()),
+        ..Default::default()
+    };
+    (config, cert_path.display().to_string())
+}
+
+#[tokio::test]
+async fn driver_runs_the_client_until_its_handles_drop() {
+    let dir = tempfile::tempdir().unwrap();
+    let (config, cert_path) = localhost_server(dir.path());
+    let server = Server::spawn_in_process(config).await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let client = Client::new(SystemEnv::new(), ClientIdentity::new(42));
+    let config = ClientDriverConfig::new(server_addr, "localhost", cert_path);
+    let (driver, mut channels) = ClientDriver::connect(client, config).await.unwrap();
+    let running = tokio::spawn(driver.run());
+
+    // Events reach the client, and what it sends reaches the server
+    channels.handle.send(ClientEvent::SendHeartbeat).await.unwrap();
+    let result = channels
+        .handle
+        .send(ClientEvent::SendMessage { room_id: 0x42, plaintext: b"hi".to_vec() })
+        .await;
+    assert!(result.is_err(), "unknown room should be refused");
+
+    // Frames from the server reach the client, and the actions the driver
+    // does not execute reach the application
+    channels.handle.send(ClientEvent::ListRooms { after: None, limit: 10 }).await.unwrap();
+    let action = tokio::time::timeout(Duration::from_secs(5), channels.actions.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        matches!(&action, ClientAction::RoomDirectory { rooms, more: false } if rooms.is_empty()),
+        "expected an empty directory, got {action:?}"
+    );
+
+    drop(channels);
+    let client = tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap();
+    assert_eq!(client.unwrap().sender_id(), 42);
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn driver_proves_the_identity_key_the_server_requires() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut config, cert_path) = localhost_server(dir.path());
+    let connection = ConnectionConfig { require_identity: true, ..ConnectionConfig::default() };
+    config.driver = DriverConfig { connection, ..DriverConfig::default() };
+    let server = Server::spawn_in_process(config).await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let driver_config = ClientDriverConfig::new(server_addr, "localhost", cert_path);
+
+    // Without a key the handshake fails
+    let client = Client::new(SystemEnv::new(), ClientIdentity::new(7));
+    assert!(ClientDriver::connect(client, driver_config.clone()).await.is_err());
+
+    let identity = ClientIdentity::new(7).with_identity_key(SigningKey::from_bytes(&[7; 32]));
+    let client = Client::new(SystemEnv::new(), identity);
+    let (driver, mut channels) = ClientDriver::connect(client, driver_config).await.unwrap();
+    let running = tokio::spawn(driver.run());
+
+    // Oracle: the session is authenticated, so requests are answered
+    channels.handle.send(ClientEvent::ListRooms { after: None, limit: 10 }).await.unwrap();
+    let action = tokio::time::timeout(Duration::from_secs(5), channels.actions.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(action, ClientAction::RoomDirectory { .. }), "got {action:?}");
+
+    drop(channels);
+    tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+    server.shutdown().await.unwrap();
+}
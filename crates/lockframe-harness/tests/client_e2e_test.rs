@@ -5,10 +5,11 @@
 //! - Empty message handling (edge case)
 //! - Malformed payload rejection (garbage bytes)
 //! - Encryption determinism (same seed → same output, critical for DST)
+//! - Joining through a Welcome (two clients, no server)
 
 use lockframe_client::{Client, ClientAction, ClientEvent, ClientIdentity};
 use lockframe_harness::SimEnv;
-use lockframe_proto::Frame;
+use lockframe_proto::{Frame, Opcode};
 use turmoil::Builder;
 
 /// Test room ID
@@ -276,3 +277,87 @@ fn client_encryption_determinism() {
 
     sim.run().unwrap();
 }
+
+/// Test that a member added by another joins through the Welcome and
+/// decrypts the next message.
+///
+/// WHY THIS TEST IS NEEDED:
+/// The model treats membership as a set. At the protocol level, joining
+/// needs:
+/// - The `KeyPackage` state the Welcome was made for, kept by the joiner
+/// - The group at the epoch the adder's commit moves to
+/// - Sender keys both sides derive from that epoch
+///
+/// If this test fails, it indicates:
+/// - The Welcome was matched to the wrong `KeyPackage` state
+/// - The adder did not take up its own commit when it was sequenced
+/// - Sender key derivation differs between creator and joiner
+#[test]
+fn client_joins_via_welcome_and_decrypts() {
+    let mut sim = Builder::new().build();
+
+    sim.host("test", || async {
+        let env = SimEnv::with_seed(7);
+        let mut alice = Client::new(env.clone(), ClientIdentity::new(1));
+        let mut bob = Client::new(env.clone(), ClientIdentity::new(2));
+
+        alice.handle(ClientEvent::CreateRoom { room_id: ROOM_ID }).expect("create room");
+
+        let actions = bob.handle(ClientEvent::GenerateKeyPackage).expect("generate key package");
+        let key_package = actions
+            .into_iter()
+            .find_map(|action| match action {
+                ClientAction::PublishKeyPackage(key_package) => Some(key_package),
+                _ => None,
+            })
+            .expect("key package published");
+
+        let actions = alice
+            .handle(ClientEvent::AddMembers { room_id: ROOM_ID, key_packages: vec![key_package] })
+            .expect("add bob");
+        let frames = extract_send_frames(&actions);
+        let opcode = |frame: &&Frame| frame.header.opcode_enum();
+        let mut commit = frames
+            .iter()
+            .find(|frame| opcode(frame) == Some(Opcode::Commit))
+            .expect("commit sent")
+            .clone();
+        let welcome = frames
+            .iter()
+            .find(|frame| opcode(frame) == Some(Opcode::Welcome))
+            .expect("welcome sent")
+            .clone();
+
+        // The sequenced commit comes back to alice
+        commit.header.set_log_index(0);
+        alice.handle(ClientEvent::FrameReceived(commit)).expect("alice takes up her commit");
+
+        let actions = bob.handle(ClientEvent::FrameReceived(welcome)).expect("bob joins");
+        let epoch = alice.epoch(ROOM_ID).expect("alice in room");
+        assert_eq!(epoch, 1);
+        assert!(actions.iter().any(|action| matches!(action, ClientAction::RoomJoined {
+            room_id: ROOM_ID,
+            epoch: 1
+        })));
+        assert_eq!(bob.epoch(ROOM_ID), Some(epoch));
+
+        let actions = alice
+            .handle(ClientEvent::SendMessage { room_id: ROOM_ID, plaintext: b"welcome".to_vec() })
+            .expect("send message");
+        let mut message = extract_send_frames(&actions).remove(0);
+        message.header.set_log_index(1);
+
+        let actions = bob.handle(ClientEvent::FrameReceived(message)).expect("bob receives");
+        let delivered = actions.iter().find_map(|action| match action {
+            ClientAction::DeliverMessage { sender_id, plaintext, .. } => {
+                Some((*sender_id, plaintext.clone()))
+            },
+            _ => None,
+        });
+        assert_eq!(delivered, Some((1, b"welcome".to_vec())));
+
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
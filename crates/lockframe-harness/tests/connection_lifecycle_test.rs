@@ -5,7 +5,10 @@
 //! - Heartbeat/keepalive
 //! - Timeout detection
 //! - Graceful shutdown
+//! - Identity challenge answered by the client, against the server driver
 
+use ed25519_dalek::SigningKey;
+use lockframe_client::{Client, ClientAction, ClientEvent, ClientIdentity, HOME_SERVER};
 use lockframe_core::{
     connection::{Connection, ConnectionConfig, ConnectionState},
     env::Environment,
@@ -16,6 +19,7 @@ use lockframe_proto::{
     Frame, FrameHeader, Opcode, Payload,
     payloads::session::{Goodbye, Hello, HelloReply},
 };
+use lockframe_server::{DriverConfig, MemoryStorage, ServerAction, ServerDriver, ServerEvent};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 /// Helper to convert any error to Box<dyn Error>
@@ -61,6 +65,7 @@ fn connection_handshake_lifecycle() {
                     session_id,
                     capabilities: vec![],
                     challenge: None,
+                    time_sync: None,
                 });
 
                 let reply_frame =
@@ -207,3 +212,48 @@ fn connection_graceful_shutdown() {
 
     sim.run().expect("graceful shutdown should complete");
 }
+
+#[test]
+fn client_answers_identity_challenge() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let identity = ClientIdentity::new(1).with_identity_key(key.clone());
+    let mut client = Client::new(SimEnv::with_seed(1), identity);
+
+    let connection = ConnectionConfig { require_identity: true, ..ConnectionConfig::default() };
+    let config = DriverConfig { connection, ..DriverConfig::default() };
+    let mut server = ServerDriver::new(SimEnv::with_seed(2), MemoryStorage::new(), config);
+    server.process_event(ServerEvent::ConnectionAccepted { session_id: 1 }).unwrap();
+
+    // Carry frames both ways until neither side has more to say
+    let mut to_server = vec![client.hello(HOME_SERVER, None).unwrap()];
+    while !to_server.is_empty() {
+        let mut to_client = Vec::new();
+        for frame in to_server.drain(..) {
+            let event = ServerEvent::FrameReceived { session_id: 1, frame };
+            for action in server.process_event(event).unwrap() {
+                match action {
+                    ServerAction::SendToSession { session_id: 1, frame } => to_client.push(frame),
+                    ServerAction::CloseConnection { reason, .. } => panic!("closed: {reason}"),
+                    _ => {},
+                }
+            }
+        }
+        for frame in to_client {
+            for action in client.handle(ClientEvent::FrameReceived(frame)).unwrap() {
+                if let ClientAction::Send(frame) = action {
+                    to_server.push(frame);
+                }
+            }
+        }
+    }
+
+    // Oracle: both sides finished the handshake, and the server knows the
+    // client by the key it proved
+    assert!(client.is_authenticated(HOME_SERVER));
+    let principal = key
+        .verifying_key()
+        .as_bytes()
+        .iter()
+        .fold(String::from("ed25519:"), |principal, byte| format!("{principal}{byte:02x}"));
+    assert_eq!(server.session_principal(1), Some(principal.as_str()));
+}
@@ -0,0 +1,310 @@
+//! Crash-consistency tests using fault points inside the driver.
+//!
+//! Process-level kills stop the server between frames. These tests stop it
+//! between the stages of one frame instead, via a `FaultHook`:
+//! - Before sequencing: the frame is lost, and nothing of it is stored
+//! - After persist: the frame is stored but never broadcast, and a restarted
+//!   server serves it to syncing members without reusing its log index
+//! - Before broadcast, delayed: delivery lags but storage does not
+//! - After persisting a proposal: the restarted server still has it queued for
+//!   the next commit
+//!
+//! Storage that tracks flushes stands in for a disk losing what was never
+//! flushed, so a crash just before `flush_storage` and one just after it can
+//! be told apart.
+//!
+//! # Oracle Pattern
+//!
+//! Each test ends by checking the stored log has no gaps and no duplicate log
+//! indices across the restart.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use lockframe_harness::SimEnv;
+use lockframe_proto::{
+    Frame, FrameHeader, Opcode, Payload,
+    payloads::{
+        app::EncryptedMessage,
+        session::{SyncMode, SyncRequest},
+    },
+};
+use lockframe_server::{
+    DriverConfig, DriverError, Fault, FaultPoint, InjectedFault, MemoryStorage, ServerAction,
+    ServerDriver, ServerEvent, Storage,
+};
+
+const ROOM: u128 = 0xc0ff_ee00_0000_0000_0000_0000_0000_0001;
+
+type Driver = ServerDriver<SimEnv, MemoryStorage>;
+
+fn message(body: &str) -> Frame {
+    let mut header = FrameHeader::new(Opcode::AppMessage);
+    header.set_room_id(ROOM);
+    header.set_sender_id(1);
+    header.set_epoch(0);
+
+    let mut ciphertext = body.as_bytes().to_vec();
+    ciphertext.resize(body.len() + EncryptedMessage::TAG_SIZE, 0);
+    let message = EncryptedMessage {
+        epoch: 0,
+        sender_index: 0,
+        generation: 0,
+        padding: 0,
+        cipher_suite: 0,
+        nonce: [0; 24],
+        ciphertext,
+        push_keys: None,
+    };
+    Payload::AppMessage(message).into_frame(header).unwrap()
+}
+
+fn proposal(body: &str) -> Frame {
+    let mut header = FrameHeader::new(Opcode::Proposal);
+    header.set_room_id(ROOM);
+    header.set_sender_id(1);
+    header.set_epoch(0);
+    Frame::new(header, body.as_bytes().to_vec())
+}
+
+/// A server with session 1 connected and in the room, over `storage`.
+fn start(storage: &MemoryStorage) -> Driver {
+    let mut driver = ServerDriver::new(SimEnv::new(), storage.clone(), DriverConfig::default());
+    driver.process_event(ServerEvent::ConnectionAccepted { session_id: 1 }).unwrap();
+    driver.create_room(ROOM, 1).unwrap();
+    driver
+}
+
+/// Crash at `point` the first time it is reached.
+fn crash_once_at(driver: &mut Driver, point: FaultPoint) {
+    crash_or_delay_once_at(driver, point, Fault::Crash);
+}
+
+fn crash_or_delay_once_at(driver: &mut Driver, point: FaultPoint, fault: Fault) {
+    let mut armed = true;
+    driver.set_fault_hook(move |at, _: &Frame| {
+        if armed && at == point {
+            armed = false;
+            fault
+        } else {
+            Fault::Continue
+        }
+    });
+}
+
+/// Execute the storage writes among `actions`, as a runtime would.
+fn persist(driver: &Driver, actions: &[ServerAction]) {
+    let storage = driver.storage();
+    for action in actions {
+        match action {
+            ServerAction::PersistFrame { room_id, log_index, frame } => {
+                // The sequencer may ask for one frame twice
+                let _ = storage.store_frame(*room_id, *log_index, frame);
+            },
+            ServerAction::PersistCommit { room_id, log_index, frame, state } => {
+                storage.store_commit(*room_id, *log_index, frame, state).unwrap();
+            },
+            ServerAction::PersistProposal { room_id, proposal } => {
+                storage.store_pending_proposal(*room_id, proposal).unwrap();
+            },
+            ServerAction::ClearProposals { room_id } => {
+                storage.clear_pending_proposals(*room_id).unwrap();
+            },
+            _ => {},
+        }
+    }
+}
+
+fn send(driver: &mut Driver, body: &str) -> Result<Vec<ServerAction>, DriverError> {
+    submit(driver, message(body))
+}
+
+fn propose(driver: &mut Driver, body: &str) -> Result<Vec<ServerAction>, DriverError> {
+    submit(driver, proposal(body))
+}
+
+fn submit(driver: &mut Driver, frame: Frame) -> Result<Vec<ServerAction>, DriverError> {
+    let result = driver.process_event(ServerEvent::FrameReceived { session_id: 1, frame });
+    if let Ok(actions) = &result {
+        persist(driver, actions);
+    }
+    result
+}
+
+fn queued(driver: &mut Driver) -> Vec<u64> {
+    let pending = driver.pending_proposals(ROOM).unwrap();
+    pending.iter().map(|proposal| proposal.header.log_index()).collect()
+}
+
+fn injected(result: Result<Vec<ServerAction>, DriverError>) -> InjectedFault {
+    match result {
+        Err(DriverError::Injected(injected)) => *injected,
+        other => panic!("expected an injected fault, got {other:?}"),
+    }
+}
+
+fn broadcasts(actions: &[ServerAction]) -> usize {
+    actions.iter().filter(|action| matches!(action, ServerAction::BroadcastToRoom { .. })).count()
+}
+
+/// Oracle: the stored log is gap-free and holds `expected` frames.
+fn verify_log(storage: &MemoryStorage, expected: usize) -> Vec<Frame> {
+    let log = storage.load_frames(ROOM, 0, usize::MAX).unwrap();
+    assert_eq!(log.len(), expected);
+    for (index, frame) in log.iter().enumerate() {
+        assert_eq!(frame.header.log_index(), index as u64, "log has a gap or duplicate");
+    }
+    log
+}
+
+#[test]
+fn crash_before_sequencing_loses_only_that_frame() {
+    let storage = MemoryStorage::new();
+    let mut driver = start(&storage);
+    send(&mut driver, "first").unwrap();
+
+    crash_once_at(&mut driver, FaultPoint::BeforeSequencing);
+    let fault = injected(send(&mut driver, "lost"));
+    assert_eq!(fault.point, FaultPoint::BeforeSequencing);
+    assert!(fault.completed.is_empty() && fault.pending.is_empty());
+    drop(driver);
+
+    let mut driver = start(&storage);
+    send(&mut driver, "retried").unwrap();
+
+    let log = verify_log(&storage, 2);
+    assert_eq!(log[1].payload, message("retried").payload);
+}
+
+#[test]
+fn crash_after_persist_keeps_the_frame_for_sync() {
+    let storage = MemoryStorage::new();
+    let mut driver = start(&storage);
+
+    crash_once_at(&mut driver, FaultPoint::AfterPersist);
+    let fault = injected(send(&mut driver, "stored, never broadcast"));
+    assert_eq!(fault.point, FaultPoint::AfterPersist);
+    assert_eq!(broadcasts(&fault.completed), 0);
+    assert_eq!(broadcasts(&fault.pending), 1);
+    persist(&driver, &fault.completed);
+    drop(driver);
+
+    // Members that missed the broadcast find the frame on the restarted server
+    let mut driver = start(&storage);
+    let mut header = FrameHeader::new(Opcode::SyncRequest);
+    header.set_room_id(ROOM);
+    let request = SyncRequest { from_log_index: 0, limit: 10, mode: SyncMode::Full };
+    let frame = Payload::SyncRequest(request).into_frame(header).unwrap();
+    let actions =
+        driver.process_event(ServerEvent::FrameReceived { session_id: 1, frame }).unwrap();
+    let synced = actions.iter().find_map(|action| match action {
+        ServerAction::SendToSession { frame, .. } => match Payload::from_frame(frame.clone()) {
+            Ok(Payload::SyncResponse(response)) => Some(response.frames.len()),
+            _ => None,
+        },
+        _ => None,
+    });
+    assert_eq!(synced, Some(1));
+
+    // and the next frame does not reuse its log index
+    send(&mut driver, "after restart").unwrap();
+    verify_log(&storage, 2);
+}
+
+#[test]
+fn crash_after_persisting_a_proposal_keeps_it_queued() {
+    let storage = MemoryStorage::new();
+    let mut driver = start(&storage);
+    propose(&mut driver, "first").unwrap();
+
+    crash_once_at(&mut driver, FaultPoint::AfterPersist);
+    let fault = injected(propose(&mut driver, "stored, never broadcast"));
+    assert_eq!(fault.point, FaultPoint::AfterPersist);
+    persist(&driver, &fault.completed);
+
+    crash_once_at(&mut driver, FaultPoint::BeforeSequencing);
+    injected(propose(&mut driver, "lost"));
+    drop(driver);
+
+    // The restarted server queues both stored proposals for the next commit
+    let mut driver = start(&storage);
+    assert_eq!(queued(&mut driver), vec![0, 1]);
+
+    // and the next proposal queues behind them without reusing a log index
+    propose(&mut driver, "after restart").unwrap();
+    assert_eq!(queued(&mut driver), vec![0, 1, 2]);
+    verify_log(&storage, 3);
+}
+
+#[test]
+fn delay_before_broadcast_holds_back_delivery_only() {
+    let storage = MemoryStorage::new();
+    let mut driver = start(&storage);
+    let delay = Duration::from_millis(250);
+
+    crash_or_delay_once_at(&mut driver, FaultPoint::BeforeBroadcast, Fault::Delay(delay));
+    let fault = injected(send(&mut driver, "late"));
+    assert_eq!(fault.fault, Fault::Delay(delay));
+    assert_eq!(broadcasts(&fault.completed), 0);
+    assert_eq!(broadcasts(&fault.pending), 1);
+    persist(&driver, &fault.completed);
+    verify_log(&storage, 1);
+
+    // The driver carries on: later frames are neither blocked nor reordered
+    let actions = send(&mut driver, "on time").unwrap();
+    assert_eq!(broadcasts(&actions), 1);
+    verify_log(&storage, 2);
+}
+
+#[test]
+fn crash_before_flush_loses_unflushed_frames() {
+    let storage = MemoryStorage::with_flush_tracking();
+    let mut driver = start(&storage);
+    send(&mut driver, "flushed").unwrap();
+    driver.flush_storage().unwrap();
+    send(&mut driver, "never flushed").unwrap();
+    drop(driver);
+
+    let restarted = storage.after_crash();
+    let log = verify_log(&restarted, 1);
+    assert_eq!(log[0].payload, message("flushed").payload);
+
+    // The restarted server numbers the lost frame's slot afresh
+    let mut driver = start(&restarted);
+    send(&mut driver, "resent").unwrap();
+    verify_log(&restarted, 2);
+}
+
+#[test]
+fn crash_after_close_keeps_every_frame() {
+    let storage = MemoryStorage::with_flush_tracking();
+    let mut driver = start(&storage);
+    for body in ["one", "two", "three"] {
+        send(&mut driver, body).unwrap();
+    }
+    driver.close_storage().unwrap();
+    drop(driver);
+
+    verify_log(&storage.after_crash(), 3);
+}
+
+#[test]
+fn hooks_see_every_stage_in_pipeline_order() {
+    let storage = MemoryStorage::new();
+    let mut driver = start(&storage);
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let record = Arc::clone(&seen);
+    driver.set_fault_hook(move |point, _: &Frame| {
+        record.lock().unwrap().push(point);
+        Fault::Continue
+    });
+
+    send(&mut driver, "observed").unwrap();
+    assert_eq!(*seen.lock().unwrap(), vec![
+        FaultPoint::BeforeSequencing,
+        FaultPoint::AfterPersist,
+        FaultPoint::BeforeBroadcast,
+    ]);
+}
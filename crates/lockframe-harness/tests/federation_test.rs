@@ -0,0 +1,201 @@
+//! Federation tests: one room with members on several servers.
+//!
+//! These tests verify:
+//! - Frames from members on any server are sequenced once, by the room's home
+//! - Every server stores the same log and delivers it to its members
+//! - Duplicated and reordered relays are applied once, in log order
+//! - Lost relays are resent once the gap they leave is noticed
+//! - Answers for one member travel back to the server it is connected to,
+//!   whatever session IDs the home uses itself
+//!
+//! # Oracle Pattern
+//!
+//! Each test ends by comparing every server's copy of the room's log with the
+//! home's.
+
+use lockframe_harness::{DeliverySchedule, SimFederation};
+use lockframe_proto::{
+    Frame, FrameHeader, Opcode, Payload,
+    payloads::{
+        app::EncryptedMessage,
+        session::{SyncMode, SyncRequest},
+    },
+};
+use lockframe_server::{RESEND_INTERVAL, ServerEvent, ServerId};
+
+const ROOM: u128 = 0xfede_0000_0000_0000_0000_0000_0000_0001;
+
+const HOME: ServerId = ServerId(1);
+const EDGE: ServerId = ServerId(2);
+const FAR: ServerId = ServerId(3);
+
+fn message(sender_id: u64, body: &str) -> Frame {
+    let mut header = FrameHeader::new(Opcode::AppMessage);
+    header.set_room_id(ROOM);
+    header.set_sender_id(sender_id);
+    header.set_epoch(0);
+
+    let mut ciphertext = body.as_bytes().to_vec();
+    ciphertext.resize(body.len() + EncryptedMessage::TAG_SIZE, 0);
+    let message = EncryptedMessage {
+        epoch: 0,
+        sender_index: 0,
+        generation: 0,
+        padding: 0,
+        cipher_suite: 0,
+        nonce: [0; 24],
+        ciphertext,
+        push_keys: None,
+    };
+    Payload::AppMessage(message).into_frame(header).unwrap()
+}
+
+fn send(federation: &mut SimFederation, server: ServerId, session_id: u64, body: &str) {
+    let frame = message(session_id, body);
+    federation.process(server, ServerEvent::FrameReceived { session_id, frame }).unwrap();
+}
+
+fn connect(federation: &mut SimFederation, server: ServerId, session_id: u64) {
+    federation.process(server, ServerEvent::ConnectionAccepted { session_id }).unwrap();
+}
+
+fn payloads(frames: &[Frame]) -> Vec<&[u8]> {
+    frames.iter().map(|frame| frame.payload.as_ref()).collect()
+}
+
+/// Oracle: every server holds the home's log, without gaps.
+fn verify_logs_match_home(federation: &SimFederation, servers: &[ServerId]) -> Vec<Frame> {
+    let home_log = federation.stored_log(HOME, ROOM);
+    for (index, frame) in home_log.iter().enumerate() {
+        assert_eq!(frame.header.log_index(), index as u64, "home log has a gap");
+    }
+    for &server in servers {
+        let log = federation.stored_log(server, ROOM);
+        assert_eq!(payloads(&log), payloads(&home_log), "{server} diverged from the home");
+    }
+    home_log
+}
+
+#[test]
+fn members_on_different_servers_share_one_log() {
+    let mut federation = SimFederation::new([HOME, EDGE]);
+    connect(&mut federation, HOME, 1);
+    federation.driver_mut(HOME).create_room(ROOM, 1).unwrap();
+    federation.home_room(ROOM, HOME);
+    connect(&mut federation, EDGE, 2);
+
+    // The home sequences its own member's frame before the forwarded one
+    // arrives
+    send(&mut federation, EDGE, 2, "from edge");
+    send(&mut federation, HOME, 1, "from home");
+    federation.deliver_relays().unwrap();
+
+    // The edge server never sequences the room itself
+    assert!(!federation.driver(EDGE).has_room(ROOM));
+
+    let log = verify_logs_match_home(&federation, &[EDGE]);
+    assert_eq!(payloads(&log), payloads(&[message(1, "from home"), message(2, "from edge")]));
+    assert_eq!(payloads(federation.received(HOME, 1)), payloads(&log));
+    assert_eq!(payloads(federation.received(EDGE, 2)), payloads(&log));
+}
+
+#[test]
+fn duplicated_and_reordered_relays_apply_once_in_order() {
+    for seed in 0..16 {
+        let mut federation = SimFederation::new([HOME, EDGE, FAR])
+            .with_schedule(DeliverySchedule::seeded(seed))
+            .with_duplicates();
+        connect(&mut federation, HOME, 1);
+        federation.driver_mut(HOME).create_room(ROOM, 1).unwrap();
+        federation.home_room(ROOM, HOME);
+        for (server, session_id) in [(EDGE, 2), (FAR, 3)] {
+            connect(&mut federation, server, session_id);
+            federation.driver_mut(server).subscribe_to_room(session_id, ROOM);
+        }
+
+        for i in 0..6 {
+            send(&mut federation, HOME, 1, &format!("message {i}"));
+        }
+        assert_eq!(federation.deliver_relays().unwrap(), 24);
+
+        let log = verify_logs_match_home(&federation, &[EDGE, FAR]);
+        assert_eq!(log.len(), 6);
+        for (server, session_id) in [(EDGE, 2), (FAR, 3)] {
+            let received = federation.received(server, session_id);
+            assert_eq!(payloads(received), payloads(&log), "seed {seed}: {server} delivery");
+
+            let federated = federation.driver(server).federation();
+            assert_eq!(federated.duplicates(), 6, "seed {seed}: {server} dedup");
+            assert_eq!(federated.held(ROOM), 0);
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn lost_relays_are_resent() {
+    let mut federation = SimFederation::new([HOME, EDGE]);
+    connect(&mut federation, HOME, 1);
+    federation.driver_mut(HOME).create_room(ROOM, 1).unwrap();
+    federation.home_room(ROOM, HOME);
+    connect(&mut federation, EDGE, 2);
+    federation.driver_mut(EDGE).subscribe_to_room(2, ROOM);
+
+    federation.lose_relays(1);
+    for i in 0..3 {
+        send(&mut federation, HOME, 1, &format!("message {i}"));
+    }
+    federation.deliver_relays().unwrap();
+    assert_eq!(federation.driver(EDGE).federation().held(ROOM), 2);
+
+    // The gap may still be reordering at first; once it has lasted, the
+    // edge asks the home to fill it
+    for _ in 0..2 {
+        federation.process(EDGE, ServerEvent::Tick).unwrap();
+        tokio::time::advance(RESEND_INTERVAL).await;
+    }
+    federation.process(EDGE, ServerEvent::Tick).unwrap();
+    federation.deliver_relays().unwrap();
+
+    let log = verify_logs_match_home(&federation, &[EDGE]);
+    assert_eq!(log.len(), 3);
+    assert_eq!(payloads(federation.received(EDGE, 2)), payloads(&log));
+    assert_eq!(federation.driver(EDGE).federation().held(ROOM), 0);
+}
+
+#[test]
+fn answers_reach_the_member_through_its_server() {
+    let mut federation = SimFederation::new([HOME, EDGE]);
+    connect(&mut federation, HOME, 2);
+    federation.driver_mut(HOME).create_room(ROOM, 2).unwrap();
+    federation.home_room(ROOM, HOME);
+    // The same session ID as the home's own member
+    connect(&mut federation, EDGE, 2);
+
+    send(&mut federation, HOME, 2, "before sync");
+    federation.deliver_relays().unwrap();
+    let home_received = federation.received(HOME, 2).len();
+
+    let mut header = FrameHeader::new(Opcode::SyncRequest);
+    header.set_room_id(ROOM);
+    let request = SyncRequest { from_log_index: 0, limit: 10, mode: SyncMode::Full };
+    let frame = Payload::SyncRequest(request).into_frame(header).unwrap();
+    federation.process(EDGE, ServerEvent::FrameReceived { session_id: 2, frame }).unwrap();
+    federation.deliver_relays().unwrap();
+
+    let received = federation.received(EDGE, 2);
+    assert_eq!(received.len(), 1);
+    let Ok(Payload::SyncResponse(response)) = Payload::from_frame(received[0].clone()) else {
+        panic!("expected a sync response, got {:?}", received[0].header.opcode_enum());
+    };
+    assert_eq!(response.frames.len(), 1);
+    assert_eq!(federation.received(HOME, 2).len(), home_received);
+    assert_eq!(federation.driver(HOME).federation().remote_session_count(), 1);
+
+    // The home forgets the member's session once it closes
+    let event = ServerEvent::ConnectionClosed { session_id: 2, reason: "done".to_string() };
+    federation.process(EDGE, event).unwrap();
+    federation.deliver_relays().unwrap();
+    assert_eq!(federation.driver(HOME).federation().remote_session_count(), 0);
+
+    verify_logs_match_home(&federation, &[EDGE]);
+}
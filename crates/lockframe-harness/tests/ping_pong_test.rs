@@ -129,6 +129,7 @@ fn ping_pong_with_payload() {
                     session_id: 0x1234_5678_9ABC_DEF0,
                     capabilities: vec![],
                     challenge: None,
+                    time_sync: None,
                 });
 
                 let reply_frame =
@@ -15,8 +15,10 @@
 //! Each test ends with an Oracle function that verifies global consistency:
 //! - No gaps in log indices
 //! - Monotonic ordering
+//! - Sequencer watermarks match storage after every batch
 
 use bytes::Bytes;
+use lockframe_harness::{WatermarkError, WatermarkOracle};
 use lockframe_proto::{Frame, FrameHeader, Opcode};
 use lockframe_server::{MemoryStorage, Sequencer, SequencerAction, Storage};
 
@@ -304,3 +306,71 @@ fn test_sequencer_accepts_all_senders() {
     let stored_senders: Vec<u64> = frames.iter().map(|f| f.header.sender_id()).collect();
     assert_eq!(stored_senders, vec![200, 300, 400, 500, 600]);
 }
+
+/// Sequence `frames` as one batch, storing each unless its index is in
+/// `dropped`.
+fn run_batch(
+    sequencer: &mut Sequencer,
+    storage: &MemoryStorage,
+    frames: Vec<Frame>,
+    dropped: &[u64],
+) {
+    for frame in frames {
+        let actions = sequencer.process_frame(frame, storage).expect("process_frame failed");
+        for action in actions {
+            if let SequencerAction::StoreFrame { room_id, log_index, frame } = action {
+                if !dropped.contains(&log_index) {
+                    storage.store_frame(room_id, log_index, &frame).expect("store_frame failed");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_watermarks_agree_after_every_batch() {
+    let mut sequencer = Sequencer::new();
+    let storage = MemoryStorage::new();
+    let mut oracle = WatermarkOracle::new();
+
+    for batch in 0..4 {
+        let frames = (0..3)
+            .flat_map(|i| {
+                [
+                    create_test_frame(100, 200, 0, &format!("a-{batch}-{i}")),
+                    create_test_frame(101, 200, 0, &format!("b-{batch}-{i}")),
+                ]
+            })
+            .collect();
+        run_batch(&mut sequencer, &storage, frames, &[]);
+
+        // Oracle: Sequencer and storage agree at every batch boundary
+        oracle.check(&sequencer, &storage).expect("watermark drift");
+    }
+
+    assert_eq!(oracle.batches_checked(), 4);
+    assert_eq!(sequencer.next_log_index(100), Some(12));
+    verify_sequential_indices(&storage, 101, 12);
+}
+
+#[test]
+fn test_watermark_drift_caught_at_diverging_batch() {
+    let mut sequencer = Sequencer::new();
+    let storage = MemoryStorage::new();
+    let mut oracle = WatermarkOracle::new();
+
+    let batch =
+        |n: u64| (0..2).map(|i| create_test_frame(100, 200, 0, &format!("{n}-{i}"))).collect();
+
+    run_batch(&mut sequencer, &storage, batch(1), &[]);
+    oracle.check(&sequencer, &storage).expect("batch 1 persisted everything");
+
+    // The second batch loses its last write
+    run_batch(&mut sequencer, &storage, batch(2), &[3]);
+
+    // Oracle: The drift is reported for the batch that caused it
+    assert_eq!(
+        oracle.check(&sequencer, &storage),
+        Err(WatermarkError::Drift { batch: 2, room_id: 100, sequencer_next: 4, storage_next: 3 })
+    );
+}
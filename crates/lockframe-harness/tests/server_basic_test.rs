@@ -14,8 +14,11 @@ use std::time::Duration;
 
 use bytes::Bytes;
 use lockframe_harness::SimServer;
-use lockframe_proto::{Frame, FrameHeader, Opcode, Payload, payloads::session::Hello};
-use lockframe_server::ServerEvent;
+use lockframe_proto::{
+    Frame, FrameHeader, Opcode, Payload,
+    payloads::{app::EncryptedMessage, session::Hello},
+};
+use lockframe_server::{DriverConfig, FaultProfile, MemoryStorage, ServerEvent};
 use tokio::io::AsyncReadExt;
 use turmoil::{Builder, net::TcpStream};
 
@@ -33,6 +36,29 @@ fn verify_room_exists(server: &SimServer, room_id: u128, context: &str) {
     assert!(server.has_room(room_id), "{}: room {:032x} should exist", context, room_id);
 }
 
+/// Well-formed `AppMessage` in [`ROOM_ID`] from `sender_id` at epoch 0 and
+/// `generation`.
+fn app_message(sender_id: u64, generation: u32) -> Frame {
+    let mut header = FrameHeader::new(Opcode::AppMessage);
+    header.set_room_id(ROOM_ID);
+    header.set_sender_id(sender_id);
+    header.set_epoch(0);
+
+    let mut nonce = [0; 24];
+    nonce[12..16].copy_from_slice(&generation.to_be_bytes());
+    let message = EncryptedMessage {
+        epoch: 0,
+        sender_index: 0,
+        generation,
+        padding: 0,
+        cipher_suite: 0,
+        nonce,
+        ciphertext: vec![0; EncryptedMessage::TAG_SIZE],
+        push_keys: None,
+    };
+    Payload::AppMessage(message).into_frame(header).expect("AppMessage should encode")
+}
+
 #[test]
 fn server_accepts_connection() {
     let mut sim = Builder::new().build();
@@ -45,7 +71,7 @@ fn server_accepts_connection() {
 
         // Oracle: Connection should be registered
         verify_connection_count(&server, 1, "after accept");
-        assert_eq!(conn_id, 1, "First connection should have ID 1");
+        assert_ne!(conn_id, 0, "Session IDs are never zero");
 
         Ok(())
     });
@@ -160,10 +186,8 @@ fn server_multiple_connections() {
         // Oracle: All connections should be registered
         verify_connection_count(&server, 3, "after 3 accepts");
 
-        // Connection IDs should be sequential
-        assert_eq!(conn1, 1);
-        assert_eq!(conn2, 2);
-        assert_eq!(conn3, 3);
+        // Connection IDs should be unique
+        assert!(conn1 != conn2 && conn2 != conn3 && conn1 != conn3);
 
         Ok(())
     });
@@ -209,12 +233,7 @@ fn server_processes_app_message_frame() {
         server.create_room(ROOM_ID, conn_id)?;
 
         // Create an AppMessage frame
-        let mut header = FrameHeader::new(Opcode::AppMessage);
-        header.set_room_id(ROOM_ID);
-        header.set_sender_id(conn_id);
-        header.set_epoch(0);
-
-        let frame = Frame::new(header, Bytes::from("test message"));
+        let frame = app_message(conn_id, 0);
 
         // Process frame - should succeed
         let result = server.process_frame(conn_id, frame).await;
@@ -232,6 +251,79 @@ fn server_processes_app_message_frame() {
     sim.run().unwrap();
 }
 
+#[test]
+fn server_watermarks_checked_after_every_batch() {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let mut server = SimServer::bind("0.0.0.0:443").await?;
+        server.check_watermarks();
+
+        let conn_id = server.accept_connection().await?;
+        server.create_room(ROOM_ID, conn_id)?;
+
+        for generation in 0..5u32 {
+            let frame = app_message(conn_id, generation);
+
+            // Fails as soon as a batch leaves storage behind the sequencer
+            server.process_frame(conn_id, frame).await?;
+        }
+
+        // Oracle: Every message was sequenced and persisted
+        assert_eq!(server.driver().sequencer().next_log_index(ROOM_ID), Some(5));
+
+        Ok(())
+    });
+
+    sim.client("client", async {
+        let _stream = TcpStream::connect("server:443").await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
+
+#[test]
+fn server_waits_out_storage_latency_spikes() {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let spike = Duration::from_millis(20);
+        let profile = FaultProfile::new(7).with_latency_spikes(1.0, spike);
+        let mut server = SimServer::bind_with_faults(
+            "0.0.0.0:443",
+            DriverConfig::default(),
+            MemoryStorage::new(),
+            profile,
+        )
+        .await?;
+
+        let conn_id = server.accept_connection().await?;
+        server.create_room(ROOM_ID, conn_id)?;
+        server.driver().storage().take_stall();
+
+        let frame = app_message(conn_id, 0);
+
+        let started = tokio::time::Instant::now();
+        server.process_frame(conn_id, frame).await?;
+
+        // Oracle: The spike passed in simulated time and nothing is left owing
+        assert!(started.elapsed() >= spike);
+        assert_eq!(server.driver().storage().take_stall(), Duration::ZERO);
+
+        Ok(())
+    });
+
+    sim.client("client", async {
+        let _stream = TcpStream::connect("server:443").await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
+
 #[test]
 fn server_rejects_frame_for_unknown_room() {
     let mut sim = Builder::new().build();
@@ -4,6 +4,8 @@
 //! - Messages are broadcast to all room members
 //! - Sender exclusion works correctly
 //! - Multiple rooms are isolated
+//! - Batched events from several connections sequence in batch order
+//! - Members offline when a frame is broadcast get it queued and a push notice
 //!
 //! # Oracle Pattern
 //!
@@ -12,7 +14,7 @@
 use bytes::Bytes;
 use lockframe_harness::SimServer;
 use lockframe_proto::{Frame, FrameHeader, Opcode};
-use lockframe_server::ServerEvent;
+use lockframe_server::{OfflineNotice, ServerAction, ServerEvent};
 use turmoil::Builder;
 
 /// Test room IDs
@@ -345,3 +347,146 @@ fn empty_room_after_all_disconnect() {
 
     sim.run().unwrap();
 }
+
+#[test]
+fn batched_events_interleave_in_order() {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let mut server = SimServer::bind("0.0.0.0:443").await?;
+
+        let accepted = server
+            .driver_mut()
+            .process_events(
+                (1..=3).map(|session_id| ServerEvent::ConnectionAccepted { session_id }),
+            )
+            .expect("accept batch");
+        assert_eq!(accepted.len(), 3);
+
+        server.create_room(ROOM_1, 1)?;
+        server.subscribe_to_room(2, ROOM_1);
+        server.subscribe_to_room(3, ROOM_1);
+
+        // Alternate senders within one batch
+        let senders = [1, 2, 1, 3, 2];
+        let events = senders.iter().map(|&sender| {
+            let mut header = FrameHeader::new(Opcode::AppMessage);
+            header.set_room_id(ROOM_1);
+            header.set_sender_id(sender);
+            header.set_epoch(0);
+
+            ServerEvent::FrameReceived {
+                session_id: sender,
+                frame: Frame::new(header, Bytes::from(format!("from {sender}"))),
+            }
+        });
+        let actions = server.driver_mut().process_events(events).expect("frame batch");
+
+        // Oracle: log order matches batch order, one broadcast per frame
+        let persisted: Vec<(u64, u64)> = actions
+            .iter()
+            .filter_map(|action| match action {
+                ServerAction::PersistFrame { log_index, frame, .. } => {
+                    Some((*log_index, frame.header.sender_id()))
+                },
+                _ => None,
+            })
+            .collect();
+        let expected: Vec<(u64, u64)> = senders.iter().zip(0..).map(|(&s, i)| (i, s)).collect();
+        assert_eq!(persisted, expected);
+
+        let broadcasts: Vec<Option<u64>> = actions
+            .iter()
+            .filter_map(|action| match action {
+                ServerAction::BroadcastToRoom { exclude_session, .. } => Some(*exclude_session),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(broadcasts, senders.iter().map(|&s| Some(s)).collect::<Vec<_>>());
+
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
+
+#[test]
+fn offline_member_receives_queued_frames_on_return() {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let mut server = SimServer::bind("0.0.0.0:443").await?;
+
+        let message = |session_id: u64, sender: u64| {
+            let mut header = FrameHeader::new(Opcode::AppMessage);
+            header.set_room_id(ROOM_1);
+            header.set_sender_id(sender);
+            header.set_epoch(0);
+
+            ServerEvent::FrameReceived {
+                session_id,
+                frame: Frame::new(header, Bytes::from(format!("from {sender}"))),
+            }
+        };
+
+        server
+            .driver_mut()
+            .process_events(
+                (1..=2).map(|session_id| ServerEvent::ConnectionAccepted { session_id }),
+            )
+            .expect("accept batch");
+        let authenticated = |session_id: u64| ServerEvent::PeerAuthenticated {
+            session_id,
+            principal: "sha256:member-2".to_string(),
+        };
+        server.driver_mut().process_event(authenticated(2)).expect("authenticate member 2");
+        server.create_room(ROOM_1, 1)?;
+        server.subscribe_to_room(2, ROOM_1);
+
+        // Member 2 speaks, then drops while member 1 keeps talking
+        let events = [
+            message(2, 2),
+            ServerEvent::ConnectionClosed { session_id: 2, reason: "network lost".into() },
+            message(1, 1),
+            message(1, 1),
+        ];
+        let actions = server.driver_mut().process_events(events).expect("offline batch");
+        verify_room_membership(&server, ROOM_1, 1, "after member 2 dropped");
+
+        // Oracle: each missed frame raises a push notice for member 2
+        let notices: Vec<OfflineNotice> = actions
+            .iter()
+            .filter_map(|action| match action {
+                ServerAction::NotifyOffline(notice) => Some(*notice),
+                _ => None,
+            })
+            .collect();
+        let notice =
+            |log_index| OfflineNotice { room_id: ROOM_1, member_id: 2, sender_id: 1, log_index };
+        assert_eq!(notices, vec![notice(1), notice(2)]);
+
+        // Member 2 returns on a new session
+        let events =
+            [ServerEvent::ConnectionAccepted { session_id: 3 }, authenticated(3), message(3, 2)];
+        let actions = server.driver_mut().process_events(events).expect("return batch");
+
+        // Oracle: missed frames arrive in log order, before the member's own
+        // frame is broadcast
+        let delivered: Vec<u64> = actions
+            .iter()
+            .take_while(|action| !matches!(action, ServerAction::BroadcastToRoom { .. }))
+            .filter_map(|action| match action {
+                ServerAction::SendToSession { session_id: 3, frame } => {
+                    Some(frame.header.log_index())
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(delivered, vec![1, 2]);
+        verify_room_membership(&server, ROOM_1, 2, "after member 2 returned");
+
+        Ok(())
+    });
+
+    sim.run().unwrap();
+}
@@ -9,7 +9,9 @@
 use bytes::Bytes;
 use lockframe_core::mls::MlsGroupState;
 use lockframe_proto::{Frame, FrameHeader, Opcode};
-use lockframe_server::{MemoryStorage, Storage, StorageError};
+use lockframe_server::{
+    MemoryStorage, SegmentedStorage, Storage, StorageError, storage::SegmentConfig,
+};
 
 // Helper to create test frames
 fn create_frame(room_id: u128, log_index: u64, sender_id: u64, epoch: u64) -> Frame {
@@ -219,3 +221,41 @@ fn test_storage_clone_shares_state() {
     assert_eq!(storage1.total_frame_count(), 2);
     assert_eq!(storage2.total_frame_count(), 2);
 }
+
+#[test]
+fn test_segmented_storage_invariants() {
+    let storage =
+        SegmentedStorage::with_config(SegmentConfig { max_frames: 7, max_span_millis: 60_000 });
+    let rooms = [100, 200];
+
+    // Interleave rooms so both span many segments
+    for i in 0..50 {
+        for &room_id in &rooms {
+            let frame = create_frame(room_id, i, 100, 0);
+            storage.store_frame(room_id, i, &frame).expect("store failed");
+        }
+    }
+
+    for &room_id in &rooms {
+        verify_storage_invariants(&storage, room_id, 50);
+        assert_eq!(storage.manifest(room_id).len(), 8);
+
+        // Pages crossing segment boundaries
+        for from in (0..50).step_by(5) {
+            let page = storage.load_frames(room_id, from, 5).expect("load failed");
+            assert_eq!(page.first().map(|f| f.header.log_index()), Some(from));
+            assert_eq!(page.len(), 5);
+        }
+    }
+
+    // Archiving one room leaves the other untouched
+    let archived = storage.archive_before(100, 50);
+    assert_eq!(archived.len(), 7);
+    assert!(matches!(storage.load_frames(100, 0, 10), Err(StorageError::Archived { .. })));
+    verify_storage_invariants(&storage, 200, 50);
+
+    for segment in archived {
+        storage.restore(segment).expect("restore failed");
+    }
+    verify_storage_invariants(&storage, 100, 50);
+}
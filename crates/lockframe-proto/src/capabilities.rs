@@ -0,0 +1,125 @@
+//! Session capabilities for the Lockframe protocol.
+//!
+//! Clients advertise capabilities by name in `Hello`; the server replies in
+//! `HelloReply` with the subset it also supports. Only that negotiated subset
+//! may be used for the rest of the session. Frames that depend on a capability
+//! the session did not negotiate are rejected rather than forwarded to peers
+//! that may not understand them.
+//!
+//! Names are free-form strings on the wire so peers can advertise features
+//! this build doesn't know about. Unknown names are ignored when parsing.
+
+use bitflags::bitflags;
+
+use crate::{FrameFlags, FrameHeader, Opcode};
+
+bitflags! {
+    /// Set of session capabilities
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Capabilities: u32 {
+        /// Payloads may be zstd compressed (`FrameFlags::COMPRESSED`)
+        const COMPRESSION = 0b0000_0001;
+
+        /// Several frames may be coalesced into one batch
+        const BATCHING = 0b0000_0010;
+
+        /// Ephemeral frames may travel over unreliable datagrams
+        const DATAGRAMS = 0b0000_0100;
+
+        /// Delivery receipts (`Opcode::AppReceipt`)
+        const RECEIPTS = 0b0000_1000;
+    }
+}
+
+impl Capabilities {
+    /// Wire names for each capability.
+    const NAMES: [(Self, &'static str); 4] = [
+        (Self::COMPRESSION, "compression"),
+        (Self::BATCHING, "batching"),
+        (Self::DATAGRAMS, "datagrams"),
+        (Self::RECEIPTS, "receipts"),
+    ];
+
+    /// Parse capability names as sent in `Hello`/`HelloReply`.
+    ///
+    /// Unknown names are ignored so newer peers can advertise features older
+    /// builds don't implement.
+    pub fn from_names<S: AsRef<str>>(names: impl IntoIterator<Item = S>) -> Self {
+        names
+            .into_iter()
+            .filter_map(|name| {
+                Self::NAMES
+                    .iter()
+                    .find(|(_, known)| *known == name.as_ref())
+                    .map(|(capability, _)| *capability)
+            })
+            .collect()
+    }
+
+    /// Wire names for the capabilities in this set, in a stable order.
+    #[must_use]
+    pub fn to_names(self) -> Vec<String> {
+        Self::NAMES
+            .iter()
+            .filter(|(capability, _)| self.contains(*capability))
+            .map(|(_, name)| (*name).to_string())
+            .collect()
+    }
+
+    /// Capabilities a session must have negotiated to send this frame.
+    ///
+    /// Empty for frames every session may send.
+    #[must_use]
+    pub fn required_for(header: &FrameHeader) -> Self {
+        let mut required = Self::empty();
+
+        if header.opcode_enum() == Some(Opcode::AppReceipt) {
+            required |= Self::RECEIPTS;
+        }
+        if header.flags().contains(FrameFlags::COMPRESSED) {
+            required |= Self::COMPRESSION;
+        }
+
+        required
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        let caps = Capabilities::RECEIPTS | Capabilities::COMPRESSION;
+        assert_eq!(caps.to_names(), vec!["compression", "receipts"]);
+        assert_eq!(Capabilities::from_names(caps.to_names()), caps);
+    }
+
+    #[test]
+    fn unknown_names_ignored() {
+        let caps = Capabilities::from_names(["mls", "receipts", "e2ee", "datagrams"]);
+        assert_eq!(caps, Capabilities::RECEIPTS | Capabilities::DATAGRAMS);
+    }
+
+    #[test]
+    fn required_for_gated_frames() {
+        assert!(Capabilities::required_for(&FrameHeader::new(Opcode::AppMessage)).is_empty());
+        assert_eq!(
+            Capabilities::required_for(&FrameHeader::new(Opcode::AppReceipt)),
+            Capabilities::RECEIPTS
+        );
+
+        let mut header = FrameHeader::new(Opcode::AppReceipt);
+        header.set_flags(FrameFlags::COMPRESSED);
+        assert_eq!(
+            Capabilities::required_for(&header),
+            Capabilities::RECEIPTS | Capabilities::COMPRESSION
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! zstd payload compression.
+//!
+//! Compression is negotiated per session with
+//! [`Capabilities::COMPRESSION`](crate::Capabilities::COMPRESSION), and a
+//! compressed payload is marked with
+//! [`FrameFlags::COMPRESSED`](crate::FrameFlags::COMPRESSED). Small payloads
+//! gain nothing from it, so senders only compress above
+//! [`COMPRESSION_THRESHOLD`], and only keep the result if it is smaller.
+//!
+//! # Security
+//!
+//! A few kilobytes of zstd can claim gigabytes of output. Decompression reads
+//! at most `max_size` bytes of output and caps the window the decoder may
+//! allocate at [`MAX_WINDOW_LOG`], so a hostile payload costs no more memory
+//! than an uncompressed one of the largest allowed size.
+
+use std::io::Read;
+
+use crate::{ProtocolError, Result};
+
+/// Smallest payload worth compressing, in bytes.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// zstd level senders compress at.
+pub const COMPRESSION_LEVEL: i32 = 3;
+
+/// Largest decoder window, as a power of two: 16 MiB, the payload limit.
+pub const MAX_WINDOW_LOG: u32 = 24;
+
+/// Compress `data` at [`COMPRESSION_LEVEL`].
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    zstd::bulk::compress(data, COMPRESSION_LEVEL)
+        .map_err(|e| ProtocolError::Compression(e.to_string()))
+}
+
+/// Decompress `data`, refusing output larger than `max_size` bytes.
+///
+/// # Errors
+///
+/// - `PayloadTooLarge`: the output exceeds `max_size`
+/// - `Compression`: `data` is not valid zstd or needs a larger window
+pub fn decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut decoder = zstd::stream::read::Decoder::with_buffer(data)
+        .map_err(|e| ProtocolError::Compression(e.to_string()))?;
+    decoder
+        .window_log_max(MAX_WINDOW_LOG)
+        .map_err(|e| ProtocolError::Compression(e.to_string()))?;
+
+    // One byte past the limit tells an exact fit from an overflow
+    let limit = u64::try_from(max_size).unwrap_or(u64::MAX).saturating_add(1);
+    let mut output = Vec::new();
+    decoder
+        .take(limit)
+        .read_to_end(&mut output)
+        .map_err(|e| ProtocolError::Compression(e.to_string()))?;
+
+    if output.len() > max_size {
+        return Err(ProtocolError::PayloadTooLarge { size: output.len(), max: max_size });
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_within_limit() {
+        let data = b"lockframe ".repeat(1000);
+        let compressed = compress(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+
+        assert!(matches!(
+            decompress(&compressed, data.len() - 1),
+            Err(ProtocolError::PayloadTooLarge { .. })
+        ));
+        assert!(matches!(decompress(b"not zstd", 1024), Err(ProtocolError::Compression(_))));
+    }
+}
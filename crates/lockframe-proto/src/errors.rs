@@ -69,6 +69,10 @@ pub enum ProtocolError {
     /// Invalid flag combination
     #[error("invalid flags: {0:#04x}")]
     InvalidFlags(u8),
+
+    /// Failed to compress or decompress a payload
+    #[error("compression failed: {0}")]
+    Compression(String),
 }
 
 /// Convenient Result type alias for protocol operations
@@ -32,8 +32,9 @@ bitflags! {
         /// Can be redacted by moderators
         const REDACTABLE = 0b0100_0000;
 
-        /// Reserved for future use
-        const RESERVED = 0b1000_0000;
+        /// Payload follows on a stream of its own, see
+        /// [`Frame::STREAM_THRESHOLD`](crate::Frame::STREAM_THRESHOLD)
+        const STREAMED = 0b1000_0000;
     }
 }
 
@@ -70,6 +71,18 @@ impl Default for FrameFlags {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for FrameFlags {
+    /// Any bit pattern, including bits no flag is defined for.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::from_bits_retain(u.arbitrary()?))
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        u8::size_hint(depth)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
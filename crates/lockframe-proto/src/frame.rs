@@ -10,7 +10,7 @@
 use bytes::{BufMut, Bytes};
 
 use crate::{
-    FrameHeader,
+    FrameFlags, FrameHeader, FrameTiming,
     errors::{ProtocolError, Result},
 };
 
@@ -51,6 +51,18 @@ pub struct Frame {
 }
 
 impl Frame {
+    /// Payload size above which senders stream the payload.
+    ///
+    /// A streamed frame has the [`FrameFlags::STREAMED`] flag and a request
+    /// ID unique among the sender's streamed frames. Its header goes on the
+    /// control stream as usual, but its payload goes on a unidirectional
+    /// stream of its own, after the request ID, so a multi-megabyte payload
+    /// doesn't hold up the frames behind it. See [`Frame::encode_streamed`].
+    pub const STREAM_THRESHOLD: usize = 256 * 1024;
+
+    /// Bytes before the payload on a payload stream: the frame's request ID.
+    pub const STREAM_PREFIX_SIZE: usize = 4;
+
     /// Create a new frame with automatic payload_size calculation
     ///
     /// The header's `payload_size` field is automatically set to match
@@ -178,6 +190,97 @@ impl Frame {
 
         Ok(Self { header: *header, payload })
     }
+
+    /// Whether the frame's payload travels on a stream of its own.
+    #[must_use]
+    pub fn is_streamed(&self) -> bool {
+        self.header.flags().contains(FrameFlags::STREAMED)
+    }
+
+    /// Encode a streamed frame for its two streams.
+    ///
+    /// Returns the header, for the control stream, and the payload stream's
+    /// bytes: the request ID followed by the payload.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Frame::encode`].
+    pub fn encode_streamed(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mut encoded = Vec::with_capacity(FrameHeader::SIZE.saturating_add(self.payload.len()));
+        self.encode(&mut encoded)?;
+        let payload = encoded.split_off(FrameHeader::SIZE);
+
+        let mut stream = Vec::with_capacity(Self::STREAM_PREFIX_SIZE.saturating_add(payload.len()));
+        stream.put_u32(self.header.request_id());
+        stream.extend_from_slice(&payload);
+        Ok((encoded, stream))
+    }
+
+    /// Split the bytes of a payload stream into the request ID of the frame
+    /// they belong to and its payload.
+    ///
+    /// # Errors
+    ///
+    /// - `ProtocolError::FrameTooShort` if the request ID is missing
+    pub fn decode_stream_payload(bytes: &[u8]) -> Result<(u32, &[u8])> {
+        let (Some(Ok(prefix)), Some(payload)) = (
+            bytes.get(..Self::STREAM_PREFIX_SIZE).map(<[u8; 4]>::try_from),
+            bytes.get(Self::STREAM_PREFIX_SIZE..),
+        ) else {
+            return Err(ProtocolError::FrameTooShort {
+                expected: Self::STREAM_PREFIX_SIZE,
+                actual: bytes.len(),
+            });
+        };
+        Ok((u32::from_be_bytes(prefix), payload))
+    }
+
+    /// Encode the frame followed by an optional server timing trailer.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Frame::encode`].
+    pub fn encode_with_timing(
+        &self,
+        timing: Option<&FrameTiming>,
+        dst: &mut impl BufMut,
+    ) -> Result<()> {
+        self.encode(dst)?;
+        if let Some(timing) = timing {
+            timing.encode(dst);
+        }
+        Ok(())
+    }
+
+    /// Decode a frame and the server timing trailer after it, if any.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Frame::decode`]. A missing or malformed trailer is not an
+    /// error.
+    pub fn decode_with_timing(bytes: &[u8]) -> Result<(Self, Option<FrameTiming>)> {
+        let frame = Self::decode(bytes)?;
+        let end = FrameHeader::SIZE.saturating_add(frame.payload.len());
+        let timing = bytes.get(end..).and_then(FrameTiming::decode);
+        Ok((frame, timing))
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Frame {
+    /// A frame whose header matches its payload length. The payload is
+    /// usually a well-formed [`Payload`](crate::Payload) for the header's
+    /// opcode, otherwise arbitrary bytes.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let header: FrameHeader = u.arbitrary()?;
+        if u.ratio(1u8, 4u8)? {
+            let payload: Vec<u8> = u.arbitrary()?;
+            return Ok(Self::new(header, payload));
+        }
+
+        let payload: crate::Payload = u.arbitrary()?;
+        payload.into_frame(header).map_err(|_| arbitrary::Error::IncorrectFormat)
+    }
 }
 
 #[cfg(test)]
@@ -233,6 +336,44 @@ mod tests {
         assert_eq!(frame.payload, parsed.payload);
     }
 
+    #[test]
+    fn timing_trailer_follows_frame() {
+        let frame = Frame::new(FrameHeader::new(Opcode::AppMessage), vec![1, 2, 3]);
+        let timing = FrameTiming { received_at_millis: 1_000, processing_micros: 250 };
+
+        let mut wire = Vec::new();
+        frame.encode_with_timing(Some(&timing), &mut wire).expect("should encode");
+
+        // Plain decoding ignores the trailer
+        assert_eq!(Frame::decode(&wire).expect("should decode"), frame);
+        let (parsed, parsed_timing) = Frame::decode_with_timing(&wire).expect("should decode");
+        assert_eq!(parsed, frame);
+        assert_eq!(parsed_timing, Some(timing));
+
+        wire.clear();
+        frame.encode(&mut wire).expect("should encode");
+        assert_eq!(Frame::decode_with_timing(&wire).expect("should decode").1, None);
+    }
+
+    #[test]
+    fn streamed_frame_splits_across_streams() {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_flags(FrameFlags::STREAMED);
+        header.set_request_id(7);
+        let frame = Frame::new(header, vec![1, 2, 3]);
+        assert!(frame.is_streamed());
+
+        let (control, stream) = frame.encode_streamed().expect("should encode");
+        assert_eq!(control.len(), FrameHeader::SIZE);
+        let (request_id, payload) = Frame::decode_stream_payload(&stream).expect("should decode");
+        assert_eq!((request_id, payload), (7, &[1, 2, 3][..]));
+
+        let mut wire = control;
+        wire.extend_from_slice(payload);
+        assert_eq!(Frame::decode(&wire).expect("should decode"), frame);
+        assert!(Frame::decode_stream_payload(&[0, 7]).is_err());
+    }
+
     #[test]
     fn reject_truncated_frame() {
         // Create header claiming 100 bytes of payload
@@ -77,9 +77,22 @@ impl FrameHeader {
     /// Magic number: "LOFR" in ASCII (0x4C4F4652)
     pub const MAGIC: u32 = 0x4C4F_4652;
 
-    /// Current protocol version
+    /// Newest protocol version this build speaks
+    ///
+    /// Peers agree on a version in the handshake: the `Hello` header carries
+    /// the oldest version the client speaks and its payload the newest, and
+    /// the server answers in the version it picked with
+    /// [`FrameHeader::negotiate_version`]. Every version from
+    /// [`FrameHeader::MIN_VERSION`] up to this one decodes:
+    ///
+    /// | Version | Changes                 |
+    /// |---------|-------------------------|
+    /// | 0x01    | Initial wire format     |
     pub const VERSION: u8 = 0x01;
 
+    /// Oldest protocol version this build still speaks
+    pub const MIN_VERSION: u8 = 0x01;
+
     /// Maximum payload size (16 MB)
     pub const MAX_PAYLOAD_SIZE: u32 = 16 * 1024 * 1024;
 
@@ -138,7 +151,7 @@ impl FrameHeader {
             return Err(ProtocolError::InvalidMagic);
         }
 
-        if header.version != Self::VERSION {
+        if !Self::is_supported_version(header.version) {
             return Err(ProtocolError::UnsupportedVersion(header.version));
         }
 
@@ -153,6 +166,20 @@ impl FrameHeader {
         Ok(header)
     }
 
+    /// Whether this build speaks protocol `version`.
+    #[must_use]
+    pub fn is_supported_version(version: u8) -> bool {
+        (Self::MIN_VERSION..=Self::VERSION).contains(&version)
+    }
+
+    /// Newest version both this build and a peer speaking `oldest..=newest`
+    /// speak, if any.
+    #[must_use]
+    pub fn negotiate_version(oldest: u8, newest: u8) -> Option<u8> {
+        let chosen = newest.min(Self::VERSION);
+        (chosen >= oldest.max(Self::MIN_VERSION)).then_some(chosen)
+    }
+
     /// Serialize header to bytes (zero-copy)
     #[must_use]
     #[allow(clippy::wrong_self_convention)] // Common serialization pattern
@@ -169,7 +196,7 @@ impl FrameHeader {
         u32::from_be_bytes(self.magic)
     }
 
-    /// Protocol version byte (currently 0x01).
+    /// Protocol version byte, see [`FrameHeader::VERSION`].
     #[must_use]
     pub fn version(&self) -> u8 {
         self.version
@@ -279,6 +306,11 @@ impl FrameHeader {
         data
     }
 
+    /// Set the protocol version, as negotiated for the session.
+    pub fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+
     /// Update room UUID.
     pub fn set_room_id(&mut self, room_id: u128) {
         self.room_id = room_id.to_be_bytes();
@@ -370,6 +402,25 @@ impl PartialEq for FrameHeader {
 
 impl Eq for FrameHeader {}
 
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for FrameHeader {
+    /// A header that parses: valid magic, version and opcode, and a payload
+    /// size within the limit. Every other field is arbitrary.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut header = Self::new(u.arbitrary()?);
+        header.set_flags(u.arbitrary()?);
+        header.request_id = u.arbitrary()?;
+        header.payload_size = u.int_in_range(0..=Self::MAX_PAYLOAD_SIZE)?.to_be_bytes();
+        header.room_id = u.arbitrary()?;
+        header.sender_id = u.arbitrary()?;
+        header.context_id = u.arbitrary()?;
+        header.hlc_timestamp = u.arbitrary()?;
+        header.epoch = u.arbitrary()?;
+        header.signature = u.arbitrary()?;
+        Ok(header)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use proptest::prelude::*;
@@ -484,6 +535,16 @@ mod tests {
         assert_eq!(result, Err(ProtocolError::UnsupportedVersion(0xFF)));
     }
 
+    #[test]
+    fn negotiates_newest_common_version() {
+        let (min, max) = (FrameHeader::MIN_VERSION, FrameHeader::VERSION);
+        assert_eq!(FrameHeader::negotiate_version(min, max), Some(max));
+        assert_eq!(FrameHeader::negotiate_version(min, 0xFF), Some(max));
+        assert_eq!(FrameHeader::negotiate_version(max + 1, 0xFF), None);
+        assert_eq!(FrameHeader::negotiate_version(0, min - 1), None);
+        assert_eq!(FrameHeader::negotiate_version(0xFF, 0), None);
+    }
+
     #[test]
     fn reject_oversized_payload() {
         let mut buf = [0u8; 128];
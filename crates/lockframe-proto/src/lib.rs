@@ -17,16 +17,23 @@
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
 
+pub mod capabilities;
+pub mod compression;
 pub mod errors;
 pub mod flags;
 pub mod frame;
 pub mod header;
 pub mod opcodes;
 pub mod payloads;
+pub mod priority;
+pub mod timing;
 
+pub use capabilities::Capabilities;
 pub use errors::{ProtocolError, Result};
 pub use flags::FrameFlags;
 pub use frame::Frame;
 pub use header::FrameHeader;
 pub use opcodes::Opcode;
 pub use payloads::Payload;
+pub use priority::Priority;
+pub use timing::FrameTiming;
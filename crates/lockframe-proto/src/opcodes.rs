@@ -29,6 +29,7 @@ use serde_repr::{Deserialize_repr, Serialize_repr};
 /// "default" behavior, preventing accidental mishandling of malicious or
 /// corrupted frames.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize_repr, Deserialize_repr)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(u16)]
 pub enum Opcode {
     // Session Management (0x0000-0x00FF)
@@ -46,6 +47,32 @@ pub enum Opcode {
     SyncRequest = 0x0006,
     /// Sync response with frames (server → client)
     SyncResponse = 0x0007,
+    /// Timestamped keepalive for RTT sampling
+    Heartbeat = 0x0008,
+    /// Heartbeat echo carrying the original timestamp
+    HeartbeatAck = 0x0009,
+    /// Server clock refresh (server → client)
+    TimeSync = 0x000A,
+    /// Signed log checkpoint (server → room)
+    Checkpoint = 0x000B,
+    /// Request a Merkle proof over a room's log (client → server)
+    ProofRequest = 0x000C,
+    /// Merkle proof over a room's log (server → client)
+    ProofResponse = 0x000D,
+    /// Revoke the account's other sessions (client → server)
+    RevokeSessions = 0x000E,
+    /// Sessions revoked, members to remove (server → client)
+    SessionsRevoked = 0x000F,
+    /// Room migrated to another server (server → client)
+    RoomMoved = 0x0010,
+    /// Signed answer to a `HelloReply` challenge (client → server)
+    ChallengeResponse = 0x0011,
+    /// Server going into maintenance, reconnect later (server → client)
+    Maintenance = 0x0012,
+    /// List the rooms in the server's directory (client → server)
+    ListRooms = 0x0013,
+    /// Page of the server's room directory (server → client)
+    ListRoomsReply = 0x0014,
     /// Error frame
     Error = 0x00FF,
 
@@ -66,6 +93,10 @@ pub enum Opcode {
     ReInit = 0x1006,
     /// Server-generated external commit
     ExternalCommit = 0x1007,
+    /// Claim a member's uploaded key package to add them (client → server)
+    ClaimKeyPackage = 0x1008,
+    /// Key package handed out for a claim (server → client)
+    KeyPackageClaimed = 0x1009,
 
     // Application Messages (0x2000-0x2FFF)
     /// Encrypted application message
@@ -82,6 +113,8 @@ pub enum Opcode {
     Typing = 0x2005,
     /// Presence/online status
     Presence = 0x2006,
+    /// Read position in a room's log
+    ReadReceipt = 0x2007,
 
     // Moderation (0x3000-0x3FFF)
     /// Remove message content
@@ -120,6 +153,16 @@ pub enum Opcode {
     CASDelete = 0x5002,
     /// Storage proof/attestation
     CASProof = 0x5003,
+    /// Start or resume an attachment upload
+    AttachmentInit = 0x5004,
+    /// Chunk of an attachment upload
+    AttachmentChunk = 0x5005,
+    /// Attachment upload finished
+    AttachmentComplete = 0x5006,
+    /// Progress of an attachment upload (server → client)
+    AttachmentStatus = 0x5007,
+    /// Download chunks of a stored attachment
+    AttachmentFetch = 0x5008,
 }
 
 impl Opcode {
@@ -129,6 +172,23 @@ impl Opcode {
         self as u16
     }
 
+    /// Whether this is an MLS handshake message the group state depends on.
+    ///
+    /// These are the frames a client must process, in log order, to move
+    /// from one epoch to the next; application traffic is not among them.
+    #[must_use]
+    pub const fn is_handshake(self) -> bool {
+        matches!(
+            self,
+            Self::Proposal
+                | Self::Commit
+                | Self::Welcome
+                | Self::PSKProposal
+                | Self::ReInit
+                | Self::ExternalCommit
+        )
+    }
+
     /// Convert from raw u16 value
     ///
     /// Returns `None` if the value doesn't correspond to a known opcode.
@@ -152,6 +212,19 @@ impl Opcode {
             0x0005 => Some(Self::Pong),
             0x0006 => Some(Self::SyncRequest),
             0x0007 => Some(Self::SyncResponse),
+            0x0008 => Some(Self::Heartbeat),
+            0x0009 => Some(Self::HeartbeatAck),
+            0x000A => Some(Self::TimeSync),
+            0x000B => Some(Self::Checkpoint),
+            0x000C => Some(Self::ProofRequest),
+            0x000D => Some(Self::ProofResponse),
+            0x000E => Some(Self::RevokeSessions),
+            0x000F => Some(Self::SessionsRevoked),
+            0x0010 => Some(Self::RoomMoved),
+            0x0011 => Some(Self::ChallengeResponse),
+            0x0012 => Some(Self::Maintenance),
+            0x0013 => Some(Self::ListRooms),
+            0x0014 => Some(Self::ListRoomsReply),
             0x00FF => Some(Self::Error),
 
             0x1000 => Some(Self::KeyPackage),
@@ -162,6 +235,8 @@ impl Opcode {
             0x1005 => Some(Self::PSKProposal),
             0x1006 => Some(Self::ReInit),
             0x1007 => Some(Self::ExternalCommit),
+            0x1008 => Some(Self::ClaimKeyPackage),
+            0x1009 => Some(Self::KeyPackageClaimed),
 
             0x2000 => Some(Self::AppMessage),
             0x2001 => Some(Self::AppReceipt),
@@ -170,6 +245,7 @@ impl Opcode {
             0x2004 => Some(Self::AppDelete),
             0x2005 => Some(Self::Typing),
             0x2006 => Some(Self::Presence),
+            0x2007 => Some(Self::ReadReceipt),
 
             0x3000 => Some(Self::Redact),
             0x3001 => Some(Self::Ban),
@@ -189,6 +265,11 @@ impl Opcode {
             0x5001 => Some(Self::CASGet),
             0x5002 => Some(Self::CASDelete),
             0x5003 => Some(Self::CASProof),
+            0x5004 => Some(Self::AttachmentInit),
+            0x5005 => Some(Self::AttachmentChunk),
+            0x5006 => Some(Self::AttachmentComplete),
+            0x5007 => Some(Self::AttachmentStatus),
+            0x5008 => Some(Self::AttachmentFetch),
 
             _ => None,
         }
@@ -222,4 +303,14 @@ mod tests {
         assert_eq!(Opcode::from_u16(0x9999), None);
         assert_eq!(Opcode::from_u16(0x0000), None);
     }
+
+    #[test]
+    fn handshake_opcodes() {
+        assert!(Opcode::Commit.is_handshake());
+        assert!(Opcode::Welcome.is_handshake());
+        assert!(Opcode::ExternalCommit.is_handshake());
+        assert!(!Opcode::AppMessage.is_handshake());
+        assert!(!Opcode::KeyPackage.is_handshake());
+        assert!(!Opcode::SyncResponse.is_handshake());
+    }
 }
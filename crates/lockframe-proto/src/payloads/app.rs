@@ -1,14 +1,18 @@
 //! Application message payload types.
 //!
 //! These payloads handle user-visible messages: encrypted content, delivery
-//! receipts, and reactions.
+//! receipts, read receipts, reactions, and typing indicators.
 
+use ciborium::Value;
 use serde::{Deserialize, Serialize};
 
+use crate::{ProtocolError, Result};
+
 /// Encrypted application message
 ///
 /// Primary message type for user-to-user communication. Messages are encrypted
-/// with XChaCha20-Poly1305 using sender keys derived from the MLS epoch secret.
+/// with the room's AEAD suite (XChaCha20-Poly1305 or AES-256-GCM) using sender
+/// keys derived from the MLS epoch secret.
 /// The nonce is deterministically derived from (epoch, sender_index,
 /// generation) plus a random suffix to prevent reuse.
 ///
@@ -16,7 +20,12 @@ use serde::{Deserialize, Serialize};
 /// correct decryption key from their sender key ratchet state. These duplicate
 /// some header fields but are included in the CBOR payload for authenticated
 /// binding.
+///
+/// On the wire the message is a versioned, canonical CBOR array written by
+/// [`EncryptedMessage::encode`], so equal messages always encode to equal
+/// bytes.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct EncryptedMessage {
     /// The MLS epoch this message was encrypted under.
     /// Must match the epoch in the frame header.
@@ -30,11 +39,22 @@ pub struct EncryptedMessage {
     /// Receivers advance their ratchet to this generation before decrypting.
     pub generation: u32,
 
-    /// Nonce for XChaCha20 (24 bytes).
+    /// Padding scheme applied to the plaintext before encryption (0 for
+    /// none). Authenticated with the ciphertext, so receivers know how to
+    /// strip the padding.
+    #[serde(default)]
+    pub padding: u8,
+
+    /// AEAD suite the message is encrypted with (0 for XChaCha20-Poly1305,
+    /// 1 for AES-256-GCM). Fixed per room by its MLS ciphersuite.
+    #[serde(default)]
+    pub cipher_suite: u8,
+
+    /// Nonce (24 bytes), of which AES-256-GCM uses the last 12.
     /// Structure: [epoch:8][sender_index:4][generation:4][random:8]
     pub nonce: [u8; 24],
 
-    /// Ciphertext including 16-byte Poly1305 authentication tag.
+    /// Ciphertext including 16-byte authentication tag.
     pub ciphertext: Vec<u8>,
 
     /// Optional: Push-Carried Ephemeral Keys (PCEK)
@@ -45,6 +65,172 @@ pub struct EncryptedMessage {
     pub push_keys: Option<Vec<PushKey>>,
 }
 
+impl EncryptedMessage {
+    /// Size of the authentication tag at the end of `ciphertext`.
+    pub const TAG_SIZE: usize = 16;
+
+    /// Version of the wire encoding, leading every encoded message.
+    ///
+    /// | Version | Change                              |
+    /// |---------|-------------------------------------|
+    /// | 1       | Initial encoding                    |
+    /// | 2       | Adds the plaintext `padding` scheme |
+    /// | 3       | Adds the AEAD `cipher_suite`        |
+    pub const ENCODING_VERSION: u8 = 3;
+
+    /// Encode as canonical CBOR.
+    ///
+    /// The message is a definite-length array led by
+    /// [`ENCODING_VERSION`](Self::ENCODING_VERSION):
+    /// `[version, epoch, sender_index, generation, padding, cipher_suite,
+    /// nonce, ciphertext]`, with `push_keys` appended as an array of
+    /// `[recipient_id, encrypted_key]` when present. Byte fields are byte
+    /// strings and integers take their shortest form.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        self.encode_version(Self::ENCODING_VERSION)
+    }
+
+    /// Encode as canonical CBOR in encoding `version`, leaving out the
+    /// fields it predates.
+    fn encode_version(&self, version: u8) -> Result<Vec<u8>> {
+        let mut fields = vec![
+            Value::from(version),
+            Value::from(self.epoch),
+            Value::from(self.sender_index),
+            Value::from(self.generation),
+        ];
+        if version >= 2 {
+            fields.push(Value::from(self.padding));
+        }
+        if version >= 3 {
+            fields.push(Value::from(self.cipher_suite));
+        }
+        fields.push(Value::Bytes(self.nonce.to_vec()));
+        fields.push(Value::Bytes(self.ciphertext.clone()));
+        if let Some(push_keys) = &self.push_keys {
+            let keys = push_keys.iter().map(|key| {
+                Value::Array(vec![
+                    Value::from(key.recipient_id),
+                    Value::Bytes(key.encrypted_key.clone()),
+                ])
+            });
+            fields.push(Value::Array(keys.collect()));
+        }
+
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&Value::Array(fields), &mut bytes)
+            .map_err(|e| ProtocolError::CborEncode(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    /// Decode a message written by [`encode`](Self::encode).
+    ///
+    /// Messages in an earlier encoding version decode too, with the fields
+    /// their version predates at their old meaning: version 1 messages are
+    /// unpadded (`padding` 0), and version 1 and 2 messages are
+    /// XChaCha20-Poly1305 (`cipher_suite` 0).
+    ///
+    /// Only the canonical encoding is accepted: an unknown version, missing
+    /// or extra fields, trailing bytes and non-minimal or indefinite-length
+    /// items are all rejected, so a message has exactly one encoding.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let value: Value = ciborium::de::from_reader(bytes)
+            .map_err(|e| ProtocolError::CborDecode(e.to_string()))?;
+        let Value::Array(fields) = value else {
+            return Err(malformed("expected an array"));
+        };
+
+        let mut fields = fields.into_iter();
+        let version: u8 = integer(fields.next())?;
+        if !(1..=Self::ENCODING_VERSION).contains(&version) {
+            return Err(malformed(&format!("unknown encoding version {version}")));
+        }
+
+        let epoch = integer(fields.next())?;
+        let sender_index = integer(fields.next())?;
+        let generation = integer(fields.next())?;
+        let padding = if version >= 2 { integer(fields.next())? } else { 0 };
+        let cipher_suite = if version >= 3 { integer(fields.next())? } else { 0 };
+        let nonce = byte_string(fields.next())?
+            .try_into()
+            .map_err(|_| malformed("nonce must be 24 bytes"))?;
+        let ciphertext = byte_string(fields.next())?;
+        let push_keys = fields.next().map(push_keys).transpose()?;
+        if fields.next().is_some() {
+            return Err(malformed("too many fields"));
+        }
+
+        let message = Self {
+            epoch,
+            sender_index,
+            generation,
+            padding,
+            cipher_suite,
+            nonce,
+            ciphertext,
+            push_keys,
+        };
+        if message.encode_version(version)? != bytes {
+            return Err(malformed("not canonically encoded"));
+        }
+        Ok(message)
+    }
+
+    /// Whether the nonce prefix encodes this message's epoch, sender_index,
+    /// and generation.
+    ///
+    /// Senders derive the first 16 nonce bytes from these fields, so a
+    /// mismatch means the envelope was not produced by the sender key
+    /// encryption path.
+    #[must_use]
+    pub fn nonce_matches_metadata(&self) -> bool {
+        self.nonce[0..8] == self.epoch.to_be_bytes()
+            && self.nonce[8..12] == self.sender_index.to_be_bytes()
+            && self.nonce[12..16] == self.generation.to_be_bytes()
+    }
+}
+
+/// Decode error for a malformed [`EncryptedMessage`].
+fn malformed(reason: &str) -> ProtocolError {
+    ProtocolError::CborDecode(format!("malformed encrypted message: {reason}"))
+}
+
+/// Integer field of an encoded [`EncryptedMessage`].
+fn integer<T: TryFrom<ciborium::value::Integer>>(field: Option<Value>) -> Result<T> {
+    field
+        .and_then(|field| field.as_integer())
+        .and_then(|integer| T::try_from(integer).ok())
+        .ok_or_else(|| malformed("expected an integer in range"))
+}
+
+/// Byte string field of an encoded [`EncryptedMessage`].
+fn byte_string(field: Option<Value>) -> Result<Vec<u8>> {
+    match field {
+        Some(Value::Bytes(bytes)) => Ok(bytes),
+        _ => Err(malformed("expected a byte string")),
+    }
+}
+
+/// `push_keys` field of an encoded [`EncryptedMessage`].
+fn push_keys(field: Value) -> Result<Vec<PushKey>> {
+    let Value::Array(keys) = field else {
+        return Err(malformed("expected an array of push keys"));
+    };
+    keys.into_iter()
+        .map(|key| {
+            let Value::Array(key) = key else {
+                return Err(malformed("expected a push key"));
+            };
+            let [recipient_id, encrypted_key] = <[Value; 2]>::try_from(key)
+                .map_err(|_| malformed("push key must have two fields"))?;
+            Ok(PushKey {
+                recipient_id: integer(Some(recipient_id))?,
+                encrypted_key: byte_string(Some(encrypted_key))?,
+            })
+        })
+        .collect()
+}
+
 /// Push-Carried Ephemeral Key for a specific recipient
 ///
 /// For high-priority messages (DMs, mentions), the sender can include encrypted
@@ -60,6 +246,7 @@ pub struct EncryptedMessage {
 /// - Selective Recipients: Only critical recipients receive push keys. Regular
 ///   group messages rely on the MLS ratchet tree instead.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct PushKey {
     /// Recipient device ID
     pub recipient_id: u64,
@@ -70,6 +257,7 @@ pub struct PushKey {
 
 /// Delivery receipt
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Receipt {
     /// Log index of the message being acknowledged
     pub message_log_index: u64,
@@ -83,6 +271,7 @@ pub struct Receipt {
 
 /// Receipt type
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum ReceiptType {
     /// Message delivered to device
     Delivered,
@@ -92,6 +281,7 @@ pub enum ReceiptType {
 
 /// Message reaction (emoji, etc.)
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Reaction {
     /// Log index of the message being reacted to
     pub message_log_index: u64,
@@ -103,6 +293,34 @@ pub struct Reaction {
     pub add: bool,
 }
 
+/// Typing indicator
+///
+/// Ephemeral: the server relays it to the room's connected members as it
+/// arrives, without sequencing, storing or queueing it for offline members,
+/// so it has no log index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Typing {
+    /// True while the member is typing, false once they stop
+    pub typing: bool,
+}
+
+/// Read receipt
+///
+/// Marks every message up to and including `up_to_log_index` as read by the
+/// sender. Unlike [`Receipt`], it is sent in the clear and sequenced in the
+/// room's log, so the server can keep each member's read position and other
+/// members learn it through sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ReadReceipt {
+    /// Room the receipt is for, matching the frame header
+    pub room_id: u128,
+
+    /// Log index of the last message read
+    pub up_to_log_index: u64,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,6 +331,8 @@ mod tests {
             epoch: 1,
             sender_index: 42,
             generation: 0,
+            padding: 0,
+            cipher_suite: 0,
             nonce: [0; 24],
             ciphertext: vec![1, 2, 3, 4],
             push_keys: None,
@@ -128,6 +348,8 @@ mod tests {
             epoch: 42,
             sender_index: 7,
             generation: 100,
+            padding: 0,
+            cipher_suite: 0,
             nonce: [0xAB; 24],
             ciphertext: vec![1, 2, 3, 4, 5, 6, 7, 8],
             push_keys: None,
@@ -143,6 +365,111 @@ mod tests {
         assert_eq!(original, decoded);
     }
 
+    #[test]
+    fn canonical_encoding_round_trip() {
+        let mut msg = EncryptedMessage {
+            epoch: 42,
+            sender_index: 7,
+            generation: 100,
+            padding: 0,
+            cipher_suite: 0,
+            nonce: [0xAB; 24],
+            ciphertext: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            push_keys: None,
+        };
+
+        let encoded = msg.encode().unwrap();
+        assert_eq!(encoded[1], EncryptedMessage::ENCODING_VERSION);
+        assert_eq!(EncryptedMessage::decode(&encoded).unwrap(), msg);
+
+        msg.padding = 1;
+        msg.cipher_suite = 1;
+        msg.push_keys = Some(vec![PushKey { recipient_id: 9, encrypted_key: vec![5; 80] }]);
+        let encoded = msg.encode().unwrap();
+        assert_eq!(EncryptedMessage::decode(&encoded).unwrap(), msg);
+
+        // Trailing bytes, other versions and the serde map form are refused
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(EncryptedMessage::decode(&trailing).is_err());
+
+        let mut future = encoded;
+        future[1] = EncryptedMessage::ENCODING_VERSION + 1;
+        assert!(EncryptedMessage::decode(&future).is_err());
+
+        let mut serde_form = Vec::new();
+        ciborium::ser::into_writer(&msg, &mut serde_form).unwrap();
+        assert!(EncryptedMessage::decode(&serde_form).is_err());
+    }
+
+    #[test]
+    fn version_2_messages_decode_as_xchacha() {
+        // [2, 5, 0, 0, 1, h'00'*24, h''], written before the suite was recorded
+        let mut bytes = vec![0x87, 0x02, 0x05, 0x00, 0x00, 0x01, 0x58, 24];
+        bytes.extend([0; 24]);
+        bytes.push(0x40);
+
+        let msg = EncryptedMessage::decode(&bytes).unwrap();
+        assert_eq!((msg.epoch, msg.padding, msg.cipher_suite), (5, 1, 0));
+
+        // Still held to its version's canonical form
+        bytes.push(0);
+        assert!(EncryptedMessage::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn version_1_messages_decode_unpadded() {
+        // [1, 5, 2, 3, h'00'*24, h'', [[9, h'']]], written before padding
+        let mut bytes = vec![0x87, 0x01, 0x05, 0x02, 0x03, 0x58, 24];
+        bytes.extend([0; 24]);
+        bytes.extend([0x40, 0x81, 0x82, 0x09, 0x40]);
+
+        let msg = EncryptedMessage::decode(&bytes).unwrap();
+        assert_eq!((msg.epoch, msg.sender_index, msg.generation), (5, 2, 3));
+        assert_eq!((msg.padding, msg.cipher_suite), (0, 0));
+        assert_eq!(msg.push_keys, Some(vec![PushKey { recipient_id: 9, encrypted_key: vec![] }]));
+
+        // Version 0 was never written
+        bytes[1] = 0x00;
+        assert!(EncryptedMessage::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn non_minimal_integers_are_refused() {
+        // [3, 0, 0, 0, 0, 0, h'00'*24, h''] with the epoch as a 1-byte integer
+        let mut bytes = vec![0x88, 0x03, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x58, 24];
+        bytes.extend([0; 24]);
+        bytes.push(0x40);
+        assert!(EncryptedMessage::decode(&bytes).is_err());
+
+        bytes.remove(2);
+        let msg = EncryptedMessage::decode(&bytes).unwrap();
+        assert_eq!(msg.epoch, 0);
+    }
+
+    #[test]
+    fn nonce_prefix_must_match_metadata() {
+        let mut msg = EncryptedMessage {
+            epoch: 3,
+            sender_index: 1,
+            generation: 9,
+            padding: 0,
+            cipher_suite: 0,
+            nonce: [0; 24],
+            ciphertext: vec![0; EncryptedMessage::TAG_SIZE],
+            push_keys: None,
+        };
+        assert!(!msg.nonce_matches_metadata());
+
+        msg.nonce[0..8].copy_from_slice(&3u64.to_be_bytes());
+        msg.nonce[8..12].copy_from_slice(&1u32.to_be_bytes());
+        msg.nonce[12..16].copy_from_slice(&9u32.to_be_bytes());
+        assert!(msg.nonce_matches_metadata());
+
+        msg.generation = 10;
+        assert!(!msg.nonce_matches_metadata());
+    }
+
     #[test]
     fn receipt_serde() {
         let receipt =
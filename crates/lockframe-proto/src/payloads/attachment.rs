@@ -0,0 +1,92 @@
+//! Attachment transfer payload types.
+//!
+//! Attachments are too large for one frame, so they are uploaded to the
+//! server a chunk at a time and kept as a blob keyed by the SHA-256 hash of
+//! its bytes. Clients encrypt attachments before chunking them, so the server
+//! only ever sees and hashes ciphertext; the key travels to the room's
+//! members in an ordinary encrypted message.
+//!
+//! An upload is resumable: sending [`AttachmentInit`] again for the same hash
+//! is answered with the chunk the server needs next, so a client that lost
+//! its connection continues where it stopped.
+//!
+//! Members download a stored attachment a window of chunks at a time with
+//! [`AttachmentFetch`]: the server answers with the chunks of the window as
+//! [`AttachmentChunk`] frames, then ends the window with the attachment's
+//! complete [`AttachmentStatus`], whose `next_chunk` is the number of chunks.
+
+use serde::{Deserialize, Serialize};
+
+/// Start or resume an upload (client → server)
+///
+/// Answered with an [`AttachmentStatus`] saying which chunk to send next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AttachmentInit {
+    /// SHA-256 hash of the whole attachment
+    pub content_hash: [u8; 32],
+
+    /// Size of the attachment in bytes
+    pub total_size: u64,
+
+    /// Size of every chunk but the last, which may be shorter
+    pub chunk_size: u32,
+}
+
+/// One chunk of an upload (client → server)
+///
+/// Chunks are sent in order, starting from the one the server asked for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AttachmentChunk {
+    /// Hash of the attachment the chunk belongs to
+    pub content_hash: [u8; 32],
+
+    /// Position of the chunk, from 0
+    pub index: u32,
+
+    /// Chunk bytes
+    pub data: Vec<u8>,
+}
+
+/// Every chunk of an upload was sent (client → server)
+///
+/// The server checks the assembled bytes against the hash, stores the blob
+/// and answers with a complete [`AttachmentStatus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AttachmentComplete {
+    /// Hash of the finished attachment
+    pub content_hash: [u8; 32],
+}
+
+/// Progress of an upload (server → client)
+///
+/// Once the server holds the whole attachment `next_chunk` is the number of
+/// chunks it was split into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AttachmentStatus {
+    /// Hash of the attachment
+    pub content_hash: [u8; 32],
+
+    /// Chunk the server needs next
+    pub next_chunk: u32,
+
+    /// Whether the server holds the whole attachment
+    pub complete: bool,
+}
+
+/// Download chunks of a stored attachment (client → server)
+///
+/// Answered with the chunks from `from_chunk` on, as many as the server sends
+/// per request, followed by the attachment's [`AttachmentStatus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AttachmentFetch {
+    /// Hash of the attachment
+    pub content_hash: [u8; 32],
+
+    /// First chunk to send
+    pub from_chunk: u32,
+}
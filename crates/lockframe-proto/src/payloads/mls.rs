@@ -11,15 +11,41 @@ use serde::{Deserialize, Serialize};
 ///
 /// # Protocol Flow
 ///
-/// Sent by a client who wants to join a room. The server stores this KeyPackage
-/// and later includes it in a Welcome message when the client is added to the
-/// group by another member's Commit.
+/// Sent by a client who wants to be added to rooms. The server keeps the
+/// KeyPackage in its directory under the sender until a room member claims it
+/// with a [`KeyPackageClaim`] to add the client by a Commit.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct KeyPackageData {
     /// Serialized MLS KeyPackage (from openmls)
     pub key_package_bytes: Vec<u8>,
 }
 
+/// Key package claim
+///
+/// # Protocol Flow
+///
+/// Sent by a member of the room in the frame header who is about to add
+/// `member_id` to it. The server answers with a [`ClaimedKeyPackage`], one of
+/// the KeyPackages `member_id` uploaded, and counts the claim against the
+/// sender until a Commit adding `member_id` to the room is sequenced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct KeyPackageClaim {
+    /// Member whose KeyPackage is claimed
+    pub member_id: u64,
+}
+
+/// Key package handed out for a [`KeyPackageClaim`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ClaimedKeyPackage {
+    /// Member the KeyPackage belongs to
+    pub member_id: u64,
+    /// Serialized MLS KeyPackage (from openmls)
+    pub key_package_bytes: Vec<u8>,
+}
+
 /// MLS proposal
 ///
 /// Proposals are staged changes to the group (add member, remove member, etc.)
@@ -33,6 +59,7 @@ pub struct KeyPackageData {
 /// 3. Any member can send Commit referencing pending Proposals
 /// 4. Commit advances the group epoch and applies all pending Proposals
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ProposalData {
     /// Serialized MLS Proposal
     pub proposal_bytes: Vec<u8>,
@@ -43,6 +70,7 @@ pub struct ProposalData {
 
 /// Type of MLS proposal
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[allow(clippy::upper_case_acronyms)]
 pub enum ProposalType {
     /// Add a new member
@@ -75,6 +103,7 @@ pub enum ProposalType {
 /// 5. All members apply Commit and advance to new epoch
 /// 6. Old epoch keys are deleted (forward secrecy)
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct CommitData {
     /// Serialized MLS Commit
     pub commit_bytes: Vec<u8>,
@@ -102,6 +131,7 @@ pub struct CommitData {
 /// 4. Server sends Welcome directly to B (not broadcast to group)
 /// 5. B decrypts Welcome and joins the group at current epoch
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct WelcomeData {
     /// Serialized MLS Welcome
     pub welcome_bytes: Vec<u8>,
@@ -15,6 +15,7 @@
 //! exhaustiveness). Round-trip encoding must produce identical values.
 
 pub mod app;
+pub mod attachment;
 pub mod mls;
 pub mod moderation;
 pub mod session;
@@ -52,6 +53,7 @@ use crate::{
 ///   a new variant will cause compile errors in `encode()`, `decode()`, and
 ///   `opcode()`, ensuring no variant is accidentally left unhandled.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Payload {
     // Session Management
     /// Initial handshake
@@ -64,14 +66,44 @@ pub enum Payload {
     Ping,
     /// Pong response
     Pong,
+    /// Timestamped keepalive
+    Heartbeat(session::Heartbeat),
+    /// Heartbeat echo
+    HeartbeatAck(session::Heartbeat),
+    /// Server clock refresh
+    TimeSync(session::TimeSync),
+    /// Signed log checkpoint
+    Checkpoint(session::Checkpoint),
     /// Client sync request
     SyncRequest(session::SyncRequest),
     /// Server sync response
     SyncResponse(session::SyncResponse),
+    /// Client Merkle proof request
+    ProofRequest(session::ProofRequest),
+    /// Server Merkle proof
+    ProofResponse(session::ProofResponse),
+    /// Client request to revoke its other sessions
+    RevokeSessions(session::RevokeSessions),
+    /// Server confirmation of a revocation
+    SessionsRevoked(session::SessionsRevoked),
+    /// Server notice that a room now lives on another server
+    RoomMoved(session::RoomMoved),
+    /// Server notice that it is going into maintenance
+    Maintenance(session::Maintenance),
+    /// Client request for a page of the room directory
+    ListRooms(session::ListRooms),
+    /// Server page of the room directory
+    ListRoomsReply(session::ListRoomsReply),
+    /// Client proof of its identity key
+    ChallengeResponse(session::ChallengeResponse),
 
     // MLS Operations
     /// Key package upload
     KeyPackage(mls::KeyPackageData),
+    /// Claim of a member's key package
+    ClaimKeyPackage(mls::KeyPackageClaim),
+    /// Key package handed out for a claim
+    KeyPackageClaimed(mls::ClaimedKeyPackage),
     /// MLS proposal
     Proposal(mls::ProposalData),
     /// MLS commit
@@ -86,6 +118,10 @@ pub enum Payload {
     AppReceipt(app::Receipt),
     /// Message reaction
     AppReaction(app::Reaction),
+    /// Typing indicator
+    Typing(app::Typing),
+    /// Read receipt
+    ReadReceipt(app::ReadReceipt),
 
     // Moderation
     /// Redact message content
@@ -95,6 +131,18 @@ pub enum Payload {
     /// Kick user
     Kick(moderation::Kick),
 
+    // Storage
+    /// Start or resume an attachment upload
+    AttachmentInit(attachment::AttachmentInit),
+    /// Chunk of an attachment upload
+    AttachmentChunk(attachment::AttachmentChunk),
+    /// Attachment upload finished
+    AttachmentComplete(attachment::AttachmentComplete),
+    /// Progress of an attachment upload
+    AttachmentStatus(attachment::AttachmentStatus),
+    /// Download chunks of a stored attachment
+    AttachmentFetch(attachment::AttachmentFetch),
+
     // Error frame
     /// Error response
     Error(ErrorPayload),
@@ -102,14 +150,31 @@ pub enum Payload {
 
 /// Error payload for error frames.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ErrorPayload {
     /// Error code identifying the type of error.
     pub code: u16,
     /// Human-readable error message.
     pub message: String,
+    /// Whether sending the same request again may succeed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retryable: bool,
     /// Optional retry-after duration in seconds.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub retry_after: Option<u64>,
+    /// Member counts for a `ROOM_FULL` error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_full: Option<RoomFull>,
+}
+
+/// Why a commit was rejected for exceeding a room's member limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RoomFull {
+    /// The room's member limit.
+    pub max_members: u32,
+    /// Members the room would have had after the commit.
+    pub member_count: u32,
 }
 
 impl ErrorPayload {
@@ -125,10 +190,26 @@ impl ErrorPayload {
     pub const MLS_ERROR: u16 = 0x0005;
     /// Sequencer error (e.g., duplicate log index).
     pub const SEQUENCER_ERROR: u16 = 0x0006;
+    /// Frame requires a capability the session did not negotiate.
+    pub const CAPABILITY_REQUIRED: u16 = 0x0007;
+    /// Request exceeded the session's budget; retry after `retry_after`.
+    pub const RATE_LIMITED: u16 = 0x0008;
+    /// Commit would take the room past its member limit; see `room_full`.
+    pub const ROOM_FULL: u16 = 0x0009;
+    /// Server is shedding load; retry after `retry_after`.
+    pub const OVERLOADED: u16 = 0x000A;
+    /// Hello offered no protocol version the server speaks.
+    pub const UNSUPPORTED_VERSION: u16 = 0x000B;
 
     /// Create a frame rejection error.
     pub fn frame_rejected(reason: impl Into<String>) -> Self {
-        Self { code: Self::FRAME_REJECTED, message: reason.into(), retry_after: None }
+        Self {
+            code: Self::FRAME_REJECTED,
+            message: reason.into(),
+            retryable: false,
+            retry_after: None,
+            room_full: None,
+        }
     }
 
     /// Create a room not found error.
@@ -136,28 +217,119 @@ impl ErrorPayload {
         Self {
             code: Self::ROOM_NOT_FOUND,
             message: format!("room not found: {:032x}", room_id),
+            retryable: false,
             retry_after: None,
+            room_full: None,
         }
     }
 
-    /// Create a storage error.
+    /// Create a storage error. Storage failures are transient, so the
+    /// request may be retried.
     pub fn storage_error(msg: impl Into<String>) -> Self {
-        Self { code: Self::STORAGE_ERROR, message: msg.into(), retry_after: None }
+        Self {
+            code: Self::STORAGE_ERROR,
+            message: msg.into(),
+            retryable: true,
+            retry_after: None,
+            room_full: None,
+        }
     }
 
     /// Create an invalid payload error.
     pub fn invalid_payload(msg: impl Into<String>) -> Self {
-        Self { code: Self::INVALID_PAYLOAD, message: msg.into(), retry_after: None }
+        Self {
+            code: Self::INVALID_PAYLOAD,
+            message: msg.into(),
+            retryable: false,
+            retry_after: None,
+            room_full: None,
+        }
     }
 
     /// Create an MLS error.
     pub fn mls_error(msg: impl Into<String>) -> Self {
-        Self { code: Self::MLS_ERROR, message: msg.into(), retry_after: None }
+        Self {
+            code: Self::MLS_ERROR,
+            message: msg.into(),
+            retryable: false,
+            retry_after: None,
+            room_full: None,
+        }
+    }
+
+    /// Create a capability-required error naming the missing capabilities.
+    pub fn capability_required(missing: crate::Capabilities) -> Self {
+        Self {
+            code: Self::CAPABILITY_REQUIRED,
+            message: format!("capability not negotiated: {}", missing.to_names().join(", ")),
+            retryable: false,
+            retry_after: None,
+            room_full: None,
+        }
+    }
+
+    /// Create a rate-limited error asking the client to retry after
+    /// `retry_after_secs` seconds.
+    pub fn rate_limited(msg: impl Into<String>, retry_after_secs: u64) -> Self {
+        Self {
+            code: Self::RATE_LIMITED,
+            message: msg.into(),
+            retryable: true,
+            retry_after: Some(retry_after_secs),
+            room_full: None,
+        }
+    }
+
+    /// Create an overloaded error asking the client to retry after
+    /// `retry_after_secs` seconds.
+    pub fn overloaded(msg: impl Into<String>, retry_after_secs: u64) -> Self {
+        Self {
+            code: Self::OVERLOADED,
+            message: msg.into(),
+            retryable: true,
+            retry_after: Some(retry_after_secs),
+            room_full: None,
+        }
+    }
+
+    /// Create a room-full error for a commit that would leave the room with
+    /// `member_count` members when at most `max_members` are allowed.
+    pub fn room_full(max_members: u32, member_count: u32) -> Self {
+        Self {
+            code: Self::ROOM_FULL,
+            message: format!("room full: {member_count} members exceeds limit of {max_members}"),
+            retryable: false,
+            retry_after: None,
+            room_full: Some(RoomFull { max_members, member_count }),
+        }
+    }
+
+    /// Create an unsupported-version error for a client speaking
+    /// `oldest..=newest`.
+    pub fn unsupported_version(oldest: u8, newest: u8) -> Self {
+        Self {
+            code: Self::UNSUPPORTED_VERSION,
+            message: format!(
+                "unsupported protocol version: client speaks {oldest}..={newest}, server speaks \
+                 {}..={}",
+                FrameHeader::MIN_VERSION,
+                FrameHeader::VERSION
+            ),
+            retryable: false,
+            retry_after: None,
+            room_full: None,
+        }
     }
 
     /// Create a sequencer error.
     pub fn sequencer_error(msg: impl Into<String>) -> Self {
-        Self { code: Self::SEQUENCER_ERROR, message: msg.into(), retry_after: None }
+        Self {
+            code: Self::SEQUENCER_ERROR,
+            message: msg.into(),
+            retryable: false,
+            retry_after: None,
+            room_full: None,
+        }
     }
 }
 
@@ -171,18 +343,40 @@ impl Payload {
             Self::Goodbye(_) => Opcode::Goodbye,
             Self::Ping => Opcode::Ping,
             Self::Pong => Opcode::Pong,
+            Self::Heartbeat(_) => Opcode::Heartbeat,
+            Self::HeartbeatAck(_) => Opcode::HeartbeatAck,
+            Self::TimeSync(_) => Opcode::TimeSync,
+            Self::Checkpoint(_) => Opcode::Checkpoint,
             Self::SyncRequest(_) => Opcode::SyncRequest,
             Self::SyncResponse(_) => Opcode::SyncResponse,
+            Self::ProofRequest(_) => Opcode::ProofRequest,
+            Self::ProofResponse(_) => Opcode::ProofResponse,
+            Self::RevokeSessions(_) => Opcode::RevokeSessions,
+            Self::SessionsRevoked(_) => Opcode::SessionsRevoked,
+            Self::RoomMoved(_) => Opcode::RoomMoved,
+            Self::Maintenance(_) => Opcode::Maintenance,
+            Self::ListRooms(_) => Opcode::ListRooms,
+            Self::ListRoomsReply(_) => Opcode::ListRoomsReply,
+            Self::ChallengeResponse(_) => Opcode::ChallengeResponse,
             Self::KeyPackage(_) => Opcode::KeyPackage,
+            Self::ClaimKeyPackage(_) => Opcode::ClaimKeyPackage,
+            Self::KeyPackageClaimed(_) => Opcode::KeyPackageClaimed,
             Self::Proposal(_) => Opcode::Proposal,
             Self::Commit(_) => Opcode::Commit,
             Self::Welcome(_) => Opcode::Welcome,
             Self::AppMessage(_) => Opcode::AppMessage,
             Self::AppReceipt(_) => Opcode::AppReceipt,
             Self::AppReaction(_) => Opcode::AppReaction,
+            Self::Typing(_) => Opcode::Typing,
+            Self::ReadReceipt(_) => Opcode::ReadReceipt,
             Self::Redact(_) => Opcode::Redact,
             Self::Ban(_) => Opcode::Ban,
             Self::Kick(_) => Opcode::Kick,
+            Self::AttachmentInit(_) => Opcode::AttachmentInit,
+            Self::AttachmentChunk(_) => Opcode::AttachmentChunk,
+            Self::AttachmentComplete(_) => Opcode::AttachmentComplete,
+            Self::AttachmentStatus(_) => Opcode::AttachmentStatus,
+            Self::AttachmentFetch(_) => Opcode::AttachmentFetch,
             Self::Error(_) => Opcode::Error,
         }
     }
@@ -214,18 +408,44 @@ impl Payload {
             Self::HelloReply(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::Goodbye(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::Ping | Self::Pong => Ok(()), // Zero-byte payloads
+            Self::Heartbeat(inner) | Self::HeartbeatAck(inner) => {
+                ciborium::ser::into_writer(inner, &mut writer)
+            },
+            Self::TimeSync(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::Checkpoint(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::SyncRequest(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::SyncResponse(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::ProofRequest(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::ProofResponse(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::RevokeSessions(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::SessionsRevoked(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::RoomMoved(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::Maintenance(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::ListRooms(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::ListRoomsReply(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::ChallengeResponse(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::KeyPackage(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::ClaimKeyPackage(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::KeyPackageClaimed(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::Proposal(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::Commit(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::Welcome(inner) => ciborium::ser::into_writer(inner, &mut writer),
-            Self::AppMessage(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::AppMessage(inner) => {
+                writer.get_mut().put_slice(&inner.encode()?);
+                Ok(())
+            },
             Self::AppReceipt(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::AppReaction(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::Typing(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::ReadReceipt(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::Redact(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::Ban(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::Kick(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::AttachmentInit(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::AttachmentChunk(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::AttachmentComplete(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::AttachmentStatus(inner) => ciborium::ser::into_writer(inner, &mut writer),
+            Self::AttachmentFetch(inner) => ciborium::ser::into_writer(inner, &mut writer),
             Self::Error(inner) => ciborium::ser::into_writer(inner, &mut writer),
         }
         .map_err(|e| ProtocolError::CborEncode(e.to_string()))
@@ -251,6 +471,7 @@ impl Payload {
     ///   MB)
     /// - `ProtocolError::CborDecode` if CBOR deserialization fails
     /// - `ProtocolError::CborDecode` if opcode is not recognized
+    #[allow(clippy::too_many_lines)]
     pub fn decode(opcode: Opcode, bytes: &[u8]) -> Result<Self> {
         if bytes.len() > FrameHeader::MAX_PAYLOAD_SIZE as usize {
             return Err(ProtocolError::PayloadTooLarge {
@@ -274,6 +495,22 @@ impl Payload {
             ),
             Opcode::Ping => Self::Ping,
             Opcode::Pong => Self::Pong,
+            Opcode::Heartbeat => Self::Heartbeat(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::HeartbeatAck => Self::HeartbeatAck(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::TimeSync => Self::TimeSync(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::Checkpoint => Self::Checkpoint(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
             Opcode::SyncRequest => Self::SyncRequest(
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
@@ -282,10 +519,54 @@ impl Payload {
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
             ),
+            Opcode::ProofRequest => Self::ProofRequest(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::ProofResponse => Self::ProofResponse(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::RevokeSessions => Self::RevokeSessions(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::SessionsRevoked => Self::SessionsRevoked(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::RoomMoved => Self::RoomMoved(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::Maintenance => Self::Maintenance(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::ListRooms => Self::ListRooms(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::ListRoomsReply => Self::ListRoomsReply(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::ChallengeResponse => Self::ChallengeResponse(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
             Opcode::KeyPackage => Self::KeyPackage(
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
             ),
+            Opcode::ClaimKeyPackage => Self::ClaimKeyPackage(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::KeyPackageClaimed => Self::KeyPackageClaimed(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
             Opcode::Proposal => Self::Proposal(
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
@@ -298,15 +579,20 @@ impl Payload {
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
             ),
-            Opcode::AppMessage => Self::AppMessage(
+            Opcode::AppMessage => Self::AppMessage(app::EncryptedMessage::decode(bytes)?),
+            Opcode::AppReceipt => Self::AppReceipt(
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
             ),
-            Opcode::AppReceipt => Self::AppReceipt(
+            Opcode::AppReaction => Self::AppReaction(
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
             ),
-            Opcode::AppReaction => Self::AppReaction(
+            Opcode::Typing => Self::Typing(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::ReadReceipt => Self::ReadReceipt(
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
             ),
@@ -322,6 +608,26 @@ impl Payload {
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
             ),
+            Opcode::AttachmentInit => Self::AttachmentInit(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::AttachmentChunk => Self::AttachmentChunk(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::AttachmentComplete => Self::AttachmentComplete(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::AttachmentStatus => Self::AttachmentStatus(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
+            Opcode::AttachmentFetch => Self::AttachmentFetch(
+                ciborium::de::from_reader(bytes)
+                    .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
+            ),
             Opcode::Error => Self::Error(
                 ciborium::de::from_reader(bytes)
                     .map_err(|e| ProtocolError::CborDecode(e.to_string()))?,
@@ -393,12 +699,193 @@ mod tests {
         assert_eq!(payload, decoded);
     }
 
+    #[test]
+    fn payload_heartbeat_round_trip() {
+        let heartbeat = session::Heartbeat { timestamp_micros: 1_500_000 };
+
+        for payload in [Payload::Heartbeat(heartbeat.clone()), Payload::HeartbeatAck(heartbeat)] {
+            let frame = payload.clone().into_frame(FrameHeader::new(payload.opcode())).unwrap();
+            let decoded = Payload::from_frame(frame).expect("should parse payload");
+            assert_eq!(payload, decoded);
+        }
+    }
+
+    #[test]
+    fn payload_checkpoint_round_trip() {
+        let payload = Payload::Checkpoint(session::Checkpoint {
+            log_index: 99,
+            log_hash: [0x5A; 32],
+            signature: vec![0xC3; 64],
+        });
+
+        let frame = payload.clone().into_frame(FrameHeader::new(Opcode::Checkpoint)).unwrap();
+        let decoded = Payload::from_frame(frame).expect("should parse payload");
+        assert_eq!(payload, decoded);
+    }
+
+    #[test]
+    fn payload_proof_round_trip() {
+        let request = session::ProofRequest::Consistency { old_size: 8, new_size: None };
+        let payloads = [
+            Payload::ProofRequest(request),
+            Payload::ProofResponse(session::ProofResponse {
+                request,
+                tree_size: 13,
+                root: [0x11; 32],
+                path: vec![[0x22; 32], [0x33; 32]],
+            }),
+        ];
+
+        for payload in payloads {
+            let frame = payload.clone().into_frame(FrameHeader::new(payload.opcode())).unwrap();
+            let decoded = Payload::from_frame(frame).expect("should parse payload");
+            assert_eq!(payload, decoded);
+        }
+    }
+
+    #[test]
+    fn payload_revocation_round_trip() {
+        let payloads = [
+            Payload::RevokeSessions(session::RevokeSessions { member_ids: vec![7] }),
+            Payload::SessionsRevoked(session::SessionsRevoked {
+                member_ids: vec![7, 9],
+                sessions_closed: 2,
+            }),
+        ];
+
+        for payload in payloads {
+            let frame = payload.clone().into_frame(FrameHeader::new(payload.opcode())).unwrap();
+            let decoded = Payload::from_frame(frame).expect("should parse payload");
+            assert_eq!(payload, decoded);
+        }
+    }
+
+    #[test]
+    fn payload_room_moved_round_trip() {
+        let payload = Payload::RoomMoved(session::RoomMoved {
+            target: "lockframe-2.example:4433".to_string(),
+            cutover_log_index: 42,
+        });
+
+        let frame = payload.clone().into_frame(FrameHeader::new(Opcode::RoomMoved)).unwrap();
+        assert_eq!(Payload::from_frame(frame).expect("should parse payload"), payload);
+    }
+
+    #[test]
+    fn payload_challenge_response_round_trip() {
+        let payload = Payload::ChallengeResponse(session::ChallengeResponse {
+            identity_key: vec![7; 32],
+            signature: vec![9; 64],
+        });
+
+        let frame =
+            payload.clone().into_frame(FrameHeader::new(Opcode::ChallengeResponse)).unwrap();
+        assert_eq!(Payload::from_frame(frame).expect("should parse payload"), payload);
+    }
+
+    #[test]
+    fn payload_maintenance_round_trip() {
+        let payload = Payload::Maintenance(session::Maintenance {
+            reconnect_after_secs: 300,
+            reason: "upgrade".to_string(),
+        });
+
+        let frame = payload.clone().into_frame(FrameHeader::new(Opcode::Maintenance)).unwrap();
+        assert_eq!(Payload::from_frame(frame).expect("should parse payload"), payload);
+    }
+
+    #[test]
+    fn payload_typing_round_trip() {
+        let payload = Payload::Typing(app::Typing { typing: true });
+
+        let frame = payload.clone().into_frame(FrameHeader::new(Opcode::Typing)).unwrap();
+        assert_eq!(Payload::from_frame(frame).expect("should parse payload"), payload);
+    }
+
+    #[test]
+    fn payload_read_receipt_round_trip() {
+        let payload =
+            Payload::ReadReceipt(app::ReadReceipt { room_id: 0x1234, up_to_log_index: 42 });
+
+        let frame = payload.clone().into_frame(FrameHeader::new(Opcode::ReadReceipt)).unwrap();
+        assert_eq!(Payload::from_frame(frame).expect("should parse payload"), payload);
+    }
+
+    #[test]
+    fn payload_attachment_round_trip() {
+        let content_hash = [7; 32];
+        let payloads = [
+            Payload::AttachmentInit(attachment::AttachmentInit {
+                content_hash,
+                total_size: 100_000,
+                chunk_size: 65_536,
+            }),
+            Payload::AttachmentChunk(attachment::AttachmentChunk {
+                content_hash,
+                index: 1,
+                data: vec![0xAB; 64],
+            }),
+            Payload::AttachmentComplete(attachment::AttachmentComplete { content_hash }),
+            Payload::AttachmentStatus(attachment::AttachmentStatus {
+                content_hash,
+                next_chunk: 2,
+                complete: true,
+            }),
+            Payload::AttachmentFetch(attachment::AttachmentFetch { content_hash, from_chunk: 1 }),
+        ];
+
+        for payload in payloads {
+            let frame = payload.clone().into_frame(FrameHeader::new(payload.opcode())).unwrap();
+            assert_eq!(Payload::from_frame(frame).expect("should parse payload"), payload);
+        }
+    }
+
+    #[test]
+    fn payload_key_package_claim_round_trip() {
+        let payloads = [
+            Payload::ClaimKeyPackage(mls::KeyPackageClaim { member_id: 7 }),
+            Payload::KeyPackageClaimed(mls::ClaimedKeyPackage {
+                member_id: 7,
+                key_package_bytes: vec![0xAB; 64],
+            }),
+        ];
+
+        for payload in payloads {
+            let frame = payload.clone().into_frame(FrameHeader::new(payload.opcode())).unwrap();
+            assert_eq!(Payload::from_frame(frame).expect("should parse payload"), payload);
+        }
+    }
+
+    #[test]
+    fn payload_room_directory_round_trip() {
+        let payloads = [
+            Payload::ListRooms(session::ListRooms { after: Some(0x42), limit: 20 }),
+            Payload::ListRoomsReply(session::ListRoomsReply {
+                rooms: vec![session::DirectoryEntry {
+                    room_id: 0x43,
+                    name: "lobby".to_string(),
+                    description: "Say hello".to_string(),
+                    members: 12,
+                }],
+                more: true,
+            }),
+        ];
+
+        for payload in payloads {
+            let frame = payload.clone().into_frame(FrameHeader::new(payload.opcode())).unwrap();
+            let decoded = Payload::from_frame(frame).expect("should parse payload");
+            assert_eq!(payload, decoded);
+        }
+    }
+
     #[test]
     fn payload_error_round_trip() {
         let payload = Payload::Error(ErrorPayload {
             code: 0x00FF,
             message: "Test error".to_string(),
+            retryable: true,
             retry_after: Some(30),
+            room_full: None,
         });
 
         // Create valid header
@@ -10,6 +10,7 @@ use serde::{Deserialize, Serialize};
 /// Removes message content via cryptographic erasure (deleting the payload
 /// key).
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Redact {
     /// Log index of the message to redact
     pub message_log_index: u64,
@@ -25,6 +26,7 @@ pub struct Redact {
 ///
 /// Removes user via MLS External Commit, preventing future message decryption.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Ban {
     /// User ID to ban
     pub user_id: u64,
@@ -44,6 +46,7 @@ pub struct Ban {
 ///
 /// Temporary removal without ban. User can rejoin.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Kick {
     /// User ID to kick
     pub user_id: u64,
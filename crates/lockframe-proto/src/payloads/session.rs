@@ -24,6 +24,7 @@ use serde::{Deserialize, Serialize};
 ///   accidental logging of credentials. Always use custom `Debug`
 ///   implementations for types containing secrets.
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Hello {
     /// Protocol version
     pub version: u8,
@@ -56,13 +57,15 @@ impl std::fmt::Debug for Hello {
 ///
 /// Sent as immediate response to client's `Hello`. The session_id in this
 /// message must be used by the client in all subsequent frame headers to
-/// identify its session.
+/// identify its session. `time_sync` gives the client an initial reading of
+/// the server clock; later readings arrive as [`TimeSync`] frames.
 ///
 /// # Security
 ///
 /// - Debug Redaction: The `Debug` impl redacts `challenge` to prevent logging
 ///   cryptographic nonces or auth challenges.
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct HelloReply {
     /// Assigned session ID
     pub session_id: u64,
@@ -71,6 +74,9 @@ pub struct HelloReply {
     /// Authentication challenge (if needed)
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub challenge: Option<Vec<u8>>,
+    /// Server clock at the time of the reply
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub time_sync: Option<TimeSync>,
 }
 
 impl std::fmt::Debug for HelloReply {
@@ -82,21 +88,84 @@ impl std::fmt::Debug for HelloReply {
                 "challenge",
                 &self.challenge.as_ref().map(|ch| format!("<redacted {} bytes>", ch.len())),
             )
+            .field("time_sync", &self.time_sync)
             .finish()
     }
 }
 
+/// Server clock reading
+///
+/// Embedded in [`HelloReply`] and refreshed periodically as a standalone
+/// [`Opcode::TimeSync`](crate::Opcode::TimeSync) frame. Clients use
+/// `wall_clock_millis` to estimate their offset from the server for display,
+/// and merge `hlc` into their own hybrid logical clock so locally stamped
+/// frames order after everything the server has seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct TimeSync {
+    /// Server wall-clock time in Unix milliseconds.
+    pub wall_clock_millis: u64,
+    /// Server hybrid logical clock (48-bit milliseconds, 16-bit counter).
+    pub hlc: u64,
+}
+
+/// Signed log checkpoint
+///
+/// Sequenced into a room's log by the server every so often. `log_hash` is
+/// the rolling hash of every frame up to and including `log_index`, signed by
+/// the server so a client that has verified a checkpoint can detect any later
+/// rewrite of that prefix of the log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Checkpoint {
+    /// Log index of the last frame covered by `log_hash`.
+    pub log_index: u64,
+    /// Rolling SHA-256 hash of the log through `log_index`.
+    pub log_hash: [u8; 32],
+    /// Ed25519 signature by the server's checkpoint key (64 bytes).
+    pub signature: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// Domain separator for checkpoint signatures.
+    pub const SIGNING_CONTEXT: &'static [u8] = b"lockframe-checkpoint-v1";
+
+    /// Bytes covered by the signature, binding the checkpoint to its room.
+    #[must_use]
+    pub fn signing_data(room_id: u128, log_index: u64, log_hash: &[u8; 32]) -> Vec<u8> {
+        let mut data = Self::SIGNING_CONTEXT.to_vec();
+        data.extend_from_slice(&room_id.to_be_bytes());
+        data.extend_from_slice(&log_index.to_be_bytes());
+        data.extend_from_slice(log_hash);
+        data
+    }
+}
+
 /// Graceful disconnect
 ///
 /// Sent by either client or server to terminate a session cleanly.
 /// After sending or receiving `Goodbye`, both parties should close the
 /// connection.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Goodbye {
     /// Reason for disconnect (for logging/debugging)
     pub reason: String,
 }
 
+/// Timestamped keepalive
+///
+/// Sent as [`Opcode::Heartbeat`](crate::Opcode::Heartbeat) and echoed
+/// unchanged as [`Opcode::HeartbeatAck`](crate::Opcode::HeartbeatAck). Only the
+/// sender interprets the timestamp, so each side can sample round-trip time
+/// against its own clock without the two clocks agreeing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Heartbeat {
+    /// Sender's monotonic clock reading in microseconds.
+    pub timestamp_micros: u64,
+}
+
 /// Client request for missing frames (epoch sync)
 ///
 /// Sent by a client when it detects it's behind the server's epoch
@@ -106,6 +175,7 @@ pub struct Goodbye {
 /// server responds with SyncResponse containing frames, and client processes
 /// frames in order to catch up.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SyncRequest {
     /// Start replaying frames from this log index (inclusive).
     ///
@@ -119,6 +189,29 @@ pub struct SyncRequest {
     /// Default: 100 frames per batch.
     #[serde(default = "default_limit")]
     pub limit: u64,
+
+    /// Which frames to return.
+    #[serde(default)]
+    pub mode: SyncMode,
+}
+
+/// Which frames a sync returns
+///
+/// A client many epochs behind only needs the MLS handshake frames to reach
+/// the current epoch. It can sync in [`SyncMode::EpochChanges`] first and
+/// backfill the application messages it skipped with [`SyncMode::Backfill`]
+/// later.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum SyncMode {
+    /// Every frame in the range.
+    #[default]
+    Full,
+    /// Only MLS handshake frames (proposals, commits, welcomes).
+    EpochChanges,
+    /// Every frame in the range, as a page of messages skipped by an
+    /// [`EpochChanges`](Self::EpochChanges) sync.
+    Backfill,
 }
 
 fn default_limit() -> u64 {
@@ -131,6 +224,7 @@ fn default_limit() -> u64 {
 /// If `has_more` is true, the client should send another `SyncRequest`
 /// with `from_log_index` = last frame's log_index + 1.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SyncResponse {
     /// Frames in log_index order.
     ///
@@ -147,6 +241,169 @@ pub struct SyncResponse {
     ///
     /// After processing all frames, client epoch should match this.
     pub server_epoch: u64,
+
+    /// Mode of the request this answers.
+    #[serde(default)]
+    pub mode: SyncMode,
+
+    /// Log index to continue from when `has_more` is true.
+    ///
+    /// In [`SyncMode::EpochChanges`] the server skips frames, so this can be
+    /// past the last returned frame. Older servers leave it unset; the
+    /// client then continues after the last frame.
+    #[serde(default)]
+    pub next_log_index: Option<u64>,
+}
+
+/// Client request for a Merkle proof over a room's log
+///
+/// Tree sizes count frames from log index 0, so the tree of size `n` covers
+/// log indices `0..n`. A missing size means the server's current log size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum ProofRequest {
+    /// Prove the frame at `leaf_index` is in the tree of `tree_size` frames.
+    Inclusion {
+        /// Log index of the frame.
+        leaf_index: u64,
+        /// Tree size to prove against.
+        tree_size: Option<u64>,
+    },
+    /// Prove the tree of `old_size` frames is a prefix of the tree of
+    /// `new_size` frames.
+    Consistency {
+        /// Size of the tree the client already trusts.
+        old_size: u64,
+        /// Size of the newer tree.
+        new_size: Option<u64>,
+    },
+}
+
+/// Server response to a [`ProofRequest`]
+///
+/// `tree_size` and `root` describe the tree the proof was built against, so a
+/// client can pin the root and ask for consistency with it later.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ProofResponse {
+    /// The request being answered.
+    pub request: ProofRequest,
+    /// Size of the tree the proof was built against.
+    pub tree_size: u64,
+    /// Merkle root of that tree.
+    pub root: [u8; 32],
+    /// Proof nodes, in RFC 6962 order.
+    pub path: Vec<[u8; 32]>,
+}
+
+/// Client request to revoke the account's other sessions
+///
+/// An account is every session authenticated as the same principal: the
+/// client certificate principal, or the identity key proven in answer to the
+/// [`HelloReply`] challenge. The server closes the revoked sessions, drops what
+/// was queued for their members while offline, and refuses those members from
+/// then on. It answers with [`SessionsRevoked`], after which the requesting
+/// client commits the revoked members out of every room it shares with them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RevokeSessions {
+    /// Members (devices) to revoke. Empty revokes every member of the
+    /// account other than the sender.
+    pub member_ids: Vec<u64>,
+}
+
+/// Server answer to [`RevokeSessions`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SessionsRevoked {
+    /// Members that were revoked
+    pub member_ids: Vec<u64>,
+    /// Connections the server closed
+    pub sessions_closed: u32,
+}
+
+/// Server notice that the room in the frame header moved to another server
+///
+/// Sent to every session in the room when an operator migrates it, and in
+/// answer to any later frame for the room. The old server sequenced the log
+/// up to, but not including, `cutover_log_index`; the new server continues
+/// from there. Clients reconnect to `target` and sync from their last seen
+/// index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RoomMoved {
+    /// Address of the server now hosting the room
+    pub target: String,
+    /// First log index sequenced by the new server
+    pub cutover_log_index: u64,
+}
+
+/// Server notice that it is going into maintenance
+///
+/// Sent to every session when an operator starts maintenance. The server
+/// stops accepting connections, delivers the frames it has queued, flushes
+/// storage and then closes the session. Clients reconnect no sooner than
+/// `reconnect_after_secs` after receiving it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Maintenance {
+    /// Seconds until the server accepts connections again
+    pub reconnect_after_secs: u64,
+    /// Why the server is going down, for display
+    pub reason: String,
+}
+
+/// Client request for a page of the server's room directory
+///
+/// Only rooms an operator listed are in the directory. Entries are ordered by
+/// room ID; a client pages through them by passing the last room ID of the
+/// previous page as `after`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ListRooms {
+    /// List rooms after this room ID (`None` starts from the beginning)
+    pub after: Option<u128>,
+    /// Maximum number of rooms to return (the server may return fewer)
+    pub limit: u32,
+}
+
+/// One listed room in a [`ListRoomsReply`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DirectoryEntry {
+    /// Room to join
+    pub room_id: u128,
+    /// Display name
+    pub name: String,
+    /// What the room is about, for display
+    pub description: String,
+    /// Sessions currently subscribed to the room
+    pub members: u32,
+}
+
+/// Server answer to [`ListRooms`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ListRoomsReply {
+    /// Listed rooms, ordered by room ID
+    pub rooms: Vec<DirectoryEntry>,
+    /// Whether more rooms follow the last one
+    pub more: bool,
+}
+
+/// Client proof that it holds its identity key
+///
+/// Sent in answer to a [`HelloReply`] carrying a `challenge`. `signature` is
+/// the Ed25519 signature by `identity_key` over the session ID and the
+/// challenge; the server keeps the session out of the authenticated state
+/// until it checks out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ChallengeResponse {
+    /// Ed25519 public key the client identifies with
+    pub identity_key: Vec<u8>,
+    /// Signature over the challenge
+    pub signature: Vec<u8>,
 }
 
 #[cfg(test)]
@@ -161,9 +418,31 @@ mod tests {
         assert!(cbor.is_ok());
     }
 
+    #[test]
+    fn hello_reply_time_sync_is_optional() {
+        let legacy =
+            HelloReply { session_id: 7, capabilities: vec![], challenge: None, time_sync: None };
+        let mut legacy_bytes = Vec::new();
+        ciborium::ser::into_writer(&legacy, &mut legacy_bytes).expect("encode");
+
+        let reply = HelloReply {
+            time_sync: Some(TimeSync { wall_clock_millis: 1_700_000_000_000, hlc: 42 }),
+            ..legacy.clone()
+        };
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&reply, &mut bytes).expect("encode");
+
+        let decoded: HelloReply = ciborium::de::from_reader(&bytes[..]).expect("decode");
+        assert_eq!(reply, decoded);
+
+        // Replies without time_sync decode unchanged
+        let decoded: HelloReply = ciborium::de::from_reader(&legacy_bytes[..]).expect("decode");
+        assert_eq!(legacy, decoded);
+    }
+
     #[test]
     fn sync_request_serde() {
-        let request = SyncRequest { from_log_index: 42, limit: 50 };
+        let request = SyncRequest { from_log_index: 42, limit: 50, mode: SyncMode::EpochChanges };
 
         let mut bytes = Vec::new();
         ciborium::ser::into_writer(&request, &mut bytes).expect("encode");
@@ -175,7 +454,8 @@ mod tests {
     #[test]
     fn sync_request_default_limit() {
         // Encode without limit field
-        let request_no_limit = SyncRequest { from_log_index: 10, limit: default_limit() };
+        let request_no_limit =
+            SyncRequest { from_log_index: 10, limit: default_limit(), mode: SyncMode::Full };
 
         let mut bytes = Vec::new();
         ciborium::ser::into_writer(&request_no_limit, &mut bytes).expect("encode");
@@ -190,6 +470,8 @@ mod tests {
             frames: vec![vec![1, 2, 3], vec![4, 5, 6]],
             has_more: true,
             server_epoch: 5,
+            mode: SyncMode::EpochChanges,
+            next_log_index: Some(40),
         };
 
         let mut bytes = Vec::new();
@@ -198,4 +480,31 @@ mod tests {
         let decoded: SyncResponse = ciborium::de::from_reader(&bytes[..]).expect("decode");
         assert_eq!(response, decoded);
     }
+
+    #[test]
+    fn sync_fields_default_for_older_peers() {
+        #[derive(Serialize)]
+        struct LegacyRequest {
+            from_log_index: u64,
+            limit: u64,
+        }
+        #[derive(Serialize)]
+        struct LegacyResponse {
+            frames: Vec<Vec<u8>>,
+            has_more: bool,
+            server_epoch: u64,
+        }
+
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&LegacyRequest { from_log_index: 3, limit: 10 }, &mut bytes)
+            .expect("encode");
+        let decoded: SyncRequest = ciborium::de::from_reader(&bytes[..]).expect("decode");
+        assert_eq!(decoded.mode, SyncMode::Full);
+
+        let legacy = LegacyResponse { frames: vec![], has_more: false, server_epoch: 2 };
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&legacy, &mut bytes).expect("encode");
+        let decoded: SyncResponse = ciborium::de::from_reader(&bytes[..]).expect("decode");
+        assert_eq!((decoded.mode, decoded.next_log_index), (SyncMode::Full, None));
+    }
 }
@@ -0,0 +1,149 @@
+//! Frame priority classes.
+//!
+//! Outgoing frames are queued per session and drained highest class first, so
+//! a flood of application messages can't delay the frames that keep a session
+//! and its groups consistent. The class is derived from the opcode, with the
+//! `PRIORITY` flag promoting a content frame ahead of bulk traffic.
+
+use crate::{FrameFlags, FrameHeader, Opcode};
+
+/// Scheduling class of a frame. Earlier variants are sent first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Session management, MLS operations, and acknowledgements
+    Control,
+
+    /// Content frames flagged with `FrameFlags::PRIORITY` (DMs, mentions)
+    High,
+
+    /// Everything else
+    Bulk,
+}
+
+impl Priority {
+    /// All classes in scheduling order.
+    pub const ALL: [Self; 3] = [Self::Control, Self::High, Self::Bulk];
+
+    /// Scheduling class for a frame with this header.
+    ///
+    /// Unknown opcodes are treated as bulk.
+    #[must_use]
+    pub fn of(header: &FrameHeader) -> Self {
+        if header.opcode_enum().is_some_and(Self::is_control) {
+            Self::Control
+        } else if header.flags().contains(FrameFlags::PRIORITY) {
+            Self::High
+        } else {
+            Self::Bulk
+        }
+    }
+
+    /// Position of this class in [`Priority::ALL`].
+    #[must_use]
+    pub const fn index(self) -> usize {
+        match self {
+            Self::Control => 0,
+            Self::High => 1,
+            Self::Bulk => 2,
+        }
+    }
+
+    /// Whether an opcode is always scheduled as control traffic.
+    fn is_control(opcode: Opcode) -> bool {
+        match opcode {
+            Opcode::Hello
+            | Opcode::HelloReply
+            | Opcode::Goodbye
+            | Opcode::Ping
+            | Opcode::Pong
+            | Opcode::SyncRequest
+            | Opcode::SyncResponse
+            | Opcode::Heartbeat
+            | Opcode::HeartbeatAck
+            | Opcode::TimeSync
+            | Opcode::Checkpoint
+            | Opcode::ProofRequest
+            | Opcode::ProofResponse
+            | Opcode::RevokeSessions
+            | Opcode::SessionsRevoked
+            | Opcode::RoomMoved
+            | Opcode::Maintenance
+            | Opcode::ListRooms
+            | Opcode::ListRoomsReply
+            | Opcode::ChallengeResponse
+            | Opcode::Error
+            | Opcode::KeyPackage
+            | Opcode::ClaimKeyPackage
+            | Opcode::KeyPackageClaimed
+            | Opcode::Proposal
+            | Opcode::Commit
+            | Opcode::Welcome
+            | Opcode::GroupInfo
+            | Opcode::PSKProposal
+            | Opcode::ReInit
+            | Opcode::ExternalCommit
+            | Opcode::AppReceipt
+            | Opcode::ReadReceipt
+            | Opcode::AttachmentInit
+            | Opcode::AttachmentComplete
+            | Opcode::AttachmentStatus
+            | Opcode::AttachmentFetch
+            | Opcode::FedAck
+            | Opcode::FedNack => true,
+
+            Opcode::AppMessage
+            | Opcode::AppReaction
+            | Opcode::AppEdit
+            | Opcode::AppDelete
+            | Opcode::Typing
+            | Opcode::Presence
+            | Opcode::Redact
+            | Opcode::Ban
+            | Opcode::Unban
+            | Opcode::Kick
+            | Opcode::Mute
+            | Opcode::Pin
+            | Opcode::Report
+            | Opcode::FedAppend
+            | Opcode::FedSync
+            | Opcode::FedQuery
+            | Opcode::CASPut
+            | Opcode::CASGet
+            | Opcode::CASDelete
+            | Opcode::CASProof
+            | Opcode::AttachmentChunk => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_opcodes_outrank_content() {
+        assert_eq!(Priority::of(&FrameHeader::new(Opcode::Commit)), Priority::Control);
+        assert_eq!(Priority::of(&FrameHeader::new(Opcode::SyncResponse)), Priority::Control);
+        assert_eq!(Priority::of(&FrameHeader::new(Opcode::AppReceipt)), Priority::Control);
+        assert_eq!(Priority::of(&FrameHeader::new(Opcode::AppMessage)), Priority::Bulk);
+        assert!(Priority::Control < Priority::Bulk);
+    }
+
+    #[test]
+    fn priority_flag_promotes_content_only() {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_flags(FrameFlags::PRIORITY);
+        assert_eq!(Priority::of(&header), Priority::High);
+
+        let mut header = FrameHeader::new(Opcode::Commit);
+        header.set_flags(FrameFlags::PRIORITY);
+        assert_eq!(Priority::of(&header), Priority::Control);
+    }
+
+    #[test]
+    fn index_matches_scheduling_order() {
+        for (i, priority) in Priority::ALL.into_iter().enumerate() {
+            assert_eq!(priority.index(), i);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Server timing trailer for delivered frames.
+//!
+//! Every header field a sender signs is off limits to the server, so the
+//! timestamps it adds when relaying a frame travel outside the frame: a
+//! fixed 16-byte trailer written right after the payload.
+//!
+//! ```text
+//! [frame: header + payload] [magic "LFTM": 4] [received_at_millis: 8] [processing_micros: 4]
+//! ```
+//!
+//! [`Frame::decode`](crate::Frame::decode) stops at the payload, so receivers
+//! that predate the trailer ignore it. The trailer is unauthenticated and is
+//! only good for measurement.
+
+use bytes::BufMut;
+
+/// Server timestamps for one relayed frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameTiming {
+    /// Server wall clock (Unix milliseconds) when the frame arrived
+    pub received_at_millis: u64,
+    /// Time between arrival and the broadcast being ready, in microseconds
+    pub processing_micros: u32,
+}
+
+impl FrameTiming {
+    /// Marks the start of a timing trailer.
+    pub const MAGIC: [u8; 4] = *b"LFTM";
+
+    /// Encoded size of the trailer.
+    pub const SIZE: usize = 16;
+
+    /// Write the trailer.
+    pub fn encode(&self, dst: &mut impl BufMut) {
+        dst.put_slice(&Self::MAGIC);
+        dst.put_u64(self.received_at_millis);
+        dst.put_u32(self.processing_micros);
+    }
+
+    /// Parse a trailer from the bytes following a frame.
+    ///
+    /// Returns `None` unless `bytes` starts with a complete trailer; anything
+    /// after it is ignored, as with frames.
+    #[must_use]
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let magic = bytes.get(..4)?;
+        let received_at: [u8; 8] = bytes.get(4..12)?.try_into().ok()?;
+        let processing: [u8; 4] = bytes.get(12..Self::SIZE)?.try_into().ok()?;
+
+        (magic == Self::MAGIC).then(|| Self {
+            received_at_millis: u64::from_be_bytes(received_at),
+            processing_micros: u32::from_be_bytes(processing),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailer_round_trip() {
+        let timing = FrameTiming { received_at_millis: 1_700_000_000_123, processing_micros: 42 };
+        let mut buf = Vec::new();
+        timing.encode(&mut buf);
+
+        assert_eq!(buf.len(), FrameTiming::SIZE);
+        assert_eq!(FrameTiming::decode(&buf), Some(timing));
+    }
+
+    #[test]
+    fn rejects_missing_or_foreign_trailer() {
+        assert_eq!(FrameTiming::decode(&[]), None);
+        assert_eq!(FrameTiming::decode(b"LFTM"), None);
+        assert_eq!(FrameTiming::decode(&[0; FrameTiming::SIZE]), None);
+    }
+}
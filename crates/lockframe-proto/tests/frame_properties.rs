@@ -223,3 +223,42 @@ fn prop_frame_encoded_size_correct() {
         );
     });
 }
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn prop_arbitrary_frames_survive_the_wire() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    proptest!(|(data in prop::collection::vec(any::<u8>(), 0..4096))| {
+        let Ok(frame) = Frame::arbitrary(&mut Unstructured::new(&data)) else {
+            return Ok(());
+        };
+
+        // PROPERTY: Generated frames are structurally valid
+        prop_assert!(frame.header.opcode_enum().is_some(), "Unknown opcode generated");
+        let mut buf = Vec::new();
+        frame.encode(&mut buf).expect("encode should succeed");
+        let decoded = Frame::decode(&buf).expect("decode should succeed");
+        prop_assert_eq!(decoded, frame);
+    });
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn prop_arbitrary_payloads_roundtrip() {
+    use arbitrary::{Arbitrary, Unstructured};
+    use lockframe_proto::Payload;
+
+    proptest!(|(data in prop::collection::vec(any::<u8>(), 0..4096))| {
+        let Ok(payload) = Payload::arbitrary(&mut Unstructured::new(&data)) else {
+            return Ok(());
+        };
+
+        // PROPERTY: A generated payload decodes back from its own frame
+        let frame = payload.clone().into_frame(FrameHeader::new(Opcode::Ping))
+            .expect("into_frame should succeed");
+        prop_assert_eq!(frame.header.opcode_enum(), Some(payload.opcode()));
+        let decoded = Payload::from_frame(frame).expect("decode should succeed");
+        prop_assert_eq!(decoded, payload);
+    });
+}
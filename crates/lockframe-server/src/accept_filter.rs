@@ -0,0 +1,443 @@
+//! Connection accept filters.
+//!
+//! Every accepted connection runs through a chain of [`AcceptFilter`]s before
+//! the driver registers a session for it. The first filter to refuse closes
+//! the connection; the driver never sees it. Address allow/deny lists and a
+//! per-address connection cap are built in and set up from [`AcceptConfig`];
+//! other rules (geo lookups, reputation services) plug in through
+//! [`Server::add_accept_filter`](crate::Server::add_accept_filter).
+//!
+//! Filters that judge only the remote address also screen QUIC connection
+//! attempts before the TLS handshake, so refused peers cost no handshake.
+//! The whole chain runs again once the handshake has identified the peer.
+//!
+//! During maintenance every connection is refused ahead of the chain until
+//! the maintenance window ends.
+//!
+//! The runtime writes each decision to [`AUDIT_LOG_TARGET`] as structured
+//! fields.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Instant,
+};
+
+/// Tracing target connection accept decisions are logged under.
+pub const AUDIT_LOG_TARGET: &str = "lockframe_server::audit";
+
+/// What a filter knows about a connecting peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// Remote address (`None` for in-process connections)
+    pub remote_addr: Option<SocketAddr>,
+    /// Principal from the client certificate, if mutual TLS is on
+    pub principal: Option<String>,
+}
+
+/// A filter's verdict on a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcceptDecision {
+    /// Let the connection through to the next filter
+    Accept,
+    /// Close the connection
+    Reject {
+        /// Why, for the audit log and the client
+        reason: String,
+    },
+}
+
+/// A rule connections must pass before a session is registered.
+pub trait AcceptFilter: Send {
+    /// Name used in audit records.
+    fn name(&self) -> &'static str;
+
+    /// Decide whether `peer` may connect.
+    fn check(&mut self, peer: &PeerInfo) -> AcceptDecision;
+
+    /// Whether [`check`](Self::check) looks at nothing but the remote
+    /// address, so it can run before the handshake, without a principal.
+    fn address_only(&self) -> bool {
+        false
+    }
+
+    /// Every filter in the chain accepted `peer`.
+    fn opened(&mut self, _peer: &PeerInfo) {}
+
+    /// A connection [`opened`](Self::opened) for `peer` closed.
+    fn closed(&mut self, _peer: &PeerInfo) {}
+}
+
+/// A connection refused by the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcceptRejection {
+    /// Filter that refused it
+    pub filter: &'static str,
+    /// Why
+    pub reason: String,
+}
+
+/// Built-in filters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AcceptConfig {
+    /// Only addresses in these ranges may connect (any if empty)
+    pub allow: Vec<IpRange>,
+    /// Addresses in these ranges may not connect, even if allowed
+    pub deny: Vec<IpRange>,
+    /// Most open connections from one address (unlimited if omitted)
+    pub max_per_ip: Option<usize>,
+}
+
+/// Filters run in order on every accepted connection.
+#[derive(Default)]
+pub struct AcceptFilters {
+    filters: Vec<Box<dyn AcceptFilter>>,
+    /// Refuse everything until then, and why
+    refusing: Option<(Instant, String)>,
+}
+
+impl AcceptFilters {
+    /// Chain with the built-in filters `config` turns on.
+    pub fn from_config(config: &AcceptConfig) -> Self {
+        let mut filters = Self::default();
+        if !config.allow.is_empty() || !config.deny.is_empty() {
+            filters.push(IpAccessList::new(config.allow.clone(), config.deny.clone()));
+        }
+        if let Some(max) = config.max_per_ip {
+            filters.push(PerIpLimit::new(max));
+        }
+        filters
+    }
+
+    /// Append a filter, run after those already in the chain.
+    pub fn push(&mut self, filter: impl AcceptFilter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Refuse every connection until `until`, without running the chain.
+    pub fn refuse_until(&mut self, until: Instant, reason: String) {
+        self.refusing = Some((until, reason));
+    }
+
+    /// Run the address-only filters on a connection attempt from `remote`,
+    /// before its handshake. Nothing is counted as opened.
+    pub fn screen(&mut self, remote: SocketAddr) -> Result<(), AcceptRejection> {
+        self.check_maintenance()?;
+        let peer = PeerInfo { remote_addr: Some(remote), principal: None };
+        for filter in self.filters.iter_mut().filter(|filter| filter.address_only()) {
+            if let AcceptDecision::Reject { reason } = filter.check(&peer) {
+                return Err(AcceptRejection { filter: filter.name(), reason });
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the chain, stopping at the first filter that refuses. Filters are
+    /// told the connection opened only if all of them accepted it.
+    pub fn check(&mut self, peer: &PeerInfo) -> Result<(), AcceptRejection> {
+        self.check_maintenance()?;
+        for filter in &mut self.filters {
+            if let AcceptDecision::Reject { reason } = filter.check(peer) {
+                return Err(AcceptRejection { filter: filter.name(), reason });
+            }
+        }
+        for filter in &mut self.filters {
+            filter.opened(peer);
+        }
+        Ok(())
+    }
+
+    /// An accepted connection closed.
+    pub fn closed(&mut self, peer: &PeerInfo) {
+        for filter in &mut self.filters {
+            filter.closed(peer);
+        }
+    }
+
+    fn check_maintenance(&mut self) -> Result<(), AcceptRejection> {
+        if let Some((until, reason)) = &self.refusing {
+            if Instant::now() < *until {
+                return Err(AcceptRejection { filter: "maintenance", reason: reason.clone() });
+            }
+            self.refusing = None;
+        }
+        Ok(())
+    }
+}
+
+/// An address range in CIDR notation, e.g. `10.0.0.0/8` or `2001:db8::/32`.
+/// A bare address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Whether `ip` is in the range. IPv4-mapped IPv6 addresses match IPv4
+    /// ranges.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_matches(
+                u128::from(u32::from(net)),
+                u128::from(u32::from(ip)),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix_len)
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Whether the top `prefix_len` of `bits` address bits agree.
+fn prefix_matches(net: u128, ip: u128, bits: u8, prefix_len: u8) -> bool {
+    let shift = u32::from(bits.saturating_sub(prefix_len));
+    net.checked_shr(shift).unwrap_or(0) == ip.checked_shr(shift).unwrap_or(0)
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    /// Parse `ADDR/PREFIX` or a bare `ADDR`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = value.split_once('/').map_or((value, None), |(a, p)| (a, Some(p)));
+        let addr: IpAddr =
+            addr.parse().map_err(|e| format!("invalid address in {value:?}: {e}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max)
+                .ok_or_else(|| format!("invalid prefix length in {value:?}"))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Address allow and deny lists. In-process connections have no address and
+/// always pass.
+#[derive(Debug, Clone)]
+pub struct IpAccessList {
+    allow: Vec<IpRange>,
+    deny: Vec<IpRange>,
+}
+
+impl IpAccessList {
+    /// Create a list. An empty `allow` admits every address not denied.
+    pub fn new(allow: Vec<IpRange>, deny: Vec<IpRange>) -> Self {
+        Self { allow, deny }
+    }
+}
+
+impl AcceptFilter for IpAccessList {
+    fn name(&self) -> &'static str {
+        "ip_access_list"
+    }
+
+    fn address_only(&self) -> bool {
+        true
+    }
+
+    fn check(&mut self, peer: &PeerInfo) -> AcceptDecision {
+        let Some(ip) = peer.remote_addr.map(|addr| addr.ip()) else {
+            return AcceptDecision::Accept;
+        };
+        if let Some(range) = self.deny.iter().find(|range| range.contains(ip)) {
+            return AcceptDecision::Reject { reason: format!("{ip} is denied by {range}") };
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|range| range.contains(ip)) {
+            return AcceptDecision::Reject { reason: format!("{ip} is not allowed") };
+        }
+        AcceptDecision::Accept
+    }
+}
+
+/// Cap on open connections from one address.
+#[derive(Debug, Clone)]
+pub struct PerIpLimit {
+    max: usize,
+    open: HashMap<IpAddr, usize>,
+}
+
+impl PerIpLimit {
+    /// Allow at most `max` open connections per address.
+    pub fn new(max: usize) -> Self {
+        Self { max, open: HashMap::new() }
+    }
+}
+
+impl AcceptFilter for PerIpLimit {
+    fn name(&self) -> &'static str {
+        "per_ip_limit"
+    }
+
+    fn address_only(&self) -> bool {
+        true
+    }
+
+    fn check(&mut self, peer: &PeerInfo) -> AcceptDecision {
+        let Some(ip) = peer.remote_addr.map(|addr| addr.ip()) else {
+            return AcceptDecision::Accept;
+        };
+        if self.open.get(&ip).copied().unwrap_or(0) >= self.max {
+            return AcceptDecision::Reject {
+                reason: format!("{ip} already has {} open connections", self.max),
+            };
+        }
+        AcceptDecision::Accept
+    }
+
+    fn opened(&mut self, peer: &PeerInfo) {
+        if let Some(addr) = peer.remote_addr {
+            let open = self.open.entry(addr.ip()).or_default();
+            *open = open.saturating_add(1);
+        }
+    }
+
+    fn closed(&mut self, peer: &PeerInfo) {
+        let Some(addr) = peer.remote_addr else { return };
+        if let Some(open) = self.open.get_mut(&addr.ip()) {
+            *open = open.saturating_sub(1);
+            if *open == 0 {
+                self.open.remove(&addr.ip());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(addr: &str) -> PeerInfo {
+        PeerInfo { remote_addr: Some(addr.parse().unwrap()), principal: None }
+    }
+
+    #[test]
+    fn ranges_match_by_prefix() {
+        let net: IpRange = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains("10.1.200.3".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.0.1".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(!net.contains("2001:db8::1".parse().unwrap()));
+
+        let any: IpRange = "::/0".parse().unwrap();
+        assert!(any.contains("2001:db8::1".parse().unwrap()));
+        assert_eq!("192.0.2.7".parse::<IpRange>().unwrap().to_string(), "192.0.2.7/32");
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("example.com".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn deny_list_wins_over_allow_list() {
+        let config = AcceptConfig {
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.9.0.0/16".parse().unwrap()],
+            max_per_ip: None,
+        };
+        let mut filters = AcceptFilters::from_config(&config);
+
+        assert!(filters.check(&peer("10.1.0.1:4000")).is_ok());
+        let denied = filters.check(&peer("10.9.0.1:4000")).unwrap_err();
+        assert_eq!(denied.filter, "ip_access_list");
+        assert!(filters.check(&peer("192.0.2.1:4000")).is_err());
+        assert!(filters.check(&PeerInfo { remote_addr: None, principal: None }).is_ok());
+    }
+
+    #[test]
+    fn per_ip_limit_frees_slots_on_close() {
+        let config = AcceptConfig { max_per_ip: Some(2), ..AcceptConfig::default() };
+        let mut filters = AcceptFilters::from_config(&config);
+        let first = peer("192.0.2.1:4000");
+        let second = peer("192.0.2.1:4001");
+
+        assert!(filters.check(&first).is_ok());
+        assert!(filters.check(&second).is_ok());
+        let refused = filters.check(&peer("192.0.2.1:4002")).unwrap_err();
+        assert_eq!(refused.filter, "per_ip_limit");
+        assert!(filters.check(&peer("192.0.2.2:4000")).is_ok());
+
+        filters.closed(&first);
+        assert!(filters.check(&peer("192.0.2.1:4002")).is_ok());
+    }
+
+    #[test]
+    fn rejected_connections_do_not_count_against_the_limit() {
+        struct RejectAll;
+
+        impl AcceptFilter for RejectAll {
+            fn name(&self) -> &'static str {
+                "reject_all"
+            }
+
+            fn check(&mut self, _peer: &PeerInfo) -> AcceptDecision {
+                AcceptDecision::Reject { reason: "closed for maintenance".to_string() }
+            }
+        }
+
+        let first = peer("192.0.2.1:4000");
+        let mut filters = AcceptFilters::default();
+        filters.push(PerIpLimit::new(1));
+        filters.push(RejectAll);
+
+        assert_eq!(filters.check(&first).unwrap_err().filter, "reject_all");
+        assert_eq!(filters.filters[0].check(&first), AcceptDecision::Accept);
+    }
+    #[test]
+    fn screening_runs_only_address_filters() {
+        struct NeedsPrincipal;
+
+        impl AcceptFilter for NeedsPrincipal {
+            fn name(&self) -> &'static str {
+                "needs_principal"
+            }
+
+            fn check(&mut self, peer: &PeerInfo) -> AcceptDecision {
+                if peer.principal.is_some() {
+                    AcceptDecision::Accept
+                } else {
+                    AcceptDecision::Reject { reason: "no client certificate".to_string() }
+                }
+            }
+        }
+
+        let config = AcceptConfig {
+            deny: vec!["10.9.0.0/16".parse().unwrap()],
+            max_per_ip: Some(1),
+            ..AcceptConfig::default()
+        };
+        let mut filters = AcceptFilters::from_config(&config);
+        filters.push(NeedsPrincipal);
+
+        let denied = filters.screen("10.9.0.1:4000".parse().unwrap()).unwrap_err();
+        assert_eq!(denied.filter, "ip_access_list");
+        assert!(filters.screen("192.0.2.1:4000".parse().unwrap()).is_ok());
+        // Screening opens nothing, so the per-address slot is still free
+        assert!(filters.screen("192.0.2.1:4001".parse().unwrap()).is_ok());
+        assert_eq!(filters.check(&peer("192.0.2.1:4000")).unwrap_err().filter, "needs_principal");
+    }
+
+    #[test]
+    fn maintenance_refuses_until_it_ends() {
+        let mut filters = AcceptFilters::default();
+        filters.refuse_until(Instant::now() + std::time::Duration::from_secs(60), "upgrade".into());
+        let refused = filters.check(&peer("192.0.2.1:4000")).unwrap_err();
+        assert_eq!((refused.filter, refused.reason.as_str()), ("maintenance", "upgrade"));
+
+        filters.refuse_until(Instant::now(), "upgrade".into());
+        assert!(filters.check(&peer("192.0.2.1:4000")).is_ok());
+    }
+}
@@ -0,0 +1,219 @@
+//! Accounts and self-service session revocation.
+//!
+//! Sessions authenticated as the same principal belong to one account: the
+//! client certificate principal the transport verified, or the identity key
+//! the client proved in the challenge-response handshake. Accounts are keyed
+//! by the principal's SHA-256 hash. A bare `auth_token` in a Hello proves
+//! nothing and names no account.
+//!
+//! Member IDs in frame headers are the client's claim. A member is bound to
+//! the first account that acts as it, and from then on sessions of any other
+//! account, or of none, are refused when they act as it. The binding
+//! outlives the connection, so a device stays revocable after its
+//! connection is gone, which is the usual case after device loss.
+//!
+//! An account only ever revokes its own members. Revoking a member is
+//! permanent. The driver closes the sessions acting as
+//! it, drops its offline queues (the only way a reconnecting device resumes
+//! without a sync round), and closes any later session that acts as it.
+//! Removing the member from its rooms, and rotating their keys, is a commit
+//! the requesting device makes once the server confirms the revocation.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use sha2::{Digest, Sha256};
+
+/// Account identifier: SHA-256 of the account's authenticated principal.
+pub type AccountId = [u8; 32];
+
+/// Outcome of a revocation request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revocation {
+    /// Members revoked, ascending
+    pub member_ids: Vec<u64>,
+    /// Sessions to close, ascending
+    pub sessions: Vec<u64>,
+}
+
+/// Accounts, the members their sessions act as, and revoked members.
+#[derive(Debug, Default)]
+pub struct Accounts {
+    /// Session ID → account
+    sessions: HashMap<u64, AccountId>,
+    /// Account → members bound to it
+    members: HashMap<AccountId, BTreeSet<u64>>,
+    /// Member → account it is bound to
+    owners: HashMap<u64, AccountId>,
+    /// Session ID → members the session acted as
+    session_members: HashMap<u64, HashSet<u64>>,
+    /// Members that may no longer be acted as
+    revoked: HashSet<u64>,
+}
+
+impl Accounts {
+    /// Create an empty account table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach `session_id` to the account of `principal`, an identity the
+    /// session authenticated as.
+    pub fn register(&mut self, session_id: u64, principal: &[u8]) {
+        let account: AccountId = Sha256::digest(principal).into();
+        self.sessions.insert(session_id, account);
+        self.members.entry(account).or_default();
+    }
+
+    /// Account of a session. `None` if it authenticated as no principal.
+    pub fn account(&self, session_id: u64) -> Option<&AccountId> {
+        self.sessions.get(&session_id)
+    }
+
+    /// Record that `session_id` acts as `member_id`, binding an unbound
+    /// member to the session's account.
+    ///
+    /// Returns `false`, recording nothing, if the member is bound to an
+    /// account the session does not belong to.
+    pub fn record(&mut self, session_id: u64, member_id: u64) -> bool {
+        let account = self.sessions.get(&session_id).copied();
+        match (self.owners.get(&member_id), account) {
+            (Some(owner), Some(account)) if *owner == account => {},
+            (Some(_), _) => return false,
+            (None, Some(account)) => {
+                self.owners.insert(member_id, account);
+                self.members.entry(account).or_default().insert(member_id);
+            },
+            (None, None) => {},
+        }
+        self.session_members.entry(session_id).or_default().insert(member_id);
+        true
+    }
+
+    /// Whether `member_id` is bound to the account of `session_id`.
+    pub fn owns(&self, session_id: u64, member_id: u64) -> bool {
+        self.sessions
+            .get(&session_id)
+            .is_some_and(|account| self.owners.get(&member_id) == Some(account))
+    }
+
+    /// Whether `member_id` was revoked.
+    pub fn is_revoked(&self, member_id: u64) -> bool {
+        self.revoked.contains(&member_id)
+    }
+
+    /// Forget a closed session. Its account keeps the members bound to it.
+    pub fn remove_session(&mut self, session_id: u64) {
+        self.sessions.remove(&session_id);
+        self.session_members.remove(&session_id);
+    }
+
+    /// Revoke members of the account `session_id` belongs to.
+    ///
+    /// `keep` is the member the requesting device acts as and is never
+    /// revoked. An empty `member_ids` revokes every other member bound to the
+    /// account and closes every other session of it; otherwise only the
+    /// listed members bound to the account are revoked, and only the
+    /// account's sessions that acted as them are closed. Returns `None` if
+    /// the session has no account.
+    pub fn revoke(&mut self, session_id: u64, keep: u64, member_ids: &[u64]) -> Option<Revocation> {
+        let account = *self.sessions.get(&session_id)?;
+        let known = self.members.entry(account).or_default();
+
+        let targets: BTreeSet<u64> = known
+            .iter()
+            .copied()
+            .filter(|member| *member != keep)
+            .filter(|member| member_ids.is_empty() || member_ids.contains(member))
+            .collect();
+        known.retain(|member| !targets.contains(member));
+        self.revoked.extend(&targets);
+
+        let acted_as_target = |session: &u64| {
+            self.session_members
+                .get(session)
+                .is_some_and(|acted| acted.iter().any(|member| targets.contains(member)))
+        };
+        let mut sessions: Vec<u64> = self
+            .sessions
+            .iter()
+            .filter(|&(other, other_account)| *other != session_id && *other_account == account)
+            .map(|(&other, _)| other)
+            .filter(|other| member_ids.is_empty() || acted_as_target(other))
+            .collect();
+        sessions.sort_unstable();
+
+        Some(Revocation { member_ids: targets.into_iter().collect(), sessions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revokes_other_devices_of_the_account() {
+        let mut accounts = Accounts::new();
+        accounts.register(1, b"sha256:alice");
+        accounts.register(2, b"sha256:alice");
+        accounts.register(3, b"sha256:bob");
+        assert!(accounts.record(1, 10));
+        assert!(accounts.record(2, 20));
+        assert!(accounts.record(3, 30));
+
+        // A lost device that is no longer connected
+        accounts.register(4, b"sha256:alice");
+        accounts.record(4, 40);
+        accounts.remove_session(4);
+
+        let revocation = accounts.revoke(1, 10, &[]).unwrap();
+        assert_eq!(revocation, Revocation { member_ids: vec![20, 40], sessions: vec![2] });
+        assert!(accounts.is_revoked(40));
+        assert!(!accounts.is_revoked(10));
+        assert!(!accounts.is_revoked(30));
+
+        // Nothing left to revoke
+        assert_eq!(accounts.revoke(1, 10, &[]).unwrap().member_ids, Vec::<u64>::new());
+    }
+
+    #[test]
+    fn targeted_revocation_stays_within_the_account() {
+        let mut accounts = Accounts::new();
+        accounts.register(1, b"sha256:alice");
+        accounts.register(2, b"sha256:alice");
+        accounts.register(3, b"sha256:alice");
+        accounts.register(4, b"sha256:bob");
+        accounts.record(1, 10);
+        accounts.record(2, 20);
+        accounts.record(3, 30);
+        accounts.record(4, 40);
+
+        // Bob's member is not Alice's to revoke
+        let revocation = accounts.revoke(1, 10, &[20, 40]).unwrap();
+        assert_eq!(revocation, Revocation { member_ids: vec![20], sessions: vec![2] });
+        assert!(!accounts.is_revoked(40));
+
+        // Sessions without a principal have no account
+        assert!(accounts.record(5, 50));
+        assert_eq!(accounts.revoke(5, 50, &[]), None);
+    }
+
+    #[test]
+    fn members_stay_bound_to_their_account() {
+        let mut accounts = Accounts::new();
+        accounts.register(1, b"sha256:alice");
+        accounts.register(2, b"sha256:mallory");
+        assert!(accounts.record(1, 10));
+        assert!(accounts.owns(1, 10));
+
+        // Claiming Alice's member in a header gets another account nowhere
+        assert!(!accounts.record(2, 10));
+        assert!(!accounts.owns(2, 10));
+        assert!(!accounts.record(3, 10));
+        assert_eq!(accounts.revoke(2, 20, &[10]).unwrap().member_ids, Vec::<u64>::new());
+        assert!(!accounts.is_revoked(10));
+
+        // Unbound members may still be acted as without an account
+        assert!(accounts.record(3, 30));
+        assert!(!accounts.owns(3, 30));
+    }
+}
@@ -0,0 +1,671 @@
+//! Admin HTTP API.
+//!
+//! An optional listener, off unless
+//! [`ServerRuntimeConfig::admin_bind`](crate::ServerRuntimeConfig::admin_bind)
+//! is set, that answers operators with JSON:
+//!
+//! - `GET /rooms`: hosted rooms with their epoch, members and log length
+//! - `GET /sessions`: connected sessions and the rooms they are subscribed to
+//! - `GET /storage`: frames stored, bytes on disk and vacuum counters
+//! - `GET /audit?from={seq}&limit={n}`: audit records from `seq` on, oldest
+//!   first, see [`AuditRecord`]
+//! - `GET /usage`: per-room usage counted in the open billing window
+//! - `POST /usage/close`: close the open billing window and return its usage,
+//!   see [`ServerHandle::close_usage_window`](crate::ServerHandle::close_usage_window)
+//! - `POST /sessions/{id}/close`: close a session
+//! - `POST /rooms/{id}/publish?name={name}&description={text}`: list a room in
+//!   the room directory, or update its listing, see
+//!   [`ServerHandle::publish_room`](crate::ServerHandle::publish_room)
+//! - `POST /rooms/{id}/unlist`: remove a room from the room directory
+//! - `POST /maintenance?secs={n}`: refuse connections for `n` seconds, close
+//!   every session and flush storage, see
+//!   [`ServerHandle::enter_maintenance`](crate::ServerHandle::enter_maintenance)
+//!
+//! Like the archive client, this is a deliberately small HTTP/1.1
+//! implementation: one request per connection, request bodies are ignored,
+//! and there is no authentication. Bind it to a loopback or management
+//! address only.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    MaintenanceReport,
+    audit::{AuditEvent, AuditRecord},
+    directory::Listing,
+    error::ServerError,
+    usage::RoomUsage,
+    vacuum::VacuumMetrics,
+};
+
+/// Longest a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most bytes read while looking for the end of the request head.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// Audit records returned when the request gives no `limit`.
+const DEFAULT_AUDIT_PAGE: usize = 100;
+
+/// Most audit records returned for one request.
+const MAX_AUDIT_PAGE: usize = 1000;
+
+/// Admin API listener and the files whose size `GET /storage` reports.
+pub struct AdminListener {
+    listener: TcpListener,
+    storage_paths: Vec<PathBuf>,
+}
+
+impl AdminListener {
+    /// Bind the admin API to `address`.
+    pub async fn bind(address: &str, storage_paths: Vec<PathBuf>) -> Result<Self, ServerError> {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| ServerError::Config(format!("failed to bind admin API: {e}")))?;
+        Ok(Self { listener, storage_paths })
+    }
+
+    /// Address the admin API listens on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    /// Wait for the next client.
+    pub async fn accept(&self) -> std::io::Result<TcpStream> {
+        self.listener.accept().await.map(|(stream, _)| stream)
+    }
+
+    /// Files and directories holding the server's storage.
+    pub fn storage_paths(&self) -> &[PathBuf] {
+        &self.storage_paths
+    }
+}
+
+/// A request the admin API serves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminRequest {
+    /// `GET /rooms`
+    Rooms,
+    /// `GET /sessions`
+    Sessions,
+    /// `GET /storage`
+    Storage,
+    /// `GET /audit?from={seq}&limit={n}`
+    Audit {
+        /// First sequence number to return
+        from_seq: u64,
+        /// Most records to return
+        limit: usize,
+    },
+    /// `GET /usage`
+    Usage,
+    /// `POST /usage/close`
+    CloseUsage,
+    /// `POST /sessions/{id}/close`
+    CloseSession(u64),
+    /// `POST /maintenance?secs={n}`
+    Maintenance(Duration),
+    /// `POST /rooms/{id}/publish?name={name}&description={text}`
+    PublishRoom {
+        /// Room to list
+        room_id: u128,
+        /// What the directory shows for it
+        listing: Listing,
+    },
+    /// `POST /rooms/{id}/unlist`
+    UnlistRoom(u128),
+}
+
+/// Status line and JSON body sent back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminResponse {
+    /// Status code and reason, e.g. `200 OK`
+    pub status: &'static str,
+    /// JSON body
+    pub body: String,
+}
+
+impl AdminResponse {
+    /// `200 OK` with `body`.
+    pub fn ok(body: String) -> Self {
+        Self { status: "200 OK", body }
+    }
+
+    /// An error status with `message` as the body's `error` field.
+    pub fn error(status: &'static str, message: &str) -> Self {
+        Self { status, body: format!("{{\"error\":{}}}", json_string(message)) }
+    }
+}
+
+/// A room as listed by `GET /rooms`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomSummary {
+    /// Room ID
+    pub room_id: u128,
+    /// Current MLS epoch
+    pub epoch: u64,
+    /// Members of the MLS group
+    pub members: usize,
+    /// Frames sequenced, including any compacted away
+    pub frames: u64,
+    /// Sessions subscribed to the room
+    pub sessions: usize,
+}
+
+/// A session as listed by `GET /sessions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    /// Session ID
+    pub session_id: u64,
+    /// Whether the handshake completed
+    pub authenticated: bool,
+    /// User the session authenticated as
+    pub user_id: Option<u64>,
+    /// Identity the transport authenticated, such as a client certificate
+    pub principal: Option<String>,
+    /// Rooms the session is subscribed to
+    pub rooms: Vec<u128>,
+}
+
+/// Storage as reported by `GET /storage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageSummary {
+    /// Rooms hosted
+    pub rooms: usize,
+    /// Frames sequenced across all rooms
+    pub frames: u64,
+    /// Bytes the storage files take on disk (`None` for in-memory storage)
+    pub disk_bytes: Option<u64>,
+    /// Vacuum passes
+    pub vacuum: VacuumMetrics,
+}
+
+/// Read a request head from `stream` and work out what it asks for.
+pub async fn read_request(stream: &mut TcpStream) -> Result<AdminRequest, AdminResponse> {
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(stream))
+        .await
+        .map_err(|_| AdminResponse::error("408 Request Timeout", "request timed out"))??;
+    parse_request(&head)
+}
+
+async fn read_head(stream: &mut TcpStream) -> Result<String, AdminResponse> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_HEAD {
+            return Err(AdminResponse::error(
+                "431 Request Header Fields Too Large",
+                "head too large",
+            ));
+        }
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|e| AdminResponse::error("400 Bad Request", &e.to_string()))?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(chunk.get(..read).unwrap_or_default());
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Route a request head to what it asks for.
+pub fn parse_request(head: &str) -> Result<AdminRequest, AdminResponse> {
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(AdminResponse::error("400 Bad Request", "malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let (expected, request) = match path.trim_end_matches('/') {
+        "/rooms" => ("GET", AdminRequest::Rooms),
+        "/sessions" => ("GET", AdminRequest::Sessions),
+        "/storage" => ("GET", AdminRequest::Storage),
+        "/usage" => ("GET", AdminRequest::Usage),
+        "/usage/close" => ("POST", AdminRequest::CloseUsage),
+        "/audit" => {
+            let from_seq = query_param(query, "from").unwrap_or(0);
+            let limit = query_param(query, "limit").unwrap_or(DEFAULT_AUDIT_PAGE);
+            ("GET", AdminRequest::Audit { from_seq, limit: limit.min(MAX_AUDIT_PAGE) })
+        },
+        "/maintenance" => {
+            let secs = query_param(query, "secs")
+                .ok_or_else(|| AdminResponse::error("400 Bad Request", "secs is required"))?;
+            ("POST", AdminRequest::Maintenance(Duration::from_secs(secs)))
+        },
+        other => route_by_id(other, query)?,
+    };
+
+    if method != expected {
+        return Err(AdminResponse::error("405 Method Not Allowed", &format!("use {expected}")));
+    }
+    Ok(request)
+}
+
+/// Route a path naming a session or room, such as `/sessions/{id}/close`.
+fn route_by_id(path: &str, query: &str) -> Result<(&'static str, AdminRequest), AdminResponse> {
+    let not_found = || AdminResponse::error("404 Not Found", "no such endpoint");
+    if let Some(rest) = path.strip_prefix("/sessions/") {
+        let session_id =
+            rest.strip_suffix("/close").and_then(|id| id.parse().ok()).ok_or_else(not_found)?;
+        return Ok(("POST", AdminRequest::CloseSession(session_id)));
+    }
+
+    // Room IDs are written in hex, as `GET /rooms` lists them
+    let (id, action) =
+        path.strip_prefix("/rooms/").and_then(|rest| rest.split_once('/')).ok_or_else(not_found)?;
+    let room_id = u128::from_str_radix(id, 16).map_err(|_| not_found())?;
+    match action {
+        "publish" => {
+            let name = query_text(query, "name")
+                .ok_or_else(|| AdminResponse::error("400 Bad Request", "name is required"))?;
+            let description = query_text(query, "description").unwrap_or_default();
+            let listing = Listing { name, description };
+            Ok(("POST", AdminRequest::PublishRoom { room_id, listing }))
+        },
+        "unlist" => Ok(("POST", AdminRequest::UnlistRoom(room_id))),
+        _ => Err(not_found()),
+    }
+}
+
+/// Value of `name` in a query string, percent-decoded, if present and
+/// valid UTF-8.
+fn query_text(query: &str, name: &str) -> Option<String> {
+    let (_, value) =
+        query.split('&').filter_map(|pair| pair.split_once('=')).find(|(key, _)| *key == name)?;
+
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = std::str::from_utf8(rest.get(..2)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = rest.get(2..)?;
+            },
+            byte => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Value of `name` in a query string, if present and parseable.
+fn query_param<T: std::str::FromStr>(query: &str, name: &str) -> Option<T> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key == name { value.parse().ok() } else { None }
+    })
+}
+
+/// Write `response` and close the connection.
+pub async fn write_response(
+    stream: &mut TcpStream,
+    response: &AdminResponse,
+) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        response.status,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// `GET /rooms` body.
+pub fn rooms_json(rooms: &[RoomSummary]) -> String {
+    json_array(rooms, |json, room| {
+        let _ = write!(
+            json,
+            "{{\"room_id\":\"{:032x}\",\"epoch\":{},\"members\":{},\"frames\":{},\"sessions\":{}}}",
+            room.room_id, room.epoch, room.members, room.frames, room.sessions
+        );
+    })
+}
+
+/// `GET /sessions` body.
+pub fn sessions_json(sessions: &[SessionSummary]) -> String {
+    json_array(sessions, |json, session| {
+        let _ = write!(
+            json,
+            "{{\"session_id\":{},\"authenticated\":{},\"user_id\":{},\"principal\":{},\"rooms\":",
+            session.session_id,
+            session.authenticated,
+            session.user_id.map_or_else(|| "null".to_string(), |id| id.to_string()),
+            session.principal.as_deref().map_or_else(|| "null".to_string(), json_string),
+        );
+        json.push_str(&json_array(&session.rooms, |json, room_id| {
+            let _ = write!(json, "\"{room_id:032x}\"");
+        }));
+        json.push('}');
+    })
+}
+
+/// `GET /audit` body.
+pub fn audit_json(records: &[AuditRecord]) -> String {
+    json_array(records, |json, record| {
+        let _ = write!(
+            json,
+            "{{\"seq\":{},\"at_millis\":{},\"principal\":{},\"kind\":\"{}\",",
+            record.seq,
+            record.at_millis,
+            record.principal.as_deref().map_or_else(|| "null".to_string(), json_string),
+            record.event.kind()
+        );
+        let _ = match &record.event {
+            AuditEvent::RoomCreated { room_id, creator } => {
+                write!(json, "\"room_id\":\"{room_id:032x}\",\"creator\":{creator}}}")
+            },
+            AuditEvent::MemberAdded { room_id, member_id, epoch }
+            | AuditEvent::MemberRemoved { room_id, member_id, epoch } => write!(
+                json,
+                "\"room_id\":\"{room_id:032x}\",\"member_id\":{member_id},\"epoch\":{epoch}}}"
+            ),
+            AuditEvent::FrameRejected { room_id, sender_id, reason } => write!(
+                json,
+                "\"room_id\":\"{room_id:032x}\",\"sender_id\":{sender_id},\"reason\":{}}}",
+                json_string(reason)
+            ),
+            AuditEvent::ConnectionClosed { session_id, reason } => {
+                write!(json, "\"session_id\":{session_id},\"reason\":{}}}", json_string(reason))
+            },
+        };
+    })
+}
+
+/// `GET /usage` and `POST /usage/close` body, `ended_at_millis` being
+/// `None` for the open window.
+pub fn usage_json(
+    started_at_millis: u64,
+    ended_at_millis: Option<u64>,
+    rooms: &BTreeMap<u128, RoomUsage>,
+) -> String {
+    let rooms: Vec<_> = rooms.iter().collect();
+    format!(
+        "{{\"started_at_millis\":{started_at_millis},\"ended_at_millis\":{},\"rooms\":{}}}",
+        ended_at_millis.map_or_else(|| "null".to_string(), |millis| millis.to_string()),
+        json_array(&rooms, |json, (room_id, usage)| {
+            let _ = write!(
+                json,
+                "{{\"room_id\":\"{room_id:032x}\",\"messages\":{},\"bytes_stored\":{},\
+                 \"peak_members\":{},\"sync_bytes_served\":{},\"last_log_index\":{}}}",
+                usage.messages,
+                usage.bytes_stored,
+                usage.peak_members,
+                usage.sync_bytes_served,
+                usage.last_log_index.map_or_else(|| "null".to_string(), |index| index.to_string()),
+            );
+        })
+    )
+}
+
+/// `POST /rooms/{id}/publish` body.
+pub fn listing_json(room_id: u128, listing: &Listing) -> String {
+    format!(
+        "{{\"room_id\":\"{room_id:032x}\",\"name\":{},\"description\":{}}}",
+        json_string(&listing.name),
+        json_string(&listing.description)
+    )
+}
+
+/// `POST /maintenance` body.
+pub fn maintenance_json(report: &MaintenanceReport) -> String {
+    format!(
+        "{{\"sessions_closed\":{},\"drained\":{},\"duration_secs\":{}}}",
+        report.sessions_closed,
+        report.drained,
+        report.duration.as_secs()
+    )
+}
+
+/// `GET /storage` body.
+pub fn storage_json(storage: &StorageSummary) -> String {
+    let vacuum = &storage.vacuum;
+    format!(
+        "{{\"rooms\":{},\"frames\":{},\"disk_bytes\":{},\"vacuum\":{{\"passes\":{},\
+         \"skipped\":{},\"failures\":{},\"bytes_reclaimed\":{}}}}}",
+        storage.rooms,
+        storage.frames,
+        storage.disk_bytes.map_or_else(|| "null".to_string(), |bytes| bytes.to_string()),
+        vacuum.passes,
+        vacuum.skipped,
+        vacuum.failures,
+        vacuum.bytes_reclaimed,
+    )
+}
+
+fn json_array<T>(items: &[T], mut write_item: impl FnMut(&mut String, &T)) -> String {
+    let mut json = String::from("[");
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write_item(&mut json, item);
+    }
+    json.push(']');
+    json
+}
+
+/// `value` as a quoted JSON string.
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len().saturating_add(2));
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", u32::from(c));
+            },
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Bytes the files under `paths` take up, `None` if there are no paths.
+/// Missing paths count as empty.
+pub fn disk_usage(paths: &[PathBuf]) -> Option<u64> {
+    if paths.is_empty() {
+        return None;
+    }
+    Some(paths.iter().fold(0u64, |total, path| total.saturating_add(path_bytes(path))))
+}
+
+fn path_bytes(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else { return 0 };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path).map_or(0, |entries| {
+        entries
+            .filter_map(Result::ok)
+            .fold(0u64, |total, entry| total.saturating_add(path_bytes(&entry.path())))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(head: &str) -> &'static str {
+        parse_request(head).unwrap_err().status
+    }
+
+    #[test]
+    fn requests_are_routed_by_method_and_path() {
+        assert_eq!(parse_request("GET /rooms HTTP/1.1\r\n\r\n"), Ok(AdminRequest::Rooms));
+        assert_eq!(parse_request("GET /sessions/?all HTTP/1.1\r\n"), Ok(AdminRequest::Sessions));
+        assert_eq!(
+            parse_request("POST /sessions/42/close HTTP/1.1\r\n"),
+            Ok(AdminRequest::CloseSession(42))
+        );
+
+        assert_eq!(parse_request("GET /usage HTTP/1.1\r\n"), Ok(AdminRequest::Usage));
+        assert_eq!(parse_request("POST /usage/close HTTP/1.1\r\n"), Ok(AdminRequest::CloseUsage));
+
+        assert_eq!(
+            parse_request("POST /maintenance?secs=300 HTTP/1.1\r\n"),
+            Ok(AdminRequest::Maintenance(Duration::from_secs(300)))
+        );
+
+        assert_eq!(
+            parse_request("GET /audit?limit=5000&from=7 HTTP/1.1\r\n"),
+            Ok(AdminRequest::Audit { from_seq: 7, limit: MAX_AUDIT_PAGE })
+        );
+        assert_eq!(
+            parse_request("GET /audit HTTP/1.1\r\n"),
+            Ok(AdminRequest::Audit { from_seq: 0, limit: DEFAULT_AUDIT_PAGE })
+        );
+
+        assert_eq!(
+            parse_request(
+                "POST /rooms/ab/publish?name=The+lobby&description=Say%20hi%21 HTTP/1.1\r\n"
+            ),
+            Ok(AdminRequest::PublishRoom {
+                room_id: 0xab,
+                listing: Listing {
+                    name: "The lobby".to_string(),
+                    description: "Say hi!".to_string()
+                },
+            })
+        );
+        assert_eq!(
+            parse_request("POST /rooms/00000000000000000000000000000ab/unlist HTTP/1.1\r\n"),
+            Ok(AdminRequest::UnlistRoom(0xab))
+        );
+
+        assert_eq!(status("POST /rooms/ab/publish HTTP/1.1\r\n"), "400 Bad Request");
+        assert_eq!(status("POST /rooms/ab/publish?name=%zz HTTP/1.1\r\n"), "400 Bad Request");
+        assert_eq!(status("GET /rooms/ab/unlist HTTP/1.1\r\n"), "405 Method Not Allowed");
+        assert_eq!(status("POST /rooms/xyz/unlist HTTP/1.1\r\n"), "404 Not Found");
+        assert_eq!(status("POST /maintenance HTTP/1.1\r\n"), "400 Bad Request");
+        assert_eq!(status("POST /rooms HTTP/1.1\r\n"), "405 Method Not Allowed");
+        assert_eq!(status("GET /sessions/42/close HTTP/1.1\r\n"), "405 Method Not Allowed");
+        assert_eq!(status("POST /sessions/abc/close HTTP/1.1\r\n"), "404 Not Found");
+        assert_eq!(status("GET /metrics HTTP/1.1\r\n"), "404 Not Found");
+        assert_eq!(status("\r\n"), "400 Bad Request");
+    }
+
+    #[test]
+    fn summaries_render_as_json() {
+        let rooms = [RoomSummary { room_id: 0xab, epoch: 3, members: 2, frames: 10, sessions: 1 }];
+        assert_eq!(
+            rooms_json(&rooms),
+            "[{\"room_id\":\"000000000000000000000000000000ab\",\"epoch\":3,\"members\":2,\
+             \"frames\":10,\"sessions\":1}]"
+        );
+
+        let sessions = [
+            SessionSummary {
+                session_id: 1,
+                authenticated: true,
+                user_id: Some(7),
+                principal: Some("ops \"team\"".to_string()),
+                rooms: vec![1, 2],
+            },
+            SessionSummary {
+                session_id: 2,
+                authenticated: false,
+                user_id: None,
+                principal: None,
+                rooms: vec![],
+            },
+        ];
+        assert_eq!(
+            sessions_json(&sessions),
+            "[{\"session_id\":1,\"authenticated\":true,\"user_id\":7,\
+             \"principal\":\"ops \\\"team\\\"\",\"rooms\":[\"00000000000000000000000000000001\",\
+             \"00000000000000000000000000000002\"]},{\"session_id\":2,\"authenticated\":false,\
+             \"user_id\":null,\"principal\":null,\"rooms\":[]}]"
+        );
+        assert_eq!(rooms_json(&[]), "[]");
+
+        let usage = BTreeMap::from([(0xab, RoomUsage {
+            messages: 2,
+            bytes_stored: 300,
+            peak_members: 3,
+            sync_bytes_served: 100,
+            last_log_index: Some(4),
+        })]);
+        assert_eq!(
+            usage_json(10, Some(20), &usage),
+            "{\"started_at_millis\":10,\"ended_at_millis\":20,\"rooms\":[{\"room_id\":\
+             \"000000000000000000000000000000ab\",\"messages\":2,\"bytes_stored\":300,\
+             \"peak_members\":3,\"sync_bytes_served\":100,\"last_log_index\":4}]}"
+        );
+        assert_eq!(
+            usage_json(10, None, &BTreeMap::new()),
+            "{\"started_at_millis\":10,\"ended_at_millis\":null,\"rooms\":[]}"
+        );
+        assert_eq!(
+            maintenance_json(&MaintenanceReport {
+                sessions_closed: 2,
+                drained: true,
+                duration: Duration::from_secs(60),
+            }),
+            "{\"sessions_closed\":2,\"drained\":true,\"duration_secs\":60}"
+        );
+        assert_eq!(
+            listing_json(0xab, &Listing {
+                name: "lobby".to_string(),
+                description: "say \"hi\"".to_string()
+            }),
+            format!(
+                "{{\"room_id\":\"{:032x}\",\"name\":\"lobby\",\"description\":\"say \\\"hi\\\"\"}}",
+                0xab
+            )
+        );
+
+        let audit = [
+            AuditRecord {
+                seq: 0,
+                at_millis: 10,
+                principal: Some("ops".to_string()),
+                event: AuditEvent::MemberAdded { room_id: 0xab, member_id: 2, epoch: 1 },
+            },
+            AuditRecord {
+                seq: 1,
+                at_millis: 20,
+                principal: None,
+                event: AuditEvent::ConnectionClosed { session_id: 3, reason: "idle".to_string() },
+            },
+        ];
+        assert_eq!(
+            audit_json(&audit),
+            "[{\"seq\":0,\"at_millis\":10,\"principal\":\"ops\",\"kind\":\"member_added\",\
+             \"room_id\":\"000000000000000000000000000000ab\",\"member_id\":2,\"epoch\":1},\
+             {\"seq\":1,\"at_millis\":20,\"principal\":null,\"kind\":\"connection_closed\",\
+             \"session_id\":3,\"reason\":\"idle\"}]"
+        );
+    }
+
+    #[test]
+    fn disk_usage_sums_files_under_each_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("db")).unwrap();
+        std::fs::write(dir.path().join("db/a"), [0u8; 100]).unwrap();
+        std::fs::write(dir.path().join("db/b"), [0u8; 20]).unwrap();
+        std::fs::write(dir.path().join("wal"), [0u8; 3]).unwrap();
+
+        let paths =
+            [dir.path().join("db"), dir.path().join("wal"), dir.path().join("missing.db-wal")];
+        assert_eq!(disk_usage(&paths), Some(123));
+        assert_eq!(disk_usage(&[]), None);
+    }
+}
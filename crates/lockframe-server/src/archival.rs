@@ -0,0 +1,434 @@
+//! Compliance archiving of room logs.
+//!
+//! A room with an [`ArchivalConfig`] in its metadata has every frame it
+//! sequences handed to an external archiver, still end-to-end encrypted,
+//! together with the metadata the server assigned to it. Frames are queued
+//! per room in log order and sent in batches, one batch in flight at a time.
+//!
+//! Delivery is at-least-once: a batch stays queued until the runtime reports
+//! it delivered, and a failed batch is sent again after a backoff that doubles
+//! with each consecutive failure, jittered so rooms that failed together do
+//! not retry together. Archivers must therefore accept a batch they have
+//! already seen.
+//!
+//! Each room's cursor, the first log index its archiver has not accepted, is
+//! persisted on delivery. Only the most recent frames are kept in memory;
+//! older undelivered frames, and those left over from before a restart, are
+//! read back from storage when their turn comes.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use ciborium::Value;
+use lockframe_core::{backoff::Backoff, env::Environment, hlc::HlcTimestamp};
+use lockframe_proto::Frame;
+
+use crate::storage::{Storage, StorageError};
+
+/// Default most frames sent to an archiver in one batch.
+pub const DEFAULT_ARCHIVE_BATCH_FRAMES: usize = 256;
+
+/// Default delay before retrying a failed batch.
+pub const DEFAULT_ARCHIVE_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Default cap on the delay between retries.
+pub const DEFAULT_ARCHIVE_MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Most undelivered frames kept in memory per room.
+pub const MAX_QUEUED_ARCHIVE_FRAMES: usize = 1024;
+
+/// Archive batch format version.
+const ARCHIVE_VERSION: u32 = 1;
+
+/// Where and how a room's frames are archived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivalConfig {
+    /// Archiver URL batches are posted to
+    pub endpoint: String,
+    /// Most frames sent in one batch
+    pub max_batch_frames: usize,
+    /// Delay before the first retry of a failed batch
+    pub initial_backoff: Duration,
+    /// Cap on the delay between retries
+    pub max_backoff: Duration,
+}
+
+impl ArchivalConfig {
+    /// Archive to `endpoint` with the default batch size and backoff.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            max_batch_frames: DEFAULT_ARCHIVE_BATCH_FRAMES,
+            initial_backoff: DEFAULT_ARCHIVE_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_ARCHIVE_MAX_BACKOFF,
+        }
+    }
+
+    /// Delay before retrying after `failures` consecutive failed attempts,
+    /// drawn from the upper half of the doubling schedule with `env`'s RNG.
+    pub fn backoff<E: Environment>(&self, failures: u32, env: &E) -> Duration {
+        Backoff::new(self.initial_backoff, self.max_backoff).jittered().delay(failures, env)
+    }
+}
+
+/// A sequenced frame and the metadata the server assigned to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedFrame {
+    /// Room the frame was sequenced in
+    pub room_id: u128,
+    /// Log index assigned by the sequencer
+    pub log_index: u64,
+    /// MLS epoch the frame was sent in
+    pub epoch: u64,
+    /// Member that sent the frame
+    pub sender_id: u64,
+    /// Server wall clock time when the frame was sequenced, in Unix millis
+    ///
+    /// Frames read back from storage carry their header timestamp instead.
+    pub sequenced_at_millis: u64,
+    /// The frame as stored, payload still encrypted
+    pub frame: Frame,
+}
+
+/// Encode a batch for an archiver.
+///
+/// The body is a CBOR array `[version, room_id, frames]` where each frame is
+/// `[log_index, epoch, sender_id, sequenced_at_millis, encoded_frame]`.
+pub fn encode_batch(room_id: u128, frames: &[ArchivedFrame]) -> Result<Vec<u8>, String> {
+    let mut records = Vec::with_capacity(frames.len());
+    for archived in frames {
+        let mut encoded = Vec::new();
+        archived.frame.encode(&mut encoded).map_err(|e| e.to_string())?;
+        records.push((
+            archived.log_index,
+            archived.epoch,
+            archived.sender_id,
+            archived.sequenced_at_millis,
+            Value::Bytes(encoded),
+        ));
+    }
+
+    let mut body = Vec::new();
+    ciborium::ser::into_writer(&(ARCHIVE_VERSION, room_id, records), &mut body)
+        .map_err(|e| e.to_string())?;
+    Ok(body)
+}
+
+/// Read up to `limit` of a room's frames from `from` on back out of storage.
+pub fn load_archived(
+    storage: &impl Storage,
+    room_id: u128,
+    from: u64,
+    limit: usize,
+) -> Result<Vec<ArchivedFrame>, StorageError> {
+    let frames = storage.load_frames(room_id, from, limit)?;
+    Ok(frames
+        .into_iter()
+        .map(|frame| ArchivedFrame {
+            room_id,
+            log_index: frame.header.log_index(),
+            epoch: frame.header.epoch(),
+            sender_id: frame.header.sender_id(),
+            sequenced_at_millis: HlcTimestamp::from_u64(frame.header.hlc_timestamp())
+                .physical_millis(),
+            frame,
+        })
+        .collect())
+}
+
+/// Frames waiting to be archived for one room.
+#[derive(Debug)]
+struct RoomArchive {
+    /// First log index the archiver has not accepted
+    next: u64,
+    /// Most recent undelivered frames, oldest first. Frames between `next`
+    /// and the front are only in storage.
+    recent: VecDeque<ArchivedFrame>,
+    /// Whether a batch starting at `next` is with the runtime
+    in_flight: bool,
+    /// Consecutive failed attempts of the batch in flight
+    failures: u32,
+}
+
+impl RoomArchive {
+    fn new(next: u64) -> Self {
+        Self { next, recent: VecDeque::new(), in_flight: false, failures: 0 }
+    }
+}
+
+/// Per-room archive queues.
+#[derive(Debug, Default)]
+pub struct ArchivalQueues {
+    rooms: HashMap<u128, RoomArchive>,
+}
+
+impl ArchivalQueues {
+    /// Create empty queues.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a room has a queue.
+    pub fn contains(&self, room_id: u128) -> bool {
+        self.rooms.contains_key(&room_id)
+    }
+
+    /// Archive a room from `next_log_index` on, e.g. from its stored cursor.
+    ///
+    /// Replaces any queue the room had.
+    pub fn resume(&mut self, room_id: u128, next_log_index: u64) {
+        self.rooms.insert(room_id, RoomArchive::new(next_log_index));
+    }
+
+    /// Queue a newly sequenced frame.
+    ///
+    /// A room without a queue is archived from this frame on. A frame below
+    /// the cursor or at or below the last queued index of its room is a
+    /// repeat and is ignored. Past [`MAX_QUEUED_ARCHIVE_FRAMES`] the oldest
+    /// frame is dropped from memory, to be read back from storage.
+    pub fn push(&mut self, frame: ArchivedFrame) {
+        let room =
+            self.rooms.entry(frame.room_id).or_insert_with(|| RoomArchive::new(frame.log_index));
+        if frame.log_index < room.next
+            || room.recent.back().is_some_and(|last| last.log_index >= frame.log_index)
+        {
+            return;
+        }
+        if room.recent.len() >= MAX_QUEUED_ARCHIVE_FRAMES {
+            room.recent.pop_front();
+        }
+        room.recent.push_back(frame);
+    }
+
+    /// Next batch to send for a room, if nothing is in flight.
+    ///
+    /// `load` reads up to `limit` frames from `from` on out of storage, for
+    /// undelivered frames no longer in memory.
+    pub fn next_batch<E>(
+        &mut self,
+        room_id: u128,
+        config: &ArchivalConfig,
+        load: impl FnOnce(u64, usize) -> Result<Vec<ArchivedFrame>, E>,
+    ) -> Result<Option<Vec<ArchivedFrame>>, E> {
+        let Some(room) = self.rooms.get_mut(&room_id) else {
+            return Ok(None);
+        };
+        let Some(front) = room.recent.front().map(|frame| frame.log_index) else {
+            return Ok(None);
+        };
+        if room.in_flight {
+            return Ok(None);
+        }
+
+        let limit = config.max_batch_frames.max(1);
+        let mut batch = Vec::new();
+        if front > room.next {
+            let missing = usize::try_from(front.saturating_sub(room.next)).unwrap_or(usize::MAX);
+            batch = load(room.next, limit.min(missing))?;
+            batch.retain(|frame| frame.log_index >= room.next && frame.log_index < front);
+        }
+        if batch.is_empty() {
+            // Nothing stored before the queued frames, e.g. compacted away
+            room.next = room.next.max(front);
+            batch = room.recent.iter().take(limit).cloned().collect();
+        }
+
+        room.in_flight = true;
+        Ok(Some(batch))
+    }
+
+    /// The runtime delivered a room's batch through `through_log_index`.
+    ///
+    /// Returns the room's new cursor, to be persisted.
+    pub fn delivered(&mut self, room_id: u128, through_log_index: u64) -> Option<u64> {
+        let room = self.rooms.get_mut(&room_id)?;
+        room.next = room.next.max(through_log_index.saturating_add(1));
+        while room.recent.front().is_some_and(|frame| frame.log_index < room.next) {
+            room.recent.pop_front();
+        }
+        room.in_flight = false;
+        room.failures = 0;
+        Some(room.next)
+    }
+
+    /// The runtime failed to deliver a room's batch.
+    ///
+    /// Returns the batch to send again and the delay to wait first, jittered
+    /// with `env`. `load` is as for [`next_batch`](Self::next_batch).
+    pub fn failed<Env: Environment, E>(
+        &mut self,
+        room_id: u128,
+        config: &ArchivalConfig,
+        env: &Env,
+        load: impl FnOnce(u64, usize) -> Result<Vec<ArchivedFrame>, E>,
+    ) -> Result<Option<(Vec<ArchivedFrame>, Duration)>, E> {
+        let Some(room) = self.rooms.get_mut(&room_id) else {
+            return Ok(None);
+        };
+        room.failures = room.failures.saturating_add(1);
+        room.in_flight = false;
+        let delay = config.backoff(room.failures, env);
+        Ok(self.next_batch(room_id, config, load)?.map(|batch| (batch, delay)))
+    }
+
+    /// Drop a room's queue, e.g. when archiving is turned off.
+    pub fn remove(&mut self, room_id: u128) {
+        self.rooms.remove(&room_id);
+    }
+
+    /// Number of undelivered frames for a room, in memory or not.
+    pub fn pending(&self, room_id: u128) -> usize {
+        self.rooms
+            .get(&room_id)
+            .and_then(|room| {
+                let last = room.recent.back()?;
+                Some(last.log_index.saturating_add(1).saturating_sub(room.next))
+            })
+            .map_or(0, |pending| usize::try_from(pending).unwrap_or(usize::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+    use crate::SystemEnv;
+
+    fn archived(log_index: u64) -> ArchivedFrame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(7);
+        header.set_log_index(log_index);
+        ArchivedFrame {
+            room_id: 7,
+            log_index,
+            epoch: 0,
+            sender_id: 1,
+            sequenced_at_millis: 1_000,
+            frame: Frame::new(header, Vec::new()),
+        }
+    }
+
+    fn indices(batch: &[ArchivedFrame]) -> Vec<u64> {
+        batch.iter().map(|frame| frame.log_index).collect()
+    }
+
+    fn nothing_stored(_: u64, _: usize) -> Result<Vec<ArchivedFrame>, ()> {
+        Ok(Vec::new())
+    }
+
+    #[test]
+    fn one_batch_in_flight_until_delivered() {
+        let config = ArchivalConfig { max_batch_frames: 2, ..ArchivalConfig::new("http://a/") };
+        let mut queues = ArchivalQueues::new();
+        for i in 0..3 {
+            queues.push(archived(i));
+            queues.push(archived(i));
+        }
+
+        let batch = queues.next_batch(7, &config, nothing_stored).unwrap().unwrap();
+        assert_eq!(indices(&batch), vec![0, 1]);
+        assert!(queues.next_batch(7, &config, nothing_stored).unwrap().is_none());
+
+        queues.delivered(7, 1);
+        assert_eq!(queues.pending(7), 1);
+        assert_eq!(
+            indices(&queues.next_batch(7, &config, nothing_stored).unwrap().unwrap()),
+            vec![2]
+        );
+
+        queues.delivered(7, 2);
+        assert_eq!(queues.pending(7), 0);
+        assert!(queues.next_batch(7, &config, nothing_stored).unwrap().is_none());
+    }
+
+    #[test]
+    fn failed_batch_resent_with_growing_backoff() {
+        let config = ArchivalConfig {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+            ..ArchivalConfig::new("http://a/")
+        };
+        let mut queues = ArchivalQueues::new();
+        queues.push(archived(0));
+        queues.next_batch(7, &config, nothing_stored).unwrap().unwrap();
+
+        let env = SystemEnv::new();
+        // Each delay is jittered into the upper half of the doubling schedule
+        for base in [1000, 2000, 3000] {
+            let (batch, delay) = queues.failed(7, &config, &env, nothing_stored).unwrap().unwrap();
+            assert_eq!(indices(&batch), vec![0]);
+            assert!((base / 2..=base).contains(&delay.as_millis()), "{delay:?} for {base}ms");
+        }
+
+        // Frames sequenced meanwhile join the next batch after delivery
+        queues.push(archived(1));
+        queues.delivered(7, 0);
+        queues.next_batch(7, &config, nothing_stored).unwrap().unwrap();
+        let (batch, delay) = queues.failed(7, &config, &env, nothing_stored).unwrap().unwrap();
+        assert_eq!(indices(&batch), vec![1]);
+        assert!(delay <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn frames_dropped_from_memory_are_read_back_from_storage() {
+        let config = ArchivalConfig { max_batch_frames: 4, ..ArchivalConfig::new("http://a/") };
+        let mut queues = ArchivalQueues::new();
+        let overflow = MAX_QUEUED_ARCHIVE_FRAMES as u64 + 2;
+        for i in 0..overflow {
+            queues.push(archived(i));
+        }
+        assert_eq!(queues.pending(7), MAX_QUEUED_ARCHIVE_FRAMES + 2);
+
+        let load = |from: u64, limit: usize| -> Result<_, ()> {
+            Ok((from..from + limit as u64).map(archived).collect())
+        };
+        let batch = queues.next_batch(7, &config, load).unwrap().unwrap();
+        assert_eq!(indices(&batch), vec![0, 1]);
+        assert_eq!(queues.delivered(7, 1), Some(2));
+
+        let batch = queues.next_batch(7, &config, load).unwrap().unwrap();
+        assert_eq!(indices(&batch), vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn resumed_room_archives_from_its_cursor() {
+        let config = ArchivalConfig::new("http://a/");
+        let mut queues = ArchivalQueues::new();
+        queues.resume(7, 3);
+        queues.push(archived(2));
+        queues.push(archived(5));
+        assert_eq!(queues.pending(7), 3);
+
+        let mut asked = None;
+        let batch = queues
+            .next_batch(7, &config, |from, limit| -> Result<_, ()> {
+                asked = Some((from, limit));
+                Ok(vec![archived(3), archived(4)])
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(asked, Some((3, 2)));
+        assert_eq!(indices(&batch), vec![3, 4]);
+    }
+
+    #[test]
+    fn batch_encodes_frames_with_metadata() {
+        let frames = [archived(4), archived(5)];
+        let body = encode_batch(7, &frames).unwrap();
+
+        let (version, room_id, records): (u32, u128, Vec<(u64, u64, u64, u64, Value)>) =
+            ciborium::de::from_reader(&body[..]).unwrap();
+        assert_eq!((version, room_id), (ARCHIVE_VERSION, 7));
+        assert_eq!(records.len(), 2);
+
+        let (log_index, _, sender_id, sequenced_at_millis, Value::Bytes(encoded)) = &records[1]
+        else {
+            panic!("expected encoded frame");
+        };
+        assert_eq!((*log_index, *sender_id, *sequenced_at_millis), (5, 1, 1_000));
+        assert_eq!(Frame::decode(encoded).unwrap().header.log_index(), 5);
+    }
+}
@@ -0,0 +1,512 @@
+//! Chunked attachment uploads.
+//!
+//! Members upload an attachment a chunk at a time with `AttachmentInit`,
+//! `AttachmentChunk` and `AttachmentComplete` frames. Chunks are accepted in
+//! order and written to storage as they arrive, hashing them on the way, so
+//! the server never holds more than one chunk of an upload. Once the upload
+//! is complete the hash is checked against the announced one before storage
+//! marks the attachment complete. Attachments are stored per room and keyed
+//! by their hash, so an attachment uploaded twice to a room is stored once.
+//!
+//! Uploads are resumable while the server runs: `AttachmentInit` for an
+//! upload in progress reports the chunk to continue from. Uploads that see no
+//! chunk for [`AttachmentConfig::idle_timeout`] are dropped, as are all
+//! uploads in progress when the server restarts. Each session's uploads in
+//! progress are capped at [`AttachmentConfig::max_session_bytes`].
+//!
+//! Members download a complete attachment with `AttachmentFetch`, a window of
+//! [`AttachmentConfig::fetch_window`] chunks per request.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use lockframe_proto::payloads::attachment::{AttachmentChunk, AttachmentInit, AttachmentStatus};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Default largest attachment accepted.
+pub const DEFAULT_MAX_ATTACHMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Default largest chunk accepted.
+pub const DEFAULT_MAX_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// Default number of uploads in progress at once.
+pub const DEFAULT_MAX_UPLOADS: usize = 256;
+
+/// Default time an upload may go without a chunk before it is dropped.
+pub const DEFAULT_UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Default total size of one session's uploads in progress.
+pub const DEFAULT_MAX_SESSION_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Default number of chunks sent per `AttachmentFetch`.
+pub const DEFAULT_FETCH_WINDOW: u32 = 4;
+
+/// Limits on attachment uploads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttachmentConfig {
+    /// Largest attachment accepted, in bytes
+    pub max_size: u64,
+    /// Largest chunk accepted, in bytes
+    pub max_chunk_size: u32,
+    /// Uploads in progress at once
+    pub max_uploads: usize,
+    /// Time an upload may go without a chunk before it is dropped
+    pub idle_timeout: Duration,
+    /// Total announced size of one session's uploads in progress, in bytes
+    pub max_session_bytes: u64,
+    /// Chunks sent per `AttachmentFetch`
+    pub fetch_window: u32,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_ATTACHMENT_SIZE,
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            max_uploads: DEFAULT_MAX_UPLOADS,
+            idle_timeout: DEFAULT_UPLOAD_IDLE_TIMEOUT,
+            max_session_bytes: DEFAULT_MAX_SESSION_BYTES,
+            fetch_window: DEFAULT_FETCH_WINDOW,
+        }
+    }
+}
+
+/// Why an upload step was refused.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AttachmentError {
+    /// Attachment is larger than allowed
+    #[error("attachment of {size} bytes exceeds limit of {max}")]
+    TooLarge {
+        /// Announced size
+        size: u64,
+        /// Largest size accepted
+        max: u64,
+    },
+
+    /// Chunk size is zero or larger than allowed
+    #[error("chunk size {0} is not accepted")]
+    InvalidChunkSize(u32),
+
+    /// Upload was started again with a different size or chunk size
+    #[error("upload already in progress with a different layout")]
+    LayoutMismatch,
+
+    /// No upload is in progress for the hash
+    #[error("no upload in progress")]
+    UnknownUpload,
+
+    /// Too many uploads are in progress
+    #[error("too many uploads in progress")]
+    TooManyUploads,
+
+    /// Session already has as many bytes in progress as it may
+    #[error("upload of {size} bytes exceeds the session's remaining quota of {remaining}")]
+    QuotaExceeded {
+        /// Announced size
+        size: u64,
+        /// Bytes the session may still start uploading
+        remaining: u64,
+    },
+
+    /// Chunk is not the one the upload needs next
+    #[error("expected chunk {expected}, got chunk {got}")]
+    OutOfOrder {
+        /// Chunk the upload needs next
+        expected: u32,
+        /// Chunk that was sent
+        got: u32,
+    },
+
+    /// Chunk has the wrong length for its position
+    #[error("chunk {index} is {len} bytes, expected {expected}")]
+    ChunkLength {
+        /// Position of the chunk
+        index: u32,
+        /// Length of the chunk
+        len: usize,
+        /// Length the chunk should have
+        expected: u64,
+    },
+
+    /// Upload was completed before every chunk arrived
+    #[error("upload incomplete: {received} of {total} bytes received")]
+    Incomplete {
+        /// Bytes received
+        received: u64,
+        /// Bytes announced
+        total: u64,
+    },
+
+    /// Assembled bytes don't match the announced hash
+    #[error("attachment does not match its content hash")]
+    HashMismatch,
+}
+
+/// A complete attachment, as storage records it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredAttachment {
+    /// Size of the attachment in bytes
+    pub total_size: u64,
+    /// Size of every chunk but the last, in bytes
+    pub chunk_size: u32,
+    /// When the upload completed, in Unix milliseconds
+    pub stored_at_millis: u64,
+}
+
+impl StoredAttachment {
+    /// Number of chunks the attachment is stored as.
+    pub fn chunk_count(&self) -> u32 {
+        chunk_count(self.total_size, self.chunk_size)
+    }
+}
+
+/// An upload in progress.
+#[derive(Debug)]
+struct Upload {
+    session_id: u64,
+    total_size: u64,
+    chunk_size: u32,
+    next_chunk: u32,
+    received: u64,
+    hasher: Sha256,
+    last_activity: Instant,
+}
+
+impl Upload {
+    /// Length chunk `index` must have.
+    fn chunk_len(&self, index: u32) -> u64 {
+        let offset = u64::from(index).saturating_mul(u64::from(self.chunk_size));
+        self.total_size.saturating_sub(offset).min(u64::from(self.chunk_size))
+    }
+}
+
+/// Number of chunks an attachment of `total_size` bytes is split into.
+///
+/// An empty attachment has no chunks.
+fn chunk_count(total_size: u64, chunk_size: u32) -> u32 {
+    let chunk_size = u64::from(chunk_size.max(1));
+    let count = total_size.div_ceil(chunk_size);
+    u32::try_from(count).unwrap_or(u32::MAX)
+}
+
+/// Uploads in progress on this server, by room and content hash.
+#[derive(Debug, Default)]
+pub struct Attachments {
+    config: AttachmentConfig,
+    uploads: HashMap<(u128, [u8; 32]), Upload>,
+}
+
+impl Attachments {
+    /// Create an upload tracker with `config` limits.
+    pub fn new(config: AttachmentConfig) -> Self {
+        Self { config, uploads: HashMap::new() }
+    }
+
+    /// Number of uploads in progress.
+    pub fn len(&self) -> usize {
+        self.uploads.len()
+    }
+
+    /// Whether no upload is in progress.
+    pub fn is_empty(&self) -> bool {
+        self.uploads.is_empty()
+    }
+
+    /// Limits this tracker enforces.
+    pub fn config(&self) -> &AttachmentConfig {
+        &self.config
+    }
+
+    /// Start an upload by `session_id` for `room_id`, or report where one in
+    /// progress stands.
+    ///
+    /// `stored` is the attachment as storage already holds it, in which case
+    /// nothing needs to be sent. A session resuming another session's upload
+    /// takes it over, counting it against its own quota.
+    pub fn init(
+        &mut self,
+        session_id: u64,
+        room_id: u128,
+        init: &AttachmentInit,
+        stored: Option<StoredAttachment>,
+        now: Instant,
+    ) -> Result<AttachmentStatus, AttachmentError> {
+        let AttachmentInit { content_hash, total_size, chunk_size } = *init;
+        if total_size > self.config.max_size {
+            return Err(AttachmentError::TooLarge { size: total_size, max: self.config.max_size });
+        }
+        if chunk_size == 0 || chunk_size > self.config.max_chunk_size {
+            return Err(AttachmentError::InvalidChunkSize(chunk_size));
+        }
+
+        let key = (room_id, content_hash);
+        if let Some(stored) = stored {
+            self.uploads.remove(&key);
+            let next_chunk = stored.chunk_count();
+            return Ok(AttachmentStatus { content_hash, next_chunk, complete: true });
+        }
+
+        let at_capacity = self.uploads.len() >= self.config.max_uploads;
+        let remaining =
+            self.config.max_session_bytes.saturating_sub(self.session_bytes(session_id));
+        let next_chunk = match self.uploads.get_mut(&key) {
+            Some(upload) if upload.total_size != total_size || upload.chunk_size != chunk_size => {
+                return Err(AttachmentError::LayoutMismatch);
+            },
+            Some(upload) if upload.session_id != session_id && total_size > remaining => {
+                return Err(AttachmentError::QuotaExceeded { size: total_size, remaining });
+            },
+            Some(upload) => {
+                upload.session_id = session_id;
+                upload.last_activity = now;
+                upload.next_chunk
+            },
+            None if at_capacity => {
+                return Err(AttachmentError::TooManyUploads);
+            },
+            None if total_size > remaining => {
+                return Err(AttachmentError::QuotaExceeded { size: total_size, remaining });
+            },
+            None => {
+                self.uploads.insert(key, Upload {
+                    session_id,
+                    total_size,
+                    chunk_size,
+                    next_chunk: 0,
+                    received: 0,
+                    hasher: Sha256::new(),
+                    last_activity: now,
+                });
+                0
+            },
+        };
+        Ok(AttachmentStatus { content_hash, next_chunk, complete: false })
+    }
+
+    /// Accept the next chunk of an upload for `room_id`.
+    ///
+    /// The chunk is hashed but not kept; the caller stores it, and
+    /// [`cancel`](Self::cancel)s the upload if that fails.
+    pub fn chunk(
+        &mut self,
+        room_id: u128,
+        chunk: &AttachmentChunk,
+        now: Instant,
+    ) -> Result<(), AttachmentError> {
+        let upload = self.upload_mut(room_id, &chunk.content_hash)?;
+        if chunk.index != upload.next_chunk {
+            return Err(AttachmentError::OutOfOrder {
+                expected: upload.next_chunk,
+                got: chunk.index,
+            });
+        }
+
+        let expected = upload.chunk_len(chunk.index);
+        let len = chunk.data.len();
+        if expected == 0 || u64::try_from(len).ok() != Some(expected) {
+            return Err(AttachmentError::ChunkLength { index: chunk.index, len, expected });
+        }
+
+        upload.hasher.update(&chunk.data);
+        upload.received = upload.received.saturating_add(expected);
+        upload.next_chunk = upload.next_chunk.saturating_add(1);
+        upload.last_activity = now;
+        Ok(())
+    }
+
+    /// Finish an upload for `room_id`, returning its final status and the
+    /// attachment to record once its chunks match the hash.
+    ///
+    /// The upload stays in progress if it is refused, except on a hash
+    /// mismatch: the chunks can't be fixed by sending more, and the caller
+    /// deletes them.
+    pub fn complete(
+        &mut self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        now_millis: u64,
+    ) -> Result<(AttachmentStatus, StoredAttachment), AttachmentError> {
+        let upload = self.upload_mut(room_id, content_hash)?;
+        if upload.received != upload.total_size {
+            return Err(AttachmentError::Incomplete {
+                received: upload.received,
+                total: upload.total_size,
+            });
+        }
+
+        let upload =
+            self.uploads.remove(&(room_id, *content_hash)).ok_or(AttachmentError::UnknownUpload)?;
+        if upload.hasher.finalize().as_slice() != content_hash {
+            return Err(AttachmentError::HashMismatch);
+        }
+        let status = AttachmentStatus {
+            content_hash: *content_hash,
+            next_chunk: upload.next_chunk,
+            complete: true,
+        };
+        let stored = StoredAttachment {
+            total_size: upload.total_size,
+            chunk_size: upload.chunk_size,
+            stored_at_millis: now_millis,
+        };
+        Ok((status, stored))
+    }
+
+    /// Drop an upload in progress, for instance after its chunk could not be
+    /// stored.
+    pub fn cancel(&mut self, room_id: u128, content_hash: &[u8; 32]) {
+        self.uploads.remove(&(room_id, *content_hash));
+    }
+
+    /// Drop uploads that have gone without a chunk for the idle timeout,
+    /// returning the room and content hash of each so their chunks can be
+    /// deleted.
+    pub fn expire(&mut self, now: Instant) -> Vec<(u128, [u8; 32])> {
+        let idle_timeout = self.config.idle_timeout;
+        let expired: Vec<(u128, [u8; 32])> = self
+            .uploads
+            .iter()
+            .filter(|(_, upload)| {
+                now.saturating_duration_since(upload.last_activity) >= idle_timeout
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &expired {
+            self.uploads.remove(key);
+        }
+        expired
+    }
+
+    /// Total announced size of the uploads `session_id` has in progress.
+    fn session_bytes(&self, session_id: u64) -> u64 {
+        self.uploads
+            .values()
+            .filter(|upload| upload.session_id == session_id)
+            .fold(0, |total, upload| total.saturating_add(upload.total_size))
+    }
+
+    fn upload_mut(
+        &mut self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<&mut Upload, AttachmentError> {
+        self.uploads.get_mut(&(room_id, *content_hash)).ok_or(AttachmentError::UnknownUpload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init(data: &[u8], chunk_size: u32) -> AttachmentInit {
+        AttachmentInit {
+            content_hash: Sha256::digest(data).into(),
+            total_size: data.len() as u64,
+            chunk_size,
+        }
+    }
+
+    fn chunk(data: &[u8], index: u32, bytes: &[u8]) -> AttachmentChunk {
+        AttachmentChunk { content_hash: Sha256::digest(data).into(), index, data: bytes.to_vec() }
+    }
+
+    #[test]
+    fn upload_resumes_where_it_stopped() {
+        let mut attachments = Attachments::default();
+        let now = Instant::now();
+        let data = b"ten bytes!";
+        let init = init(data, 4);
+
+        let status = attachments.init(1, 1, &init, None, now).unwrap();
+        assert_eq!((status.next_chunk, status.complete), (0, false));
+        attachments.chunk(1, &chunk(data, 0, &data[..4]), now).unwrap();
+
+        // Reconnected: the server asks for the second chunk
+        let status = attachments.init(2, 1, &init, None, now).unwrap();
+        assert_eq!(status.next_chunk, 1);
+
+        let result = attachments.chunk(1, &chunk(data, 2, &data[8..]), now);
+        assert_eq!(result, Err(AttachmentError::OutOfOrder { expected: 1, got: 2 }));
+        assert!(matches!(
+            attachments.complete(1, &init.content_hash, 0),
+            Err(AttachmentError::Incomplete { received: 4, total: 10 })
+        ));
+
+        attachments.chunk(1, &chunk(data, 1, &data[4..8]), now).unwrap();
+        attachments.chunk(1, &chunk(data, 2, &data[8..]), now).unwrap();
+        let (status, stored) = attachments.complete(1, &init.content_hash, 7).unwrap();
+        assert_eq!((status.next_chunk, status.complete), (3, true));
+        assert_eq!(stored, StoredAttachment { total_size: 10, chunk_size: 4, stored_at_millis: 7 });
+        assert!(attachments.is_empty());
+
+        // Stored already, so nothing needs to be sent again
+        let status = attachments.init(1, 1, &init, Some(stored), now).unwrap();
+        assert_eq!((status.next_chunk, status.complete), (3, true));
+    }
+
+    #[test]
+    fn uploads_are_checked_against_limits_and_hash() {
+        let config = AttachmentConfig { max_size: 16, max_uploads: 1, ..Default::default() };
+        let mut attachments = Attachments::new(config);
+        let now = Instant::now();
+
+        let too_large = AttachmentInit { total_size: 17, ..init(b"", 4) };
+        assert!(matches!(
+            attachments.init(1, 1, &too_large, None, now),
+            Err(AttachmentError::TooLarge { .. })
+        ));
+
+        let forged = AttachmentInit { content_hash: [0; 32], total_size: 3, chunk_size: 4 };
+        attachments.init(1, 1, &forged, None, now).unwrap();
+        assert_eq!(
+            attachments.init(1, 1, &init(b"abc", 4), None, now),
+            Err(AttachmentError::TooManyUploads)
+        );
+        assert_eq!(
+            attachments.chunk(2, &chunk(b"", 0, b"abc"), now),
+            Err(AttachmentError::UnknownUpload)
+        );
+
+        let mut bytes = chunk(b"", 0, b"abc");
+        bytes.content_hash = [0; 32];
+        attachments.chunk(1, &bytes, now).unwrap();
+        assert_eq!(attachments.complete(1, &[0; 32], 0), Err(AttachmentError::HashMismatch));
+        assert!(attachments.is_empty());
+
+        // Idle uploads are dropped
+        let abc = init(b"abc", 4);
+        attachments.init(1, 1, &abc, None, now).unwrap();
+        let expired = attachments.expire(now + DEFAULT_UPLOAD_IDLE_TIMEOUT);
+        assert_eq!(expired, vec![(1, abc.content_hash)]);
+    }
+
+    #[test]
+    fn sessions_are_held_to_their_quota() {
+        let config = AttachmentConfig { max_session_bytes: 8, ..Default::default() };
+        let mut attachments = Attachments::new(config);
+        let now = Instant::now();
+
+        attachments.init(1, 1, &init(b"12345", 4), None, now).unwrap();
+        assert_eq!(
+            attachments.init(1, 1, &init(b"6789", 4), None, now),
+            Err(AttachmentError::QuotaExceeded { size: 4, remaining: 3 })
+        );
+
+        // Another session may start it, and take over the first upload
+        attachments.init(2, 1, &init(b"6789", 4), None, now).unwrap();
+        assert!(matches!(
+            attachments.init(2, 1, &init(b"12345", 4), None, now),
+            Err(AttachmentError::QuotaExceeded { .. })
+        ));
+        attachments.init(3, 1, &init(b"12345", 4), None, now).unwrap();
+        attachments.init(1, 1, &init(b"abc", 4), None, now).unwrap();
+
+        // The same attachment uploads to another room separately
+        attachments.init(1, 2, &init(b"abc", 4), None, now).unwrap();
+        assert_eq!(attachments.len(), 4);
+    }
+}
@@ -0,0 +1,128 @@
+//! Append-only audit log of membership and moderation events.
+//!
+//! The room manager reports rooms it creates, members that commits add or
+//! remove, and frames it rejects; the driver adds the connections it sees
+//! close, with the reason. Rejected frames are audited only as the reject log
+//! samples them, so a flood of bad frames is not a flood of audit writes.
+//! Each event is stamped with the next sequence number, the wall clock time
+//! and the authenticated principal of the session behind it, appended to
+//! storage with
+//! [`Storage::append_audit`](crate::storage::Storage::append_audit), and
+//! handed to the runtime as [`ServerAction::Audit`](crate::ServerAction::Audit)
+//! to write to [`AUDIT_LOG_TARGET`](crate::AUDIT_LOG_TARGET).
+//!
+//! Records are never changed or removed once appended. Sequence numbers carry
+//! on from the last stored record after a restart, so `GET /audit` on the
+//! admin API can page through the log with `from`. Until the last stored
+//! record can be read, events are logged as errors rather than numbered from
+//! zero again over the records already stored.
+
+use serde::{Deserialize, Serialize};
+
+/// Something worth an audit record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditEvent {
+    /// A room was created
+    RoomCreated {
+        /// Room created
+        room_id: u128,
+        /// User who created it
+        creator: u64,
+    },
+    /// A commit added a member
+    MemberAdded {
+        /// Room the member joined
+        room_id: u128,
+        /// Member added
+        member_id: u64,
+        /// Epoch the commit moved the room to
+        epoch: u64,
+    },
+    /// A commit removed a member
+    MemberRemoved {
+        /// Room the member left
+        room_id: u128,
+        /// Member removed
+        member_id: u64,
+        /// Epoch the commit moved the room to
+        epoch: u64,
+    },
+    /// The room manager refused a frame
+    FrameRejected {
+        /// Room the frame was for
+        room_id: u128,
+        /// Sender of the frame
+        sender_id: u64,
+        /// Why it was refused
+        reason: String,
+    },
+    /// A connection closed
+    ConnectionClosed {
+        /// Session of the connection
+        session_id: u64,
+        /// Why it closed
+        reason: String,
+    },
+}
+
+impl AuditEvent {
+    /// Name used in log records and the admin API.
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::RoomCreated { .. } => "room_created",
+            Self::MemberAdded { .. } => "member_added",
+            Self::MemberRemoved { .. } => "member_removed",
+            Self::FrameRejected { .. } => "frame_rejected",
+            Self::ConnectionClosed { .. } => "connection_closed",
+        }
+    }
+}
+
+/// An audit event as appended to the log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Position in the log, starting at 0
+    pub seq: u64,
+    /// Wall clock time the event was recorded (Unix millis)
+    pub at_millis: u64,
+    /// Authenticated principal of the session behind the event, if it had
+    /// one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub principal: Option<String>,
+    /// What happened
+    pub event: AuditEvent,
+}
+
+/// Hands out audit sequence numbers.
+///
+/// A default log does not know where the stored log ends and numbers
+/// nothing until it is [resumed](Self::resume).
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    next_seq: Option<u64>,
+}
+
+impl AuditLog {
+    /// Continue after the last stored record, `None` for an empty log.
+    pub fn resume(&mut self, last_seq: Option<u64>) {
+        self.next_seq = Some(last_seq.map_or(0, |seq| seq.saturating_add(1)));
+    }
+
+    /// Whether the end of the stored log is known.
+    pub fn is_resumed(&self) -> bool {
+        self.next_seq.is_some()
+    }
+
+    /// Stamp `event` with the next sequence number. `None` until the log is
+    /// resumed.
+    pub fn record(
+        &mut self,
+        at_millis: u64,
+        principal: Option<String>,
+        event: AuditEvent,
+    ) -> Option<AuditRecord> {
+        let seq = self.next_seq?;
+        self.next_seq = Some(seq.saturating_add(1));
+        Some(AuditRecord { seq, at_millis, principal, event })
+    }
+}
@@ -0,0 +1,120 @@
+//! Directory of publicly listed rooms.
+//!
+//! Rooms are unlisted unless an operator publishes them. A listed room has a
+//! display name and description that clients page through with `ListRooms`,
+//! so they can find a room to ask to join without learning its ID out of
+//! band. Listing a room does not admit anyone to it: joining still takes a
+//! Welcome from a member.
+
+use std::{collections::BTreeMap, ops::Bound};
+
+use serde::{Deserialize, Serialize};
+
+/// Most rooms returned in one `ListRoomsReply`.
+pub const MAX_DIRECTORY_PAGE: u32 = 100;
+
+/// What the directory shows for a listed room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Listing {
+    /// Display name
+    pub name: String,
+    /// What the room is about
+    pub description: String,
+}
+
+/// Publicly listed rooms, ordered by room ID.
+#[derive(Debug, Default)]
+pub struct RoomDirectory {
+    listings: BTreeMap<u128, Listing>,
+}
+
+impl RoomDirectory {
+    /// Create an empty directory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a directory holding `listings`, as loaded from storage.
+    pub const fn restore(listings: BTreeMap<u128, Listing>) -> Self {
+        Self { listings }
+    }
+
+    /// List `room_id`, or update its listing. Returns the previous listing.
+    pub fn publish(&mut self, room_id: u128, listing: Listing) -> Option<Listing> {
+        self.listings.insert(room_id, listing)
+    }
+
+    /// Remove `room_id` from the directory. Returns its listing, if it had
+    /// one.
+    pub fn unlist(&mut self, room_id: u128) -> Option<Listing> {
+        self.listings.remove(&room_id)
+    }
+
+    /// Listing of `room_id`, if it is listed.
+    pub fn get(&self, room_id: u128) -> Option<&Listing> {
+        self.listings.get(&room_id)
+    }
+
+    /// Number of listed rooms.
+    pub fn len(&self) -> usize {
+        self.listings.len()
+    }
+
+    /// Whether no room is listed.
+    pub fn is_empty(&self) -> bool {
+        self.listings.is_empty()
+    }
+
+    /// Up to `limit` listings with room IDs above `after`, and whether more
+    /// follow.
+    ///
+    /// `limit` is clamped between 1 and [`MAX_DIRECTORY_PAGE`].
+    pub fn page(&self, after: Option<u128>, limit: u32) -> (Vec<(u128, Listing)>, bool) {
+        let limit = usize::try_from(limit.clamp(1, MAX_DIRECTORY_PAGE)).unwrap_or(usize::MAX);
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut listings = self
+            .listings
+            .range((start, Bound::Unbounded))
+            .map(|(room_id, listing)| (*room_id, listing.clone()));
+
+        let page: Vec<_> = listings.by_ref().take(limit).collect();
+        let more = listings.next().is_some();
+        (page, more)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(name: &str) -> Listing {
+        Listing { name: name.to_string(), description: String::new() }
+    }
+
+    #[test]
+    fn pages_follow_room_order() {
+        let mut directory = RoomDirectory::new();
+        for room_id in [30, 10, 20] {
+            directory.publish(room_id, listing(&format!("room {room_id}")));
+        }
+
+        let (page, more) = directory.page(None, 2);
+        assert_eq!(page.iter().map(|(room_id, _)| *room_id).collect::<Vec<_>>(), vec![10, 20]);
+        assert!(more);
+
+        let (page, more) = directory.page(Some(20), 2);
+        assert_eq!(page.iter().map(|(room_id, _)| *room_id).collect::<Vec<_>>(), vec![30]);
+        assert!(!more);
+    }
+
+    #[test]
+    fn unlisted_rooms_leave_the_directory() {
+        let mut directory = RoomDirectory::new();
+        assert!(directory.publish(1, listing("lobby")).is_none());
+        assert_eq!(directory.publish(1, listing("hall")), Some(listing("lobby")));
+
+        assert_eq!(directory.unlist(1), Some(listing("hall")));
+        assert!(directory.is_empty());
+        assert!(directory.page(None, 10).0.is_empty());
+    }
+}
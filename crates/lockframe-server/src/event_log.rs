@@ -0,0 +1,451 @@
+//! Structured event log.
+//!
+//! When enabled, the production runtime writes every [`ServerEvent`] it feeds
+//! the driver to the [`EVENT_LOG_TARGET`] tracing target, one line per event.
+//! Because the driver is deterministic given its inputs, a log captured from a
+//! real deployment is enough to reproduce an incident offline.
+//!
+//! # Line format
+//!
+//! ```text
+//! lfevent/1 <micros> accept <session_id>
+//! lfevent/1 <micros> frame <session_id> <hex frame>
+//! lfevent/1 <micros> close <session_id> <reason>
+//! lfevent/1 <micros> tick
+//! lfevent/1 <micros> archived <room_id> <through_log_index>
+//! lfevent/1 <micros> archive_failed <room_id> <reason>
+//! lfevent/1 <micros> relay <server_id> submit <session_id> <hex frame>
+//! lfevent/1 <micros> relay <server_id> sequenced <log_index> <hex frame>
+//! lfevent/1 <micros> relay <server_id> sequenced_for <session_id> <log_index> <hex frame>
+//! lfevent/1 <micros> relay <server_id> reply <session_id> <hex frame>
+//! lfevent/1 <micros> relay <server_id> resend <room_id> <from_log_index>
+//! lfevent/1 <micros> relay <server_id> closed <session_id>
+//! lfevent/1 <micros> load <queue_depth> <storage_latency_micros>
+//! ```
+//!
+//! `micros` is the time since the server started. Anything before the marker
+//! (timestamps, levels, targets added by the subscriber) is ignored when
+//! decoding, so logs can be fed back in as written.
+//!
+//! Hello frames are logged without their `auth_token`. The driver never reads
+//! it, so replay is unaffected, and the log does not become a store of
+//! credentials.
+
+use std::{fmt::Write as _, time::Duration};
+
+use lockframe_proto::{Frame, Opcode, Payload, payloads::session::Hello};
+
+use crate::{LoadReport, Relayed, ServerEvent, ServerId};
+
+/// Tracing target the runtime writes event records to.
+pub const EVENT_LOG_TARGET: &str = "lockframe_server::events";
+
+/// Marker that starts every encoded record.
+const MARKER: &str = "lfevent/1";
+
+/// Errors decoding an event log line.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum EventLogError {
+    /// A field was missing from the record
+    #[error("missing {0}")]
+    Missing(&'static str),
+
+    /// A field could not be parsed
+    #[error("invalid {field}: {value}")]
+    Invalid {
+        /// Field that failed to parse
+        field: &'static str,
+        /// Offending value
+        value: String,
+    },
+
+    /// Unknown event kind
+    #[error("unknown event kind: {0}")]
+    UnknownKind(String),
+}
+
+/// A server event and when the runtime processed it.
+#[derive(Debug, Clone)]
+pub struct EventRecord {
+    /// Time since the server started
+    pub at: Duration,
+    /// The event fed to the driver
+    pub event: ServerEvent,
+}
+
+impl EventRecord {
+    /// Encode as a single log line.
+    pub fn encode(&self) -> String {
+        let micros = self.at.as_micros();
+        match &self.event {
+            ServerEvent::ConnectionAccepted { session_id } => {
+                format!("{MARKER} {micros} accept {session_id}")
+            },
+            ServerEvent::PeerAuthenticated { session_id, principal } => {
+                format!("{MARKER} {micros} principal {session_id} {principal}")
+            },
+            ServerEvent::FrameReceived { session_id, frame } => {
+                format!("{MARKER} {micros} frame {session_id} {}", frame_hex(frame))
+            },
+            ServerEvent::ConnectionClosed { session_id, reason } => {
+                format!("{MARKER} {micros} close {session_id} {reason}")
+            },
+            ServerEvent::Tick => format!("{MARKER} {micros} tick"),
+            ServerEvent::ArchiveDelivered { room_id, through_log_index } => {
+                format!("{MARKER} {micros} archived {room_id} {through_log_index}")
+            },
+            ServerEvent::ArchiveFailed { room_id, reason } => {
+                format!("{MARKER} {micros} archive_failed {room_id} {reason}")
+            },
+            ServerEvent::Relayed { from, relayed } => {
+                let relayed = match relayed {
+                    Relayed::Submit { session_id, frame } => {
+                        format!("submit {session_id} {}", frame_hex(frame))
+                    },
+                    Relayed::Sequenced { log_index, frame, origin: None } => {
+                        format!("sequenced {log_index} {}", frame_hex(frame))
+                    },
+                    Relayed::Sequenced { log_index, frame, origin: Some(session_id) } => {
+                        format!("sequenced_for {session_id} {log_index} {}", frame_hex(frame))
+                    },
+                    Relayed::Reply { session_id, frame } => {
+                        format!("reply {session_id} {}", frame_hex(frame))
+                    },
+                    Relayed::Resend { room_id, from_log_index } => {
+                        format!("resend {room_id} {from_log_index}")
+                    },
+                    Relayed::Closed { session_id } => format!("closed {session_id}"),
+                };
+                format!("{MARKER} {micros} relay {} {relayed}", from.0)
+            },
+            ServerEvent::LoadReported(load) => {
+                let latency = load.storage_latency.as_micros();
+                format!("{MARKER} {micros} load {} {latency}", load.queue_depth)
+            },
+        }
+    }
+
+    /// Decode a log line.
+    ///
+    /// Returns `None` for lines that carry no event record.
+    #[allow(clippy::too_many_lines)]
+    pub fn decode(line: &str) -> Result<Option<Self>, EventLogError> {
+        let Some((_, record)) = line.split_once(MARKER) else {
+            return Ok(None);
+        };
+
+        let (micros, rest) = split_field(record, "time")?;
+        let micros: u64 = parse(micros, "time")?;
+        let at = Duration::from_micros(micros);
+
+        let (kind, rest) = split_field(rest, "event kind")?;
+        let event = match kind {
+            "accept" => {
+                let (session_id, _) = split_field(rest, "session id")?;
+                ServerEvent::ConnectionAccepted { session_id: parse(session_id, "session id")? }
+            },
+            "principal" => {
+                let (session_id, principal) = split_field(rest, "session id")?;
+                ServerEvent::PeerAuthenticated {
+                    session_id: parse(session_id, "session id")?,
+                    principal: principal.to_string(),
+                }
+            },
+            "frame" => {
+                let (session_id, rest) = split_field(rest, "session id")?;
+                let frame = parse_frame(rest)?;
+                ServerEvent::FrameReceived { session_id: parse(session_id, "session id")?, frame }
+            },
+            "close" => {
+                let (session_id, reason) = split_field(rest, "session id")?;
+                ServerEvent::ConnectionClosed {
+                    session_id: parse(session_id, "session id")?,
+                    reason: reason.to_string(),
+                }
+            },
+            "tick" => ServerEvent::Tick,
+            "archived" => {
+                let (room_id, rest) = split_field(rest, "room id")?;
+                let (through_log_index, _) = split_field(rest, "log index")?;
+                ServerEvent::ArchiveDelivered {
+                    room_id: parse(room_id, "room id")?,
+                    through_log_index: parse(through_log_index, "log index")?,
+                }
+            },
+            "archive_failed" => {
+                let (room_id, reason) = split_field(rest, "room id")?;
+                ServerEvent::ArchiveFailed {
+                    room_id: parse(room_id, "room id")?,
+                    reason: reason.to_string(),
+                }
+            },
+            "relay" => {
+                let (from, rest) = split_field(rest, "server id")?;
+                let (kind, rest) = split_field(rest, "relay kind")?;
+                let (number, rest) = split_field(rest, "relay target")?;
+                let relayed = match kind {
+                    "submit" => Relayed::Submit {
+                        session_id: parse(number, "session id")?,
+                        frame: parse_frame(rest)?,
+                    },
+                    "sequenced" => Relayed::Sequenced {
+                        log_index: parse(number, "log index")?,
+                        frame: parse_frame(rest)?,
+                        origin: None,
+                    },
+                    "sequenced_for" => {
+                        let (log_index, rest) = split_field(rest, "log index")?;
+                        Relayed::Sequenced {
+                            log_index: parse(log_index, "log index")?,
+                            frame: parse_frame(rest)?,
+                            origin: Some(parse(number, "session id")?),
+                        }
+                    },
+                    "reply" => Relayed::Reply {
+                        session_id: parse(number, "session id")?,
+                        frame: parse_frame(rest)?,
+                    },
+                    "resend" => {
+                        let (from_log_index, _) = split_field(rest, "log index")?;
+                        Relayed::Resend {
+                            room_id: parse(number, "room id")?,
+                            from_log_index: parse(from_log_index, "log index")?,
+                        }
+                    },
+                    "closed" => Relayed::Closed { session_id: parse(number, "session id")? },
+                    other => {
+                        return Err(EventLogError::Invalid {
+                            field: "relay kind",
+                            value: other.to_string(),
+                        });
+                    },
+                };
+                ServerEvent::Relayed { from: ServerId(parse(from, "server id")?), relayed }
+            },
+            "load" => {
+                let (queue_depth, rest) = split_field(rest, "queue depth")?;
+                let (latency, _) = split_field(rest, "storage latency")?;
+                ServerEvent::LoadReported(LoadReport {
+                    queue_depth: parse(queue_depth, "queue depth")?,
+                    storage_latency: Duration::from_micros(parse(latency, "storage latency")?),
+                })
+            },
+            other => return Err(EventLogError::UnknownKind(other.to_string())),
+        };
+
+        Ok(Some(Self { at, event }))
+    }
+}
+
+/// Split off the first whitespace-separated field.
+fn split_field<'a>(s: &'a str, name: &'static str) -> Result<(&'a str, &'a str), EventLogError> {
+    let s = s.trim_start();
+    if s.is_empty() {
+        return Err(EventLogError::Missing(name));
+    }
+    Ok(s.split_once(' ').map_or((s, ""), |(field, rest)| (field, rest)))
+}
+
+fn parse<T: std::str::FromStr>(value: &str, field: &'static str) -> Result<T, EventLogError> {
+    value.parse().map_err(|_| EventLogError::Invalid { field, value: value.to_string() })
+}
+
+/// Hex encoding of a frame's wire form, with any Hello `auth_token` removed.
+fn frame_hex(frame: &Frame) -> String {
+    let redacted = redact(frame);
+    let mut bytes = Vec::new();
+    // Frames reaching the driver were decoded from the wire, so they re-encode
+    if redacted.as_ref().unwrap_or(frame).encode(&mut bytes).is_err() {
+        bytes.clear();
+    }
+    to_hex(&bytes)
+}
+
+/// Copy of `frame` without its `auth_token`, if it is a Hello carrying one.
+fn redact(frame: &Frame) -> Option<Frame> {
+    if frame.header.opcode_enum() != Some(Opcode::Hello) {
+        return None;
+    }
+    match Payload::from_frame(frame.clone()) {
+        Ok(Payload::Hello(hello)) if hello.auth_token.is_some() => {
+            Payload::Hello(Hello { auth_token: None, ..hello }).into_frame(frame.header).ok()
+        },
+        _ => None,
+    }
+}
+
+/// Decode the hex frame that starts `s`.
+fn parse_frame(s: &str) -> Result<Frame, EventLogError> {
+    let (hex, _) = split_field(s, "frame")?;
+    let bytes = from_hex(hex)
+        .ok_or_else(|| EventLogError::Invalid { field: "frame", value: hex.into() })?;
+    Frame::decode(&bytes)
+        .map_err(|e| EventLogError::Invalid { field: "frame", value: e.to_string() })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().saturating_mul(2));
+    for byte in bytes {
+        let _ = write!(out, "{byte:02x}");
+    }
+    out
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| std::str::from_utf8(pair).ok().and_then(|s| u8::from_str_radix(s, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+
+    fn roundtrip(event: ServerEvent) -> ServerEvent {
+        let record = EventRecord { at: Duration::from_micros(1_500), event };
+        let line = format!("2026-01-01T00:00:00Z  INFO {EVENT_LOG_TARGET}: {}", record.encode());
+        let decoded = EventRecord::decode(&line).expect("valid record").expect("has record");
+        assert_eq!(decoded.at, record.at);
+        decoded.event
+    }
+
+    #[test]
+    fn records_roundtrip_through_log_lines() {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(0x42);
+        let frame = Frame::new(header, b"payload".to_vec());
+
+        match roundtrip(ServerEvent::FrameReceived { session_id: 7, frame: frame.clone() }) {
+            ServerEvent::FrameReceived { session_id, frame: decoded } => {
+                assert_eq!(session_id, 7);
+                assert_eq!(decoded, frame);
+            },
+            other => panic!("unexpected event: {other:?}"),
+        }
+
+        match roundtrip(ServerEvent::ConnectionClosed {
+            session_id: 3,
+            reason: "connection closed".into(),
+        }) {
+            ServerEvent::ConnectionClosed { session_id, reason } => {
+                assert_eq!(session_id, 3);
+                assert_eq!(reason, "connection closed");
+            },
+            other => panic!("unexpected event: {other:?}"),
+        }
+
+        assert!(matches!(
+            roundtrip(ServerEvent::ConnectionAccepted { session_id: 9 }),
+            ServerEvent::ConnectionAccepted { session_id: 9 }
+        ));
+        assert!(matches!(roundtrip(ServerEvent::Tick), ServerEvent::Tick));
+
+        match roundtrip(ServerEvent::PeerAuthenticated {
+            session_id: 9,
+            principal: "sha256:00ff".into(),
+        }) {
+            ServerEvent::PeerAuthenticated { session_id, principal } => {
+                assert_eq!(session_id, 9);
+                assert_eq!(principal, "sha256:00ff");
+            },
+            other => panic!("unexpected event: {other:?}"),
+        }
+
+        assert!(matches!(
+            roundtrip(ServerEvent::ArchiveDelivered { room_id: 0x42, through_log_index: 17 }),
+            ServerEvent::ArchiveDelivered { room_id: 0x42, through_log_index: 17 }
+        ));
+        match roundtrip(ServerEvent::ArchiveFailed {
+            room_id: 0x42,
+            reason: "archiver responded 503".into(),
+        }) {
+            ServerEvent::ArchiveFailed { room_id, reason } => {
+                assert_eq!(room_id, 0x42);
+                assert_eq!(reason, "archiver responded 503");
+            },
+            other => panic!("unexpected event: {other:?}"),
+        }
+
+        for origin in [None, Some(7)] {
+            let relayed = Relayed::Sequenced { log_index: 12, frame: frame.clone(), origin };
+            match roundtrip(ServerEvent::Relayed { from: ServerId(5), relayed }) {
+                ServerEvent::Relayed {
+                    from: ServerId(5),
+                    relayed:
+                        Relayed::Sequenced { log_index: 12, frame: decoded, origin: decoded_origin },
+                } => assert_eq!((decoded, decoded_origin), (frame.clone(), origin)),
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+        let relayed = Relayed::Resend { room_id: 0x42, from_log_index: 3 };
+        assert!(matches!(
+            roundtrip(ServerEvent::Relayed { from: ServerId(5), relayed }),
+            ServerEvent::Relayed {
+                from: ServerId(5),
+                relayed: Relayed::Resend { room_id: 0x42, from_log_index: 3 }
+            }
+        ));
+        assert!(matches!(
+            roundtrip(ServerEvent::Relayed {
+                from: ServerId(5),
+                relayed: Relayed::Closed { session_id: 9 }
+            }),
+            ServerEvent::Relayed { relayed: Relayed::Closed { session_id: 9 }, .. }
+        ));
+
+        let load = LoadReport { queue_depth: 250, storage_latency: Duration::from_micros(1_500) };
+        assert!(matches!(
+            roundtrip(ServerEvent::LoadReported(load)),
+            ServerEvent::LoadReported(decoded) if decoded == load
+        ));
+    }
+
+    #[test]
+    fn hello_auth_tokens_are_not_logged() {
+        let hello = Hello {
+            version: 1,
+            capabilities: vec!["sync".into()],
+            auth_token: Some(b"secret-token".to_vec()),
+        };
+        let frame = Payload::Hello(hello).into_frame(FrameHeader::new(Opcode::Hello)).unwrap();
+        let record = EventRecord {
+            at: Duration::ZERO,
+            event: ServerEvent::FrameReceived { session_id: 1, frame },
+        };
+
+        let line = record.encode();
+        assert!(!line.contains(&to_hex(b"secret-token")));
+        let Some(decoded) = EventRecord::decode(&line).unwrap() else { panic!("no record") };
+        let ServerEvent::FrameReceived { frame, .. } = decoded.event else {
+            panic!("expected a frame")
+        };
+        let Payload::Hello(logged) = Payload::from_frame(frame).unwrap() else {
+            panic!("expected a Hello")
+        };
+        assert_eq!(logged.auth_token, None);
+        assert_eq!(logged.capabilities, vec!["sync".to_string()]);
+    }
+
+    #[test]
+    fn unrelated_and_malformed_lines() {
+        assert!(EventRecord::decode("INFO Server starting").unwrap().is_none());
+        assert_eq!(
+            EventRecord::decode("lfevent/1 12").unwrap_err(),
+            EventLogError::Missing("event kind")
+        );
+        assert!(matches!(
+            EventRecord::decode("lfevent/1 12 frame 1 zz").unwrap_err(),
+            EventLogError::Invalid { field: "frame", .. }
+        ));
+        assert!(matches!(
+            EventRecord::decode("lfevent/1 12 reboot").unwrap_err(),
+            EventLogError::UnknownKind(_)
+        ));
+    }
+}
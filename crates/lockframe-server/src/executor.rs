@@ -1,7 +1,21 @@
-//! Broadcast policy configuration for server I/O.
+//! Outbound scheduling and broadcast policy for server I/O.
 //!
 //! Defines how the server handles broadcast failures when sending frames
-//! to multiple recipients.
+//! to multiple recipients, and the per-session queues that order outgoing
+//! frames by [`Priority`] before they reach the transport. Queues can be
+//! bounded so a slow consumer cannot hold up fanout to everyone else; what
+//! happens when one fills is an [`OverflowPolicy`]. Consecutive frame writes
+//! for one room are coalesced by [`PersistBatch`] so they reach storage as a
+//! single commit.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+    time::Duration,
+};
+
+use lockframe_core::{backoff::Backoff, env::Environment};
+use lockframe_proto::{Frame, FrameTiming, Priority};
 
 /// Policy for handling broadcast send failures.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -21,9 +35,368 @@ pub enum BroadcastPolicy {
     },
 }
 
+impl BroadcastPolicy {
+    /// Number of times a failed send is retried before the recipient is
+    /// given up on.
+    pub fn retries(&self) -> u32 {
+        match self {
+            Self::BestEffort => 0,
+            Self::Retry { max_attempts, .. } => *max_attempts,
+        }
+    }
+
+    /// Delay before the given retry, counting from 1. Doubles with each
+    /// retry and is jittered with `env`'s RNG, so recipients whose sends
+    /// failed together are not retried together.
+    pub fn backoff<E: Environment>(&self, retry: u32, env: &E) -> Duration {
+        match self {
+            Self::BestEffort => Duration::ZERO,
+            Self::Retry { initial_backoff_ms, .. } => Backoff::new(
+                Duration::from_millis(*initial_backoff_ms),
+                Duration::from_millis(u64::MAX),
+            )
+            .jittered()
+            .delay(retry, env),
+        }
+    }
+}
+
+/// Default most frames queued for one session.
+pub const DEFAULT_SESSION_QUEUE_FRAMES: usize = 1024;
+
+/// What to do when a session's outbound queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Drop the session's oldest frame of its lowest queued priority class,
+    /// so control frames outlive bulk traffic.
+    #[default]
+    DropOldest,
+
+    /// Discard the session's queue and disconnect it. The client resyncs
+    /// when it reconnects.
+    DropConnection,
+
+    /// Queue the frame anyway and pause the frame's room until the session
+    /// drains below the limit. Nothing is lost, at the cost of the whole
+    /// room moving at the pace of its slowest member.
+    PauseRoom,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    /// Parse `drop-oldest`, `drop-connection` or `pause-room`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "drop-oldest" => Ok(Self::DropOldest),
+            "drop-connection" => Ok(Self::DropConnection),
+            "pause-room" => Ok(Self::PauseRoom),
+            _ => Err(format!(
+                "invalid overflow policy {value:?}, expected drop-oldest, drop-connection or \
+                 pause-room"
+            )),
+        }
+    }
+}
+
+/// Bound on each session's outbound queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimits {
+    /// Most frames queued for one session
+    pub max_frames: usize,
+    /// What to do when a session's queue is full
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self { max_frames: DEFAULT_SESSION_QUEUE_FRAMES, overflow: OverflowPolicy::default() }
+    }
+}
+
+/// What a push into a full queue did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Overflow {
+    /// The frame was queued and an older one dropped.
+    DroppedOldest(Frame),
+    /// Nothing was queued; the session's queue was discarded and the
+    /// session should be disconnected.
+    Disconnect,
+    /// The frame was queued past the limit and its room paused.
+    PauseRoom {
+        /// Room that is now paused
+        room_id: u128,
+    },
+}
+
+/// Outbound queue depths and overflow counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutboundMetrics {
+    /// Sessions with queued frames
+    pub sessions: usize,
+    /// Frames queued across all sessions
+    pub queued_frames: usize,
+    /// Frames queued for the most backed up session
+    pub deepest_queue: usize,
+    /// Frames dropped by [`OverflowPolicy::DropOldest`]
+    pub dropped_frames: u64,
+    /// Sessions disconnected by [`OverflowPolicy::DropConnection`]
+    pub disconnects: u64,
+    /// Rooms paused by [`OverflowPolicy::PauseRoom`]
+    pub room_pauses: u64,
+}
+
+/// A queued frame and the timing trailer to send after it.
+type Outgoing = (Frame, Option<FrameTiming>);
+
+/// Outgoing frames for one session, one FIFO per priority class.
+#[derive(Debug, Default)]
+struct SessionQueue {
+    classes: [VecDeque<Outgoing>; Priority::ALL.len()],
+    /// Rooms paused until this queue drains below the limit
+    paused: HashSet<u128>,
+}
+
+impl SessionQueue {
+    fn len(&self) -> usize {
+        self.classes.iter().map(VecDeque::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.classes.iter().all(VecDeque::is_empty)
+    }
+}
+
+/// Per-session outbound queues drained in priority order.
+///
+/// Frames are ordered first by [`Priority`] and then by arrival, so a commit
+/// queued behind a burst of app messages for the same session is still sent
+/// first. Ordering between sessions is unaffected.
+#[derive(Debug, Default)]
+pub struct OutboundQueues {
+    sessions: HashMap<u64, SessionQueue>,
+    limits: Option<QueueLimits>,
+    metrics: OutboundMetrics,
+}
+
+impl OutboundQueues {
+    /// Create empty, unbounded queues.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create empty queues holding at most `limits.max_frames` per session.
+    pub fn bounded(limits: QueueLimits) -> Self {
+        Self { limits: Some(limits), ..Self::default() }
+    }
+
+    /// Queue a frame for a session.
+    ///
+    /// Returns what was done to make room if the session's queue was full.
+    pub fn push(&mut self, session_id: u64, frame: Frame) -> Option<Overflow> {
+        self.push_timed(session_id, frame, None)
+    }
+
+    /// Queue a frame for a session along with its server timing trailer.
+    pub fn push_timed(
+        &mut self,
+        session_id: u64,
+        frame: Frame,
+        timing: Option<FrameTiming>,
+    ) -> Option<Overflow> {
+        let Some(limits) = self.limits.filter(|limits| self.len(session_id) >= limits.max_frames)
+        else {
+            self.enqueue(session_id, frame, timing);
+            return None;
+        };
+
+        match limits.overflow {
+            OverflowPolicy::DropOldest => {
+                let queue = self.enqueue(session_id, frame, timing);
+                let dropped = queue.classes.iter_mut().rev().find_map(VecDeque::pop_front);
+                self.metrics.dropped_frames = self.metrics.dropped_frames.saturating_add(1);
+                dropped.map(|(frame, _)| Overflow::DroppedOldest(frame))
+            },
+            OverflowPolicy::DropConnection => {
+                self.sessions.remove(&session_id);
+                self.metrics.disconnects = self.metrics.disconnects.saturating_add(1);
+                Some(Overflow::Disconnect)
+            },
+            OverflowPolicy::PauseRoom => {
+                // Frames outside any room are queued without pausing anything
+                let room_id = frame.header.room_id();
+                let queue = self.enqueue(session_id, frame, timing);
+                if room_id == 0 {
+                    return None;
+                }
+                if queue.paused.insert(room_id) {
+                    self.metrics.room_pauses = self.metrics.room_pauses.saturating_add(1);
+                }
+                Some(Overflow::PauseRoom { room_id })
+            },
+        }
+    }
+
+    /// Append a frame to its priority class of a session's queue.
+    fn enqueue(
+        &mut self,
+        session_id: u64,
+        frame: Frame,
+        timing: Option<FrameTiming>,
+    ) -> &mut SessionQueue {
+        let priority = Priority::of(&frame.header);
+        let queue = self.sessions.entry(session_id).or_default();
+        if let Some(class) = queue.classes.get_mut(priority.index()) {
+            class.push_back((frame, timing));
+        }
+        queue
+    }
+
+    /// Next frame to send to a session, highest priority first.
+    pub fn pop(&mut self, session_id: u64) -> Option<Frame> {
+        self.pop_timed(session_id).map(|(frame, _)| frame)
+    }
+
+    /// Like [`OutboundQueues::pop`], keeping the frame's timing trailer.
+    ///
+    /// Rooms paused by the session are resumed once it is below the limit.
+    pub fn pop_timed(&mut self, session_id: u64) -> Option<(Frame, Option<FrameTiming>)> {
+        let queue = self.sessions.get_mut(&session_id)?;
+        let frame = queue.classes.iter_mut().find_map(VecDeque::pop_front);
+
+        if self.limits.is_some_and(|limits| queue.len() < limits.max_frames) {
+            queue.paused.clear();
+        }
+        if queue.is_empty() {
+            self.sessions.remove(&session_id);
+        }
+
+        frame
+    }
+
+    /// Whether a full session queue has paused a room.
+    pub fn is_paused(&self, room_id: u128) -> bool {
+        self.sessions.values().any(|queue| queue.paused.contains(&room_id))
+    }
+
+    /// Current queue depths and overflow counters.
+    pub fn metrics(&self) -> OutboundMetrics {
+        let depths = self.sessions.values().map(SessionQueue::len);
+        OutboundMetrics {
+            sessions: self.sessions.len(),
+            queued_frames: depths.clone().sum(),
+            deepest_queue: depths.max().unwrap_or(0),
+            ..self.metrics
+        }
+    }
+
+    /// Remove and return every queued frame for a session, in send order.
+    pub fn drain(&mut self, session_id: u64) -> Vec<Frame> {
+        self.drain_timed(session_id).into_iter().map(|(frame, _)| frame).collect()
+    }
+
+    /// Like [`OutboundQueues::drain`], keeping each frame's timing trailer.
+    pub fn drain_timed(&mut self, session_id: u64) -> Vec<(Frame, Option<FrameTiming>)> {
+        self.sessions
+            .remove(&session_id)
+            .map(|queue| queue.classes.into_iter().flatten().collect())
+            .unwrap_or_default()
+    }
+
+    /// Sessions with queued frames, in ascending ID order.
+    pub fn pending_sessions(&self) -> Vec<u64> {
+        let mut sessions: Vec<u64> = self.sessions.keys().copied().collect();
+        sessions.sort_unstable();
+        sessions
+    }
+
+    /// Number of frames queued for a session.
+    pub fn len(&self, session_id: u64) -> usize {
+        self.sessions.get(&session_id).map_or(0, SessionQueue::len)
+    }
+
+    /// Whether no frames are queued for any session.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+/// Consecutive `PersistFrame` actions for one room, written as one batch.
+///
+/// Executors push every frame they are asked to persist and write out the
+/// batch handed back whenever the next frame cannot extend it, and before
+/// executing any other action so writes stay ordered with the rest of the
+/// action stream.
+#[derive(Debug, Default)]
+pub struct PersistBatch {
+    room_id: u128,
+    next_index: u64,
+    frames: Vec<Frame>,
+}
+
+impl PersistBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a frame at `log_index` in `room_id`.
+    ///
+    /// Returns the previous batch if the frame belongs to another room or
+    /// does not directly follow it; the frame then starts a new batch.
+    pub fn push(
+        &mut self,
+        room_id: u128,
+        log_index: u64,
+        frame: Frame,
+    ) -> Option<(u128, Vec<Frame>)> {
+        let flushed = if self.frames.is_empty()
+            || (room_id == self.room_id && log_index == self.next_index)
+        {
+            None
+        } else {
+            self.take()
+        };
+
+        if self.frames.is_empty() {
+            self.room_id = room_id;
+        }
+        self.next_index = log_index.saturating_add(1);
+        self.frames.push(frame);
+        flushed
+    }
+
+    /// Take the pending batch, leaving this one empty.
+    pub fn take(&mut self) -> Option<(u128, Vec<Frame>)> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        Some((self.room_id, std::mem::take(&mut self.frames)))
+    }
+
+    /// Number of frames in the pending batch.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether no frames are pending.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use lockframe_proto::{FrameHeader, Opcode};
+
     use super::*;
+    use crate::SystemEnv;
+
+    fn frame(opcode: Opcode, tag: u64) -> Frame {
+        let mut header = FrameHeader::new(opcode);
+        header.set_sender_id(tag);
+        Frame::new(header, Vec::new())
+    }
 
     #[test]
     fn broadcast_policy_default() {
@@ -42,4 +415,153 @@ mod tests {
             _ => panic!("expected Retry policy"),
         }
     }
+
+    #[test]
+    fn broadcast_policy_backoff_doubles() {
+        let policy = BroadcastPolicy::Retry { max_attempts: 3, initial_backoff_ms: 100 };
+        assert_eq!(policy.retries(), 3);
+        let env = SystemEnv::new();
+        for (retry, base) in [(1, 100), (2, 200), (3, 400)] {
+            let delay = policy.backoff(retry, &env).as_millis();
+            assert!((base / 2..=base).contains(&delay), "{delay}ms for retry {retry}");
+        }
+
+        assert_eq!(BroadcastPolicy::BestEffort.retries(), 0);
+        assert_eq!(BroadcastPolicy::BestEffort.backoff(1, &env), Duration::ZERO);
+        assert!(policy.backoff(200, &env) >= Duration::from_millis(u64::MAX / 2));
+    }
+
+    #[test]
+    fn control_frames_jump_bulk_backlog() {
+        let mut queues = OutboundQueues::new();
+        for tag in 0..3 {
+            queues.push(1, frame(Opcode::AppMessage, tag));
+        }
+        queues.push(1, frame(Opcode::Commit, 10));
+        queues.push(1, frame(Opcode::SyncResponse, 11));
+
+        let order: Vec<(Option<Opcode>, u64)> = queues
+            .drain(1)
+            .iter()
+            .map(|f| (f.header.opcode_enum(), f.header.sender_id()))
+            .collect();
+
+        assert_eq!(order, vec![
+            (Some(Opcode::Commit), 10),
+            (Some(Opcode::SyncResponse), 11),
+            (Some(Opcode::AppMessage), 0),
+            (Some(Opcode::AppMessage), 1),
+            (Some(Opcode::AppMessage), 2),
+        ]);
+        assert!(queues.is_empty());
+    }
+
+    #[test]
+    fn queues_are_per_session() {
+        let mut queues = OutboundQueues::new();
+        queues.push(2, frame(Opcode::AppMessage, 0));
+        queues.push(1, frame(Opcode::Commit, 1));
+
+        assert_eq!(queues.pending_sessions(), vec![1, 2]);
+        assert_eq!(queues.len(2), 1);
+
+        assert_eq!(queues.pop(2).map(|f| f.header.sender_id()), Some(0));
+        assert!(queues.pop(2).is_none());
+        assert_eq!(queues.pending_sessions(), vec![1]);
+    }
+
+    #[test]
+    fn full_queue_drops_oldest_bulk_frame() {
+        let limits = QueueLimits { max_frames: 2, overflow: OverflowPolicy::DropOldest };
+        let mut queues = OutboundQueues::bounded(limits);
+        assert!(queues.push(1, frame(Opcode::AppMessage, 0)).is_none());
+        assert!(queues.push(1, frame(Opcode::Commit, 1)).is_none());
+
+        let Some(Overflow::DroppedOldest(dropped)) = queues.push(1, frame(Opcode::AppMessage, 2))
+        else {
+            panic!("expected a dropped frame");
+        };
+        assert_eq!(dropped.header.sender_id(), 0);
+
+        let order: Vec<u64> = queues.drain(1).iter().map(|f| f.header.sender_id()).collect();
+        assert_eq!(order, vec![1, 2]);
+        assert_eq!(queues.metrics().dropped_frames, 1);
+    }
+
+    #[test]
+    fn full_queue_disconnects_session() {
+        let limits = QueueLimits { max_frames: 1, overflow: OverflowPolicy::DropConnection };
+        let mut queues = OutboundQueues::bounded(limits);
+        queues.push(1, frame(Opcode::AppMessage, 0));
+        queues.push(2, frame(Opcode::AppMessage, 0));
+
+        assert_eq!(queues.push(1, frame(Opcode::AppMessage, 1)), Some(Overflow::Disconnect));
+        assert_eq!(queues.len(1), 0);
+        assert_eq!(queues.len(2), 1);
+        assert_eq!(queues.metrics().disconnects, 1);
+    }
+
+    #[test]
+    fn full_queue_pauses_room_until_drained() {
+        let limits = QueueLimits { max_frames: 2, overflow: OverflowPolicy::PauseRoom };
+        let mut queues = OutboundQueues::bounded(limits);
+        let mut message = frame(Opcode::AppMessage, 0);
+        message.header.set_room_id(7);
+        for _ in 0..2 {
+            assert!(queues.push(1, message.clone()).is_none());
+        }
+
+        assert_eq!(queues.push(1, message.clone()), Some(Overflow::PauseRoom { room_id: 7 }));
+        assert_eq!(queues.push(1, message), Some(Overflow::PauseRoom { room_id: 7 }));
+        assert!(queues.is_paused(7));
+        assert_eq!(queues.metrics(), OutboundMetrics {
+            sessions: 1,
+            queued_frames: 4,
+            deepest_queue: 4,
+            room_pauses: 1,
+            ..OutboundMetrics::default()
+        });
+
+        queues.pop(1);
+        queues.pop(1);
+        assert!(queues.is_paused(7));
+        queues.pop(1);
+        assert!(!queues.is_paused(7));
+    }
+
+    #[test]
+    fn overflow_policies_parse() {
+        assert_eq!("pause-room".parse(), Ok(OverflowPolicy::PauseRoom));
+        assert_eq!("drop-connection".parse(), Ok(OverflowPolicy::DropConnection));
+        assert!("drop-newest".parse::<OverflowPolicy>().is_err());
+    }
+
+    fn indexed(room_id: u128, log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_log_index(log_index);
+        Frame::new(header, Vec::new())
+    }
+
+    #[test]
+    fn persist_batch_coalesces_consecutive_frames() {
+        let mut batch = PersistBatch::new();
+        for log_index in 0..3 {
+            assert!(batch.push(1, log_index, indexed(1, log_index)).is_none());
+        }
+        assert_eq!(batch.len(), 3);
+
+        // Another room closes the batch
+        let (room_id, frames) = batch.push(2, 0, indexed(2, 0)).unwrap();
+        assert_eq!(room_id, 1);
+        assert_eq!(frames.iter().map(|f| f.header.log_index()).collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        // So does a gap in the log
+        let (room_id, frames) = batch.push(2, 5, indexed(2, 5)).unwrap();
+        assert_eq!((room_id, frames.len()), (2, 1));
+
+        assert_eq!(batch.take().map(|(room_id, frames)| (room_id, frames.len())), Some((2, 1)));
+        assert!(batch.is_empty());
+        assert!(batch.take().is_none());
+    }
 }
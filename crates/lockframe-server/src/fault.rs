@@ -0,0 +1,77 @@
+//! Fault injection inside the driver, for tests.
+//!
+//! A [`FaultHook`] installed with `ServerDriver::set_fault_hook`, which only
+//! the `fault-injection` feature builds, is consulted at fixed stages of frame
+//! processing and may crash the driver or delay what follows, which lets
+//! crash-consistency tests stop the server between two actions of one frame
+//! rather than only between frames.
+//!
+//! The driver does no I/O, so a fault is reported as
+//! [`ServerError::Injected`](crate::DriverError::Injected): the actions that
+//! happened before the fault point and those after it are handed back
+//! separately. A runtime simulating a crash executes the first and drops the
+//! driver; one simulating a delay executes the second once the delay is over.
+//!
+//! The types here are built either way, so enabling the feature somewhere in
+//! a build only adds the hook and never changes what [`ServerError`] is.
+//!
+//! [`ServerError`]: crate::DriverError
+
+use std::time::Duration;
+
+use lockframe_proto::Frame;
+
+use crate::ServerAction;
+
+/// Stage of frame processing a [`FaultHook`] is consulted at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultPoint {
+    /// A room frame passed admission and is about to be sequenced
+    BeforeSequencing,
+    /// The frames the event sequenced have been persisted
+    AfterPersist,
+    /// The first broadcast the event causes is about to be sent
+    BeforeBroadcast,
+}
+
+/// What a [`FaultHook`] does at a fault point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Carry on as normal
+    Continue,
+    /// Stop the server at this point
+    Crash,
+    /// Hold back everything after this point
+    Delay(Duration),
+}
+
+/// Decides which fault, if any, to inject at each fault point.
+pub trait FaultHook: Send {
+    /// Fault to inject at `point` while processing `frame`.
+    ///
+    /// `frame` is the frame about to be sequenced, the last frame persisted
+    /// or the first frame broadcast, depending on `point`.
+    fn at(&mut self, point: FaultPoint, frame: &Frame) -> Fault;
+}
+
+impl<F> FaultHook for F
+where
+    F: FnMut(FaultPoint, &Frame) -> Fault + Send,
+{
+    fn at(&mut self, point: FaultPoint, frame: &Frame) -> Fault {
+        self(point, frame)
+    }
+}
+
+/// A fault injected while processing an event.
+#[derive(Debug)]
+pub struct InjectedFault {
+    /// Where the fault was injected
+    pub point: FaultPoint,
+    /// The fault, [`Fault::Crash`] or [`Fault::Delay`]
+    pub fault: Fault,
+    /// Actions that took effect before the fault point
+    pub completed: Vec<ServerAction>,
+    /// Actions after the fault point: lost in a crash, late after a delay
+    pub pending: Vec<ServerAction>,
+}
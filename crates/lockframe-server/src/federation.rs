@@ -0,0 +1,450 @@
+//! Federation between servers.
+//!
+//! Several servers can host members of one room. Each room has a home server
+//! that owns its sequencer: members connected elsewhere have their frames
+//! forwarded to the home as [`Relayed::Submit`], and every frame the home
+//! sequences is relayed back to the servers hosting members as
+//! [`Relayed::Sequenced`], which store and broadcast it as if they had
+//! sequenced it themselves. Answers meant for one member only (rejections,
+//! sync and proof responses) travel back as [`Relayed::Reply`].
+//!
+//! Sessions on a forwarding server are stood in for on the home by a local
+//! session ID of their own, so they share no state with the home's sessions.
+//! The forwarding server sends [`Relayed::Closed`] when such a session
+//! closes, and the home releases the stand-in.
+//!
+//! Links between servers may duplicate, reorder or lose relays. A server
+//! applies each `(room, log_index)` once and in log order, holding up to
+//! [`MAX_HELD_RELAYS`] frames that arrive ahead of a gap. A gap still open
+//! after [`RESEND_INTERVAL`] is taken to be a loss rather than reordering,
+//! and the home is asked to fill it with [`Relayed::Resend`], again every
+//! interval until it is filled.
+//!
+//! The driver only decides what to relay where; carrying relays between
+//! servers is left to the runtime.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, hash_map::Entry},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use lockframe_proto::Frame;
+
+/// Frames held per room ahead of a gap. Frames further ahead are dropped, to
+/// be resent once the gap is filled.
+pub const MAX_HELD_RELAYS: usize = 1024;
+
+/// How long a gap waits for the frames missing before they are asked for,
+/// and between asking again.
+pub const RESEND_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Carries relays from this server to one federated server.
+///
+/// Whatever connects the two servers ends in the other server's
+/// `ServerHandle::relay`. Called on the runtime's action executor, so a link
+/// over the network should hand relays off to a task of its own.
+pub trait RelayLink: Send + Sync {
+    /// Send `relayed` to the server behind the link.
+    fn send(&self, relayed: Relayed);
+}
+
+impl<F> RelayLink for F
+where
+    F: Fn(Relayed) + Send + Sync,
+{
+    fn send(&self, relayed: Relayed) {
+        self(relayed);
+    }
+}
+
+/// Identity of a server in a federation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServerId(pub u64);
+
+impl fmt::Display for ServerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "server {:016x}", self.0)
+    }
+}
+
+/// A frame passed between federated servers.
+#[derive(Debug, Clone)]
+pub enum Relayed {
+    /// A member's frame, forwarded to the room's home to be processed there
+    Submit {
+        /// Session on the forwarding server the frame came from
+        session_id: u64,
+        /// Frame as the member sent it
+        frame: Frame,
+    },
+
+    /// A frame the room's home sequenced
+    Sequenced {
+        /// Log index the home assigned
+        log_index: u64,
+        /// Sequenced frame
+        frame: Frame,
+        /// Session on the receiving server that submitted the frame, if the
+        /// home's broadcast of it left the sender out
+        origin: Option<u64>,
+    },
+
+    /// The home's answer to a forwarded frame, for the session that sent it
+    Reply {
+        /// Session on the receiving server to deliver to
+        session_id: u64,
+        /// Answer frame
+        frame: Frame,
+    },
+
+    /// A request to the home of `room_id` for its frames from
+    /// `from_log_index` on, to fill a gap in the relays received
+    Resend {
+        /// Room homed on the receiving server
+        room_id: u128,
+        /// First log index missing
+        from_log_index: u64,
+    },
+
+    /// A session whose frames were forwarded to the receiving server closed
+    Closed {
+        /// Session on the sending server
+        session_id: u64,
+    },
+}
+
+/// Sequenced frames received for a room homed elsewhere.
+#[derive(Debug)]
+struct RelayLog {
+    /// Log index of the next frame to apply
+    next: u64,
+    /// Frames received ahead of `next`, by log index
+    held: BTreeMap<u64, Frame>,
+    /// Start of the open gap, and when it was noticed or last asked to be
+    /// filled
+    gap: Option<(u64, Instant)>,
+}
+
+/// Room homes, the servers hosting their members, and relay dedup.
+#[derive(Debug, Default)]
+pub struct Federation {
+    /// This server
+    local: ServerId,
+    /// Rooms homed on another server
+    homes: HashMap<u128, ServerId>,
+    /// Servers hosting members of rooms homed here
+    peers: HashMap<u128, BTreeSet<ServerId>>,
+    /// Progress through rooms homed elsewhere
+    logs: HashMap<u128, RelayLog>,
+    /// Relayed frames dropped as already applied
+    duplicates: u64,
+    /// Servers each local session had frames forwarded to
+    forwarded: HashMap<u64, BTreeSet<ServerId>>,
+    /// Local stand-in for each session on another server that forwarded
+    /// frames here
+    proxies: HashMap<(ServerId, u64), u64>,
+    /// Session on another server each stand-in is for
+    remote_sessions: HashMap<u64, (ServerId, u64)>,
+}
+
+impl Federation {
+    /// Federation state for the server `local`, homing every room itself.
+    pub fn new(local: ServerId) -> Self {
+        Self { local, ..Self::default() }
+    }
+
+    /// This server.
+    pub fn local(&self) -> ServerId {
+        self.local
+    }
+
+    /// Set the server that sequences `room_id`.
+    ///
+    /// Pointing a room at this server makes it local again.
+    pub fn set_home(&mut self, room_id: u128, home: ServerId) {
+        if home == self.local {
+            self.homes.remove(&room_id);
+            self.logs.remove(&room_id);
+        } else {
+            self.homes.insert(room_id, home);
+            self.peers.remove(&room_id);
+        }
+    }
+
+    /// Server that sequences `room_id`.
+    pub fn home(&self, room_id: u128) -> ServerId {
+        self.homes.get(&room_id).copied().unwrap_or(self.local)
+    }
+
+    /// Home of `room_id` if it is another server.
+    pub fn remote_home(&self, room_id: u128) -> Option<ServerId> {
+        self.homes.get(&room_id).copied()
+    }
+
+    /// Record that `peer` hosts members of `room_id`, a room homed here.
+    ///
+    /// Returns `false` if the peer was already known, or the room is homed
+    /// elsewhere.
+    pub fn add_peer(&mut self, room_id: u128, peer: ServerId) -> bool {
+        if peer == self.local || self.homes.contains_key(&room_id) {
+            return false;
+        }
+        self.peers.entry(room_id).or_default().insert(peer)
+    }
+
+    /// Stop relaying `room_id` to `peer`.
+    pub fn remove_peer(&mut self, room_id: u128, peer: ServerId) -> bool {
+        let Some(peers) = self.peers.get_mut(&room_id) else {
+            return false;
+        };
+        let removed = peers.remove(&peer);
+        if peers.is_empty() {
+            self.peers.remove(&room_id);
+        }
+        removed
+    }
+
+    /// Servers hosting members of `room_id`, ascending.
+    pub fn peers(&self, room_id: u128) -> impl Iterator<Item = ServerId> + '_ {
+        self.peers.get(&room_id).into_iter().flatten().copied()
+    }
+
+    /// Record that `session_id`, connected here, had a frame forwarded to
+    /// `home`.
+    pub fn forwarded(&mut self, session_id: u64, home: ServerId) {
+        self.forwarded.entry(session_id).or_default().insert(home);
+    }
+
+    /// Forget a local session that closed, returning the servers to tell.
+    pub fn close_forwarded(&mut self, session_id: u64) -> BTreeSet<ServerId> {
+        self.forwarded.remove(&session_id).unwrap_or_default()
+    }
+
+    /// Local stand-in for `session_id` on `server`, allocated with
+    /// `allocate` the first time the session forwards a frame here.
+    pub fn proxy(
+        &mut self,
+        server: ServerId,
+        session_id: u64,
+        allocate: impl FnOnce() -> u64,
+    ) -> u64 {
+        match self.proxies.entry((server, session_id)) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                let proxy = allocate();
+                self.remote_sessions.insert(proxy, (server, session_id));
+                *entry.insert(proxy)
+            },
+        }
+    }
+
+    /// Server and session a local stand-in is for.
+    pub fn remote_session(&self, proxy: u64) -> Option<(ServerId, u64)> {
+        self.remote_sessions.get(&proxy).copied()
+    }
+
+    /// Sessions on other servers stood in for here.
+    pub fn remote_session_count(&self) -> usize {
+        self.remote_sessions.len()
+    }
+
+    /// Forget the stand-in for a closed session on `server`, returning it.
+    pub fn release_proxy(&mut self, server: ServerId, session_id: u64) -> Option<u64> {
+        let proxy = self.proxies.remove(&(server, session_id))?;
+        self.remote_sessions.remove(&proxy);
+        Some(proxy)
+    }
+
+    /// Accept a frame the home of `room_id` sequenced at `log_index`.
+    ///
+    /// `stored_next` is where the room's local copy of the log ends; it is
+    /// consulted the first time the room is seen. Returns the frames that are
+    /// now next in log order, which is none for a duplicate or a frame ahead
+    /// of a gap. Frames more than [`MAX_HELD_RELAYS`] ahead are dropped.
+    pub fn receive<E>(
+        &mut self,
+        room_id: u128,
+        log_index: u64,
+        frame: Frame,
+        stored_next: impl FnOnce() -> Result<u64, E>,
+    ) -> Result<Vec<(u64, Frame)>, E> {
+        let log = match self.logs.entry(room_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let next = stored_next()?;
+                entry.insert(RelayLog { next, held: BTreeMap::new(), gap: None })
+            },
+        };
+
+        if log_index < log.next || log.held.contains_key(&log_index) {
+            self.duplicates = self.duplicates.saturating_add(1);
+            return Ok(Vec::new());
+        }
+        // The frame is asked for again once the frames before it arrive
+        if log_index > log.next && log.held.len() >= MAX_HELD_RELAYS {
+            return Ok(Vec::new());
+        }
+        log.held.insert(log_index, frame);
+
+        let mut ready = Vec::new();
+        while let Some(frame) = log.held.remove(&log.next) {
+            ready.push((log.next, frame));
+            log.next = log.next.saturating_add(1);
+        }
+        Ok(ready)
+    }
+
+    /// Frames held for `room_id` until a gap before them is filled.
+    pub fn held(&self, room_id: u128) -> usize {
+        self.logs.get(&room_id).map_or(0, |log| log.held.len())
+    }
+
+    /// Gaps to ask the homes of their rooms to fill, as
+    /// [`Relayed::Resend`] to send to each home.
+    ///
+    /// A gap is noticed the first time it is seen open here, and asked to be
+    /// filled once it has stayed open for [`RESEND_INTERVAL`], then again
+    /// every interval. A gap that moves on without closing is noticed anew.
+    pub fn resends(&mut self, now: Instant) -> Vec<(ServerId, Relayed)> {
+        let mut resends = Vec::new();
+        for (&room_id, log) in &mut self.logs {
+            let Some(&home) = self.homes.get(&room_id) else {
+                continue;
+            };
+            if log.held.is_empty() {
+                log.gap = None;
+                continue;
+            }
+            match log.gap {
+                Some((from, since)) if from == log.next => {
+                    if now.saturating_duration_since(since) >= RESEND_INTERVAL {
+                        log.gap = Some((from, now));
+                        resends.push((home, Relayed::Resend { room_id, from_log_index: from }));
+                    }
+                },
+                _ => log.gap = Some((log.next, now)),
+            }
+        }
+        resends
+    }
+
+    /// Relayed frames dropped because they were already applied.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+
+    const ROOM: u128 = 0x4242;
+
+    fn frame(log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(ROOM);
+        header.set_log_index(log_index);
+        Frame::new(header, Bytes::new())
+    }
+
+    fn receive(federation: &mut Federation, log_index: u64) -> Vec<u64> {
+        receive_at(federation, log_index, 3)
+    }
+
+    fn receive_at(federation: &mut Federation, log_index: u64, stored_next: u64) -> Vec<u64> {
+        federation
+            .receive(ROOM, log_index, frame(log_index), || Ok::<_, ()>(stored_next))
+            .unwrap()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    #[test]
+    fn relays_apply_once_and_in_log_order() {
+        let mut federation = Federation::new(ServerId(2));
+        federation.set_home(ROOM, ServerId(1));
+
+        assert!(receive(&mut federation, 2).is_empty());
+        assert!(receive(&mut federation, 5).is_empty());
+        assert_eq!(federation.held(ROOM), 1);
+        assert_eq!(receive(&mut federation, 3), vec![3]);
+        assert!(receive(&mut federation, 5).is_empty());
+        assert_eq!(receive(&mut federation, 4), vec![4, 5]);
+        assert!(receive(&mut federation, 4).is_empty());
+
+        assert_eq!(federation.held(ROOM), 0);
+        assert_eq!(federation.duplicates(), 3);
+    }
+
+    #[test]
+    fn gaps_are_bounded_and_asked_to_be_filled() {
+        let mut federation = Federation::new(ServerId(2));
+        federation.set_home(ROOM, ServerId(1));
+        let now = Instant::now();
+        let requested = |federation: &mut Federation, now| -> Vec<u64> {
+            federation
+                .resends(now)
+                .into_iter()
+                .map(|(home, relayed)| match relayed {
+                    Relayed::Resend { room_id: ROOM, from_log_index } if home == ServerId(1) => {
+                        from_log_index
+                    },
+                    other => panic!("unexpected relay {other:?}"),
+                })
+                .collect()
+        };
+
+        let last = u64::try_from(MAX_HELD_RELAYS).unwrap() + 1;
+        for log_index in 1..=last {
+            assert!(receive_at(&mut federation, log_index, 0).is_empty());
+        }
+        assert_eq!(federation.held(ROOM), MAX_HELD_RELAYS);
+
+        // Reordering gets an interval to fill the gap before it is a loss
+        assert!(requested(&mut federation, now).is_empty());
+        assert_eq!(requested(&mut federation, now + RESEND_INTERVAL), vec![0]);
+        assert!(requested(&mut federation, now + RESEND_INTERVAL).is_empty());
+        assert_eq!(requested(&mut federation, now + RESEND_INTERVAL * 2), vec![0]);
+
+        // Filling the gap applies what was held; the dropped frame is next
+        assert_eq!(receive(&mut federation, 0).len(), MAX_HELD_RELAYS + 1);
+        assert!(requested(&mut federation, now + RESEND_INTERVAL * 3).is_empty());
+        assert_eq!(receive(&mut federation, last), vec![last]);
+    }
+
+    #[test]
+    fn remote_sessions_get_their_own_stand_ins() {
+        let mut federation = Federation::new(ServerId(1));
+        let proxy = federation.proxy(ServerId(2), 7, || 100);
+        assert_eq!(federation.proxy(ServerId(2), 7, || 101), proxy);
+        assert_eq!(federation.proxy(ServerId(3), 7, || 102), 102);
+        assert_eq!(federation.remote_session(proxy), Some((ServerId(2), 7)));
+
+        assert_eq!(federation.release_proxy(ServerId(2), 7), Some(proxy));
+        assert_eq!(federation.remote_session(proxy), None);
+        assert_eq!(federation.release_proxy(ServerId(2), 7), None);
+    }
+
+    #[test]
+    fn only_rooms_homed_here_have_peers() {
+        let mut federation = Federation::new(ServerId(1));
+        assert_eq!(federation.home(ROOM), ServerId(1));
+        assert!(federation.add_peer(ROOM, ServerId(2)));
+        assert!(!federation.add_peer(ROOM, ServerId(2)));
+        assert!(!federation.add_peer(ROOM, ServerId(1)));
+        assert_eq!(federation.peers(ROOM).collect::<Vec<_>>(), vec![ServerId(2)]);
+
+        federation.set_home(ROOM, ServerId(3));
+        assert_eq!(federation.remote_home(ROOM), Some(ServerId(3)));
+        assert_eq!(federation.peers(ROOM).count(), 0);
+        assert!(!federation.add_peer(ROOM, ServerId(2)));
+
+        federation.set_home(ROOM, ServerId(1));
+        assert_eq!(federation.remote_home(ROOM), None);
+    }
+}
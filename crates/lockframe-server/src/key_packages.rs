@@ -0,0 +1,341 @@
+//! KeyPackage directory.
+//!
+//! Members upload KeyPackages with `KeyPackage` frames so others can add them
+//! to rooms while they are offline, and a room member about to add someone
+//! claims one of theirs with `ClaimKeyPackage`. Each KeyPackage is handed out
+//! once, which makes the directory a target: claiming a member's KeyPackages
+//! in a loop leaves nobody able to add them. Claims are limited three ways:
+//!
+//! - Each claimer may claim [`KeyPackageConfig::claims_per_window`] KeyPackages
+//!   per [`KeyPackageConfig::claim_window`]
+//! - A claim names the room the claimed member is to be added to, and only
+//!   members of that room may make it. The claim stays open until a commit
+//!   adding the claimed member to the room is sequenced, and a claimer may hold
+//!   only [`KeyPackageConfig::max_open_claims`] open claims per room, so claims
+//!   must be spent on real Adds
+//! - Claims that leave a member fewer than [`KeyPackageConfig::low_watermark`]
+//!   KeyPackages are counted and reported, so operators can alert on them and
+//!   clients upload more
+//!
+//! The directory lives in memory; members upload again after a restart.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+/// Default number of KeyPackages kept per member.
+pub const DEFAULT_MAX_KEY_PACKAGES: usize = 100;
+
+/// Default number of claims one claimer may make per window.
+pub const DEFAULT_CLAIMS_PER_WINDOW: u32 = 20;
+
+/// Default length of a claim rate window.
+pub const DEFAULT_CLAIM_WINDOW: Duration = Duration::from_secs(60);
+
+/// Default number of claims one claimer may hold open per room.
+pub const DEFAULT_MAX_OPEN_CLAIMS: usize = 10;
+
+/// Default number of KeyPackages below which a member is reported.
+pub const DEFAULT_LOW_WATERMARK: usize = 5;
+
+/// Limits on the KeyPackage directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPackageConfig {
+    /// KeyPackages kept per member; uploading more drops the oldest
+    pub max_per_member: usize,
+    /// Claims one claimer may make per `claim_window`
+    pub claims_per_window: u32,
+    /// Length of a claim rate window
+    pub claim_window: Duration,
+    /// Claims one claimer may hold open per room, waiting for the commit
+    /// that adds the claimed member
+    pub max_open_claims: usize,
+    /// Members left with fewer KeyPackages than this are reported
+    pub low_watermark: usize,
+}
+
+impl Default for KeyPackageConfig {
+    fn default() -> Self {
+        Self {
+            max_per_member: DEFAULT_MAX_KEY_PACKAGES,
+            claims_per_window: DEFAULT_CLAIMS_PER_WINDOW,
+            claim_window: DEFAULT_CLAIM_WINDOW,
+            max_open_claims: DEFAULT_MAX_OPEN_CLAIMS,
+            low_watermark: DEFAULT_LOW_WATERMARK,
+        }
+    }
+}
+
+/// Server-wide KeyPackage directory counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyPackageMetrics {
+    /// KeyPackages uploaded
+    pub uploaded: u64,
+    /// KeyPackages handed out
+    pub claimed: u64,
+    /// Claims refused for exceeding the claimer's rate
+    pub rate_limited: u64,
+    /// Claims refused for too many claims open without a commit
+    pub unbound: u64,
+    /// Claims for a member with no KeyPackages left
+    pub exhausted: u64,
+    /// Claims that left the member below the low watermark
+    pub low: u64,
+}
+
+impl KeyPackageMetrics {
+    /// Add the counters of `other`, e.g. another server's, to these.
+    pub fn merge(&mut self, other: &Self) {
+        self.uploaded = self.uploaded.saturating_add(other.uploaded);
+        self.claimed = self.claimed.saturating_add(other.claimed);
+        self.rate_limited = self.rate_limited.saturating_add(other.rate_limited);
+        self.unbound = self.unbound.saturating_add(other.unbound);
+        self.exhausted = self.exhausted.saturating_add(other.exhausted);
+        self.low = self.low.saturating_add(other.low);
+    }
+}
+
+/// Why a claim was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ClaimError {
+    /// Claimer made too many claims this window
+    #[error("too many KeyPackage claims, retry in {retry_after:?}")]
+    RateLimited {
+        /// Time until the window ends
+        retry_after: Duration,
+    },
+
+    /// Claimer holds too many claims in the room no commit has used
+    #[error("{open} KeyPackage claims in this room still wait for a commit adding them")]
+    TooManyOpen {
+        /// Claims the claimer holds open in the room
+        open: usize,
+    },
+
+    /// Member has no KeyPackages left
+    #[error("member {member_id} has no KeyPackages left")]
+    Exhausted {
+        /// Member whose KeyPackage was claimed
+        member_id: u64,
+    },
+}
+
+/// KeyPackage handed out for a claim.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claimed {
+    /// Serialized KeyPackage
+    pub key_package: Vec<u8>,
+    /// KeyPackages the member has left
+    pub remaining: usize,
+    /// Whether `remaining` is below the low watermark
+    pub low: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClaimWindow {
+    started: Instant,
+    claims: u32,
+}
+
+/// Uploaded KeyPackages and the claims made on them.
+#[derive(Debug, Default)]
+pub struct KeyPackageDirectory {
+    config: KeyPackageConfig,
+    /// Each member's KeyPackages, oldest first
+    packages: HashMap<u64, VecDeque<Vec<u8>>>,
+    /// Each claimer's current rate window
+    windows: HashMap<u64, ClaimWindow>,
+    /// Members each `(room_id, claimer)` claimed and no commit added yet
+    open: HashMap<(u128, u64), Vec<u64>>,
+    metrics: KeyPackageMetrics,
+}
+
+impl KeyPackageDirectory {
+    /// Create an empty directory with `config` limits.
+    pub fn new(config: KeyPackageConfig) -> Self {
+        Self { config, ..Self::default() }
+    }
+
+    /// Directory limits.
+    pub fn config(&self) -> &KeyPackageConfig {
+        &self.config
+    }
+
+    /// Keep a KeyPackage of `member_id`, dropping their oldest once they
+    /// have [`KeyPackageConfig::max_per_member`]. Returns how many the
+    /// member has.
+    pub fn publish(&mut self, member_id: u64, key_package: Vec<u8>) -> usize {
+        let packages = self.packages.entry(member_id).or_default();
+        packages.push_back(key_package);
+        while packages.len() > self.config.max_per_member {
+            packages.pop_front();
+        }
+        self.metrics.uploaded = self.metrics.uploaded.saturating_add(1);
+        packages.len()
+    }
+
+    /// KeyPackages `member_id` has left.
+    pub fn remaining(&self, member_id: u64) -> usize {
+        self.packages.get(&member_id).map_or(0, VecDeque::len)
+    }
+
+    /// Hand `claimer` a KeyPackage of `member_id` to add them to `room_id`.
+    ///
+    /// The caller checks that `claimer` is a member of `room_id`. The claim
+    /// stays open until [`settle`](Self::settle) sees `member_id` added to
+    /// the room.
+    pub fn claim(
+        &mut self,
+        claimer: u64,
+        room_id: u128,
+        member_id: u64,
+        now: Instant,
+    ) -> Result<Claimed, ClaimError> {
+        let config = self.config;
+        let window = self.windows.entry(claimer).or_insert(ClaimWindow { started: now, claims: 0 });
+        let elapsed = now.saturating_duration_since(window.started);
+        if elapsed >= config.claim_window {
+            *window = ClaimWindow { started: now, claims: 0 };
+        }
+        if window.claims >= config.claims_per_window {
+            self.metrics.rate_limited = self.metrics.rate_limited.saturating_add(1);
+            let retry_after = config.claim_window.saturating_sub(elapsed);
+            return Err(ClaimError::RateLimited { retry_after });
+        }
+
+        let open = self.open.get(&(room_id, claimer)).map_or(0, Vec::len);
+        if open >= config.max_open_claims {
+            self.metrics.unbound = self.metrics.unbound.saturating_add(1);
+            return Err(ClaimError::TooManyOpen { open });
+        }
+
+        // A refused claim of an exhausted member still counts against the
+        // claimer, so probing for members costs rate too
+        window.claims = window.claims.saturating_add(1);
+        let Some(key_package) = self.packages.get_mut(&member_id).and_then(VecDeque::pop_front)
+        else {
+            self.metrics.exhausted = self.metrics.exhausted.saturating_add(1);
+            return Err(ClaimError::Exhausted { member_id });
+        };
+
+        self.open.entry((room_id, claimer)).or_default().push(member_id);
+        let remaining = self.remaining(member_id);
+        if remaining == 0 {
+            self.packages.remove(&member_id);
+        }
+        let low = remaining < config.low_watermark;
+        self.metrics.claimed = self.metrics.claimed.saturating_add(1);
+        if low {
+            self.metrics.low = self.metrics.low.saturating_add(1);
+        }
+        Ok(Claimed { key_package, remaining, low })
+    }
+
+    /// Close the claims on `added`, who a commit just added to `room_id`.
+    pub fn settle(&mut self, room_id: u128, added: &[u64]) {
+        self.open.retain(|&(claimed_in, _), members| {
+            if claimed_in == room_id {
+                members.retain(|member_id| !added.contains(member_id));
+            }
+            !members.is_empty()
+        });
+    }
+
+    /// Drop rate windows that have ended.
+    pub fn expire(&mut self, now: Instant) {
+        let window = self.config.claim_window;
+        self.windows.retain(|_, w| now.saturating_duration_since(w.started) < window);
+    }
+
+    /// Directory counters.
+    pub fn metrics(&self) -> KeyPackageMetrics {
+        self.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(config: KeyPackageConfig) -> KeyPackageDirectory {
+        let mut directory = KeyPackageDirectory::new(config);
+        for i in 0..4u8 {
+            directory.publish(7, vec![i]);
+        }
+        directory
+    }
+
+    #[test]
+    fn claims_hand_out_each_key_package_once() {
+        let now = Instant::now();
+        let mut directory = directory(KeyPackageConfig::default());
+
+        let claimed = directory.claim(1, 0x10, 7, now).unwrap();
+        assert_eq!(claimed, Claimed { key_package: vec![0], remaining: 3, low: true });
+        assert_eq!(directory.claim(1, 0x10, 7, now).unwrap().key_package, vec![1]);
+        assert_eq!(directory.remaining(7), 2);
+
+        let result = directory.claim(1, 0x10, 8, now);
+        assert_eq!(result, Err(ClaimError::Exhausted { member_id: 8 }));
+        assert_eq!(directory.metrics().claimed, 2);
+        assert_eq!(directory.metrics().low, 2);
+        assert_eq!(directory.metrics().exhausted, 1);
+    }
+
+    #[test]
+    fn uploads_beyond_the_cap_drop_the_oldest() {
+        let config = KeyPackageConfig { max_per_member: 2, ..Default::default() };
+        let mut directory = directory(config);
+
+        assert_eq!(directory.remaining(7), 2);
+        assert_eq!(directory.claim(1, 0x10, 7, Instant::now()).unwrap().key_package, vec![2]);
+    }
+
+    #[test]
+    fn claims_are_rate_limited_per_claimer() {
+        let config = KeyPackageConfig {
+            claims_per_window: 2,
+            claim_window: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let mut directory = directory(config);
+        let start = Instant::now();
+
+        directory.claim(1, 0x10, 7, start).unwrap();
+        directory.claim(1, 0x10, 7, start).unwrap();
+        let result = directory.claim(1, 0x10, 7, start + Duration::from_secs(4));
+        assert_eq!(result, Err(ClaimError::RateLimited { retry_after: Duration::from_secs(6) }));
+
+        // Other claimers have their own window, and the window ends
+        directory.claim(2, 0x10, 7, start).unwrap();
+        directory.claim(1, 0x10, 7, start + Duration::from_secs(10)).unwrap();
+        assert_eq!(directory.metrics().rate_limited, 1);
+    }
+
+    #[test]
+    fn open_claims_wait_for_a_commit_adding_the_member() {
+        let config = KeyPackageConfig { max_open_claims: 2, ..Default::default() };
+        let mut directory = directory(config);
+        let now = Instant::now();
+
+        directory.claim(1, 0x10, 7, now).unwrap();
+        directory.claim(1, 0x10, 7, now).unwrap();
+        let result = directory.claim(1, 0x10, 7, now);
+        assert_eq!(result, Err(ClaimError::TooManyOpen { open: 2 }));
+
+        // Claims are bound to their room
+        directory.claim(1, 0x20, 7, now).unwrap();
+
+        // A commit adding someone else, or in another room, settles nothing
+        directory.settle(0x10, &[8]);
+        directory.settle(0x30, &[7]);
+        assert!(directory.claim(1, 0x10, 7, now).is_err());
+
+        directory.settle(0x10, &[7]);
+        assert_eq!(directory.claim(1, 0x10, 7, now).unwrap().key_package, vec![3]);
+        assert_eq!(directory.metrics().unbound, 2);
+    }
+}
@@ -0,0 +1,58 @@
+//! Server-side frame latency.
+//!
+//! The driver stamps every frame it relays with a [`FrameTiming`]: when the
+//! frame arrived and how long it took to turn into a broadcast. The same
+//! numbers feed these aggregates, alongside ingress latency measured from
+//! the sender's HLC timestamp. Ingress includes the sender's clock skew, so
+//! it is only meaningful for clients that keep their clock in step via
+//! `TimeSync`.
+
+use std::time::Duration;
+
+use lockframe_core::{hlc::HlcTimestamp, latency::LatencyHistogram};
+use lockframe_proto::FrameTiming;
+
+/// Aggregate latency of frames relayed by the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyMetrics {
+    /// Sender HLC timestamp to arrival at the server
+    pub ingress: LatencyHistogram,
+    /// Arrival to broadcast
+    pub processing: LatencyHistogram,
+}
+
+impl LatencyMetrics {
+    /// Record one relayed frame stamped with `timing`.
+    ///
+    /// `hlc` is the frame's header timestamp. Frames without one, or stamped
+    /// ahead of the server clock, only count towards processing time.
+    pub fn record(&mut self, hlc: u64, timing: &FrameTiming) {
+        self.processing.record(Duration::from_micros(u64::from(timing.processing_micros)));
+
+        let sent_at = HlcTimestamp::from_u64(hlc).physical_millis();
+        let ingress = timing.received_at_millis.checked_sub(sent_at).filter(|_| sent_at > 0);
+        if let Some(ingress) = ingress {
+            self.ingress.record(Duration::from_millis(ingress));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ingress_skips_unstamped_and_future_frames() {
+        let mut metrics = LatencyMetrics::default();
+        let timing = FrameTiming { received_at_millis: 10_000, processing_micros: 300 };
+
+        metrics.record(HlcTimestamp::new(9_950, 0).as_u64(), &timing);
+        metrics.record(0, &timing);
+        metrics.record(HlcTimestamp::new(10_500, 0).as_u64(), &timing);
+
+        assert_eq!(metrics.processing.count(), 3);
+        assert_eq!(metrics.processing.max(), Some(Duration::from_micros(300)));
+        assert_eq!(metrics.ingress.count(), 1);
+        assert_eq!(metrics.ingress.max(), Some(Duration::from_millis(50)));
+    }
+}
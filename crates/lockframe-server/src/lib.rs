@@ -10,50 +10,421 @@
 //! pattern (see [`lockframe_core`] for details), while [`Server`] executes the
 //! actions using Quinn QUIC and Tokio async runtime.
 //!
+//! Rooms can be spread over several drivers
+//! ([`ServerRuntimeConfig::shards`]). The first owns every session and admits
+//! each frame; the room's own driver then sequences it, so busy rooms in
+//! different shards don't wait on each other.
+//!
 //! # Components
 //!
 //! - [`ServerDriver`]: Action-based orchestrator (pure logic, no I/O)
 //! - [`Server`]: Production runtime that executes ServerDriver actions
+//! - [`ServerHandle`]: Control of a server embedded in another process
 //! - [`QuinnTransport`]: QUIC transport via Quinn library
+//! - [`MemoryTransport`]: In-process transport for embedded servers
 //! - [`SystemEnv`]: Production environment (real time, crypto RNG)
 
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
 
+mod accept_filter;
+mod accounts;
+mod admin;
+mod archival;
+mod attachments;
+mod audit;
+mod directory;
 mod driver;
 mod error;
+mod event_log;
 mod executor;
+mod fault;
+mod federation;
+mod key_packages;
+mod latency;
+mod memory_transport;
+mod migration;
+mod notification;
+mod offline;
+mod opcode_metrics;
+mod overload;
+mod rate_limit;
 mod registry;
+mod reject_log;
+mod resend_dedup;
+mod retention;
 mod room_manager;
+mod room_throughput;
 pub mod sequencer;
 mod server_error;
 pub mod storage;
+mod streamed;
+mod sync_budget;
 mod system_env;
 mod transport;
+mod usage;
+mod vacuum;
+mod webhook;
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
-use bytes::BytesMut;
-pub use driver::{LogLevel, ServerAction, ServerConfig as DriverConfig, ServerDriver, ServerEvent};
+pub use accept_filter::{
+    AUDIT_LOG_TARGET, AcceptConfig, AcceptDecision, AcceptFilter, AcceptFilters, AcceptRejection,
+    IpAccessList, IpRange, PeerInfo, PerIpLimit,
+};
+pub use accounts::{AccountId, Accounts, Revocation};
+use admin::{AdminListener, AdminRequest, AdminResponse};
+pub use admin::{RoomSummary, SessionSummary, StorageSummary};
+pub use archival::{
+    ArchivalConfig, ArchivalQueues, ArchivedFrame, DEFAULT_ARCHIVE_BATCH_FRAMES,
+    DEFAULT_ARCHIVE_INITIAL_BACKOFF, DEFAULT_ARCHIVE_MAX_BACKOFF, encode_batch,
+};
+pub use attachments::{
+    AttachmentConfig, AttachmentError, Attachments, DEFAULT_MAX_ATTACHMENT_SIZE,
+    DEFAULT_MAX_CHUNK_SIZE, DEFAULT_MAX_UPLOADS, DEFAULT_UPLOAD_IDLE_TIMEOUT,
+};
+pub use audit::{AuditEvent, AuditLog, AuditRecord};
+use bytes::{Bytes, BytesMut};
+pub use directory::{Listing, MAX_DIRECTORY_PAGE, RoomDirectory};
+pub use driver::{
+    LogLevel, MembershipChange, MembershipHook, ServerAction, ServerConfig as DriverConfig,
+    ServerDriver, ServerEvent, routes_to_room,
+};
 pub use error::ServerError;
-pub use executor::BroadcastPolicy;
-use lockframe_proto::{Frame, FrameHeader};
+pub use event_log::{EVENT_LOG_TARGET, EventLogError, EventRecord};
+pub use executor::{
+    BroadcastPolicy, DEFAULT_SESSION_QUEUE_FRAMES, OutboundMetrics, OutboundQueues, Overflow,
+    OverflowPolicy, PersistBatch, QueueLimits,
+};
+pub use fault::{Fault, FaultHook, FaultPoint, InjectedFault};
+pub use federation::{Federation, MAX_HELD_RELAYS, RESEND_INTERVAL, RelayLink, Relayed, ServerId};
+pub use key_packages::{
+    ClaimError, Claimed, DEFAULT_CLAIM_WINDOW, DEFAULT_CLAIMS_PER_WINDOW, DEFAULT_LOW_WATERMARK,
+    DEFAULT_MAX_KEY_PACKAGES, DEFAULT_MAX_OPEN_CLAIMS, KeyPackageConfig, KeyPackageDirectory,
+    KeyPackageMetrics,
+};
+pub use latency::LatencyMetrics;
+use lockframe_core::{env::Environment, mls::MlsGroupState};
+use lockframe_proto::{Frame, FrameFlags, FrameHeader, FrameTiming};
+pub use memory_transport::{
+    MemoryClient, MemoryConnection, MemoryConnector, MemoryLink, MemoryTransport, memory_transport,
+};
+pub use migration::RoomMigration;
+pub use notification::{NotificationSink, OfflineNotice};
+pub use offline::{
+    DEFAULT_OFFLINE_QUEUE_FRAMES, DEFAULT_OFFLINE_QUEUE_TTL, OfflineQueueConfig, OfflineQueues,
+};
+pub use opcode_metrics::{OpcodeMetrics, OpcodeStats};
+pub use overload::{
+    DEFAULT_LOAD_REPORT_INTERVAL, DEFAULT_OVERLOAD_RETRY_AFTER, LoadReport, Overload,
+    OverloadConfig, OverloadLevel, OverloadMetrics, OverloadThresholds,
+};
+pub use rate_limit::{
+    DEFAULT_BURST_FRAMES, DEFAULT_FRAMES_PER_SEC, DEFAULT_MAX_RATE_VIOLATIONS, RateDecision,
+    RateLimitConfig, RateLimiter,
+};
 pub use registry::{ConnectionRegistry, SessionInfo};
-pub use room_manager::{RoomAction, RoomError, RoomManager, RoomMetadata};
-pub use sequencer::{Sequencer, SequencerAction, SequencerError};
-pub use server_error::{ExecutorError, ServerError as DriverError};
-pub use storage::{ChaoticStorage, MemoryStorage, Storage, StorageError};
+pub use reject_log::{
+    DEFAULT_REJECT_LOG_PER_SEC, DEFAULT_REJECT_LOG_SAMPLE, REJECT_LOG_TARGET, RejectKind,
+    RejectLog, RejectLogConfig, RejectMetrics, RejectRecord,
+};
+pub use retention::{
+    DEFAULT_RETENTION_INTERVAL, Pruned, Retention, RetentionConfig, RetentionPolicy,
+};
+pub use room_manager::{MAX_PENDING_PROPOSALS, RoomAction, RoomError, RoomManager, RoomMetadata};
+pub use room_throughput::{DEFAULT_ROOM_BURST_SECS, RoomThroughput, RoomThroughputConfig};
+pub use sequencer::{Sequencer, SequencerAction, SequencerBackend, SequencerError};
+pub use server_error::{BatchError, ExecutorError, ServerError as DriverError};
+pub use storage::{
+    ArchiveConfig, ArchiveStore, ArchivedStorage, BackupSummary, CachedStorage, ChaoticStorage,
+    DEFAULT_HOT_FRAMES, EncryptedStateStorage, FaultProfile, FsObjectStore, MemoryObjectStore,
+    MemoryStorage, ObjectStore, RoomSnapshot, S3Config, S3ObjectStore, SegmentedStorage,
+    ServerStorage, SledStorage, SqliteStorage, Storage, StorageBackend, StorageError, WalStorage,
+};
+use streamed::{Matched, STREAM_MATCH_TIMEOUT, StreamedPayloads};
+pub use sync_budget::{
+    DEFAULT_MAX_CONCURRENT_SYNCS, DEFAULT_SYNC_BURST_FRAMES, DEFAULT_SYNC_FRAMES_PER_SEC,
+    DEFAULT_SYNC_IDLE_TIMEOUT, SyncBudgetConfig, SyncBudgets, SyncDenied, SyncMetrics,
+};
 pub use system_env::SystemEnv;
-use tokio::sync::RwLock;
-pub use transport::{QuinnConnection, QuinnTransport};
+use tokio::{
+    sync::{Mutex, Notify, OwnedSemaphorePermit, RwLock, mpsc, oneshot, watch},
+    task::JoinHandle,
+};
+pub use transport::{QuinnConnection, QuinnIncoming, QuinnTransport};
+pub use usage::{RoomUsage, UsageAccumulator, UsageReport, UsageWindow};
+pub use vacuum::{
+    DEFAULT_VACUUM_INTERVAL, DEFAULT_VACUUM_MAX_BYTES, Vacuum, VacuumConfig, VacuumMetrics,
+    VacuumWindow,
+};
 use zerocopy::FromBytes;
 
 /// Shared state for all connections.
 ///
-/// This holds the connection map for broadcasts and closes.
+/// Holds the session → connection map every outgoing frame is routed
+/// through, so a targeted send reaches its session's peer whichever stream
+/// or task produced it. The driver's [`ConnectionRegistry`] stays free of
+/// transport handles.
+///
+/// Outgoing frames are queued per session and written by one writer task
+/// per connection, so a slow peer only backs up its own queue.
 struct SharedState {
-    /// Map of connection ID to QUIC connection
-    connections: RwLock<HashMap<u64, QuinnConnection>>,
+    /// Map of session ID to connected peer
+    connections: RwLock<HashMap<u64, Peer>>,
+    /// Frames waiting for each session's writer
+    outbound: Arc<Mutex<OutboundQueues>>,
+    /// Signalled whenever queued frames are taken, waking streams that wait
+    /// for a paused room
+    drained: Notify,
+    /// When the server started, the origin for event log times
+    started: Instant,
+    /// Whether events are written to the event log
+    log_events: bool,
+    /// How failed sends to a recipient are handled
+    broadcast: BroadcastPolicy,
+    /// Randomness and sleep for send retries
+    env: SystemEnv,
+    /// Archive batches waiting for delivery
+    archive_jobs: mpsc::UnboundedSender<ArchiveJob>,
+    /// Filters every accepted connection must pass
+    accept: Mutex<AcceptFilters>,
+    /// Sinks told about frames queued for offline members
+    notifications: Vec<Box<dyn NotificationSink>>,
+    /// Links to federated servers
+    relay_links: RelayLinks,
+    /// Received frames waiting in connection mailboxes
+    inbound: AtomicU64,
+    /// Slowest frame persist since the last load report, in microseconds
+    persist_micros: AtomicU64,
+}
+
+/// Drivers the server's rooms are spread over.
+///
+/// The primary owns every session: it accepts connections, runs handshakes
+/// and admits each frame. A room frame is then sequenced by the shard that
+/// hosts its room. The primary hosts rooms only when there are no other
+/// shards, so frames for different shards only contend on the primary's
+/// lock for admission, which does no I/O. Every shard knows every session
+/// so it can route its rooms' broadcasts.
+struct Shards<Q: SequencerBackend> {
+    /// Driver for sessions, and for rooms if not sharded
+    primary: Mutex<ServerDriver<SystemEnv, ServerStorage, Q>>,
+    /// Drivers for the shards of rooms
+    others: Vec<Mutex<ServerDriver<SystemEnv, ServerStorage, Q>>>,
+}
+
+impl<Q: SequencerBackend> Shards<Q> {
+    fn new(
+        primary: ServerDriver<SystemEnv, ServerStorage, Q>,
+        others: Vec<ServerDriver<SystemEnv, ServerStorage, Q>>,
+    ) -> Self {
+        Self { primary: Mutex::new(primary), others: others.into_iter().map(Mutex::new).collect() }
+    }
+
+    /// The driver owning sessions.
+    const fn primary(&self) -> &Mutex<ServerDriver<SystemEnv, ServerStorage, Q>> {
+        &self.primary
+    }
+
+    /// The shards of rooms, if sharded.
+    fn others(&self) -> impl Iterator<Item = &Mutex<ServerDriver<SystemEnv, ServerStorage, Q>>> {
+        self.others.iter()
+    }
+
+    /// Every shard, primary first.
+    fn all(&self) -> impl Iterator<Item = &Mutex<ServerDriver<SystemEnv, ServerStorage, Q>>> {
+        std::iter::once(&self.primary).chain(&self.others)
+    }
+
+    /// The driver hosting `room_id`.
+    fn for_room(&self, room_id: u128) -> &Mutex<ServerDriver<SystemEnv, ServerStorage, Q>> {
+        self.others.get(shard_index(room_id, self.others.len())).unwrap_or(&self.primary)
+    }
+
+    /// The driver that processes `frame` once admitted.
+    fn for_frame(&self, frame: &Frame) -> &Mutex<ServerDriver<SystemEnv, ServerStorage, Q>> {
+        if routes_to_room(frame) { self.for_room(frame.header.room_id()) } else { &self.primary }
+    }
+
+    /// Rooms across all shards, by room ID.
+    async fn room_summaries(&self) -> Result<Vec<RoomSummary>, StorageError> {
+        let mut rooms = Vec::new();
+        for shard in self.all() {
+            rooms.extend(shard.lock().await.room_summaries()?);
+        }
+        rooms.sort_unstable_by_key(|room| room.room_id);
+        Ok(rooms)
+    }
+
+    /// Sessions with their rooms across all shards.
+    async fn session_summaries(&self) -> Vec<SessionSummary> {
+        let mut sessions = self.primary.lock().await.session_summaries();
+        for shard in self.others() {
+            let shard = shard.lock().await;
+            for session in &mut sessions {
+                session.rooms.extend(shard.session_rooms(session.session_id));
+            }
+        }
+        for session in &mut sessions {
+            session.rooms.sort_unstable();
+        }
+        sessions
+    }
+}
+
+/// Index of the shard hosting `room_id` among `shards` shards.
+fn shard_index(room_id: u128, shards: usize) -> usize {
+    let shards = u128::try_from(shards).unwrap_or(u128::MAX);
+    room_id.checked_rem(shards).and_then(|index| usize::try_from(index).ok()).unwrap_or(0)
+}
+
+/// What a connection's readers hand its actor, in the order they read it.
+enum Inbound {
+    /// A decoded frame
+    Frame(Frame),
+    /// Bytes that did not decode, with the reason; their stream was
+    /// abandoned
+    Undecodable(String),
+}
+
+/// A connected session as seen by its writer task.
+#[derive(Clone)]
+struct Peer {
+    /// Connection to the client
+    link: Link,
+    /// Signalled when frames are queued or the session is closed
+    wake: Arc<Notify>,
+    /// Set once the connection should close after its queue is flushed
+    closing: Arc<OnceLock<String>>,
+}
+
+/// Where a server accepts clients.
+enum Listener {
+    /// QUIC over UDP
+    Quic(QuinnTransport),
+    /// Channels within this process
+    Memory(MemoryTransport),
+}
+
+impl Listener {
+    async fn accept(&mut self) -> Result<Incoming, ServerError> {
+        match self {
+            Self::Quic(transport) => {
+                transport.accept_incoming().await.map(|incoming| Incoming::Quic(Box::new(incoming)))
+            },
+            Self::Memory(transport) => transport.accept().await.map(Incoming::Memory),
+        }
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, ServerError> {
+        match self {
+            Self::Quic(transport) => transport.local_addr(),
+            Self::Memory(_) => {
+                Err(ServerError::Config("in-memory transport has no address".to_string()))
+            },
+        }
+    }
+}
+
+/// A client connection attempt, before any handshake.
+enum Incoming {
+    Quic(Box<QuinnIncoming>),
+    Memory(MemoryConnection),
+}
+
+/// A newly accepted client connection.
+enum Accepted {
+    Quic(QuinnConnection),
+    Memory(MemoryConnection),
+}
+
+/// Sending half of a client connection.
+#[derive(Clone)]
+enum Link {
+    Quic(QuinnConnection),
+    Memory(MemoryLink),
+}
+
+impl Link {
+    /// Send one encoded frame.
+    async fn send(&self, buf: &[u8]) -> Result<(), String> {
+        match self {
+            Self::Quic(conn) => send_frame(conn, buf).await,
+            Self::Memory(link) => link.send(buf).await,
+        }
+    }
+
+    /// Close the connection, telling the client why.
+    fn close(&self, reason: &str) {
+        match self {
+            Self::Quic(conn) => conn.close(0u32.into(), reason.as_bytes()),
+            Self::Memory(link) => link.close(reason),
+        }
+    }
+}
+
+/// Read-only view of the runtime's outbound queues.
+///
+/// Stays usable after [`Server::run`] takes the server.
+#[derive(Clone)]
+pub struct OutboundMonitor(Arc<Mutex<OutboundQueues>>);
+
+impl OutboundMonitor {
+    /// Current queue depths and overflow counters.
+    pub async fn metrics(&self) -> OutboundMetrics {
+        self.0.lock().await.metrics()
+    }
+
+    /// Number of frames queued for a session.
+    pub async fn depth(&self, session_id: u64) -> usize {
+        self.0.lock().await.len(session_id)
+    }
+}
+
+/// A batch from [`ServerAction::ArchiveFrames`] handed to the delivery task.
+struct ArchiveJob {
+    room_id: u128,
+    endpoint: String,
+    frames: Vec<ArchivedFrame>,
+    delay: Duration,
+}
+
+/// Longest a maintenance window waits for queued frames to be sent.
+const MAINTENANCE_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Frames read for a connection but not yet handled by its actor. Readers
+/// wait once the mailbox is full, which slows a flooding peer down to the
+/// rate its frames are processed.
+const MAILBOX_FRAMES: usize = 64;
+
+/// Links to federated servers, by server.
+type RelayLinks = Arc<RwLock<HashMap<ServerId, Box<dyn RelayLink>>>>;
+
+/// Request from a [`ServerHandle`] to the running server.
+enum Control {
+    Relay {
+        from: ServerId,
+        relayed: Relayed,
+    },
+    MigrateRoom {
+        room_id: u128,
+        target: String,
+        reply: oneshot::Sender<Result<RoomMigration, ServerError>>,
+    },
+    Maintenance {
+        duration: Duration,
+        reason: String,
+        reply: oneshot::Sender<Result<MaintenanceReport, ServerError>>,
+    },
 }
 
 /// Server configuration for the production runtime.
@@ -65,8 +436,66 @@ pub struct ServerRuntimeConfig {
     pub cert_path: Option<String>,
     /// Path to TLS private key (PEM format)
     pub key_path: Option<String>,
+    /// CA certificates (PEM format) clients must present a certificate from;
+    /// client certificates are not requested if omitted
+    pub client_ca_path: Option<String>,
+    /// Built-in filters run on every accepted connection
+    pub accept: AcceptConfig,
     /// Driver configuration (timeouts, limits)
     pub driver: DriverConfig,
+    /// Where frames and MLS state are stored
+    pub storage: StorageBackend,
+    /// Write-ahead log file for frames, replayed into storage on startup
+    pub wal_path: Option<PathBuf>,
+    /// Object store directory for frames older than the hot window
+    pub archive_dir: Option<PathBuf>,
+    /// S3 bucket for frames older than the hot window, used if
+    /// `archive_dir` is unset
+    pub archive_s3: Option<S3Config>,
+    /// When frames are moved to the archive
+    pub archive: ArchiveConfig,
+    /// Write every driver event to the [`EVENT_LOG_TARGET`] tracing target
+    pub event_log: bool,
+    /// How failed sends to a recipient are handled
+    pub broadcast: BroadcastPolicy,
+    /// Bound on each session's outbound queue
+    pub send_queue: QueueLimits,
+    /// Accept clients over in-process channels instead of QUIC; the bind
+    /// address and TLS paths are ignored. See [`Server::spawn_in_process`].
+    pub in_memory: bool,
+    /// Address to serve the admin HTTP API on (off if omitted). The API has
+    /// no authentication; bind it to a loopback or management address.
+    pub admin_bind: Option<String>,
+    /// Drivers rooms are spread over, so frames for rooms in different
+    /// shards are processed in parallel (0 and 1 keep every room on the
+    /// driver owning sessions). See [`ServerDriver::shard`].
+    pub shards: usize,
+}
+
+impl ServerRuntimeConfig {
+    /// Open the configured storage, with the archive and write-ahead log in
+    /// front of it if configured.
+    pub fn open_storage(&self) -> Result<ServerStorage, ServerError> {
+        self.storage
+            .open()
+            .and_then(|storage| match (&self.archive_dir, &self.archive_s3) {
+                (Some(dir), _) => storage.with_archive(dir, self.archive),
+                (None, Some(s3)) => storage.with_s3_archive(s3.clone(), self.archive),
+                (None, None) => Ok(storage),
+            })
+            .and_then(|storage| match &self.wal_path {
+                Some(path) => storage.with_wal(path),
+                None => Ok(storage),
+            })
+            .map_err(|e| ServerError::Config(format!("failed to open storage: {e}")))
+    }
+
+    /// Files and directories the configured storage keeps its data in.
+    pub fn storage_paths(&self) -> Vec<PathBuf> {
+        let mut paths = self.storage.paths();
+        paths.extend(self.wal_path.iter().chain(&self.archive_dir).cloned());
+        paths
+    }
 }
 
 impl Default for ServerRuntimeConfig {
@@ -75,7 +504,20 @@ impl Default for ServerRuntimeConfig {
             bind_address: "0.0.0.0:4433".to_string(),
             cert_path: None,
             key_path: None,
+            client_ca_path: None,
+            accept: AcceptConfig::default(),
             driver: DriverConfig::default(),
+            storage: StorageBackend::default(),
+            wal_path: None,
+            archive_dir: None,
+            archive_s3: None,
+            archive: ArchiveConfig::default(),
+            event_log: false,
+            broadcast: BroadcastPolicy::default(),
+            send_queue: QueueLimits::default(),
+            in_memory: false,
+            admin_bind: None,
+            shards: 1,
         }
     }
 }
@@ -83,134 +525,1146 @@ impl Default for ServerRuntimeConfig {
 /// Production Lockframe server.
 ///
 /// Wraps `ServerDriver` with Quinn QUIC transport and system environment.
-pub struct Server {
-    /// The action-based server driver
-    driver: ServerDriver<SystemEnv, MemoryStorage>,
-    /// QUIC endpoint
-    transport: QuinnTransport,
+/// [`Server::spawn_in_process`] runs it inside another process instead,
+/// optionally with clients connected over in-memory channels.
+pub struct Server<Q: SequencerBackend = Sequencer> {
+    /// The action-based server driver, owning sessions, and rooms if not
+    /// sharded
+    driver: ServerDriver<SystemEnv, ServerStorage, Q>,
+    /// Drivers hosting the shards of rooms
+    shards: Vec<ServerDriver<SystemEnv, ServerStorage, Q>>,
+    /// Where clients connect
+    listener: Listener,
+    /// Opens in-process connections, if the transport is in memory
+    connector: Option<MemoryConnector>,
     /// Environment
     env: SystemEnv,
+    /// Settings the running server shares with its connections
+    runtime: RuntimeOptions,
+}
+
+/// Runtime settings carried from [`ServerRuntimeConfig`] into [`SharedState`].
+struct RuntimeOptions {
+    /// Whether events are written to the event log
+    log_events: bool,
+    /// How failed sends to a recipient are handled
+    broadcast: BroadcastPolicy,
+    /// Per-session outbound queues
+    outbound: Arc<Mutex<OutboundQueues>>,
+    /// Filters every accepted connection must pass
+    accept: AcceptFilters,
+    /// Sinks told about frames queued for offline members
+    notifications: Vec<Box<dyn NotificationSink>>,
+    /// Links to federated servers
+    relay_links: RelayLinks,
+    /// Admin HTTP API, if enabled
+    admin: Option<AdminListener>,
 }
 
 impl Server {
-    /// Create and bind a new server.
+    /// Create and bind a new server, sequencing with the single-node
+    /// [`Sequencer`].
     pub async fn bind(config: ServerRuntimeConfig) -> Result<Self, ServerError> {
+        Self::bind_with_sequencer(config, Sequencer::new).await
+    }
+
+    /// Bind a server and run it on a background task of the current Tokio
+    /// runtime.
+    ///
+    /// With [`ServerRuntimeConfig::in_memory`] set, clients connect through
+    /// [`ServerHandle::connect`] instead of the network.
+    pub async fn spawn_in_process(
+        config: ServerRuntimeConfig,
+    ) -> Result<ServerHandle, ServerError> {
+        Self::bind(config).await.map(Self::spawn)
+    }
+}
+
+impl<Q: SequencerBackend + 'static> Server<Q> {
+    /// Create and bind a new server that sequences frames with backends
+    /// from `new_sequencer`, one per shard of rooms.
+    ///
+    /// See [`ServerDriver::with_sequencer`].
+    pub async fn bind_with_sequencer(
+        config: ServerRuntimeConfig,
+        mut new_sequencer: impl FnMut() -> Q,
+    ) -> Result<Self, ServerError> {
         let env = SystemEnv::new();
-        let storage = MemoryStorage::new();
-        let driver = ServerDriver::new(env.clone(), storage, config.driver);
+        let storage = config.open_storage()?;
+        let admin = match &config.admin_bind {
+            Some(address) => Some(AdminListener::bind(address, config.storage_paths()).await?),
+            None => None,
+        };
+        let driver =
+            ServerDriver::with_sequencer(env.clone(), storage, config.driver, new_sequencer());
+        // Once sharded, the primary keeps to sessions so its lock is only
+        // taken to admit frames, never while a room is sequenced
+        let shards = if config.shards > 1 {
+            (0..config.shards).map(|_| driver.shard(new_sequencer())).collect()
+        } else {
+            Vec::new()
+        };
+
+        let (listener, connector) = if config.in_memory {
+            let (transport, connector) = memory_transport();
+            (Listener::Memory(transport), Some(connector))
+        } else {
+            let transport = QuinnTransport::bind(
+                &config.bind_address,
+                config.cert_path,
+                config.key_path,
+                config.client_ca_path,
+            )
+            .await?;
+            (Listener::Quic(transport), None)
+        };
+
+        Ok(Self {
+            driver,
+            shards,
+            listener,
+            connector,
+            env,
+            runtime: RuntimeOptions {
+                log_events: config.event_log,
+                broadcast: config.broadcast,
+                outbound: Arc::new(Mutex::new(OutboundQueues::bounded(config.send_queue))),
+                accept: AcceptFilters::from_config(&config.accept),
+                notifications: Vec::new(),
+                relay_links: Arc::default(),
+                admin,
+            },
+        })
+    }
+
+    /// Handle for watching outbound queue depths while the server runs.
+    pub fn outbound_monitor(&self) -> OutboundMonitor {
+        OutboundMonitor(Arc::clone(&self.runtime.outbound))
+    }
+
+    /// Register a callback for room membership changes.
+    ///
+    /// See [`ServerDriver::on_membership_change`]. Register hooks before
+    /// calling [`run`](Self::run).
+    pub fn on_membership_change(&mut self, hook: impl FnMut(&MembershipChange) + Send + 'static) {
+        self.driver.on_membership_change(hook);
+    }
+
+    /// Add a filter connections must pass before a session is registered.
+    ///
+    /// Runs after the built-in filters from [`ServerRuntimeConfig::accept`]
+    /// and any added before it. Add filters before calling
+    /// [`run`](Self::run).
+    pub fn add_accept_filter(&mut self, filter: impl AcceptFilter + 'static) {
+        self.runtime.accept.push(filter);
+    }
+
+    /// Add a sink told about every frame queued for an offline member.
+    ///
+    /// Sinks see room, sender and member IDs and the log index, never the
+    /// frame itself. Add sinks before calling [`run`](Self::run).
+    pub fn add_notification_sink(&mut self, sink: impl NotificationSink + 'static) {
+        self.runtime.notifications.push(Box::new(sink));
+    }
+
+    /// Override the offline queue limits for one room.
+    ///
+    /// See [`ServerDriver::set_room_offline_queue`].
+    pub fn set_room_offline_queue(&mut self, room_id: u128, config: OfflineQueueConfig) {
+        self.driver_for_room(room_id).set_room_offline_queue(room_id, config);
+    }
 
-        let transport =
-            QuinnTransport::bind(&config.bind_address, config.cert_path, config.key_path).await?;
+    /// Override the retention policy for one room.
+    ///
+    /// See [`ServerDriver::set_room_retention`].
+    pub fn set_room_retention(&mut self, room_id: u128, policy: RetentionPolicy) {
+        self.driver_for_room(room_id).set_room_retention(room_id, policy);
+    }
 
-        Ok(Self { driver, transport, env })
+    /// Archive a room's sequenced frames to an external archiver.
+    ///
+    /// See [`ServerDriver::set_room_archival`].
+    pub fn set_room_archival(
+        &mut self,
+        room_id: u128,
+        archival: Option<ArchivalConfig>,
+    ) -> Result<(), StorageError> {
+        self.driver_for_room(room_id).set_room_archival(room_id, archival)
+    }
+
+    /// Have the federated server `home` sequence `room_id`.
+    ///
+    /// See [`ServerDriver::set_room_home`]. Relays reach `home` over the
+    /// link added with [`ServerHandle::add_relay_link`].
+    pub fn set_room_home(&mut self, room_id: u128, home: ServerId) {
+        self.driver_for_room(room_id).set_room_home(room_id, home);
+    }
+
+    /// Relay every frame sequenced in `room_id`, homed here, to `peer`.
+    ///
+    /// See [`ServerDriver::add_room_peer`].
+    pub fn add_room_peer(&mut self, room_id: u128, peer: ServerId) -> bool {
+        self.driver_for_room(room_id).add_room_peer(room_id, peer)
+    }
+
+    /// Driver hosting `room_id`.
+    fn driver_for_room(&mut self, room_id: u128) -> &mut ServerDriver<SystemEnv, ServerStorage, Q> {
+        let index = shard_index(room_id, self.shards.len());
+        match self.shards.get_mut(index) {
+            Some(shard) => shard,
+            None => &mut self.driver,
+        }
+    }
+
+    /// Run a bound server on a background task of the current Tokio runtime.
+    ///
+    /// Like [`spawn_in_process`](Self::spawn_in_process), for servers that
+    /// need hooks or room overrides registered first.
+    pub fn spawn(self) -> ServerHandle<Q> {
+        let local_addr = self.local_addr().ok();
+        let connector = self.connector.clone();
+        let outbound = self.outbound_monitor();
+        let admin_addr = self.runtime.admin.as_ref().and_then(AdminListener::local_addr);
+        let relay_links = Arc::clone(&self.runtime.relay_links);
+        let (stop, stopped) = watch::channel(false);
+        let (control, controls) = mpsc::unbounded_channel();
+        let driver = Arc::new(Shards::new(self.driver, self.shards));
+        let task = tokio::spawn(serve(
+            self.listener,
+            Arc::clone(&driver),
+            self.runtime,
+            self.env,
+            stopped,
+            controls,
+        ));
+
+        ServerHandle {
+            driver,
+            stop,
+            control,
+            task,
+            connector,
+            local_addr,
+            admin_addr,
+            outbound,
+            relay_links,
+        }
     }
 
     /// Run the server, accepting connections and processing frames.
     ///
     /// This method runs until the server is shut down or an error occurs.
     pub async fn run(self) -> Result<(), ServerError> {
-        tracing::info!("Server starting on {}", self.transport.local_addr()?);
+        let (_stop, stopped) = watch::channel(false);
+        let (_control, controls) = mpsc::unbounded_channel();
+        let driver = Arc::new(Shards::new(self.driver, self.shards));
+        serve(self.listener, driver, self.runtime, self.env, stopped, controls).await
+    }
 
-        let driver = Arc::new(tokio::sync::Mutex::new(self.driver));
-        let shared = Arc::new(SharedState { connections: RwLock::new(HashMap::new()) });
-        let env = self.env;
+    /// Local address the server is bound to.
+    ///
+    /// Fails for the in-memory transport, which has no address.
+    pub fn local_addr(&self) -> Result<SocketAddr, ServerError> {
+        self.listener.local_addr()
+    }
 
-        loop {
-            match self.transport.accept().await {
-                Ok(conn) => {
-                    let driver = Arc::clone(&driver);
-                    let shared = Arc::clone(&shared);
-                    let env = env.clone();
-
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_connection(conn, driver, shared, env).await {
-                            tracing::error!("Connection error: {}", e);
-                        }
-                    });
-                },
-                Err(e) => {
-                    tracing::error!("Accept error: {}", e);
-                },
-            }
+    /// Opens connections to a server on the in-memory transport.
+    pub fn memory_connector(&self) -> Option<MemoryConnector> {
+        self.connector.clone()
+    }
+}
+
+/// Handle to a server running on a background task, from
+/// [`Server::spawn_in_process`] or [`Server::spawn`].
+///
+/// Dropping the handle shuts the server down without waiting for it.
+pub struct ServerHandle<Q: SequencerBackend = Sequencer> {
+    driver: Arc<Shards<Q>>,
+    stop: watch::Sender<bool>,
+    control: mpsc::UnboundedSender<Control>,
+    task: JoinHandle<Result<(), ServerError>>,
+    connector: Option<MemoryConnector>,
+    local_addr: Option<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
+    outbound: OutboundMonitor,
+    relay_links: RelayLinks,
+}
+
+/// Counters of a running server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Open client connections
+    pub connections: usize,
+    /// Rooms the server hosts
+    pub rooms: usize,
+    /// Sync requests served and throttled
+    pub sync: SyncMetrics,
+    /// Storage vacuum passes
+    pub vacuum: VacuumMetrics,
+    /// Rejected frames
+    pub rejects: RejectMetrics,
+    /// Frames processed and their processing time, per opcode
+    pub opcodes: OpcodeMetrics,
+    /// Outbound queue depths
+    pub outbound: OutboundMetrics,
+    /// Load shedding level and counters
+    pub overload: OverloadMetrics,
+    /// KeyPackage uploads and claims
+    pub key_packages: KeyPackageMetrics,
+}
+
+/// Outcome of starting a maintenance window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Sessions told to reconnect later and closed
+    pub sessions_closed: usize,
+    /// Whether every queued frame was sent before the sessions closed
+    pub drained: bool,
+    /// How long new connections are refused for
+    pub duration: Duration,
+}
+
+impl<Q: SequencerBackend + 'static> ServerHandle<Q> {
+    /// Address the server accepts QUIC connections on; `None` for the
+    /// in-memory transport.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Address of the admin HTTP API; `None` unless
+    /// [`ServerRuntimeConfig::admin_bind`] was set.
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_addr
+    }
+
+    /// Connect a client over the in-memory transport.
+    pub fn connect(&self) -> Result<MemoryClient, ServerError> {
+        self.connector
+            .as_ref()
+            .ok_or_else(|| ServerError::Config("server is not on the in-memory transport".into()))?
+            .connect()
+    }
+
+    /// Current counters.
+    pub async fn stats(&self) -> ServerStats {
+        let (connections, vacuum, overload, key_packages) = {
+            let driver = self.driver.primary().lock().await;
+            (
+                driver.connection_count(),
+                driver.vacuum_metrics(),
+                driver.overload_metrics(),
+                driver.key_package_metrics(),
+            )
+        };
+        let mut rooms = 0usize;
+        let mut sync = SyncMetrics::default();
+        let mut rejects = RejectMetrics::default();
+        let mut opcodes = OpcodeMetrics::default();
+        for shard in self.driver.all() {
+            let driver = shard.lock().await;
+            rooms = rooms.saturating_add(driver.room_count());
+            sync.merge(&driver.sync_metrics());
+            rejects.merge(&driver.reject_metrics());
+            opcodes.merge(driver.opcode_metrics());
+        }
+        ServerStats {
+            connections,
+            rooms,
+            sync,
+            vacuum,
+            rejects,
+            opcodes,
+            outbound: self.outbound.metrics().await,
+            overload,
+            key_packages,
         }
     }
 
-    /// Local address the server is bound to.
-    pub fn local_addr(&self) -> Result<std::net::SocketAddr, ServerError> {
-        self.transport.local_addr()
+    /// Close the open usage window and return its per-room usage.
+    ///
+    /// See [`ServerDriver::close_usage_window`]. Billing calls this at the
+    /// end of each period; windows are contiguous, so no frame is counted
+    /// twice or missed.
+    pub async fn close_usage_window(&self) -> Result<UsageReport, StorageError> {
+        // Shards share one usage window
+        self.driver.primary().lock().await.close_usage_window()
+    }
+
+    /// List a room in the room directory, or update its listing.
+    ///
+    /// See [`ServerDriver::publish_room`].
+    pub async fn publish_room(
+        &self,
+        room_id: u128,
+        name: &str,
+        description: &str,
+    ) -> Result<(), DriverError> {
+        self.driver.for_room(room_id).lock().await.publish_room(room_id, name, description)
+    }
+
+    /// Remove a room from the room directory. Returns `false` if it was not
+    /// listed.
+    ///
+    /// See [`ServerDriver::unlist_room`].
+    pub async fn unlist_room(&self, room_id: u128) -> Result<bool, StorageError> {
+        self.driver.for_room(room_id).lock().await.unlist_room(room_id)
+    }
+
+    /// Move a room to the server at `target`, returning the backup to load
+    /// there.
+    ///
+    /// See [`ServerDriver::migrate_room`]. Clients in the room are told to
+    /// reconnect to `target`, so the backup should be restored on it with
+    /// [`storage::restore`] before they do.
+    pub async fn migrate_room(
+        &self,
+        room_id: u128,
+        target: &str,
+    ) -> Result<RoomMigration, ServerError> {
+        let (reply, migrated) = oneshot::channel();
+        let target = target.to_string();
+        self.control
+            .send(Control::MigrateRoom { room_id, target, reply })
+            .map_err(|_| ServerError::Transport("server is not running".to_string()))?;
+        migrated.await.map_err(|_| ServerError::Transport("server is not running".to_string()))?
+    }
+
+    /// Put the server into maintenance for `duration`, so it can be
+    /// restarted without losing data.
+    ///
+    /// New connections are refused until `duration` has passed. Every
+    /// session is sent a `Maintenance` frame saying when to reconnect and is
+    /// closed once its queued frames are sent, then storage is flushed. The
+    /// server keeps running; it is safe to stop once this returns.
+    pub async fn enter_maintenance(
+        &self,
+        duration: Duration,
+        reason: &str,
+    ) -> Result<MaintenanceReport, ServerError> {
+        let (reply, entered) = oneshot::channel();
+        let reason = reason.to_string();
+        self.control
+            .send(Control::Maintenance { duration, reason, reply })
+            .map_err(|_| ServerError::Transport("server is not running".to_string()))?;
+        entered.await.map_err(|_| ServerError::Transport("server is not running".to_string()))?
+    }
+
+    /// Send relays for the federated server `peer` over `link`, replacing
+    /// any earlier link to it.
+    ///
+    /// Without a link, relays to `peer` are dropped. For a server in the
+    /// same process, [`relay_link`](Self::relay_link) of its handle is the
+    /// link.
+    pub async fn add_relay_link(&self, peer: ServerId, link: impl RelayLink + 'static) {
+        self.relay_links.write().await.insert(peer, Box::new(link));
+    }
+
+    /// Hand the server a relay from the federated server `from`.
+    pub fn relay(&self, from: ServerId, relayed: Relayed) -> Result<(), ServerError> {
+        self.control
+            .send(Control::Relay { from, relayed })
+            .map_err(|_| ServerError::Transport("server is not running".to_string()))
+    }
+
+    /// Link that hands relays to this server as coming from `from`, for a
+    /// server running in the same process.
+    pub fn relay_link(&self, from: ServerId) -> impl RelayLink + 'static {
+        let control = self.control.clone();
+        move |relayed| {
+            // Relays may be lost; a stopped server loses them all
+            let _ = control.send(Control::Relay { from, relayed });
+        }
+    }
+
+    /// Stop accepting clients, close every connection and wait for the
+    /// server to stop.
+    pub async fn shutdown(self) -> Result<(), ServerError> {
+        // The server may already have stopped on its own
+        let _ = self.stop.send(true);
+        self.task.await.map_err(|e| ServerError::Internal(format!("server task failed: {e}")))?
     }
 }
 
-/// Handle a single QUIC connection.
-async fn handle_connection(
-    conn: QuinnConnection,
-    driver: Arc<tokio::sync::Mutex<ServerDriver<SystemEnv, MemoryStorage>>>,
-    shared: Arc<SharedState>,
-    _env: SystemEnv,
+/// Accept and serve clients until `stopped` fires, then close every
+/// connection.
+async fn serve<Q: SequencerBackend + 'static>(
+    mut listener: Listener,
+    driver: Arc<Shards<Q>>,
+    runtime: RuntimeOptions,
+    env: SystemEnv,
+    mut stopped: watch::Receiver<bool>,
+    mut controls: mpsc::UnboundedReceiver<Control>,
 ) -> Result<(), ServerError> {
-    let session_id = {
-        let mut buf = [0u8; 8];
-        getrandom::fill(&mut buf).map_err(|e| ServerError::Internal(e.to_string()))?;
-        u64::from_le_bytes(buf)
-    };
+    if let Ok(addr) = listener.local_addr() {
+        tracing::info!("Server starting on {}", addr);
+    } else {
+        tracing::info!("Server starting in process");
+    }
 
-    tracing::debug!("New connection: {}", session_id);
+    let (archive_jobs, jobs) = mpsc::unbounded_channel();
+    let shared = Arc::new(SharedState {
+        connections: RwLock::new(HashMap::new()),
+        outbound: runtime.outbound,
+        drained: Notify::new(),
+        started: Instant::now(),
+        log_events: runtime.log_events,
+        broadcast: runtime.broadcast,
+        env: env.clone(),
+        archive_jobs,
+        accept: Mutex::new(runtime.accept),
+        notifications: runtime.notifications,
+        relay_links: runtime.relay_links,
+        inbound: AtomicU64::new(0),
+        persist_micros: AtomicU64::new(0),
+    });
 
-    {
-        let mut connections = shared.connections.write().await;
-        connections.insert(session_id, conn.clone());
+    let load_reports = driver.primary().lock().await.load_report_interval();
+    let mut background = vec![
+        tokio::spawn(run_retention(Arc::clone(&driver), Arc::clone(&shared), env.clone())),
+        tokio::spawn(run_vacuum(Arc::clone(&driver), Arc::clone(&shared), env.clone())),
+        tokio::spawn(run_archival(Arc::clone(&driver), Arc::clone(&shared), jobs)),
+    ];
+    if let Some(interval) = load_reports {
+        let (driver, shared) = (Arc::clone(&driver), Arc::clone(&shared));
+        background.push(tokio::spawn(run_load_reports(driver, shared, env, interval)));
     }
-
-    {
-        let mut driver = driver.lock().await;
-        let actions = driver.process_event(ServerEvent::ConnectionAccepted { session_id })?;
-        execute_actions(&mut *driver, actions, &shared).await?;
+    if let Some(admin) = runtime.admin {
+        background.push(tokio::spawn(run_admin(admin, Arc::clone(&driver), Arc::clone(&shared))));
     }
 
     loop {
-        match conn.accept_bi().await {
-            Ok((send, recv)) => {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(control) = controls.recv() => {
+                run_control(&driver, &shared, control).await;
+                continue;
+            },
+            _ = stopped.changed() => break,
+        };
+
+        match accepted {
+            Ok(conn) => {
                 let driver = Arc::clone(&driver);
                 let shared = Arc::clone(&shared);
 
                 tokio::spawn(async move {
-                    if let Err(e) = handle_stream(session_id, send, recv, driver, &shared).await {
-                        tracing::debug!("Stream error: {}", e);
+                    if let Err(e) = handle_connection(conn, driver, shared).await {
+                        tracing::error!("Connection error: {}", e);
                     }
                 });
             },
-            Err(e) => {
-                tracing::debug!("Connection closed: {}", e);
+            // No connector is left, so no client can reach the server again
+            Err(e) if matches!(listener, Listener::Memory(_)) => {
+                tracing::info!("Server stopping: {}", e);
                 break;
             },
+            Err(e) => {
+                tracing::error!("Accept error: {}", e);
+            },
         }
     }
 
+    for task in background {
+        task.abort();
+    }
+    let sessions: Vec<u64> = shared.connections.read().await.keys().copied().collect();
+    for session_id in sessions {
+        close_session(&shared, session_id, "server shutting down").await;
+    }
+
+    // Shards share the primary's storage
+    driver.primary().lock().await.close_storage()?;
+    tracing::info!("Server stopped");
+
+    Ok(())
+}
+
+/// Carry out a request from the server's handle.
+async fn run_control<Q: SequencerBackend + 'static>(
+    driver: &Arc<Shards<Q>>,
+    shared: &Arc<SharedState>,
+    control: Control,
+) {
+    match control {
+        Control::Relay { from, relayed } => {
+            // Replies are for sessions, which the primary owns; every shard
+            // may stand in for a closed session
+            let shards: Vec<_> = match &relayed {
+                Relayed::Submit { frame, .. } => vec![driver.for_frame(frame)],
+                Relayed::Sequenced { frame, .. } => vec![driver.for_room(frame.header.room_id())],
+                Relayed::Resend { room_id, .. } => vec![driver.for_room(*room_id)],
+                Relayed::Reply { .. } => vec![driver.primary()],
+                Relayed::Closed { .. } => driver.all().collect(),
+            };
+            for shard in shards {
+                let mut driver = shard.lock().await;
+                let event = ServerEvent::Relayed { from, relayed: relayed.clone() };
+                let result = match process_event(&mut driver, event, shared) {
+                    Ok(actions) => execute_actions(&mut driver, actions, shared).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    tracing::error!("Relay from {} failed: {}", from, e);
+                }
+            }
+        },
+        Control::MigrateRoom { room_id, target, reply } => {
+            let mut driver = driver.for_room(room_id).lock().await;
+            let result = match driver.migrate_room(room_id, target) {
+                Ok((migration, actions)) => {
+                    execute_actions(&mut driver, actions, shared).await.map(|()| migration)
+                },
+                Err(e) => Err(e.into()),
+            };
+            // The caller may have given up waiting
+            let _ = reply.send(result);
+        },
+        // Draining takes a while; keep accepting (and refusing) meanwhile
+        Control::Maintenance { duration, reason, reply } => {
+            let driver = Arc::clone(driver);
+            let shared = Arc::clone(shared);
+            tokio::spawn(async move {
+                let _ = reply.send(run_maintenance(&driver, &shared, duration, &reason).await);
+            });
+        },
+    }
+}
+
+/// Refuse new connections for `duration`, tell every session when to come
+/// back, close them once their queues are sent and flush storage.
+async fn run_maintenance<Q: SequencerBackend + 'static>(
+    driver: &Shards<Q>,
+    shared: &SharedState,
+    duration: Duration,
+    reason: &str,
+) -> Result<MaintenanceReport, ServerError> {
+    let until = Instant::now().checked_add(duration).ok_or_else(|| {
+        ServerError::Config(format!("maintenance duration {duration:?} is too long"))
+    })?;
+    shared.accept.lock().await.refuse_until(until, reason.to_string());
+    tracing::info!(
+        target: AUDIT_LOG_TARGET,
+        duration_secs = duration.as_secs(),
+        reason,
+        "maintenance started"
+    );
+
     {
-        let mut connections = shared.connections.write().await;
-        connections.remove(&session_id);
+        let mut driver = driver.primary().lock().await;
+        let actions = driver.maintenance_notice(duration, reason)?;
+        execute_actions(&mut driver, actions, shared).await?;
+    }
+
+    let sessions: Vec<u64> = shared.connections.read().await.keys().copied().collect();
+    for &session_id in &sessions {
+        close_session(shared, session_id, "server in maintenance").await;
+    }
+    let drained = wait_drained(shared, MAINTENANCE_DRAIN_TIMEOUT).await;
+    if !drained {
+        tracing::warn!("Maintenance: outbound queues not drained, closing anyway");
+    }
+
+    // Shards share the primary's storage
+    driver.primary().lock().await.flush_storage()?;
+    Ok(MaintenanceReport { sessions_closed: sessions.len(), drained, duration })
+}
+
+/// Wait until no frames are queued for any session, at most `timeout`.
+async fn wait_drained(shared: &SharedState, timeout: Duration) -> bool {
+    let drained = async {
+        loop {
+            let notified = shared.drained.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if shared.outbound.lock().await.is_empty() {
+                return;
+            }
+            notified.await;
+        }
+    };
+    tokio::time::timeout(timeout, drained).await.is_ok()
+}
+
+/// Answer admin API requests until the server shuts down.
+async fn run_admin<Q: SequencerBackend + 'static>(
+    admin: AdminListener,
+    driver: Arc<Shards<Q>>,
+    shared: Arc<SharedState>,
+) {
+    if let Some(addr) = admin.local_addr() {
+        tracing::info!("Admin API listening on {}", addr);
+    }
+    let storage_paths: Arc<[PathBuf]> = admin.storage_paths().into();
+
+    loop {
+        let mut stream = match admin.accept().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("Admin API accept error: {}", e);
+                continue;
+            },
+        };
+        let driver = Arc::clone(&driver);
+        let shared = Arc::clone(&shared);
+        let storage_paths = Arc::clone(&storage_paths);
+
+        tokio::spawn(async move {
+            let response = match admin::read_request(&mut stream).await {
+                Ok(request) => answer_admin(request, &driver, &shared, storage_paths).await,
+                Err(response) => response,
+            };
+            if let Err(e) = admin::write_response(&mut stream, &response).await {
+                tracing::debug!("Admin API response failed: {}", e);
+            }
+        });
     }
+}
+
+/// Carry out an admin API request.
+async fn answer_admin<Q: SequencerBackend + 'static>(
+    request: AdminRequest,
+    driver: &Shards<Q>,
+    shared: &SharedState,
+    storage_paths: Arc<[PathBuf]>,
+) -> AdminResponse {
+    let storage_failed = |e: StorageError| {
+        AdminResponse::error("500 Internal Server Error", &format!("storage failed: {e}"))
+    };
 
+    match request {
+        AdminRequest::Rooms => {
+            let rooms = driver.room_summaries().await;
+            rooms.map_or_else(storage_failed, |rooms| AdminResponse::ok(admin::rooms_json(&rooms)))
+        },
+        AdminRequest::Sessions => {
+            AdminResponse::ok(admin::sessions_json(&driver.session_summaries().await))
+        },
+        AdminRequest::Storage => {
+            let rooms = match driver.room_summaries().await {
+                Ok(rooms) => rooms,
+                Err(e) => return storage_failed(e),
+            };
+            let vacuum = driver.primary().lock().await.vacuum_metrics();
+            let disk_bytes = tokio::task::spawn_blocking(move || admin::disk_usage(&storage_paths))
+                .await
+                .ok()
+                .flatten();
+            AdminResponse::ok(admin::storage_json(&StorageSummary {
+                rooms: rooms.len(),
+                frames: rooms.iter().fold(0u64, |total, room| total.saturating_add(room.frames)),
+                disk_bytes,
+                vacuum,
+            }))
+        },
+        AdminRequest::CloseSession(session_id) => {
+            let connected = shared.connections.read().await.contains_key(&session_id);
+            if !connected {
+                return AdminResponse::error("404 Not Found", "no such session");
+            }
+            close_session(shared, session_id, "closed by administrator").await;
+            tracing::info!(target: AUDIT_LOG_TARGET, session_id, "session closed by administrator");
+            AdminResponse::ok(format!("{{\"closed\":{session_id}}}"))
+        },
+        AdminRequest::Usage => {
+            let window = driver.primary().lock().await.usage_window();
+            AdminResponse::ok(admin::usage_json(window.started_at_millis, None, &window.rooms))
+        },
+        AdminRequest::CloseUsage => {
+            let report = driver.primary().lock().await.close_usage_window();
+            report.map_or_else(storage_failed, |report| {
+                AdminResponse::ok(admin::usage_json(
+                    report.started_at_millis,
+                    Some(report.ended_at_millis),
+                    &report.rooms,
+                ))
+            })
+        },
+        AdminRequest::Audit { from_seq, limit } => {
+            let records = driver.primary().lock().await.storage().load_audit(from_seq, limit);
+            records.map_or_else(storage_failed, |records| {
+                AdminResponse::ok(admin::audit_json(&records))
+            })
+        },
+        AdminRequest::PublishRoom { room_id, listing } => {
+            let body = admin::listing_json(room_id, &listing);
+            let published = driver.for_room(room_id).lock().await.publish_room(
+                room_id,
+                listing.name,
+                listing.description,
+            );
+            match published {
+                Ok(()) => {
+                    tracing::info!(target: AUDIT_LOG_TARGET, room_id, "room published by administrator");
+                    AdminResponse::ok(body)
+                },
+                Err(DriverError::Room(RoomError::RoomNotFound(_))) => {
+                    AdminResponse::error("404 Not Found", "no such room")
+                },
+                Err(e) => AdminResponse::error(
+                    "500 Internal Server Error",
+                    &format!("publish failed: {e}"),
+                ),
+            }
+        },
+        AdminRequest::UnlistRoom(room_id) => {
+            let unlisted = driver.for_room(room_id).lock().await.unlist_room(room_id);
+            match unlisted {
+                Ok(true) => {
+                    tracing::info!(target: AUDIT_LOG_TARGET, room_id, "room unlisted by administrator");
+                    AdminResponse::ok(format!("{{\"unlisted\":\"{room_id:032x}\"}}"))
+                },
+                Ok(false) => AdminResponse::error("404 Not Found", "room is not listed"),
+                Err(e) => storage_failed(e),
+            }
+        },
+        AdminRequest::Maintenance(duration) => {
+            match run_maintenance(driver, shared, duration, "scheduled maintenance").await {
+                Ok(report) => AdminResponse::ok(admin::maintenance_json(&report)),
+                Err(e) => AdminResponse::error(
+                    "500 Internal Server Error",
+                    &format!("maintenance failed: {e}"),
+                ),
+            }
+        },
+    }
+}
+
+/// Run retention passes until the server shuts down.
+async fn run_retention<Q: SequencerBackend + 'static>(
+    driver: Arc<Shards<Q>>,
+    shared: Arc<SharedState>,
+    env: SystemEnv,
+) {
+    loop {
+        let interval = driver.primary().lock().await.retention_interval();
+        env.sleep(interval).await;
+
+        for shard in driver.all() {
+            let result = {
+                let mut driver = shard.lock().await;
+                let actions = driver.prune_expired();
+                execute_actions(&mut driver, actions, &shared).await
+            };
+            if let Err(e) = result {
+                tracing::error!("Retention error: {}", e);
+            }
+        }
+    }
+}
+
+/// Run storage vacuum passes until the server shuts down.
+async fn run_vacuum<Q: SequencerBackend + 'static>(
+    driver: Arc<Shards<Q>>,
+    shared: Arc<SharedState>,
+    env: SystemEnv,
+) {
+    loop {
+        let interval = driver.primary().lock().await.vacuum_interval();
+        env.sleep(interval).await;
+
+        // Shards share the primary's storage
+        let result = {
+            let mut driver = driver.primary().lock().await;
+            let actions = driver.vacuum_storage();
+            execute_actions(&mut driver, actions, &shared).await
+        };
+        if let Err(e) = result {
+            tracing::error!("Vacuum error: {}", e);
+        }
+    }
+}
+
+/// Report load to the primary, which admits connections and frames, until
+/// the server shuts down.
+async fn run_load_reports<Q: SequencerBackend + 'static>(
+    driver: Arc<Shards<Q>>,
+    shared: Arc<SharedState>,
+    env: SystemEnv,
+    interval: Duration,
+) {
+    loop {
+        env.sleep(interval).await;
+
+        let load = LoadReport {
+            queue_depth: shared.inbound.load(Ordering::Relaxed),
+            storage_latency: Duration::from_micros(
+                shared.persist_micros.swap(0, Ordering::Relaxed),
+            ),
+        };
+        let result = {
+            let mut primary = driver.primary().lock().await;
+            match process_event(&mut primary, ServerEvent::LoadReported(load), &shared) {
+                Ok(actions) => execute_actions(&mut primary, actions, &shared).await,
+                Err(e) => Err(e.into()),
+            }
+        };
+        if let Err(e) = result {
+            tracing::error!("Load report error: {}", e);
+        }
+    }
+}
+
+/// Deliver archive batches until the server shuts down.
+///
+/// Each batch is posted on its own task so a slow archiver only delays its
+/// own rooms; the driver keeps at most one batch per room in flight.
+async fn run_archival<Q: SequencerBackend + 'static>(
+    driver: Arc<Shards<Q>>,
+    shared: Arc<SharedState>,
+    mut jobs: mpsc::UnboundedReceiver<ArchiveJob>,
+) {
+    while let Some(job) = jobs.recv().await {
+        tokio::spawn(deliver_archive(Arc::clone(&driver), Arc::clone(&shared), job));
+    }
+}
+
+/// Post one archive batch and report the outcome to the driver.
+async fn deliver_archive<Q: SequencerBackend + 'static>(
+    driver: Arc<Shards<Q>>,
+    shared: Arc<SharedState>,
+    job: ArchiveJob,
+) {
+    let ArchiveJob { room_id, endpoint, frames, delay } = job;
+    let Some(through_log_index) = frames.last().map(|frame| frame.log_index) else {
+        return;
+    };
+
+    tokio::time::sleep(delay).await;
+    let result = match encode_batch(room_id, &frames) {
+        Ok(body) => webhook::post(&endpoint, &body).await,
+        Err(e) => Err(e),
+    };
+    let event = match result {
+        Ok(()) => ServerEvent::ArchiveDelivered { room_id, through_log_index },
+        Err(reason) => ServerEvent::ArchiveFailed { room_id, reason },
+    };
+
+    let mut driver = driver.for_room(room_id).lock().await;
+    let result = match process_event(&mut driver, event, &shared) {
+        Ok(actions) => execute_actions(&mut driver, actions, &shared).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = result {
+        tracing::error!("Archive error: {}", e);
+    }
+}
+
+/// Handle a single client connection.
+///
+/// A QUIC attempt is screened by the address-only filters before its
+/// handshake; the full chain runs once the handshake completes.
+async fn handle_connection<Q: SequencerBackend + 'static>(
+    incoming: Incoming,
+    driver: Arc<Shards<Q>>,
+    shared: Arc<SharedState>,
+) -> Result<(), ServerError> {
+    let conn = match incoming {
+        Incoming::Quic(incoming) => {
+            let remote = incoming.remote_addr();
+            let screened = shared.accept.lock().await.screen(remote);
+            if let Err(rejection) = screened {
+                audit_rejection(&rejection, &remote.to_string(), "");
+                incoming.refuse();
+                return Ok(());
+            }
+            Accepted::Quic(incoming.connect().await?)
+        },
+        Incoming::Memory(conn) => Accepted::Memory(conn),
+    };
+    let peer = match &conn {
+        Accepted::Quic(conn) => {
+            PeerInfo { remote_addr: Some(conn.remote_addr()), principal: conn.peer_principal() }
+        },
+        Accepted::Memory(_) => PeerInfo { remote_addr: None, principal: None },
+    };
+    let remote = peer.remote_addr.map_or_else(|| "in-process".to_string(), |a| a.to_string());
+    let principal = peer.principal.as_deref().unwrap_or("");
+
+    let checked = shared.accept.lock().await.check(&peer);
+    if let Err(rejection) = checked {
+        audit_rejection(&rejection, &remote, principal);
+        match conn {
+            Accepted::Quic(conn) => conn.close(0u32.into(), rejection.reason.as_bytes()),
+            Accepted::Memory(conn) => conn.link().close(&rejection.reason),
+        }
+        return Ok(());
+    }
+    tracing::info!(
+        target: AUDIT_LOG_TARGET,
+        decision = "accept",
+        remote = %remote,
+        principal,
+        "connection accepted"
+    );
+
+    let result = serve_connection(conn, driver, Arc::clone(&shared)).await;
+    shared.accept.lock().await.closed(&peer);
+    result
+}
+
+/// Write a refused connection to the audit log.
+fn audit_rejection(rejection: &AcceptRejection, remote: &str, principal: &str) {
+    tracing::info!(
+        target: AUDIT_LOG_TARGET,
+        decision = "reject",
+        filter = rejection.filter,
+        remote = %remote,
+        principal,
+        reason = %rejection.reason,
+        "connection rejected"
+    );
+}
+
+/// Register a session for an accepted connection and serve it until it
+/// closes.
+///
+/// The connection is run as an actor: reader tasks decode frames from its
+/// streams into a mailbox, this task handles them one at a time, and the
+/// actions it executes reach the peer through the session's outbound queue
+/// and writer task. Reads and writes of one connection are therefore
+/// serialized without the driver being locked per stream.
+async fn serve_connection<Q: SequencerBackend + 'static>(
+    conn: Accepted,
+    driver: Arc<Shards<Q>>,
+    shared: Arc<SharedState>,
+) -> Result<(), ServerError> {
+    let session_id = driver.primary().lock().await.allocate_session_id();
+
+    tracing::debug!("New connection: {}", session_id);
+
+    let link = match &conn {
+        Accepted::Quic(conn) => Link::Quic(conn.clone()),
+        Accepted::Memory(conn) => Link::Memory(conn.link()),
+    };
+    let peer = Peer { link, wake: Arc::new(Notify::new()), closing: Arc::new(OnceLock::new()) };
+    let closing = Arc::clone(&peer.closing);
+    shared.connections.write().await.insert(session_id, peer.clone());
+    tokio::spawn(run_writer(session_id, peer, Arc::clone(&shared)));
+
+    let principal = match &conn {
+        Accepted::Quic(conn) => conn.peer_principal(),
+        Accepted::Memory(_) => None,
+    };
+    let info = {
+        let mut primary = driver.primary().lock().await;
+        let mut actions =
+            process_event(&mut primary, ServerEvent::ConnectionAccepted { session_id }, &shared)?;
+        if let Some(principal) = principal {
+            let event = ServerEvent::PeerAuthenticated { session_id, principal };
+            actions.extend(process_event(&mut primary, event, &shared)?);
+        }
+        execute_actions(&mut primary, actions, &shared).await?;
+        primary.session_info(session_id).cloned()
+    };
+    if let Some(info) = info {
+        sync_session(&driver, session_id, info).await;
+    }
+
+    // The connection's actor: frames from every stream are handled one at a
+    // time, in the order they were read, so each is sequenced and its
+    // actions executed before the next is looked at
+    let (mailbox, mut inbox) = mpsc::channel(MAILBOX_FRAMES);
+    let reader = tokio::spawn(read_connection(conn, mailbox));
+    let mut waiting = 0;
+    let served = async {
+        while let Some(inbound) = inbox.recv().await {
+            waiting = track_inbound(&shared, waiting, inbox.len());
+            match inbound {
+                Inbound::Frame(frame) => handle_frame(session_id, frame, &driver, &shared).await?,
+                Inbound::Undecodable(reason) => {
+                    report_decode_failure(&driver, &shared, session_id, &reason).await?;
+                },
+            }
+        }
+        Ok::<_, ServerError>(())
+    };
+    let served = served.await;
+    reader.abort();
+    track_inbound(&shared, waiting, 0);
+    served?;
+
+    close_session(&shared, session_id, "connection closed").await;
+
+    // The first reason given for closing, e.g. by the driver or an operator
+    let reason = closing.get().map_or_else(|| "connection closed".to_string(), Clone::clone);
     {
-        let mut driver = driver.lock().await;
-        let actions = driver.process_event(ServerEvent::ConnectionClosed {
-            session_id,
-            reason: "connection closed".to_string(),
-        })?;
-        execute_actions(&mut *driver, actions, &shared).await?;
+        let mut primary = driver.primary().lock().await;
+        let event = ServerEvent::ConnectionClosed { session_id, reason: reason.clone() };
+        let actions = process_event(&mut primary, event, &shared)?;
+        execute_actions(&mut primary, actions, &shared).await?;
+    }
+    // Other shards only leave the session's rooms; the primary logged the event
+    for other in driver.others() {
+        let mut other = other.lock().await;
+        let event = ServerEvent::ConnectionClosed { session_id, reason: reason.clone() };
+        let actions = other.process_event(event)?;
+        execute_actions(&mut other, actions, &shared).await?;
     }
 
     Ok(())
 }
 
-/// Handle a single bidirectional stream.
-async fn handle_stream(
-    session_id: u64,
+/// Update the count of received frames waiting across all connections, for
+/// a mailbox that went from `was` to `now` frames. Returns the new count.
+fn track_inbound(shared: &SharedState, was: u64, now: usize) -> u64 {
+    let now = u64::try_from(now).unwrap_or(u64::MAX);
+    if now >= was {
+        shared.inbound.fetch_add(now.abs_diff(was), Ordering::Relaxed);
+    } else {
+        shared.inbound.fetch_sub(was.abs_diff(now), Ordering::Relaxed);
+    }
+    now
+}
+
+/// Read frames from every stream of a connection into its actor's mailbox
+/// until the connection closes or the actor stops.
+///
+/// A stream whose bytes don't decode is read no further. The in-process
+/// transport has a single stream, so that ends the connection. Clients open
+/// unidirectional streams only for the payloads of streamed frames.
+async fn read_connection(conn: Accepted, mailbox: mpsc::Sender<Inbound>) {
+    match conn {
+        Accepted::Quic(conn) => {
+            let streamed = Arc::new(StreamedPayloads::default());
+            loop {
+                let accepted = tokio::select! {
+                    bi = conn.accept_bi() => bi.map(|(send, recv)| {
+                        tokio::spawn(read_stream(send, recv, mailbox.clone(), streamed.clone()));
+                    }),
+                    uni = conn.accept_uni() => uni.map(|recv| {
+                        tokio::spawn(read_payload_stream(recv, mailbox.clone(), streamed.clone()));
+                    }),
+                };
+                if let Err(e) = accepted {
+                    tracing::debug!("Connection closed: {}", e);
+                    break;
+                }
+            }
+        },
+        // One encoded frame per message
+        Accepted::Memory(mut conn) => {
+            while let Some(bytes) = conn.recv().await {
+                let inbound = match Frame::decode(&bytes) {
+                    Ok(frame) => Inbound::Frame(frame),
+                    Err(e) => Inbound::Undecodable(format!("frame decode error: {e}")),
+                };
+                let undecodable = matches!(inbound, Inbound::Undecodable(_));
+                if mailbox.send(inbound).await.is_err() || undecodable {
+                    break;
+                }
+            }
+        },
+    }
+}
+
+/// Read frames from a single bidirectional stream into the mailbox.
+async fn read_stream(
     send: quinn::SendStream,
     mut recv: quinn::RecvStream,
-    driver: Arc<tokio::sync::Mutex<ServerDriver<SystemEnv, MemoryStorage>>>,
-    shared: &Arc<SharedState>,
-) -> Result<(), ServerError> {
+    mailbox: mpsc::Sender<Inbound>,
+    streamed: Arc<StreamedPayloads>,
+) {
     drop(send); // not used for now
 
     let mut buf = BytesMut::with_capacity(65536);
@@ -227,16 +1681,23 @@ async fn handle_stream(
             },
         }
 
-        let header: &FrameHeader = match FrameHeader::ref_from_bytes(&buf[..128]) {
-            Ok(h) => h,
-            Err(_) => {
-                tracing::warn!("Invalid frame header");
-                break;
-            },
+        let Ok(header) = FrameHeader::ref_from_bytes(&buf[..128]) else {
+            let _ = mailbox.send(Inbound::Undecodable("invalid frame header".to_string())).await;
+            break;
         };
 
         let payload_size = header.payload_size() as usize;
 
+        // The payload follows on its own stream
+        if header.flags().contains(FrameFlags::STREAMED) {
+            let header = *header;
+            if let Err(reason) = read_streamed(header, &mailbox, &streamed).await {
+                let _ = mailbox.send(Inbound::Undecodable(reason)).await;
+                break;
+            }
+            continue;
+        }
+
         if payload_size > 0 {
             buf.resize(128 + payload_size, 0);
             if let Err(e) = recv.read_exact(&mut buf[128..]).await {
@@ -245,88 +1706,255 @@ async fn handle_stream(
             }
         }
 
-        let frame = match Frame::decode(&buf) {
-            Ok(f) => f,
+        let inbound = match Frame::decode(&buf) {
+            Ok(frame) => Inbound::Frame(frame),
             Err(e) => {
-                tracing::warn!("Frame decode error: {}", e);
+                let _ =
+                    mailbox.send(Inbound::Undecodable(format!("frame decode error: {e}"))).await;
                 break;
             },
         };
+        // The actor stopped, so the connection is closing
+        if mailbox.send(inbound).await.is_err() {
+            break;
+        }
+    }
+}
 
-        let actions = {
-            let mut driver = driver.lock().await;
-            match driver.process_event(ServerEvent::FrameReceived { session_id, frame }) {
-                Ok(actions) => actions,
-                Err(e) => {
-                    tracing::warn!("Frame processing error: {}", e);
-                    continue;
-                },
-            }
-        };
+/// Match a streamed frame's header with its payload and hand the frame to
+/// the mailbox once both arrived.
+///
+/// The control stream is not read on until the payload arrives, so frames
+/// sent after the streamed one cannot overtake it.
+async fn read_streamed(
+    header: FrameHeader,
+    mailbox: &mpsc::Sender<Inbound>,
+    streamed: &StreamedPayloads,
+) -> Result<(), String> {
+    let request_id = header.request_id();
+    if header.payload_size() > FrameHeader::MAX_PAYLOAD_SIZE {
+        return Err(format!("streamed payload of {} bytes is too large", header.payload_size()));
+    }
+
+    let payload = match streamed.header(request_id).await? {
+        Matched::Ready(payload) => payload,
+        Matched::Waiting { payload, ticket } => {
+            let Ok(Ok(payload)) = tokio::time::timeout(STREAM_MATCH_TIMEOUT, payload).await else {
+                streamed.forget(request_id, ticket).await;
+                return Err(format!("payload of streamed request {request_id} never arrived"));
+            };
+            payload
+        },
+    };
+    let _ = mailbox.send(streamed_frame(&header, &payload)).await;
+    Ok(())
+}
+
+/// Read the payload of a streamed frame from its unidirectional stream.
+async fn read_payload_stream(
+    mut recv: quinn::RecvStream,
+    mailbox: mpsc::Sender<Inbound>,
+    streamed: Arc<StreamedPayloads>,
+) {
+    let kept = match read_budgeted(&mut recv, &streamed).await {
+        Ok(Some((bytes, budget))) => match Frame::decode_stream_payload(&bytes) {
+            Ok((request_id, payload)) => streamed
+                .payload(request_id, bytes.slice_ref(payload), budget)
+                .await
+                .map(|ticket| ticket.map(|ticket| (request_id, ticket))),
+            Err(e) => Err(format!("payload stream decode error: {e}")),
+        },
+        Ok(None) => return,
+        Err(reason) => Err(reason),
+    };
+    match kept {
+        // The header has a while to catch up
+        Ok(Some((request_id, ticket))) => {
+            tokio::time::sleep(STREAM_MATCH_TIMEOUT).await;
+            streamed.forget(request_id, ticket).await;
+        },
+        Ok(None) => {},
+        Err(reason) => {
+            let _ = mailbox.send(Inbound::Undecodable(reason)).await;
+        },
+    }
+}
 
-        {
-            let mut driver = driver.lock().await;
-            execute_actions(&mut *driver, actions, shared).await?;
+/// Read a payload stream to its end, taking each chunk from the
+/// connection's byte budget before buffering it. `None` if the stream failed.
+async fn read_budgeted(
+    recv: &mut quinn::RecvStream,
+    streamed: &StreamedPayloads,
+) -> Result<Option<(Bytes, OwnedSemaphorePermit)>, String> {
+    let limit = (FrameHeader::MAX_PAYLOAD_SIZE as usize).saturating_add(Frame::STREAM_PREFIX_SIZE);
+    let mut bytes = BytesMut::new();
+    let mut budget = streamed.reserve(0)?;
+    loop {
+        let remaining = limit.saturating_sub(bytes.len());
+        match recv.read_chunk(remaining.max(1), true).await {
+            Ok(Some(chunk)) => {
+                if chunk.bytes.len() > remaining {
+                    return Err(format!("payload stream longer than {limit} bytes"));
+                }
+                budget.merge(streamed.reserve(chunk.bytes.len())?);
+                bytes.extend_from_slice(&chunk.bytes);
+            },
+            Ok(None) => return Ok(Some((bytes.freeze(), budget))),
+            Err(e) => {
+                tracing::debug!("Payload stream read error: {}", e);
+                return Ok(None);
+            },
         }
     }
+}
+
+/// Join a streamed frame's header and payload.
+fn streamed_frame(header: &FrameHeader, payload: &[u8]) -> Inbound {
+    let expected = header.payload_size() as usize;
+    if payload.len() != expected {
+        return Inbound::Undecodable(format!(
+            "streamed payload is {} bytes, header says {}",
+            payload.len(),
+            expected
+        ));
+    }
 
+    let mut buf = BytesMut::with_capacity(FrameHeader::SIZE.saturating_add(expected));
+    buf.extend_from_slice(&header.to_bytes());
+    buf.extend_from_slice(payload);
+    match Frame::decode(&buf) {
+        Ok(frame) => Inbound::Frame(frame),
+        Err(e) => Inbound::Undecodable(format!("frame decode error: {e}")),
+    }
+}
+
+/// Feed a frame from a session to the driver and execute what it decides.
+async fn handle_frame<Q: SequencerBackend + 'static>(
+    session_id: u64,
+    frame: Frame,
+    driver: &Shards<Q>,
+    shared: &SharedState,
+) -> Result<(), ServerError> {
+    wait_while_paused(shared, frame.header.room_id()).await;
+
+    // The primary owns sessions, so it admits every frame
+    {
+        let mut primary = driver.primary().lock().await;
+        if shared.log_events {
+            log_event(shared, &ServerEvent::FrameReceived { session_id, frame: frame.clone() });
+        }
+        match primary.admit_frame(session_id, &frame) {
+            Ok(None) => {},
+            Ok(Some(refused)) => return execute_actions(&mut primary, refused, shared).await,
+            Err(e) => {
+                tracing::warn!("Frame processing error: {}", e);
+                return Ok(());
+            },
+        }
+    }
+
+    // Actions are executed under the same lock they were decided under, so
+    // broadcasts leave in the order their frames were sequenced
+    let changed = {
+        let mut owner = driver.for_frame(&frame).lock().await;
+        // Session frames are processed by the primary; only a handshake
+        // step changes what the shards need to know about the session
+        let before = owner.session_info(session_id).cloned();
+        match owner.process_admitted_frame(session_id, frame) {
+            Ok(actions) => execute_actions(&mut owner, actions, shared).await?,
+            Err(e) => {
+                tracing::warn!("Frame processing error: {}", e);
+                return Ok(());
+            },
+        }
+        owner.session_info(session_id).filter(|&after| Some(after) != before.as_ref()).cloned()
+    };
+    if let Some(info) = changed {
+        sync_session(driver, session_id, info).await;
+    }
     Ok(())
 }
 
+/// Copy what the primary knows about a session, e.g. after its handshake,
+/// to the shards of rooms.
+async fn sync_session<Q: SequencerBackend + 'static>(
+    driver: &Shards<Q>,
+    session_id: u64,
+    info: SessionInfo,
+) {
+    for shard in driver.others() {
+        shard.lock().await.attach_session(session_id, info.clone());
+    }
+}
+
+/// Count bytes that did not decode, logging them if sampled.
+async fn report_decode_failure<Q: SequencerBackend + 'static>(
+    driver: &Shards<Q>,
+    shared: &SharedState,
+    session_id: u64,
+    reason: &str,
+) -> Result<(), ServerError> {
+    let mut driver = driver.primary().lock().await;
+    let actions = driver.record_decode_failure(session_id, reason);
+    execute_actions(&mut driver, actions, shared).await
+}
+
+/// Feed an event to the driver, recording it in the event log if enabled.
+fn process_event<Q: SequencerBackend>(
+    driver: &mut ServerDriver<SystemEnv, ServerStorage, Q>,
+    event: ServerEvent,
+    shared: &SharedState,
+) -> Result<Vec<ServerAction>, DriverError> {
+    if shared.log_events {
+        log_event(shared, &event);
+    }
+    driver.process_event(event)
+}
+
+/// Record an event in the event log.
+fn log_event(shared: &SharedState, event: &ServerEvent) {
+    let record = EventRecord { at: shared.started.elapsed(), event: event.clone() };
+    tracing::info!(target: EVENT_LOG_TARGET, "{}", record.encode());
+}
+
 /// Execute server actions.
-async fn execute_actions(
-    driver: &mut ServerDriver<SystemEnv, MemoryStorage>,
+///
+/// Outgoing frames are handed to the sessions' writer tasks, which send them
+/// in priority order. A closed connection is closed once its queued frames
+/// are sent.
+#[allow(clippy::too_many_lines)]
+async fn execute_actions<Q: SequencerBackend + 'static>(
+    driver: &mut ServerDriver<SystemEnv, ServerStorage, Q>,
     actions: Vec<ServerAction>,
     shared: &SharedState,
 ) -> Result<(), ServerError> {
+    let mut persist = PersistBatch::new();
+
     for action in actions {
+        if !matches!(action, ServerAction::PersistFrame { .. }) {
+            persist_frames(driver.storage(), shared, persist.take());
+        }
+
         match action {
             ServerAction::SendToSession { session_id, frame } => {
-                let connections = shared.connections.read().await;
-                if let Some(conn) = connections.get(&session_id) {
-                    let mut buf = Vec::new();
-                    frame.encode(&mut buf).map_err(|e| ServerError::Protocol(e.to_string()))?;
-
-                    if let Ok(mut send) = conn.open_uni().await {
-                        let _ = send.write_all(&buf).await;
-                        let _ = send.finish();
-                    }
-                } else {
-                    tracing::warn!("SendToSession: session {} not found", session_id);
-                }
+                queue_frame(shared, &[session_id], &frame, None).await;
             },
 
-            ServerAction::BroadcastToRoom { room_id, frame, exclude_session } => {
-                let sessions: Vec<u64> = driver.sessions_in_room(room_id).collect();
-
-                let mut buf = Vec::new();
-                frame.encode(&mut buf).map_err(|e| ServerError::Protocol(e.to_string()))?;
-
-                let connections = shared.connections.read().await;
-                for session_id in sessions {
-                    if Some(session_id) != exclude_session {
-                        if let Some(conn) = connections.get(&session_id) {
-                            if let Ok(mut send) = conn.open_uni().await {
-                                let _ = send.write_all(&buf).await;
-                                let _ = send.finish();
-                            }
-                        }
-                    }
-                }
+            ServerAction::BroadcastToRoom { room_id, frame, exclude_session, timing } => {
+                let recipients: Vec<u64> = driver
+                    .sessions_in_room(room_id)
+                    .filter(|session_id| Some(*session_id) != exclude_session)
+                    .collect();
+                queue_frame(shared, &recipients, &frame, timing).await;
             },
 
             ServerAction::CloseConnection { session_id, reason } => {
                 tracing::info!("Closing connection {}: {}", session_id, reason);
-                let mut connections = shared.connections.write().await;
-                if let Some(conn) = connections.remove(&session_id) {
-                    conn.close(0u32.into(), reason.as_bytes());
-                }
+                close_session(shared, session_id, &reason).await;
             },
 
             ServerAction::PersistFrame { room_id, log_index, frame } => {
-                if let Err(e) = driver.storage().store_frame(room_id, log_index, &frame) {
-                    tracing::error!("Failed to persist frame: {}", e);
-                }
+                persist_frames(driver.storage(), shared, persist.push(room_id, log_index, frame));
             },
 
             ServerAction::PersistMlsState { room_id, state } => {
@@ -335,6 +1963,80 @@ async fn execute_actions(
                 }
             },
 
+            ServerAction::PersistCommit { room_id, log_index, frame, state } => {
+                persist_commit(driver.storage(), shared, room_id, log_index, &frame, &state);
+            },
+
+            ServerAction::PersistProposal { room_id, proposal } => {
+                if let Err(e) = driver.storage().store_pending_proposal(room_id, &proposal) {
+                    tracing::error!("Failed to persist pending proposal: {}", e);
+                }
+            },
+
+            ServerAction::ClearProposals { room_id } => {
+                if let Err(e) = driver.storage().clear_pending_proposals(room_id) {
+                    tracing::error!("Failed to clear pending proposals: {}", e);
+                }
+            },
+
+            ServerAction::PersistReadMarker { room_id, member_id, up_to_log_index } => {
+                if let Err(e) =
+                    driver.storage().store_read_marker(room_id, member_id, up_to_log_index)
+                {
+                    tracing::error!("Failed to persist read marker: {}", e);
+                }
+            },
+
+            ServerAction::ArchiveFrames { room_id, endpoint, frames, delay } => {
+                let job = ArchiveJob { room_id, endpoint, frames, delay };
+                if shared.archive_jobs.send(job).is_err() {
+                    tracing::error!(
+                        "Archive delivery stopped, dropping batch for {:032x}",
+                        room_id
+                    );
+                }
+            },
+
+            // Delivered to membership hooks by the driver
+            ServerAction::MembershipChanged(_) => {},
+
+            ServerAction::NotifyOffline(notice) => {
+                for sink in &shared.notifications {
+                    sink.notify(&notice);
+                }
+            },
+
+            ServerAction::Relay { to, relayed } => {
+                if let Some(link) = shared.relay_links.read().await.get(&to) {
+                    link.send(relayed);
+                } else {
+                    tracing::warn!("No link to federated {}, dropping relay", to);
+                }
+            },
+
+            ServerAction::Rejected(record) => {
+                tracing::warn!(
+                    target: REJECT_LOG_TARGET,
+                    kind = record.kind.as_str(),
+                    session_id = record.session_id,
+                    room_id = %format_args!("{:032x}", record.room_id),
+                    suppressed = record.suppressed,
+                    "{}",
+                    record.reason
+                );
+            },
+
+            ServerAction::Audit(record) => {
+                tracing::info!(
+                    target: AUDIT_LOG_TARGET,
+                    seq = record.seq,
+                    principal = record.principal.as_deref(),
+                    kind = record.event.kind(),
+                    event = ?record.event,
+                    "audit"
+                );
+            },
+
             ServerAction::Log { level, message, .. } => match level {
                 LogLevel::Debug => tracing::debug!("{}", message),
                 LogLevel::Info => tracing::info!("{}", message),
@@ -344,5 +2046,187 @@ async fn execute_actions(
         }
     }
 
+    persist_frames(driver.storage(), shared, persist.take());
     Ok(())
 }
+
+/// Write a coalesced batch of frames in one storage commit.
+fn persist_frames(
+    storage: &ServerStorage,
+    shared: &SharedState,
+    batch: Option<(u128, Vec<Frame>)>,
+) {
+    if let Some((room_id, frames)) = batch {
+        let started = Instant::now();
+        if let Err(e) = storage.store_frames_batch(room_id, &frames) {
+            tracing::error!("Failed to persist {} frames: {}", frames.len(), e);
+        }
+        // Reported as storage latency by the next load report
+        let micros = u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX);
+        shared.persist_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+}
+
+/// Write a commit frame and the MLS state it produced in one storage commit.
+fn persist_commit(
+    storage: &ServerStorage,
+    shared: &SharedState,
+    room_id: u128,
+    log_index: u64,
+    frame: &Frame,
+    state: &MlsGroupState,
+) {
+    let started = Instant::now();
+    if let Err(e) = storage.store_commit(room_id, log_index, frame, state) {
+        tracing::error!("Failed to persist commit: {}", e);
+    }
+    let micros = u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX);
+    shared.persist_micros.fetch_max(micros, Ordering::Relaxed);
+}
+
+/// Queue a frame for each recipient and wake their writers.
+///
+/// Applies the queue's [`OverflowPolicy`] to recipients that are backed up.
+async fn queue_frame(
+    shared: &SharedState,
+    recipients: &[u64],
+    frame: &Frame,
+    timing: Option<FrameTiming>,
+) {
+    let peers: Vec<(u64, Option<Peer>)> = {
+        let connections = shared.connections.read().await;
+        recipients.iter().map(|id| (*id, connections.get(id).cloned())).collect()
+    };
+
+    let mut overflows = Vec::new();
+    {
+        let mut outbound = shared.outbound.lock().await;
+        for (session_id, peer) in &peers {
+            if peer.is_none() {
+                tracing::warn!("Session {} has no connection, dropping frame", session_id);
+                continue;
+            }
+            if let Some(overflow) = outbound.push_timed(*session_id, frame.clone(), timing) {
+                overflows.push((*session_id, overflow));
+            }
+        }
+    }
+
+    for (session_id, overflow) in overflows {
+        match overflow {
+            Overflow::DroppedOldest(_) => {
+                tracing::debug!("Send queue of session {} full, dropped oldest frame", session_id);
+            },
+            Overflow::Disconnect => {
+                tracing::warn!("Send queue of session {} full, disconnecting", session_id);
+                close_session(shared, session_id, "send queue full").await;
+            },
+            Overflow::PauseRoom { room_id } => {
+                tracing::debug!(
+                    "Send queue of session {} full, pausing room {:032x}",
+                    session_id,
+                    room_id
+                );
+            },
+        }
+    }
+
+    for peer in peers.into_iter().filter_map(|(_, peer)| peer) {
+        peer.wake.notify_one();
+    }
+}
+
+/// Close a session once its writer has sent what is already queued.
+async fn close_session(shared: &SharedState, session_id: u64, reason: &str) {
+    let peer = shared.connections.write().await.remove(&session_id);
+    if let Some(peer) = peer {
+        // Only the first reason is sent; the session is already closing otherwise
+        let _ = peer.closing.set(reason.to_string());
+        peer.wake.notify_one();
+    }
+}
+
+/// Write a session's queued frames until it is closed, highest priority
+/// first.
+///
+/// A session whose send still fails after the [`BroadcastPolicy`] retries has
+/// its queue discarded so later frames are not delivered out of order.
+async fn run_writer(session_id: u64, peer: Peer, shared: Arc<SharedState>) {
+    loop {
+        let next = shared.outbound.lock().await.pop_timed(session_id);
+        let Some((frame, timing)) = next else {
+            if let Some(reason) = peer.closing.get() {
+                peer.link.close(reason);
+                return;
+            }
+            peer.wake.notified().await;
+            continue;
+        };
+        shared.drained.notify_waiters();
+
+        let mut buf = Vec::new();
+        if let Err(e) = frame.encode_with_timing(timing.as_ref(), &mut buf) {
+            tracing::error!("Failed to encode frame for session {}: {}", session_id, e);
+            continue;
+        }
+
+        if let Err(e) = send_with_policy(&peer.link, session_id, &buf, &shared).await {
+            let dropped = shared.outbound.lock().await.drain(session_id).len();
+            shared.drained.notify_waiters();
+            tracing::warn!("Dropping {} frames for session {}: {}", dropped, session_id, e);
+        }
+    }
+}
+
+/// Hold back a frame for a room paused by a full send queue.
+async fn wait_while_paused(shared: &SharedState, room_id: u128) {
+    loop {
+        let drained = shared.drained.notified();
+        tokio::pin!(drained);
+        drained.as_mut().enable();
+
+        if !shared.outbound.lock().await.is_paused(room_id) {
+            return;
+        }
+        drained.await;
+    }
+}
+
+/// Send one encoded frame on its own stream, retrying per the broadcast
+/// policy.
+///
+/// Runs on the session's writer task and holds neither the driver nor the
+/// outbound queues while it backs off, so a slow recipient only delays its
+/// own frames.
+async fn send_with_policy(
+    link: &Link,
+    session_id: u64,
+    buf: &[u8],
+    shared: &SharedState,
+) -> Result<(), ExecutorError> {
+    let policy = shared.broadcast;
+    let mut retry = 0;
+    loop {
+        match link.send(buf).await {
+            Ok(()) => return Ok(()),
+            Err(reason) if retry < policy.retries() => {
+                retry = retry.saturating_add(1);
+                tracing::debug!(
+                    "Send to session {} failed, retry {}: {}",
+                    session_id,
+                    retry,
+                    reason
+                );
+                shared.env.sleep(policy.backoff(retry, &shared.env)).await;
+            },
+            Err(reason) => return Err(ExecutorError::SendFailed { session_id, reason }),
+        }
+    }
+}
+
+/// Write an encoded frame to a fresh unidirectional stream.
+async fn send_frame(conn: &QuinnConnection, buf: &[u8]) -> Result<(), String> {
+    let mut send = conn.open_uni().await.map_err(|e| e.to_string())?;
+    send.write_all(buf).await.map_err(|e| e.to_string())?;
+    send.finish().map_err(|e| e.to_string())
+}
@@ -8,10 +8,68 @@
 //!
 //! # Start with TLS certificate (production)
 //! lockframe-server --bind 0.0.0.0:4433 --cert cert.pem --key key.pem
+//!
+//! # Require client certificates issued by a private CA (mutual TLS)
+//! lockframe-server --bind 0.0.0.0:4433 --cert cert.pem --key key.pem --client-ca clients.pem
+//!
+//! # Make clients prove they hold their identity key before authenticating them
+//! lockframe-server --bind 0.0.0.0:4433 --require-identity
+//!
+//! # Only accept clients from the office network, at most 4 connections each
+//! lockframe-server --bind 0.0.0.0:4433 --allow-ip 203.0.113.0/24 --deny-ip 203.0.113.66 \
+//!     --max-connections-per-ip 4
+//!
+//! # Persist frames and MLS state across restarts
+//! lockframe-server --bind 0.0.0.0:4433 --data-dir /var/lib/lockframe
+//!
+//! # Single-node deployment on SQLite
+//! lockframe-server --bind 0.0.0.0:4433 --sqlite /var/lib/lockframe.db
+//!
+//! # Log frames to a write-ahead log before they reach the database
+//! lockframe-server --bind 0.0.0.0:4433 --sqlite /var/lib/lockframe.db --wal /var/lib/lockframe.wal
+//!
+//! # Keep the latest 10000 frames per room hot, archive older ones
+//! lockframe-server --bind 0.0.0.0:4433 --data-dir /var/lib/lockframe \
+//!     --archive-dir /mnt/archive --hot-frames 10000
+//!
+//! # Archive old frames to an S3 bucket instead, signing as the AWS_* credentials
+//! AWS_ACCESS_KEY_ID=... AWS_SECRET_ACCESS_KEY=... lockframe-server --data-dir /var/lib/lockframe \
+//!     --archive-s3-endpoint http://minio:9000 --archive-s3-bucket lockframe-archive
+//!
+//! # Keep at most 30 days or 100000 frames of history per room
+//! lockframe-server --bind 0.0.0.0:4433 --data-dir /var/lib/lockframe \
+//!     --retention-max-age-secs 2592000 --retention-max-frames 100000
+//!
+//! # Reclaim disk space freed by pruning between 02:00 and 05:00 UTC
+//! lockframe-server --bind 0.0.0.0:4433 --sqlite /var/lib/lockframe.db \
+//!     --retention-max-frames 100000 --vacuum-window 02:00-05:00
+//!
+//! # Record every driver event for offline incident replay
+//! lockframe-server --bind 0.0.0.0:4433 --event-log
+//!
+//! # Back up a room's log and MLS state, then restore it on another node
+//! lockframe-server --data-dir /var/lib/lockframe dump --room 0x1234 --output room.cbor
+//! lockframe-server --data-dir /var/lib/lockframe restore --input room.cbor
 //! ```
 
-use clap::Parser;
-use lockframe_server::{DriverConfig, Server, ServerRuntimeConfig};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use lockframe_core::connection::ConnectionConfig;
+use lockframe_server::{
+    AcceptConfig, ArchiveConfig, BroadcastPolicy, DEFAULT_BURST_FRAMES, DEFAULT_FRAMES_PER_SEC,
+    DEFAULT_HOT_FRAMES, DEFAULT_MAX_RATE_VIOLATIONS, DEFAULT_REJECT_LOG_PER_SEC,
+    DEFAULT_REJECT_LOG_SAMPLE, DEFAULT_ROOM_BURST_SECS, DEFAULT_SESSION_QUEUE_FRAMES,
+    DEFAULT_VACUUM_INTERVAL, DEFAULT_VACUUM_MAX_BYTES, DriverConfig, IpRange, OverflowPolicy,
+    QueueLimits, RateLimitConfig, RejectLogConfig, RetentionConfig, RetentionPolicy,
+    RoomThroughputConfig, S3Config, Server, ServerRuntimeConfig, StorageBackend, VacuumConfig,
+    VacuumWindow, storage,
+};
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 /// Lockframe protocol server
@@ -32,13 +90,248 @@ struct Args {
     #[arg(short, long)]
     key: Option<String>,
 
+    /// CA certificates clients must authenticate with (PEM format); enables
+    /// mutual TLS
+    #[arg(long)]
+    client_ca: Option<String>,
+
+    /// Challenge clients to sign with their identity key before authenticating
+    /// them
+    #[arg(long)]
+    require_identity: bool,
+
+    /// Only accept connections from this address or CIDR range (repeatable;
+    /// any address if omitted)
+    #[arg(long)]
+    allow_ip: Vec<IpRange>,
+
+    /// Refuse connections from this address or CIDR range (repeatable)
+    #[arg(long)]
+    deny_ip: Vec<IpRange>,
+
+    /// Most open connections from one address (unlimited if omitted)
+    #[arg(long)]
+    max_connections_per_ip: Option<usize>,
+
+    /// Directory for durable storage (in-memory if omitted)
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+
+    /// SQLite database file for durable storage
+    #[arg(long, conflicts_with = "data_dir")]
+    sqlite: Option<PathBuf>,
+
+    /// Write-ahead log file for frames, replayed into storage on startup
+    #[arg(long)]
+    wal: Option<PathBuf>,
+
+    /// Directory to archive old frames to (kept in storage if omitted)
+    #[arg(long)]
+    archive_dir: Option<PathBuf>,
+
+    /// S3-compatible service to archive old frames to, `http://host[:port]`.
+    /// Requests are signed with `AWS_ACCESS_KEY_ID` and
+    /// `AWS_SECRET_ACCESS_KEY` from the environment.
+    #[arg(long, conflicts_with = "archive_dir", requires = "archive_s3_bucket")]
+    archive_s3_endpoint: Option<String>,
+
+    /// Bucket to archive old frames to
+    #[arg(long, requires = "archive_s3_endpoint")]
+    archive_s3_bucket: Option<String>,
+
+    /// Region S3 requests are signed for
+    #[arg(long, default_value = "us-east-1")]
+    archive_s3_region: String,
+
+    /// Most recent frames per room kept out of the archive
+    #[arg(long, default_value_t = DEFAULT_HOT_FRAMES)]
+    hot_frames: u64,
+
+    /// Log every driver event so incidents can be replayed in simulation
+    #[arg(long)]
+    event_log: bool,
+
+    /// Serve the admin HTTP API on this address (off if omitted). It has no
+    /// authentication; use a loopback or management address.
+    #[arg(long)]
+    admin_bind: Option<String>,
+
+    /// Spread rooms over this many drivers so busy rooms are sequenced in
+    /// parallel
+    #[arg(long, default_value_t = 1)]
+    shards: usize,
+
+    /// Prune frames older than this many seconds
+    #[arg(long)]
+    retention_max_age_secs: Option<u64>,
+
+    /// Keep at most this many frames per room
+    #[arg(long)]
+    retention_max_frames: Option<u64>,
+
+    /// Seconds between storage vacuum passes
+    #[arg(long, default_value_t = DEFAULT_VACUUM_INTERVAL.as_secs())]
+    vacuum_interval_secs: u64,
+
+    /// Daily UTC window for vacuum passes, as HH:MM-HH:MM (any time if omitted)
+    #[arg(long)]
+    vacuum_window: Option<VacuumWindow>,
+
+    /// Most bytes a vacuum pass rewrites
+    #[arg(long, default_value_t = DEFAULT_VACUUM_MAX_BYTES)]
+    vacuum_max_bytes: u64,
+
+    /// Times a failed send to a client is retried (dropped at once if omitted)
+    #[arg(long)]
+    send_retries: Option<u32>,
+
+    /// Delay before the first send retry, doubling with each retry
+    #[arg(long, default_value = "50")]
+    send_backoff_ms: u64,
+
+    /// Most frames queued for one client before the overflow policy applies
+    #[arg(long, default_value_t = DEFAULT_SESSION_QUEUE_FRAMES)]
+    send_queue_frames: usize,
+
+    /// What to do when a client's send queue is full (drop-oldest,
+    /// drop-connection, pause-room)
+    #[arg(long, default_value = "drop-oldest")]
+    send_queue_overflow: OverflowPolicy,
+
+    /// Frames per second each client may send
+    #[arg(long, default_value_t = DEFAULT_FRAMES_PER_SEC)]
+    rate_limit_per_sec: u64,
+
+    /// Frames a client may send in a burst above its rate
+    #[arg(long, default_value_t = DEFAULT_BURST_FRAMES)]
+    rate_limit_burst: u64,
+
+    /// Frames refused for the rate limit before a client is disconnected
+    #[arg(long, default_value_t = DEFAULT_MAX_RATE_VIOLATIONS)]
+    rate_limit_violations: u32,
+
+    /// Log one in this many rejected frames in full (0 logs none; all are
+    /// counted)
+    #[arg(long, default_value_t = DEFAULT_REJECT_LOG_SAMPLE)]
+    reject_log_sample: u64,
+
+    /// Most rejected frames logged per second
+    #[arg(long, default_value_t = DEFAULT_REJECT_LOG_PER_SEC)]
+    reject_log_per_sec: u32,
+
     /// Maximum concurrent connections
     #[arg(long, default_value = "10000")]
     max_connections: usize,
 
+    /// Maximum members per room (unlimited if omitted)
+    #[arg(long)]
+    max_members_per_room: Option<usize>,
+
+    /// Application messages per second each room may sequence (unlimited if
+    /// omitted)
+    #[arg(long)]
+    room_messages_per_sec: Option<u64>,
+
+    /// Application message bytes per second each room may sequence
+    /// (unlimited if omitted)
+    #[arg(long)]
+    room_bytes_per_sec: Option<u64>,
+
+    /// Seconds of room throughput a quiet room can save up
+    #[arg(long, default_value_t = DEFAULT_ROOM_BURST_SECS)]
+    room_burst_secs: u64,
+
     /// Log level (trace, debug, info, warn, error)
     #[arg(long, default_value = "info")]
     log_level: String,
+
+    /// Run a maintenance command against the storage instead of serving
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Offline maintenance commands
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write a room's log and MLS state to a CBOR backup file
+    Dump {
+        /// Room ID in hex
+        #[arg(long, value_parser = parse_room_id)]
+        room: u128,
+
+        /// Backup file to create
+        #[arg(long)]
+        output: PathBuf,
+    },
+
+    /// Load a backup written by `dump` into the storage
+    Restore {
+        /// Backup file to read
+        #[arg(long)]
+        input: PathBuf,
+    },
+}
+
+fn parse_room_id(value: &str) -> Result<u128, String> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    u128::from_str_radix(digits, 16).map_err(|e| format!("invalid room ID {value:?}: {e}"))
+}
+
+/// Driver settings from the command line.
+fn driver_config(args: &Args) -> DriverConfig {
+    DriverConfig {
+        max_connections: args.max_connections,
+        max_members_per_room: args.max_members_per_room,
+        room_throughput: RoomThroughputConfig {
+            messages_per_sec: args.room_messages_per_sec,
+            bytes_per_sec: args.room_bytes_per_sec,
+            burst_secs: args.room_burst_secs,
+        },
+        connection: ConnectionConfig {
+            require_identity: args.require_identity,
+            ..Default::default()
+        },
+        rate_limit: RateLimitConfig {
+            frames_per_sec: args.rate_limit_per_sec,
+            burst_frames: args.rate_limit_burst,
+            max_violations: args.rate_limit_violations,
+        },
+        reject_log: RejectLogConfig {
+            sample_one_in: args.reject_log_sample,
+            max_per_second: args.reject_log_per_sec,
+        },
+        vacuum: VacuumConfig {
+            interval: Duration::from_secs(args.vacuum_interval_secs),
+            window: args.vacuum_window,
+            max_bytes_per_pass: args.vacuum_max_bytes,
+        },
+        retention: RetentionConfig {
+            policy: RetentionPolicy {
+                max_age: args.retention_max_age_secs.map(Duration::from_secs),
+                max_frames: args.retention_max_frames,
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// S3 archive the arguments configure, with credentials from the environment.
+fn archive_s3(args: &Args) -> Result<Option<S3Config>, Box<dyn std::error::Error>> {
+    let (Some(endpoint), Some(bucket)) = (&args.archive_s3_endpoint, &args.archive_s3_bucket)
+    else {
+        return Ok(None);
+    };
+    let credential = |name: &str| {
+        std::env::var(name).map_err(|_| format!("{name} must be set to archive to S3"))
+    };
+    Ok(Some(S3Config {
+        endpoint: endpoint.clone(),
+        region: args.archive_s3_region.clone(),
+        bucket: bucket.clone(),
+        access_key_id: credential("AWS_ACCESS_KEY_ID")?,
+        secret_access_key: credential("AWS_SECRET_ACCESS_KEY")?.into(),
+    }))
 }
 
 #[tokio::main]
@@ -49,21 +342,82 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tracing_subscriber::registry().with(fmt::layer()).with(filter).init();
 
-    tracing::info!("Lockframe server starting");
-    tracing::info!("Binding to {}", args.bind);
-
-    if args.cert.is_none() || args.key.is_none() {
-        tracing::warn!("No TLS certificate provided - using self-signed certificate");
-        tracing::warn!("This is NOT suitable for production use!");
-    }
+    let driver = driver_config(&args);
+    let archive_s3 = archive_s3(&args)?;
+    let storage = if let Some(path) = args.sqlite {
+        tracing::info!("Storing data in SQLite database {}", path.display());
+        StorageBackend::Sqlite { path }
+    } else if let Some(path) = args.data_dir {
+        tracing::info!("Storing data in {}", path.display());
+        StorageBackend::Sled { path }
+    } else {
+        tracing::warn!("No data directory provided - frames are lost on restart");
+        StorageBackend::Memory
+    };
 
     let config = ServerRuntimeConfig {
         bind_address: args.bind,
         cert_path: args.cert,
         key_path: args.key,
-        driver: DriverConfig { max_connections: args.max_connections, ..Default::default() },
+        client_ca_path: args.client_ca,
+        accept: AcceptConfig {
+            allow: args.allow_ip,
+            deny: args.deny_ip,
+            max_per_ip: args.max_connections_per_ip,
+        },
+        driver,
+        storage,
+        wal_path: args.wal,
+        archive_dir: args.archive_dir,
+        archive_s3,
+        archive: ArchiveConfig { hot_frames: args.hot_frames, ..Default::default() },
+        event_log: args.event_log,
+        broadcast: args.send_retries.map_or(BroadcastPolicy::BestEffort, |max_attempts| {
+            BroadcastPolicy::Retry { max_attempts, initial_backoff_ms: args.send_backoff_ms }
+        }),
+        send_queue: QueueLimits {
+            max_frames: args.send_queue_frames,
+            overflow: args.send_queue_overflow,
+        },
+        in_memory: false,
+        admin_bind: args.admin_bind,
+        shards: args.shards,
     };
 
+    match args.command {
+        Some(Command::Dump { room, output }) => {
+            let storage = config.open_storage()?;
+            let summary = storage::dump(&storage, room, BufWriter::new(File::create(&output)?))?;
+            tracing::info!(
+                "Dumped {} frames of room {:032x} to {}",
+                summary.frames,
+                summary.room_id,
+                output.display()
+            );
+            return Ok(());
+        },
+        Some(Command::Restore { input }) => {
+            let storage = config.open_storage()?;
+            let summary = storage::restore(&storage, BufReader::new(File::open(&input)?))?;
+            tracing::info!(
+                "Restored {} frames of room {:032x} from {}",
+                summary.frames,
+                summary.room_id,
+                input.display()
+            );
+            return Ok(());
+        },
+        None => {},
+    }
+
+    tracing::info!("Lockframe server starting");
+    tracing::info!("Binding to {}", config.bind_address);
+
+    if config.cert_path.is_none() || config.key_path.is_none() {
+        tracing::warn!("No TLS certificate provided - using self-signed certificate");
+        tracing::warn!("This is NOT suitable for production use!");
+    }
+
     let server = Server::bind(config).await?;
 
     tracing::info!("Server listening on {}", server.local_addr()?);
@@ -0,0 +1,147 @@
+//! In-process transport.
+//!
+//! Connects clients living in the same process as the server without
+//! sockets or TLS, for application test suites and desktop deployments that
+//! run a local server. Each connection is a pair of channels carrying one
+//! encoded frame per message, the same framing as one QUIC stream, so server
+//! timing trailers reach the client as they would over the network.
+
+use std::sync::Arc;
+
+use lockframe_proto::{Frame, FrameTiming};
+use tokio::sync::{mpsc, watch};
+
+use crate::error::ServerError;
+
+/// Frames buffered in each direction of a connection.
+const CHANNEL_FRAMES: usize = 1024;
+
+/// Connections accepted by an in-process server.
+pub struct MemoryTransport {
+    incoming: mpsc::UnboundedReceiver<MemoryConnection>,
+}
+
+/// Opens connections to an in-process server. Cheap to clone.
+#[derive(Clone)]
+pub struct MemoryConnector {
+    incoming: mpsc::UnboundedSender<MemoryConnection>,
+}
+
+/// Create a transport and the connector clients use to reach it.
+pub fn memory_transport() -> (MemoryTransport, MemoryConnector) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (MemoryTransport { incoming: rx }, MemoryConnector { incoming: tx })
+}
+
+impl MemoryTransport {
+    /// Accept the next connection.
+    pub async fn accept(&mut self) -> Result<MemoryConnection, ServerError> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| ServerError::Transport("all connectors dropped".to_string()))
+    }
+}
+
+impl MemoryConnector {
+    /// Connect a new client.
+    pub fn connect(&self) -> Result<MemoryClient, ServerError> {
+        let (to_server, inbound) = mpsc::channel(CHANNEL_FRAMES);
+        let (outbound, from_server) = mpsc::channel(CHANNEL_FRAMES);
+        let (closed, closed_rx) = watch::channel(None);
+
+        let conn = MemoryConnection {
+            link: MemoryLink { outbound, closed: Arc::new(closed) },
+            inbound,
+            closed: closed_rx,
+        };
+        self.incoming
+            .send(conn)
+            .map_err(|_| ServerError::Transport("server is not running".to_string()))?;
+
+        Ok(MemoryClient { to_server, from_server })
+    }
+}
+
+/// Server side of an in-process connection.
+pub struct MemoryConnection {
+    link: MemoryLink,
+    inbound: mpsc::Receiver<Vec<u8>>,
+    closed: watch::Receiver<Option<String>>,
+}
+
+impl MemoryConnection {
+    /// Sending half, shared by everything that writes to the client.
+    pub fn link(&self) -> MemoryLink {
+        self.link.clone()
+    }
+
+    /// Next encoded frame from the client. `None` once the client hung up or
+    /// the server closed the connection.
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        if self.closed.borrow().is_some() {
+            return None;
+        }
+        tokio::select! {
+            bytes = self.inbound.recv() => bytes,
+            _ = self.closed.changed() => None,
+        }
+    }
+}
+
+/// Sending half of an in-process connection.
+#[derive(Clone)]
+pub struct MemoryLink {
+    outbound: mpsc::Sender<Vec<u8>>,
+    closed: Arc<watch::Sender<Option<String>>>,
+}
+
+impl MemoryLink {
+    /// Deliver one encoded frame, waiting while the client's buffer is full.
+    pub async fn send(&self, buf: &[u8]) -> Result<(), String> {
+        if self.closed.borrow().is_some() {
+            return Err("connection closed".to_string());
+        }
+        self.outbound.send(buf.to_vec()).await.map_err(|_| "client disconnected".to_string())
+    }
+
+    /// Close the connection. The client sees the end of its frames once the
+    /// server lets go of the link.
+    pub fn close(&self, reason: &str) {
+        self.closed.send_if_modified(|closed| {
+            let first = closed.is_none();
+            if first {
+                *closed = Some(reason.to_string());
+            }
+            first
+        });
+    }
+}
+
+/// Client side of an in-process connection.
+///
+/// Dropping it disconnects the client.
+pub struct MemoryClient {
+    to_server: mpsc::Sender<Vec<u8>>,
+    from_server: mpsc::Receiver<Vec<u8>>,
+}
+
+impl MemoryClient {
+    /// Send a frame to the server.
+    pub async fn send(&self, frame: &Frame) -> Result<(), ServerError> {
+        let mut buf = Vec::new();
+        frame.encode(&mut buf).map_err(|e| ServerError::Protocol(e.to_string()))?;
+        self.to_server
+            .send(buf)
+            .await
+            .map_err(|_| ServerError::Transport("connection closed".to_string()))
+    }
+
+    /// Next frame from the server and its timing trailer, if any.
+    ///
+    /// `None` once the server closed the connection.
+    pub async fn recv(&mut self) -> Option<Result<(Frame, Option<FrameTiming>), ServerError>> {
+        let bytes = self.from_server.recv().await?;
+        Some(Frame::decode_with_timing(&bytes).map_err(|e| ServerError::Protocol(e.to_string())))
+    }
+}
@@ -0,0 +1,44 @@
+//! Room migration.
+//!
+//! Moving a room to another server freezes its log at a cutover index,
+//! exports the frames and MLS state up to it in the format of
+//! [`storage::dump`](crate::storage::dump), and tells every session in the
+//! room where it went with a [`RoomMoved`] frame. Frames for the room that
+//! arrive after the cutover get the same answer, so clients that missed the
+//! notice still find the new server.
+//!
+//! Carrying the backup to the target is left to whoever triggered the
+//! migration: it is loaded there with
+//! [`storage::restore`](crate::storage::restore) before clients reconnect. The
+//! old server keeps the room's stored log.
+
+use lockframe_proto::{Frame, FrameHeader, Opcode, Payload, payloads::session::RoomMoved};
+
+use crate::{room_manager::RoomMetadata, server_error::ServerError, storage::BackupSummary};
+
+/// A room moved off this server.
+#[derive(Debug, Clone)]
+pub struct RoomMigration {
+    /// Room that moved
+    pub room_id: u128,
+    /// Address of the server now hosting the room
+    pub target: String,
+    /// First log index the target sequences; the backup ends right before it
+    pub cutover_log_index: u64,
+    /// Room settings, to apply on the target
+    pub metadata: RoomMetadata,
+    /// What the backup holds
+    pub summary: BackupSummary,
+    /// The room's log and MLS state, as written by
+    /// [`storage::dump`](crate::storage::dump)
+    pub backup: Vec<u8>,
+}
+
+/// Frame telling a client that `room_id` moved.
+pub fn room_moved_frame(room_id: u128, moved: &RoomMoved) -> Result<Frame, ServerError> {
+    let mut header = FrameHeader::new(Opcode::RoomMoved);
+    header.set_room_id(room_id);
+    Payload::RoomMoved(moved.clone())
+        .into_frame(header)
+        .map_err(|e| ServerError::Protocol(format!("failed to encode RoomMoved: {e}")))
+}
@@ -0,0 +1,42 @@
+//! Push notification hooks for offline members.
+//!
+//! A member whose sessions have all closed misses the frames broadcast to
+//! its rooms. They are held in its offline queue, and the runtime also hands
+//! a [`NotificationSink`] an [`OfflineNotice`] for each, so an operator can
+//! wake the member's device through a push gateway such as APNs or FCM.
+//!
+//! Notices carry identifiers only. The frame's payload stays with the
+//! server, and is end-to-end encrypted anyway: a device woken by a push
+//! reconnects and syncs to read what arrived.
+
+/// A frame was broadcast to a room while one of its members was offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OfflineNotice {
+    /// Room the frame was broadcast to
+    pub room_id: u128,
+    /// Offline member to notify
+    pub member_id: u64,
+    /// Member that sent the frame
+    pub sender_id: u64,
+    /// Log index of the frame
+    pub log_index: u64,
+}
+
+/// Receives a notice for every frame queued for an offline member.
+///
+/// Called on the runtime's action executor, so a sink talking to a remote
+/// gateway should hand notices off to a task of its own rather than wait
+/// on the network.
+pub trait NotificationSink: Send + Sync {
+    /// `notice.member_id` missed a frame.
+    fn notify(&self, notice: &OfflineNotice);
+}
+
+impl<F> NotificationSink for F
+where
+    F: Fn(&OfflineNotice) + Send + Sync,
+{
+    fn notify(&self, notice: &OfflineNotice) {
+        self(notice);
+    }
+}
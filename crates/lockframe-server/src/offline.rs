@@ -0,0 +1,336 @@
+//! Offline delivery queues for disconnected room members.
+//!
+//! When a session closes, every room member it was speaking for is marked
+//! offline and gets a bounded queue in that room. Frames broadcast to the room
+//! while the member is away are appended to its queue, and handed back when a
+//! session next acts as that member, so a brief disconnect is repaired without
+//! a sync round.
+//!
+//! Member IDs in frame headers are the client's claim, so a queue belongs to
+//! the principal the member's session authenticated as, and only a session
+//! authenticated as the same principal drains it. The frames are stored exactly
+//! as broadcast: application payloads stay end-to-end encrypted and the server
+//! never sees plaintext.
+//!
+//! Queues are lossy by design. Frames older than the TTL are dropped and a
+//! full queue drops its oldest frame; the client notices the gap in log
+//! indices and falls back to a regular sync.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use lockframe_proto::Frame;
+
+/// Default maximum frames queued per offline member.
+pub const DEFAULT_OFFLINE_QUEUE_FRAMES: usize = 256;
+
+/// Default time a queued frame is retained.
+pub const DEFAULT_OFFLINE_QUEUE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Offline queue limits, set server-wide and optionally per room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OfflineQueueConfig {
+    /// Maximum frames queued per offline member (0 disables queueing)
+    pub max_frames: usize,
+    /// How long a queued frame is retained
+    pub ttl: Duration,
+}
+
+impl Default for OfflineQueueConfig {
+    fn default() -> Self {
+        Self { max_frames: DEFAULT_OFFLINE_QUEUE_FRAMES, ttl: DEFAULT_OFFLINE_QUEUE_TTL }
+    }
+}
+
+impl OfflineQueueConfig {
+    /// Configuration that never queues.
+    pub fn disabled() -> Self {
+        Self { max_frames: 0, ..Self::default() }
+    }
+}
+
+/// Frames waiting for one offline member.
+#[derive(Debug)]
+struct MemberQueue {
+    /// Principal the member's session authenticated as
+    owner: String,
+    /// When the member went offline
+    since: Instant,
+    /// Queued frames with the time they were queued, oldest first
+    frames: VecDeque<(Instant, Frame)>,
+}
+
+impl MemberQueue {
+    fn expire(&mut self, now: Instant, ttl: Duration) {
+        while self
+            .frames
+            .front()
+            .is_some_and(|(queued_at, _)| now.saturating_duration_since(*queued_at) > ttl)
+        {
+            self.frames.pop_front();
+        }
+    }
+}
+
+/// Offline queues for every room.
+#[derive(Debug, Default)]
+pub struct OfflineQueues {
+    /// Limits for rooms without an override
+    default_config: OfflineQueueConfig,
+    /// Per-room overrides
+    room_configs: HashMap<u128, OfflineQueueConfig>,
+    /// Session ID → principal of the session and (room, member) pairs it
+    /// has acted as
+    online: HashMap<u64, (String, HashSet<(u128, u64)>)>,
+    /// Room ID → offline member ID → queue
+    queues: HashMap<u128, HashMap<u64, MemberQueue>>,
+}
+
+impl OfflineQueues {
+    /// Create queues using `config` for every room.
+    pub fn new(config: OfflineQueueConfig) -> Self {
+        Self { default_config: config, ..Self::default() }
+    }
+
+    /// Override the limits for one room.
+    ///
+    /// Disabling a room drops the frames already queued in it.
+    pub fn set_room_config(&mut self, room_id: u128, config: OfflineQueueConfig) {
+        self.room_configs.insert(room_id, config);
+        if config.max_frames == 0 {
+            self.queues.remove(&room_id);
+        }
+    }
+
+    /// Limits in effect for a room.
+    pub fn room_config(&self, room_id: u128) -> OfflineQueueConfig {
+        self.room_configs.get(&room_id).copied().unwrap_or(self.default_config)
+    }
+
+    /// Record that `session_id`, authenticated as `principal`, acts as
+    /// `member_id` in `room_id`.
+    ///
+    /// Returns the frames queued while the member was offline, oldest first,
+    /// or `None` if the member was not offline in the room or its queue
+    /// belongs to another principal.
+    pub fn attach(
+        &mut self,
+        session_id: u64,
+        principal: &str,
+        room_id: u128,
+        member_id: u64,
+        now: Instant,
+    ) -> Option<Vec<Frame>> {
+        let (_, pairs) = self
+            .online
+            .entry(session_id)
+            .or_insert_with(|| (principal.to_string(), HashSet::new()));
+        pairs.insert((room_id, member_id));
+
+        let ttl = self.room_config(room_id).ttl;
+        let room = self.queues.get_mut(&room_id)?;
+        if room.get(&member_id)?.owner != principal {
+            return None;
+        }
+        let mut queue = room.remove(&member_id)?;
+        if room.is_empty() {
+            self.queues.remove(&room_id);
+        }
+
+        queue.expire(now, ttl);
+        Some(queue.frames.into_iter().map(|(_, frame)| frame).collect())
+    }
+
+    /// Mark every member `session_id` acted as offline, unless another
+    /// session still acts as it.
+    pub fn detach(&mut self, session_id: u64, now: Instant) {
+        let Some((principal, pairs)) = self.online.remove(&session_id) else {
+            return;
+        };
+
+        for (room_id, member_id) in pairs {
+            let still_online =
+                self.online.values().any(|(_, other)| other.contains(&(room_id, member_id)));
+            if still_online || self.room_config(room_id).max_frames == 0 {
+                continue;
+            }
+
+            self.queues.entry(room_id).or_default().entry(member_id).or_insert_with(|| {
+                MemberQueue { owner: principal.clone(), since: now, frames: VecDeque::new() }
+            });
+        }
+    }
+
+    /// Queue a broadcast frame for every offline member of its room other
+    /// than its sender.
+    ///
+    /// Returns the members it was queued for, ascending.
+    pub fn enqueue(&mut self, room_id: u128, frame: &Frame, now: Instant) -> Vec<u64> {
+        let config = self.room_config(room_id);
+        let Some(room) = self.queues.get_mut(&room_id) else {
+            return Vec::new();
+        };
+
+        let sender_id = frame.header.sender_id();
+        let mut queued = Vec::new();
+        for (&member_id, queue) in room.iter_mut() {
+            if member_id == sender_id {
+                continue;
+            }
+
+            queue.expire(now, config.ttl);
+            queue.frames.push_back((now, frame.clone()));
+            while queue.frames.len() > config.max_frames {
+                queue.frames.pop_front();
+            }
+            queued.push(member_id);
+        }
+
+        queued.sort_unstable();
+        queued
+    }
+
+    /// Drop everything queued for `member_id` in every room and stop treating
+    /// it as online.
+    pub fn forget_member(&mut self, member_id: u64) {
+        for (_, pairs) in self.online.values_mut() {
+            pairs.retain(|&(_, member)| member != member_id);
+        }
+        self.queues.retain(|_, room| {
+            room.remove(&member_id);
+            !room.is_empty()
+        });
+    }
+
+    /// Drop expired frames, and the queues of members offline longer than
+    /// the TTL.
+    pub fn expire(&mut self, now: Instant) {
+        let default_config = self.default_config;
+        let room_configs = &self.room_configs;
+
+        self.queues.retain(|room_id, room| {
+            let ttl = room_configs.get(room_id).copied().unwrap_or(default_config).ttl;
+            room.retain(|_, queue| {
+                queue.expire(now, ttl);
+                now.saturating_duration_since(queue.since) <= ttl
+            });
+            !room.is_empty()
+        });
+    }
+
+    /// Frames queued for a member. `None` if the member is not offline in
+    /// the room.
+    pub fn queued(&self, room_id: u128, member_id: u64) -> Option<usize> {
+        self.queues.get(&room_id)?.get(&member_id).map(|queue| queue.frames.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+
+    const ROOM: u128 = 0x1234;
+    const ALICE: &str = "sha256:alice";
+
+    fn frame(sender_id: u64, log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(ROOM);
+        header.set_sender_id(sender_id);
+        header.set_log_index(log_index);
+        Frame::new(header, Bytes::new())
+    }
+
+    fn indices(frames: &[Frame]) -> Vec<u64> {
+        frames.iter().map(|f| f.header.log_index()).collect()
+    }
+
+    #[test]
+    fn offline_member_receives_queued_frames_on_reattach() {
+        let now = Instant::now();
+        let mut queues = OfflineQueues::new(OfflineQueueConfig::default());
+
+        assert!(queues.attach(10, ALICE, ROOM, 2, now).is_none());
+        queues.detach(10, now);
+        assert_eq!(queues.queued(ROOM, 2), Some(0));
+
+        assert_eq!(queues.enqueue(ROOM, &frame(1, 0), now), vec![2]);
+        assert!(queues.enqueue(ROOM, &frame(2, 1), now).is_empty()); // own frame is not queued
+        queues.enqueue(ROOM, &frame(1, 2), now);
+
+        let delivered = queues.attach(11, ALICE, ROOM, 2, now).expect("member was offline");
+        assert_eq!(indices(&delivered), vec![0, 2]);
+        assert_eq!(queues.queued(ROOM, 2), None);
+    }
+
+    #[test]
+    fn queue_is_only_drained_by_its_principal() {
+        let now = Instant::now();
+        let mut queues = OfflineQueues::new(OfflineQueueConfig::default());
+
+        queues.attach(10, ALICE, ROOM, 2, now);
+        queues.detach(10, now);
+        queues.enqueue(ROOM, &frame(1, 0), now);
+
+        // Claiming the member from another principal leaves the queue alone
+        assert!(queues.attach(11, "sha256:mallory", ROOM, 2, now).is_none());
+        assert_eq!(queues.queued(ROOM, 2), Some(1));
+
+        let delivered = queues.attach(12, ALICE, ROOM, 2, now).expect("member was offline");
+        assert_eq!(indices(&delivered), vec![0]);
+    }
+
+    #[test]
+    fn member_online_elsewhere_is_not_queued() {
+        let now = Instant::now();
+        let mut queues = OfflineQueues::new(OfflineQueueConfig::default());
+
+        queues.attach(10, ALICE, ROOM, 2, now);
+        queues.attach(11, ALICE, ROOM, 2, now);
+        queues.detach(10, now);
+
+        assert_eq!(queues.queued(ROOM, 2), None);
+    }
+
+    #[test]
+    fn queue_is_bounded_and_expires() {
+        let now = Instant::now();
+        let config = OfflineQueueConfig { max_frames: 2, ttl: Duration::from_secs(10) };
+        let mut queues = OfflineQueues::new(config);
+
+        queues.attach(10, ALICE, ROOM, 2, now);
+        queues.detach(10, now);
+        for i in 0..3 {
+            queues.enqueue(ROOM, &frame(1, i), now);
+        }
+        assert_eq!(queues.queued(ROOM, 2), Some(2));
+
+        let later = now + Duration::from_secs(11);
+        queues.enqueue(ROOM, &frame(1, 3), later);
+        let delivered = queues.attach(11, ALICE, ROOM, 2, later).expect("member was offline");
+        assert_eq!(indices(&delivered), vec![3]);
+
+        // Members offline longer than the TTL are forgotten
+        queues.detach(11, later);
+        queues.expire(later + Duration::from_secs(11));
+        assert_eq!(queues.queued(ROOM, 2), None);
+    }
+
+    #[test]
+    fn room_override_disables_queueing() {
+        let now = Instant::now();
+        let mut queues = OfflineQueues::new(OfflineQueueConfig::default());
+        queues.set_room_config(ROOM, OfflineQueueConfig::disabled());
+
+        queues.attach(10, ALICE, ROOM, 2, now);
+        queues.detach(10, now);
+        queues.enqueue(ROOM, &frame(1, 0), now);
+
+        assert_eq!(queues.queued(ROOM, 2), None);
+        assert_eq!(queues.room_config(0x5678), OfflineQueueConfig::default());
+    }
+}
@@ -0,0 +1,278 @@
+//! Load shedding when the server falls behind.
+//!
+//! The runtime reports how far behind it is, as the number of received
+//! frames waiting to be handled and the time storage took to persist frames.
+//! When a report crosses a configured threshold the server sheds work in
+//! steps, least valuable first:
+//!
+//! 1. [`OverloadLevel::RefusingConnections`]: new connections are refused, so
+//!    sessions already served are not slowed down further.
+//! 2. [`OverloadLevel::ThrottlingMessages`]: application content (messages,
+//!    receipts, reactions, typing and presence) is refused too.
+//!
+//! Commits, proposals, Welcomes and sync requests are always admitted:
+//! refusing them would leave members behind or stuck on an old epoch, which
+//! costs more to recover from than the load they add. Refused clients get an
+//! `OVERLOADED` error frame saying when to retry.
+
+use std::time::Duration;
+
+use lockframe_proto::Opcode;
+
+/// Default time a refused client is told to wait before retrying.
+pub const DEFAULT_OVERLOAD_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Default interval between load reports from the runtime.
+pub const DEFAULT_LOAD_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Load at which each step of shedding starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverloadThresholds<T> {
+    /// Load at which new connections are refused
+    pub refuse_connections: T,
+    /// Load at which application messages are refused as well
+    pub throttle_messages: T,
+}
+
+impl<T: PartialOrd> OverloadThresholds<T> {
+    fn level(&self, load: &T) -> OverloadLevel {
+        if *load >= self.throttle_messages {
+            OverloadLevel::ThrottlingMessages
+        } else if *load >= self.refuse_connections {
+            OverloadLevel::RefusingConnections
+        } else {
+            OverloadLevel::Normal
+        }
+    }
+}
+
+/// When the server sheds load. Shedding is off unless a threshold is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverloadConfig {
+    /// Received frames waiting to be handled (`None` to ignore)
+    pub queue_depth: Option<OverloadThresholds<u64>>,
+    /// Slowest frame persist since the previous report (`None` to ignore)
+    pub storage_latency: Option<OverloadThresholds<Duration>>,
+    /// How long refused clients are told to wait before retrying
+    pub retry_after: Duration,
+    /// How often the runtime reports load
+    pub report_interval: Duration,
+}
+
+impl Default for OverloadConfig {
+    fn default() -> Self {
+        Self {
+            queue_depth: None,
+            storage_latency: None,
+            retry_after: DEFAULT_OVERLOAD_RETRY_AFTER,
+            report_interval: DEFAULT_LOAD_REPORT_INTERVAL,
+        }
+    }
+}
+
+impl OverloadConfig {
+    /// Whether any threshold is set.
+    pub const fn is_enabled(&self) -> bool {
+        self.queue_depth.is_some() || self.storage_latency.is_some()
+    }
+}
+
+/// How much load is being shed, in increasing order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OverloadLevel {
+    /// Everything is admitted
+    #[default]
+    Normal,
+    /// New connections are refused
+    RefusingConnections,
+    /// New connections and application messages are refused
+    ThrottlingMessages,
+}
+
+impl OverloadLevel {
+    /// Name used in logs.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::RefusingConnections => "refusing_connections",
+            Self::ThrottlingMessages => "throttling_messages",
+        }
+    }
+}
+
+/// Load measured by the runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    /// Received frames waiting to be handled
+    pub queue_depth: u64,
+    /// Slowest frame persist since the previous report
+    pub storage_latency: Duration,
+}
+
+/// Overload state and shedding counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OverloadMetrics {
+    /// Current level
+    pub level: OverloadLevel,
+    /// Most recent load report
+    pub load: LoadReport,
+    /// Times the level went up from [`OverloadLevel::Normal`]
+    pub episodes: u64,
+    /// Connections refused
+    pub connections_refused: u64,
+    /// Frames refused
+    pub frames_refused: u64,
+}
+
+/// Decides what to admit from the latest load report.
+#[derive(Debug, Default)]
+pub struct Overload {
+    config: OverloadConfig,
+    metrics: OverloadMetrics,
+}
+
+impl Overload {
+    /// Create overload state for `config`, starting at normal load.
+    pub fn new(config: OverloadConfig) -> Self {
+        Self { config, metrics: OverloadMetrics::default() }
+    }
+
+    /// Shedding thresholds and timing.
+    pub const fn config(&self) -> &OverloadConfig {
+        &self.config
+    }
+
+    /// Current level.
+    pub const fn level(&self) -> OverloadLevel {
+        self.metrics.level
+    }
+
+    /// Current state and counters.
+    pub const fn metrics(&self) -> OverloadMetrics {
+        self.metrics
+    }
+
+    /// Take a load report. Returns the previous level if it changed.
+    ///
+    /// The level is the highest any watched signal reaches, so the server
+    /// recovers as soon as every signal is back under its thresholds.
+    pub fn report(&mut self, load: LoadReport) -> Option<OverloadLevel> {
+        let by_queue = self.config.queue_depth.map(|t| t.level(&load.queue_depth));
+        let by_storage = self.config.storage_latency.map(|t| t.level(&load.storage_latency));
+        let level = by_queue.max(by_storage).unwrap_or_default();
+
+        let previous = self.metrics.level;
+        self.metrics.load = load;
+        self.metrics.level = level;
+        if previous == level {
+            return None;
+        }
+        if previous == OverloadLevel::Normal {
+            self.metrics.episodes = self.metrics.episodes.saturating_add(1);
+        }
+        Some(previous)
+    }
+
+    /// Whether to accept a new connection, counting it if refused.
+    pub fn admit_connection(&mut self) -> bool {
+        let admitted = self.metrics.level < OverloadLevel::RefusingConnections;
+        if !admitted {
+            self.metrics.connections_refused = self.metrics.connections_refused.saturating_add(1);
+        }
+        admitted
+    }
+
+    /// Whether to admit a frame with `opcode`, counting it if refused.
+    pub fn admit_frame(&mut self, opcode: Option<Opcode>) -> bool {
+        let admitted = self.metrics.level < OverloadLevel::ThrottlingMessages || !sheddable(opcode);
+        if !admitted {
+            self.metrics.frames_refused = self.metrics.frames_refused.saturating_add(1);
+        }
+        admitted
+    }
+}
+
+/// Application content, which can be sent again later without harm.
+const fn sheddable(opcode: Option<Opcode>) -> bool {
+    matches!(
+        opcode,
+        Some(
+            Opcode::AppMessage
+                | Opcode::AppReceipt
+                | Opcode::ReadReceipt
+                | Opcode::AppReaction
+                | Opcode::AppEdit
+                | Opcode::AppDelete
+                | Opcode::Typing
+                | Opcode::Presence
+                | Opcode::AttachmentInit
+                | Opcode::AttachmentChunk
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> OverloadConfig {
+        OverloadConfig {
+            queue_depth: Some(OverloadThresholds {
+                refuse_connections: 100,
+                throttle_messages: 500,
+            }),
+            storage_latency: Some(OverloadThresholds {
+                refuse_connections: Duration::from_millis(50),
+                throttle_messages: Duration::from_millis(200),
+            }),
+            ..OverloadConfig::default()
+        }
+    }
+
+    fn load(queue_depth: u64, storage_millis: u64) -> LoadReport {
+        LoadReport { queue_depth, storage_latency: Duration::from_millis(storage_millis) }
+    }
+
+    #[test]
+    fn level_follows_the_worst_signal() {
+        let mut overload = Overload::new(config());
+
+        assert_eq!(overload.report(load(10, 1)), None);
+        assert_eq!(overload.report(load(150, 1)), Some(OverloadLevel::Normal));
+        assert_eq!(overload.level(), OverloadLevel::RefusingConnections);
+
+        overload.report(load(150, 300));
+        assert_eq!(overload.level(), OverloadLevel::ThrottlingMessages);
+
+        overload.report(load(0, 0));
+        assert_eq!(overload.level(), OverloadLevel::Normal);
+        assert_eq!(overload.metrics().episodes, 1);
+    }
+
+    #[test]
+    fn shedding_refuses_connections_before_messages() {
+        let mut overload = Overload::new(config());
+
+        overload.report(load(100, 0));
+        assert!(!overload.admit_connection());
+        assert!(overload.admit_frame(Some(Opcode::AppMessage)));
+
+        overload.report(load(500, 0));
+        assert!(!overload.admit_frame(Some(Opcode::AppMessage)));
+        assert!(overload.admit_frame(Some(Opcode::Commit)));
+        assert!(overload.admit_frame(Some(Opcode::SyncRequest)));
+
+        let metrics = overload.metrics();
+        assert_eq!((metrics.connections_refused, metrics.frames_refused), (1, 1));
+    }
+
+    #[test]
+    fn nothing_is_shed_without_thresholds() {
+        let mut overload = Overload::new(OverloadConfig::default());
+        assert!(!overload.config().is_enabled());
+
+        overload.report(load(u64::MAX, u64::MAX));
+        assert_eq!(overload.level(), OverloadLevel::Normal);
+        assert!(overload.admit_connection());
+    }
+}
@@ -0,0 +1,189 @@
+//! Per-session frame rate limits.
+//!
+//! Every frame a client sends costs the driver a decode and usually a trip
+//! through the room manager, so one session must not be able to flood it.
+//! Each session gets a token bucket measured in frames that refills at a fixed
+//! rate up to a burst size. A frame arriving to an empty bucket is refused,
+//! and a session that keeps sending into an empty bucket is disconnected.
+//! Violations are forgiven once the bucket has refilled completely, i.e.
+//! after the client stayed quiet for a full burst's worth of time.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Default frames per second refilled into each session's bucket.
+pub const DEFAULT_FRAMES_PER_SEC: u64 = 500;
+
+/// Default maximum frames a session can burst.
+pub const DEFAULT_BURST_FRAMES: u64 = 2_000;
+
+/// Default refused frames after which a session is disconnected.
+pub const DEFAULT_MAX_RATE_VIOLATIONS: u32 = 100;
+
+/// Token precision: one frame is this many tokens.
+const TOKENS_PER_FRAME: u64 = 1_000;
+
+/// Frame rate limits applied to every session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Frames per second refilled into each session's bucket
+    pub frames_per_sec: u64,
+    /// Maximum frames a session can accumulate and spend at once
+    pub burst_frames: u64,
+    /// Refused frames, without the bucket refilling in between, after which
+    /// the session is disconnected
+    pub max_violations: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            frames_per_sec: DEFAULT_FRAMES_PER_SEC,
+            burst_frames: DEFAULT_BURST_FRAMES,
+            max_violations: DEFAULT_MAX_RATE_VIOLATIONS,
+        }
+    }
+}
+
+/// Verdict on one received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateDecision {
+    /// Within the limit
+    Allowed,
+    /// Over the limit; refuse the frame
+    Limited {
+        /// Time until the next frame is allowed
+        retry_after: Duration,
+    },
+    /// Over the limit too many times; disconnect the session
+    Exceeded,
+}
+
+#[derive(Debug)]
+struct SessionBucket {
+    /// Available tokens (`TOKENS_PER_FRAME` per frame)
+    tokens: u64,
+    /// When tokens were last refilled
+    refilled_at: Instant,
+    /// Frames refused since the bucket was last full
+    violations: u32,
+}
+
+/// Frame rate limits for every session.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    sessions: HashMap<u64, SessionBucket>,
+}
+
+impl RateLimiter {
+    /// Create limits using `config` for every session.
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self { config, sessions: HashMap::new() }
+    }
+
+    /// Spend one frame from the session's bucket.
+    pub fn check(&mut self, session_id: u64, now: Instant) -> RateDecision {
+        let config = self.config;
+        let cap = config.burst_frames.saturating_mul(TOKENS_PER_FRAME);
+        let bucket = self.sessions.entry(session_id).or_insert_with(|| SessionBucket {
+            tokens: cap,
+            refilled_at: now,
+            violations: 0,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        let elapsed_millis = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+        // frames/sec * ms = frames * 1000, one token per millisecond-frame
+        let earned = elapsed_millis.saturating_mul(config.frames_per_sec);
+        if earned > 0 {
+            bucket.tokens = bucket.tokens.saturating_add(earned).min(cap);
+            bucket.refilled_at = now;
+        }
+        if bucket.tokens == cap {
+            bucket.violations = 0;
+        }
+
+        if bucket.tokens >= TOKENS_PER_FRAME {
+            bucket.tokens = bucket.tokens.saturating_sub(TOKENS_PER_FRAME);
+            return RateDecision::Allowed;
+        }
+
+        bucket.violations = bucket.violations.saturating_add(1);
+        if bucket.violations > config.max_violations {
+            return RateDecision::Exceeded;
+        }
+
+        let missing = TOKENS_PER_FRAME.saturating_sub(bucket.tokens);
+        let retry_after = if config.frames_per_sec == 0 {
+            Duration::MAX
+        } else {
+            Duration::from_millis(missing.div_ceil(config.frames_per_sec))
+        };
+        RateDecision::Limited { retry_after }
+    }
+
+    /// Forget a closed session.
+    pub fn remove_session(&mut self, session_id: u64) {
+        self.sessions.remove(&session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig { frames_per_sec: 10, burst_frames: 3, max_violations: 2 })
+    }
+
+    #[test]
+    fn burst_then_refused_until_refilled() {
+        let mut limits = limiter();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limits.check(1, now), RateDecision::Allowed);
+        }
+        assert_eq!(limits.check(1, now), RateDecision::Limited {
+            retry_after: Duration::from_millis(100)
+        });
+        // Other sessions have their own bucket
+        assert_eq!(limits.check(2, now), RateDecision::Allowed);
+
+        assert_eq!(limits.check(1, now + Duration::from_millis(100)), RateDecision::Allowed);
+    }
+
+    #[test]
+    fn repeated_violations_disconnect() {
+        let mut limits = limiter();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            limits.check(1, now);
+        }
+        assert!(matches!(limits.check(1, now), RateDecision::Limited { .. }));
+        assert!(matches!(limits.check(1, now), RateDecision::Limited { .. }));
+        assert_eq!(limits.check(1, now), RateDecision::Exceeded);
+    }
+
+    #[test]
+    fn violations_forgiven_once_bucket_refills() {
+        let mut limits = limiter();
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            limits.check(1, now);
+        }
+
+        // Quiet long enough to refill the whole burst
+        let later = now + Duration::from_millis(300);
+        for _ in 0..3 {
+            assert_eq!(limits.check(1, later), RateDecision::Allowed);
+        }
+        assert!(matches!(limits.check(1, later), RateDecision::Limited { .. }));
+        assert!(matches!(limits.check(1, later), RateDecision::Limited { .. }));
+    }
+}
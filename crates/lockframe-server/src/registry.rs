@@ -9,13 +9,20 @@
 
 use std::collections::{HashMap, HashSet};
 
+use lockframe_proto::Capabilities;
+
 /// Information about a registered session.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SessionInfo {
     /// User ID associated with this session (after authentication)
     pub user_id: Option<u64>,
     /// Whether the session has completed handshake
     pub authenticated: bool,
+    /// Capabilities negotiated during the handshake
+    pub capabilities: Capabilities,
+    /// Identity the session authenticated as: the principal of its client
+    /// certificate, or `ed25519:<hex>` for an identity key it proved
+    pub principal: Option<String>,
 }
 
 impl Default for SessionInfo {
@@ -27,12 +34,22 @@ impl Default for SessionInfo {
 impl SessionInfo {
     /// Create a new unauthenticated session info.
     pub fn new() -> Self {
-        Self { user_id: None, authenticated: false }
+        Self {
+            user_id: None,
+            authenticated: false,
+            capabilities: Capabilities::empty(),
+            principal: None,
+        }
     }
 
     /// Create an authenticated session info with user ID.
     pub fn authenticated(user_id: u64) -> Self {
-        Self { user_id: Some(user_id), authenticated: true }
+        Self {
+            user_id: Some(user_id),
+            authenticated: true,
+            capabilities: Capabilities::empty(),
+            principal: None,
+        }
     }
 }
 
@@ -0,0 +1,242 @@
+//! Sampled logging of rejected frames.
+//!
+//! A flood of bad frames must not turn into a flood of log lines: formatting
+//! and writing one line per reject is work an attacker gets for free. Every
+//! reject is counted, but only a sample is logged in full, and at most
+//! [`RejectLogConfig::max_per_second`] of those. Each logged record carries
+//! the number of rejects left out since the previous one, so the log still
+//! shows the volume. The runtime writes records to [`REJECT_LOG_TARGET`] as
+//! structured fields.
+
+use std::time::{Duration, Instant};
+
+/// Tracing target rejects are logged under.
+pub const REJECT_LOG_TARGET: &str = "lockframe_server::rejects";
+
+/// Default: log every reject, subject to the rate limit.
+pub const DEFAULT_REJECT_LOG_SAMPLE: u64 = 1;
+
+/// Default most rejects logged per second.
+pub const DEFAULT_REJECT_LOG_PER_SEC: u32 = 20;
+
+/// Why a frame was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectKind {
+    /// The frame or its payload could not be decoded, or was not what the
+    /// opcode requires
+    Malformed,
+    /// The opcode needs a capability the session did not negotiate
+    Capability,
+    /// MLS validation or sequencing refused the frame
+    Invalid,
+    /// A well-formed request could not be served
+    Failed,
+    /// The session sent frames faster than its rate limit
+    RateLimited,
+}
+
+impl RejectKind {
+    /// Name used in log records.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed",
+            Self::Capability => "capability",
+            Self::Invalid => "invalid",
+            Self::Failed => "failed",
+            Self::RateLimited => "rate_limited",
+        }
+    }
+}
+
+/// How many rejects are logged in full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RejectLogConfig {
+    /// Log one in this many rejects (0 logs none)
+    pub sample_one_in: u64,
+    /// Most rejects logged in any one second
+    pub max_per_second: u32,
+}
+
+impl Default for RejectLogConfig {
+    fn default() -> Self {
+        Self {
+            sample_one_in: DEFAULT_REJECT_LOG_SAMPLE,
+            max_per_second: DEFAULT_REJECT_LOG_PER_SEC,
+        }
+    }
+}
+
+/// Reject counters, incremented whether or not the reject was logged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RejectMetrics {
+    /// Frames that could not be decoded
+    pub malformed: u64,
+    /// Frames needing an unnegotiated capability
+    pub capability: u64,
+    /// Frames refused by validation or sequencing
+    pub invalid: u64,
+    /// Requests that could not be served
+    pub failed: u64,
+    /// Frames over the session's rate limit
+    pub rate_limited: u64,
+    /// Rejects logged in full
+    pub logged: u64,
+    /// Rejects left out by sampling or the rate limit
+    pub suppressed: u64,
+}
+
+impl RejectMetrics {
+    /// Add the counters of `other`, e.g. another shard's, to these.
+    pub fn merge(&mut self, other: &Self) {
+        self.malformed = self.malformed.saturating_add(other.malformed);
+        self.capability = self.capability.saturating_add(other.capability);
+        self.invalid = self.invalid.saturating_add(other.invalid);
+        self.failed = self.failed.saturating_add(other.failed);
+        self.rate_limited = self.rate_limited.saturating_add(other.rate_limited);
+        self.logged = self.logged.saturating_add(other.logged);
+        self.suppressed = self.suppressed.saturating_add(other.suppressed);
+    }
+}
+
+/// A reject selected for logging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectRecord {
+    /// Why the frame was refused
+    pub kind: RejectKind,
+    /// Session that sent the frame
+    pub session_id: u64,
+    /// Room the frame was for (0 if none)
+    pub room_id: u128,
+    /// Details of the reject
+    pub reason: String,
+    /// Rejects left out since the previous logged one
+    pub suppressed: u64,
+}
+
+/// Counts rejects and decides which are logged.
+#[derive(Debug)]
+pub struct RejectLog {
+    config: RejectLogConfig,
+    metrics: RejectMetrics,
+    /// Rejects seen, for sampling
+    seen: u64,
+    /// Start of the current rate limit window and rejects logged in it
+    window: Option<(Instant, u32)>,
+    /// Rejects left out since the last logged one
+    suppressed: u64,
+}
+
+impl RejectLog {
+    /// Create a reject log with the given sampling.
+    pub fn new(config: RejectLogConfig) -> Self {
+        Self { config, metrics: RejectMetrics::default(), seen: 0, window: None, suppressed: 0 }
+    }
+
+    /// Count a reject, returning a record if it should be logged.
+    ///
+    /// `reason` is only formatted for rejects that are logged.
+    pub fn record(
+        &mut self,
+        now: Instant,
+        kind: RejectKind,
+        session_id: u64,
+        room_id: u128,
+        reason: impl FnOnce() -> String,
+    ) -> Option<RejectRecord> {
+        let counter = match kind {
+            RejectKind::Malformed => &mut self.metrics.malformed,
+            RejectKind::Capability => &mut self.metrics.capability,
+            RejectKind::Invalid => &mut self.metrics.invalid,
+            RejectKind::Failed => &mut self.metrics.failed,
+            RejectKind::RateLimited => &mut self.metrics.rate_limited,
+        };
+        *counter = counter.saturating_add(1);
+
+        let sampled = self.seen.checked_rem(self.config.sample_one_in) == Some(0);
+        self.seen = self.seen.wrapping_add(1);
+        if !sampled || !self.take_slot(now) {
+            self.suppressed = self.suppressed.saturating_add(1);
+            self.metrics.suppressed = self.metrics.suppressed.saturating_add(1);
+            return None;
+        }
+
+        self.metrics.logged = self.metrics.logged.saturating_add(1);
+        Some(RejectRecord {
+            kind,
+            session_id,
+            room_id,
+            reason: reason(),
+            suppressed: std::mem::take(&mut self.suppressed),
+        })
+    }
+
+    /// Reject counters.
+    pub fn metrics(&self) -> RejectMetrics {
+        self.metrics
+    }
+
+    /// Use up one of the current second's log slots, if any are left.
+    fn take_slot(&mut self, now: Instant) -> bool {
+        let (start, logged) = match self.window {
+            Some((start, logged)) if now.duration_since(start) < Duration::from_secs(1) => {
+                (start, logged)
+            },
+            _ => (now, 0),
+        };
+        if logged >= self.config.max_per_second {
+            self.window = Some((start, logged));
+            return false;
+        }
+
+        self.window = Some((start, logged.saturating_add(1)));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(log: &mut RejectLog, now: Instant) -> Option<RejectRecord> {
+        log.record(now, RejectKind::Malformed, 1, 0, || "bad magic".to_string())
+    }
+
+    #[test]
+    fn every_reject_counted_but_a_sample_logged() {
+        let config = RejectLogConfig { sample_one_in: 3, max_per_second: 100 };
+        let mut log = RejectLog::new(config);
+        let now = Instant::now();
+
+        let logged: Vec<u64> =
+            (0..7).filter_map(|_| record(&mut log, now)).map(|r| r.suppressed).collect();
+        assert_eq!(logged, vec![0, 2, 2]);
+        assert_eq!(log.metrics(), RejectMetrics {
+            malformed: 7,
+            logged: 3,
+            suppressed: 4,
+            ..RejectMetrics::default()
+        });
+    }
+
+    #[test]
+    fn logging_is_rate_limited_per_second() {
+        let config = RejectLogConfig { sample_one_in: 1, max_per_second: 2 };
+        let mut log = RejectLog::new(config);
+        let start = Instant::now();
+
+        let logged = (0..10).filter(|_| record(&mut log, start).is_some()).count();
+        assert_eq!(logged, 2);
+
+        let next = record(&mut log, start + Duration::from_secs(1)).unwrap();
+        assert_eq!(next.suppressed, 8);
+        assert_eq!(log.metrics().malformed, 11);
+    }
+
+    #[test]
+    fn zero_sample_logs_nothing() {
+        let config = RejectLogConfig { sample_one_in: 0, ..RejectLogConfig::default() };
+        let mut log = RejectLog::new(config);
+        assert!(record(&mut log, Instant::now()).is_none());
+        assert_eq!(log.metrics().suppressed, 1);
+    }
+}
@@ -0,0 +1,111 @@
+//! Duplicate suppression for resent application messages.
+//!
+//! A client whose connection drops before its message's sequenced copy comes
+//! back resends the message unchanged after reconnecting. If the server did
+//! sequence the original, the resend must not be sequenced again. A sender
+//! key message is identified by its sender, epoch, sender index and ratchet
+//! generation, so the room manager remembers where each room's recent
+//! messages were sequenced and answers a resend with the sequenced copy. Only
+//! a byte-identical copy counts as a resend; anything else under the same
+//! identity is sequenced as usual.
+//!
+//! The window is bounded per room and kept in memory only. A resend older
+//! than the window, or arriving after a restart, is sequenced again and left
+//! to the receivers' own duplicate suppression.
+
+use std::collections::{HashMap, VecDeque};
+
+use lockframe_proto::payloads::app::EncryptedMessage;
+
+/// Most message identities remembered per room.
+pub const RESEND_WINDOW: usize = 4096;
+
+/// Identity of one application message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SentMessage {
+    /// Member who sent it.
+    pub sender_id: u64,
+    /// Epoch it was encrypted in.
+    pub epoch: u64,
+    /// Sender's leaf index.
+    pub sender_index: u32,
+    /// Sender ratchet generation.
+    pub generation: u32,
+}
+
+impl SentMessage {
+    /// Identity of `message`, sent by `sender_id`.
+    pub fn new(sender_id: u64, message: &EncryptedMessage) -> Self {
+        Self {
+            sender_id,
+            epoch: message.epoch,
+            sender_index: message.sender_index,
+            generation: message.generation,
+        }
+    }
+}
+
+/// Where one room's recent messages were sequenced.
+#[derive(Debug, Default)]
+struct RoomWindow {
+    log_indices: HashMap<SentMessage, u64>,
+    order: VecDeque<SentMessage>,
+}
+
+/// Recently sequenced application messages of every room.
+#[derive(Debug, Default)]
+pub struct ResendDedup {
+    rooms: HashMap<u128, RoomWindow>,
+}
+
+impl ResendDedup {
+    /// Log index `message` was sequenced at, if it is recent.
+    pub fn sequenced_at(&self, room_id: u128, message: &SentMessage) -> Option<u64> {
+        self.rooms.get(&room_id)?.log_indices.get(message).copied()
+    }
+
+    /// Remember that `message` was sequenced at `log_index`.
+    pub fn record(&mut self, room_id: u128, message: SentMessage, log_index: u64) {
+        let window = self.rooms.entry(room_id).or_default();
+        if window.log_indices.insert(message, log_index).is_none() {
+            window.order.push_back(message);
+        }
+        while window.order.len() > RESEND_WINDOW {
+            if let Some(oldest) = window.order.pop_front() {
+                window.log_indices.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forget a room's messages.
+    pub fn remove_room(&mut self, room_id: u128) {
+        self.rooms.remove(&room_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(generation: u32) -> SentMessage {
+        SentMessage { sender_id: 7, epoch: 1, sender_index: 0, generation }
+    }
+
+    #[test]
+    fn remembers_a_bounded_window_per_room() {
+        let mut dedup = ResendDedup::default();
+        for generation in 0..=u32::try_from(RESEND_WINDOW).unwrap_or(u32::MAX) {
+            dedup.record(1, sent(generation), u64::from(generation));
+        }
+
+        // The oldest fell out of the window
+        assert_eq!(dedup.sequenced_at(1, &sent(0)), None);
+        assert_eq!(dedup.sequenced_at(1, &sent(1)), Some(1));
+        assert_eq!(dedup.sequenced_at(2, &sent(1)), None);
+        // Another sender's message with the same generation is distinct
+        assert_eq!(dedup.sequenced_at(1, &SentMessage { sender_id: 8, ..sent(1) }), None);
+
+        dedup.remove_room(1);
+        assert_eq!(dedup.sequenced_at(1, &sent(1)), None);
+    }
+}
@@ -0,0 +1,311 @@
+//! Retention policies for stored frames.
+//!
+//! Operators bound how long a room's history is kept, by age, by count, or
+//! both. A retention pass finds the oldest frame each room must keep, takes a
+//! snapshot just below it and compacts storage, so pruning goes through the
+//! same truncation point sync already understands: `latest_log_index` is
+//! untouched and clients asking for pruned ranges are served from the
+//! boundary.
+//!
+//! Rooms with a message TTL (disappearing messages) also prune frames older
+//! than the TTL, whichever of the TTL and the policy's `max_age` is shorter.
+//! Attachments are held to the same age limit, counted from when their
+//! upload completed.
+//!
+//! A frame's age is its HLC physical time, which clients stamp from the
+//! server-synchronized clock. Pruning only ever removes a prefix of the log,
+//! so a frame stamped out of order is kept until everything before it has
+//! expired too.
+
+use std::{collections::HashMap, time::Duration};
+
+use lockframe_core::hlc::HlcTimestamp;
+
+use crate::storage::{Storage, StorageError};
+
+/// Default time between retention passes.
+pub const DEFAULT_RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Frames loaded per storage read while looking for expired frames.
+const SCAN_PAGE: usize = 256;
+
+/// How much history a room keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Frames older than this are pruned (`None` keeps frames of any age)
+    pub max_age: Option<Duration>,
+    /// Only this many most recent frames are kept (`None` keeps any number)
+    pub max_frames: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Whether the policy never prunes anything.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age.is_none() && self.max_frames.is_none()
+    }
+}
+
+/// Retention settings, set server-wide and optionally per room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionConfig {
+    /// Policy for rooms without an override
+    pub policy: RetentionPolicy,
+    /// Time between retention passes
+    pub interval: Duration,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self { policy: RetentionPolicy::default(), interval: DEFAULT_RETENTION_INTERVAL }
+    }
+}
+
+/// Frames removed from a room by a retention pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pruned {
+    /// Room that was pruned
+    pub room_id: u128,
+    /// First log index still stored
+    pub first_retained: u64,
+    /// Number of frames removed
+    pub frames: u64,
+}
+
+/// Retention policies for every room.
+#[derive(Debug, Default)]
+pub struct Retention {
+    config: RetentionConfig,
+    overrides: HashMap<u128, RetentionPolicy>,
+}
+
+impl Retention {
+    /// Create retention using `config` for rooms without an override.
+    pub fn new(config: RetentionConfig) -> Self {
+        Self { config, overrides: HashMap::new() }
+    }
+
+    /// Override the policy for one room.
+    pub fn set_room_policy(&mut self, room_id: u128, policy: RetentionPolicy) {
+        self.overrides.insert(room_id, policy);
+    }
+
+    /// Policy applied to `room_id`.
+    pub fn policy(&self, room_id: u128) -> RetentionPolicy {
+        self.overrides.get(&room_id).copied().unwrap_or(self.config.policy)
+    }
+
+    /// Time between retention passes.
+    pub fn interval(&self) -> Duration {
+        self.config.interval
+    }
+
+    /// Policy applied to `room_id`, with its age limit capped at the room's
+    /// message TTL.
+    fn effective_policy(&self, room_id: u128, message_ttl: Option<Duration>) -> RetentionPolicy {
+        let mut policy = self.policy(room_id);
+        if let Some(ttl) = message_ttl {
+            policy.max_age = Some(policy.max_age.map_or(ttl, |max_age| max_age.min(ttl)));
+        }
+        policy
+    }
+
+    /// Prune `room_id` according to its policy and message TTL.
+    ///
+    /// `now_millis` is the current wall-clock time in Unix milliseconds.
+    /// Returns `None` if nothing had to be pruned.
+    pub fn prune<S: Storage>(
+        &self,
+        storage: &S,
+        room_id: u128,
+        message_ttl: Option<Duration>,
+        now_millis: u64,
+    ) -> Result<Option<Pruned>, StorageError> {
+        let policy = self.effective_policy(room_id, message_ttl);
+        if policy.is_unlimited() {
+            return Ok(None);
+        }
+
+        let Some(latest) = storage.latest_log_index(room_id)? else {
+            return Ok(None);
+        };
+        let end = latest.saturating_add(1);
+        let first = storage.load_snapshot(room_id)?.map_or(0, |s| s.first_retained_index());
+
+        let by_count = policy.max_frames.map_or(first, |max| end.saturating_sub(max));
+        let by_age = match policy.max_age {
+            Some(max_age) => {
+                let max_age = u64::try_from(max_age.as_millis()).unwrap_or(u64::MAX);
+                first_unexpired(storage, room_id, first, end, now_millis.saturating_sub(max_age))?
+            },
+            None => first,
+        };
+
+        let first_retained = by_count.max(by_age);
+        if first_retained <= first {
+            return Ok(None);
+        }
+
+        storage.snapshot(room_id, first_retained.saturating_sub(1))?;
+        let frames = storage.compact(room_id)?;
+        Ok(Some(Pruned { room_id, first_retained, frames }))
+    }
+
+    /// Delete the attachments of `room_id` stored longer ago than its
+    /// policy's age limit or message TTL, returning how many were deleted.
+    pub fn prune_attachments<S: Storage>(
+        &self,
+        storage: &S,
+        room_id: u128,
+        message_ttl: Option<Duration>,
+        now_millis: u64,
+    ) -> Result<usize, StorageError> {
+        let Some(max_age) = self.effective_policy(room_id, message_ttl).max_age else {
+            return Ok(0);
+        };
+        let max_age = u64::try_from(max_age.as_millis()).unwrap_or(u64::MAX);
+        let cutoff_millis = now_millis.saturating_sub(max_age);
+
+        let mut deleted: usize = 0;
+        for (content_hash, attachment) in storage.list_attachments(room_id)? {
+            if attachment.stored_at_millis < cutoff_millis {
+                storage.delete_attachment(room_id, &content_hash)?;
+                deleted = deleted.saturating_add(1);
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+/// First index in `[from, end)` holding a frame stamped at or after
+/// `cutoff_millis`, or `end` if every frame is older.
+fn first_unexpired<S: Storage>(
+    storage: &S,
+    room_id: u128,
+    from: u64,
+    end: u64,
+    cutoff_millis: u64,
+) -> Result<u64, StorageError> {
+    let mut index = from;
+    while index < end {
+        let frames = storage.load_frames(room_id, index, SCAN_PAGE)?;
+        if frames.is_empty() {
+            break;
+        }
+        for frame in &frames {
+            let millis = HlcTimestamp::from_u64(frame.header.hlc_timestamp()).physical_millis();
+            if millis >= cutoff_millis {
+                return Ok(frame.header.log_index());
+            }
+        }
+        index = index.saturating_add(frames.len() as u64);
+    }
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use lockframe_proto::{Frame, FrameHeader, Opcode};
+
+    use super::*;
+    use crate::{attachments::StoredAttachment, storage::MemoryStorage};
+
+    const ROOM: u128 = 0x1234;
+
+    fn store(storage: &MemoryStorage, log_index: u64, millis: u64) {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(ROOM);
+        header.set_log_index(log_index);
+        header.set_hlc_timestamp(HlcTimestamp::new(millis, 0).as_u64());
+        storage.store_frame(ROOM, log_index, &Frame::new(header, Vec::new())).unwrap();
+    }
+
+    #[test]
+    fn prunes_by_count_and_age() {
+        let storage = MemoryStorage::new();
+        for index in 0..10 {
+            store(&storage, index, 1_000 * (index + 1));
+        }
+
+        let mut retention = Retention::new(RetentionConfig {
+            policy: RetentionPolicy { max_age: None, max_frames: Some(8) },
+            ..RetentionConfig::default()
+        });
+        let pruned = retention.prune(&storage, ROOM, None, 10_000).unwrap();
+        assert_eq!(pruned, Some(Pruned { room_id: ROOM, first_retained: 2, frames: 2 }));
+
+        // Already within the limit
+        assert_eq!(retention.prune(&storage, ROOM, None, 10_000).unwrap(), None);
+
+        // Frames stamped before 6s are expired
+        retention.set_room_policy(ROOM, RetentionPolicy {
+            max_age: Some(Duration::from_secs(4)),
+            max_frames: Some(8),
+        });
+        let pruned = retention.prune(&storage, ROOM, None, 10_000).unwrap();
+        assert_eq!(pruned, Some(Pruned { room_id: ROOM, first_retained: 5, frames: 3 }));
+
+        assert_eq!(storage.latest_log_index(ROOM).unwrap(), Some(9));
+        assert_eq!(storage.load_frames(ROOM, 5, 100).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn everything_expired_keeps_latest_index() {
+        let storage = MemoryStorage::new();
+        for index in 0..3 {
+            store(&storage, index, 1_000);
+        }
+
+        let retention = Retention::new(RetentionConfig {
+            policy: RetentionPolicy { max_age: Some(Duration::from_secs(1)), max_frames: None },
+            ..RetentionConfig::default()
+        });
+        let pruned = retention.prune(&storage, ROOM, None, 60_000).unwrap();
+        assert_eq!(pruned, Some(Pruned { room_id: ROOM, first_retained: 3, frames: 3 }));
+        assert_eq!(storage.latest_log_index(ROOM).unwrap(), Some(2));
+
+        // Unlimited rooms and empty rooms are left alone
+        assert_eq!(Retention::default().prune(&storage, ROOM, None, 60_000).unwrap(), None);
+        assert_eq!(retention.prune(&storage, 0x99, None, 60_000).unwrap(), None);
+    }
+
+    #[test]
+    fn message_ttl_shortens_max_age() {
+        let storage = MemoryStorage::new();
+        for index in 0..10 {
+            store(&storage, index, 1_000 * (index + 1));
+        }
+
+        // TTL alone prunes an otherwise unlimited room
+        let retention = Retention::default();
+        let pruned = retention.prune(&storage, ROOM, Some(Duration::from_secs(7)), 10_000).unwrap();
+        assert_eq!(pruned, Some(Pruned { room_id: ROOM, first_retained: 2, frames: 2 }));
+
+        // The shorter of policy and TTL wins
+        let retention = Retention::new(RetentionConfig {
+            policy: RetentionPolicy { max_age: Some(Duration::from_secs(4)), max_frames: None },
+            ..RetentionConfig::default()
+        });
+        let pruned =
+            retention.prune(&storage, ROOM, Some(Duration::from_secs(60)), 10_000).unwrap();
+        assert_eq!(pruned, Some(Pruned { room_id: ROOM, first_retained: 5, frames: 3 }));
+    }
+
+    #[test]
+    fn attachments_expire_with_the_room_history() {
+        let storage = MemoryStorage::new();
+        for (hash, stored_at_millis) in [([1; 32], 2_000), ([2; 32], 8_000)] {
+            let attachment = StoredAttachment { total_size: 1, chunk_size: 1, stored_at_millis };
+            storage.store_attachment_chunk(ROOM, &hash, 0, b"x").unwrap();
+            storage.complete_attachment(ROOM, &hash, &attachment).unwrap();
+        }
+
+        let retention = Retention::default();
+        assert_eq!(retention.prune_attachments(&storage, ROOM, None, 10_000).unwrap(), 0);
+
+        let ttl = Some(Duration::from_secs(5));
+        assert_eq!(retention.prune_attachments(&storage, ROOM, ttl, 10_000).unwrap(), 1);
+        assert_eq!(storage.load_attachment_chunk(ROOM, &[1; 32], 0).unwrap(), None);
+        let kept = storage.list_attachments(ROOM).unwrap();
+        assert_eq!(kept.iter().map(|(hash, _)| *hash).collect::<Vec<_>>(), vec![[2; 32]]);
+    }
+}
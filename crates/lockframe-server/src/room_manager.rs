@@ -8,19 +8,54 @@
 //! rooms and enable future auth. RoomMetadata is an extension point for
 //! permissions/roles.
 
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, hash_map::Entry},
+    time::{Duration, Instant},
+};
 
+use ed25519_dalek::SigningKey;
 use lockframe_core::{
+    checkpoint::sign_checkpoint,
     env::Environment,
-    mls::{MlsValidator, ValidationResult, error::MlsError, group::MlsGroup, state::MlsGroupState},
+    mls::{
+        MlsValidator, StagedPeerCommit, ValidationResult, error::MlsError, group::MlsGroup,
+        state::MlsGroupState,
+    },
+};
+use lockframe_proto::{
+    Frame, FrameHeader, Opcode, Payload,
+    payloads::{
+        ErrorPayload,
+        app::{EncryptedMessage, ReadReceipt},
+        session::{ProofRequest, ProofResponse, SyncMode, SyncRequest},
+    },
 };
-use lockframe_proto::{Frame, Opcode};
 
 use crate::{
-    sequencer::{Sequencer, SequencerAction, SequencerError},
+    archival::ArchivalConfig,
+    audit::AuditEvent,
+    resend_dedup::{ResendDedup, SentMessage},
+    room_throughput::{RoomThroughput, RoomThroughputConfig},
+    sequencer::{Sequencer, SequencerAction, SequencerBackend, SequencerError},
     storage::{Storage, StorageError},
 };
 
+/// Frames read from storage per step of an epoch-changes sync scan.
+const EPOCH_SYNC_SCAN_BATCH: usize = 256;
+
+/// Most frames one epoch-changes sync request scans.
+///
+/// Bounds the storage reads a single request costs when the range holds
+/// mostly application traffic; the client continues from where the scan
+/// stopped.
+pub const EPOCH_SYNC_MAX_SCAN: usize = 4096;
+
+/// Most proposals a room holds for one epoch.
+///
+/// Further proposals are refused until a commit covers the queue, so a member
+/// cannot grow it, or the commit that has to reference it, without bound.
+pub const MAX_PENDING_PROPOSALS: usize = 256;
+
 /// Metadata about a room (extension point for future authorization)
 #[derive(Debug, Clone)]
 pub struct RoomMetadata {
@@ -28,20 +63,46 @@ pub struct RoomMetadata {
     pub creator: u64, // UserId
     /// When the room was created
     pub created_at: std::time::Instant,
+    /// Most members the room may have (`None` for no limit)
+    pub max_members: Option<usize>,
+    /// Where sequenced frames are archived (`None` to not archive)
+    pub archival: Option<ArchivalConfig>,
     // Future: admins, members, permissions
 }
 
 /// Orchestrates MLS validation + frame sequencing per room
-pub struct RoomManager<E>
+///
+/// Frames are sequenced by `Q`, the single-node [`Sequencer`] unless another
+/// [`SequencerBackend`] is given to [`RoomManager::with_sequencer`].
+pub struct RoomManager<E, Q = Sequencer>
 where
     E: Environment,
+    Q: SequencerBackend,
 {
     /// Per-room MLS group state
     groups: HashMap<u128, MlsGroup<E>>,
     /// Frame sequencer (assigns log indices)
-    sequencer: Sequencer,
+    sequencer: Q,
     /// Room metadata (for future authorization)
     room_metadata: HashMap<u128, RoomMetadata>,
+    /// Member limit given to newly created rooms
+    max_members: Option<usize>,
+    /// Sequenced proposals of the current epoch per room, loaded from
+    /// storage on first use
+    pending_proposals: HashMap<u128, Vec<Frame>>,
+    /// Read markers per room, member ID → last log index read, loaded from
+    /// storage on first use
+    read_markers: HashMap<u128, BTreeMap<u64, u64>>,
+    /// First damaged log index of rooms whose log failed a checksum
+    corrupted: HashMap<u128, u64>,
+    /// Archival set for rooms before they were created
+    pending_archival: HashMap<u128, ArchivalConfig>,
+    /// Per-room message and byte budgets
+    throughput: RoomThroughput,
+    /// Where recent application messages were sequenced, to answer resends
+    resend_dedup: ResendDedup,
+    /// Audit events not yet taken by the driver
+    audit: Vec<AuditEvent>,
 }
 
 /// Actions returned by RoomManager for driver to execute.
@@ -81,12 +142,73 @@ pub enum RoomAction {
         processed_at: std::time::Instant,
     },
 
+    /// Persist a commit frame together with the MLS state it produced
+    PersistCommit {
+        /// Room ID
+        room_id: u128,
+        /// Log index for the commit
+        log_index: u64,
+        /// Commit frame to persist
+        frame: Frame,
+        /// MLS state after merging the commit
+        state: MlsGroupState,
+        /// When the commit was processed by the server
+        processed_at: std::time::Instant,
+    },
+
+    /// Add a sequenced proposal to the room's stored pending proposals
+    PersistProposal {
+        /// Room ID
+        room_id: u128,
+        /// Proposal, with its log index
+        proposal: Frame,
+        /// When the proposal was sequenced
+        processed_at: std::time::Instant,
+    },
+
+    /// Drop the room's stored pending proposals, which a commit covered
+    ClearProposals {
+        /// Room ID
+        room_id: u128,
+        /// When the commit was processed
+        processed_at: std::time::Instant,
+    },
+
+    /// Persist a member's read marker, replacing the stored one
+    PersistReadMarker {
+        /// Room ID
+        room_id: u128,
+        /// Member who sent the read receipt
+        member_id: u64,
+        /// Log index of the last message the member has read
+        up_to_log_index: u64,
+        /// When the receipt was sequenced
+        processed_at: std::time::Instant,
+    },
+
+    /// Send a resent message's sequenced copy back to its sender instead of
+    /// sequencing it again
+    Redeliver {
+        /// Room the message was sequenced in
+        room_id: u128,
+        /// Sender who resent the message
+        sender_id: u64,
+        /// The message as sequenced
+        frame: Frame,
+        /// When the resend was processed
+        processed_at: std::time::Instant,
+    },
+
     /// Reject frame (send error to sender)
     Reject {
+        /// Room the frame was for
+        room_id: u128,
         /// Sender who should receive the rejection
         sender_id: u64,
-        /// Reason for rejection
-        reason: String,
+        /// Request ID of the rejected frame, echoed in the error
+        request_id: u32,
+        /// Error sent to the sender, its message giving the reason
+        error: ErrorPayload,
         /// When the rejection occurred
         processed_at: std::time::Instant,
     },
@@ -103,9 +225,39 @@ pub enum RoomAction {
         has_more: bool,
         /// Current epoch for this room
         server_epoch: u64,
+        /// Mode the frames were selected by
+        mode: SyncMode,
+        /// Log index to continue from
+        next_log_index: u64,
         /// When the response was prepared
         processed_at: std::time::Instant,
     },
+
+    /// Send Merkle proof response to client
+    SendProofResponse {
+        /// Sender to reply to
+        sender_id: u64,
+        /// Room ID the proof is for
+        room_id: u128,
+        /// Proof and the tree it was built against
+        response: ProofResponse,
+        /// When the response was prepared
+        processed_at: std::time::Instant,
+    },
+
+    /// A sequenced commit advanced the room's epoch
+    EpochAdvanced {
+        /// Room ID
+        room_id: u128,
+        /// Epoch after the commit
+        epoch: u64,
+        /// Members added by the commit, ascending
+        added: Vec<u64>,
+        /// Members removed by the commit, ascending
+        removed: Vec<u64>,
+        /// When the commit was processed
+        processed_at: std::time::Instant,
+    },
 }
 
 /// Errors from RoomManager operations
@@ -143,11 +295,160 @@ pub enum RoomError {
     /// Not a member of the group
     #[error("not a member: {0}")]
     NotMember(u64),
+
+    /// Frame type may only be produced by the server
+    #[error("opcode {0:#06x} is server-only")]
+    ServerOnly(u16),
+
+    /// `AppMessage` payload is not a well-formed encrypted envelope
+    #[error("malformed message envelope: {0}")]
+    MalformedEnvelope(String),
+
+    /// `ReadReceipt` payload is malformed or points past the room's log
+    #[error("invalid read receipt: {0}")]
+    InvalidReadReceipt(String),
+
+    /// Room log failed a checksum and is not served until a scrub passes
+    #[error("room {room_id:032x} log corrupted at index {log_index}")]
+    Corrupted {
+        /// Room whose log is damaged
+        room_id: u128,
+        /// First damaged log index found
+        log_index: u64,
+    },
+
+    /// Requested proof is outside the room's log
+    #[error("proof unavailable: {0}")]
+    ProofUnavailable(String),
+
+    /// Commit would take the room past its member limit
+    #[error("room {room_id:032x} full: {member_count} members exceeds limit of {max_members}")]
+    RoomFull {
+        /// Room the commit was for
+        room_id: u128,
+        /// The room's member limit
+        max_members: usize,
+        /// Members the room would have had after the commit
+        member_count: usize,
+    },
+
+    /// Room holds as many pending proposals as it may until a commit
+    #[error("room {room_id:032x} has {limit} pending proposals, commit them first")]
+    TooManyProposals {
+        /// Room the proposal was for
+        room_id: u128,
+        /// Most pending proposals a room holds
+        limit: usize,
+    },
+
+    /// Room is over its message or byte budget
+    #[error("room {room_id:032x} over its throughput limit, retry in {retry_after:?}")]
+    Throttled {
+        /// Room the message was for
+        room_id: u128,
+        /// Time until the room can sequence the message
+        retry_after: Duration,
+    },
 }
 
-impl<E> RoomManager<E>
+impl RoomError {
+    /// Whether the frame itself was refused, as opposed to the room being
+    /// unknown, busy or unable to reach storage.
+    pub const fn is_rejection(&self) -> bool {
+        matches!(
+            self,
+            Self::MlsValidation(_)
+                | Self::InvalidEpoch { .. }
+                | Self::NotMember(_)
+                | Self::ServerOnly(_)
+                | Self::MalformedEnvelope(_)
+                | Self::InvalidReadReceipt(_)
+                | Self::RoomFull { .. }
+                | Self::TooManyProposals { .. }
+        )
+    }
+}
+
+/// Check that an `AppMessage` payload is an encrypted envelope before it is
+/// sequenced.
+///
+/// The server cannot decrypt, but it can refuse payloads that were clearly not
+/// produced by sender key encryption: anything that doesn't decode as an
+/// `EncryptedMessage`, claims a different epoch than the header, carries a
+/// nonce that doesn't encode its own metadata, or is too short to hold an
+/// authentication tag. Other opcodes pass through unchanged.
+///
+/// Returns the identity of a well-formed `AppMessage`.
+fn validate_app_message_envelope(frame: &Frame) -> Result<Option<SentMessage>, RoomError> {
+    if frame.header.opcode_enum() != Some(Opcode::AppMessage) {
+        return Ok(None);
+    }
+
+    let message = match Payload::decode(Opcode::AppMessage, &frame.payload) {
+        Ok(Payload::AppMessage(message)) => message,
+        Ok(_) => return Err(RoomError::MalformedEnvelope("unexpected payload type".to_string())),
+        Err(e) => return Err(RoomError::MalformedEnvelope(e.to_string())),
+    };
+
+    if message.epoch != frame.header.epoch() {
+        return Err(RoomError::MalformedEnvelope(format!(
+            "envelope epoch {} does not match header epoch {}",
+            message.epoch,
+            frame.header.epoch()
+        )));
+    }
+
+    if !message.nonce_matches_metadata() {
+        return Err(RoomError::MalformedEnvelope(
+            "nonce does not encode epoch, sender_index and generation".to_string(),
+        ));
+    }
+
+    if message.ciphertext.len() < EncryptedMessage::TAG_SIZE {
+        return Err(RoomError::MalformedEnvelope(format!(
+            "ciphertext is {} bytes, shorter than the {} byte tag",
+            message.ciphertext.len(),
+            EncryptedMessage::TAG_SIZE
+        )));
+    }
+
+    Ok(Some(SentMessage::new(frame.header.sender_id(), &message)))
+}
+
+/// Decode a `ReadReceipt` payload and check it before it is sequenced.
+///
+/// The receipt must be for the frame's room and may only mark frames the room
+/// has already sequenced as read.
+fn validate_read_receipt(frame: &Frame, storage: &impl Storage) -> Result<ReadReceipt, RoomError> {
+    let receipt = match Payload::decode(Opcode::ReadReceipt, &frame.payload) {
+        Ok(Payload::ReadReceipt(receipt)) => receipt,
+        Ok(_) => return Err(RoomError::InvalidReadReceipt("unexpected payload type".to_string())),
+        Err(e) => return Err(RoomError::InvalidReadReceipt(e.to_string())),
+    };
+
+    let room_id = frame.header.room_id();
+    if receipt.room_id != room_id {
+        return Err(RoomError::InvalidReadReceipt(format!(
+            "receipt for room {:032x} sent to room {room_id:032x}",
+            receipt.room_id
+        )));
+    }
+
+    let latest = storage.latest_log_index(room_id)?;
+    if latest < Some(receipt.up_to_log_index) {
+        return Err(RoomError::InvalidReadReceipt(format!(
+            "log index {} has not been sequenced",
+            receipt.up_to_log_index
+        )));
+    }
+
+    Ok(receipt)
+}
+
+impl<E, Q> RoomManager<E, Q>
 where
     E: Environment,
+    Q: SequencerBackend,
 {
     /// Validate basic frame properties (epoch, membership) without signature
     /// verification This is done before sequencing to ensure the frame is
@@ -235,7 +536,41 @@ where
 {
     /// Create a new RoomManager
     pub fn new() -> Self {
-        Self { groups: HashMap::new(), sequencer: Sequencer::new(), room_metadata: HashMap::new() }
+        Self::with_max_members(None)
+    }
+
+    /// Create a RoomManager whose rooms are limited to `max_members` members.
+    pub fn with_max_members(max_members: Option<usize>) -> Self {
+        Self::with_sequencer(Sequencer::new(), max_members)
+    }
+}
+
+impl<E, Q> RoomManager<E, Q>
+where
+    E: Environment,
+    Q: SequencerBackend,
+{
+    /// Create a RoomManager that sequences frames with `sequencer` and limits
+    /// rooms to `max_members` members.
+    pub fn with_sequencer(sequencer: Q, max_members: Option<usize>) -> Self {
+        Self {
+            groups: HashMap::new(),
+            sequencer,
+            room_metadata: HashMap::new(),
+            max_members,
+            pending_proposals: HashMap::new(),
+            read_markers: HashMap::new(),
+            corrupted: HashMap::new(),
+            pending_archival: HashMap::new(),
+            throughput: RoomThroughput::default(),
+            resend_dedup: ResendDedup::default(),
+            audit: Vec::new(),
+        }
+    }
+
+    /// Limit how fast every room may sequence application messages.
+    pub fn set_throughput(&mut self, config: RoomThroughputConfig) {
+        self.throughput = RoomThroughput::new(config);
     }
 
     /// Check if a room exists
@@ -243,6 +578,47 @@ where
         self.room_metadata.contains_key(&room_id)
     }
 
+    /// Metadata of a room. `None` if room doesn't exist.
+    pub fn metadata(&self, room_id: u128) -> Option<&RoomMetadata> {
+        self.room_metadata.get(&room_id)
+    }
+
+    /// Archive a room's sequenced frames, or stop archiving with `None`.
+    ///
+    /// For a room not created yet, the setting is applied when it is.
+    pub fn set_archival(&mut self, room_id: u128, archival: Option<ArchivalConfig>) {
+        match self.room_metadata.get_mut(&room_id) {
+            Some(metadata) => metadata.archival = archival,
+            None => match archival {
+                Some(archival) => {
+                    self.pending_archival.insert(room_id, archival);
+                },
+                None => {
+                    self.pending_archival.remove(&room_id);
+                },
+            },
+        }
+    }
+
+    /// Stop hosting a room, returning its metadata. `None` if the room
+    /// doesn't exist.
+    ///
+    /// The room's stored log and MLS state are left in place.
+    pub fn remove_room(&mut self, room_id: u128) -> Option<RoomMetadata> {
+        self.groups.remove(&room_id);
+        self.pending_proposals.remove(&room_id);
+        self.read_markers.remove(&room_id);
+        self.corrupted.remove(&room_id);
+        self.throughput.remove_room(room_id);
+        self.resend_dedup.remove_room(room_id);
+        self.room_metadata.remove(&room_id)
+    }
+
+    /// IDs of every known room, in no particular order.
+    pub fn room_ids(&self) -> Vec<u128> {
+        self.room_metadata.keys().copied().collect()
+    }
+
     /// Current MLS epoch for a room. `None` if room doesn't exist.
     ///
     /// Returns `None` if the room doesn't exist.
@@ -250,6 +626,17 @@ where
         self.groups.get(&room_id).map(|g| g.epoch())
     }
 
+    /// Message TTL of a room, as its members set it in the group context.
+    /// `None` if the room doesn't exist or keeps messages indefinitely.
+    pub fn message_ttl(&self, room_id: u128) -> Option<Duration> {
+        self.groups.get(&room_id).and_then(MlsGroup::message_ttl)
+    }
+
+    /// Members in a room's MLS group. `None` if room doesn't exist.
+    pub fn member_count(&self, room_id: u128) -> Option<usize> {
+        self.groups.get(&room_id).map(|g| g.member_leaf_indices().len())
+    }
+
     /// Creates a room with the specified ID and records the creator for
     /// future authorization checks. Prevents duplicate room creation.
     pub fn create_room(&mut self, room_id: u128, creator: u64, env: &E) -> Result<(), RoomError> {
@@ -265,8 +652,14 @@ where
         self.groups.insert(room_id, group);
 
         // Store metadata (placeholder for future auth)
-        let metadata = RoomMetadata { creator, created_at: env.now() };
+        let metadata = RoomMetadata {
+            creator,
+            created_at: env.now(),
+            max_members: self.max_members,
+            archival: self.pending_archival.remove(&room_id),
+        };
         self.room_metadata.insert(room_id, metadata);
+        self.audit.push(AuditEvent::RoomCreated { room_id, creator });
 
         Ok(())
     }
@@ -274,13 +667,17 @@ where
     /// Add members to a room by their KeyPackages.
     ///
     /// Creates MLS commits and welcomes for adding new members.
-    /// The returned actions should be executed by the driver.
+    /// The returned actions should be executed by the driver. Fails with
+    /// [`RoomError::RoomFull`] if the room would exceed its member limit.
     pub fn add_members(
         &mut self,
         room_id: u128,
         key_packages: &[Vec<u8>],
     ) -> Result<Vec<lockframe_core::mls::MlsAction>, RoomError> {
         let group = self.groups.get_mut(&room_id).ok_or(RoomError::RoomNotFound(room_id))?;
+        let member_count = group.member_leaf_indices().len().saturating_add(key_packages.len());
+        check_member_limit(self.room_metadata.get(&room_id), room_id, member_count)?;
+
         let actions = group.add_members_from_bytes(key_packages)?;
         Ok(actions)
     }
@@ -322,21 +719,81 @@ where
     /// `from_log_index`, server loads frames from storage and sends
     /// SyncResponse. Client processes frames in order to catch up. If
     /// `has_more` is true, client sends another SyncRequest.
+    ///
+    /// In [`SyncMode::EpochChanges`] only MLS handshake frames are returned,
+    /// scanning at most [`EPOCH_SYNC_MAX_SCAN`] frames of the log.
     pub fn handle_sync_request(
-        &self,
+        &mut self,
         room_id: u128,
         sender_id: u64,
-        from_log_index: u64,
-        limit: usize,
+        request: &SyncRequest,
+        env: &E,
+        storage: &impl Storage,
+    ) -> Result<RoomAction, RoomError> {
+        self.handle_sync_request_within(
+            room_id,
+            sender_id,
+            request,
+            EPOCH_SYNC_MAX_SCAN,
+            env,
+            storage,
+        )
+    }
+
+    /// Handle a sync request, scanning at most `max_scan` frames of the log
+    /// in [`SyncMode::EpochChanges`].
+    ///
+    /// See [`handle_sync_request`](Self::handle_sync_request).
+    pub fn handle_sync_request_within(
+        &mut self,
+        room_id: u128,
+        sender_id: u64,
+        request: &SyncRequest,
+        max_scan: usize,
         env: &E,
         storage: &impl Storage,
     ) -> Result<RoomAction, RoomError> {
         let now = env.now();
+        let SyncRequest { from_log_index, mode, .. } = *request;
+        let limit = usize::try_from(request.limit).unwrap_or(usize::MAX);
 
         let group = self.groups.get(&room_id).ok_or(RoomError::RoomNotFound(room_id))?;
         let server_epoch = group.epoch();
 
-        let frames = storage.load_frames(room_id, from_log_index, limit)?;
+        // A damaged log is refused outright rather than served in part
+        if let Some(&log_index) = self.corrupted.get(&room_id) {
+            return Err(RoomError::Corrupted { room_id, log_index });
+        }
+
+        let loaded = match mode {
+            SyncMode::Full | SyncMode::Backfill => {
+                load_retained(storage, room_id, from_log_index, limit).map(|(from, frames)| {
+                    let next_log_index = from.saturating_add(frames.len() as u64);
+                    (frames, next_log_index)
+                })
+            },
+            SyncMode::EpochChanges => {
+                let max_scan = max_scan.min(EPOCH_SYNC_MAX_SCAN);
+                scan_epoch_changes(storage, room_id, from_log_index, limit, max_scan)
+            },
+        };
+        let (mut frames, next_log_index) = loaded.map_err(|e| self.quarantine(room_id, e))?;
+
+        // Pending proposals in the range that compaction dropped from the log
+        // are still needed to process the next commit, so they are served
+        // from the proposal queue ahead of the retained frames
+        let served_from = frames.first().map_or(next_log_index, |frame| frame.header.log_index());
+        if from_log_index < served_from {
+            let skipped = from_log_index..served_from;
+            let mut proposals: Vec<Frame> = self
+                .proposal_queue(room_id, storage)?
+                .iter()
+                .filter(|proposal| skipped.contains(&proposal.header.log_index()))
+                .cloned()
+                .collect();
+            proposals.append(&mut frames);
+            frames = proposals;
+        }
 
         let frame_bytes: Vec<Vec<u8>> = frames
             .iter()
@@ -349,12 +806,7 @@ where
             .collect();
 
         let latest_index = storage.latest_log_index(room_id)?;
-        let last_loaded_index = if frames.is_empty() {
-            from_log_index.saturating_sub(1)
-        } else {
-            from_log_index + frames.len() as u64 - 1
-        };
-        let has_more = latest_index.is_some_and(|latest| last_loaded_index < latest);
+        let has_more = latest_index.is_some_and(|latest| next_log_index <= latest);
 
         Ok(RoomAction::SendSyncResponse {
             sender_id,
@@ -362,10 +814,100 @@ where
             frames: frame_bytes,
             has_more,
             server_epoch,
+            mode,
+            next_log_index,
             processed_at: now,
         })
     }
 
+    /// Scrub a room's log for corruption.
+    ///
+    /// Returns how many frames were checked. A room found damaged stops being
+    /// served to syncing clients; one that passes, for example after being
+    /// restored from a backup, is served again.
+    pub fn scrub(&mut self, room_id: u128, storage: &impl Storage) -> Result<u64, RoomError> {
+        if !self.has_room(room_id) {
+            return Err(RoomError::RoomNotFound(room_id));
+        }
+
+        let checked = storage.verify(room_id).map_err(|e| self.quarantine(room_id, e))?;
+        self.corrupted.remove(&room_id);
+        Ok(checked)
+    }
+
+    /// First damaged log index of a room found corrupted. `None` if the
+    /// room's log is not known to be damaged.
+    pub fn corrupted_at(&self, room_id: u128) -> Option<u64> {
+        self.corrupted.get(&room_id).copied()
+    }
+
+    /// Convert a storage failure, marking the room damaged if it was
+    /// corruption.
+    fn quarantine(&mut self, room_id: u128, error: StorageError) -> RoomError {
+        match error {
+            StorageError::Corrupted { log_index, .. } => {
+                self.corrupted.insert(room_id, log_index);
+                RoomError::Corrupted { room_id, log_index }
+            },
+            error => RoomError::Storage(error),
+        }
+    }
+
+    /// Handle a Merkle proof request from a client.
+    ///
+    /// Builds the requested inclusion or consistency proof from the room's
+    /// tree and returns a `SendProofResponse` action. Sizes left unset in the
+    /// request resolve to the current log size.
+    pub fn handle_proof_request(
+        &mut self,
+        room_id: u128,
+        sender_id: u64,
+        request: ProofRequest,
+        env: &E,
+        storage: &impl Storage,
+    ) -> Result<RoomAction, RoomError> {
+        if !self.groups.contains_key(&room_id) {
+            return Err(RoomError::RoomNotFound(room_id));
+        }
+
+        let merkle = self.sequencer.merkle_log(room_id, storage)?;
+        let current = merkle.len();
+
+        let (tree_size, root, path) = match request {
+            ProofRequest::Inclusion { leaf_index, tree_size } => {
+                let tree_size = tree_size.unwrap_or(current);
+                let (root, path) = merkle
+                    .root_at(tree_size)
+                    .zip(merkle.inclusion_proof(leaf_index, tree_size))
+                    .ok_or_else(|| {
+                        RoomError::ProofUnavailable(format!(
+                            "leaf {leaf_index} not in tree of size {tree_size} (log size {current})"
+                        ))
+                    })?;
+                (tree_size, root, path)
+            },
+            ProofRequest::Consistency { old_size, new_size } => {
+                let new_size = new_size.unwrap_or(current);
+                let (root, path) = merkle
+                    .root_at(new_size)
+                    .zip(merkle.consistency_proof(old_size, new_size))
+                    .ok_or_else(|| {
+                        RoomError::ProofUnavailable(format!(
+                            "no consistency proof from {old_size} to {new_size} (log size {current})"
+                        ))
+                    })?;
+                (new_size, root, path)
+            },
+        };
+
+        Ok(RoomAction::SendProofResponse {
+            sender_id,
+            room_id,
+            response: ProofResponse { request, tree_size, root, path },
+            processed_at: env.now(),
+        })
+    }
+
     /// Process a frame through MLS validation and sequencing
     ///
     /// This method orchestrates the full frame processing pipeline:
@@ -374,11 +916,193 @@ where
     /// 3. Sequence the frame (assign log index)
     /// 4. Convert SequencerAction to RoomAction
     /// 5. Return actions for driver to execute
+    ///
+    /// Membership changes and rejected frames are recorded for
+    /// [`RoomManager::take_audit_events`].
     pub fn process_frame(
         &mut self,
         frame: Frame,
         env: &E,
         storage: &impl Storage,
+    ) -> Result<Vec<RoomAction>, RoomError> {
+        let room_id = frame.header.room_id();
+        let sender_id = frame.header.sender_id();
+        let result = self.sequence_frame(frame, env, storage);
+
+        match &result {
+            Ok(actions) => {
+                for action in actions {
+                    self.audit_action(action);
+                }
+            },
+            Err(error) if error.is_rejection() => {
+                let reason = error.to_string();
+                self.audit.push(AuditEvent::FrameRejected { room_id, sender_id, reason });
+            },
+            Err(_) => {},
+        }
+        result
+    }
+
+    /// Validate an ephemeral frame, such as a typing indicator, and relay it
+    /// to the room without sequencing it.
+    ///
+    /// The sender must be a member at the room's epoch and have signed the
+    /// frame, as for sequenced frames, but the frame gets no log index and is
+    /// neither stored nor checkpointed.
+    pub fn relay_ephemeral(
+        &self,
+        frame: Frame,
+        env: &E,
+        storage: &impl Storage,
+    ) -> Result<Vec<RoomAction>, RoomError> {
+        self.authorize(&frame, storage)?;
+        let room_id = frame.header.room_id();
+
+        Ok(vec![RoomAction::Broadcast {
+            room_id,
+            frame,
+            exclude_sender: true,
+            processed_at: env.now(),
+        }])
+    }
+
+    /// Check that a frame's sender is a member of its room at the room's
+    /// epoch and signed the frame, without sequencing it.
+    pub fn authorize(&self, frame: &Frame, storage: &impl Storage) -> Result<(), RoomError> {
+        let room_id = frame.header.room_id();
+        let group = self.groups.get(&room_id).ok_or(RoomError::RoomNotFound(room_id))?;
+
+        let mls_state = storage.load_mls_state(room_id)?;
+        self.validate_frame_basic(frame, group, mls_state.as_ref())?;
+        if let Some(state) = &mls_state {
+            if let ValidationResult::Reject { reason } =
+                MlsValidator::validate_signature(frame, state)?
+            {
+                return Err(RoomError::MlsValidation(MlsError::ValidationFailed(reason)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Audit events recorded since the last call, oldest first.
+    pub fn take_audit_events(&mut self) -> Vec<AuditEvent> {
+        std::mem::take(&mut self.audit)
+    }
+
+    fn audit_action(&mut self, action: &RoomAction) {
+        match action {
+            RoomAction::EpochAdvanced { room_id, epoch, added, removed, .. } => {
+                let (room_id, epoch) = (*room_id, *epoch);
+                self.audit.extend(added.iter().map(|&member_id| AuditEvent::MemberAdded {
+                    room_id,
+                    member_id,
+                    epoch,
+                }));
+                self.audit.extend(removed.iter().map(|&member_id| AuditEvent::MemberRemoved {
+                    room_id,
+                    member_id,
+                    epoch,
+                }));
+            },
+            RoomAction::Reject { room_id, sender_id, error, .. } => {
+                self.audit.push(AuditEvent::FrameRejected {
+                    room_id: *room_id,
+                    sender_id: *sender_id,
+                    reason: error.message.clone(),
+                });
+            },
+            _ => {},
+        }
+    }
+
+    /// Verify a peer's commit and check it against the member limit. `None`
+    /// if the commit is our own.
+    fn stage_peer_commit(
+        &mut self,
+        room_id: u128,
+        commit: &Frame,
+    ) -> Result<Option<StagedPeerCommit>, RoomError> {
+        let group = self.groups.get_mut(&room_id).ok_or(RoomError::RoomNotFound(room_id))?;
+        if group.has_mls_pending_commit() {
+            // We created this commit; the limit was checked in add_members
+            return Ok(None);
+        }
+        let staged = group.stage_commit(commit)?;
+        check_member_limit(self.room_metadata.get(&room_id), room_id, staged.member_count())?;
+        Ok(Some(staged))
+    }
+
+    /// Admit an application message: a resend of one the room sequenced
+    /// recently is answered with the sequenced copy, anything else spends
+    /// the room's budget and goes on to be sequenced (`None`).
+    fn admit_app_message(
+        &mut self,
+        room_id: u128,
+        message: &SentMessage,
+        frame: &Frame,
+        storage: &impl Storage,
+        now: Instant,
+    ) -> Result<Option<RoomAction>, RoomError> {
+        if let Some(redelivered) = self.redeliver(room_id, message, frame, storage, now)? {
+            return Ok(Some(redelivered));
+        }
+        self.throughput
+            .check(room_id, frame.payload.len(), now)
+            .map_err(|retry_after| RoomError::Throttled { room_id, retry_after })?;
+        Ok(None)
+    }
+
+    /// Log index the frame was sequenced at, if it was, remembering it for
+    /// an application message so a resend can be answered.
+    fn record_sequenced(
+        &mut self,
+        room_id: u128,
+        message: Option<SentMessage>,
+        room_actions: &[RoomAction],
+    ) -> Option<u64> {
+        let log_index = room_actions.iter().find_map(|action| match action {
+            RoomAction::PersistFrame { log_index, .. } => Some(*log_index),
+            _ => None,
+        })?;
+        if let Some(message) = message {
+            self.resend_dedup.record(room_id, message, log_index);
+        }
+        Some(log_index)
+    }
+
+    /// The sequenced copy of `message`, if `resent` is a byte-identical
+    /// resend of a message the room sequenced recently and the copy is still
+    /// stored.
+    fn redeliver(
+        &self,
+        room_id: u128,
+        message: &SentMessage,
+        resent: &Frame,
+        storage: &impl Storage,
+        now: Instant,
+    ) -> Result<Option<RoomAction>, RoomError> {
+        let Some(log_index) = self.resend_dedup.sequenced_at(room_id, message) else {
+            return Ok(None);
+        };
+        let frame = storage
+            .load_frames(room_id, log_index, 1)?
+            .into_iter()
+            .next()
+            .filter(|stored| stored.payload == resent.payload);
+        Ok(frame.map(|frame| RoomAction::Redeliver {
+            room_id,
+            sender_id: message.sender_id,
+            frame,
+            processed_at: now,
+        }))
+    }
+
+    fn sequence_frame(
+        &mut self,
+        frame: Frame,
+        env: &E,
+        storage: &impl Storage,
     ) -> Result<Vec<RoomAction>, RoomError> {
         let now = env.now();
 
@@ -386,13 +1110,46 @@ where
         let room_id = frame.header.room_id();
         let group = self.groups.get(&room_id).ok_or(RoomError::RoomNotFound(room_id))?;
 
-        // 2. Basic frame validation (epoch, membership) - NOT signature yet
+        // Checkpoints are only ever produced by the server itself
+        if frame.header.opcode_enum() == Some(Opcode::Checkpoint) {
+            return Err(RoomError::ServerOnly(frame.header.opcode()));
+        }
+
+        // 2. Basic frame validation (epoch, membership, envelope) - NOT signature yet
         let mls_state = storage.load_mls_state(room_id)?;
         self.validate_frame_basic(&frame, &group, mls_state.as_ref())?;
+        let sent_message = validate_app_message_envelope(&frame)?;
+        if let Some(message) = &sent_message {
+            if let Some(redelivered) =
+                self.admit_app_message(room_id, message, &frame, storage, now)?
+            {
+                return Ok(vec![redelivered]);
+            }
+        }
 
-        // Check if this is a Commit before sequencing (we need the frame later)
+        // Peer commits are verified and checked against the member limit before
+        // sequencing, and merged once the frame has a log index
         let is_commit = frame.header.opcode_enum() == Some(Opcode::Commit);
-        let frame_for_mls = if is_commit { Some(frame.clone()) } else { None };
+        let staged_commit = if is_commit { self.stage_peer_commit(room_id, &frame)? } else { None };
+
+        // Proposals and commits change the proposal queue; load it before the
+        // frame is sequenced so a storage failure cannot strand a log index
+        let is_proposal = frame.header.opcode_enum() == Some(Opcode::Proposal);
+        if is_proposal || is_commit {
+            let queued = self.proposal_queue(room_id, storage)?.len();
+            if is_proposal && queued >= MAX_PENDING_PROPOSALS {
+                return Err(RoomError::TooManyProposals { room_id, limit: MAX_PENDING_PROPOSALS });
+            }
+        }
+
+        // Likewise, read markers are loaded before a receipt is sequenced
+        let read_receipt = if frame.header.opcode_enum() == Some(Opcode::ReadReceipt) {
+            let receipt = validate_read_receipt(&frame, storage)?;
+            self.room_read_markers(room_id, storage)?;
+            Some((frame.header.sender_id(), receipt))
+        } else {
+            None
+        };
 
         // 3. Sequence the frame (assign log index) - this modifies context_id
         let sequencer_actions = self.sequencer.process_frame(frame, storage)?;
@@ -401,54 +1158,351 @@ where
         self.validate_sequenced_actions_signatures(&sequencer_actions, mls_state.as_ref())?;
 
         // 5. Convert SequencerAction to RoomAction
-        let mut room_actions: Vec<RoomAction> = sequencer_actions
-            .into_iter()
-            .map(|action| match action {
-                SequencerAction::AcceptFrame { room_id, log_index, frame } => {
-                    RoomAction::PersistFrame { room_id, log_index, frame, processed_at: now }
-                },
-                SequencerAction::StoreFrame { room_id, log_index, frame } => {
-                    RoomAction::PersistFrame { room_id, log_index, frame, processed_at: now }
-                },
-                SequencerAction::BroadcastToRoom { room_id, frame } => RoomAction::Broadcast {
+        let mut room_actions = convert_sequencer_actions(sequencer_actions, now);
+
+        if is_proposal {
+            let sequenced = room_actions.iter().find_map(|action| match action {
+                RoomAction::PersistFrame { frame, .. } => Some(frame.clone()),
+                _ => None,
+            });
+            if let Some(proposal) = sequenced {
+                self.pending_proposals.entry(room_id).or_default().push(proposal.clone());
+                // Queued ahead of its frame, so a crash once the frame is
+                // stored cannot leave the proposal out of the queue
+                let at = room_actions
+                    .iter()
+                    .position(|action| matches!(action, RoomAction::PersistFrame { .. }))
+                    .unwrap_or(room_actions.len());
+                room_actions.insert(at, RoomAction::PersistProposal {
                     room_id,
-                    frame,
-                    exclude_sender: false,
+                    proposal,
                     processed_at: now,
-                },
-                SequencerAction::RejectFrame { room_id: _, reason, original_frame } => {
-                    RoomAction::Reject {
-                        sender_id: original_frame.header.sender_id(),
-                        reason,
-                        processed_at: now,
-                    }
-                },
-            })
-            .collect();
+                });
+            }
+        }
+
+        let sequenced = self.record_sequenced(room_id, sent_message, &room_actions);
+        if let Some((sender_id, receipt)) = read_receipt.filter(|_| sequenced.is_some()) {
+            room_actions.extend(self.advance_read_marker(room_id, sender_id, &receipt, now));
+        }
 
         // 6. Update MLS state if this was a Commit
-        if frame_for_mls.is_some() {
+        if is_commit {
             let group = self.groups.get_mut(&room_id).ok_or(RoomError::RoomNotFound(room_id))?;
+            let members_before = member_ids(group);
 
-            if group.has_mls_pending_commit() {
-                // We created this commit - merge our pending state
-                group.merge_pending_commit()?;
-            } else {
+            match staged_commit {
                 // MLS actions from peer commit are primarily logging (epoch advanced). These
-                // are handled via tracing. The critical outcome is that
-                // process_message() called merge_staged_commit() to advance our
-                // epoch
-                let commit_frame =
-                    frame_for_mls.as_ref().expect("invariant: frame_for_mls is Some");
-                let _mls_actions = group.process_message(commit_frame.clone())?;
+                // are handled via tracing. The critical outcome is that the staged commit is
+                // merged to advance our epoch
+                Some(staged) => {
+                    let _mls_actions = group.merge_staged_commit(staged)?;
+                },
+                // We created this commit - merge our pending state
+                None => group.merge_pending_commit()?,
             }
 
+            let members_after = member_ids(group);
+            room_actions.push(RoomAction::EpochAdvanced {
+                room_id,
+                epoch: group.epoch(),
+                added: members_after.difference(&members_before).copied().collect(),
+                removed: members_before.difference(&members_after).copied().collect(),
+                processed_at: now,
+            });
+
             let state = group.export_group_state()?;
-            room_actions.push(RoomAction::PersistMlsState { room_id, state, processed_at: now });
+            attach_commit_state(&mut room_actions, room_id, state, now);
+
+            // The commit covers or discards every proposal of the old epoch
+            if self.pending_proposals.get_mut(&room_id).is_some_and(|proposals| {
+                let had_proposals = !proposals.is_empty();
+                proposals.clear();
+                had_proposals
+            }) {
+                room_actions.push(RoomAction::ClearProposals { room_id, processed_at: now });
+            }
         }
 
         Ok(room_actions)
     }
+
+    /// Proposals sequenced in the room's current epoch that no commit has
+    /// covered yet, in log order.
+    ///
+    /// Loaded from storage the first time, so the queue survives a restart.
+    pub fn pending_proposals(
+        &mut self,
+        room_id: u128,
+        storage: &impl Storage,
+    ) -> Result<&[Frame], RoomError> {
+        Ok(self.proposal_queue(room_id, storage)?)
+    }
+
+    /// The room's proposal queue, loading it from storage if needed.
+    ///
+    /// Stored proposals from an earlier epoch are dropped: a commit covered
+    /// them but the server stopped before the cleared queue was persisted.
+    /// So are proposals the log does not hold: the server stopped after
+    /// queueing one but before storing its frame.
+    fn proposal_queue(
+        &mut self,
+        room_id: u128,
+        storage: &impl Storage,
+    ) -> Result<&mut Vec<Frame>, RoomError> {
+        let epoch = self.groups.get(&room_id).ok_or(RoomError::RoomNotFound(room_id))?.epoch();
+        match self.pending_proposals.entry(room_id) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let mut proposals = Vec::new();
+                for proposal in storage.load_pending_proposals(room_id)? {
+                    if proposal.header.epoch() == epoch && is_logged(storage, room_id, &proposal)? {
+                        proposals.push(proposal);
+                    }
+                }
+                Ok(entry.insert(proposals))
+            },
+        }
+    }
+
+    /// Move a member's read marker to a sequenced receipt.
+    ///
+    /// Markers only move forward: a receipt sequenced after a newer one
+    /// leaves the marker where it is.
+    fn advance_read_marker(
+        &mut self,
+        room_id: u128,
+        member_id: u64,
+        receipt: &ReadReceipt,
+        now: Instant,
+    ) -> Option<RoomAction> {
+        let markers = self.read_markers.entry(room_id).or_default();
+        if markers.get(&member_id).copied() >= Some(receipt.up_to_log_index) {
+            return None;
+        }
+        markers.insert(member_id, receipt.up_to_log_index);
+        Some(RoomAction::PersistReadMarker {
+            room_id,
+            member_id,
+            up_to_log_index: receipt.up_to_log_index,
+            processed_at: now,
+        })
+    }
+
+    /// Each member's read position in a room, member ID → log index of the
+    /// last message they have read.
+    ///
+    /// Loaded from storage the first time, so markers survive a restart.
+    pub fn read_markers(
+        &mut self,
+        room_id: u128,
+        storage: &impl Storage,
+    ) -> Result<&BTreeMap<u64, u64>, RoomError> {
+        Ok(self.room_read_markers(room_id, storage)?)
+    }
+
+    /// The room's read markers, loading them from storage if needed.
+    fn room_read_markers(
+        &mut self,
+        room_id: u128,
+        storage: &impl Storage,
+    ) -> Result<&mut BTreeMap<u64, u64>, RoomError> {
+        if !self.groups.contains_key(&room_id) {
+            return Err(RoomError::RoomNotFound(room_id));
+        }
+        match self.read_markers.entry(room_id) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(storage.load_read_markers(room_id)?)),
+        }
+    }
+
+    /// Sequencer assigning the rooms' log indices.
+    pub fn sequencer(&self) -> &Q {
+        &self.sequencer
+    }
+
+    /// Frames sequenced in a room since its last checkpoint.
+    pub fn frames_since_checkpoint(&self, room_id: u128) -> u64 {
+        self.sequencer.frames_since_checkpoint(room_id)
+    }
+
+    /// Sign and sequence a checkpoint over the room's log so far.
+    ///
+    /// The checkpoint covers every frame sequenced before it and is itself
+    /// persisted and broadcast like any other frame. Returns no actions if
+    /// nothing was sequenced since the previous checkpoint.
+    pub fn checkpoint(
+        &mut self,
+        room_id: u128,
+        key: &SigningKey,
+        env: &E,
+        storage: &impl Storage,
+    ) -> Result<Vec<RoomAction>, RoomError> {
+        let group = self.groups.get(&room_id).ok_or(RoomError::RoomNotFound(room_id))?;
+
+        if self.sequencer.frames_since_checkpoint(room_id) == 0 {
+            return Ok(Vec::new());
+        }
+        let Some((log_index, log_hash)) = self.sequencer.log_head(room_id) else {
+            return Ok(Vec::new());
+        };
+
+        let mut header = FrameHeader::new(Opcode::Checkpoint);
+        header.set_room_id(room_id);
+        header.set_epoch(group.epoch());
+
+        let checkpoint = sign_checkpoint(key, room_id, log_index, log_hash);
+        let frame = Payload::Checkpoint(checkpoint)
+            .into_frame(header)
+            .map_err(|e| SequencerError::Validation(e.to_string()))?;
+
+        let sequencer_actions = self.sequencer.process_frame(frame, storage)?;
+        Ok(convert_sequencer_actions(sequencer_actions, env.now()))
+    }
+}
+
+/// Reject a membership change that leaves the room with more members than
+/// its limit allows.
+fn check_member_limit(
+    metadata: Option<&RoomMetadata>,
+    room_id: u128,
+    member_count: usize,
+) -> Result<(), RoomError> {
+    match metadata.and_then(|metadata| metadata.max_members) {
+        Some(max_members) if member_count > max_members => {
+            Err(RoomError::RoomFull { room_id, max_members, member_count })
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Store `state` with the commit's frame, so a crash cannot keep one without
+/// the other.
+///
+/// Turns the first [`RoomAction::PersistFrame`] into a
+/// [`RoomAction::PersistCommit`], or persists the state alone if the commit
+/// frame is not being stored.
+fn attach_commit_state(
+    actions: &mut Vec<RoomAction>,
+    room_id: u128,
+    state: MlsGroupState,
+    now: Instant,
+) {
+    for action in actions.iter_mut() {
+        if let RoomAction::PersistFrame { log_index, frame, .. } = action {
+            let (log_index, frame) = (*log_index, frame.clone());
+            *action =
+                RoomAction::PersistCommit { room_id, log_index, frame, state, processed_at: now };
+            return;
+        }
+    }
+    actions.push(RoomAction::PersistMlsState { room_id, state, processed_at: now });
+}
+
+/// Load frames from `from_log_index`, or from the first retained frame if
+/// that one was compacted away.
+///
+/// Returns the index the frames start at along with the frames.
+fn load_retained(
+    storage: &impl Storage,
+    room_id: u128,
+    from_log_index: u64,
+    limit: usize,
+) -> Result<(u64, Vec<Frame>), StorageError> {
+    match storage.load_frames(room_id, from_log_index, limit) {
+        Err(StorageError::Compacted { first_index, .. }) => {
+            Ok((first_index, storage.load_frames(room_id, first_index, limit)?))
+        },
+        loaded => Ok((from_log_index, loaded?)),
+    }
+}
+
+/// Whether the log holds `proposal` at its log index. A proposal compacted
+/// out of the log counts as logged; the queue is all that is left of it.
+fn is_logged(
+    storage: &impl Storage,
+    room_id: u128,
+    proposal: &Frame,
+) -> Result<bool, StorageError> {
+    match storage.load_frames(room_id, proposal.header.log_index(), 1) {
+        Ok(frames) => Ok(frames.first() == Some(proposal)),
+        Err(StorageError::Compacted { .. }) => Ok(true),
+        Err(error) => Err(error),
+    }
+}
+
+/// Collect up to `limit` handshake frames from `from_log_index` on,
+/// scanning at most `max_scan` frames.
+///
+/// Returns the frames and the index right after the last frame scanned.
+fn scan_epoch_changes(
+    storage: &impl Storage,
+    room_id: u128,
+    from_log_index: u64,
+    limit: usize,
+    max_scan: usize,
+) -> Result<(Vec<Frame>, u64), StorageError> {
+    let mut frames = Vec::new();
+    let mut next_log_index = from_log_index;
+    let mut scanned = 0usize;
+
+    'scan: while frames.len() < limit && scanned < max_scan {
+        let batch_size = EPOCH_SYNC_SCAN_BATCH.min(max_scan.saturating_sub(scanned));
+        let (start, batch) = load_retained(storage, room_id, next_log_index, batch_size)?;
+        if batch.is_empty() {
+            break;
+        }
+
+        next_log_index = start;
+        for frame in batch {
+            next_log_index = next_log_index.saturating_add(1);
+            scanned = scanned.saturating_add(1);
+            if frame.header.opcode_enum().is_some_and(Opcode::is_handshake) {
+                frames.push(frame);
+                if frames.len() >= limit {
+                    break 'scan;
+                }
+            }
+        }
+    }
+
+    Ok((frames, next_log_index))
+}
+
+/// Member IDs currently in the group.
+fn member_ids<E: Environment>(group: &MlsGroup<E>) -> BTreeSet<u64> {
+    group
+        .member_leaf_indices()
+        .into_iter()
+        .filter_map(|leaf_index| group.member_id_by_leaf_index(leaf_index))
+        .collect()
+}
+
+/// Map sequencer output onto the actions the driver executes.
+fn convert_sequencer_actions(actions: Vec<SequencerAction>, now: Instant) -> Vec<RoomAction> {
+    actions
+        .into_iter()
+        .map(|action| match action {
+            SequencerAction::AcceptFrame { room_id, log_index, frame } => {
+                RoomAction::PersistFrame { room_id, log_index, frame, processed_at: now }
+            },
+            SequencerAction::StoreFrame { room_id, log_index, frame } => {
+                RoomAction::PersistFrame { room_id, log_index, frame, processed_at: now }
+            },
+            SequencerAction::BroadcastToRoom { room_id, frame } => {
+                RoomAction::Broadcast { room_id, frame, exclude_sender: false, processed_at: now }
+            },
+            SequencerAction::RejectFrame { room_id, reason, original_frame } => {
+                RoomAction::Reject {
+                    room_id,
+                    sender_id: original_frame.header.sender_id(),
+                    request_id: original_frame.header.request_id(),
+                    error: ErrorPayload::sequencer_error(reason),
+                    processed_at: now,
+                }
+            },
+        })
+        .collect()
 }
 
 impl<E> Default for RoomManager<E>
@@ -460,9 +1514,10 @@ where
     }
 }
 
-impl<E> std::fmt::Debug for RoomManager<E>
+impl<E, Q> std::fmt::Debug for RoomManager<E, Q>
 where
     E: Environment,
+    Q: SequencerBackend,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RoomManager")
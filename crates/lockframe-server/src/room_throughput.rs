@@ -0,0 +1,202 @@
+//! Per-room throughput limits.
+//!
+//! Every room's frames go through the same sequencer, so a room flooded with
+//! messages delays all the others. The room manager checks each application
+//! message against its room's message and byte budgets before sequencing it
+//! and refuses it with [`RoomError::Throttled`](crate::RoomError::Throttled)
+//! when either budget is spent. Budgets are token buckets that refill at a
+//! fixed rate and hold up to `burst_secs` of it. Proposals and commits are not
+//! limited, so membership still changes in a busy room.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Default seconds of throughput a quiet room can save up.
+pub const DEFAULT_ROOM_BURST_SECS: u64 = 2;
+
+/// Token precision: one message or byte is this many tokens.
+const TOKENS_PER_UNIT: u64 = 1_000;
+
+/// Throughput limits applied to every room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoomThroughputConfig {
+    /// Application messages per second a room may sequence (`None` for no
+    /// limit)
+    pub messages_per_sec: Option<u64>,
+    /// Application message payload bytes per second a room may sequence
+    /// (`None` for no limit)
+    pub bytes_per_sec: Option<u64>,
+    /// Seconds of throughput a quiet room can save up and spend at once
+    pub burst_secs: u64,
+}
+
+impl Default for RoomThroughputConfig {
+    fn default() -> Self {
+        Self { messages_per_sec: None, bytes_per_sec: None, burst_secs: DEFAULT_ROOM_BURST_SECS }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Available tokens (`TOKENS_PER_UNIT` per message or byte)
+    tokens: u64,
+    /// When tokens were last refilled
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn full(rate: u64, burst_secs: u64, now: Instant) -> Self {
+        Self { tokens: capacity(rate, burst_secs), refilled_at: now }
+    }
+
+    fn refill(&mut self, rate: u64, burst_secs: u64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        let elapsed_millis = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+        // units/sec * ms = units * 1000, one token per millisecond-unit
+        let earned = elapsed_millis.saturating_mul(rate);
+        if earned > 0 {
+            self.tokens = self.tokens.saturating_add(earned).min(capacity(rate, burst_secs));
+            self.refilled_at = now;
+        }
+    }
+
+    /// Time until `cost` tokens are available, zero if they are now.
+    fn wait_for(&self, cost: u64, rate: u64) -> Duration {
+        let missing = cost.saturating_sub(self.tokens);
+        if missing == 0 {
+            Duration::ZERO
+        } else if rate == 0 {
+            Duration::MAX
+        } else {
+            Duration::from_millis(missing.div_ceil(rate))
+        }
+    }
+}
+
+fn capacity(rate: u64, burst_secs: u64) -> u64 {
+    rate.saturating_mul(burst_secs.max(1)).saturating_mul(TOKENS_PER_UNIT)
+}
+
+#[derive(Debug, Default)]
+struct RoomBudget {
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+/// Throughput budgets for every room.
+#[derive(Debug, Default)]
+pub struct RoomThroughput {
+    config: RoomThroughputConfig,
+    rooms: HashMap<u128, RoomBudget>,
+}
+
+impl RoomThroughput {
+    /// Create budgets using `config` for every room.
+    pub fn new(config: RoomThroughputConfig) -> Self {
+        Self { config, rooms: HashMap::new() }
+    }
+
+    /// Spend one message of `bytes` payload bytes from the room's budgets.
+    ///
+    /// Nothing is spent if either budget is short; the error is the time
+    /// until both can pay. A message larger than the whole byte budget goes
+    /// through once that budget is full.
+    pub fn check(&mut self, room_id: u128, bytes: usize, now: Instant) -> Result<(), Duration> {
+        let RoomThroughputConfig { messages_per_sec, bytes_per_sec, burst_secs } = self.config;
+        if messages_per_sec.is_none() && bytes_per_sec.is_none() {
+            return Ok(());
+        }
+
+        let budget = self.rooms.entry(room_id).or_insert_with(|| RoomBudget {
+            messages: messages_per_sec.map(|rate| Bucket::full(rate, burst_secs, now)),
+            bytes: bytes_per_sec.map(|rate| Bucket::full(rate, burst_secs, now)),
+        });
+
+        let bytes = u64::try_from(bytes).unwrap_or(u64::MAX);
+        let charges = [
+            (budget.messages.as_mut(), messages_per_sec, TOKENS_PER_UNIT),
+            (budget.bytes.as_mut(), bytes_per_sec, bytes.saturating_mul(TOKENS_PER_UNIT)),
+        ];
+
+        let mut pending = Vec::with_capacity(charges.len());
+        let mut retry_after = Duration::ZERO;
+        for (bucket, rate, cost) in charges {
+            let (Some(bucket), Some(rate)) = (bucket, rate) else { continue };
+            bucket.refill(rate, burst_secs, now);
+            // Oversized messages wait for a full budget; a zero rate refuses all
+            let cost = cost.min(capacity(rate, burst_secs).max(1));
+            retry_after = retry_after.max(bucket.wait_for(cost, rate));
+            pending.push((bucket, cost));
+        }
+
+        if retry_after > Duration::ZERO {
+            return Err(retry_after);
+        }
+        for (bucket, cost) in pending {
+            bucket.tokens = bucket.tokens.saturating_sub(cost);
+        }
+        Ok(())
+    }
+
+    /// Forget a room no longer hosted here.
+    pub fn remove_room(&mut self, room_id: u128) {
+        self.rooms.remove(&room_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_budget_refuses_then_refills() {
+        let mut rooms = RoomThroughput::new(RoomThroughputConfig {
+            messages_per_sec: Some(2),
+            bytes_per_sec: None,
+            burst_secs: 1,
+        });
+        let now = Instant::now();
+
+        assert!(rooms.check(1, 10, now).is_ok());
+        assert!(rooms.check(1, 10, now).is_ok());
+        assert_eq!(rooms.check(1, 10, now), Err(Duration::from_millis(500)));
+        // Other rooms have their own budget
+        assert!(rooms.check(2, 10, now).is_ok());
+
+        assert!(rooms.check(1, 10, now + Duration::from_millis(500)).is_ok());
+    }
+
+    #[test]
+    fn refused_messages_spend_nothing() {
+        let mut rooms = RoomThroughput::new(RoomThroughputConfig {
+            messages_per_sec: Some(10),
+            bytes_per_sec: Some(100),
+            burst_secs: 1,
+        });
+        let now = Instant::now();
+
+        assert!(rooms.check(1, 80, now).is_ok());
+        // Bytes are short; the message budget is left alone
+        assert_eq!(rooms.check(1, 40, now), Err(Duration::from_millis(200)));
+        for _ in 0..9 {
+            assert!(rooms.check(1, 0, now).is_ok());
+        }
+        assert!(rooms.check(1, 0, now).is_err());
+    }
+
+    #[test]
+    fn oversized_message_waits_for_a_full_budget() {
+        let mut rooms = RoomThroughput::new(RoomThroughputConfig {
+            messages_per_sec: None,
+            bytes_per_sec: Some(100),
+            burst_secs: 1,
+        });
+        let now = Instant::now();
+
+        assert!(rooms.check(1, 500, now).is_ok());
+        assert_eq!(rooms.check(1, 500, now), Err(Duration::from_secs(1)));
+        assert!(rooms.check(1, 500, now + Duration::from_secs(1)).is_ok());
+    }
+}
@@ -6,11 +6,19 @@
 //!
 //! Flow: load state from storage, validate frame structure (magic, version,
 //! payload size), assign next log_index, return sequencing actions.
+//!
+//! Every sequenced frame is also folded into the room's rolling [`LogHash`],
+//! which signed checkpoints commit to, and appended to the room's
+//! [`MerkleLog`], which answers inclusion and consistency proof requests.
+//!
+//! [`SequencerBackend`] is what the room manager and driver sequence through,
+//! so a replicated backend (lease-based, Raft) can take the place of the
+//! single-node [`Sequencer`] shipped here, which is the default.
 
 use std::collections::HashMap;
 
-use lockframe_core::mls::MAX_EPOCH;
-use lockframe_proto::{Frame, FrameHeader};
+use lockframe_core::{checkpoint::LogHash, merkle::MerkleLog, mls::MAX_EPOCH};
+use lockframe_proto::{Frame, FrameHeader, Opcode};
 use thiserror::Error;
 
 use crate::storage::{Storage, StorageError};
@@ -79,14 +87,66 @@ pub enum SequencerAction {
     },
 }
 
+/// Assigns log indices to the frames of each room.
+///
+/// Implementations must give every room one total order: each accepted frame
+/// gets the room's next log index, indices are never reused or skipped, and
+/// a backend that starts over picks up from storage where the last one
+/// stopped. A replicated backend agrees on the index with its peers before
+/// returning [`SequencerAction::AcceptFrame`], and refuses frames on nodes
+/// that may not sequence the room.
+pub trait SequencerBackend: std::fmt::Debug + Send {
+    /// Sequence a frame validated by the room manager, returning what to
+    /// accept, store and broadcast.
+    fn process_frame(
+        &mut self,
+        frame: Frame,
+        storage: &impl Storage,
+    ) -> Result<Vec<SequencerAction>, SequencerError>;
+
+    /// Log index and rolling hash of the last frame sequenced in a room.
+    ///
+    /// `None` until the room has sequenced at least one frame.
+    fn log_head(&self, room_id: u128) -> Option<(u64, LogHash)>;
+
+    /// Frames sequenced in a room since its last checkpoint.
+    fn frames_since_checkpoint(&self, room_id: u128) -> u64;
+
+    /// Merkle tree over a room's log, loading the room from storage if it
+    /// has not been touched since startup.
+    fn merkle_log(
+        &mut self,
+        room_id: u128,
+        storage: &impl Storage,
+    ) -> Result<&MerkleLog, SequencerError>;
+
+    /// Next log index that will be assigned. `None` until the room is loaded.
+    fn next_log_index(&self, room_id: u128) -> Option<u64>;
+
+    /// Next log index of every loaded room, in no particular order.
+    ///
+    /// Once the frames sequenced so far are persisted, each watermark is one
+    /// past the room's latest stored log index.
+    fn watermarks(&self) -> impl Iterator<Item = (u128, u64)> + '_;
+}
+
+/// Number of frames loaded per batch when rebuilding the log hash and tree.
+const LOG_REPLAY_BATCH: usize = 256;
+
 /// Per-room sequencer state (cached)
 #[derive(Debug, Clone)]
 struct RoomSequencer {
     /// Next log index to assign
     next_log_index: u64,
+    /// Rolling hash of every frame sequenced so far
+    log_hash: LogHash,
+    /// Frames sequenced since the last checkpoint
+    since_checkpoint: u64,
+    /// Merkle tree over every frame sequenced so far
+    merkle: MerkleLog,
 }
 
-/// Server-side frame sequencer
+/// Single-node frame sequencer, the default [`SequencerBackend`]
 ///
 /// The Sequencer maintains per-room state (next_log_index)
 /// and assigns monotonic log indices to incoming frames.
@@ -175,6 +235,69 @@ impl Sequencer {
             return Ok(vec![SequencerAction::BroadcastToRoom { room_id, frame }]);
         }
 
+        let room = self.load_room(room_id, storage)?;
+        let log_index = room.next_log_index;
+
+        room.next_log_index = room.next_log_index.checked_add(1).ok_or_else(|| {
+            SequencerError::Validation(format!(
+                "log_index overflow for room {}: attempted to increment beyond u64::MAX",
+                room_id
+            ))
+        })?;
+
+        debug_assert!(room.next_log_index > log_index);
+
+        let sequenced_frame = rebuild_frame_with_index(frame, log_index)?;
+
+        debug_assert_eq!(sequenced_frame.header.log_index(), log_index);
+
+        room.log_hash = room.log_hash.chain(&sequenced_frame);
+        room.since_checkpoint = if sequenced_frame.header.opcode_enum() == Some(Opcode::Checkpoint)
+        {
+            0
+        } else {
+            room.since_checkpoint.saturating_add(1)
+        };
+        room.merkle.push(&sequenced_frame);
+
+        let frame_for_actions = sequenced_frame;
+        Ok(vec![
+            SequencerAction::AcceptFrame { room_id, log_index, frame: frame_for_actions.clone() },
+            SequencerAction::StoreFrame { room_id, log_index, frame: frame_for_actions.clone() },
+            SequencerAction::BroadcastToRoom { room_id, frame: frame_for_actions },
+        ])
+    }
+
+    /// Log index and rolling hash of the last frame sequenced in a room.
+    ///
+    /// `None` until the room has sequenced at least one frame.
+    pub fn log_head(&self, room_id: u128) -> Option<(u64, LogHash)> {
+        let room = self.rooms.get(&room_id)?;
+        let last = room.next_log_index.checked_sub(1)?;
+        Some((last, room.log_hash))
+    }
+
+    /// Frames sequenced in a room since its last checkpoint.
+    pub fn frames_since_checkpoint(&self, room_id: u128) -> u64 {
+        self.rooms.get(&room_id).map_or(0, |room| room.since_checkpoint)
+    }
+
+    /// Merkle tree over a room's log, loading the room from storage if it
+    /// has not been touched since startup.
+    pub fn merkle_log(
+        &mut self,
+        room_id: u128,
+        storage: &impl Storage,
+    ) -> Result<&MerkleLog, SequencerError> {
+        Ok(&self.load_room(room_id, storage)?.merkle)
+    }
+
+    /// Cached state for a room, initialized from storage on first use.
+    fn load_room(
+        &mut self,
+        room_id: u128,
+        storage: &impl Storage,
+    ) -> Result<&mut RoomSequencer, SequencerError> {
         if !self.rooms.contains_key(&room_id) {
             let latest_index = storage.latest_log_index(room_id).map_err(|e| {
                 tracing::error!(
@@ -191,44 +314,37 @@ impl Sequencer {
                 latest_index.map(|i| next_log_index == i + 1).unwrap_or(next_log_index == 0)
             );
 
+            let (log_hash, merkle) = replay_log(room_id, next_log_index, storage)?;
+
             tracing::debug!(
                 room_id = %room_id,
                 next_log_index,
                 "Initialized room state from storage"
             );
 
-            self.rooms.insert(room_id, RoomSequencer { next_log_index });
+            self.rooms.insert(room_id, RoomSequencer {
+                next_log_index,
+                log_hash,
+                since_checkpoint: 0,
+                merkle,
+            });
         }
 
-        let room = self.rooms.get_mut(&room_id).expect("room must exist after initialization");
-        let log_index = room.next_log_index;
-
-        room.next_log_index = room.next_log_index.checked_add(1).ok_or_else(|| {
-            SequencerError::Validation(format!(
-                "log_index overflow for room {}: attempted to increment beyond u64::MAX",
-                room_id
-            ))
-        })?;
-
-        debug_assert!(room.next_log_index > log_index);
-
-        let sequenced_frame = rebuild_frame_with_index(frame, log_index)?;
-
-        debug_assert_eq!(sequenced_frame.header.log_index(), log_index);
-
-        let frame_for_actions = sequenced_frame;
-        Ok(vec![
-            SequencerAction::AcceptFrame { room_id, log_index, frame: frame_for_actions.clone() },
-            SequencerAction::StoreFrame { room_id, log_index, frame: frame_for_actions.clone() },
-            SequencerAction::BroadcastToRoom { room_id, frame: frame_for_actions },
-        ])
+        Ok(self.rooms.get_mut(&room_id).expect("room must exist after initialization"))
     }
 
-    /// Next log index that will be assigned (for testing/debugging).
-    #[cfg(test)]
+    /// Next log index that will be assigned. `None` until the room is loaded.
     pub fn next_log_index(&self, room_id: u128) -> Option<u64> {
         self.rooms.get(&room_id).map(|r| r.next_log_index)
     }
+
+    /// Next log index of every loaded room, in no particular order.
+    ///
+    /// Once the frames sequenced so far are persisted, each watermark is one
+    /// past the room's latest stored log index.
+    pub fn watermarks(&self) -> impl Iterator<Item = (u128, u64)> + '_ {
+        self.rooms.iter().map(|(&room_id, room)| (room_id, room.next_log_index))
+    }
 }
 
 impl Default for Sequencer {
@@ -237,6 +353,69 @@ impl Default for Sequencer {
     }
 }
 
+impl SequencerBackend for Sequencer {
+    fn process_frame(
+        &mut self,
+        frame: Frame,
+        storage: &impl Storage,
+    ) -> Result<Vec<SequencerAction>, SequencerError> {
+        Self::process_frame(self, frame, storage)
+    }
+
+    fn log_head(&self, room_id: u128) -> Option<(u64, LogHash)> {
+        Self::log_head(self, room_id)
+    }
+
+    fn frames_since_checkpoint(&self, room_id: u128) -> u64 {
+        Self::frames_since_checkpoint(self, room_id)
+    }
+
+    fn merkle_log(
+        &mut self,
+        room_id: u128,
+        storage: &impl Storage,
+    ) -> Result<&MerkleLog, SequencerError> {
+        Self::merkle_log(self, room_id, storage)
+    }
+
+    fn next_log_index(&self, room_id: u128) -> Option<u64> {
+        Self::next_log_index(self, room_id)
+    }
+
+    fn watermarks(&self) -> impl Iterator<Item = (u128, u64)> + '_ {
+        Self::watermarks(self)
+    }
+}
+
+/// Recompute a room's rolling hash and Merkle tree from the frames already in
+/// storage.
+fn replay_log(
+    room_id: u128,
+    next_log_index: u64,
+    storage: &impl Storage,
+) -> Result<(LogHash, MerkleLog), SequencerError> {
+    let mut log_hash = LogHash::GENESIS;
+    let mut merkle = MerkleLog::new();
+    let mut from = 0;
+
+    while from < next_log_index {
+        let frames = storage.load_frames(room_id, from, LOG_REPLAY_BATCH)?;
+        if frames.is_empty() {
+            return Err(SequencerError::Storage(format!(
+                "log for room {room_id:032x} ends at {from}, expected {next_log_index} frames"
+            )));
+        }
+
+        for frame in &frames {
+            log_hash = log_hash.chain(frame);
+            merkle.push(frame);
+        }
+        from = from.saturating_add(frames.len() as u64);
+    }
+
+    Ok((log_hash, merkle))
+}
+
 /// Rebuild frame with new header containing assigned log_index
 ///
 /// This creates a new FrameHeader with the updated log_index while
@@ -363,4 +542,66 @@ mod tests {
         assert_eq!(sequencer.next_log_index(100), Some(3));
         assert_eq!(sequencer.next_log_index(200), Some(5));
     }
+
+    #[test]
+    fn log_hash_survives_reload_from_storage() {
+        let storage = MemoryStorage::new();
+        let room_id = 100;
+
+        let mut sequencer = Sequencer::new();
+        assert_eq!(sequencer.log_head(room_id), None);
+
+        for _ in 0..3 {
+            let actions = sequencer
+                .process_frame(create_test_frame(room_id, 200, 0), &storage)
+                .expect("sequencing failed");
+            for action in actions {
+                if let SequencerAction::StoreFrame { room_id, log_index, frame } = action {
+                    storage.store_frame(room_id, log_index, &frame).expect("store failed");
+                }
+            }
+        }
+
+        let head = sequencer.log_head(room_id).expect("room has frames");
+        assert_eq!(head.0, 2);
+        assert_eq!(sequencer.frames_since_checkpoint(room_id), 3);
+
+        // A fresh sequencer rebuilds the same hash from storage
+        let mut reloaded = Sequencer::new();
+        for sequencer in [&mut sequencer, &mut reloaded] {
+            sequencer
+                .process_frame(create_test_frame(room_id, 200, 0), &storage)
+                .expect("sequencing failed");
+        }
+
+        assert_eq!(sequencer.log_head(room_id), reloaded.log_head(room_id));
+        assert_ne!(sequencer.log_head(room_id), Some(head));
+
+        let root = sequencer.merkle_log(room_id, &storage).expect("room loaded").root();
+        let reloaded_root = reloaded.merkle_log(room_id, &storage).expect("room loaded").root();
+        assert_eq!(root, reloaded_root);
+    }
+
+    #[test]
+    fn merkle_log_loads_untouched_room() {
+        let storage = MemoryStorage::new();
+        let room_id = 100;
+
+        let mut sequencer = Sequencer::new();
+        for _ in 0..5 {
+            for action in sequencer
+                .process_frame(create_test_frame(room_id, 200, 0), &storage)
+                .expect("sequencing failed")
+            {
+                if let SequencerAction::StoreFrame { room_id, log_index, frame } = action {
+                    storage.store_frame(room_id, log_index, &frame).expect("store failed");
+                }
+            }
+        }
+
+        let mut reloaded = Sequencer::new();
+        let merkle = reloaded.merkle_log(room_id, &storage).expect("room loaded");
+        assert_eq!(merkle.len(), 5);
+        assert_eq!(reloaded.next_log_index(room_id), Some(5));
+    }
 }
@@ -5,9 +5,12 @@
 //! - Room subscription (subscribe, unsubscribe)
 //! - Action execution (send, broadcast, persist)
 
-use std::fmt;
+use std::{fmt, time::Duration};
 
-use crate::{room_manager::RoomError, storage::StorageError};
+use crate::{
+    driver::ServerAction, fault::InjectedFault, room_manager::RoomError, storage::StorageError,
+    sync_budget::SyncDenied,
+};
 
 /// Errors that can occur during server operations.
 #[derive(Debug)]
@@ -54,6 +57,22 @@ pub enum ServerError {
     /// Invalid frame format received from client or failed to encode response.
     /// Fatal for that frame/connection - indicates protocol violation or bug.
     Protocol(String),
+
+    /// Request exceeded the session's budget.
+    ///
+    /// Transient - the client should retry after `retry_after`.
+    RateLimited {
+        /// What limit was hit
+        reason: String,
+        /// When the client may retry
+        retry_after: Duration,
+    },
+
+    /// A fault hook failed the event on purpose.
+    ///
+    /// Only raised while a [`FaultHook`](crate::FaultHook) is installed,
+    /// which takes the `fault-injection` feature.
+    Injected(Box<InjectedFault>),
 }
 
 impl fmt::Display for ServerError {
@@ -67,6 +86,12 @@ impl fmt::Display for ServerError {
                 write!(f, "connection failed for session {}: {}", session_id, reason)
             },
             Self::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Self::RateLimited { reason, retry_after } => {
+                write!(f, "rate limited: {} (retry after {:?})", reason, retry_after)
+            },
+            Self::Injected(injected) => {
+                write!(f, "injected {:?} at {:?}", injected.fault, injected.point)
+            },
         }
     }
 }
@@ -81,6 +106,32 @@ impl std::error::Error for ServerError {
     }
 }
 
+/// An event in a batch failed.
+///
+/// Carries the actions of the events before it, which have already changed
+/// driver state and still need executing.
+#[derive(Debug)]
+pub struct BatchError {
+    /// Actions produced before the failing event, in order.
+    pub actions: Vec<ServerAction>,
+    /// Position of the failing event in the batch.
+    pub failed_at: usize,
+    /// Why the event failed.
+    pub error: ServerError,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "event {} of batch failed: {}", self.failed_at, self.error)
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
 impl From<RoomError> for ServerError {
     fn from(err: RoomError) -> Self {
         Self::Room(err)
@@ -93,6 +144,20 @@ impl From<StorageError> for ServerError {
     }
 }
 
+impl From<SyncDenied> for ServerError {
+    fn from(denied: SyncDenied) -> Self {
+        match denied {
+            SyncDenied::BudgetExhausted { retry_after } => {
+                Self::RateLimited { reason: "sync budget exhausted".to_string(), retry_after }
+            },
+            SyncDenied::TooManyConcurrent { limit } => Self::RateLimited {
+                reason: format!("more than {} concurrent syncs", limit),
+                retry_after: Duration::from_secs(1),
+            },
+        }
+    }
+}
+
 impl From<lockframe_proto::ProtocolError> for ServerError {
     fn from(err: lockframe_proto::ProtocolError) -> Self {
         Self::Protocol(err.to_string())
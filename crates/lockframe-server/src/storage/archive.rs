@@ -0,0 +1,916 @@
+//! Cold-tier archival to an object store
+//!
+//! [`ArchivedStorage`] keeps the most recent frames of each room in a hot
+//! storage and moves older ones, a chunk at a time, to an [`ObjectStore`].
+//! Once a chunk is safely in the object store the hot storage is snapshotted
+//! and compacted below it. Reads of old ranges (sync from far behind) are
+//! served from the archived chunks transparently, so callers see one log.
+//!
+//! [`ObjectStore`] mirrors the three S3 calls the tier needs: put, get and
+//! delete of whole objects by key. [`FsObjectStore`] lays objects out as
+//! files under a directory, which works for local disks and mounted buckets;
+//! a client for a remote S3-compatible service only has to implement the
+//! trait.
+//!
+//! # Object layout
+//!
+//! ```text
+//! rooms/<room_id hex>/manifest             chunk list, snapshot, first index
+//! rooms/<room_id hex>/chunks/<first index> frames of one chunk
+//! ```
+//!
+//! Chunks are written before the manifest that references them, and the
+//! manifest is written before the hot storage is compacted, so a crash at any
+//! point leaves every frame readable from one of the tiers. A crash can leave
+//! orphaned chunk objects behind; they are overwritten when the range is
+//! archived again.
+//!
+//! # Snapshots
+//!
+//! The hot storage's own snapshot tracks the archive boundary, so room
+//! snapshots taken through [`Storage::snapshot`] are kept in the manifest
+//! instead. Compacting a room deletes the archived chunks its snapshot covers.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bytes::BytesMut;
+use lockframe_core::mls::MlsGroupState;
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
+
+use super::{RoomSnapshot, Storage, StorageError, check_log_empty, check_snapshot_index};
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
+
+/// Default number of most recent frames per room kept in the hot storage.
+pub const DEFAULT_HOT_FRAMES: u64 = 10_000;
+
+/// Default number of frames per archived chunk.
+pub const DEFAULT_CHUNK_FRAMES: usize = 1024;
+
+/// Object storage holding archived chunks
+///
+/// Keys are `/`-separated paths. Implementations share state across clones,
+/// like [`Storage`] implementations.
+pub trait ObjectStore: Clone + Send + Sync + 'static {
+    /// Store `bytes` under `key`, replacing any existing object
+    fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), StorageError>;
+
+    /// Load the object under `key`. `None` if it does not exist.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Delete the object under `key`. Deleting a missing object succeeds.
+    fn delete(&self, key: &str) -> Result<(), StorageError>;
+}
+
+/// In-memory object store for testing and simulation
+#[derive(Clone, Default)]
+pub struct MemoryObjectStore {
+    objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl MemoryObjectStore {
+    /// Create an empty object store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of stored objects
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn len(&self) -> usize {
+        self.objects.lock().expect("MemoryObjectStore mutex poisoned").len()
+    }
+
+    /// Whether the store holds no objects
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ObjectStore for MemoryObjectStore {
+    fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), StorageError> {
+        self.objects
+            .lock()
+            .expect("MemoryObjectStore mutex poisoned")
+            .insert(key.to_string(), bytes);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.objects.lock().expect("MemoryObjectStore mutex poisoned").get(key).cloned())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.objects.lock().expect("MemoryObjectStore mutex poisoned").remove(key);
+        Ok(())
+    }
+}
+
+/// Object store backed by files under a directory
+///
+/// Each object is one file at its key's path. Writes go to a temporary file
+/// that is renamed into place, so readers never see a partial object.
+#[derive(Debug, Clone)]
+pub struct FsObjectStore {
+    root: PathBuf,
+}
+
+impl FsObjectStore {
+    /// Open the store rooted at `root`, creating the directory if missing
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, StorageError> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(Self { root: root.as_ref().to_path_buf() })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl ObjectStore for FsObjectStore {
+    fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), StorageError> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bytes)?;
+        fs::File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match fs::read(self.path(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Archival thresholds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveConfig {
+    /// Most recent frames per room kept in the hot storage
+    pub hot_frames: u64,
+    /// Frames per archived chunk
+    pub chunk_frames: usize,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self { hot_frames: DEFAULT_HOT_FRAMES, chunk_frames: DEFAULT_CHUNK_FRAMES }
+    }
+}
+
+/// Archived range of a room's log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Chunk {
+    /// Log index of the first frame in the chunk
+    first: u64,
+    /// Number of frames in the chunk
+    frames: u64,
+}
+
+impl Chunk {
+    fn end(&self) -> u64 {
+        self.first.saturating_add(self.frames)
+    }
+}
+
+/// Per-room archive state, persisted as the room's manifest object
+#[derive(Debug, Clone, Default)]
+struct Manifest {
+    /// Archived chunks in log order
+    chunks: Vec<Chunk>,
+    /// Oldest log index still readable, non-zero after compaction
+    first_index: u64,
+    /// Latest room snapshot
+    snapshot: Option<RoomSnapshot>,
+}
+
+impl Manifest {
+    /// First log index held by the hot storage
+    fn hot_first(&self) -> u64 {
+        self.chunks.last().map_or(0, Chunk::end).max(self.first_index)
+    }
+}
+
+/// Encoded form of [`Manifest`]: chunks, first index, snapshot
+type ManifestRecord = (Vec<(u64, u64)>, u64, Option<(u64, Option<MlsGroupState>)>);
+
+/// Cached manifest of one room, locked while the room is written or archived
+type RoomManifest = Arc<Mutex<Manifest>>;
+
+/// Storage that archives old frames to an object store
+///
+/// Manifests are cached after first use, each behind a lock of its own, so
+/// object store calls for one room never wait on another room. The cache
+/// itself is only locked to look a room up. The wrapper panics if either
+/// mutex is poisoned.
+#[derive(Clone)]
+pub struct ArchivedStorage<S: Storage, O: ObjectStore> {
+    hot: S,
+    cold: O,
+    config: ArchiveConfig,
+    manifests: Arc<Mutex<HashMap<u128, RoomManifest>>>,
+}
+
+impl<S: Storage, O: ObjectStore> ArchivedStorage<S, O> {
+    /// Archive frames from `hot` into `cold` with default thresholds
+    pub fn new(hot: S, cold: O) -> Self {
+        Self::with_config(hot, cold, ArchiveConfig::default())
+    }
+
+    /// Archive frames from `hot` into `cold` with custom thresholds
+    pub fn with_config(hot: S, cold: O, config: ArchiveConfig) -> Self {
+        let config = ArchiveConfig { chunk_frames: config.chunk_frames.max(1), ..config };
+        Self { hot, cold, config, manifests: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Hot storage holding recent frames
+    pub fn hot(&self) -> &S {
+        &self.hot
+    }
+
+    /// Object store holding archived chunks
+    pub fn cold(&self) -> &O {
+        &self.cold
+    }
+
+    /// First log index of `room_id` still in the hot storage
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn hot_first_index(&self, room_id: u128) -> Result<u64, StorageError> {
+        let room = self.manifest(room_id)?;
+        let manifest = room.lock().expect("ArchivedStorage mutex poisoned");
+        Ok(manifest.hot_first())
+    }
+
+    /// Move full chunks beyond the hot window of `room_id` to the object store
+    ///
+    /// Runs after every stored frame; call it directly to retry after an
+    /// object store failure. Returns how many frames were archived.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn archive(&self, room_id: u128) -> Result<u64, StorageError> {
+        let room = self.manifest(room_id)?;
+        let mut manifest = room.lock().expect("ArchivedStorage mutex poisoned");
+        self.archive_room(room_id, &mut manifest)
+    }
+
+    /// Manifest of `room_id`, loaded into the cache if needed.
+    ///
+    /// The object store is read without holding the cache lock; if another
+    /// caller cached the room meanwhile, its manifest wins.
+    fn manifest(&self, room_id: u128) -> Result<RoomManifest, StorageError> {
+        if let Some(room) =
+            self.manifests.lock().expect("ArchivedStorage mutex poisoned").get(&room_id)
+        {
+            return Ok(Arc::clone(room));
+        }
+
+        let manifest = match self.cold.get(&manifest_key(room_id))? {
+            Some(bytes) => decode_manifest(room_id, &bytes)?,
+            None => Manifest::default(),
+        };
+        let mut manifests = self.manifests.lock().expect("ArchivedStorage mutex poisoned");
+        Ok(Arc::clone(manifests.entry(room_id).or_insert_with(|| Arc::new(Mutex::new(manifest)))))
+    }
+
+    fn archive_room(&self, room_id: u128, manifest: &mut Manifest) -> Result<u64, StorageError> {
+        let Some(latest) = self.hot.latest_log_index(room_id)? else {
+            return Ok(0);
+        };
+        let end = latest.saturating_add(1);
+        let chunk_frames = self.config.chunk_frames as u64;
+        let threshold = self.config.hot_frames.saturating_add(chunk_frames);
+
+        let mut archived = manifest.clone();
+        while end.saturating_sub(archived.hot_first()) >= threshold {
+            let first = archived.hot_first();
+            let frames = self.hot.load_frames(room_id, first, self.config.chunk_frames)?;
+            self.cold.put(&chunk_key(room_id, first), encode_chunk(&frames)?)?;
+            archived.chunks.push(Chunk { first, frames: frames.len() as u64 });
+        }
+
+        let moved = archived.hot_first().saturating_sub(manifest.hot_first());
+        if moved == 0 {
+            return Ok(0);
+        }
+
+        self.cold.put(&manifest_key(room_id), encode_manifest(&archived)?)?;
+        *manifest = archived;
+
+        self.hot.snapshot(room_id, manifest.hot_first().saturating_sub(1))?;
+        self.hot.compact(room_id)?;
+        Ok(moved)
+    }
+
+    /// Load `[from, from+limit)` from archived chunks, stopping at the hot
+    /// boundary.
+    fn load_cold(
+        &self,
+        room_id: u128,
+        manifest: &Manifest,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<Frame>, StorageError> {
+        let mut frames = Vec::new();
+        let mut index = from;
+
+        for chunk in manifest.chunks.iter().filter(|chunk| chunk.end() > from) {
+            if frames.len() >= limit {
+                break;
+            }
+            let bytes = self
+                .cold
+                .get(&chunk_key(room_id, chunk.first))?
+                .ok_or(StorageError::Archived { room_id, log_index: index })?;
+            let skip = usize::try_from(index.saturating_sub(chunk.first)).unwrap_or(usize::MAX);
+            let wanted = limit.saturating_sub(frames.len());
+            frames.extend(decode_chunk(&bytes)?.into_iter().skip(skip).take(wanted));
+            index = chunk.end();
+        }
+
+        Ok(frames)
+    }
+}
+
+impl<S: Storage, O: ObjectStore> Storage for ArchivedStorage<S, O> {
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_frame(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+    ) -> Result<(), StorageError> {
+        let room = self.manifest(room_id)?;
+        let mut manifest = room.lock().expect("ArchivedStorage mutex poisoned");
+        self.hot.store_frame(room_id, log_index, frame)?;
+
+        // The frame is stored; archival is retried on the next write if the
+        // object store is unavailable.
+        let _ = self.archive_room(room_id, &mut manifest);
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        let room = self.manifest(room_id)?;
+        let mut manifest = room.lock().expect("ArchivedStorage mutex poisoned");
+        self.hot.store_frames_batch(room_id, frames)?;
+
+        let _ = self.archive_room(room_id, &mut manifest);
+        Ok(())
+    }
+
+    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.hot.latest_log_index(room_id)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_frames(
+        &self,
+        room_id: u128,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<Frame>, StorageError> {
+        let manifest =
+            self.manifest(room_id)?.lock().expect("ArchivedStorage mutex poisoned").clone();
+
+        if from < manifest.first_index {
+            return Err(StorageError::Compacted { room_id, first_index: manifest.first_index });
+        }
+        let hot_first = manifest.hot_first();
+        if from >= hot_first {
+            return self.hot.load_frames(room_id, from, limit);
+        }
+
+        let mut frames = self.load_cold(room_id, &manifest, from, limit)?;
+        let remaining = limit.saturating_sub(frames.len());
+        if remaining > 0 {
+            frames.extend(self.hot.load_frames(room_id, hot_first, remaining)?);
+        }
+        Ok(frames)
+    }
+
+    fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError> {
+        self.hot.store_mls_state(room_id, state)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_commit(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+        state: &MlsGroupState,
+    ) -> Result<(), StorageError> {
+        let room = self.manifest(room_id)?;
+        let mut manifest = room.lock().expect("ArchivedStorage mutex poisoned");
+        self.hot.store_commit(room_id, log_index, frame, state)?;
+
+        let _ = self.archive_room(room_id, &mut manifest);
+        Ok(())
+    }
+
+    fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+        self.hot.load_mls_state(room_id)
+    }
+
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        self.hot.store_pending_proposal(room_id, proposal)
+    }
+
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        self.hot.clear_pending_proposals(room_id)
+    }
+
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        self.hot.load_pending_proposals(room_id)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        let room = self.manifest(room_id)?;
+        let mut manifest = room.lock().expect("ArchivedStorage mutex poisoned");
+        let latest = self.hot.latest_log_index(room_id)?;
+        check_snapshot_index(room_id, up_to_index, latest, manifest.snapshot.as_ref())?;
+
+        let snapshot = RoomSnapshot {
+            room_id,
+            log_index: up_to_index,
+            mls_state: self.hot.load_mls_state(room_id)?,
+        };
+        let updated = Manifest { snapshot: Some(snapshot.clone()), ..manifest.clone() };
+        self.cold.put(&manifest_key(room_id), encode_manifest(&updated)?)?;
+        *manifest = updated;
+
+        Ok(snapshot)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        let room = self.manifest(room_id)?;
+        let manifest = room.lock().expect("ArchivedStorage mutex poisoned");
+        Ok(manifest.snapshot.clone())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        let room = self.manifest(room_id)?;
+        let mut manifest = room.lock().expect("ArchivedStorage mutex poisoned");
+        let Some(snapshot) = &manifest.snapshot else {
+            return Ok(0);
+        };
+
+        let first_index = snapshot.first_retained_index();
+        if first_index <= manifest.first_index {
+            return Ok(0);
+        }
+        let dropped = first_index.saturating_sub(manifest.first_index);
+        let hot_first = manifest.hot_first();
+
+        let (deleted, kept): (Vec<Chunk>, Vec<Chunk>) =
+            manifest.chunks.iter().partition(|chunk| chunk.end() <= first_index);
+        let updated = Manifest { chunks: kept, first_index, snapshot: manifest.snapshot.clone() };
+        self.cold.put(&manifest_key(room_id), encode_manifest(&updated)?)?;
+        *manifest = updated;
+
+        // Unreferenced from here on, so a failed delete only leaks the object
+        for chunk in deleted {
+            let _ = self.cold.delete(&chunk_key(room_id, chunk.first));
+        }
+        if first_index > hot_first {
+            self.hot.snapshot(room_id, first_index.saturating_sub(1))?;
+            self.hot.compact(room_id)?;
+        }
+
+        Ok(dropped)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        let room_id = snapshot.room_id;
+        let room = self.manifest(room_id)?;
+        let mut manifest = room.lock().expect("ArchivedStorage mutex poisoned");
+        check_log_empty(self.hot.latest_log_index(room_id)?, snapshot)?;
+
+        self.hot.import_snapshot(snapshot)?;
+        let updated = Manifest {
+            chunks: Vec::new(),
+            first_index: snapshot.first_retained_index(),
+            snapshot: Some(snapshot.clone()),
+        };
+        self.cold.put(&manifest_key(room_id), encode_manifest(&updated)?)?;
+        *manifest = updated;
+
+        Ok(())
+    }
+
+    /// Archiving compacts the hot tier, so its space is what needs
+    /// reclaiming; objects are deleted outright.
+    fn vacuum(&self, max_bytes: u64) -> Result<u64, StorageError> {
+        self.hot.vacuum(max_bytes)
+    }
+
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        self.hot.store_usage(window)
+    }
+
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        self.hot.load_usage()
+    }
+
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        self.hot.store_read_marker(room_id, member_id, up_to_log_index)
+    }
+
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        self.hot.load_read_markers(room_id)
+    }
+
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        self.hot.store_room_moved(room_id, moved)
+    }
+
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        self.hot.load_moved_rooms()
+    }
+
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        self.hot.store_archive_cursor(room_id, next_log_index)
+    }
+
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.hot.load_archive_cursor(room_id)
+    }
+
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        self.hot.store_listing(room_id, listing)
+    }
+
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        self.hot.load_listings()
+    }
+
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        self.hot.store_attachment_chunk(room_id, content_hash, index, bytes)
+    }
+
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        self.hot.load_attachment_chunk(room_id, content_hash, index)
+    }
+
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        self.hot.complete_attachment(room_id, content_hash, attachment)
+    }
+
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        self.hot.load_attachment(room_id, content_hash)
+    }
+
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        self.hot.list_attachments(room_id)
+    }
+
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        self.hot.delete_attachment(room_id, content_hash)
+    }
+
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        self.hot.delete_attachments(room_id)
+    }
+
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        self.hot.append_audit(record)
+    }
+
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        self.hot.load_audit(from_seq, limit)
+    }
+
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        self.hot.latest_audit_seq()
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.hot.flush()
+    }
+
+    fn close(&self) -> Result<(), StorageError> {
+        self.hot.close()
+    }
+}
+
+fn manifest_key(room_id: u128) -> String {
+    format!("rooms/{room_id:032x}/manifest")
+}
+
+fn chunk_key(room_id: u128, first: u64) -> String {
+    format!("rooms/{room_id:032x}/chunks/{first:020}")
+}
+
+fn encode_manifest(manifest: &Manifest) -> Result<Vec<u8>, StorageError> {
+    let record: ManifestRecord = (
+        manifest.chunks.iter().map(|chunk| (chunk.first, chunk.frames)).collect(),
+        manifest.first_index,
+        manifest.snapshot.as_ref().map(|s| (s.log_index, s.mls_state.clone())),
+    );
+    let mut encoded = Vec::new();
+    ciborium::ser::into_writer(&record, &mut encoded)
+        .map_err(|e| StorageError::Serialization(e.to_string()))?;
+    Ok(encoded)
+}
+
+fn decode_manifest(room_id: u128, bytes: &[u8]) -> Result<Manifest, StorageError> {
+    let (chunks, first_index, snapshot): ManifestRecord =
+        ciborium::de::from_reader(bytes).map_err(|e| StorageError::Serialization(e.to_string()))?;
+    Ok(Manifest {
+        chunks: chunks.into_iter().map(|(first, frames)| Chunk { first, frames }).collect(),
+        first_index,
+        snapshot: snapshot.map(|(log_index, mls_state)| RoomSnapshot {
+            room_id,
+            log_index,
+            mls_state,
+        }),
+    })
+}
+
+/// Encode frames as a sequence of `len: u32 | frame: [u8; len]`.
+fn encode_chunk(frames: &[Frame]) -> Result<Vec<u8>, StorageError> {
+    let mut chunk = Vec::new();
+    for frame in frames {
+        let mut encoded = BytesMut::new();
+        frame.encode(&mut encoded).map_err(|e| StorageError::Serialization(e.to_string()))?;
+        let len = u32::try_from(encoded.len())
+            .map_err(|_| StorageError::Serialization("frame too large for chunk".to_string()))?;
+        chunk.extend_from_slice(&len.to_be_bytes());
+        chunk.extend_from_slice(&encoded);
+    }
+    Ok(chunk)
+}
+
+fn decode_chunk(mut bytes: &[u8]) -> Result<Vec<Frame>, StorageError> {
+    let corrupt = || StorageError::Serialization("corrupt archive chunk".to_string());
+    let mut frames = Vec::new();
+
+    while !bytes.is_empty() {
+        let len = bytes.get(..4).and_then(|b| b.try_into().ok()).map(u32::from_be_bytes);
+        let len = len.and_then(|len| usize::try_from(len).ok()).ok_or_else(corrupt)?;
+        let end = len.checked_add(4).ok_or_else(corrupt)?;
+        let encoded = bytes.get(4..end).ok_or_else(corrupt)?;
+        frames
+            .push(Frame::decode(encoded).map_err(|e| StorageError::Serialization(e.to_string()))?);
+        bytes = bytes.get(end..).ok_or_else(corrupt)?;
+    }
+
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    const ROOM: u128 = 0xabcd;
+
+    fn frame(log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(ROOM);
+        header.set_log_index(log_index);
+        Frame::new(header, log_index.to_be_bytes().to_vec())
+    }
+
+    fn indices(frames: &[Frame]) -> Vec<u64> {
+        frames.iter().map(|f| f.header.log_index()).collect()
+    }
+
+    fn archived(cold: MemoryObjectStore) -> ArchivedStorage<MemoryStorage, MemoryObjectStore> {
+        ArchivedStorage::with_config(MemoryStorage::new(), cold, ArchiveConfig {
+            hot_frames: 4,
+            chunk_frames: 3,
+        })
+    }
+
+    #[test]
+    fn old_frames_move_to_cold_tier_and_stay_loadable() {
+        let cold = MemoryObjectStore::new();
+        let storage = archived(cold.clone());
+        for index in 0..12 {
+            storage.store_frame(ROOM, index, &frame(index)).unwrap();
+        }
+
+        // Two chunks archived, the rest hot
+        assert_eq!(storage.hot_first_index(ROOM).unwrap(), 6);
+        assert!(matches!(
+            storage.hot().load_frames(ROOM, 0, 10),
+            Err(StorageError::Compacted { first_index: 6, .. })
+        ));
+        assert_eq!(cold.len(), 3);
+
+        // Reads stitch the tiers together
+        assert_eq!(
+            indices(&storage.load_frames(ROOM, 0, 100).unwrap()),
+            (0..12).collect::<Vec<_>>()
+        );
+        assert_eq!(indices(&storage.load_frames(ROOM, 4, 4).unwrap()), vec![4, 5, 6, 7]);
+        assert_eq!(indices(&storage.load_frames(ROOM, 1, 2).unwrap()), vec![1, 2]);
+        assert_eq!(storage.latest_log_index(ROOM).unwrap(), Some(11));
+
+        // A fresh wrapper recovers the archive from the manifest
+        let reopened = ArchivedStorage::with_config(storage.hot().clone(), cold, ArchiveConfig {
+            hot_frames: 4,
+            chunk_frames: 3,
+        });
+        assert_eq!(indices(&reopened.load_frames(ROOM, 2, 5).unwrap()), vec![2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn compaction_drops_archived_chunks() {
+        let cold = MemoryObjectStore::new();
+        let storage = archived(cold.clone());
+        for index in 0..12 {
+            storage.store_frame(ROOM, index, &frame(index)).unwrap();
+        }
+
+        let snapshot = storage.snapshot(ROOM, 3).unwrap();
+        assert_eq!(storage.load_snapshot(ROOM).unwrap(), Some(snapshot));
+        assert_eq!(storage.compact(ROOM).unwrap(), 4);
+
+        // First chunk deleted; the second is still partially needed
+        assert_eq!(cold.len(), 2);
+        assert_eq!(storage.load_frames(ROOM, 0, 10).unwrap_err(), StorageError::Compacted {
+            room_id: ROOM,
+            first_index: 4
+        });
+        assert_eq!(indices(&storage.load_frames(ROOM, 4, 3).unwrap()), vec![4, 5, 6]);
+
+        // Compacting into the hot tier
+        storage.snapshot(ROOM, 8).unwrap();
+        assert_eq!(storage.compact(ROOM).unwrap(), 5);
+        assert_eq!(cold.len(), 1);
+        assert_eq!(indices(&storage.load_frames(ROOM, 9, 10).unwrap()), vec![9, 10, 11]);
+    }
+
+    /// Object store whose puts under `prefix` wait until released.
+    #[derive(Clone)]
+    struct GatedObjectStore {
+        inner: MemoryObjectStore,
+        prefix: String,
+        blocked: Arc<(Mutex<bool>, std::sync::Condvar)>,
+    }
+
+    impl GatedObjectStore {
+        fn release(&self) {
+            let (blocked, released) = &*self.blocked;
+            *blocked.lock().unwrap() = false;
+            released.notify_all();
+        }
+    }
+
+    impl ObjectStore for GatedObjectStore {
+        fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), StorageError> {
+            if key.starts_with(&self.prefix) {
+                let (blocked, released) = &*self.blocked;
+                drop(released.wait_while(blocked.lock().unwrap(), |blocked| *blocked).unwrap());
+            }
+            self.inner.put(key, bytes)
+        }
+
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+            self.inner.get(key)
+        }
+
+        fn delete(&self, key: &str) -> Result<(), StorageError> {
+            self.inner.delete(key)
+        }
+    }
+
+    #[test]
+    fn slow_archival_of_one_room_does_not_block_others() {
+        const OTHER: u128 = 0x1234;
+        let cold = GatedObjectStore {
+            inner: MemoryObjectStore::new(),
+            prefix: format!("rooms/{ROOM:032x}"),
+            blocked: Arc::new((Mutex::new(true), std::sync::Condvar::new())),
+        };
+        let storage =
+            ArchivedStorage::with_config(MemoryStorage::new(), cold.clone(), ArchiveConfig {
+                hot_frames: 0,
+                chunk_frames: 1,
+            });
+
+        // The first frame of ROOM archives at once and stalls in the store
+        let stalled = storage.clone();
+        let writer = std::thread::spawn(move || stalled.store_frame(ROOM, 0, &frame(0)));
+
+        let (done, finished) = std::sync::mpsc::channel();
+        let other = storage.clone();
+        std::thread::spawn(move || {
+            let result = other
+                .store_frame(OTHER, 0, &frame(0))
+                .and_then(|()| other.load_frames(OTHER, 0, 10));
+            let _ = done.send(result);
+        });
+        let frames = finished.recv_timeout(std::time::Duration::from_secs(10)).unwrap().unwrap();
+        assert_eq!(indices(&frames), vec![0]);
+
+        cold.release();
+        writer.join().unwrap().unwrap();
+        assert_eq!(storage.hot_first_index(ROOM).unwrap(), 1);
+    }
+
+    #[test]
+    fn fs_object_store_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsObjectStore::open(dir.path()).unwrap();
+
+        store.put("rooms/01/chunks/00", b"chunk".to_vec()).unwrap();
+        assert_eq!(store.get("rooms/01/chunks/00").unwrap(), Some(b"chunk".to_vec()));
+        assert_eq!(store.get("rooms/01/manifest").unwrap(), None);
+
+        store.delete("rooms/01/chunks/00").unwrap();
+        store.delete("rooms/01/chunks/00").unwrap();
+        assert_eq!(store.get("rooms/01/chunks/00").unwrap(), None);
+    }
+}
@@ -0,0 +1,583 @@
+//! Runtime storage selection
+//!
+//! The production server picks its storage at startup from configuration.
+//! [`StorageBackend`] names the choice and [`ServerStorage`] dispatches to
+//! whichever implementation was opened.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use lockframe_core::mls::MlsGroupState;
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
+
+use super::{
+    ArchiveConfig, ArchivedStorage, FsObjectStore, MemoryStorage, ObjectStore, RoomSnapshot,
+    S3Config, S3ObjectStore, SledStorage, SqliteStorage, Storage, StorageError, WalStorage,
+};
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
+
+/// Storage selected by the server configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// In-memory storage; everything is lost on restart
+    #[default]
+    Memory,
+    /// Durable sled database in the given directory
+    Sled {
+        /// Database directory, created if missing
+        path: PathBuf,
+    },
+    /// SQLite database file
+    Sqlite {
+        /// Database file, created if missing
+        path: PathBuf,
+    },
+}
+
+impl StorageBackend {
+    /// Open the configured storage
+    pub fn open(&self) -> Result<ServerStorage, StorageError> {
+        match self {
+            Self::Memory => Ok(ServerStorage::Memory(MemoryStorage::new())),
+            Self::Sled { path } => SledStorage::open(path).map(ServerStorage::Sled),
+            Self::Sqlite { path } => SqliteStorage::open(path).map(ServerStorage::Sqlite),
+        }
+    }
+
+    /// Files and directories the storage keeps its data in, including
+    /// SQLite's write-ahead log and shared memory files
+    pub fn paths(&self) -> Vec<PathBuf> {
+        match self {
+            Self::Memory => Vec::new(),
+            Self::Sled { path } => vec![path.clone()],
+            Self::Sqlite { path } => ["", "-wal", "-shm"]
+                .iter()
+                .map(|suffix| {
+                    let mut file = path.clone().into_os_string();
+                    file.push(suffix);
+                    PathBuf::from(file)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Storage opened from a [`StorageBackend`]
+#[derive(Clone)]
+pub enum ServerStorage {
+    /// In-memory storage
+    Memory(MemoryStorage),
+    /// Durable sled storage
+    Sled(SledStorage),
+    /// SQLite storage
+    Sqlite(SqliteStorage),
+    /// Any of the above behind a write-ahead log
+    Wal(Box<WalStorage<Self>>),
+    /// Any of the above as the hot tier, archiving old frames to an object
+    /// store
+    Archived(Box<ArchivedStorage<Self, ArchiveStore>>),
+}
+
+/// Object store the archive tier of a [`ServerStorage`] writes to
+#[derive(Debug, Clone)]
+pub enum ArchiveStore {
+    /// Files under a directory
+    Fs(FsObjectStore),
+    /// Bucket of an S3-compatible service
+    S3(S3ObjectStore),
+}
+
+impl ObjectStore for ArchiveStore {
+    fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), StorageError> {
+        match self {
+            Self::Fs(store) => store.put(key, bytes),
+            Self::S3(store) => store.put(key, bytes),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match self {
+            Self::Fs(store) => store.get(key),
+            Self::S3(store) => store.get(key),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match self {
+            Self::Fs(store) => store.delete(key),
+            Self::S3(store) => store.delete(key),
+        }
+    }
+}
+
+impl ServerStorage {
+    /// Put a write-ahead log at `path` in front of this storage, replaying
+    /// any frames it is missing
+    pub fn with_wal(self, path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        WalStorage::open(self, path).map(|wal| Self::Wal(Box::new(wal)))
+    }
+
+    /// Keep the most recent frames in this storage and archive older ones to
+    /// an object store rooted at `dir`
+    pub fn with_archive(
+        self,
+        dir: impl AsRef<Path>,
+        config: ArchiveConfig,
+    ) -> Result<Self, StorageError> {
+        let cold = ArchiveStore::Fs(FsObjectStore::open(dir)?);
+        Ok(Self::Archived(Box::new(ArchivedStorage::with_config(self, cold, config))))
+    }
+
+    /// Keep the most recent frames in this storage and archive older ones to
+    /// the S3 bucket `s3` names
+    pub fn with_s3_archive(
+        self,
+        s3: S3Config,
+        config: ArchiveConfig,
+    ) -> Result<Self, StorageError> {
+        let cold = ArchiveStore::S3(S3ObjectStore::new(s3)?);
+        Ok(Self::Archived(Box::new(ArchivedStorage::with_config(self, cold, config))))
+    }
+}
+
+impl Storage for ServerStorage {
+    fn store_frame(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+    ) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_frame(room_id, log_index, frame),
+            Self::Sled(storage) => storage.store_frame(room_id, log_index, frame),
+            Self::Sqlite(storage) => storage.store_frame(room_id, log_index, frame),
+            Self::Wal(storage) => storage.store_frame(room_id, log_index, frame),
+            Self::Archived(storage) => storage.store_frame(room_id, log_index, frame),
+        }
+    }
+
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_frames_batch(room_id, frames),
+            Self::Sled(storage) => storage.store_frames_batch(room_id, frames),
+            Self::Sqlite(storage) => storage.store_frames_batch(room_id, frames),
+            Self::Wal(storage) => storage.store_frames_batch(room_id, frames),
+            Self::Archived(storage) => storage.store_frames_batch(room_id, frames),
+        }
+    }
+
+    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.latest_log_index(room_id),
+            Self::Sled(storage) => storage.latest_log_index(room_id),
+            Self::Sqlite(storage) => storage.latest_log_index(room_id),
+            Self::Wal(storage) => storage.latest_log_index(room_id),
+            Self::Archived(storage) => storage.latest_log_index(room_id),
+        }
+    }
+
+    fn load_frames(
+        &self,
+        room_id: u128,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<Frame>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_frames(room_id, from, limit),
+            Self::Sled(storage) => storage.load_frames(room_id, from, limit),
+            Self::Sqlite(storage) => storage.load_frames(room_id, from, limit),
+            Self::Wal(storage) => storage.load_frames(room_id, from, limit),
+            Self::Archived(storage) => storage.load_frames(room_id, from, limit),
+        }
+    }
+
+    fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_mls_state(room_id, state),
+            Self::Sled(storage) => storage.store_mls_state(room_id, state),
+            Self::Sqlite(storage) => storage.store_mls_state(room_id, state),
+            Self::Wal(storage) => storage.store_mls_state(room_id, state),
+            Self::Archived(storage) => storage.store_mls_state(room_id, state),
+        }
+    }
+
+    fn store_commit(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+        state: &MlsGroupState,
+    ) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_commit(room_id, log_index, frame, state),
+            Self::Sled(storage) => storage.store_commit(room_id, log_index, frame, state),
+            Self::Sqlite(storage) => storage.store_commit(room_id, log_index, frame, state),
+            Self::Wal(storage) => storage.store_commit(room_id, log_index, frame, state),
+            Self::Archived(storage) => storage.store_commit(room_id, log_index, frame, state),
+        }
+    }
+
+    fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_mls_state(room_id),
+            Self::Sled(storage) => storage.load_mls_state(room_id),
+            Self::Sqlite(storage) => storage.load_mls_state(room_id),
+            Self::Wal(storage) => storage.load_mls_state(room_id),
+            Self::Archived(storage) => storage.load_mls_state(room_id),
+        }
+    }
+
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_pending_proposal(room_id, proposal),
+            Self::Sled(storage) => storage.store_pending_proposal(room_id, proposal),
+            Self::Sqlite(storage) => storage.store_pending_proposal(room_id, proposal),
+            Self::Wal(storage) => storage.store_pending_proposal(room_id, proposal),
+            Self::Archived(storage) => storage.store_pending_proposal(room_id, proposal),
+        }
+    }
+
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.clear_pending_proposals(room_id),
+            Self::Sled(storage) => storage.clear_pending_proposals(room_id),
+            Self::Sqlite(storage) => storage.clear_pending_proposals(room_id),
+            Self::Wal(storage) => storage.clear_pending_proposals(room_id),
+            Self::Archived(storage) => storage.clear_pending_proposals(room_id),
+        }
+    }
+
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_pending_proposals(room_id),
+            Self::Sled(storage) => storage.load_pending_proposals(room_id),
+            Self::Sqlite(storage) => storage.load_pending_proposals(room_id),
+            Self::Wal(storage) => storage.load_pending_proposals(room_id),
+            Self::Archived(storage) => storage.load_pending_proposals(room_id),
+        }
+    }
+
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.snapshot(room_id, up_to_index),
+            Self::Sled(storage) => storage.snapshot(room_id, up_to_index),
+            Self::Sqlite(storage) => storage.snapshot(room_id, up_to_index),
+            Self::Wal(storage) => storage.snapshot(room_id, up_to_index),
+            Self::Archived(storage) => storage.snapshot(room_id, up_to_index),
+        }
+    }
+
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_snapshot(room_id),
+            Self::Sled(storage) => storage.load_snapshot(room_id),
+            Self::Sqlite(storage) => storage.load_snapshot(room_id),
+            Self::Wal(storage) => storage.load_snapshot(room_id),
+            Self::Archived(storage) => storage.load_snapshot(room_id),
+        }
+    }
+
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.compact(room_id),
+            Self::Sled(storage) => storage.compact(room_id),
+            Self::Sqlite(storage) => storage.compact(room_id),
+            Self::Wal(storage) => storage.compact(room_id),
+            Self::Archived(storage) => storage.compact(room_id),
+        }
+    }
+
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.import_snapshot(snapshot),
+            Self::Sled(storage) => storage.import_snapshot(snapshot),
+            Self::Sqlite(storage) => storage.import_snapshot(snapshot),
+            Self::Wal(storage) => storage.import_snapshot(snapshot),
+            Self::Archived(storage) => storage.import_snapshot(snapshot),
+        }
+    }
+
+    fn vacuum(&self, max_bytes: u64) -> Result<u64, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.vacuum(max_bytes),
+            Self::Sled(storage) => storage.vacuum(max_bytes),
+            Self::Sqlite(storage) => storage.vacuum(max_bytes),
+            Self::Wal(storage) => storage.vacuum(max_bytes),
+            Self::Archived(storage) => storage.vacuum(max_bytes),
+        }
+    }
+
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_usage(window),
+            Self::Sled(storage) => storage.store_usage(window),
+            Self::Sqlite(storage) => storage.store_usage(window),
+            Self::Wal(storage) => storage.store_usage(window),
+            Self::Archived(storage) => storage.store_usage(window),
+        }
+    }
+
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_usage(),
+            Self::Sled(storage) => storage.load_usage(),
+            Self::Sqlite(storage) => storage.load_usage(),
+            Self::Wal(storage) => storage.load_usage(),
+            Self::Archived(storage) => storage.load_usage(),
+        }
+    }
+
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_read_marker(room_id, member_id, up_to_log_index),
+            Self::Sled(storage) => storage.store_read_marker(room_id, member_id, up_to_log_index),
+            Self::Sqlite(storage) => storage.store_read_marker(room_id, member_id, up_to_log_index),
+            Self::Wal(storage) => storage.store_read_marker(room_id, member_id, up_to_log_index),
+            Self::Archived(storage) => {
+                storage.store_read_marker(room_id, member_id, up_to_log_index)
+            },
+        }
+    }
+
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_read_markers(room_id),
+            Self::Sled(storage) => storage.load_read_markers(room_id),
+            Self::Sqlite(storage) => storage.load_read_markers(room_id),
+            Self::Wal(storage) => storage.load_read_markers(room_id),
+            Self::Archived(storage) => storage.load_read_markers(room_id),
+        }
+    }
+
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_room_moved(room_id, moved),
+            Self::Sled(storage) => storage.store_room_moved(room_id, moved),
+            Self::Sqlite(storage) => storage.store_room_moved(room_id, moved),
+            Self::Wal(storage) => storage.store_room_moved(room_id, moved),
+            Self::Archived(storage) => storage.store_room_moved(room_id, moved),
+        }
+    }
+
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_moved_rooms(),
+            Self::Sled(storage) => storage.load_moved_rooms(),
+            Self::Sqlite(storage) => storage.load_moved_rooms(),
+            Self::Wal(storage) => storage.load_moved_rooms(),
+            Self::Archived(storage) => storage.load_moved_rooms(),
+        }
+    }
+
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_archive_cursor(room_id, next_log_index),
+            Self::Sled(storage) => storage.store_archive_cursor(room_id, next_log_index),
+            Self::Sqlite(storage) => storage.store_archive_cursor(room_id, next_log_index),
+            Self::Wal(storage) => storage.store_archive_cursor(room_id, next_log_index),
+            Self::Archived(storage) => storage.store_archive_cursor(room_id, next_log_index),
+        }
+    }
+
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_archive_cursor(room_id),
+            Self::Sled(storage) => storage.load_archive_cursor(room_id),
+            Self::Sqlite(storage) => storage.load_archive_cursor(room_id),
+            Self::Wal(storage) => storage.load_archive_cursor(room_id),
+            Self::Archived(storage) => storage.load_archive_cursor(room_id),
+        }
+    }
+
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.store_listing(room_id, listing),
+            Self::Sled(storage) => storage.store_listing(room_id, listing),
+            Self::Sqlite(storage) => storage.store_listing(room_id, listing),
+            Self::Wal(storage) => storage.store_listing(room_id, listing),
+            Self::Archived(storage) => storage.store_listing(room_id, listing),
+        }
+    }
+
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_listings(),
+            Self::Sled(storage) => storage.load_listings(),
+            Self::Sqlite(storage) => storage.load_listings(),
+            Self::Wal(storage) => storage.load_listings(),
+            Self::Archived(storage) => storage.load_listings(),
+        }
+    }
+
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => {
+                storage.store_attachment_chunk(room_id, content_hash, index, bytes)
+            },
+            Self::Sled(storage) => {
+                storage.store_attachment_chunk(room_id, content_hash, index, bytes)
+            },
+            Self::Sqlite(storage) => {
+                storage.store_attachment_chunk(room_id, content_hash, index, bytes)
+            },
+            Self::Wal(storage) => {
+                storage.store_attachment_chunk(room_id, content_hash, index, bytes)
+            },
+            Self::Archived(storage) => {
+                storage.store_attachment_chunk(room_id, content_hash, index, bytes)
+            },
+        }
+    }
+
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_attachment_chunk(room_id, content_hash, index),
+            Self::Sled(storage) => storage.load_attachment_chunk(room_id, content_hash, index),
+            Self::Sqlite(storage) => storage.load_attachment_chunk(room_id, content_hash, index),
+            Self::Wal(storage) => storage.load_attachment_chunk(room_id, content_hash, index),
+            Self::Archived(storage) => storage.load_attachment_chunk(room_id, content_hash, index),
+        }
+    }
+
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.complete_attachment(room_id, content_hash, attachment),
+            Self::Sled(storage) => storage.complete_attachment(room_id, content_hash, attachment),
+            Self::Sqlite(storage) => storage.complete_attachment(room_id, content_hash, attachment),
+            Self::Wal(storage) => storage.complete_attachment(room_id, content_hash, attachment),
+            Self::Archived(storage) => {
+                storage.complete_attachment(room_id, content_hash, attachment)
+            },
+        }
+    }
+
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_attachment(room_id, content_hash),
+            Self::Sled(storage) => storage.load_attachment(room_id, content_hash),
+            Self::Sqlite(storage) => storage.load_attachment(room_id, content_hash),
+            Self::Wal(storage) => storage.load_attachment(room_id, content_hash),
+            Self::Archived(storage) => storage.load_attachment(room_id, content_hash),
+        }
+    }
+
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.list_attachments(room_id),
+            Self::Sled(storage) => storage.list_attachments(room_id),
+            Self::Sqlite(storage) => storage.list_attachments(room_id),
+            Self::Wal(storage) => storage.list_attachments(room_id),
+            Self::Archived(storage) => storage.list_attachments(room_id),
+        }
+    }
+
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.delete_attachment(room_id, content_hash),
+            Self::Sled(storage) => storage.delete_attachment(room_id, content_hash),
+            Self::Sqlite(storage) => storage.delete_attachment(room_id, content_hash),
+            Self::Wal(storage) => storage.delete_attachment(room_id, content_hash),
+            Self::Archived(storage) => storage.delete_attachment(room_id, content_hash),
+        }
+    }
+
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.delete_attachments(room_id),
+            Self::Sled(storage) => storage.delete_attachments(room_id),
+            Self::Sqlite(storage) => storage.delete_attachments(room_id),
+            Self::Wal(storage) => storage.delete_attachments(room_id),
+            Self::Archived(storage) => storage.delete_attachments(room_id),
+        }
+    }
+
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.append_audit(record),
+            Self::Sled(storage) => storage.append_audit(record),
+            Self::Sqlite(storage) => storage.append_audit(record),
+            Self::Wal(storage) => storage.append_audit(record),
+            Self::Archived(storage) => storage.append_audit(record),
+        }
+    }
+
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.load_audit(from_seq, limit),
+            Self::Sled(storage) => storage.load_audit(from_seq, limit),
+            Self::Sqlite(storage) => storage.load_audit(from_seq, limit),
+            Self::Wal(storage) => storage.load_audit(from_seq, limit),
+            Self::Archived(storage) => storage.load_audit(from_seq, limit),
+        }
+    }
+
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        match self {
+            Self::Memory(storage) => storage.latest_audit_seq(),
+            Self::Sled(storage) => storage.latest_audit_seq(),
+            Self::Sqlite(storage) => storage.latest_audit_seq(),
+            Self::Wal(storage) => storage.latest_audit_seq(),
+            Self::Archived(storage) => storage.latest_audit_seq(),
+        }
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.flush(),
+            Self::Sled(storage) => storage.flush(),
+            Self::Sqlite(storage) => storage.flush(),
+            Self::Wal(storage) => storage.flush(),
+            Self::Archived(storage) => storage.flush(),
+        }
+    }
+
+    fn close(&self) -> Result<(), StorageError> {
+        match self {
+            Self::Memory(storage) => storage.close(),
+            Self::Sled(storage) => storage.close(),
+            Self::Sqlite(storage) => storage.close(),
+            Self::Wal(storage) => storage.close(),
+            Self::Archived(storage) => storage.close(),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Room backups
+//!
+//! A backup is a CBOR sequence: a header item
+//! `(version, room_id, mls_state, snapshot)`, one byte-string item per frame
+//! in log order, and a null item closing the log. The snapshot is the room's
+//! latest, as `(log_index, mls_state)`; version 1 headers end before it. The
+//! end marker tells a complete backup from one cut short by a full disk.
+//! Frames are written as they are exported, so a dump never holds the room
+//! log in memory. A restore reads the whole backup before storing anything,
+//! so a truncated or corrupt backup leaves the target untouched.
+//!
+//! An export starts at the first retained frame. Restored onto storage with
+//! no log for the room, a compacted room's log starts after its snapshot;
+//! frames the snapshot covers that compaction had not dropped yet are left
+//! out. Restored onto storage that has a log for the room, that log must end
+//! right before the first exported frame.
+
+use std::io::{Read, Write};
+
+use ciborium::Value;
+use lockframe_core::mls::MlsGroupState;
+use lockframe_proto::Frame;
+
+use super::{RoomSnapshot, Storage, StorageError};
+
+/// Frames loaded per storage read while exporting.
+pub const EXPORT_BATCH_FRAMES: usize = 1024;
+
+/// Backup format version.
+const BACKUP_VERSION: u32 = 2;
+
+/// Header item: version, room, MLS state, snapshot
+type HeaderRecord = (u32, u128, Option<MlsGroupState>, Option<SnapshotRecord>);
+
+/// Header item before snapshots were backed up: version, room, MLS state
+type HeaderRecordV1 = (u32, u128, Option<MlsGroupState>);
+
+/// Snapshot in a header: last log index covered, MLS state
+type SnapshotRecord = (u64, Option<MlsGroupState>);
+
+/// What a dump wrote or a restore read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupSummary {
+    /// Room backed up
+    pub room_id: u128,
+    /// Frames in the backup
+    pub frames: u64,
+    /// Whether the backup carries MLS state
+    pub mls_state: bool,
+}
+
+/// Iterator over a room's retained log, returned by [`Storage::export`].
+pub struct Export<'a, S: ?Sized> {
+    storage: &'a S,
+    room_id: u128,
+    next: u64,
+    batch: std::vec::IntoIter<Frame>,
+    done: bool,
+}
+
+impl<'a, S: Storage> Export<'a, S> {
+    pub(super) fn new(storage: &'a S, room_id: u128) -> Self {
+        Self { storage, room_id, next: 0, batch: Vec::new().into_iter(), done: false }
+    }
+
+    fn load_batch(&mut self) -> Result<(), StorageError> {
+        let frames = match self.storage.load_frames(self.room_id, self.next, EXPORT_BATCH_FRAMES) {
+            Err(StorageError::Compacted { first_index, .. }) if first_index > self.next => {
+                self.next = first_index;
+                self.storage.load_frames(self.room_id, self.next, EXPORT_BATCH_FRAMES)?
+            },
+            // A room that never stored a frame exports nothing
+            Err(StorageError::NotFound { .. }) if self.next == 0 => Vec::new(),
+            result => result?,
+        };
+
+        self.done = frames.len() < EXPORT_BATCH_FRAMES;
+        self.next = self.next.saturating_add(frames.len() as u64);
+        self.batch = frames.into_iter();
+        Ok(())
+    }
+}
+
+impl<S: Storage> Iterator for Export<'_, S> {
+    type Item = Result<Frame, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(frame) = self.batch.next() {
+                return Some(Ok(frame));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.load_batch() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Write a backup of `room_id`'s retained log, MLS state and snapshot to
+/// `writer`.
+pub fn dump<S: Storage>(
+    storage: &S,
+    room_id: u128,
+    mut writer: impl Write,
+) -> Result<BackupSummary, StorageError> {
+    let mls_state = storage.load_mls_state(room_id)?;
+    let summary_state = mls_state.is_some();
+    let snapshot =
+        storage.load_snapshot(room_id)?.map(|snapshot| (snapshot.log_index, snapshot.mls_state));
+    let header: HeaderRecord = (BACKUP_VERSION, room_id, mls_state, snapshot);
+    write_item(&header, &mut writer)?;
+
+    let mut frames = 0u64;
+    for frame in storage.export(room_id) {
+        let mut encoded = Vec::new();
+        frame?.encode(&mut encoded).map_err(|e| StorageError::Serialization(e.to_string()))?;
+        write_item(&Value::Bytes(encoded), &mut writer)?;
+        frames = frames.saturating_add(1);
+    }
+    write_item(&Value::Null, &mut writer)?;
+    writer.flush()?;
+
+    Ok(BackupSummary { room_id, frames, mls_state: summary_state })
+}
+
+/// Restore a backup written by [`dump`] into `storage`.
+///
+/// Frames go through [`Storage::import`], so the room's log in `storage` must
+/// end right before the first frame of the backup, or be empty. An empty log
+/// of a compacted room is started after the backup's snapshot with
+/// [`Storage::import_snapshot`].
+pub fn restore<S: Storage>(
+    storage: &S,
+    mut reader: impl Read,
+) -> Result<BackupSummary, StorageError> {
+    let (room_id, mls_state, snapshot) = read_header(&mut reader)?;
+    let mut frames = Vec::new();
+    while let Some(frame) = read_frame(&mut reader)? {
+        frames.push(frame);
+    }
+    let read = frames.len() as u64;
+
+    let compacted = frames.first().map_or(true, |frame| frame.header.log_index() > 0);
+    if let Some(snapshot) = snapshot.filter(|_| compacted) {
+        if storage.latest_log_index(room_id)?.is_none() {
+            storage.import_snapshot(&snapshot)?;
+            let first_retained = snapshot.first_retained_index();
+            frames.retain(|frame| frame.header.log_index() >= first_retained);
+        }
+    }
+    storage.import(room_id, frames)?;
+
+    if let Some(state) = &mls_state {
+        storage.store_mls_state(room_id, state)?;
+    }
+
+    Ok(BackupSummary { room_id, frames: read, mls_state: mls_state.is_some() })
+}
+
+/// Room, MLS state and snapshot from a backup's header.
+fn read_header(
+    reader: &mut impl Read,
+) -> Result<(u128, Option<MlsGroupState>, Option<RoomSnapshot>), StorageError> {
+    let header: Value = read_item(reader)?;
+    let version = header
+        .as_array()
+        .and_then(|items| items.first())
+        .and_then(Value::as_integer)
+        .and_then(|version| u32::try_from(version).ok());
+
+    match version {
+        Some(1) => {
+            let (_, room_id, mls_state): HeaderRecordV1 = deserialize_header(&header)?;
+            Ok((room_id, mls_state, None))
+        },
+        Some(BACKUP_VERSION) => {
+            let (_, room_id, mls_state, snapshot): HeaderRecord = deserialize_header(&header)?;
+            let snapshot = snapshot.map(|(log_index, mls_state)| RoomSnapshot {
+                room_id,
+                log_index,
+                mls_state,
+            });
+            Ok((room_id, mls_state, snapshot))
+        },
+        Some(version) => {
+            Err(StorageError::Serialization(format!("unsupported backup version {version}")))
+        },
+        None => Err(StorageError::Serialization("malformed backup header".to_string())),
+    }
+}
+
+fn deserialize_header<T: serde::de::DeserializeOwned>(header: &Value) -> Result<T, StorageError> {
+    header.deserialized().map_err(|e| StorageError::Serialization(e.to_string()))
+}
+
+/// Next frame of a backup; `None` at the end marker.
+fn read_frame(reader: &mut impl Read) -> Result<Option<Frame>, StorageError> {
+    match read_item(reader)? {
+        Value::Null => Ok(None),
+        Value::Bytes(encoded) => Frame::decode(&encoded)
+            .map(Some)
+            .map_err(|e| StorageError::Serialization(e.to_string())),
+        other => Err(StorageError::Serialization(format!("unexpected backup item {other:?}"))),
+    }
+}
+
+fn write_item(item: &impl serde::Serialize, writer: &mut impl Write) -> Result<(), StorageError> {
+    ciborium::ser::into_writer(item, writer).map_err(|e| StorageError::Serialization(e.to_string()))
+}
+
+fn read_item<T: serde::de::DeserializeOwned>(reader: &mut impl Read) -> Result<T, StorageError> {
+    ciborium::de::from_reader(reader).map_err(|e| StorageError::Serialization(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn frame(room_id: u128, log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_log_index(log_index);
+        Frame::new(header, Bytes::from(log_index.to_be_bytes().to_vec()))
+    }
+
+    fn state(room_id: u128) -> MlsGroupState {
+        MlsGroupState::new(room_id, 3, [7u8; 32], vec![1, 2], vec![9; 16])
+    }
+
+    #[test]
+    fn dump_and_restore_round_trip() {
+        let room_id = 0xabcd;
+        let source = MemoryStorage::new();
+        let count = EXPORT_BATCH_FRAMES as u64 + 5;
+        for log_index in 0..count {
+            source.store_frame(room_id, log_index, &frame(room_id, log_index)).unwrap();
+        }
+        source.store_mls_state(room_id, &state(room_id)).unwrap();
+
+        let mut backup = Vec::new();
+        let dumped = dump(&source, room_id, &mut backup).unwrap();
+        assert_eq!(dumped, BackupSummary { room_id, frames: count, mls_state: true });
+
+        let target = MemoryStorage::new();
+        let restored = restore(&target, backup.as_slice()).unwrap();
+        assert_eq!(restored, dumped);
+        assert_eq!(target.latest_log_index(room_id).unwrap(), Some(count - 1));
+        assert_eq!(
+            target.load_frames(room_id, 0, usize::MAX).unwrap(),
+            source.load_frames(room_id, 0, usize::MAX).unwrap()
+        );
+        assert_eq!(target.load_mls_state(room_id).unwrap(), Some(state(room_id)));
+    }
+
+    #[test]
+    fn export_skips_compacted_frames() {
+        let room_id = 1;
+        let storage = MemoryStorage::new();
+        for log_index in 0..10 {
+            storage.store_frame(room_id, log_index, &frame(room_id, log_index)).unwrap();
+        }
+        storage.snapshot(room_id, 3).unwrap();
+        storage.compact(room_id).unwrap();
+
+        let indices: Vec<u64> =
+            storage.export(room_id).map(|frame| frame.unwrap().header.log_index()).collect();
+        assert_eq!(indices, (4..10).collect::<Vec<_>>());
+        assert_eq!(storage.export(2).count(), 0);
+    }
+
+    #[test]
+    fn truncated_backup_is_rejected() {
+        let room_id = 5;
+        let source = MemoryStorage::new();
+        for log_index in 0..3 {
+            source.store_frame(room_id, log_index, &frame(room_id, log_index)).unwrap();
+        }
+
+        let mut backup = Vec::new();
+        dump(&source, room_id, &mut backup).unwrap();
+        backup.pop();
+
+        let target = MemoryStorage::new();
+        assert!(matches!(restore(&target, backup.as_slice()), Err(StorageError::Serialization(_))));
+        assert_eq!(target.latest_log_index(room_id).unwrap(), None);
+    }
+
+    #[test]
+    fn compacted_room_restores_onto_empty_storage() {
+        let room_id = 9;
+        let source = MemoryStorage::new();
+        for log_index in 0..10 {
+            source.store_frame(room_id, log_index, &frame(room_id, log_index)).unwrap();
+        }
+        source.store_mls_state(room_id, &state(room_id)).unwrap();
+        source.snapshot(room_id, 3).unwrap();
+        source.compact(room_id).unwrap();
+
+        let mut backup = Vec::new();
+        dump(&source, room_id, &mut backup).unwrap();
+
+        let target = MemoryStorage::new();
+        let restored = restore(&target, backup.as_slice()).unwrap();
+        assert_eq!(restored.frames, 6);
+        assert_eq!(target.load_snapshot(room_id).unwrap(), source.load_snapshot(room_id).unwrap());
+        assert_eq!(target.latest_log_index(room_id).unwrap(), Some(9));
+        assert_eq!(
+            target.load_frames(room_id, 4, usize::MAX).unwrap(),
+            source.load_frames(room_id, 4, usize::MAX).unwrap()
+        );
+        assert!(matches!(
+            target.load_frames(room_id, 0, 1),
+            Err(StorageError::Compacted { first_index: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn version_1_backups_restore() {
+        let room_id = 3;
+        let mut backup = Vec::new();
+        let header: HeaderRecordV1 = (1, room_id, Some(state(room_id)));
+        write_item(&header, &mut backup).unwrap();
+        for log_index in 0..2 {
+            let mut encoded = Vec::new();
+            frame(room_id, log_index).encode(&mut encoded).unwrap();
+            write_item(&Value::Bytes(encoded), &mut backup).unwrap();
+        }
+        write_item(&Value::Null, &mut backup).unwrap();
+
+        let target = MemoryStorage::new();
+        let restored = restore(&target, backup.as_slice()).unwrap();
+        assert_eq!(restored, BackupSummary { room_id, frames: 2, mls_state: true });
+        assert_eq!(target.latest_log_index(room_id).unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,585 @@
+//! Read-through cache for recently sequenced frames
+//!
+//! Wraps a persistent [`Storage`] and keeps the tail of each active room's log
+//! in memory. Writes go through to the inner storage first and are cached
+//! only once persisted. Sync requests for the recent tail, the common case
+//! after a brief disconnect, are answered from the cache; anything older falls
+//! through to the inner storage.
+//!
+//! Each room keeps at most `max_frames_per_room` of its newest frames. When
+//! the total across rooms exceeds `max_frames`, the least recently used
+//! room's tail is dropped.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use lockframe_core::mls::MlsGroupState;
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
+
+use super::{RoomSnapshot, Storage, StorageError};
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
+
+/// Default maximum frames cached across all rooms.
+pub const DEFAULT_CACHE_FRAMES: usize = 65_536;
+
+/// Default maximum frames cached per room.
+pub const DEFAULT_CACHE_FRAMES_PER_ROOM: usize = 1024;
+
+/// Cache capacity limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Maximum frames cached across all rooms
+    pub max_frames: usize,
+    /// Maximum frames cached per room (newest kept)
+    pub max_frames_per_room: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_frames: DEFAULT_CACHE_FRAMES,
+            max_frames_per_room: DEFAULT_CACHE_FRAMES_PER_ROOM,
+        }
+    }
+}
+
+/// Cache hit and miss counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Reads answered from the cache
+    pub hits: u64,
+    /// Reads passed to the inner storage
+    pub misses: u64,
+}
+
+/// Read-through cache over another storage
+///
+/// Assumes it is the only writer to the inner storage, so a cached tail always
+/// ends at the room's latest frame. Cache state is shared via `Arc<Mutex<>>`
+/// and the wrapper panics if that mutex is poisoned.
+#[derive(Clone)]
+pub struct CachedStorage<S: Storage> {
+    inner: S,
+    cache: Arc<Mutex<FrameCache>>,
+}
+
+struct FrameCache {
+    config: CacheConfig,
+
+    /// Cached tail per room
+    rooms: HashMap<u128, RoomTail>,
+
+    /// Frames cached across all rooms
+    total: usize,
+
+    /// Access counter for least recently used eviction
+    clock: u64,
+
+    stats: CacheStats,
+}
+
+/// Newest frames of one room. Never empty.
+struct RoomTail {
+    /// Log index of the first cached frame
+    first_index: u64,
+    frames: VecDeque<Frame>,
+    /// Clock value at the last access
+    last_used: u64,
+}
+
+impl RoomTail {
+    fn end_index(&self) -> u64 {
+        self.first_index.saturating_add(self.frames.len() as u64)
+    }
+}
+
+impl FrameCache {
+    fn tick(&mut self) -> u64 {
+        self.clock = self.clock.saturating_add(1);
+        self.clock
+    }
+
+    /// Frames `[from, from+limit)` if `from` is within or just past the
+    /// cached tail.
+    fn load(&mut self, room_id: u128, from: u64, limit: usize) -> Option<Vec<Frame>> {
+        let now = self.tick();
+        let tail = self.rooms.get_mut(&room_id).filter(|tail| from >= tail.first_index)?;
+        tail.last_used = now;
+
+        let offset = usize::try_from(from - tail.first_index).unwrap_or(usize::MAX);
+        Some(tail.frames.iter().skip(offset).take(limit).cloned().collect())
+    }
+
+    fn latest_log_index(&mut self, room_id: u128) -> Option<u64> {
+        let now = self.tick();
+        let tail = self.rooms.get_mut(&room_id)?;
+        tail.last_used = now;
+        tail.end_index().checked_sub(1)
+    }
+
+    /// Cache a frame that was just persisted at `log_index`.
+    fn insert(&mut self, room_id: u128, log_index: u64, frame: &Frame) {
+        let now = self.tick();
+        let max_per_room = self.config.max_frames_per_room;
+
+        let tail = self.rooms.entry(room_id).or_insert_with(|| RoomTail {
+            first_index: log_index,
+            frames: VecDeque::new(),
+            last_used: now,
+        });
+
+        // Stale tail, e.g. after the inner storage was written directly
+        if tail.end_index() != log_index {
+            self.total = self.total.saturating_sub(tail.frames.len());
+            tail.frames.clear();
+            tail.first_index = log_index;
+        }
+
+        tail.frames.push_back(frame.clone());
+        tail.last_used = now;
+        self.total = self.total.saturating_add(1);
+
+        while tail.frames.len() > max_per_room {
+            tail.frames.pop_front();
+            tail.first_index = tail.first_index.saturating_add(1);
+            self.total = self.total.saturating_sub(1);
+        }
+
+        self.evict();
+    }
+
+    /// Forget a room's cached tail.
+    fn remove(&mut self, room_id: u128) {
+        if let Some(tail) = self.rooms.remove(&room_id) {
+            self.total = self.total.saturating_sub(tail.frames.len());
+        }
+    }
+
+    /// Drop least recently used rooms until within `max_frames`.
+    fn evict(&mut self) {
+        while self.total > self.config.max_frames {
+            let Some(room_id) = self
+                .rooms
+                .iter()
+                .min_by_key(|(_, tail)| tail.last_used)
+                .map(|(&room_id, _)| room_id)
+            else {
+                break;
+            };
+
+            self.remove(room_id);
+        }
+    }
+}
+
+impl<S: Storage> CachedStorage<S> {
+    /// Wrap `inner` with the default cache limits
+    pub fn new(inner: S) -> Self {
+        Self::with_config(inner, CacheConfig::default())
+    }
+
+    /// Wrap `inner` with custom cache limits
+    pub fn with_config(inner: S, config: CacheConfig) -> Self {
+        debug_assert!(config.max_frames_per_room > 0);
+
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(FrameCache {
+                config,
+                rooms: HashMap::new(),
+                total: 0,
+                clock: 0,
+                stats: CacheStats::default(),
+            })),
+        }
+    }
+
+    /// Underlying storage
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Hit and miss counters since creation
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn stats(&self) -> CacheStats {
+        self.cache.lock().expect("CachedStorage mutex poisoned").stats
+    }
+
+    /// Frames currently cached across all rooms
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn cached_frame_count(&self) -> usize {
+        self.cache.lock().expect("CachedStorage mutex poisoned").total
+    }
+}
+
+impl<S: Storage> Storage for CachedStorage<S> {
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_frame(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+    ) -> Result<(), StorageError> {
+        self.inner.store_frame(room_id, log_index, frame)?;
+
+        let mut cache = self.cache.lock().expect("CachedStorage mutex poisoned");
+        cache.insert(room_id, log_index, frame);
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        self.inner.store_frames_batch(room_id, frames)?;
+
+        let mut cache = self.cache.lock().expect("CachedStorage mutex poisoned");
+        for frame in frames {
+            cache.insert(room_id, frame.header.log_index(), frame);
+        }
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        {
+            let mut cache = self.cache.lock().expect("CachedStorage mutex poisoned");
+            if let Some(latest) = cache.latest_log_index(room_id) {
+                cache.stats.hits += 1;
+                return Ok(Some(latest));
+            }
+            cache.stats.misses += 1;
+        }
+
+        self.inner.latest_log_index(room_id)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_frames(
+        &self,
+        room_id: u128,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<Frame>, StorageError> {
+        {
+            let mut cache = self.cache.lock().expect("CachedStorage mutex poisoned");
+            if let Some(frames) = cache.load(room_id, from, limit) {
+                cache.stats.hits += 1;
+                return Ok(frames);
+            }
+            cache.stats.misses += 1;
+        }
+
+        self.inner.load_frames(room_id, from, limit)
+    }
+
+    /// Scrubs the inner storage directly; cached copies would hide damage
+    /// behind them.
+    fn verify(&self, room_id: u128) -> Result<u64, StorageError> {
+        self.inner.verify(room_id)
+    }
+
+    fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError> {
+        self.inner.store_mls_state(room_id, state)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_commit(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+        state: &MlsGroupState,
+    ) -> Result<(), StorageError> {
+        self.inner.store_commit(room_id, log_index, frame, state)?;
+
+        let mut cache = self.cache.lock().expect("CachedStorage mutex poisoned");
+        cache.insert(room_id, log_index, frame);
+
+        Ok(())
+    }
+
+    fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+        self.inner.load_mls_state(room_id)
+    }
+
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        self.inner.store_pending_proposal(room_id, proposal)
+    }
+
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        self.inner.clear_pending_proposals(room_id)
+    }
+
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        self.inner.load_pending_proposals(room_id)
+    }
+
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        self.inner.snapshot(room_id, up_to_index)
+    }
+
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        self.inner.load_snapshot(room_id)
+    }
+
+    /// The room's cached tail is dropped, since it may hold compacted frames.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        let dropped = self.inner.compact(room_id)?;
+        self.cache.lock().expect("CachedStorage mutex poisoned").remove(room_id);
+
+        Ok(dropped)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        self.inner.import_snapshot(snapshot)?;
+        self.cache.lock().expect("CachedStorage mutex poisoned").remove(snapshot.room_id);
+        Ok(())
+    }
+
+    fn vacuum(&self, max_bytes: u64) -> Result<u64, StorageError> {
+        self.inner.vacuum(max_bytes)
+    }
+
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        self.inner.store_usage(window)
+    }
+
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        self.inner.load_usage()
+    }
+
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        self.inner.store_read_marker(room_id, member_id, up_to_log_index)
+    }
+
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        self.inner.load_read_markers(room_id)
+    }
+
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        self.inner.store_room_moved(room_id, moved)
+    }
+
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        self.inner.load_moved_rooms()
+    }
+
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        self.inner.store_archive_cursor(room_id, next_log_index)
+    }
+
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.inner.load_archive_cursor(room_id)
+    }
+
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        self.inner.store_listing(room_id, listing)
+    }
+
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        self.inner.load_listings()
+    }
+
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        self.inner.store_attachment_chunk(room_id, content_hash, index, bytes)
+    }
+
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        self.inner.load_attachment_chunk(room_id, content_hash, index)
+    }
+
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        self.inner.complete_attachment(room_id, content_hash, attachment)
+    }
+
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        self.inner.load_attachment(room_id, content_hash)
+    }
+
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        self.inner.list_attachments(room_id)
+    }
+
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        self.inner.delete_attachment(room_id, content_hash)
+    }
+
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        self.inner.delete_attachments(room_id)
+    }
+
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        self.inner.append_audit(record)
+    }
+
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        self.inner.load_audit(from_seq, limit)
+    }
+
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        self.inner.latest_audit_seq()
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush()
+    }
+
+    fn close(&self) -> Result<(), StorageError> {
+        self.inner.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+    use crate::storage::{ChaoticStorage, MemoryStorage};
+
+    fn create_test_frame(room_id: u128, log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_log_index(log_index);
+
+        Frame::new(header, Bytes::from(format!("frame-{log_index}")))
+    }
+
+    fn store(storage: &impl Storage, room_id: u128, range: std::ops::Range<u64>) {
+        for i in range {
+            storage.store_frame(room_id, i, &create_test_frame(room_id, i)).expect("store failed");
+        }
+    }
+
+    fn indices(frames: &[Frame]) -> Vec<u64> {
+        frames.iter().map(|f| f.header.log_index()).collect()
+    }
+
+    fn small_cache() -> CacheConfig {
+        CacheConfig { max_frames: 8, max_frames_per_room: 4 }
+    }
+
+    #[test]
+    fn test_recent_tail_served_from_cache() {
+        let storage = CachedStorage::with_config(MemoryStorage::new(), small_cache());
+        store(&storage, 100, 0..10);
+
+        assert_eq!(indices(&storage.load_frames(100, 7, 10).expect("load failed")), vec![7, 8, 9]);
+        assert!(storage.load_frames(100, 10, 10).expect("load failed").is_empty());
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(9));
+        assert_eq!(storage.stats(), CacheStats { hits: 3, misses: 0 });
+
+        // Older than the cached tail reads through
+        assert_eq!(indices(&storage.load_frames(100, 4, 3).expect("load failed")), vec![4, 5, 6]);
+        assert_eq!(storage.stats().misses, 1);
+    }
+
+    #[test]
+    fn test_least_recently_used_room_evicted() {
+        let storage = CachedStorage::with_config(MemoryStorage::new(), small_cache());
+        store(&storage, 100, 0..4);
+        store(&storage, 200, 0..4);
+        assert_eq!(storage.cached_frame_count(), 8);
+
+        // Touch room 100 so room 200 is the eviction candidate
+        storage.load_frames(100, 0, 1).expect("load failed");
+        store(&storage, 300, 0..1);
+
+        assert_eq!(storage.cached_frame_count(), 5);
+        storage.load_frames(100, 0, 4).expect("load failed");
+        assert_eq!(storage.stats().misses, 0);
+
+        // Evicted room still loads from the inner storage
+        assert_eq!(storage.load_frames(200, 0, 10).expect("load failed").len(), 4);
+        assert_eq!(storage.stats().misses, 1);
+    }
+
+    #[test]
+    fn test_failed_write_not_cached() {
+        let inner = ChaoticStorage::new(MemoryStorage::new(), 1.0);
+        let storage = CachedStorage::new(inner);
+
+        assert!(storage.store_frame(100, 0, &create_test_frame(100, 0)).is_err());
+        assert_eq!(storage.cached_frame_count(), 0);
+    }
+
+    #[test]
+    fn test_stale_tail_replaced() {
+        let storage = CachedStorage::with_config(MemoryStorage::new(), small_cache());
+        store(&storage, 100, 0..3);
+
+        // Written behind the cache's back
+        store(storage.inner(), 100, 3..6);
+        store(&storage, 100, 6..7);
+
+        assert_eq!(storage.cached_frame_count(), 1);
+        assert_eq!(
+            indices(&storage.load_frames(100, 2, 10).expect("load failed")),
+            (2..7).collect::<Vec<_>>()
+        );
+    }
+}
@@ -2,30 +2,193 @@
 //!
 //! Storage wrapper that randomly fails operations to test error handling and
 //! recovery. Used for chaos testing to ensure the system handles storage
-//! failures gracefully.
+//! failures gracefully. A [`FaultProfile`] selects which faults are injected
+//! and seeds the schedule they follow.
 
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use lockframe_core::mls::MlsGroupState;
-use lockframe_proto::Frame;
+use lockframe_core::{env::Environment, mls::MlsGroupState};
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
 
-use super::{Storage, StorageError};
+use super::{RoomSnapshot, Storage, StorageError};
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
+
+/// Seed used when none is given.
+const DEFAULT_SEED: u64 = 0x1234_5678_9ABC_DEF0;
+
+/// Which faults [`ChaoticStorage`] injects, and how often.
+///
+/// Every probability is drawn from one RNG seeded by the profile, so the
+/// same profile over the same sequence of operations produces the same
+/// failure schedule. A fault that is not configured never consumes a draw.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use lockframe_server::storage::FaultProfile;
+/// let profile = FaultProfile::new(42)
+///     .with_write_failures(0.1)
+///     .with_partial_writes(0.05)
+///     .with_latency_spikes(0.01, Duration::from_millis(5))
+///     .with_fail_after(1_000);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultProfile {
+    seed: u64,
+    write_failure_rate: f64,
+    read_failure_rate: f64,
+    read_corruption_rate: f64,
+    latency_spike_rate: f64,
+    latency_spike: Duration,
+    partial_write_rate: f64,
+    fail_after: Option<usize>,
+}
+
+impl FaultProfile {
+    /// Profile injecting no faults, drawing from `seed`.
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            write_failure_rate: 0.0,
+            read_failure_rate: 0.0,
+            read_corruption_rate: 0.0,
+            latency_spike_rate: 0.0,
+            latency_spike: Duration::ZERO,
+            partial_write_rate: 0.0,
+            fail_after: None,
+        }
+    }
+
+    /// Profile failing every operation with probability `failure_rate`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if failure_rate is not in [0.0, 1.0]
+    #[must_use]
+    pub fn uniform(seed: u64, failure_rate: f64) -> Self {
+        Self::new(seed).with_write_failures(failure_rate).with_read_failures(failure_rate)
+    }
+
+    /// Fail writes (frames, MLS state, proposals, snapshots, compaction,
+    /// vacuum) with probability `rate`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if rate is not in [0.0, 1.0]
+    #[must_use]
+    pub fn with_write_failures(mut self, rate: f64) -> Self {
+        self.write_failure_rate = checked_rate("write_failure_rate", rate);
+        self
+    }
+
+    /// Fail reads with probability `rate`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if rate is not in [0.0, 1.0]
+    #[must_use]
+    pub fn with_read_failures(mut self, rate: f64) -> Self {
+        self.read_failure_rate = checked_rate("read_failure_rate", rate);
+        self
+    }
+
+    /// Flip one payload bit of a loaded frame with probability `rate` per
+    /// successful `load_frames`. The stored frame is left intact.
+    ///
+    /// # Panics
+    ///
+    /// Panics if rate is not in [0.0, 1.0]
+    #[must_use]
+    pub fn with_read_corruption(mut self, rate: f64) -> Self {
+        self.read_corruption_rate = checked_rate("read_corruption_rate", rate);
+        self
+    }
+
+    /// Stall an operation for `duration` with probability `rate`.
+    ///
+    /// Storage calls are synchronous, so the stall does not block the calling
+    /// thread. It is added to the storage's pending stall, which the caller
+    /// waits out on its [`Environment`] with
+    /// [`ChaoticStorage::wait_out_stall`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if rate is not in [0.0, 1.0]
+    #[must_use]
+    pub fn with_latency_spikes(mut self, rate: f64, duration: Duration) -> Self {
+        self.latency_spike_rate = checked_rate("latency_spike_rate", rate);
+        self.latency_spike = duration;
+        self
+    }
+
+    /// Commit only a prefix of a frame batch and then fail, with probability
+    /// `rate` per `store_frames_batch`.
+    ///
+    /// This deliberately breaks the atomicity the [`Storage`] contract
+    /// promises for batches, to exercise recovery from torn writes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if rate is not in [0.0, 1.0]
+    #[must_use]
+    pub fn with_partial_writes(mut self, rate: f64) -> Self {
+        self.partial_write_rate = checked_rate("partial_write_rate", rate);
+        self
+    }
+
+    /// Fail every operation after the first `ops` operations.
+    #[must_use]
+    pub fn with_fail_after(mut self, ops: usize) -> Self {
+        self.fail_after = Some(ops);
+        self
+    }
+
+    /// Seed the failure schedule is drawn from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl Default for FaultProfile {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
+
+fn checked_rate(name: &str, rate: f64) -> f64 {
+    assert!((0.0..=1.0).contains(&rate), "{} must be between 0.0 and 1.0, got {}", name, rate);
+    rate
+}
+
+/// Kind of storage operation, selecting which failure rate applies.
+#[derive(Clone, Copy)]
+enum Op {
+    Read,
+    Write,
+}
 
 /// Chaotic storage wrapper that randomly injects failures
 ///
-/// Delegates to an underlying storage implementation but randomly fails
-/// operations based on a configured failure rate. Used for chaos testing to
-/// verify error handling. Uses Arc<Mutex<>> for the RNG state, making it Clone
-/// and thread-safe.
+/// Delegates to an underlying storage implementation but injects the faults
+/// of its [`FaultProfile`]. Used for chaos testing to verify error handling.
+/// Uses Arc<Mutex<>> for the RNG state, making it Clone and thread-safe.
 #[derive(Clone)]
 pub struct ChaoticStorage<S: Storage> {
     inner: S,
-    /// Failure rate (0.0 = never fail, 1.0 = always fail)
-    failure_rate: f64,
+    /// Faults to inject
+    profile: FaultProfile,
     /// RNG state for deterministic chaos
     rng: Arc<Mutex<ChaoticRng>>,
     /// Operation counter for performance testing
     operation_count: Arc<Mutex<usize>>,
+    /// Injected latency the caller has not waited out yet
+    stalled: Arc<Mutex<Duration>>,
 }
 
 /// Simple deterministic RNG for chaos injection
@@ -52,9 +215,15 @@ impl ChaoticRng {
         (self.state as f64) / (M as f64)
     }
 
-    /// Check if we should fail (returns true with probability = failure_rate)
-    fn should_fail(&mut self, failure_rate: f64) -> bool {
-        self.next() < failure_rate
+    /// Returns true with probability = rate, without a draw if rate is zero
+    fn chance(&mut self, rate: f64) -> bool {
+        rate > 0.0 && self.next() < rate
+    }
+
+    /// Uniform index in [0, n)
+    fn below(&mut self, n: usize) -> usize {
+        self.next();
+        usize::try_from(self.state).unwrap_or_default() % n.max(1)
     }
 }
 
@@ -65,28 +234,28 @@ impl<S: Storage> ChaoticStorage<S> {
     ///
     /// Panics if failure_rate is not in [0.0, 1.0]
     pub fn new(inner: S, failure_rate: f64) -> Self {
-        assert!(
-            (0.0..=1.0).contains(&failure_rate),
-            "failure_rate must be between 0.0 and 1.0, got {}",
-            failure_rate
-        );
-
-        Self::with_seed(inner, failure_rate, 0x1234_5678_9ABC_DEF0)
+        Self::with_seed(inner, failure_rate, DEFAULT_SEED)
     }
 
     /// Create with explicit seed for reproducible chaos
+    ///
+    /// # Panics
+    ///
+    /// Panics if failure_rate is not in [0.0, 1.0]
     pub fn with_seed(inner: S, failure_rate: f64, seed: u64) -> Self {
-        assert!(
-            (0.0..=1.0).contains(&failure_rate),
-            "failure_rate must be between 0.0 and 1.0, got {}",
-            failure_rate
-        );
+        checked_rate("failure_rate", failure_rate);
+        Self::with_profile(inner, FaultProfile::uniform(seed, failure_rate))
+    }
 
+    /// Create injecting the faults of `profile`.
+    pub fn with_profile(inner: S, profile: FaultProfile) -> Self {
+        let rng = ChaoticRng::new(profile.seed);
         Self {
             inner,
-            failure_rate,
-            rng: Arc::new(Mutex::new(ChaoticRng::new(seed))),
+            profile,
+            rng: Arc::new(Mutex::new(rng)),
             operation_count: Arc::new(Mutex::new(0)),
+            stalled: Arc::new(Mutex::new(Duration::ZERO)),
         }
     }
 
@@ -95,6 +264,11 @@ impl<S: Storage> ChaoticStorage<S> {
         &self.inner
     }
 
+    /// Faults this storage injects.
+    pub fn profile(&self) -> &FaultProfile {
+        &self.profile
+    }
+
     /// Total number of storage operations attempted.
     ///
     /// Used for performance oracles to verify O(n) complexity.
@@ -103,15 +277,76 @@ impl<S: Storage> ChaoticStorage<S> {
         *self.operation_count.lock().expect("operation_count mutex poisoned")
     }
 
-    /// Increment operation counter
-    fn increment_operation_count(&self) {
+    /// Latency injected since the last call, resetting it to zero.
+    pub fn take_stall(&self) -> Duration {
+        std::mem::take(&mut *self.stalled.lock().expect("stalled mutex poisoned"))
+    }
+
+    /// Sleep on `env` for the latency injected since the last call.
+    ///
+    /// Call after a batch of storage operations, so latency spikes advance
+    /// simulated time instead of blocking the thread.
+    pub async fn wait_out_stall<E: Environment>(&self, env: &E) {
+        let stall = self.take_stall();
+        if !stall.is_zero() {
+            env.sleep(stall).await;
+        }
+    }
+
+    /// Increment operation counter, returning the new count
+    fn increment_operation_count(&self) -> usize {
         let mut count = self.operation_count.lock().expect("operation_count mutex poisoned");
         *count += 1;
+        *count
     }
 
-    /// Check if this operation should fail
-    fn should_fail(&self) -> bool {
-        self.rng.lock().expect("ChaoticRng mutex poisoned").should_fail(self.failure_rate)
+    /// Count an operation and decide whether it fails
+    fn inject(&self, op: Op) -> Result<(), StorageError> {
+        let count = self.increment_operation_count();
+        let (stall, fail) = {
+            let mut rng = self.rng.lock().expect("ChaoticRng mutex poisoned");
+            let stall = rng.chance(self.profile.latency_spike_rate);
+            let rate = match op {
+                Op::Read => self.profile.read_failure_rate,
+                Op::Write => self.profile.write_failure_rate,
+            };
+            (stall, rng.chance(rate))
+        };
+
+        if stall {
+            let mut stalled = self.stalled.lock().expect("stalled mutex poisoned");
+            *stalled = stalled.saturating_add(self.profile.latency_spike);
+        }
+        if self.profile.fail_after.is_some_and(|limit| count > limit) || fail {
+            return Err(StorageError::Io("chaotic failure injection".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Length of the prefix to commit if this batch write is torn
+    fn torn_prefix(&self, len: usize) -> Option<usize> {
+        let mut rng = self.rng.lock().expect("ChaoticRng mutex poisoned");
+        rng.chance(self.profile.partial_write_rate).then(|| rng.below(len))
+    }
+
+    /// Flip one payload bit of a random frame, if the dice say so
+    fn corrupt(&self, frames: &mut [Frame]) {
+        let mut rng = self.rng.lock().expect("ChaoticRng mutex poisoned");
+        if frames.is_empty() || !rng.chance(self.profile.read_corruption_rate) {
+            return;
+        }
+
+        let index = rng.below(frames.len());
+        let Some(frame) = frames.get_mut(index) else {
+            return;
+        };
+        let mut payload = frame.payload.to_vec();
+        let byte = rng.below(payload.len());
+        let bit = rng.below(8);
+        if let Some(byte) = payload.get_mut(byte) {
+            *byte ^= 1 << bit;
+            frame.payload = payload.into();
+        }
     }
 }
 
@@ -122,18 +357,27 @@ impl<S: Storage> Storage for ChaoticStorage<S> {
         log_index: u64,
         frame: &Frame,
     ) -> Result<(), StorageError> {
-        self.increment_operation_count();
-        if self.should_fail() {
-            return Err(StorageError::Io("chaotic failure injection".to_string()));
-        }
+        self.inject(Op::Write)?;
         self.inner.store_frame(room_id, log_index, frame)
     }
 
-    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
-        self.increment_operation_count();
-        if self.should_fail() {
-            return Err(StorageError::Io("chaotic failure injection".to_string()));
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        if let Some(prefix) = self.torn_prefix(frames.len()) {
+            if let Some(committed) = frames.get(..prefix).filter(|c| !c.is_empty()) {
+                self.inner.store_frames_batch(room_id, committed)?;
+            }
+            return Err(StorageError::Io(format!(
+                "chaotic partial write: {} of {} frames",
+                prefix,
+                frames.len()
+            )));
         }
+        self.inner.store_frames_batch(room_id, frames)
+    }
+
+    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.inject(Op::Read)?;
         self.inner.latest_log_index(room_id)
     }
 
@@ -143,28 +387,218 @@ impl<S: Storage> Storage for ChaoticStorage<S> {
         from: u64,
         limit: usize,
     ) -> Result<Vec<Frame>, StorageError> {
-        self.increment_operation_count();
-        if self.should_fail() {
-            return Err(StorageError::Io("chaotic failure injection".to_string()));
-        }
-        self.inner.load_frames(room_id, from, limit)
+        self.inject(Op::Read)?;
+        let mut frames = self.inner.load_frames(room_id, from, limit)?;
+        self.corrupt(&mut frames);
+        Ok(frames)
     }
 
     fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError> {
-        self.increment_operation_count();
-        if self.should_fail() {
-            return Err(StorageError::Io("chaotic failure injection".to_string()));
-        }
+        self.inject(Op::Write)?;
         self.inner.store_mls_state(room_id, state)
     }
 
+    fn store_commit(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+        state: &MlsGroupState,
+    ) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.store_commit(room_id, log_index, frame, state)
+    }
+
     fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
-        self.increment_operation_count();
-        if self.should_fail() {
-            return Err(StorageError::Io("chaotic failure injection".to_string()));
-        }
+        self.inject(Op::Read)?;
         self.inner.load_mls_state(room_id)
     }
+
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.store_pending_proposal(room_id, proposal)
+    }
+
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.clear_pending_proposals(room_id)
+    }
+
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_pending_proposals(room_id)
+    }
+
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.snapshot(room_id, up_to_index)
+    }
+
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_snapshot(room_id)
+    }
+
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.compact(room_id)
+    }
+
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.import_snapshot(snapshot)
+    }
+
+    fn vacuum(&self, max_bytes: u64) -> Result<u64, StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.vacuum(max_bytes)
+    }
+
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.store_usage(window)
+    }
+
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_usage()
+    }
+
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.store_read_marker(room_id, member_id, up_to_log_index)
+    }
+
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_read_markers(room_id)
+    }
+
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.store_room_moved(room_id, moved)
+    }
+
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_moved_rooms()
+    }
+
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.store_archive_cursor(room_id, next_log_index)
+    }
+
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_archive_cursor(room_id)
+    }
+
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.store_listing(room_id, listing)
+    }
+
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_listings()
+    }
+
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.store_attachment_chunk(room_id, content_hash, index, bytes)
+    }
+
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_attachment_chunk(room_id, content_hash, index)
+    }
+
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.complete_attachment(room_id, content_hash, attachment)
+    }
+
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_attachment(room_id, content_hash)
+    }
+
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.list_attachments(room_id)
+    }
+
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.delete_attachment(room_id, content_hash)
+    }
+
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.delete_attachments(room_id)
+    }
+
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.append_audit(record)
+    }
+
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.load_audit(from_seq, limit)
+    }
+
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        self.inject(Op::Read)?;
+        self.inner.latest_audit_seq()
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.flush()
+    }
+
+    fn close(&self) -> Result<(), StorageError> {
+        self.inject(Op::Write)?;
+        self.inner.close()
+    }
 }
 
 #[cfg(test)]
@@ -246,4 +680,102 @@ mod tests {
         let storage = MemoryStorage::new();
         let _chaotic = ChaoticStorage::new(storage, 1.5); // Invalid!
     }
+
+    fn frame_with_payload(room_id: u128, log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_log_index(log_index);
+
+        Frame::new(header, Bytes::from_static(b"payload"))
+    }
+
+    #[test]
+    fn test_profile_fails_after_n_ops() {
+        let chaotic = ChaoticStorage::with_profile(
+            MemoryStorage::new(),
+            FaultProfile::new(7).with_fail_after(3),
+        );
+
+        for i in 0..3 {
+            chaotic.store_frame(100, i, &create_test_frame(100, i)).expect("within budget");
+        }
+        assert!(chaotic.store_frame(100, 3, &create_test_frame(100, 3)).is_err());
+        assert!(chaotic.latest_log_index(100).is_err());
+        assert_eq!(chaotic.inner().latest_log_index(100).expect("query failed"), Some(2));
+    }
+
+    #[test]
+    fn test_profile_partial_write_commits_prefix() {
+        let profile = FaultProfile::new(7).with_partial_writes(1.0);
+        let chaotic = ChaoticStorage::with_profile(MemoryStorage::new(), profile);
+
+        let frames: Vec<_> = (0..10).map(|i| create_test_frame(100, i)).collect();
+        assert!(chaotic.store_frames_batch(100, &frames).is_err());
+
+        // Whatever was committed is a gap-free prefix of the batch
+        let stored = chaotic.inner().load_frames(100, 0, 10).expect("load failed");
+        assert!(stored.len() < frames.len());
+        for (i, frame) in stored.iter().enumerate() {
+            assert_eq!(frame.header.log_index(), i as u64);
+        }
+    }
+
+    #[test]
+    fn test_profile_read_corruption_leaves_store_intact() {
+        let profile = FaultProfile::new(7).with_read_corruption(1.0);
+        let chaotic = ChaoticStorage::with_profile(MemoryStorage::new(), profile);
+        let frame = frame_with_payload(100, 0);
+        chaotic.store_frame(100, 0, &frame).expect("store failed");
+
+        let loaded = chaotic.load_frames(100, 0, 1).expect("load failed");
+        assert_ne!(loaded[0].payload, frame.payload);
+
+        let stored = chaotic.inner().load_frames(100, 0, 1).expect("load failed");
+        assert_eq!(stored[0].payload, frame.payload);
+    }
+
+    #[test]
+    fn test_profile_latency_spikes_accumulate_as_stall() {
+        let spike = Duration::from_millis(5);
+        let profile = FaultProfile::new(7).with_latency_spikes(1.0, spike);
+        let chaotic = ChaoticStorage::with_profile(MemoryStorage::new(), profile);
+
+        chaotic.store_frame(100, 0, &create_test_frame(100, 0)).expect("store failed");
+        chaotic.latest_log_index(100).expect("query failed");
+
+        // Nothing slept inline; the caller owes both spikes, once
+        assert_eq!(chaotic.take_stall(), spike * 2);
+        assert_eq!(chaotic.take_stall(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_profile_schedule_reproducible() {
+        let profile = FaultProfile::new(42)
+            .with_write_failures(0.2)
+            .with_read_failures(0.2)
+            .with_read_corruption(0.3)
+            .with_partial_writes(0.3);
+
+        let run = |profile: FaultProfile| {
+            let chaotic = ChaoticStorage::with_profile(MemoryStorage::new(), profile);
+            let mut outcome = Vec::new();
+            for i in 0..50 {
+                let next = chaotic.inner().latest_log_index(100).expect("query failed");
+                let start = next.map_or(0, |latest| latest + 1);
+                let frames: Vec<_> =
+                    (start..start + 3).map(|i| frame_with_payload(100, i)).collect();
+                outcome.push(format!("{:?}", chaotic.store_frames_batch(100, &frames)));
+                outcome.push(format!("{:?}", chaotic.load_frames(100, i, 3)));
+            }
+            outcome
+        };
+
+        assert_eq!(run(profile.clone()), run(profile));
+    }
+
+    #[test]
+    #[should_panic(expected = "partial_write_rate must be between 0.0 and 1.0")]
+    fn test_profile_rejects_invalid_rate() {
+        let _profile = FaultProfile::new(0).with_partial_writes(-0.1);
+    }
 }
@@ -0,0 +1,558 @@
+//! Encryption of stored MLS group state
+//!
+//! Wraps another [`Storage`] and seals each room's [`MlsGroupState`] with
+//! XChaCha20-Poly1305 before it reaches the inner storage. Frames pass through
+//! untouched; they are already end-to-end encrypted.
+//!
+//! The inner storage only ever sees an opaque envelope: a placeholder state
+//! with the room ID and the sealed bytes, so epoch, membership, and member keys
+//! are not readable at rest. The envelope records the ID of the key that
+//! sealed it and binds the ciphertext to its room, so an envelope copied into
+//! another room fails to open.
+//!
+//! Keys come from a [`StateKeyProvider`], typically backed by an HSM or KMS.
+//! Rotating the provider's current key does not rewrite existing rooms; each
+//! room is re-sealed under the current key the next time its state is loaded.
+//! Plaintext state written before encryption was enabled is sealed the same
+//! way.
+//!
+//! Nonces are drawn from the [`Environment`], and key material and serialized
+//! plaintext are zeroized once used.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use lockframe_core::{env::Environment, mls::MlsGroupState};
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
+use zeroize::Zeroizing;
+
+use super::{RoomSnapshot, Storage, StorageError};
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
+
+/// Marks sealed state
+const ENVELOPE_MAGIC: &[u8; 4] = b"LFSS";
+
+/// Envelope format version
+const ENVELOPE_VERSION: u8 = 1;
+
+/// Magic, version, key ID, nonce
+const ENVELOPE_HEADER_SIZE: usize = 4 + 1 + 4 + 24;
+
+/// Source of state-sealing keys
+///
+/// Implementations front an HSM or KMS. Keys are identified by a `u32` that
+/// is stored with each envelope, so retired keys must stay available until
+/// every room sealed with them has been loaded again. Key material is handed
+/// out in [`Zeroizing`] so each copy is wiped once used.
+pub trait StateKeyProvider: Clone + Send + Sync + 'static {
+    /// ID and material of the key new state is sealed with
+    fn current_key(&self) -> Result<(u32, Zeroizing<[u8; 32]>), StorageError>;
+
+    /// Key material for `key_id`. `None` if the key is unknown.
+    fn key(&self, key_id: u32) -> Result<Option<Zeroizing<[u8; 32]>>, StorageError>;
+}
+
+/// In-process keyring
+///
+/// Suitable for tests and for deployments that load keys from a secrets
+/// manager at startup. Clones share the same keys, which are zeroized when
+/// retired or when the last clone is dropped.
+#[derive(Clone)]
+pub struct StateKeyring {
+    inner: Arc<Mutex<KeyringInner>>,
+}
+
+struct KeyringInner {
+    keys: HashMap<u32, Zeroizing<[u8; 32]>>,
+    current: u32,
+}
+
+impl StateKeyring {
+    /// Create a keyring whose current key is `key` with ID `key_id`
+    pub fn new(key_id: u32, key: [u8; 32]) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(KeyringInner {
+                keys: HashMap::from([(key_id, Zeroizing::new(key))]),
+                current: key_id,
+            })),
+        }
+    }
+
+    /// Add a key and make it current
+    ///
+    /// Existing rooms are re-sealed under it as they are loaded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn rotate(&self, key_id: u32, key: [u8; 32]) {
+        let mut inner = self.inner.lock().expect("StateKeyring mutex poisoned");
+        inner.keys.insert(key_id, Zeroizing::new(key));
+        inner.current = key_id;
+    }
+
+    /// Remove a key that no longer seals any room
+    ///
+    /// Returns `false` if the key is unknown or current.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn retire(&self, key_id: u32) -> bool {
+        let mut inner = self.inner.lock().expect("StateKeyring mutex poisoned");
+        inner.current != key_id && inner.keys.remove(&key_id).is_some()
+    }
+}
+
+impl StateKeyProvider for StateKeyring {
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn current_key(&self) -> Result<(u32, Zeroizing<[u8; 32]>), StorageError> {
+        let inner = self.inner.lock().expect("StateKeyring mutex poisoned");
+        inner
+            .keys
+            .get(&inner.current)
+            .map(|key| (inner.current, key.clone()))
+            .ok_or_else(|| StorageError::Io(format!("current key {} missing", inner.current)))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn key(&self, key_id: u32) -> Result<Option<Zeroizing<[u8; 32]>>, StorageError> {
+        Ok(self.inner.lock().expect("StateKeyring mutex poisoned").keys.get(&key_id).cloned())
+    }
+}
+
+/// Storage that seals MLS group state with keys from a [`StateKeyProvider`]
+#[derive(Clone)]
+pub struct EncryptedStateStorage<S: Storage, K: StateKeyProvider, E: Environment> {
+    inner: S,
+    keys: K,
+    /// Source of sealing nonces
+    env: E,
+}
+
+impl<S: Storage, K: StateKeyProvider, E: Environment> EncryptedStateStorage<S, K, E> {
+    /// Wrap `inner`, sealing state with keys from `keys` and nonces from `env`
+    pub fn new(inner: S, keys: K, env: E) -> Self {
+        Self { inner, keys, env }
+    }
+
+    /// Underlying storage
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// ID of the key a room's stored state is sealed with. `None` if the room
+    /// has no state or its state is not sealed.
+    pub fn sealing_key_id(&self, room_id: u128) -> Result<Option<u32>, StorageError> {
+        Ok(self.inner.load_mls_state(room_id)?.and_then(|stored| {
+            parse_envelope(&stored.openmls_state).map(|envelope| envelope.key_id)
+        }))
+    }
+
+    fn seal(&self, room_id: u128, state: &MlsGroupState) -> Result<MlsGroupState, StorageError> {
+        let (key_id, key) = self.keys.current_key()?;
+
+        let mut plaintext = Zeroizing::new(Vec::new());
+        ciborium::ser::into_writer(state, &mut *plaintext)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let mut nonce = [0u8; 24];
+        self.env.random_bytes(&mut nonce);
+
+        let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+        let aad = associated_data(room_id, key_id);
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: &plaintext, aad: &aad })
+            .map_err(|_| StorageError::Serialization("state encryption failed".to_string()))?;
+
+        let mut envelope =
+            Vec::with_capacity(ENVELOPE_HEADER_SIZE.saturating_add(ciphertext.len()));
+        envelope.extend_from_slice(ENVELOPE_MAGIC);
+        envelope.push(ENVELOPE_VERSION);
+        envelope.extend_from_slice(&key_id.to_be_bytes());
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&ciphertext);
+
+        Ok(MlsGroupState::new(room_id, 0, [0u8; 32], Vec::new(), envelope))
+    }
+
+    fn open(&self, room_id: u128, envelope: &Envelope<'_>) -> Result<MlsGroupState, StorageError> {
+        let key = self.keys.key(envelope.key_id)?.ok_or_else(|| {
+            StorageError::Serialization(format!("unknown state key {}", envelope.key_id))
+        })?;
+
+        let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+        let aad = associated_data(room_id, envelope.key_id);
+        let plaintext = cipher
+            .decrypt(XNonce::from_slice(envelope.nonce), Payload {
+                msg: envelope.ciphertext,
+                aad: &aad,
+            })
+            .map(Zeroizing::new)
+            .map_err(|_| {
+                StorageError::Serialization(format!("state for room {room_id:032x} failed to open"))
+            })?;
+
+        ciborium::de::from_reader(&plaintext[..])
+            .map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+
+    /// Open a stored state, returning the key it was sealed with. `None` for
+    /// plaintext written before encryption was enabled.
+    fn unseal(
+        &self,
+        room_id: u128,
+        stored: MlsGroupState,
+    ) -> Result<(MlsGroupState, Option<u32>), StorageError> {
+        match parse_envelope(&stored.openmls_state) {
+            Some(envelope) => Ok((self.open(room_id, &envelope)?, Some(envelope.key_id))),
+            None => Ok((stored, None)),
+        }
+    }
+
+    fn open_snapshot(&self, snapshot: RoomSnapshot) -> Result<RoomSnapshot, StorageError> {
+        let RoomSnapshot { room_id, log_index, mls_state } = snapshot;
+        let mls_state = mls_state
+            .map(|stored| self.unseal(room_id, stored).map(|(state, _)| state))
+            .transpose()?;
+
+        Ok(RoomSnapshot { room_id, log_index, mls_state })
+    }
+}
+
+/// Parsed envelope fields
+struct Envelope<'a> {
+    key_id: u32,
+    nonce: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+fn parse_envelope(bytes: &[u8]) -> Option<Envelope<'_>> {
+    let rest = bytes.strip_prefix(ENVELOPE_MAGIC)?;
+    let (&version, rest) = rest.split_first()?;
+    if version != ENVELOPE_VERSION {
+        return None;
+    }
+
+    let key_id: [u8; 4] = rest.get(..4)?.try_into().ok()?;
+    let nonce = rest.get(4..28)?;
+    let ciphertext = rest.get(28..)?;
+
+    Some(Envelope { key_id: u32::from_be_bytes(key_id), nonce, ciphertext })
+}
+
+fn associated_data(room_id: u128, key_id: u32) -> [u8; 20] {
+    let mut aad = [0u8; 20];
+    let (room, key) = aad.split_at_mut(16);
+    room.copy_from_slice(&room_id.to_be_bytes());
+    key.copy_from_slice(&key_id.to_be_bytes());
+    aad
+}
+
+impl<S: Storage, K: StateKeyProvider, E: Environment> Storage for EncryptedStateStorage<S, K, E> {
+    fn store_frame(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+    ) -> Result<(), StorageError> {
+        self.inner.store_frame(room_id, log_index, frame)
+    }
+
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        self.inner.store_frames_batch(room_id, frames)
+    }
+
+    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.inner.latest_log_index(room_id)
+    }
+
+    fn load_frames(
+        &self,
+        room_id: u128,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<Frame>, StorageError> {
+        self.inner.load_frames(room_id, from, limit)
+    }
+
+    fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError> {
+        self.inner.store_mls_state(room_id, &self.seal(room_id, state)?)
+    }
+
+    fn store_commit(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+        state: &MlsGroupState,
+    ) -> Result<(), StorageError> {
+        self.inner.store_commit(room_id, log_index, frame, &self.seal(room_id, state)?)
+    }
+
+    fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+        let Some(stored) = self.inner.load_mls_state(room_id)? else {
+            return Ok(None);
+        };
+
+        let (state, sealed_with) = self.unseal(room_id, stored)?;
+
+        // Lazily re-seal under the current key
+        let (current, _) = self.keys.current_key()?;
+        if sealed_with != Some(current) {
+            self.store_mls_state(room_id, &state)?;
+        }
+
+        Ok(Some(state))
+    }
+
+    /// Proposals are log frames, which this wrapper leaves unencrypted.
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        self.inner.store_pending_proposal(room_id, proposal)
+    }
+
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        self.inner.clear_pending_proposals(room_id)
+    }
+
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        self.inner.load_pending_proposals(room_id)
+    }
+
+    /// The snapshot holds the sealed state; it is opened on load.
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        let snapshot = self.inner.snapshot(room_id, up_to_index)?;
+        self.open_snapshot(snapshot)
+    }
+
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        self.inner.load_snapshot(room_id)?.map(|snapshot| self.open_snapshot(snapshot)).transpose()
+    }
+
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        self.inner.compact(room_id)
+    }
+
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        let mls_state = snapshot
+            .mls_state
+            .as_ref()
+            .map(|state| self.seal(snapshot.room_id, state))
+            .transpose()?;
+        self.inner.import_snapshot(&RoomSnapshot { mls_state, ..snapshot.clone() })
+    }
+
+    fn vacuum(&self, max_bytes: u64) -> Result<u64, StorageError> {
+        self.inner.vacuum(max_bytes)
+    }
+
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        self.inner.store_usage(window)
+    }
+
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        self.inner.load_usage()
+    }
+
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        self.inner.store_read_marker(room_id, member_id, up_to_log_index)
+    }
+
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        self.inner.load_read_markers(room_id)
+    }
+
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        self.inner.store_room_moved(room_id, moved)
+    }
+
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        self.inner.load_moved_rooms()
+    }
+
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        self.inner.store_archive_cursor(room_id, next_log_index)
+    }
+
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.inner.load_archive_cursor(room_id)
+    }
+
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        self.inner.store_listing(room_id, listing)
+    }
+
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        self.inner.load_listings()
+    }
+
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        self.inner.store_attachment_chunk(room_id, content_hash, index, bytes)
+    }
+
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        self.inner.load_attachment_chunk(room_id, content_hash, index)
+    }
+
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        self.inner.complete_attachment(room_id, content_hash, attachment)
+    }
+
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        self.inner.load_attachment(room_id, content_hash)
+    }
+
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        self.inner.list_attachments(room_id)
+    }
+
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        self.inner.delete_attachment(room_id, content_hash)
+    }
+
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        self.inner.delete_attachments(room_id)
+    }
+
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        self.inner.append_audit(record)
+    }
+
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        self.inner.load_audit(from_seq, limit)
+    }
+
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        self.inner.latest_audit_seq()
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush()
+    }
+
+    fn close(&self) -> Result<(), StorageError> {
+        self.inner.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SystemEnv, storage::MemoryStorage};
+
+    const ROOM: u128 = 0x1234;
+
+    fn encrypted<S: Storage>(
+        inner: S,
+        keys: StateKeyring,
+    ) -> EncryptedStateStorage<S, StateKeyring, SystemEnv> {
+        EncryptedStateStorage::new(inner, keys, SystemEnv::new())
+    }
+
+    fn test_state(epoch: u64) -> MlsGroupState {
+        MlsGroupState::new(ROOM, epoch, [7u8; 32], vec![1, 2], vec![0xAB; 16])
+    }
+
+    #[test]
+    fn test_state_sealed_at_rest() {
+        let storage = encrypted(MemoryStorage::new(), StateKeyring::new(1, [1; 32]));
+        storage.store_mls_state(ROOM, &test_state(3)).expect("store failed");
+
+        let at_rest = storage.inner().load_mls_state(ROOM).expect("load failed").expect("stored");
+        assert_eq!(at_rest.epoch, 0);
+        assert!(at_rest.members.is_empty());
+        assert_ne!(at_rest.openmls_state, vec![0xAB; 16]);
+
+        assert_eq!(storage.load_mls_state(ROOM).expect("load failed"), Some(test_state(3)));
+        assert_eq!(storage.sealing_key_id(ROOM).expect("query failed"), Some(1));
+    }
+
+    #[test]
+    fn test_rotation_reseals_lazily() {
+        let keys = StateKeyring::new(1, [1; 32]);
+        let storage = encrypted(MemoryStorage::new(), keys.clone());
+        storage.store_mls_state(ROOM, &test_state(3)).expect("store failed");
+
+        keys.rotate(2, [2; 32]);
+        assert_eq!(storage.sealing_key_id(ROOM).expect("query failed"), Some(1));
+
+        assert_eq!(storage.load_mls_state(ROOM).expect("load failed"), Some(test_state(3)));
+        assert_eq!(storage.sealing_key_id(ROOM).expect("query failed"), Some(2));
+
+        // Old key no longer needed
+        assert!(keys.retire(1));
+        assert!(!keys.retire(2));
+        assert_eq!(storage.load_mls_state(ROOM).expect("load failed"), Some(test_state(3)));
+    }
+
+    #[test]
+    fn test_plaintext_state_sealed_on_load() {
+        let inner = MemoryStorage::new();
+        inner.store_mls_state(ROOM, &test_state(5)).expect("store failed");
+
+        let storage = encrypted(inner, StateKeyring::new(1, [1; 32]));
+        assert_eq!(storage.sealing_key_id(ROOM).expect("query failed"), None);
+        assert_eq!(storage.load_mls_state(ROOM).expect("load failed"), Some(test_state(5)));
+        assert_eq!(storage.sealing_key_id(ROOM).expect("query failed"), Some(1));
+    }
+
+    #[test]
+    fn test_envelope_bound_to_room() {
+        let storage = encrypted(MemoryStorage::new(), StateKeyring::new(1, [1; 32]));
+        storage.store_mls_state(ROOM, &test_state(3)).expect("store failed");
+
+        // Copy the sealed state into another room
+        let sealed = storage.inner().load_mls_state(ROOM).expect("load failed").expect("stored");
+        storage.inner().store_mls_state(0x5678, &sealed).expect("store failed");
+
+        assert!(matches!(storage.load_mls_state(0x5678), Err(StorageError::Serialization(_))));
+    }
+}
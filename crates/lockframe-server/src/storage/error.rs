@@ -3,6 +3,8 @@
 //! Defines errors that can occur during storage operations:
 //! - `NotFound`: Requested frame or room doesn't exist
 //! - `Conflict`: Log index gap detected (sequencing violation)
+//! - `Archived`: Frame was moved out of storage with its segment
+//! - `Compacted`: Frame was dropped after a snapshot covered it
 //! - `Serialization`: Failed to encode/decode data
 //! - `Io`: Underlying storage system errors
 
@@ -33,6 +35,41 @@ pub enum StorageError {
         got: u64,
     },
 
+    /// Frame belongs to an archived segment
+    ///
+    /// The frame exists but was moved to cold storage. Restore the segment
+    /// before loading this range.
+    #[error("frame archived: room {room_id}, index {log_index}")]
+    Archived {
+        /// Room ID of the archived frame
+        room_id: u128,
+        /// First archived log index requested
+        log_index: u64,
+    },
+
+    /// Frame was dropped by compaction
+    ///
+    /// The room's snapshot covers the requested index. Loads must start at or
+    /// after `first_index`.
+    #[error("frame compacted: room {room_id}, first retained index {first_index}")]
+    Compacted {
+        /// Room ID of the compacted frame
+        room_id: u128,
+        /// Oldest log index still stored
+        first_index: u64,
+    },
+
+    /// Stored frame failed its checksum or could not be decoded
+    ///
+    /// The log is damaged at `log_index`; the frame must not be served.
+    #[error("frame corrupted: room {room_id}, index {log_index}")]
+    Corrupted {
+        /// Room ID of the corrupted frame
+        room_id: u128,
+        /// Log index of the corrupted frame
+        log_index: u64,
+    },
+
     /// Serialization or deserialization failed
     #[error("serialization error: {0}")]
     Serialization(String),
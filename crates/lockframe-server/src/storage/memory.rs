@@ -1,12 +1,17 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     sync::{Arc, Mutex},
 };
 
 use lockframe_core::mls::MlsGroupState;
-use lockframe_proto::Frame;
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
 
-use super::{Storage, StorageError};
+use super::{
+    RoomSnapshot, Storage, StorageError, check_batch_indices, check_log_empty, check_snapshot_index,
+};
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
 
 /// In-memory storage implementation for testing and simulation
 ///
@@ -15,27 +20,130 @@ use super::{Storage, StorageError};
 /// through Mutex, but uses lock().expect() which will panic if the mutex is
 /// poisoned - acceptable for test code. All operations are O(1) except
 /// load_frames which is O(limit).
+///
+/// # Durability
+///
+/// Nothing outlives the process. Within it, a plain `MemoryStorage` behaves
+/// as if every write were durable on return. One made with
+/// [`with_flush_tracking`](Self::with_flush_tracking) instead keeps what
+/// the last [`flush`](Storage::flush) saw apart, so tests can model a crash
+/// with [`after_crash`](Self::after_crash).
 #[derive(Clone)]
 pub struct MemoryStorage {
     inner: Arc<Mutex<MemoryStorageInner>>,
+    /// State as of the last flush, if flushes are tracked
+    flushed: Option<Arc<Mutex<MemoryStorageInner>>>,
 }
 
+/// A room's attachment chunks by content hash and chunk index
+type AttachmentChunks = BTreeMap<([u8; 32], u32), Vec<u8>>;
+
+#[derive(Clone)]
 struct MemoryStorageInner {
     /// Frames organized by room, stored in log_index order
     frames: HashMap<u128, Vec<Frame>>,
 
+    /// Log index of the first stored frame per room, non-zero after
+    /// compaction
+    first_index: HashMap<u128, u64>,
+
     /// MLS group state per room
     mls_states: HashMap<u128, MlsGroupState>,
+
+    /// Proposals not yet covered by a commit, per room
+    pending_proposals: HashMap<u128, BTreeMap<u64, Frame>>,
+
+    /// Latest snapshot per room
+    snapshots: HashMap<u128, RoomSnapshot>,
+
+    /// Open usage window
+    usage: Option<UsageWindow>,
+
+    /// Read markers per room, member ID → last log index read
+    read_markers: HashMap<u128, BTreeMap<u64, u64>>,
+
+    /// Rooms migrated away, and where they went
+    moved_rooms: BTreeMap<u128, RoomMoved>,
+
+    /// First log index per room not yet accepted by its archiver
+    archive_cursors: HashMap<u128, u64>,
+
+    /// Directory listings of published rooms
+    listings: BTreeMap<u128, Listing>,
+
+    /// Attachment chunks per room, by content hash and chunk index
+    attachment_chunks: HashMap<u128, AttachmentChunks>,
+
+    /// Complete attachments per room, by content hash
+    attachments: HashMap<u128, BTreeMap<[u8; 32], StoredAttachment>>,
+
+    /// Audit records in sequence order
+    audit: Vec<AuditRecord>,
+}
+
+impl MemoryStorageInner {
+    fn first_index(&self, room_id: u128) -> u64 {
+        self.first_index.get(&room_id).copied().unwrap_or(0)
+    }
+}
+
+impl MemoryStorageInner {
+    fn new() -> Self {
+        Self {
+            frames: HashMap::new(),
+            first_index: HashMap::new(),
+            mls_states: HashMap::new(),
+            pending_proposals: HashMap::new(),
+            snapshots: HashMap::new(),
+            usage: None,
+            read_markers: HashMap::new(),
+            moved_rooms: BTreeMap::new(),
+            archive_cursors: HashMap::new(),
+            listings: BTreeMap::new(),
+            attachment_chunks: HashMap::new(),
+            attachments: HashMap::new(),
+            audit: Vec::new(),
+        }
+    }
 }
 
 impl MemoryStorage {
     /// Create a new empty MemoryStorage
     pub fn new() -> Self {
+        Self { inner: Arc::new(Mutex::new(MemoryStorageInner::new())), flushed: None }
+    }
+
+    /// Create an empty `MemoryStorage` whose writes only survive
+    /// [`after_crash`](Self::after_crash) once flushed.
+    pub fn with_flush_tracking() -> Self {
         Self {
-            inner: Arc::new(Mutex::new(MemoryStorageInner {
-                frames: HashMap::new(),
-                mls_states: HashMap::new(),
-            })),
+            inner: Arc::new(Mutex::new(MemoryStorageInner::new())),
+            flushed: Some(Arc::new(Mutex::new(MemoryStorageInner::new()))),
+        }
+    }
+
+    /// What a server restarted after a crash would find.
+    ///
+    /// With flush tracking, that is the state as of the last
+    /// [`flush`](Storage::flush) or [`close`](Storage::close), and the
+    /// returned storage keeps tracking flushes. Without it, every write so
+    /// far. Either way the returned storage is independent of this one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned (a thread panicked while
+    /// holding the lock). This is acceptable for test/simulation code.
+    #[must_use]
+    pub fn after_crash(&self) -> Self {
+        let Some(flushed) = &self.flushed else {
+            let state = self.inner.lock().expect("MemoryStorage mutex poisoned").clone();
+            return Self { inner: Arc::new(Mutex::new(state)), flushed: None };
+        };
+
+        let state = flushed.lock().expect("MemoryStorage mutex poisoned").clone();
+        Self {
+            inner: Arc::new(Mutex::new(state.clone())),
+            flushed: Some(Arc::new(Mutex::new(state))),
         }
     }
 
@@ -89,9 +197,10 @@ impl Storage for MemoryStorage {
     ) -> Result<(), StorageError> {
         let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
 
+        let first_index = inner.first_index(room_id);
         let frames = inner.frames.entry(room_id).or_insert_with(Vec::new);
 
-        let expected_index = frames.len() as u64;
+        let expected_index = first_index.saturating_add(frames.len() as u64);
         debug_assert!(frames.len() < u64::MAX as usize);
 
         if log_index != expected_index {
@@ -104,12 +213,28 @@ impl Storage for MemoryStorage {
         // The payload clone is cheap (Arc increment via Bytes) but header is copied.
         frames.push(frame.clone());
 
-        debug_assert_eq!(frames.len() as u64 - 1, log_index);
-        debug_assert_eq!(frames[log_index as usize].header.log_index(), log_index);
+        debug_assert_eq!(first_index + frames.len() as u64 - 1, log_index);
+        debug_assert_eq!(frames[(log_index - first_index) as usize].header.log_index(), log_index);
 
         Ok(())
     }
 
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+
+        let first_index = inner.first_index(room_id);
+        let stored = inner.frames.entry(room_id).or_default();
+        let latest = first_index.saturating_add(stored.len() as u64).checked_sub(1);
+        check_batch_indices(frames, latest)?;
+
+        stored.extend_from_slice(frames);
+        Ok(())
+    }
+
     /// # Panics
     ///
     /// Panics if the internal mutex is poisoned. This is acceptable for test
@@ -117,9 +242,11 @@ impl Storage for MemoryStorage {
     fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
         let inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
 
-        Ok(inner.frames.get(&room_id).and_then(|frames| {
-            if frames.is_empty() { None } else { Some(frames.len() as u64 - 1) }
-        }))
+        let first_index = inner.first_index(room_id);
+        Ok(inner
+            .frames
+            .get(&room_id)
+            .and_then(|frames| first_index.saturating_add(frames.len() as u64).checked_sub(1)))
     }
 
     /// # Panics
@@ -139,7 +266,12 @@ impl Storage for MemoryStorage {
             .get(&room_id)
             .ok_or(StorageError::NotFound { room_id, log_index: from })?;
 
-        let start = from as usize;
+        let first_index = inner.first_index(room_id);
+        if from < first_index {
+            return Err(StorageError::Compacted { room_id, first_index });
+        }
+
+        let start = from.saturating_sub(first_index) as usize;
         let end = (start + limit).min(frames.len());
 
         if start > frames.len() {
@@ -170,6 +302,374 @@ impl Storage for MemoryStorage {
 
         Ok(inner.mls_states.get(&room_id).cloned())
     }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        self.inner.lock().expect("MemoryStorage mutex poisoned").usage = Some(window.clone());
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        Ok(self.inner.lock().expect("MemoryStorage mutex poisoned").usage.clone())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        inner.read_markers.entry(room_id).or_default().insert(member_id, up_to_log_index);
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        let inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        Ok(inner.read_markers.get(&room_id).cloned().unwrap_or_default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        inner.moved_rooms.insert(room_id, moved.clone());
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        Ok(self.inner.lock().expect("MemoryStorage mutex poisoned").moved_rooms.clone())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        match next_log_index {
+            Some(next_log_index) => inner.archive_cursors.insert(room_id, next_log_index),
+            None => inner.archive_cursors.remove(&room_id),
+        };
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        let inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        Ok(inner.archive_cursors.get(&room_id).copied())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        match listing {
+            Some(listing) => inner.listings.insert(room_id, listing.clone()),
+            None => inner.listings.remove(&room_id),
+        };
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        Ok(self.inner.lock().expect("MemoryStorage mutex poisoned").listings.clone())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        let chunks = inner.attachment_chunks.entry(room_id).or_default();
+        chunks.insert((*content_hash, index), bytes.to_vec());
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        let inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        let chunks = inner.attachment_chunks.get(&room_id);
+        Ok(chunks.and_then(|chunks| chunks.get(&(*content_hash, index))).cloned())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        inner.attachments.entry(room_id).or_default().insert(*content_hash, *attachment);
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        let inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        Ok(inner.attachments.get(&room_id).and_then(|room| room.get(content_hash)).copied())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        let inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        let room = inner.attachments.get(&room_id);
+        Ok(room.map(|room| room.iter().map(|(hash, a)| (*hash, *a)).collect()).unwrap_or_default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        if let Some(room) = inner.attachments.get_mut(&room_id) {
+            room.remove(content_hash);
+        }
+        if let Some(chunks) = inner.attachment_chunks.get_mut(&room_id) {
+            chunks.retain(|(hash, _), _| hash != content_hash);
+        }
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        inner.attachments.remove(&room_id);
+        inner.attachment_chunks.remove(&room_id);
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        self.inner.lock().expect("MemoryStorage mutex poisoned").audit.push(record.clone());
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        let inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        let start = inner.audit.partition_point(|record| record.seq < from_seq);
+        Ok(inner.audit.iter().skip(start).take(limit).cloned().collect())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        Ok(self.inner.lock().expect("MemoryStorage mutex poisoned").audit.last().map(|r| r.seq))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+
+        let proposals = inner.pending_proposals.entry(room_id).or_default();
+        proposals.insert(proposal.header.log_index(), proposal.clone());
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+
+        inner.pending_proposals.remove(&room_id);
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        let inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+
+        Ok(inner
+            .pending_proposals
+            .get(&room_id)
+            .map(|proposals| proposals.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+
+        let first_index = inner.first_index(room_id);
+        let latest = inner
+            .frames
+            .get(&room_id)
+            .and_then(|frames| first_index.saturating_add(frames.len() as u64).checked_sub(1));
+        check_snapshot_index(room_id, up_to_index, latest, inner.snapshots.get(&room_id))?;
+
+        let snapshot = RoomSnapshot {
+            room_id,
+            log_index: up_to_index,
+            mls_state: inner.mls_states.get(&room_id).cloned(),
+        };
+        inner.snapshots.insert(room_id, snapshot.clone());
+
+        Ok(snapshot)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        let inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+
+        Ok(inner.snapshots.get(&room_id).cloned())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+
+        let Some(boundary) = inner.snapshots.get(&room_id).map(RoomSnapshot::first_retained_index)
+        else {
+            return Ok(0);
+        };
+        let first_index = inner.first_index(room_id);
+        let Some(frames) = inner.frames.get_mut(&room_id) else {
+            return Ok(0);
+        };
+
+        let dropped = boundary.saturating_sub(first_index).min(frames.len() as u64);
+        frames.drain(..dropped as usize);
+        inner.first_index.insert(room_id, first_index.saturating_add(dropped));
+
+        Ok(dropped)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("MemoryStorage mutex poisoned");
+        let room_id = snapshot.room_id;
+
+        let first_index = inner.first_index(room_id);
+        let latest = inner
+            .frames
+            .get(&room_id)
+            .and_then(|frames| first_index.saturating_add(frames.len() as u64).checked_sub(1));
+        check_log_empty(latest, snapshot)?;
+
+        inner.frames.insert(room_id, Vec::new());
+        inner.first_index.insert(room_id, snapshot.first_retained_index());
+        inner.snapshots.insert(room_id, snapshot.clone());
+        Ok(())
+    }
+
+    /// Nothing to make durable. With flush tracking, records the current
+    /// state as what [`MemoryStorage::after_crash`] returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned. This is acceptable for test
+    /// code.
+    fn flush(&self) -> Result<(), StorageError> {
+        if let Some(flushed) = &self.flushed {
+            let state = self.inner.lock().expect("MemoryStorage mutex poisoned").clone();
+            *flushed.lock().expect("MemoryStorage mutex poisoned") = state;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -267,6 +767,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_batch_conflict_stores_nothing() {
+        let storage = MemoryStorage::new();
+        let room_id = 100;
+        storage.store_frame(room_id, 0, &create_test_frame(room_id, 0)).expect("store failed");
+
+        let gapped: Vec<Frame> = [1, 2, 4].iter().map(|&i| create_test_frame(room_id, i)).collect();
+        assert_eq!(
+            storage.store_frames_batch(room_id, &gapped),
+            Err(StorageError::Conflict { expected: 3, got: 4 })
+        );
+        assert_eq!(storage.latest_log_index(room_id).expect("query failed"), Some(0));
+
+        let batch: Vec<Frame> = (1..4).map(|i| create_test_frame(room_id, i)).collect();
+        storage.store_frames_batch(room_id, &batch).expect("batch failed");
+        assert_eq!(storage.latest_log_index(room_id).expect("query failed"), Some(3));
+    }
+
     #[test]
     fn test_load_frames_pagination() {
         let storage = MemoryStorage::new();
@@ -358,6 +876,67 @@ mod tests {
         assert_eq!(loaded.openmls_state, vec![1, 2, 3, 4]);
     }
 
+    #[test]
+    fn test_compact_drops_frames_before_snapshot() {
+        let storage = MemoryStorage::new();
+        let room_id = 100;
+
+        for i in 0..10 {
+            storage.store_frame(room_id, i, &create_test_frame(room_id, i)).expect("store failed");
+        }
+        let state = MlsGroupState::new(room_id, 2, [7u8; 32], vec![100], vec![]);
+        storage.store_mls_state(room_id, &state).expect("store failed");
+
+        // Nothing to compact before a snapshot
+        assert_eq!(storage.compact(room_id).expect("compact failed"), 0);
+
+        let snapshot = storage.snapshot(room_id, 5).expect("snapshot failed");
+        assert_eq!(snapshot.mls_state, Some(state));
+        assert_eq!(storage.load_snapshot(room_id).expect("load failed"), Some(snapshot));
+        assert_eq!(storage.compact(room_id).expect("compact failed"), 6);
+        assert_eq!(storage.total_frame_count(), 4);
+
+        // Log position is unchanged and new frames keep appending
+        assert_eq!(storage.latest_log_index(room_id).expect("query failed"), Some(9));
+        storage.store_frame(room_id, 10, &create_test_frame(room_id, 10)).expect("store failed");
+
+        assert_eq!(
+            storage.load_frames(room_id, 0, 10),
+            Err(StorageError::Compacted { room_id, first_index: 6 })
+        );
+        let frames = storage.load_frames(room_id, 6, 10).expect("load failed");
+        assert_eq!(frames.first().map(|f| f.header.log_index()), Some(6));
+        assert_eq!(frames.len(), 5);
+
+        // Snapshots only move forward and only over stored frames
+        assert!(matches!(storage.snapshot(room_id, 3), Err(StorageError::Conflict { .. })));
+        assert!(matches!(storage.snapshot(room_id, 11), Err(StorageError::NotFound { .. })));
+    }
+
+    #[test]
+    fn test_crash_keeps_only_flushed_writes() {
+        let storage = MemoryStorage::with_flush_tracking();
+        let room_id = 100;
+
+        storage.store_frame(room_id, 0, &create_test_frame(room_id, 0)).expect("store failed");
+        storage.flush().expect("flush failed");
+        storage.store_frame(room_id, 1, &create_test_frame(room_id, 1)).expect("store failed");
+
+        let restarted = storage.after_crash();
+        assert_eq!(restarted.latest_log_index(room_id).expect("query failed"), Some(0));
+
+        // The restarted storage tracks flushes of its own
+        restarted.store_frame(room_id, 1, &create_test_frame(room_id, 1)).expect("store failed");
+        restarted.close().expect("close failed");
+        assert_eq!(restarted.after_crash().total_frame_count(), 2);
+        assert_eq!(storage.total_frame_count(), 2);
+
+        // Untracked storage keeps every write
+        let untracked = MemoryStorage::new();
+        untracked.store_frame(room_id, 0, &create_test_frame(room_id, 0)).expect("store failed");
+        assert_eq!(untracked.after_crash().total_frame_count(), 1);
+    }
+
     #[test]
     fn test_mls_state_overwrite() {
         let storage = MemoryStorage::new();
@@ -3,15 +3,49 @@
 //! Trait-based abstraction for persisting frames and MLS state. The trait is
 //! synchronous (no async) to maintain a clean synchronous API design.
 
+mod archive;
+mod backend;
+mod backup;
+mod cached;
 mod chaotic;
+mod encrypted;
 mod error;
 mod memory;
+mod persistent;
+mod s3;
+mod segmented;
+mod sqlite;
+mod wal;
 
-pub use chaotic::ChaoticStorage;
+use std::collections::BTreeMap;
+
+pub use archive::{
+    ArchiveConfig, ArchivedStorage, DEFAULT_CHUNK_FRAMES, DEFAULT_HOT_FRAMES, FsObjectStore,
+    MemoryObjectStore, ObjectStore,
+};
+pub use backend::{ArchiveStore, ServerStorage, StorageBackend};
+pub use backup::{BackupSummary, EXPORT_BATCH_FRAMES, Export, dump, restore};
+pub use cached::{
+    CacheConfig, CacheStats, CachedStorage, DEFAULT_CACHE_FRAMES, DEFAULT_CACHE_FRAMES_PER_ROOM,
+};
+pub use chaotic::{ChaoticStorage, FaultProfile};
+pub use encrypted::{EncryptedStateStorage, StateKeyProvider, StateKeyring};
 pub use error::StorageError;
 use lockframe_core::mls::MlsGroupState;
-use lockframe_proto::Frame;
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
 pub use memory::MemoryStorage;
+pub use persistent::SledStorage;
+pub use s3::{S3Config, S3ObjectStore};
+pub use segmented::{
+    ArchivedSegment, DEFAULT_SEGMENT_FRAMES, DEFAULT_SEGMENT_SPAN_MILLIS, SegmentConfig,
+    SegmentInfo, SegmentedStorage,
+};
+pub use sqlite::SqliteStorage;
+pub use wal::{DEFAULT_WAL_CHECKPOINT_RECORDS, WalStorage};
+
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
 
 /// Storage abstraction for frames and MLS group state
 ///
@@ -19,6 +53,21 @@ pub use memory::MemoryStorage;
 /// (thread-safe), and synchronous (no async methods). Implementations typically
 /// share internal state via Arc, so clones access the same underlying storage.
 ///
+/// # Durability
+///
+/// When a returned write survives a crash depends on the backend:
+/// - [`MemoryStorage`], [`SegmentedStorage`]: never, the process holds
+///   everything
+/// - [`SledStorage`]: on return, as every write is flushed
+/// - [`SqliteStorage`]: on return, as every write is a committed transaction
+/// - [`WalStorage`]: frames on return, once synced to the log; everything else
+///   as its inner storage
+/// - [`ArchivedStorage`], [`CachedStorage`], [`EncryptedStateStorage`]: as
+///   their inner storage
+///
+/// [`flush`](Self::flush) makes every write so far durable on any backend,
+/// and [`close`](Self::close) does the same before the server stops.
+///
 /// # Panics
 ///
 /// Implementations may panic if internal synchronization primitives are
@@ -35,6 +84,24 @@ pub trait Storage: Clone + Send + Sync + 'static {
     fn store_frame(&self, room_id: u128, log_index: u64, frame: &Frame)
     -> Result<(), StorageError>;
 
+    /// Store consecutive frames of a room's log in one write
+    ///
+    /// Each frame goes at its header's log index. Durable backends override
+    /// this to commit the whole batch atomically behind a single sync; the
+    /// default stores the frames one at a time.
+    ///
+    /// # Invariants
+    ///
+    /// - Pre: log indices are consecutive, starting at the room's next index
+    /// - Post: on a conflict, none of the frames is stored
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        check_batch_indices(frames, self.latest_log_index(room_id)?)?;
+        for frame in frames {
+            self.store_frame(room_id, frame.header.log_index(), frame)?;
+        }
+        Ok(())
+    }
+
     /// Latest log index for a room. `None` if no frames stored.
     ///
     /// Returns `None` if the room doesn't exist or has no frames.
@@ -56,8 +123,424 @@ pub trait Storage: Clone + Send + Sync + 'static {
     /// Overwrites any existing state for this room.
     fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError>;
 
+    /// Store a commit frame and the MLS state it produced
+    ///
+    /// Backends that can write both atomically override this; the default
+    /// stores the frame, then the state.
+    ///
+    /// # Invariants
+    ///
+    /// - Pre: `log_index` must equal the current length of the room's log
+    /// - Post: on a conflict, neither the frame nor the state is stored
+    fn store_commit(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+        state: &MlsGroupState,
+    ) -> Result<(), StorageError> {
+        self.store_frame(room_id, log_index, frame)?;
+        self.store_mls_state(room_id, state)
+    }
+
     /// Load MLS group state for a room
     ///
     /// Returns `None` if no state exists for this room.
     fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError>;
+
+    /// Add a proposal sequenced in a room's current epoch to the room's
+    /// pending proposals
+    ///
+    /// Keyed by the proposal's log index, so storing one twice keeps a single
+    /// copy.
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError>;
+
+    /// Drop a room's pending proposals, once a commit has covered them
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError>;
+
+    /// Load a room's pending proposals, in log order
+    ///
+    /// Returns an empty list if none are stored.
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError>;
+
+    /// Record a snapshot covering a room's log up to and including
+    /// `up_to_index`
+    ///
+    /// Captures the room's current MLS state together with the truncation
+    /// point. Frames stay loadable until [`Storage::compact`] drops them.
+    ///
+    /// # Invariants
+    ///
+    /// - Pre: `up_to_index` is at most the latest log index
+    /// - Pre: `up_to_index` is not below the room's previous snapshot
+    /// - Post: `load_snapshot` returns the new snapshot
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError>;
+
+    /// Latest snapshot for a room. `None` if none was taken.
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError>;
+
+    /// Drop the frames covered by the room's latest snapshot
+    ///
+    /// Returns how many frames were dropped; zero if there is no snapshot.
+    ///
+    /// # Invariants
+    ///
+    /// - Post: `latest_log_index` is unchanged
+    /// - Post: loading below the snapshot boundary fails with
+    ///   [`StorageError::Compacted`]
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError>;
+
+    /// Return space freed by compaction to the file system
+    ///
+    /// Rewrites at most about `max_bytes` per call, so a pass does not starve
+    /// frame writes of disk bandwidth. Returns the bytes reclaimed. Storage
+    /// without a file behind it reclaims nothing.
+    fn vacuum(&self, max_bytes: u64) -> Result<u64, StorageError> {
+        let _ = max_bytes;
+        Ok(0)
+    }
+
+    /// Store the open usage window, replacing the previous one
+    ///
+    /// Backends that keep no usage drop it, so the window restarts empty
+    /// after a restart.
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        let _ = window;
+        Ok(())
+    }
+
+    /// Load the usage window stored last
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        Ok(None)
+    }
+
+    /// Record that `member_id` has read a room's log up to and including
+    /// `up_to_log_index`
+    ///
+    /// Replaces the member's previous marker. Backends that keep no read
+    /// markers drop it, so a member's read position is unknown after a
+    /// restart until they send another receipt.
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        let _ = (room_id, member_id, up_to_log_index);
+        Ok(())
+    }
+
+    /// Load a room's read markers, member ID → last log index read
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        let _ = room_id;
+        Ok(BTreeMap::new())
+    }
+
+    /// Record that `room_id` was migrated to another server
+    ///
+    /// Replaces an earlier record for the room. Backends that keep no moved
+    /// rooms drop it, so after a restart frames for the room are answered as
+    /// for any unknown room rather than with where it went.
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        let _ = (room_id, moved);
+        Ok(())
+    }
+
+    /// Load every room migrated away, room ID → where it went
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        Ok(BTreeMap::new())
+    }
+
+    /// Record the first log index of a room its archiver has not accepted
+    ///
+    /// Replaces the room's previous cursor; `None` removes it. Backends that
+    /// keep no cursors drop it, so after a restart only frames sequenced
+    /// from then on are archived.
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        let _ = (room_id, next_log_index);
+        Ok(())
+    }
+
+    /// Load a room's archive cursor. `None` if none is stored.
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        let _ = room_id;
+        Ok(None)
+    }
+
+    /// Record a room's directory listing
+    ///
+    /// Replaces the room's previous listing; `None` unlists it. Backends
+    /// that keep no listings drop it, so after a restart the room is
+    /// unlisted until published again.
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        let _ = (room_id, listing);
+        Ok(())
+    }
+
+    /// Load every listed room, room ID → listing
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        Ok(BTreeMap::new())
+    }
+
+    /// Store chunk `index` of the attachment uploaded to a room under the
+    /// SHA-256 hash of its bytes
+    ///
+    /// Replaces a chunk stored at the same index. Backends that keep no
+    /// attachments refuse them.
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        let _ = (room_id, content_hash, index, bytes);
+        Err(StorageError::Io("attachments are not supported by this backend".to_string()))
+    }
+
+    /// Load chunk `index` of an attachment
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        let _ = (room_id, content_hash, index);
+        Ok(None)
+    }
+
+    /// Record that every chunk of an attachment is stored
+    ///
+    /// Until then the attachment is not served.
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        let _ = (room_id, content_hash, attachment);
+        Err(StorageError::Io("attachments are not supported by this backend".to_string()))
+    }
+
+    /// Load a complete attachment's record
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        let _ = (room_id, content_hash);
+        Ok(None)
+    }
+
+    /// List a room's complete attachments by content hash
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        let _ = room_id;
+        Ok(Vec::new())
+    }
+
+    /// Delete an attachment's record and chunks, complete or not
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        let _ = (room_id, content_hash);
+        Ok(())
+    }
+
+    /// Delete every attachment of a room, including the chunks of uploads
+    /// that never completed
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        let _ = room_id;
+        Ok(())
+    }
+
+    /// Append a record to the audit log
+    ///
+    /// Records arrive in sequence order. Backends that keep no audit log drop
+    /// them.
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        let _ = record;
+        Ok(())
+    }
+
+    /// Load up to `limit` audit records from sequence number `from_seq`,
+    /// oldest first
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        let _ = (from_seq, limit);
+        Ok(Vec::new())
+    }
+
+    /// Sequence number of the last audit record (`None` if there is none)
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        Ok(None)
+    }
+
+    /// Make every write so far durable, e.g. before a planned restart
+    ///
+    /// Backends that are durable on every write have nothing to do.
+    fn flush(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Make every write durable before the server stops
+    ///
+    /// Called once by the graceful shutdown path, after the last write. The
+    /// storage must not be written to afterwards; the backend releases its
+    /// files when the last clone is dropped. Defaults to
+    /// [`flush`](Self::flush).
+    fn close(&self) -> Result<(), StorageError> {
+        self.flush()
+    }
+
+    /// Scrub a room's retained log for corruption
+    ///
+    /// Loads every retained frame, which checks it against the checksum
+    /// stored with it. Returns how many frames were checked. Backends that
+    /// hold frames only in memory store no checksums, so for them this only
+    /// checks that the log loads.
+    ///
+    /// # Invariants
+    ///
+    /// - Post: fails with [`StorageError::Corrupted`] naming the first damaged
+    ///   frame
+    fn verify(&self, room_id: u128) -> Result<u64, StorageError> {
+        let mut checked = 0u64;
+        for frame in self.export(room_id) {
+            frame?;
+            checked = checked.saturating_add(1);
+        }
+        Ok(checked)
+    }
+
+    /// Iterate a room's retained log, oldest frame first
+    ///
+    /// Starts at the first frame compaction kept and loads
+    /// [`EXPORT_BATCH_FRAMES`] at a time. Ends after the first error.
+    fn export(&self, room_id: u128) -> Export<'_, Self> {
+        Export::new(self, room_id)
+    }
+
+    /// Append exported frames to a room's log at their own log indices
+    ///
+    /// Returns how many frames were stored.
+    ///
+    /// # Invariants
+    ///
+    /// - Pre: the first frame's log index is the room's next index
+    /// - Post: stops at the first frame that fails to store; earlier frames
+    ///   stay stored
+    fn import(
+        &self,
+        room_id: u128,
+        frames: impl IntoIterator<Item = Frame>,
+    ) -> Result<u64, StorageError> {
+        let mut imported = 0u64;
+        for frame in frames {
+            self.store_frame(room_id, frame.header.log_index(), &frame)?;
+            imported = imported.saturating_add(1);
+        }
+        Ok(imported)
+    }
+
+    /// Start the log of a room with no frames after `snapshot`, as if the
+    /// frames it covers had been stored and compacted
+    ///
+    /// Lets a backup of a compacted room be restored onto empty storage.
+    /// Backends that can't start a log past index 0 fail with
+    /// [`StorageError::Conflict`].
+    ///
+    /// # Invariants
+    ///
+    /// - Pre: the room has no frames
+    /// - Post: the room's next log index is the snapshot's first retained index
+    /// - Post: `load_snapshot` returns `snapshot`
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        Err(StorageError::Conflict { expected: 0, got: snapshot.first_retained_index() })
+    }
+}
+
+/// Truncation point for a room's log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomSnapshot {
+    /// Room the snapshot belongs to
+    pub room_id: u128,
+    /// Last log index covered by the snapshot
+    pub log_index: u64,
+    /// MLS group state stored when the snapshot was taken
+    pub mls_state: Option<MlsGroupState>,
+}
+
+impl RoomSnapshot {
+    /// First log index kept once the snapshot is compacted
+    pub fn first_retained_index(&self) -> u64 {
+        self.log_index.saturating_add(1)
+    }
+}
+
+/// Checksum stored with an encoded frame.
+fn frame_checksum(encoded: &[u8]) -> u32 {
+    crc32fast::hash(encoded)
+}
+
+/// Decode a stored frame, checking it against its checksum.
+///
+/// Frames written before checksums were stored have none and are only
+/// decoded. A mismatch or a frame that no longer decodes is corruption.
+fn decode_stored_frame(
+    room_id: u128,
+    log_index: u64,
+    encoded: &[u8],
+    checksum: Option<u32>,
+) -> Result<Frame, StorageError> {
+    if checksum.is_some_and(|checksum| checksum != frame_checksum(encoded)) {
+        return Err(StorageError::Corrupted { room_id, log_index });
+    }
+    Frame::decode(encoded).map_err(|_| StorageError::Corrupted { room_id, log_index })
+}
+
+/// Check that a batch continues a log whose latest index is `latest`.
+fn check_batch_indices(frames: &[Frame], latest: Option<u64>) -> Result<(), StorageError> {
+    let mut expected = latest.map_or(0, |latest| latest.saturating_add(1));
+    for frame in frames {
+        let got = frame.header.log_index();
+        if got != expected {
+            return Err(StorageError::Conflict { expected, got });
+        }
+        expected = expected.saturating_add(1);
+    }
+    Ok(())
+}
+
+/// Check that a room has no frames before starting its log at `snapshot`.
+fn check_log_empty(latest: Option<u64>, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+    latest.map_or(Ok(()), |latest| {
+        Err(StorageError::Conflict {
+            expected: latest.saturating_add(1),
+            got: snapshot.first_retained_index(),
+        })
+    })
+}
+
+/// Check a snapshot request against the room's log and previous snapshot.
+fn check_snapshot_index(
+    room_id: u128,
+    up_to_index: u64,
+    latest: Option<u64>,
+    previous: Option<&RoomSnapshot>,
+) -> Result<(), StorageError> {
+    if !latest.is_some_and(|latest| up_to_index <= latest) {
+        return Err(StorageError::NotFound { room_id, log_index: up_to_index });
+    }
+    if let Some(previous) = previous.filter(|previous| up_to_index < previous.log_index) {
+        return Err(StorageError::Conflict { expected: previous.log_index, got: up_to_index });
+    }
+    Ok(())
 }
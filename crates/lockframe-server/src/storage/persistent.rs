@@ -0,0 +1,933 @@
+//! Durable storage backed by sled
+//!
+//! Frames, MLS state and pending proposals survive restarts. Each room's log
+//! lives under a 24-byte key (`room_id` then `log_index`, both big-endian) so a
+//! room's frames are contiguous and ordered; a separate tree records the next
+//! log index per room.
+//!
+//! Writes are crash-safe: a frame, its checksum and its room's head are
+//! updated in one transaction, so a crash never leaves a gap or a frame the
+//! head does not cover, and every write is flushed to disk before it returns.
+//! Loads check each frame against its CRC-32 and fail with
+//! [`StorageError::Corrupted`] rather than return a damaged frame.
+//!
+//! Compaction records the room's first retained index before deleting the
+//! frames below it, so a crash part way through only leaves unreachable
+//! frames behind.
+
+use std::{collections::BTreeMap, path::Path};
+
+use bytes::BytesMut;
+use lockframe_core::mls::MlsGroupState;
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
+use sled::{
+    Db, Transactional, Tree,
+    transaction::{ConflictableTransactionError, TransactionError},
+};
+
+use super::{
+    RoomSnapshot, Storage, StorageError, check_batch_indices, check_log_empty,
+    check_snapshot_index, decode_stored_frame, frame_checksum,
+};
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
+
+const FRAMES_TREE: &str = "frames";
+const CHECKSUMS_TREE: &str = "checksums";
+const HEADS_TREE: &str = "heads";
+const MLS_TREE: &str = "mls_states";
+const PROPOSALS_TREE: &str = "pending_proposals";
+const SNAPSHOTS_TREE: &str = "snapshots";
+const COMPACTED_TREE: &str = "compacted";
+const USAGE_TREE: &str = "usage";
+const AUDIT_TREE: &str = "audit";
+const READ_MARKERS_TREE: &str = "read_markers";
+const MOVED_ROOMS_TREE: &str = "moved_rooms";
+const ARCHIVE_CURSORS_TREE: &str = "archive_cursors";
+const LISTINGS_TREE: &str = "listings";
+const ATTACHMENTS_TREE: &str = "stored_attachments";
+const ATTACHMENT_CHUNKS_TREE: &str = "attachment_chunks";
+
+/// Attachments stored whole under their hash alone, before they were kept
+/// per room. They can't be tied to a room, so they are dropped.
+const LEGACY_ATTACHMENTS_TREE: &str = "attachments";
+
+/// Key of the open usage window in the usage tree
+const USAGE_KEY: &[u8] = b"open";
+
+/// Durable storage in a sled database
+///
+/// Clones share the same database handle.
+#[derive(Clone)]
+pub struct SledStorage {
+    db: Db,
+    /// `room_id ++ log_index` → encoded frame
+    frames: Tree,
+    /// `room_id ++ log_index` → CRC-32 of the encoded frame
+    checksums: Tree,
+    /// `room_id` → next log index
+    heads: Tree,
+    /// `room_id` → CBOR-encoded MLS state
+    mls_states: Tree,
+    /// `room_id ++ log_index` → encoded pending proposal frame
+    pending_proposals: Tree,
+    /// `room_id` → CBOR-encoded snapshot index and MLS state
+    snapshots: Tree,
+    /// `room_id` → first log index still stored
+    compacted: Tree,
+    /// [`USAGE_KEY`] → CBOR-encoded open usage window
+    usage: Tree,
+    /// Big-endian sequence number → CBOR-encoded audit record
+    audit: Tree,
+    /// `room_id ++ member_id` → last log index read
+    read_markers: Tree,
+    /// `room_id` → CBOR-encoded record of where the room moved
+    moved_rooms: Tree,
+    /// `room_id` → first log index not yet accepted by the room's archiver
+    archive_cursors: Tree,
+    /// `room_id` → CBOR-encoded directory listing
+    listings: Tree,
+    /// `room_id ++ content_hash` → CBOR-encoded complete attachment record
+    attachments: Tree,
+    /// `room_id ++ content_hash ++ chunk index` → chunk bytes
+    attachment_chunks: Tree,
+}
+
+impl SledStorage {
+    /// Open or create a database in the directory at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::from_db(sled::open(path)?)
+    }
+
+    /// Create a database that is deleted when dropped
+    ///
+    /// Useful for testing.
+    pub fn temporary() -> Result<Self, StorageError> {
+        Self::from_db(sled::Config::new().temporary(true).open()?)
+    }
+
+    fn from_db(db: Db) -> Result<Self, StorageError> {
+        db.drop_tree(LEGACY_ATTACHMENTS_TREE)?;
+        Ok(Self {
+            frames: db.open_tree(FRAMES_TREE)?,
+            checksums: db.open_tree(CHECKSUMS_TREE)?,
+            heads: db.open_tree(HEADS_TREE)?,
+            mls_states: db.open_tree(MLS_TREE)?,
+            pending_proposals: db.open_tree(PROPOSALS_TREE)?,
+            snapshots: db.open_tree(SNAPSHOTS_TREE)?,
+            compacted: db.open_tree(COMPACTED_TREE)?,
+            usage: db.open_tree(USAGE_TREE)?,
+            audit: db.open_tree(AUDIT_TREE)?,
+            read_markers: db.open_tree(READ_MARKERS_TREE)?,
+            moved_rooms: db.open_tree(MOVED_ROOMS_TREE)?,
+            archive_cursors: db.open_tree(ARCHIVE_CURSORS_TREE)?,
+            listings: db.open_tree(LISTINGS_TREE)?,
+            attachments: db.open_tree(ATTACHMENTS_TREE)?,
+            attachment_chunks: db.open_tree(ATTACHMENT_CHUNKS_TREE)?,
+            db,
+        })
+    }
+
+    fn next_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.heads.get(room_id.to_be_bytes())?.map(|v| decode_index(&v)).transpose()
+    }
+
+    fn first_index(&self, room_id: u128) -> Result<u64, StorageError> {
+        Ok(self
+            .compacted
+            .get(room_id.to_be_bytes())?
+            .map(|v| decode_index(&v))
+            .transpose()?
+            .unwrap_or(0))
+    }
+}
+
+impl From<sled::Error> for StorageError {
+    fn from(err: sled::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
+
+fn frame_key(room_id: u128, log_index: u64) -> [u8; 24] {
+    let mut key = [0u8; 24];
+    let (room, index) = key.split_at_mut(16);
+    room.copy_from_slice(&room_id.to_be_bytes());
+    index.copy_from_slice(&log_index.to_be_bytes());
+    key
+}
+
+fn attachment_key(room_id: u128, content_hash: &[u8; 32]) -> [u8; 48] {
+    let mut key = [0u8; 48];
+    let (room, hash) = key.split_at_mut(16);
+    room.copy_from_slice(&room_id.to_be_bytes());
+    hash.copy_from_slice(content_hash);
+    key
+}
+
+fn chunk_key(room_id: u128, content_hash: &[u8; 32], index: u32) -> [u8; 52] {
+    let mut key = [0u8; 52];
+    let (prefix, chunk) = key.split_at_mut(48);
+    prefix.copy_from_slice(&attachment_key(room_id, content_hash));
+    chunk.copy_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Remove every key of `tree` starting with `prefix` in one batch.
+fn remove_prefix(tree: &Tree, prefix: &[u8]) -> Result<(), StorageError> {
+    let mut batch = sled::Batch::default();
+    for entry in tree.scan_prefix(prefix) {
+        let (key, _) = entry?;
+        batch.remove(key);
+    }
+    tree.apply_batch(batch)?;
+    Ok(())
+}
+
+fn decode_attachment(bytes: &[u8]) -> Result<StoredAttachment, StorageError> {
+    ciborium::de::from_reader(bytes).map_err(|e| StorageError::Serialization(e.to_string()))
+}
+
+fn decode_checksum(bytes: &[u8]) -> Result<u32, StorageError> {
+    let bytes: [u8; 4] = bytes
+        .try_into()
+        .map_err(|_| StorageError::Serialization("corrupt frame checksum".to_string()))?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn decode_index(bytes: &[u8]) -> Result<u64, StorageError> {
+    let bytes: [u8; 8] = bytes
+        .try_into()
+        .map_err(|_| StorageError::Serialization("corrupt log head".to_string()))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+impl Storage for SledStorage {
+    fn store_frame(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+    ) -> Result<(), StorageError> {
+        let mut encoded = BytesMut::new();
+        frame.encode(&mut encoded).map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let checksum = frame_checksum(&encoded).to_be_bytes();
+
+        let room_key = room_id.to_be_bytes();
+        let result = (&self.frames, &self.checksums, &self.heads).transaction(
+            |(frames, checksums, heads)| {
+                let expected = match heads.get(room_key)? {
+                    Some(head) => {
+                        decode_index(&head).map_err(ConflictableTransactionError::Abort)?
+                    },
+                    None => 0,
+                };
+                if log_index != expected {
+                    return Err(ConflictableTransactionError::Abort(StorageError::Conflict {
+                        expected,
+                        got: log_index,
+                    }));
+                }
+
+                let key = frame_key(room_id, log_index);
+                frames.insert(&key[..], &encoded[..])?;
+                checksums.insert(&key[..], &checksum[..])?;
+                heads.insert(&room_key[..], &log_index.saturating_add(1).to_be_bytes()[..])?;
+                Ok(())
+            },
+        );
+
+        match result {
+            Ok(()) => self.flush(),
+            Err(TransactionError::Abort(err)) => Err(err),
+            Err(TransactionError::Storage(err)) => Err(err.into()),
+        }
+    }
+
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        let Some(first) = frames.first() else {
+            return Ok(());
+        };
+        let first_index = first.header.log_index();
+        check_batch_indices(frames, first_index.checked_sub(1))?;
+
+        let mut encoded = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let mut bytes = BytesMut::new();
+            frame.encode(&mut bytes).map_err(|e| StorageError::Serialization(e.to_string()))?;
+            let checksum = frame_checksum(&bytes).to_be_bytes();
+            encoded.push((frame.header.log_index(), bytes, checksum));
+        }
+        let next = first_index.saturating_add(frames.len() as u64);
+
+        let room_key = room_id.to_be_bytes();
+        let trees = (&self.frames, &self.checksums, &self.heads);
+        let result = trees.transaction(|(tx_frames, checksums, heads)| {
+            let expected = match heads.get(room_key)? {
+                Some(head) => decode_index(&head).map_err(ConflictableTransactionError::Abort)?,
+                None => 0,
+            };
+            if first_index != expected {
+                return Err(ConflictableTransactionError::Abort(StorageError::Conflict {
+                    expected,
+                    got: first_index,
+                }));
+            }
+
+            for (log_index, bytes, checksum) in &encoded {
+                let key = frame_key(room_id, *log_index);
+                tx_frames.insert(&key[..], &bytes[..])?;
+                checksums.insert(&key[..], &checksum[..])?;
+            }
+            heads.insert(&room_key[..], &next.to_be_bytes()[..])?;
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => self.flush(),
+            Err(TransactionError::Abort(err)) => Err(err),
+            Err(TransactionError::Storage(err)) => Err(err.into()),
+        }
+    }
+
+    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        Ok(self.next_index(room_id)?.and_then(|next| next.checked_sub(1)))
+    }
+
+    fn load_frames(
+        &self,
+        room_id: u128,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<Frame>, StorageError> {
+        if self.next_index(room_id)?.is_none() {
+            return Err(StorageError::NotFound { room_id, log_index: from });
+        }
+        let first_index = self.first_index(room_id)?;
+        if from < first_index {
+            return Err(StorageError::Compacted { room_id, first_index });
+        }
+
+        let start = frame_key(room_id, from);
+        let end = frame_key(room_id.saturating_add(1), 0);
+        let range = if room_id == u128::MAX {
+            self.frames.range(start..)
+        } else {
+            self.frames.range(start..end)
+        };
+
+        range
+            .take(limit)
+            .map(|entry| {
+                let (key, value) = entry?;
+                let log_index = key.get(16..).map_or(Ok(0), decode_index)?;
+                let checksum =
+                    self.checksums.get(&key)?.map(|v| decode_checksum(&v)).transpose()?;
+                decode_stored_frame(room_id, log_index, &value, checksum)
+            })
+            .collect()
+    }
+
+    fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError> {
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(state, &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        self.mls_states.insert(room_id.to_be_bytes(), encoded)?;
+        self.flush()
+    }
+
+    fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+        self.mls_states
+            .get(room_id.to_be_bytes())?
+            .map(|value| {
+                ciborium::de::from_reader(&value[..])
+                    .map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .transpose()
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(window, &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        self.usage.insert(USAGE_KEY, encoded)?;
+        self.flush()
+    }
+
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        self.usage
+            .get(USAGE_KEY)?
+            .map(|value| {
+                ciborium::de::from_reader(&value[..])
+                    .map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .transpose()
+    }
+
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        let key = frame_key(room_id, member_id);
+        self.read_markers.insert(&key[..], &up_to_log_index.to_be_bytes()[..])?;
+        self.flush()
+    }
+
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        self.read_markers
+            .scan_prefix(room_id.to_be_bytes())
+            .map(|entry| {
+                let (key, value) = entry?;
+                let member_id = key.get(16..).ok_or_else(|| {
+                    StorageError::Serialization("corrupt read marker key".to_string())
+                })?;
+                Ok((decode_index(member_id)?, decode_index(&value)?))
+            })
+            .collect()
+    }
+
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(moved, &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        self.moved_rooms.insert(room_id.to_be_bytes(), encoded)?;
+        self.flush()
+    }
+
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        self.moved_rooms
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry?;
+                let room_id: [u8; 16] = key[..].try_into().map_err(|_| {
+                    StorageError::Serialization("corrupt moved room key".to_string())
+                })?;
+                let moved = ciborium::de::from_reader(&value[..])
+                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+                Ok((u128::from_be_bytes(room_id), moved))
+            })
+            .collect()
+    }
+
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        match next_log_index {
+            Some(next_log_index) => {
+                self.archive_cursors.insert(room_id.to_be_bytes(), &next_log_index.to_be_bytes())?
+            },
+            None => self.archive_cursors.remove(room_id.to_be_bytes())?,
+        };
+        self.flush()
+    }
+
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.archive_cursors.get(room_id.to_be_bytes())?.map(|v| decode_index(&v)).transpose()
+    }
+
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        match listing {
+            Some(listing) => {
+                let mut encoded = Vec::new();
+                ciborium::ser::into_writer(listing, &mut encoded)
+                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+                self.listings.insert(room_id.to_be_bytes(), encoded)?
+            },
+            None => self.listings.remove(room_id.to_be_bytes())?,
+        };
+        self.flush()
+    }
+
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        self.listings
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry?;
+                let room_id: [u8; 16] = key[..]
+                    .try_into()
+                    .map_err(|_| StorageError::Serialization("corrupt listing key".to_string()))?;
+                let listing = ciborium::de::from_reader(&value[..])
+                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+                Ok((u128::from_be_bytes(room_id), listing))
+            })
+            .collect()
+    }
+
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        self.attachment_chunks.insert(chunk_key(room_id, content_hash, index), bytes)?;
+        self.flush()
+    }
+
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        let key = chunk_key(room_id, content_hash, index);
+        Ok(self.attachment_chunks.get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(attachment, &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        self.attachments.insert(attachment_key(room_id, content_hash), encoded)?;
+        self.flush()
+    }
+
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        self.attachments
+            .get(attachment_key(room_id, content_hash))?
+            .map(|value| decode_attachment(&value))
+            .transpose()
+    }
+
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        self.attachments
+            .scan_prefix(room_id.to_be_bytes())
+            .map(|entry| {
+                let (key, value) = entry?;
+                let content_hash =
+                    key.get(16..).and_then(|hash| <[u8; 32]>::try_from(hash).ok()).ok_or_else(
+                        || StorageError::Serialization("corrupt attachment key".to_string()),
+                    )?;
+                Ok((content_hash, decode_attachment(&value)?))
+            })
+            .collect()
+    }
+
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        // Record first, so a crash part way through leaves only unreachable
+        // chunks behind
+        self.attachments.remove(attachment_key(room_id, content_hash))?;
+        remove_prefix(&self.attachment_chunks, &attachment_key(room_id, content_hash))?;
+        self.flush()
+    }
+
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        remove_prefix(&self.attachments, &room_id.to_be_bytes())?;
+        remove_prefix(&self.attachment_chunks, &room_id.to_be_bytes())?;
+        self.flush()
+    }
+
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(record, &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        self.audit.insert(record.seq.to_be_bytes(), encoded)?;
+        Ok(())
+    }
+
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        self.audit
+            .range(from_seq.to_be_bytes()..)
+            .take(limit)
+            .map(|entry| {
+                let (_, value) = entry?;
+                ciborium::de::from_reader(&value[..])
+                    .map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .collect()
+    }
+
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        self.audit
+            .last()?
+            .map(|(_, value)| {
+                ciborium::de::from_reader(&value[..])
+                    .map(|record: AuditRecord| record.seq)
+                    .map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .transpose()
+    }
+
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        let mut encoded = BytesMut::new();
+        proposal.encode(&mut encoded).map_err(|e| StorageError::Serialization(e.to_string()))?;
+        self.pending_proposals
+            .insert(&frame_key(room_id, proposal.header.log_index())[..], &encoded[..])?;
+        self.flush()
+    }
+
+    /// The proposals are removed in one atomic batch.
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        let mut batch = sled::Batch::default();
+        for entry in self.pending_proposals.scan_prefix(room_id.to_be_bytes()) {
+            let (key, _) = entry?;
+            batch.remove(key);
+        }
+
+        self.pending_proposals.apply_batch(batch)?;
+        self.flush()
+    }
+
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        self.pending_proposals
+            .scan_prefix(room_id.to_be_bytes())
+            .map(|entry| {
+                let (_, value) = entry?;
+                Frame::decode(&value).map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .collect()
+    }
+
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        let previous = self.load_snapshot(room_id)?;
+        check_snapshot_index(
+            room_id,
+            up_to_index,
+            self.latest_log_index(room_id)?,
+            previous.as_ref(),
+        )?;
+
+        let mls_state = self.load_mls_state(room_id)?;
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(&(up_to_index, &mls_state), &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        self.snapshots.insert(room_id.to_be_bytes(), encoded)?;
+        self.flush()?;
+
+        Ok(RoomSnapshot { room_id, log_index: up_to_index, mls_state })
+    }
+
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        self.snapshots
+            .get(room_id.to_be_bytes())?
+            .map(|value| {
+                let (log_index, mls_state) = ciborium::de::from_reader(&value[..])
+                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+                Ok(RoomSnapshot { room_id, log_index, mls_state })
+            })
+            .transpose()
+    }
+
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        let Some(snapshot) = self.load_snapshot(room_id)? else {
+            return Ok(0);
+        };
+        let first_index = self.first_index(room_id)?;
+        let boundary = snapshot.first_retained_index();
+        if boundary <= first_index {
+            return Ok(0);
+        }
+
+        self.compacted.insert(room_id.to_be_bytes(), &boundary.to_be_bytes())?;
+        self.flush()?;
+
+        let mut batch = sled::Batch::default();
+        for entry in
+            self.frames.range(frame_key(room_id, first_index)..frame_key(room_id, boundary))
+        {
+            let (key, _) = entry?;
+            batch.remove(key);
+        }
+        self.frames.apply_batch(batch.clone())?;
+        self.checksums.apply_batch(batch)?;
+        self.flush()?;
+
+        Ok(boundary.saturating_sub(first_index))
+    }
+
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        let room_id = snapshot.room_id;
+        check_log_empty(self.latest_log_index(room_id)?, snapshot)?;
+
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(&(snapshot.log_index, &snapshot.mls_state), &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let boundary = snapshot.first_retained_index().to_be_bytes();
+        self.snapshots.insert(room_id.to_be_bytes(), encoded)?;
+        self.compacted.insert(room_id.to_be_bytes(), &boundary)?;
+        self.heads.insert(room_id.to_be_bytes(), &boundary)?;
+        self.flush()
+    }
+
+    /// sled rewrites segments freed by compaction in the background and has
+    /// no incremental vacuum, so `max_bytes` is not enforced; flushing lets
+    /// it release segments whose frames are gone.
+    fn vacuum(&self, _max_bytes: u64) -> Result<u64, StorageError> {
+        let before = self.db.size_on_disk()?;
+        self.flush()?;
+        Ok(before.saturating_sub(self.db.size_on_disk()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+    use crate::audit::AuditEvent;
+
+    fn create_test_frame(room_id: u128, log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_log_index(log_index);
+
+        Frame::new(header, Bytes::from(format!("frame-{log_index}")))
+    }
+
+    #[test]
+    fn test_frames_survive_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+
+        {
+            let storage = SledStorage::open(dir.path()).expect("open failed");
+            for i in 0..3 {
+                storage.store_frame(100, i, &create_test_frame(100, i)).expect("store failed");
+            }
+            storage.store_frame(200, 0, &create_test_frame(200, 0)).expect("store failed");
+        }
+
+        let storage = SledStorage::open(dir.path()).expect("reopen failed");
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(2));
+        assert_eq!(storage.latest_log_index(300).expect("query failed"), None);
+
+        let frames = storage.load_frames(100, 1, 10).expect("load failed");
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].header.log_index(), 1);
+        assert_eq!(frames[1].payload, Bytes::from("frame-2"));
+
+        // Room boundaries are respected
+        assert_eq!(storage.load_frames(200, 0, 10).expect("load failed").len(), 1);
+    }
+
+    #[test]
+    fn test_gap_rejected() {
+        let storage = SledStorage::temporary().expect("open failed");
+        storage.store_frame(100, 0, &create_test_frame(100, 0)).expect("store failed");
+
+        let result = storage.store_frame(100, 2, &create_test_frame(100, 2));
+        assert_eq!(result, Err(StorageError::Conflict { expected: 1, got: 2 }));
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(0));
+
+        assert!(matches!(
+            storage.load_frames(300, 0, 1),
+            Err(StorageError::NotFound { room_id: 300, .. })
+        ));
+    }
+
+    #[test]
+    fn test_batch_committed_atomically() {
+        let storage = SledStorage::temporary().expect("open failed");
+        let batch: Vec<Frame> = (0..4).map(|i| create_test_frame(100, i)).collect();
+        storage.store_frames_batch(100, &batch).expect("batch failed");
+
+        let stale = [create_test_frame(100, 2), create_test_frame(100, 3)];
+        let result = storage.store_frames_batch(100, &stale);
+        assert_eq!(result, Err(StorageError::Conflict { expected: 4, got: 2 }));
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(3));
+        assert_eq!(storage.load_frames(100, 0, 10).expect("load failed").len(), 4);
+    }
+
+    #[test]
+    fn test_damaged_frame_detected() {
+        let storage = SledStorage::temporary().expect("open failed");
+        let batch: Vec<Frame> = (0..3).map(|i| create_test_frame(100, i)).collect();
+        storage.store_frames_batch(100, &batch).expect("batch failed");
+        storage.store_frame(100, 3, &create_test_frame(100, 3)).expect("store failed");
+        assert_eq!(storage.verify(100).expect("verify failed"), 4);
+
+        // Flip a bit in the stored payload of frame 2
+        let key = frame_key(100, 2);
+        let mut damaged =
+            storage.frames.get(key).expect("get failed").expect("frame missing").to_vec();
+        if let Some(byte) = damaged.last_mut() {
+            *byte ^= 1;
+        }
+        storage.frames.insert(key, damaged).expect("insert failed");
+
+        let corrupted = StorageError::Corrupted { room_id: 100, log_index: 2 };
+        assert_eq!(storage.load_frames(100, 0, 10), Err(corrupted.clone()));
+        assert_eq!(storage.verify(100), Err(corrupted));
+        assert_eq!(storage.load_frames(100, 3, 10).expect("load failed").len(), 1);
+    }
+
+    #[test]
+    fn test_pending_proposals_appended_and_cleared() {
+        let storage = SledStorage::temporary().expect("open failed");
+        let proposals = [create_test_frame(100, 3), create_test_frame(100, 5)];
+        for proposal in &proposals {
+            storage.store_pending_proposal(100, proposal).expect("store failed");
+        }
+        storage.store_pending_proposal(200, &proposals[0]).expect("store failed");
+
+        // Storing a proposal again keeps one copy
+        storage.store_pending_proposal(100, &proposals[1]).expect("store failed");
+        assert_eq!(storage.load_pending_proposals(100).expect("load failed"), proposals);
+
+        storage.clear_pending_proposals(100).expect("clear failed");
+        assert!(storage.load_pending_proposals(100).expect("load failed").is_empty());
+        assert_eq!(storage.load_pending_proposals(200).expect("load failed"), &proposals[..1]);
+    }
+
+    #[test]
+    fn test_mls_state_survives_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let state = MlsGroupState::new(100, 3, [7u8; 32], vec![1, 2], vec![0xAB; 16]);
+
+        {
+            let storage = SledStorage::open(dir.path()).expect("open failed");
+            storage.store_mls_state(100, &state).expect("store failed");
+        }
+
+        let storage = SledStorage::open(dir.path()).expect("reopen failed");
+        assert_eq!(storage.load_mls_state(100).expect("load failed"), Some(state));
+        assert_eq!(storage.load_mls_state(200).expect("load failed"), None);
+    }
+
+    #[test]
+    fn test_audit_survives_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let record = |seq| AuditRecord {
+            seq,
+            at_millis: 1_000,
+            principal: None,
+            event: AuditEvent::RoomCreated { room_id: 100, creator: seq },
+        };
+
+        {
+            let storage = SledStorage::open(dir.path()).expect("open failed");
+            for seq in [0, 1, 256] {
+                storage.append_audit(&record(seq)).expect("append failed");
+            }
+        }
+
+        let storage = SledStorage::open(dir.path()).expect("reopen failed");
+        assert_eq!(storage.latest_audit_seq().expect("latest failed"), Some(256));
+        assert_eq!(storage.load_audit(1, 10).expect("load failed"), vec![record(1), record(256)]);
+    }
+
+    #[test]
+    fn test_usage_survives_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let mut window = UsageWindow { started_at_millis: 1_000, ..UsageWindow::default() };
+        window.rooms.entry(100).or_default().bytes_stored = 512;
+
+        {
+            let storage = SledStorage::open(dir.path()).expect("open failed");
+            storage.store_usage(&window).expect("store failed");
+        }
+
+        let storage = SledStorage::open(dir.path()).expect("reopen failed");
+        assert_eq!(storage.load_usage().expect("load failed"), Some(window));
+    }
+
+    #[test]
+    fn test_read_markers_survive_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+
+        {
+            let storage = SledStorage::open(dir.path()).expect("open failed");
+            storage.store_read_marker(100, 7, 3).expect("store failed");
+            storage.store_read_marker(100, 7, 5).expect("store failed");
+            storage.store_read_marker(100, 9, 1).expect("store failed");
+            storage.store_read_marker(200, 7, 9).expect("store failed");
+        }
+
+        let storage = SledStorage::open(dir.path()).expect("reopen failed");
+        let markers = storage.load_read_markers(100).expect("load failed");
+        assert_eq!(markers.into_iter().collect::<Vec<_>>(), vec![(7, 5), (9, 1)]);
+        assert!(storage.load_read_markers(300).expect("load failed").is_empty());
+    }
+
+    #[test]
+    fn test_attachments_survive_reopen_per_room() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let attachment = StoredAttachment { total_size: 6, chunk_size: 3, stored_at_millis: 9 };
+
+        {
+            let storage = SledStorage::open(dir.path()).expect("open failed");
+            storage.store_attachment_chunk(100, &[1; 32], 0, b"one").expect("store failed");
+            storage.store_attachment_chunk(100, &[1; 32], 1, b"two").expect("store failed");
+            storage.store_attachment_chunk(100, &[2; 32], 0, b"unfinished").expect("store failed");
+            storage.complete_attachment(100, &[1; 32], &attachment).expect("store failed");
+            storage.store_attachment_chunk(200, &[1; 32], 0, b"other room").expect("store failed");
+        }
+
+        let storage = SledStorage::open(dir.path()).expect("reopen failed");
+        assert_eq!(storage.load_attachment(100, &[1; 32]).expect("load failed"), Some(attachment));
+        assert_eq!(storage.load_attachment(100, &[2; 32]).expect("load failed"), None);
+        assert_eq!(storage.list_attachments(100).expect("load failed"), vec![(
+            [1; 32], attachment
+        )]);
+        let chunk = storage.load_attachment_chunk(100, &[1; 32], 1).expect("load failed");
+        assert_eq!(chunk.as_deref(), Some(&b"two"[..]));
+
+        storage.delete_attachment(100, &[1; 32]).expect("delete failed");
+        assert_eq!(storage.load_attachment_chunk(100, &[1; 32], 0).expect("load failed"), None);
+        storage.delete_attachments(100).expect("delete failed");
+        assert_eq!(storage.load_attachment_chunk(100, &[2; 32], 0).expect("load failed"), None);
+        assert!(storage.load_attachment_chunk(200, &[1; 32], 0).expect("load failed").is_some());
+    }
+
+    #[test]
+    fn test_compaction_survives_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let state = MlsGroupState::new(100, 1, [7u8; 32], vec![1], vec![]);
+
+        {
+            let storage = SledStorage::open(dir.path()).expect("open failed");
+            for i in 0..5 {
+                storage.store_frame(100, i, &create_test_frame(100, i)).expect("store failed");
+            }
+            storage.store_mls_state(100, &state).expect("store failed");
+            storage.snapshot(100, 2).expect("snapshot failed");
+            assert_eq!(storage.compact(100).expect("compact failed"), 3);
+            assert_eq!(storage.compact(100).expect("compact failed"), 0);
+        }
+
+        let storage = SledStorage::open(dir.path()).expect("reopen failed");
+        let snapshot = storage.load_snapshot(100).expect("load failed").expect("snapshot exists");
+        assert_eq!(snapshot.log_index, 2);
+        assert_eq!(snapshot.mls_state, Some(state));
+
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(4));
+        assert_eq!(
+            storage.load_frames(100, 1, 10),
+            Err(StorageError::Compacted { room_id: 100, first_index: 3 })
+        );
+        assert_eq!(storage.load_frames(100, 3, 10).expect("load failed").len(), 2);
+    }
+}
@@ -0,0 +1,661 @@
+//! Segmented in-memory storage for large rooms
+//!
+//! Splits each room's log into consecutive segments. The open segment takes
+//! new frames until it holds `max_frames` frames or its frames span more than
+//! `max_span_millis` of HLC time, then it is sealed and a new one is opened.
+//! A per-room manifest summarizes every segment (index range, time range,
+//! state), so the latest index and the segment holding any index are found
+//! without touching frames, and range loads only visit the segments they
+//! overlap.
+//!
+//! Sealed segments can be archived: their frames are removed and handed to
+//! the caller for cold storage, while the manifest keeps their summary.
+//! Loading an archived range fails with [`StorageError::Archived`] until the
+//! segment is restored.
+//!
+//! Compaction works a segment at a time: sealed segments wholly covered by
+//! the room's snapshot are dropped, manifest entry included.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use lockframe_core::{hlc::HlcTimestamp, mls::MlsGroupState};
+use lockframe_proto::Frame;
+
+use super::{RoomSnapshot, Storage, StorageError, check_snapshot_index};
+
+/// Default maximum frames per segment.
+pub const DEFAULT_SEGMENT_FRAMES: usize = 4096;
+
+/// Default maximum HLC time covered by one segment (1 hour).
+pub const DEFAULT_SEGMENT_SPAN_MILLIS: u64 = 60 * 60 * 1000;
+
+/// When the open segment of a room is sealed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentConfig {
+    /// Seal once the segment holds this many frames
+    pub max_frames: usize,
+    /// Seal before a frame stamped more than this long after the segment's
+    /// earliest frame
+    pub max_span_millis: u64,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        Self { max_frames: DEFAULT_SEGMENT_FRAMES, max_span_millis: DEFAULT_SEGMENT_SPAN_MILLIS }
+    }
+}
+
+/// Manifest entry summarizing one segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentInfo {
+    /// Log index of the first frame
+    pub first_index: u64,
+    /// Number of frames
+    pub frame_count: u64,
+    /// Earliest and latest HLC physical time of stamped frames, if any
+    pub time_range: Option<(u64, u64)>,
+    /// No more frames will be appended
+    pub sealed: bool,
+    /// Frames have been moved out by [`SegmentedStorage::archive_before`]
+    pub archived: bool,
+}
+
+impl SegmentInfo {
+    /// Log index one past the last frame.
+    pub fn end_index(&self) -> u64 {
+        self.first_index.saturating_add(self.frame_count)
+    }
+
+    fn contains(&self, log_index: u64) -> bool {
+        (self.first_index..self.end_index()).contains(&log_index)
+    }
+}
+
+/// Frames of a sealed segment removed from storage
+#[derive(Debug, Clone)]
+pub struct ArchivedSegment {
+    /// Room the segment belongs to
+    pub room_id: u128,
+    /// Manifest entry at the time of archival
+    pub info: SegmentInfo,
+    /// Frames in log index order
+    pub frames: Vec<Frame>,
+}
+
+/// Segmented in-memory storage
+///
+/// Same contract as [`MemoryStorage`](super::MemoryStorage), with each room's
+/// log partitioned into segments described by a manifest. Shares state via
+/// `Arc<Mutex<>>` and panics if the mutex is poisoned.
+#[derive(Clone)]
+pub struct SegmentedStorage {
+    inner: Arc<Mutex<SegmentedStorageInner>>,
+}
+
+struct SegmentedStorageInner {
+    config: SegmentConfig,
+
+    /// Segmented log per room
+    rooms: HashMap<u128, RoomSegments>,
+
+    /// MLS group state per room
+    mls_states: HashMap<u128, MlsGroupState>,
+
+    /// Proposals not yet covered by a commit, per room
+    pending_proposals: HashMap<u128, BTreeMap<u64, Frame>>,
+
+    /// Latest snapshot per room
+    snapshots: HashMap<u128, RoomSnapshot>,
+}
+
+/// A room's segments in log order
+#[derive(Default)]
+struct RoomSegments {
+    segments: Vec<Segment>,
+}
+
+/// Manifest entry and frames of one segment. Archived segments have no
+/// frames.
+struct Segment {
+    info: SegmentInfo,
+    frames: Option<Vec<Frame>>,
+}
+
+impl RoomSegments {
+    fn next_index(&self) -> u64 {
+        self.segments.last().map_or(0, |segment| segment.info.end_index())
+    }
+
+    /// Position of the segment holding `log_index`.
+    fn position(&self, log_index: u64) -> Option<usize> {
+        let pos = self.segments.partition_point(|segment| segment.info.end_index() <= log_index);
+        self.segments.get(pos).filter(|segment| segment.info.contains(log_index)).map(|_| pos)
+    }
+
+    /// Seal the open segment and start a new one if the next frame (stamped
+    /// `millis`) doesn't fit.
+    fn roll_segment(&mut self, config: &SegmentConfig, millis: Option<u64>) {
+        let fits = self.segments.last().is_some_and(|segment| {
+            let info = &segment.info;
+            let full = info.frame_count >= config.max_frames as u64;
+            let too_long = info.time_range.zip(millis).is_some_and(|((first, _), millis)| {
+                millis.saturating_sub(first) > config.max_span_millis
+            });
+            !full && !too_long
+        });
+
+        if !fits {
+            let first_index = self.next_index();
+            if let Some(open) = self.segments.last_mut() {
+                open.info.sealed = true;
+            }
+            self.segments.push(Segment {
+                info: SegmentInfo {
+                    first_index,
+                    frame_count: 0,
+                    time_range: None,
+                    sealed: false,
+                    archived: false,
+                },
+                frames: Some(Vec::new()),
+            });
+        }
+    }
+
+    fn append(&mut self, config: &SegmentConfig, frame: &Frame) {
+        let millis = frame_millis(frame);
+        self.roll_segment(config, millis);
+
+        let Some(segment) = self.segments.last_mut() else {
+            return;
+        };
+
+        segment.info.frame_count += 1;
+        if let Some(millis) = millis {
+            segment.info.time_range =
+                Some(segment.info.time_range.map_or((millis, millis), |(first, last)| {
+                    (first.min(millis), last.max(millis))
+                }));
+        }
+
+        // The open segment is never archived
+        segment.frames.get_or_insert_with(Vec::new).push(frame.clone());
+    }
+}
+
+/// HLC physical time of a frame, `None` if the frame is unstamped.
+fn frame_millis(frame: &Frame) -> Option<u64> {
+    let millis = HlcTimestamp::from_u64(frame.header.hlc_timestamp()).physical_millis();
+    (millis != 0).then_some(millis)
+}
+
+impl SegmentedStorage {
+    /// Create empty storage with the default segment limits
+    pub fn new() -> Self {
+        Self::with_config(SegmentConfig::default())
+    }
+
+    /// Create empty storage with custom segment limits
+    pub fn with_config(config: SegmentConfig) -> Self {
+        debug_assert!(config.max_frames > 0);
+
+        Self {
+            inner: Arc::new(Mutex::new(SegmentedStorageInner {
+                config,
+                rooms: HashMap::new(),
+                mls_states: HashMap::new(),
+                pending_proposals: HashMap::new(),
+                snapshots: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Manifest of a room's segments in log order. Empty for unknown rooms.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn manifest(&self, room_id: u128) -> Vec<SegmentInfo> {
+        let inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+        inner
+            .rooms
+            .get(&room_id)
+            .map(|room| room.segments.iter().map(|segment| segment.info).collect())
+            .unwrap_or_default()
+    }
+
+    /// Archive every sealed segment that ends at or before `log_index`.
+    ///
+    /// Returns the archived segments' frames, oldest first. The manifest
+    /// keeps their entries and `latest_log_index` is unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn archive_before(&self, room_id: u128, log_index: u64) -> Vec<ArchivedSegment> {
+        self.archive_where(room_id, |info| info.end_index() <= log_index)
+    }
+
+    /// Archive every sealed segment whose newest stamped frame is older than
+    /// `millis`. Segments without stamped frames are kept.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn archive_older_than(&self, room_id: u128, millis: u64) -> Vec<ArchivedSegment> {
+        self.archive_where(room_id, |info| info.time_range.is_some_and(|(_, last)| last < millis))
+    }
+
+    /// Put an archived segment back so its range can be loaded again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn restore(&self, segment: ArchivedSegment) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+        let ArchivedSegment { room_id, info, frames } = segment;
+
+        let room = inner
+            .rooms
+            .get_mut(&room_id)
+            .ok_or(StorageError::NotFound { room_id, log_index: info.first_index })?;
+
+        let segment = room
+            .position(info.first_index)
+            .and_then(|pos| room.segments.get_mut(pos))
+            .filter(|segment| segment.info == info)
+            .ok_or(StorageError::NotFound { room_id, log_index: info.first_index })?;
+
+        if frames.len() as u64 != info.frame_count {
+            return Err(StorageError::Conflict {
+                expected: info.frame_count,
+                got: frames.len() as u64,
+            });
+        }
+
+        segment.info.archived = false;
+        segment.frames = Some(frames);
+        Ok(())
+    }
+
+    fn archive_where(
+        &self,
+        room_id: u128,
+        select: impl Fn(&SegmentInfo) -> bool,
+    ) -> Vec<ArchivedSegment> {
+        let mut inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+        let Some(room) = inner.rooms.get_mut(&room_id) else {
+            return Vec::new();
+        };
+
+        let mut archived = Vec::new();
+        for segment in &mut room.segments {
+            if !segment.info.sealed || !select(&segment.info) {
+                continue;
+            }
+
+            let Some(frames) = segment.frames.take() else {
+                continue;
+            };
+            segment.info.archived = true;
+            archived.push(ArchivedSegment { room_id, info: segment.info, frames });
+        }
+
+        archived
+    }
+}
+
+impl Default for SegmentedStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Storage for SegmentedStorage {
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_frame(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+    ) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+        let config = inner.config;
+
+        let room = inner.rooms.entry(room_id).or_default();
+
+        let expected = room.next_index();
+        if log_index != expected {
+            return Err(StorageError::Conflict { expected, got: log_index });
+        }
+
+        room.append(&config, frame);
+
+        debug_assert_eq!(room.next_index(), log_index + 1);
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        let inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        Ok(inner.rooms.get(&room_id).and_then(|room| room.next_index().checked_sub(1)))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_frames(
+        &self,
+        room_id: u128,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<Frame>, StorageError> {
+        let inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        let room =
+            inner.rooms.get(&room_id).ok_or(StorageError::NotFound { room_id, log_index: from })?;
+
+        let first_index = room.segments.first().map_or(0, |segment| segment.info.first_index);
+        if from < first_index {
+            return Err(StorageError::Compacted { room_id, first_index });
+        }
+
+        let Some(start) = room.position(from) else {
+            return Ok(Vec::new());
+        };
+
+        let mut loaded = Vec::with_capacity(limit.min(DEFAULT_SEGMENT_FRAMES));
+        let mut next = from;
+
+        for segment in room.segments.iter().skip(start) {
+            let remaining = limit.saturating_sub(loaded.len());
+            if remaining == 0 {
+                break;
+            }
+
+            let frames = segment
+                .frames
+                .as_ref()
+                .ok_or(StorageError::Archived { room_id, log_index: next })?;
+
+            let offset = usize::try_from(next - segment.info.first_index).unwrap_or(usize::MAX);
+            let before = loaded.len();
+            loaded.extend(frames.iter().skip(offset).take(remaining).cloned());
+            next += (loaded.len() - before) as u64;
+        }
+
+        Ok(loaded)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        inner.mls_states.insert(room_id, state.clone());
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+        let inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        Ok(inner.mls_states.get(&room_id).cloned())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        let proposals = inner.pending_proposals.entry(room_id).or_default();
+        proposals.insert(proposal.header.log_index(), proposal.clone());
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        inner.pending_proposals.remove(&room_id);
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        let inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        Ok(inner
+            .pending_proposals
+            .get(&room_id)
+            .map(|proposals| proposals.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        let mut inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        let latest = inner.rooms.get(&room_id).and_then(|room| room.next_index().checked_sub(1));
+        check_snapshot_index(room_id, up_to_index, latest, inner.snapshots.get(&room_id))?;
+
+        let snapshot = RoomSnapshot {
+            room_id,
+            log_index: up_to_index,
+            mls_state: inner.mls_states.get(&room_id).cloned(),
+        };
+        inner.snapshots.insert(room_id, snapshot.clone());
+
+        Ok(snapshot)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        let inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        Ok(inner.snapshots.get(&room_id).cloned())
+    }
+
+    /// Drops sealed segments that end at or before the snapshot boundary.
+    /// Frames sharing a segment with retained frames are kept.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        let mut inner = self.inner.lock().expect("SegmentedStorage mutex poisoned");
+
+        let Some(boundary) = inner.snapshots.get(&room_id).map(RoomSnapshot::first_retained_index)
+        else {
+            return Ok(0);
+        };
+        let Some(room) = inner.rooms.get_mut(&room_id) else {
+            return Ok(0);
+        };
+
+        let covered = room
+            .segments
+            .iter()
+            .take_while(|segment| segment.info.sealed && segment.info.end_index() <= boundary)
+            .count();
+        let dropped = room.segments.drain(..covered).map(|segment| segment.info.frame_count).sum();
+
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+
+    fn create_test_frame(room_id: u128, log_index: u64, millis: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_log_index(log_index);
+        header.set_hlc_timestamp(HlcTimestamp::new(millis, 0).as_u64());
+
+        Frame::new(header, Bytes::new())
+    }
+
+    fn storage_with(config: SegmentConfig, room_id: u128, count: u64) -> SegmentedStorage {
+        let storage = SegmentedStorage::with_config(config);
+        for i in 0..count {
+            let frame = create_test_frame(room_id, i, 1_000 + i);
+            storage.store_frame(room_id, i, &frame).expect("store failed");
+        }
+        storage
+    }
+
+    fn small_segments() -> SegmentConfig {
+        SegmentConfig { max_frames: 4, max_span_millis: DEFAULT_SEGMENT_SPAN_MILLIS }
+    }
+
+    #[test]
+    fn test_segments_seal_at_frame_limit() {
+        let storage = storage_with(small_segments(), 100, 10);
+
+        let manifest = storage.manifest(100);
+        let ranges: Vec<_> = manifest.iter().map(|s| (s.first_index, s.frame_count)).collect();
+        assert_eq!(ranges, vec![(0, 4), (4, 4), (8, 2)]);
+        assert!(manifest[0].sealed && manifest[1].sealed && !manifest[2].sealed);
+        assert_eq!(manifest[1].time_range, Some((1_004, 1_007)));
+
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(9));
+    }
+
+    #[test]
+    fn test_segments_seal_at_time_span() {
+        let storage =
+            SegmentedStorage::with_config(SegmentConfig { max_frames: 100, max_span_millis: 50 });
+
+        for (i, millis) in [1_000, 1_020, 1_050, 1_051, 1_200].into_iter().enumerate() {
+            let frame = create_test_frame(100, i as u64, millis);
+            storage.store_frame(100, i as u64, &frame).expect("store failed");
+        }
+
+        let counts: Vec<_> = storage.manifest(100).iter().map(|s| s.frame_count).collect();
+        assert_eq!(counts, vec![3, 1, 1]);
+    }
+
+    #[test]
+    fn test_load_frames_spans_segments() {
+        let storage = storage_with(small_segments(), 100, 10);
+
+        let frames = storage.load_frames(100, 2, 7).expect("load failed");
+        let indices: Vec<_> = frames.iter().map(|f| f.header.log_index()).collect();
+        assert_eq!(indices, (2..9).collect::<Vec<_>>());
+
+        assert_eq!(storage.load_frames(100, 8, 100).expect("load failed").len(), 2);
+        assert!(storage.load_frames(100, 10, 10).expect("load failed").is_empty());
+        assert!(storage.load_frames(100, 3, 0).expect("load failed").is_empty());
+    }
+
+    #[test]
+    fn test_archive_and_restore() {
+        let storage = storage_with(small_segments(), 100, 10);
+
+        // The open segment and segments reaching past the cutoff stay
+        let archived = storage.archive_before(100, 7);
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].frames.len(), 4);
+
+        assert!(storage.manifest(100)[0].archived);
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(9));
+        assert_eq!(storage.load_frames(100, 4, 10).expect("load failed").len(), 6);
+        assert!(matches!(
+            storage.load_frames(100, 1, 10),
+            Err(StorageError::Archived { room_id: 100, log_index: 1 })
+        ));
+
+        // Appending continues after archival
+        let frame = create_test_frame(100, 10, 2_000);
+        storage.store_frame(100, 10, &frame).expect("store failed");
+
+        for segment in archived {
+            storage.restore(segment).expect("restore failed");
+        }
+        assert_eq!(storage.load_frames(100, 0, 100).expect("load failed").len(), 11);
+    }
+
+    #[test]
+    fn test_archive_older_than_keeps_recent_segments() {
+        let storage = storage_with(small_segments(), 100, 10);
+
+        let archived = storage.archive_older_than(100, 1_007);
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].info.first_index, 0);
+
+        assert_eq!(storage.archive_older_than(100, u64::MAX).len(), 1);
+        assert!(!storage.manifest(100)[2].archived, "open segment is never archived");
+    }
+
+    #[test]
+    fn test_restore_rejects_mismatched_segment() {
+        let storage = storage_with(small_segments(), 100, 10);
+
+        let mut archived = storage.archive_before(100, 4).pop().expect("one segment archived");
+        archived.frames.pop();
+        assert!(matches!(storage.restore(archived), Err(StorageError::Conflict { .. })));
+    }
+
+    #[test]
+    fn test_compact_drops_covered_segments() {
+        let storage = storage_with(small_segments(), 100, 10);
+
+        storage.snapshot(100, 5).expect("snapshot failed");
+        assert_eq!(storage.compact(100).expect("compact failed"), 4);
+
+        // Segment [4, 8) straddles the boundary and is kept whole
+        let firsts: Vec<_> = storage.manifest(100).iter().map(|s| s.first_index).collect();
+        assert_eq!(firsts, vec![4, 8]);
+        assert_eq!(
+            storage.load_frames(100, 2, 10),
+            Err(StorageError::Compacted { room_id: 100, first_index: 4 })
+        );
+        assert_eq!(storage.load_frames(100, 4, 10).expect("load failed").len(), 6);
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(9));
+
+        // The open segment is never dropped
+        storage.snapshot(100, 9).expect("snapshot failed");
+        assert_eq!(storage.compact(100).expect("compact failed"), 4);
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(9));
+    }
+
+    #[test]
+    fn test_conflict_on_gap() {
+        let storage = storage_with(small_segments(), 100, 5);
+
+        let frame = create_test_frame(100, 6, 2_000);
+        assert_eq!(
+            storage.store_frame(100, 6, &frame),
+            Err(StorageError::Conflict { expected: 5, got: 6 })
+        );
+    }
+}
@@ -0,0 +1,1407 @@
+//! SQLite storage for single-node deployments
+//!
+//! Frames and pending proposals are keyed by `(room_id, log_index)` and MLS
+//! state by `room_id`.
+//! The schema is created and upgraded by migrations embedded in the binary,
+//! tracked with SQLite's `user_version` pragma, so opening an older database
+//! brings it up to date.
+//!
+//! Every write runs in a transaction on a WAL-mode database with full
+//! synchronous commits. [`Storage::store_commit`] writes a frame and the MLS
+//! state it produced together, so a crash mid-commit leaves neither.
+//! Each frame is stored with a CRC-32 that loads check, so a damaged frame
+//! fails with [`StorageError::Corrupted`] instead of being returned.
+//!
+//! New databases use incremental auto-vacuum, so pages freed by compaction
+//! are returned to the file system a bounded number at a time by
+//! [`Storage::vacuum`]. Databases created without it are converted by the
+//! first vacuum that finds free pages, which rewrites the whole file once.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use bytes::BytesMut;
+use lockframe_core::mls::MlsGroupState;
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+
+use super::{
+    RoomSnapshot, Storage, StorageError, check_log_empty, check_snapshot_index,
+    decode_stored_frame, frame_checksum,
+};
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
+
+/// Schema migrations, applied in order. Entry `i` upgrades the database from
+/// `user_version` `i` to `i + 1`. Never edit a released entry; append a new
+/// one.
+const MIGRATIONS: &[&str] = &[
+    // 1: frames and MLS state
+    "CREATE TABLE frames (
+        room_id BLOB NOT NULL,
+        log_index INTEGER NOT NULL,
+        frame BLOB NOT NULL,
+        PRIMARY KEY (room_id, log_index)
+    ) WITHOUT ROWID;
+    CREATE TABLE mls_states (
+        room_id BLOB PRIMARY KEY,
+        state BLOB NOT NULL
+    ) WITHOUT ROWID;",
+    // 2: snapshots and compaction boundaries
+    "CREATE TABLE snapshots (
+        room_id BLOB PRIMARY KEY,
+        log_index INTEGER NOT NULL,
+        mls_state BLOB,
+        first_retained INTEGER NOT NULL DEFAULT 0
+    ) WITHOUT ROWID;",
+    // 3: proposals not yet covered by a commit
+    "CREATE TABLE pending_proposals (
+        room_id BLOB NOT NULL,
+        log_index INTEGER NOT NULL,
+        frame BLOB NOT NULL,
+        PRIMARY KEY (room_id, log_index)
+    ) WITHOUT ROWID;",
+    // 4: frame checksums, NULL for frames stored before this migration
+    "ALTER TABLE frames ADD COLUMN checksum INTEGER;",
+    // 5: open usage window, a single row
+    "CREATE TABLE usage (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        window BLOB NOT NULL
+    );",
+    // 6: audit log, append only
+    "CREATE TABLE audit (
+        seq INTEGER PRIMARY KEY,
+        record BLOB NOT NULL
+    );",
+    // 7: each member's read position per room
+    "CREATE TABLE read_markers (
+        room_id BLOB NOT NULL,
+        member_id BLOB NOT NULL,
+        log_index INTEGER NOT NULL,
+        PRIMARY KEY (room_id, member_id)
+    ) WITHOUT ROWID;",
+    // 8: attachments by content hash
+    "CREATE TABLE attachments (
+        content_hash BLOB PRIMARY KEY,
+        bytes BLOB NOT NULL
+    );",
+    // 9: attachments per room, stored a chunk at a time; attachments stored
+    // whole can't be tied to a room and are dropped
+    "DROP TABLE attachments;
+    CREATE TABLE attachments (
+        room_id BLOB NOT NULL,
+        content_hash BLOB NOT NULL,
+        total_size INTEGER NOT NULL,
+        chunk_size INTEGER NOT NULL,
+        stored_at_millis INTEGER NOT NULL,
+        PRIMARY KEY (room_id, content_hash)
+    ) WITHOUT ROWID;
+    CREATE TABLE attachment_chunks (
+        room_id BLOB NOT NULL,
+        content_hash BLOB NOT NULL,
+        chunk_index INTEGER NOT NULL,
+        bytes BLOB NOT NULL,
+        PRIMARY KEY (room_id, content_hash, chunk_index)
+    ) WITHOUT ROWID;",
+    // 10: rooms migrated to another server
+    "CREATE TABLE moved_rooms (
+        room_id BLOB PRIMARY KEY,
+        moved BLOB NOT NULL
+    ) WITHOUT ROWID;",
+    // 11: first log index per room not yet accepted by its archiver
+    "CREATE TABLE archive_cursors (
+        room_id BLOB PRIMARY KEY,
+        log_index INTEGER NOT NULL
+    ) WITHOUT ROWID;",
+    // 12: directory listings of published rooms
+    "CREATE TABLE listings (
+        room_id BLOB PRIMARY KEY,
+        listing BLOB NOT NULL
+    ) WITHOUT ROWID;",
+];
+
+/// `auto_vacuum` pragma value for incremental vacuum
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+/// Storage in a SQLite database
+///
+/// Clones share one connection behind `Arc<Mutex<>>`; the storage panics if
+/// that mutex is poisoned.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Open or create the database file at `path` and apply pending
+    /// migrations
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Create a private in-memory database
+    ///
+    /// Useful for testing.
+    pub fn in_memory() -> Result<Self, StorageError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut conn: Connection) -> Result<Self, StorageError> {
+        // Only takes effect on a database that is still empty
+        conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "FULL")?;
+        migrate(&mut conn)?;
+
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Schema version of the open database
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn schema_version(&self) -> Result<usize, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        schema_version(&conn)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
+
+fn schema_version(conn: &Connection) -> Result<usize, StorageError> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    usize::try_from(version)
+        .map_err(|_| StorageError::Serialization(format!("invalid schema version {version}")))
+}
+
+/// Apply every migration newer than the database's schema version.
+fn migrate(conn: &mut Connection) -> Result<(), StorageError> {
+    let current = schema_version(conn)?;
+    if current > MIGRATIONS.len() {
+        return Err(StorageError::Serialization(format!(
+            "database schema version {current} is newer than supported version {}",
+            MIGRATIONS.len()
+        )));
+    }
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version.saturating_add(1))?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+fn to_sql_index(log_index: u64) -> Result<i64, StorageError> {
+    i64::try_from(log_index)
+        .map_err(|_| StorageError::Serialization(format!("log index {log_index} out of range")))
+}
+
+fn to_sql_seq(seq: u64) -> Result<i64, StorageError> {
+    i64::try_from(seq)
+        .map_err(|_| StorageError::Serialization(format!("audit sequence {seq} out of range")))
+}
+
+/// Latest log index of a room, counting frames dropped by compaction.
+fn latest_index(conn: &Connection, room_id: u128) -> Result<Option<u64>, StorageError> {
+    let latest: Option<i64> = conn.query_row(
+        "SELECT MAX(latest) FROM (
+             SELECT MAX(log_index) AS latest FROM frames WHERE room_id = ?1
+             UNION ALL
+             SELECT first_retained - 1 FROM snapshots WHERE room_id = ?1 AND first_retained > 0
+         )",
+        params![room_id.to_be_bytes()],
+        |row| row.get(0),
+    )?;
+
+    Ok(latest.and_then(|i| u64::try_from(i).ok()))
+}
+
+fn next_index(tx: &Transaction<'_>, room_id: u128) -> Result<u64, StorageError> {
+    Ok(latest_index(tx, room_id)?.map_or(0, |i| i.saturating_add(1)))
+}
+
+/// First log index still stored for a room.
+fn first_retained(conn: &Connection, room_id: u128) -> Result<u64, StorageError> {
+    let first: Option<i64> = conn
+        .query_row(
+            "SELECT first_retained FROM snapshots WHERE room_id = ?1",
+            params![room_id.to_be_bytes()],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    Ok(first.and_then(|i| u64::try_from(i).ok()).unwrap_or(0))
+}
+
+fn decode_mls_state(bytes: &[u8]) -> Result<MlsGroupState, StorageError> {
+    ciborium::de::from_reader(bytes).map_err(|e| StorageError::Serialization(e.to_string()))
+}
+
+fn load_mls_state(conn: &Connection, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+    let encoded: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT state FROM mls_states WHERE room_id = ?1",
+            params![room_id.to_be_bytes()],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    encoded.map(|bytes| decode_mls_state(&bytes)).transpose()
+}
+
+fn load_snapshot(conn: &Connection, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+    let row: Option<(i64, Option<Vec<u8>>)> = conn
+        .query_row(
+            "SELECT log_index, mls_state FROM snapshots WHERE room_id = ?1",
+            params![room_id.to_be_bytes()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    row.map(|(log_index, mls_state)| {
+        Ok(RoomSnapshot {
+            room_id,
+            log_index: u64::try_from(log_index).map_err(|_| {
+                StorageError::Serialization(format!("invalid snapshot index {log_index}"))
+            })?,
+            mls_state: mls_state.map(|bytes| decode_mls_state(&bytes)).transpose()?,
+        })
+    })
+    .transpose()
+}
+
+fn insert_frame(
+    tx: &Transaction<'_>,
+    room_id: u128,
+    log_index: u64,
+    frame: &Frame,
+) -> Result<(), StorageError> {
+    let expected = next_index(tx, room_id)?;
+    if log_index != expected {
+        return Err(StorageError::Conflict { expected, got: log_index });
+    }
+
+    let mut encoded = BytesMut::new();
+    frame.encode(&mut encoded).map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+    tx.execute(
+        "INSERT INTO frames (room_id, log_index, frame, checksum) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            room_id.to_be_bytes(),
+            to_sql_index(log_index)?,
+            &encoded[..],
+            frame_checksum(&encoded)
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn upsert_mls_state(
+    tx: &Transaction<'_>,
+    room_id: u128,
+    state: &MlsGroupState,
+) -> Result<(), StorageError> {
+    let mut encoded = Vec::new();
+    ciborium::ser::into_writer(state, &mut encoded)
+        .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+    tx.execute(
+        "INSERT INTO mls_states (room_id, state) VALUES (?1, ?2)
+         ON CONFLICT (room_id) DO UPDATE SET state = excluded.state",
+        params![room_id.to_be_bytes(), encoded],
+    )?;
+
+    Ok(())
+}
+
+/// Read a non-negative integer pragma.
+fn pragma_u64(conn: &Connection, pragma: &str) -> Result<u64, StorageError> {
+    let value: i64 = conn.pragma_query_value(None, pragma, |row| row.get(0))?;
+    u64::try_from(value).map_err(|e| StorageError::Io(format!("pragma {pragma}: {e}")))
+}
+
+impl Storage for SqliteStorage {
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_frame(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+    ) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let tx = conn.transaction()?;
+        insert_frame(&tx, room_id, log_index, frame)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let tx = conn.transaction()?;
+        for frame in frames {
+            insert_frame(&tx, room_id, frame.header.log_index(), frame)?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        latest_index(&conn, room_id)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_frames(
+        &self,
+        room_id: u128,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<Frame>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+
+        if latest_index(&conn, room_id)?.is_none() {
+            return Err(StorageError::NotFound { room_id, log_index: from });
+        }
+        let first_index = first_retained(&conn, room_id)?;
+        if from < first_index {
+            return Err(StorageError::Compacted { room_id, first_index });
+        }
+
+        let Ok(from) = i64::try_from(from) else {
+            return Ok(Vec::new());
+        };
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+        let mut stmt = conn.prepare_cached(
+            "SELECT log_index, frame, checksum FROM frames WHERE room_id = ?1 AND log_index >= ?2
+             ORDER BY log_index LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![room_id.to_be_bytes(), from, limit], |row| {
+            Ok((row.get::<_, u64>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, Option<u32>>(2)?))
+        })?;
+
+        rows.map(|row| {
+            let (log_index, bytes, checksum) = row?;
+            decode_stored_frame(room_id, log_index, &bytes, checksum)
+        })
+        .collect()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let tx = conn.transaction()?;
+        upsert_mls_state(&tx, room_id, state)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Both are written in one transaction, so either both are persisted or
+    /// neither is.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_commit(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+        state: &MlsGroupState,
+    ) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let tx = conn.transaction()?;
+        insert_frame(&tx, room_id, log_index, frame)?;
+        upsert_mls_state(&tx, room_id, state)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        load_mls_state(&conn, room_id)
+    }
+
+    /// Commits are already durable; this moves the write-ahead log into the
+    /// database file so the file alone holds everything.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn flush(&self) -> Result<(), StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(window, &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        conn.execute(
+            "INSERT INTO usage (id, window) VALUES (0, ?1)
+             ON CONFLICT (id) DO UPDATE SET window = excluded.window",
+            params![encoded],
+        )?;
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let encoded: Option<Vec<u8>> = conn
+            .query_row("SELECT window FROM usage WHERE id = 0", [], |row| row.get(0))
+            .optional()?;
+
+        encoded
+            .map(|bytes| {
+                ciborium::de::from_reader(&bytes[..])
+                    .map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .transpose()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        conn.execute(
+            "INSERT INTO read_markers (room_id, member_id, log_index) VALUES (?1, ?2, ?3)
+             ON CONFLICT (room_id, member_id) DO UPDATE SET log_index = excluded.log_index",
+            params![room_id.to_be_bytes(), member_id.to_be_bytes(), to_sql_index(up_to_log_index)?],
+        )?;
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let mut stmt = conn
+            .prepare_cached("SELECT member_id, log_index FROM read_markers WHERE room_id = ?1")?;
+        let rows = stmt.query_map(params![room_id.to_be_bytes()], |row| {
+            Ok((row.get::<_, [u8; 8]>(0)?, row.get::<_, i64>(1)?))
+        })?;
+
+        rows.map(|row| {
+            let (member_id, log_index) = row?;
+            let log_index = u64::try_from(log_index).map_err(|_| {
+                StorageError::Serialization(format!("invalid read marker {log_index}"))
+            })?;
+            Ok((u64::from_be_bytes(member_id), log_index))
+        })
+        .collect()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(moved, &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        conn.execute(
+            "INSERT INTO moved_rooms (room_id, moved) VALUES (?1, ?2)
+             ON CONFLICT (room_id) DO UPDATE SET moved = excluded.moved",
+            params![room_id.to_be_bytes(), encoded],
+        )?;
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let mut stmt = conn.prepare_cached("SELECT room_id, moved FROM moved_rooms")?;
+        let rows =
+            stmt.query_map([], |row| Ok((row.get::<_, [u8; 16]>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+
+        rows.map(|row| {
+            let (room_id, moved) = row?;
+            let moved = ciborium::de::from_reader(&moved[..])
+                .map_err(|e| StorageError::Serialization(e.to_string()))?;
+            Ok((u128::from_be_bytes(room_id), moved))
+        })
+        .collect()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        match next_log_index {
+            Some(next_log_index) => conn.execute(
+                "INSERT INTO archive_cursors (room_id, log_index) VALUES (?1, ?2)
+                 ON CONFLICT (room_id) DO UPDATE SET log_index = excluded.log_index",
+                params![room_id.to_be_bytes(), to_sql_index(next_log_index)?],
+            )?,
+            None => conn.execute("DELETE FROM archive_cursors WHERE room_id = ?1", params![
+                room_id.to_be_bytes()
+            ])?,
+        };
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let log_index: Option<i64> = conn
+            .query_row(
+                "SELECT log_index FROM archive_cursors WHERE room_id = ?1",
+                params![room_id.to_be_bytes()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        log_index
+            .map(|log_index| {
+                u64::try_from(log_index).map_err(|_| {
+                    StorageError::Serialization(format!("invalid archive cursor {log_index}"))
+                })
+            })
+            .transpose()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        match listing {
+            Some(listing) => {
+                let mut encoded = Vec::new();
+                ciborium::ser::into_writer(listing, &mut encoded)
+                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+                conn.execute(
+                    "INSERT INTO listings (room_id, listing) VALUES (?1, ?2)
+                     ON CONFLICT (room_id) DO UPDATE SET listing = excluded.listing",
+                    params![room_id.to_be_bytes(), encoded],
+                )?
+            },
+            None => conn.execute("DELETE FROM listings WHERE room_id = ?1", params![
+                room_id.to_be_bytes()
+            ])?,
+        };
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let mut stmt = conn.prepare_cached("SELECT room_id, listing FROM listings")?;
+        let rows =
+            stmt.query_map([], |row| Ok((row.get::<_, [u8; 16]>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+
+        rows.map(|row| {
+            let (room_id, listing) = row?;
+            let listing = ciborium::de::from_reader(&listing[..])
+                .map_err(|e| StorageError::Serialization(e.to_string()))?;
+            Ok((u128::from_be_bytes(room_id), listing))
+        })
+        .collect()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        conn.execute(
+            "INSERT OR REPLACE INTO attachment_chunks (room_id, content_hash, chunk_index, bytes)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![room_id.to_be_bytes(), &content_hash[..], index, bytes],
+        )?;
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        Ok(conn
+            .query_row(
+                "SELECT bytes FROM attachment_chunks
+                 WHERE room_id = ?1 AND content_hash = ?2 AND chunk_index = ?3",
+                params![room_id.to_be_bytes(), &content_hash[..], index],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        conn.execute(
+            "INSERT OR REPLACE INTO attachments
+             (room_id, content_hash, total_size, chunk_size, stored_at_millis)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                room_id.to_be_bytes(),
+                &content_hash[..],
+                attachment.total_size,
+                attachment.chunk_size,
+                attachment.stored_at_millis,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        Ok(conn
+            .query_row(
+                "SELECT total_size, chunk_size, stored_at_millis FROM attachments
+                 WHERE room_id = ?1 AND content_hash = ?2",
+                params![room_id.to_be_bytes(), &content_hash[..]],
+                |row| {
+                    Ok(StoredAttachment {
+                        total_size: row.get(0)?,
+                        chunk_size: row.get(1)?,
+                        stored_at_millis: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let mut stmt = conn.prepare(
+            "SELECT content_hash, total_size, chunk_size, stored_at_millis FROM attachments
+             WHERE room_id = ?1 ORDER BY content_hash",
+        )?;
+        let rows = stmt.query_map(params![room_id.to_be_bytes()], |row| {
+            let content_hash: Vec<u8> = row.get(0)?;
+            let attachment = StoredAttachment {
+                total_size: row.get(1)?,
+                chunk_size: row.get(2)?,
+                stored_at_millis: row.get(3)?,
+            };
+            Ok((content_hash, attachment))
+        })?;
+        rows.map(|row| {
+            let (content_hash, attachment) = row?;
+            let content_hash = <[u8; 32]>::try_from(content_hash)
+                .map_err(|_| StorageError::Serialization("corrupt attachment hash".to_string()))?;
+            Ok((content_hash, attachment))
+        })
+        .collect()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let tx = conn.transaction()?;
+        let key = params![room_id.to_be_bytes(), &content_hash[..]];
+        tx.execute("DELETE FROM attachments WHERE room_id = ?1 AND content_hash = ?2", key)?;
+        tx.execute("DELETE FROM attachment_chunks WHERE room_id = ?1 AND content_hash = ?2", key)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let tx = conn.transaction()?;
+        let room = params![room_id.to_be_bytes()];
+        tx.execute("DELETE FROM attachments WHERE room_id = ?1", room)?;
+        tx.execute("DELETE FROM attachment_chunks WHERE room_id = ?1", room)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        let seq = to_sql_seq(record.seq)?;
+        let mut encoded = Vec::new();
+        ciborium::ser::into_writer(record, &mut encoded)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        conn.execute("INSERT INTO audit (seq, record) VALUES (?1, ?2)", params![seq, encoded])?;
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        let Ok(from_seq) = i64::try_from(from_seq) else {
+            return Ok(Vec::new());
+        };
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let mut stmt =
+            conn.prepare_cached("SELECT record FROM audit WHERE seq >= ?1 ORDER BY seq LIMIT ?2")?;
+        let rows = stmt.query_map(params![from_seq, limit], |row| row.get::<_, Vec<u8>>(0))?;
+
+        rows.map(|row| {
+            ciborium::de::from_reader(&row?[..])
+                .map_err(|e| StorageError::Serialization(e.to_string()))
+        })
+        .collect()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let latest: Option<u64> =
+            conn.query_row("SELECT MAX(seq) FROM audit", [], |row| row.get(0))?;
+        Ok(latest)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        let mut encoded = BytesMut::new();
+        proposal.encode(&mut encoded).map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        conn.execute(
+            "INSERT OR REPLACE INTO pending_proposals (room_id, log_index, frame)
+             VALUES (?1, ?2, ?3)",
+            params![
+                room_id.to_be_bytes(),
+                to_sql_index(proposal.header.log_index())?,
+                &encoded[..]
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        conn.execute("DELETE FROM pending_proposals WHERE room_id = ?1", params![
+            room_id.to_be_bytes()
+        ])?;
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let mut stmt = conn.prepare_cached(
+            "SELECT frame FROM pending_proposals WHERE room_id = ?1 ORDER BY log_index",
+        )?;
+        let rows =
+            stmt.query_map(params![room_id.to_be_bytes()], |row| row.get::<_, Vec<u8>>(0))?;
+
+        rows.map(|bytes| {
+            Frame::decode(&bytes?).map_err(|e| StorageError::Serialization(e.to_string()))
+        })
+        .collect()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        let mut conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let tx = conn.transaction()?;
+
+        let previous = load_snapshot(&tx, room_id)?;
+        check_snapshot_index(room_id, up_to_index, latest_index(&tx, room_id)?, previous.as_ref())?;
+
+        let mls_state = load_mls_state(&tx, room_id)?;
+        let encoded = mls_state
+            .as_ref()
+            .map(|state| {
+                let mut encoded = Vec::new();
+                ciborium::ser::into_writer(state, &mut encoded)
+                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+                Ok::<_, StorageError>(encoded)
+            })
+            .transpose()?;
+
+        tx.execute(
+            "INSERT INTO snapshots (room_id, log_index, mls_state) VALUES (?1, ?2, ?3)
+             ON CONFLICT (room_id) DO UPDATE
+             SET log_index = excluded.log_index, mls_state = excluded.mls_state",
+            params![room_id.to_be_bytes(), to_sql_index(up_to_index)?, encoded],
+        )?;
+        tx.commit()?;
+
+        Ok(RoomSnapshot { room_id, log_index: up_to_index, mls_state })
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        load_snapshot(&conn, room_id)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        let mut conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let tx = conn.transaction()?;
+
+        let Some(snapshot) = load_snapshot(&tx, room_id)? else {
+            return Ok(0);
+        };
+        let boundary = to_sql_index(snapshot.first_retained_index())?;
+
+        let dropped =
+            tx.execute("DELETE FROM frames WHERE room_id = ?1 AND log_index < ?2", params![
+                room_id.to_be_bytes(),
+                boundary
+            ])?;
+        tx.execute(
+            "UPDATE snapshots SET first_retained = MAX(first_retained, ?2) WHERE room_id = ?1",
+            params![room_id.to_be_bytes(), boundary],
+        )?;
+        tx.commit()?;
+
+        Ok(dropped as u64)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        let room_id = snapshot.room_id;
+        let encoded = snapshot
+            .mls_state
+            .as_ref()
+            .map(|state| {
+                let mut encoded = Vec::new();
+                ciborium::ser::into_writer(state, &mut encoded)
+                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+                Ok::<_, StorageError>(encoded)
+            })
+            .transpose()?;
+
+        let mut conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let tx = conn.transaction()?;
+        check_log_empty(latest_index(&tx, room_id)?, snapshot)?;
+        tx.execute(
+            "INSERT INTO snapshots (room_id, log_index, mls_state, first_retained)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (room_id) DO UPDATE
+             SET log_index = excluded.log_index, mls_state = excluded.mls_state,
+                 first_retained = excluded.first_retained",
+            params![
+                room_id.to_be_bytes(),
+                to_sql_index(snapshot.log_index)?,
+                encoded,
+                to_sql_index(snapshot.first_retained_index())?
+            ],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn vacuum(&self, max_bytes: u64) -> Result<u64, StorageError> {
+        let conn = self.conn.lock().expect("SqliteStorage mutex poisoned");
+        let free_pages = pragma_u64(&conn, "freelist_count")?;
+        if free_pages == 0 {
+            return Ok(0);
+        }
+
+        let page_size = pragma_u64(&conn, "page_size")?;
+        let before = pragma_u64(&conn, "page_count")?;
+        let auto_vacuum: i64 = conn.pragma_query_value(None, "auto_vacuum", |row| row.get(0))?;
+        if auto_vacuum == AUTO_VACUUM_INCREMENTAL {
+            let pages = max_bytes.checked_div(page_size).unwrap_or(0).clamp(1, free_pages);
+            // Frees one page per step
+            let mut vacuum = conn.prepare(&format!("PRAGMA incremental_vacuum({pages})"))?;
+            let mut steps = vacuum.query([])?;
+            while steps.next()?.is_some() {}
+        } else {
+            // The pragma set on open is applied by a full rewrite
+            conn.execute_batch("VACUUM")?;
+        }
+        // Truncate the WAL so the freed pages leave the disk now
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+
+        let after = pragma_u64(&conn, "page_count")?;
+        Ok(before.saturating_sub(after).saturating_mul(page_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+    use crate::audit::AuditEvent;
+
+    fn create_test_frame(room_id: u128, log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_log_index(log_index);
+
+        Frame::new(header, Bytes::from(format!("frame-{log_index}")))
+    }
+
+    #[test]
+    fn test_migrations_applied_once() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        assert_eq!(storage.schema_version().expect("query failed"), MIGRATIONS.len());
+        storage.store_frame(100, 0, &create_test_frame(100, 0)).expect("store failed");
+        drop(storage);
+
+        // Reopening keeps data and does not rerun migrations
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(0));
+        let frames = storage.load_frames(100, 0, 10).expect("load failed");
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].payload, Bytes::from("frame-0"));
+    }
+
+    #[test]
+    fn test_damaged_frame_detected() {
+        let storage = SqliteStorage::in_memory().expect("open failed");
+        let batch: Vec<Frame> = (0..3).map(|i| create_test_frame(100, i)).collect();
+        storage.store_frames_batch(100, &batch).expect("batch failed");
+        assert_eq!(storage.verify(100).expect("verify failed"), 3);
+
+        {
+            let conn = storage.conn.lock().expect("SqliteStorage mutex poisoned");
+            let mut frame: Vec<u8> = conn
+                .query_row("SELECT frame FROM frames WHERE log_index = 1", [], |row| row.get(0))
+                .expect("query failed");
+            if let Some(byte) = frame.last_mut() {
+                *byte ^= 1;
+            }
+            conn.execute("UPDATE frames SET frame = ?1 WHERE log_index = 1", params![frame])
+                .expect("update failed");
+        }
+
+        let corrupted = StorageError::Corrupted { room_id: 100, log_index: 1 };
+        assert_eq!(storage.load_frames(100, 0, 10), Err(corrupted.clone()));
+        assert_eq!(storage.verify(100), Err(corrupted));
+    }
+
+    #[test]
+    fn test_frames_without_checksum_still_load() {
+        let storage = SqliteStorage::in_memory().expect("open failed");
+        storage.store_frame(100, 0, &create_test_frame(100, 0)).expect("store failed");
+        {
+            let conn = storage.conn.lock().expect("SqliteStorage mutex poisoned");
+            conn.execute("UPDATE frames SET checksum = NULL", []).expect("update failed");
+        }
+
+        assert_eq!(storage.verify(100).expect("verify failed"), 1);
+    }
+
+    #[test]
+    fn test_pending_proposals_survive_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+        let proposals = [create_test_frame(100, 3), create_test_frame(100, 5)];
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        for proposal in &proposals {
+            storage.store_pending_proposal(100, proposal).expect("store failed");
+        }
+        storage.store_pending_proposal(200, &proposals[0]).expect("store failed");
+        storage.clear_pending_proposals(200).expect("clear failed");
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        assert_eq!(storage.load_pending_proposals(100).expect("load failed"), proposals);
+        assert!(storage.load_pending_proposals(200).expect("load failed").is_empty());
+    }
+
+    #[test]
+    fn test_audit_pages_in_order_and_survives_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+        let record = |seq| AuditRecord {
+            seq,
+            at_millis: 1_000,
+            principal: Some("ops".to_string()),
+            event: AuditEvent::ConnectionClosed { session_id: seq, reason: "idle".to_string() },
+        };
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        assert_eq!(storage.latest_audit_seq().expect("latest failed"), None);
+        for seq in 0..5 {
+            storage.append_audit(&record(seq)).expect("append failed");
+        }
+        assert!(storage.append_audit(&record(4)).is_err(), "records are never replaced");
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        assert_eq!(storage.latest_audit_seq().expect("latest failed"), Some(4));
+        assert_eq!(storage.load_audit(2, 2).expect("load failed"), vec![record(2), record(3)]);
+        assert!(storage.load_audit(5, 10).expect("load failed").is_empty());
+    }
+
+    #[test]
+    fn test_usage_survives_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+        let mut window = UsageWindow { started_at_millis: 1_000, ..UsageWindow::default() };
+        window.rooms.entry(100).or_default().messages = 4;
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        assert_eq!(storage.load_usage().expect("load failed"), None);
+        storage.store_usage(&UsageWindow::default()).expect("store failed");
+        storage.store_usage(&window).expect("store failed");
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        assert_eq!(storage.load_usage().expect("load failed"), Some(window));
+    }
+
+    #[test]
+    fn test_read_markers_survive_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        storage.store_read_marker(100, 7, 3).expect("store failed");
+        storage.store_read_marker(100, 7, 5).expect("store failed");
+        storage.store_read_marker(100, u64::MAX, 1).expect("store failed");
+        storage.store_read_marker(200, 7, 9).expect("store failed");
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        let markers = storage.load_read_markers(100).expect("load failed");
+        assert_eq!(markers.into_iter().collect::<Vec<_>>(), vec![(7, 5), (u64::MAX, 1)]);
+        assert!(storage.load_read_markers(300).expect("load failed").is_empty());
+    }
+
+    #[test]
+    fn test_moved_rooms_survive_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+        let moved = |cutover_log_index| RoomMoved {
+            target: "lockframe-2.example:4433".to_string(),
+            cutover_log_index,
+        };
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        storage.store_room_moved(100, &moved(3)).expect("store failed");
+        storage.store_room_moved(100, &moved(5)).expect("store failed");
+        storage.store_room_moved(u128::MAX, &moved(1)).expect("store failed");
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        let rooms = storage.load_moved_rooms().expect("load failed");
+        assert_eq!(rooms.into_iter().collect::<Vec<_>>(), vec![
+            (100, moved(5)),
+            (u128::MAX, moved(1))
+        ]);
+    }
+
+    #[test]
+    fn test_listings_survive_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+        let listing = |name: &str| Listing { name: name.to_string(), description: String::new() };
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        storage.store_listing(100, Some(&listing("lobby"))).expect("store failed");
+        storage.store_listing(100, Some(&listing("hall"))).expect("store failed");
+        storage.store_listing(200, Some(&listing("attic"))).expect("store failed");
+        storage.store_listing(200, None).expect("unlist failed");
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        let listings = storage.load_listings().expect("load failed");
+        assert_eq!(listings.into_iter().collect::<Vec<_>>(), vec![(100, listing("hall"))]);
+    }
+
+    #[test]
+    fn test_imported_snapshot_starts_the_log_after_it() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+        let snapshot = RoomSnapshot { room_id: 100, log_index: 4, mls_state: None };
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        storage.import_snapshot(&snapshot).expect("import failed");
+        assert!(matches!(
+            storage.store_frame(100, 0, &create_test_frame(100, 0)),
+            Err(StorageError::Conflict { expected: 5, got: 0 })
+        ));
+        storage.store_frame(100, 5, &create_test_frame(100, 5)).expect("store failed");
+        assert!(matches!(
+            storage.import_snapshot(&snapshot),
+            Err(StorageError::Conflict { expected: 6, got: 5 })
+        ));
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        assert_eq!(storage.load_snapshot(100).expect("load failed"), Some(snapshot));
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(5));
+    }
+
+    #[test]
+    fn test_archive_cursors_survive_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        storage.store_archive_cursor(100, Some(3)).expect("store failed");
+        storage.store_archive_cursor(100, Some(7)).expect("store failed");
+        storage.store_archive_cursor(200, Some(1)).expect("store failed");
+        storage.store_archive_cursor(200, None).expect("store failed");
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        assert_eq!(storage.load_archive_cursor(100).expect("load failed"), Some(7));
+        assert_eq!(storage.load_archive_cursor(200).expect("load failed"), None);
+    }
+
+    #[test]
+    fn test_attachments_survive_reopen_per_room() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+        let attachment = StoredAttachment { total_size: 6, chunk_size: 3, stored_at_millis: 9 };
+
+        let storage = SqliteStorage::open(&path).expect("open failed");
+        storage.store_attachment_chunk(100, &[1; 32], 0, b"one").expect("store failed");
+        storage.store_attachment_chunk(100, &[1; 32], 1, b"two").expect("store failed");
+        storage.store_attachment_chunk(100, &[2; 32], 0, b"unfinished").expect("store failed");
+        storage.complete_attachment(100, &[1; 32], &attachment).expect("store failed");
+        storage.store_attachment_chunk(200, &[1; 32], 0, b"other room").expect("store failed");
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).expect("reopen failed");
+        assert_eq!(storage.load_attachment(100, &[1; 32]).expect("load failed"), Some(attachment));
+        assert_eq!(storage.load_attachment(100, &[2; 32]).expect("load failed"), None);
+        assert_eq!(storage.list_attachments(100).expect("load failed"), vec![(
+            [1; 32], attachment
+        )]);
+        let chunk = storage.load_attachment_chunk(100, &[1; 32], 1).expect("load failed");
+        assert_eq!(chunk.as_deref(), Some(&b"two"[..]));
+
+        storage.delete_attachment(100, &[1; 32]).expect("delete failed");
+        assert_eq!(storage.load_attachment_chunk(100, &[1; 32], 0).expect("load failed"), None);
+        storage.delete_attachments(100).expect("delete failed");
+        assert_eq!(storage.load_attachment_chunk(100, &[2; 32], 0).expect("load failed"), None);
+        assert!(storage.load_attachment_chunk(200, &[1; 32], 0).expect("load failed").is_some());
+    }
+
+    #[test]
+    fn test_newer_schema_rejected() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+
+        let conn = Connection::open(&path).expect("open failed");
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).expect("pragma failed");
+        drop(conn);
+
+        assert!(matches!(SqliteStorage::open(&path), Err(StorageError::Serialization(_))));
+    }
+
+    #[test]
+    fn test_gap_rejected() {
+        let storage = SqliteStorage::in_memory().expect("open failed");
+        storage.store_frame(100, 0, &create_test_frame(100, 0)).expect("store failed");
+
+        let result = storage.store_frame(100, 2, &create_test_frame(100, 2));
+        assert_eq!(result, Err(StorageError::Conflict { expected: 1, got: 2 }));
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(0));
+        assert!(matches!(storage.load_frames(200, 0, 1), Err(StorageError::NotFound { .. })));
+    }
+
+    #[test]
+    fn test_batch_committed_atomically() {
+        let storage = SqliteStorage::in_memory().expect("open failed");
+        let batch: Vec<Frame> = (0..3).map(|i| create_test_frame(100, i)).collect();
+        storage.store_frames_batch(100, &batch).expect("batch failed");
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(2));
+
+        let gapped = [create_test_frame(100, 3), create_test_frame(100, 5)];
+        let result = storage.store_frames_batch(100, &gapped);
+        assert_eq!(result, Err(StorageError::Conflict { expected: 4, got: 5 }));
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(2));
+    }
+
+    #[test]
+    fn test_compact_keeps_log_position() {
+        let storage = SqliteStorage::in_memory().expect("open failed");
+        for i in 0..4 {
+            storage.store_frame(100, i, &create_test_frame(100, i)).expect("store failed");
+        }
+
+        let snapshot = storage.snapshot(100, 3).expect("snapshot failed");
+        assert_eq!(snapshot.mls_state, None);
+        assert_eq!(storage.compact(100).expect("compact failed"), 4);
+
+        // Every frame is gone, but the log still continues from index 4
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(3));
+        assert_eq!(
+            storage.load_frames(100, 0, 10),
+            Err(StorageError::Compacted { room_id: 100, first_index: 4 })
+        );
+        assert_eq!(storage.load_frames(100, 4, 10).expect("load failed").len(), 0);
+
+        let result = storage.store_frame(100, 0, &create_test_frame(100, 0));
+        assert_eq!(result, Err(StorageError::Conflict { expected: 4, got: 0 }));
+        storage.store_frame(100, 4, &create_test_frame(100, 4)).expect("store failed");
+        assert_eq!(storage.load_frames(100, 4, 10).expect("load failed").len(), 1);
+    }
+
+    #[test]
+    fn test_vacuum_reclaims_compacted_pages_within_budget() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("lockframe.db");
+        let storage = SqliteStorage::open(&path).expect("open failed");
+
+        for i in 0..256 {
+            let mut header = FrameHeader::new(Opcode::AppMessage);
+            header.set_room_id(100);
+            header.set_log_index(i);
+            let frame = Frame::new(header, Bytes::from(vec![0xAB; 4096]));
+            storage.store_frame(100, i, &frame).expect("store failed");
+        }
+        assert_eq!(storage.vacuum(u64::MAX).expect("vacuum failed"), 0);
+
+        storage.snapshot(100, 254).expect("snapshot failed");
+        storage.compact(100).expect("compact failed");
+
+        // A small budget frees a bounded number of pages
+        let page_size = {
+            let conn = storage.conn.lock().expect("lock failed");
+            pragma_u64(&conn, "page_size").expect("pragma failed")
+        };
+        let first = storage.vacuum(page_size * 4).expect("vacuum failed");
+        assert!(first > 0 && first <= page_size * 4, "reclaimed {first}");
+
+        let rest = storage.vacuum(u64::MAX).expect("vacuum failed");
+        assert!(rest > 256 * 4096 / 2, "reclaimed {rest}");
+        assert_eq!(storage.vacuum(u64::MAX).expect("vacuum failed"), 0);
+        assert_eq!(storage.load_frames(100, 255, 10).expect("load failed").len(), 1);
+    }
+
+    #[test]
+    fn test_failed_commit_writes_nothing() {
+        let storage = SqliteStorage::in_memory().expect("open failed");
+        let state = MlsGroupState::new(100, 1, [7u8; 32], vec![1, 2], vec![0xAB; 16]);
+
+        // Gap aborts the whole commit, including the MLS state
+        let result = storage.store_commit(100, 1, &create_test_frame(100, 1), &state);
+        assert!(matches!(result, Err(StorageError::Conflict { .. })));
+        assert_eq!(storage.load_mls_state(100).expect("load failed"), None);
+
+        storage.store_commit(100, 0, &create_test_frame(100, 0), &state).expect("commit failed");
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(0));
+        assert_eq!(storage.load_mls_state(100).expect("load failed"), Some(state));
+    }
+}
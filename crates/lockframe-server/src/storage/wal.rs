@@ -0,0 +1,690 @@
+//! Write-ahead log in front of another storage
+//!
+//! Every frame is appended to an on-disk log and fsync'd before it is written
+//! to the inner storage, so once `store_frame` returns the frame is durable
+//! even if the inner write fails or the process dies. Frames whose inner write
+//! failed stay pending: reads include them, and they are retried in order on
+//! the next write and on [`WalStorage::open`], which replays the log into the
+//! inner storage at startup.
+//!
+//! The log is truncated at checkpoints, once every record has reached the
+//! inner storage. MLS state goes straight to the inner storage; it is
+//! rewritten on every commit and can be rebuilt from the frame log.
+//!
+//! # Record format
+//!
+//! ```text
+//! room_id: u128 | log_index: u64 | len: u32 | crc32: u32 | frame: [u8; len]
+//! ```
+//!
+//! All integers are big-endian and the checksum covers everything before it
+//! plus the frame bytes. A torn or corrupt record ends replay; everything
+//! after it is discarded.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use bytes::BytesMut;
+use lockframe_core::mls::MlsGroupState;
+use lockframe_proto::{Frame, payloads::session::RoomMoved};
+
+use super::{RoomSnapshot, Storage, StorageError, check_batch_indices};
+use crate::{
+    attachments::StoredAttachment, audit::AuditRecord, directory::Listing, usage::UsageWindow,
+};
+
+/// Default records between automatic checkpoints.
+pub const DEFAULT_WAL_CHECKPOINT_RECORDS: usize = 1024;
+
+/// Record header size: room ID, log index, length, checksum
+const RECORD_HEADER_SIZE: usize = 16 + 8 + 4 + 4;
+
+/// Storage with a write-ahead log for frames
+///
+/// Log state is shared via `Arc<Mutex<>>` and the wrapper panics if that
+/// mutex is poisoned.
+#[derive(Clone)]
+pub struct WalStorage<S: Storage> {
+    inner: S,
+    wal: Arc<Mutex<WalState>>,
+}
+
+struct WalState {
+    file: File,
+    /// Durable frames not yet in the inner storage, in log order
+    pending: VecDeque<(u128, u64, Frame)>,
+    /// Records appended since the last checkpoint
+    records: usize,
+    checkpoint_records: usize,
+}
+
+impl<S: Storage> WalStorage<S> {
+    /// Open the log at `path` in front of `inner`, replaying any records the
+    /// inner storage is missing
+    pub fn open(inner: S, path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::with_checkpoint_records(inner, path, DEFAULT_WAL_CHECKPOINT_RECORDS)
+    }
+
+    /// Open with a custom number of records between automatic checkpoints
+    pub fn with_checkpoint_records(
+        inner: S,
+        path: impl AsRef<Path>,
+        checkpoint_records: usize,
+    ) -> Result<Self, StorageError> {
+        let mut file =
+            OpenOptions::new().read(true).append(true).create(true).open(path.as_ref())?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut state = WalState {
+            file,
+            pending: VecDeque::new(),
+            records: 0,
+            checkpoint_records: checkpoint_records.max(1),
+        };
+        for (room_id, log_index, frame) in decode_records(&bytes) {
+            let applied =
+                inner.latest_log_index(room_id)?.is_some_and(|latest| latest >= log_index);
+            if !applied {
+                state.pending.push_back((room_id, log_index, frame));
+            }
+            state.records = state.records.saturating_add(1);
+        }
+
+        apply_pending(&inner, &mut state)?;
+        checkpoint(&mut state)?;
+
+        Ok(Self { inner, wal: Arc::new(Mutex::new(state)) })
+    }
+
+    /// Underlying storage
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Frames durable in the log but not yet in the inner storage
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn pending_count(&self) -> usize {
+        self.wal.lock().expect("WalStorage mutex poisoned").pending.len()
+    }
+
+    /// Retry pending frames and truncate the log if all reached the inner
+    /// storage
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn checkpoint(&self) -> Result<(), StorageError> {
+        let mut state = self.wal.lock().expect("WalStorage mutex poisoned");
+        apply_pending(&self.inner, &mut state)?;
+        checkpoint(&mut state)
+    }
+}
+
+/// Write pending frames to the inner storage in order, stopping at the first
+/// failure.
+fn apply_pending(inner: &impl Storage, state: &mut WalState) -> Result<(), StorageError> {
+    while let Some((room_id, log_index, frame)) = state.pending.front() {
+        inner.store_frame(*room_id, *log_index, frame)?;
+        state.pending.pop_front();
+    }
+    Ok(())
+}
+
+/// Truncate the log if nothing is pending.
+fn checkpoint(state: &mut WalState) -> Result<(), StorageError> {
+    if state.pending.is_empty() {
+        state.file.set_len(0)?;
+        state.file.seek(SeekFrom::Start(0))?;
+        state.file.sync_all()?;
+        state.records = 0;
+    }
+    Ok(())
+}
+
+fn checksum(header: &[u8], frame: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(header);
+    hasher.update(frame);
+    hasher.finalize()
+}
+
+fn encode_record(room_id: u128, log_index: u64, frame: &Frame) -> Result<Vec<u8>, StorageError> {
+    let mut encoded = BytesMut::new();
+    frame.encode(&mut encoded).map_err(|e| StorageError::Serialization(e.to_string()))?;
+    let len = u32::try_from(encoded.len())
+        .map_err(|_| StorageError::Serialization("frame too large for log".to_string()))?;
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE.saturating_add(encoded.len()));
+    record.extend_from_slice(&room_id.to_be_bytes());
+    record.extend_from_slice(&log_index.to_be_bytes());
+    record.extend_from_slice(&len.to_be_bytes());
+    let crc = checksum(&record, &encoded);
+    record.extend_from_slice(&crc.to_be_bytes());
+    record.extend_from_slice(&encoded);
+
+    Ok(record)
+}
+
+/// Decode records up to the first torn or corrupt one.
+fn decode_records(mut bytes: &[u8]) -> Vec<(u128, u64, Frame)> {
+    let mut records = Vec::new();
+
+    while let Some(record) = decode_record(bytes) {
+        let (room_id, log_index, frame, rest) = record;
+        records.push((room_id, log_index, frame));
+        bytes = rest;
+    }
+
+    records
+}
+
+fn decode_record(bytes: &[u8]) -> Option<(u128, u64, Frame, &[u8])> {
+    let room_id = u128::from_be_bytes(bytes.get(..16)?.try_into().ok()?);
+    let log_index = u64::from_be_bytes(bytes.get(16..24)?.try_into().ok()?);
+    let len = u32::from_be_bytes(bytes.get(24..28)?.try_into().ok()?);
+    let crc = u32::from_be_bytes(bytes.get(28..32)?.try_into().ok()?);
+
+    let end = RECORD_HEADER_SIZE.checked_add(usize::try_from(len).ok()?)?;
+    let encoded = bytes.get(RECORD_HEADER_SIZE..end)?;
+    if checksum(bytes.get(..28)?, encoded) != crc {
+        return None;
+    }
+
+    let frame = Frame::decode(encoded).ok()?;
+    Some((room_id, log_index, frame, bytes.get(end..)?))
+}
+
+impl<S: Storage> Storage for WalStorage<S> {
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_frame(
+        &self,
+        room_id: u128,
+        log_index: u64,
+        frame: &Frame,
+    ) -> Result<(), StorageError> {
+        let mut state = self.wal.lock().expect("WalStorage mutex poisoned");
+
+        // Earlier frames must reach the inner storage first. A failure here
+        // is fine: this frame queues behind them.
+        let _ = apply_pending(&self.inner, &mut state);
+
+        let expected =
+            latest_index(&self.inner, &state, room_id)?.map_or(0, |i| i.saturating_add(1));
+        if log_index != expected {
+            return Err(StorageError::Conflict { expected, got: log_index });
+        }
+
+        let record = encode_record(room_id, log_index, frame)?;
+        state.file.write_all(&record)?;
+        state.file.sync_data()?;
+        state.records = state.records.saturating_add(1);
+
+        // Durable from here on
+        if state.pending.is_empty() && self.inner.store_frame(room_id, log_index, frame).is_ok() {
+            if state.records >= state.checkpoint_records {
+                checkpoint(&mut state)?;
+            }
+        } else {
+            state.pending.push_back((room_id, log_index, frame.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Appends every record before a single sync. A crash mid-batch can
+    /// leave a prefix of the batch in the log, which replays as a shorter,
+    /// still consecutive log.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn store_frames_batch(&self, room_id: u128, frames: &[Frame]) -> Result<(), StorageError> {
+        let mut state = self.wal.lock().expect("WalStorage mutex poisoned");
+        let _ = apply_pending(&self.inner, &mut state);
+
+        check_batch_indices(frames, latest_index(&self.inner, &state, room_id)?)?;
+
+        let mut records = Vec::new();
+        for frame in frames {
+            records.extend(encode_record(room_id, frame.header.log_index(), frame)?);
+        }
+        state.file.write_all(&records)?;
+        state.file.sync_data()?;
+        state.records = state.records.saturating_add(frames.len());
+
+        // Durable from here on
+        if state.pending.is_empty() && self.inner.store_frames_batch(room_id, frames).is_ok() {
+            if state.records >= state.checkpoint_records {
+                checkpoint(&mut state)?;
+            }
+        } else {
+            // Whatever part of the batch reached the inner storage is skipped
+            // on replay, so queue only the rest
+            let stored = latest_index(&self.inner, &state, room_id)?;
+            state.pending.extend(
+                frames
+                    .iter()
+                    .filter(|frame| Some(frame.header.log_index()) > stored)
+                    .map(|frame| (room_id, frame.header.log_index(), frame.clone())),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        let state = self.wal.lock().expect("WalStorage mutex poisoned");
+        latest_index(&self.inner, &state, room_id)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn load_frames(
+        &self,
+        room_id: u128,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<Frame>, StorageError> {
+        let state = self.wal.lock().expect("WalStorage mutex poisoned");
+        let mut pending = state.pending.iter().filter(|(room, _, _)| *room == room_id).peekable();
+
+        let mut frames = match self.inner.load_frames(room_id, from, limit) {
+            Ok(frames) => frames,
+            Err(StorageError::NotFound { .. }) if pending.peek().is_some() => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        let next = from.saturating_add(frames.len() as u64);
+        frames.extend(
+            pending
+                .filter(|(_, index, _)| *index >= next)
+                .take(limit.saturating_sub(frames.len()))
+                .map(|(_, _, frame)| frame.clone()),
+        );
+
+        Ok(frames)
+    }
+
+    fn store_mls_state(&self, room_id: u128, state: &MlsGroupState) -> Result<(), StorageError> {
+        self.inner.store_mls_state(room_id, state)
+    }
+
+    fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+        self.inner.load_mls_state(room_id)
+    }
+
+    fn store_pending_proposal(&self, room_id: u128, proposal: &Frame) -> Result<(), StorageError> {
+        self.inner.store_pending_proposal(room_id, proposal)
+    }
+
+    fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+        self.inner.clear_pending_proposals(room_id)
+    }
+
+    fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+        self.inner.load_pending_proposals(room_id)
+    }
+
+    /// Pending frames are written to the inner storage first, so the snapshot
+    /// can cover them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+        let mut state = self.wal.lock().expect("WalStorage mutex poisoned");
+        apply_pending(&self.inner, &mut state)?;
+        self.inner.snapshot(room_id, up_to_index)
+    }
+
+    fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+        self.inner.load_snapshot(room_id)
+    }
+
+    fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+        self.inner.compact(room_id)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    fn import_snapshot(&self, snapshot: &RoomSnapshot) -> Result<(), StorageError> {
+        let mut state = self.wal.lock().expect("WalStorage mutex poisoned");
+        apply_pending(&self.inner, &mut state)?;
+        self.inner.import_snapshot(snapshot)
+    }
+
+    fn vacuum(&self, max_bytes: u64) -> Result<u64, StorageError> {
+        self.inner.vacuum(max_bytes)
+    }
+
+    fn store_usage(&self, window: &UsageWindow) -> Result<(), StorageError> {
+        self.inner.store_usage(window)
+    }
+
+    fn load_usage(&self) -> Result<Option<UsageWindow>, StorageError> {
+        self.inner.load_usage()
+    }
+
+    fn store_read_marker(
+        &self,
+        room_id: u128,
+        member_id: u64,
+        up_to_log_index: u64,
+    ) -> Result<(), StorageError> {
+        self.inner.store_read_marker(room_id, member_id, up_to_log_index)
+    }
+
+    fn load_read_markers(&self, room_id: u128) -> Result<BTreeMap<u64, u64>, StorageError> {
+        self.inner.load_read_markers(room_id)
+    }
+
+    fn store_room_moved(&self, room_id: u128, moved: &RoomMoved) -> Result<(), StorageError> {
+        self.inner.store_room_moved(room_id, moved)
+    }
+
+    fn load_moved_rooms(&self) -> Result<BTreeMap<u128, RoomMoved>, StorageError> {
+        self.inner.load_moved_rooms()
+    }
+
+    fn store_archive_cursor(
+        &self,
+        room_id: u128,
+        next_log_index: Option<u64>,
+    ) -> Result<(), StorageError> {
+        self.inner.store_archive_cursor(room_id, next_log_index)
+    }
+
+    fn load_archive_cursor(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+        self.inner.load_archive_cursor(room_id)
+    }
+
+    fn store_listing(&self, room_id: u128, listing: Option<&Listing>) -> Result<(), StorageError> {
+        self.inner.store_listing(room_id, listing)
+    }
+
+    fn load_listings(&self) -> Result<BTreeMap<u128, Listing>, StorageError> {
+        self.inner.load_listings()
+    }
+
+    fn store_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError> {
+        self.inner.store_attachment_chunk(room_id, content_hash, index, bytes)
+    }
+
+    fn load_attachment_chunk(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        index: u32,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        self.inner.load_attachment_chunk(room_id, content_hash, index)
+    }
+
+    fn complete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+        attachment: &StoredAttachment,
+    ) -> Result<(), StorageError> {
+        self.inner.complete_attachment(room_id, content_hash, attachment)
+    }
+
+    fn load_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<Option<StoredAttachment>, StorageError> {
+        self.inner.load_attachment(room_id, content_hash)
+    }
+
+    fn list_attachments(
+        &self,
+        room_id: u128,
+    ) -> Result<Vec<([u8; 32], StoredAttachment)>, StorageError> {
+        self.inner.list_attachments(room_id)
+    }
+
+    fn delete_attachment(
+        &self,
+        room_id: u128,
+        content_hash: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        self.inner.delete_attachment(room_id, content_hash)
+    }
+
+    fn delete_attachments(&self, room_id: u128) -> Result<(), StorageError> {
+        self.inner.delete_attachments(room_id)
+    }
+
+    fn append_audit(&self, record: &AuditRecord) -> Result<(), StorageError> {
+        self.inner.append_audit(record)
+    }
+
+    fn load_audit(&self, from_seq: u64, limit: usize) -> Result<Vec<AuditRecord>, StorageError> {
+        self.inner.load_audit(from_seq, limit)
+    }
+
+    fn latest_audit_seq(&self) -> Result<Option<u64>, StorageError> {
+        self.inner.latest_audit_seq()
+    }
+
+    /// Apply pending frames, flush the inner storage, then truncate the log.
+    fn flush(&self) -> Result<(), StorageError> {
+        let mut state = self.wal.lock().expect("WalStorage mutex poisoned");
+        apply_pending(&self.inner, &mut state)?;
+        self.inner.flush()?;
+        checkpoint(&mut state)
+    }
+
+    /// Flush, then close the inner storage.
+    fn close(&self) -> Result<(), StorageError> {
+        self.flush()?;
+        self.inner.close()
+    }
+}
+
+fn latest_index(
+    inner: &impl Storage,
+    state: &WalState,
+    room_id: u128,
+) -> Result<Option<u64>, StorageError> {
+    let pending = state.pending.iter().rev().find(|(room, _, _)| *room == room_id);
+    match pending {
+        Some((_, log_index, _)) => Ok(Some(*log_index)),
+        None => inner.latest_log_index(room_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use lockframe_proto::{FrameHeader, Opcode};
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    /// Storage whose frame writes always fail
+    #[derive(Clone)]
+    struct FailingWrites(MemoryStorage);
+
+    impl Storage for FailingWrites {
+        fn store_frame(&self, _: u128, _: u64, _: &Frame) -> Result<(), StorageError> {
+            Err(StorageError::Io("disk full".to_string()))
+        }
+
+        fn latest_log_index(&self, room_id: u128) -> Result<Option<u64>, StorageError> {
+            self.0.latest_log_index(room_id)
+        }
+
+        fn load_frames(
+            &self,
+            room_id: u128,
+            from: u64,
+            limit: usize,
+        ) -> Result<Vec<Frame>, StorageError> {
+            self.0.load_frames(room_id, from, limit)
+        }
+
+        fn store_mls_state(
+            &self,
+            room_id: u128,
+            state: &MlsGroupState,
+        ) -> Result<(), StorageError> {
+            self.0.store_mls_state(room_id, state)
+        }
+
+        fn load_mls_state(&self, room_id: u128) -> Result<Option<MlsGroupState>, StorageError> {
+            self.0.load_mls_state(room_id)
+        }
+
+        fn store_pending_proposal(
+            &self,
+            room_id: u128,
+            proposal: &Frame,
+        ) -> Result<(), StorageError> {
+            self.0.store_pending_proposal(room_id, proposal)
+        }
+
+        fn clear_pending_proposals(&self, room_id: u128) -> Result<(), StorageError> {
+            self.0.clear_pending_proposals(room_id)
+        }
+
+        fn load_pending_proposals(&self, room_id: u128) -> Result<Vec<Frame>, StorageError> {
+            self.0.load_pending_proposals(room_id)
+        }
+
+        fn snapshot(&self, room_id: u128, up_to_index: u64) -> Result<RoomSnapshot, StorageError> {
+            self.0.snapshot(room_id, up_to_index)
+        }
+
+        fn load_snapshot(&self, room_id: u128) -> Result<Option<RoomSnapshot>, StorageError> {
+            self.0.load_snapshot(room_id)
+        }
+
+        fn compact(&self, room_id: u128) -> Result<u64, StorageError> {
+            self.0.compact(room_id)
+        }
+    }
+
+    fn create_test_frame(room_id: u128, log_index: u64) -> Frame {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_log_index(log_index);
+
+        Frame::new(header, Bytes::from(format!("frame-{log_index}")))
+    }
+
+    fn indices(frames: &[Frame]) -> Vec<u64> {
+        frames.iter().map(|f| f.header.log_index()).collect()
+    }
+
+    #[test]
+    fn test_log_replayed_into_fresh_store() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("frames.wal");
+
+        {
+            // Inner store that loses everything, as after a crash
+            let storage = WalStorage::open(MemoryStorage::new(), &path).expect("open failed");
+            for i in 0..3 {
+                storage.store_frame(100, i, &create_test_frame(100, i)).expect("store failed");
+            }
+        }
+
+        let storage = WalStorage::open(MemoryStorage::new(), &path).expect("reopen failed");
+        assert_eq!(storage.pending_count(), 0);
+        assert_eq!(storage.inner().latest_log_index(100).expect("query failed"), Some(2));
+        assert_eq!(indices(&storage.inner().load_frames(100, 0, 10).expect("load")), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_failed_inner_write_stays_pending() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("frames.wal");
+        let inner = FailingWrites(MemoryStorage::new());
+
+        let storage = WalStorage::open(inner, &path).expect("open failed");
+        storage.store_frame(100, 0, &create_test_frame(100, 0)).expect("durable in log");
+        storage.store_frame(100, 1, &create_test_frame(100, 1)).expect("durable in log");
+
+        assert_eq!(storage.pending_count(), 2);
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(1));
+        assert_eq!(indices(&storage.load_frames(100, 0, 10).expect("load failed")), vec![0, 1]);
+        assert!(matches!(
+            storage.store_frame(100, 3, &create_test_frame(100, 3)),
+            Err(StorageError::Conflict { expected: 2, got: 3 })
+        ));
+        drop(storage);
+
+        // Restart with a healthy store: nothing was lost
+        let storage = WalStorage::open(MemoryStorage::new(), &path).expect("reopen failed");
+        assert_eq!(storage.pending_count(), 0);
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(1));
+    }
+
+    #[test]
+    fn test_batch_replayed_into_fresh_store() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("frames.wal");
+
+        {
+            let inner = FailingWrites(MemoryStorage::new());
+            let storage = WalStorage::open(inner, &path).expect("open failed");
+            let batch: Vec<Frame> = (0..3).map(|i| create_test_frame(100, i)).collect();
+            storage.store_frames_batch(100, &batch).expect("durable in log");
+            assert_eq!(storage.pending_count(), 3);
+
+            let gapped = [create_test_frame(100, 3), create_test_frame(100, 5)];
+            assert!(matches!(
+                storage.store_frames_batch(100, &gapped),
+                Err(StorageError::Conflict { expected: 4, got: 5 })
+            ));
+        }
+
+        let storage = WalStorage::open(MemoryStorage::new(), &path).expect("reopen failed");
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(2));
+        assert_eq!(indices(&storage.inner().load_frames(100, 0, 10).expect("load")), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_torn_tail_discarded() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("frames.wal");
+
+        let mut bytes = Vec::new();
+        for i in 0..2 {
+            bytes.extend(encode_record(100, i, &create_test_frame(100, i)).expect("encode"));
+        }
+        let torn = encode_record(100, 2, &create_test_frame(100, 2)).expect("encode");
+        bytes.extend_from_slice(&torn[..torn.len() - 3]);
+        std::fs::write(&path, bytes).expect("write failed");
+
+        let storage = WalStorage::open(MemoryStorage::new(), &path).expect("open failed");
+        assert_eq!(storage.latest_log_index(100).expect("query failed"), Some(1));
+
+        // Checkpoint truncated the log after replay
+        assert_eq!(std::fs::metadata(&path).expect("metadata").len(), 0);
+    }
+}
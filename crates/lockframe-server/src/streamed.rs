@@ -0,0 +1,235 @@
+//! Payloads streamed apart from their frame headers.
+//!
+//! A client sends a frame whose payload exceeds
+//! [`Frame::STREAM_THRESHOLD`](lockframe_proto::Frame::STREAM_THRESHOLD) with
+//! the `STREAMED` flag: its header goes on the control stream and its payload
+//! on a unidirectional stream of its own, after the frame's request ID. Either
+//! half may arrive first, so each connection keeps the halves waiting for the
+//! other, matched by request ID.
+//!
+//! A streamed frame holds back the frames sent after it on the control stream
+//! until its payload arrives, so frames are handed on in the order they were
+//! sent. Halves left unmatched for [`STREAM_MATCH_TIMEOUT`] are dropped.
+//!
+//! Payload bytes count against a per-connection budget of
+//! [`MAX_UNMATCHED_BYTES`] from the moment they are read until their header
+//! claims them, so a client cannot make the server buffer more than that.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use lockframe_proto::FrameHeader;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore, oneshot};
+
+/// How long one half of a streamed frame waits for the other.
+pub const STREAM_MATCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most halves a connection may have waiting at once.
+pub const MAX_UNMATCHED_STREAMS: usize = 16;
+
+/// Most payload bytes a connection may hold before their headers claim them.
+pub const MAX_UNMATCHED_BYTES: usize = 2 * FrameHeader::MAX_PAYLOAD_SIZE as usize;
+
+/// Half of a streamed frame waiting for the other.
+#[derive(Debug)]
+enum Half {
+    /// The header arrived; its reader waits for the payload
+    Header(oneshot::Sender<Bytes>),
+    /// The payload arrived first, holding its share of the byte budget
+    Payload(Bytes, OwnedSemaphorePermit),
+}
+
+/// A waiting half, tagged so only its own timeout drops it.
+#[derive(Debug)]
+struct Unmatched {
+    half: Half,
+    ticket: u64,
+}
+
+/// Payload of a streamed header, once matched.
+#[derive(Debug)]
+pub enum Matched {
+    /// The payload had already arrived
+    Ready(Bytes),
+    /// The payload will be sent here when it arrives
+    Waiting {
+        /// Receives the payload
+        payload: oneshot::Receiver<Bytes>,
+        /// Pass to [`StreamedPayloads::forget`] to give up on the payload
+        ticket: u64,
+    },
+}
+
+/// Unmatched halves of one connection's streamed frames.
+#[derive(Debug)]
+pub struct StreamedPayloads {
+    /// Request ID → half waiting for the other
+    unmatched: Mutex<HashMap<u32, Unmatched>>,
+    /// Payload bytes not yet claimed by a header
+    budget: Arc<Semaphore>,
+    /// Next ticket handed to a waiting half
+    next_ticket: AtomicU64,
+}
+
+impl Default for StreamedPayloads {
+    fn default() -> Self {
+        Self {
+            unmatched: Mutex::default(),
+            budget: Arc::new(Semaphore::new(MAX_UNMATCHED_BYTES)),
+            next_ticket: AtomicU64::new(0),
+        }
+    }
+}
+
+impl StreamedPayloads {
+    /// Take `bytes` of payload from the connection's budget, or fail if
+    /// they would exceed [`MAX_UNMATCHED_BYTES`].
+    pub fn reserve(&self, bytes: usize) -> Result<OwnedSemaphorePermit, String> {
+        u32::try_from(bytes)
+            .ok()
+            .and_then(|bytes| Arc::clone(&self.budget).try_acquire_many_owned(bytes).ok())
+            .ok_or_else(|| format!("more than {MAX_UNMATCHED_BYTES} streamed bytes unmatched"))
+    }
+
+    /// Match a streamed header with its payload, or start waiting for it.
+    pub async fn header(&self, request_id: u32) -> Result<Matched, String> {
+        let mut unmatched = self.unmatched.lock().await;
+        match unmatched.remove(&request_id) {
+            Some(Unmatched { half: Half::Payload(payload, _budget), .. }) => {
+                Ok(Matched::Ready(payload))
+            },
+            Some(waiting) => {
+                unmatched.insert(request_id, waiting);
+                Err(format!("streamed request {request_id} sent twice"))
+            },
+            None if unmatched.len() >= MAX_UNMATCHED_STREAMS => {
+                Err(format!("more than {MAX_UNMATCHED_STREAMS} unmatched streams"))
+            },
+            None => {
+                let (tx, payload) = oneshot::channel();
+                let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+                unmatched.insert(request_id, Unmatched { half: Half::Header(tx), ticket });
+                Ok(Matched::Waiting { payload, ticket })
+            },
+        }
+    }
+
+    /// Hand a payload to the header waiting for it, or keep it until the
+    /// header arrives. Returns the ticket of a kept payload.
+    ///
+    /// `budget` is the payload's share of the byte budget, released once a
+    /// header claims it.
+    pub async fn payload(
+        &self,
+        request_id: u32,
+        payload: Bytes,
+        budget: OwnedSemaphorePermit,
+    ) -> Result<Option<u64>, String> {
+        let mut unmatched = self.unmatched.lock().await;
+        match unmatched.remove(&request_id) {
+            // A reader that gave up dropped its receiver; the payload goes too
+            Some(Unmatched { half: Half::Header(tx), .. }) => {
+                let _ = tx.send(payload);
+                Ok(None)
+            },
+            Some(waiting) => {
+                unmatched.insert(request_id, waiting);
+                Err(format!("payload of streamed request {request_id} sent twice"))
+            },
+            None if unmatched.len() >= MAX_UNMATCHED_STREAMS => {
+                Err(format!("more than {MAX_UNMATCHED_STREAMS} unmatched streams"))
+            },
+            None => {
+                let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+                let half = Half::Payload(payload, budget);
+                unmatched.insert(request_id, Unmatched { half, ticket });
+                Ok(Some(ticket))
+            },
+        }
+    }
+
+    /// Drop the unmatched half of `request_id` if it is still the one
+    /// `ticket` was handed out for. A half that was matched meanwhile, or
+    /// replaced by a later frame reusing the request ID, is left alone.
+    pub async fn forget(&self, request_id: u32, ticket: u64) {
+        let mut unmatched = self.unmatched.lock().await;
+        if unmatched.get(&request_id).is_some_and(|waiting| waiting.ticket == ticket) {
+            unmatched.remove(&request_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(streamed: &StreamedPayloads, bytes: &'static [u8]) -> (Bytes, OwnedSemaphorePermit) {
+        (Bytes::from_static(bytes), streamed.reserve(bytes.len()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn halves_match_in_either_order() {
+        let streamed = StreamedPayloads::default();
+
+        // Header first
+        let Ok(Matched::Waiting { payload: rx, .. }) = streamed.header(1).await else {
+            panic!("expected to wait for the payload");
+        };
+        assert!(streamed.header(1).await.is_err());
+        let (one, budget) = payload(&streamed, b"one");
+        assert_eq!(streamed.payload(1, one, budget).await, Ok(None));
+        assert_eq!(rx.await.unwrap(), Bytes::from_static(b"one"));
+
+        // Payload first
+        let (two, budget) = payload(&streamed, b"two");
+        assert!(matches!(streamed.payload(2, two, budget).await, Ok(Some(_))));
+        assert!(matches!(streamed.header(2).await, Ok(Matched::Ready(p)) if p == "two"));
+
+        // Unmatched halves are bounded
+        let mut first = None;
+        for request_id in 0..16 {
+            let (empty, budget) = payload(&streamed, b"");
+            let ticket = streamed.payload(request_id, empty, budget).await.unwrap().unwrap();
+            first.get_or_insert(ticket);
+        }
+        assert!(streamed.header(99).await.is_err());
+        streamed.forget(0, first.unwrap()).await;
+        assert!(streamed.header(99).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn stale_tickets_leave_reused_request_ids_alone() {
+        let streamed = StreamedPayloads::default();
+
+        let (first, budget) = payload(&streamed, b"first");
+        let stale = streamed.payload(1, first, budget).await.unwrap().unwrap();
+        assert!(matches!(streamed.header(1).await, Ok(Matched::Ready(_))));
+
+        // The request ID is reused before the first payload's timeout fires
+        let (second, budget) = payload(&streamed, b"second");
+        streamed.payload(1, second, budget).await.unwrap();
+        streamed.forget(1, stale).await;
+        assert!(matches!(streamed.header(1).await, Ok(Matched::Ready(p)) if p == "second"));
+    }
+
+    #[tokio::test]
+    async fn unclaimed_payload_bytes_are_bounded() {
+        let streamed = StreamedPayloads::default();
+
+        let held = streamed.reserve(MAX_UNMATCHED_BYTES).unwrap();
+        assert!(streamed.reserve(1).is_err());
+
+        // Claiming a payload returns its bytes to the budget
+        streamed.payload(1, Bytes::new(), held).await.unwrap();
+        assert!(streamed.reserve(1).is_err());
+        assert!(matches!(streamed.header(1).await, Ok(Matched::Ready(_))));
+        assert!(streamed.reserve(MAX_UNMATCHED_BYTES).is_ok());
+    }
+}
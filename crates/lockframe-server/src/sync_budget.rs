@@ -0,0 +1,366 @@
+//! Per-session sync budgets.
+//!
+//! Sync responses are read straight from storage, so a client asking for large
+//! ranges in a loop can saturate it. Each session gets a token bucket measured
+//! in frames: every frame served costs one token, and tokens refill at a fixed
+//! rate up to a burst size. A request is shrunk to the tokens available and
+//! rejected outright only when the bucket is empty.
+//!
+//! A paged sync (one whose response had `has_more`) stays active until its
+//! final page. Sessions may have only a few active syncs at once, so a client
+//! cannot spread load across many rooms in parallel. A sync the client stops
+//! paging through is released once it has been idle for a while, or when the
+//! session leaves the room or closes.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Default frames per second refilled into each session's budget.
+pub const DEFAULT_SYNC_FRAMES_PER_SEC: u64 = 1_000;
+
+/// Default maximum frames a session can burst.
+pub const DEFAULT_SYNC_BURST_FRAMES: u64 = 5_000;
+
+/// Default maximum active paged syncs per session.
+pub const DEFAULT_MAX_CONCURRENT_SYNCS: usize = 4;
+
+/// Default time a paged sync stays active without its next page requested.
+pub const DEFAULT_SYNC_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Token precision: one frame is this many tokens.
+const TOKENS_PER_FRAME: u64 = 1_000;
+
+/// Sync rate limits applied to every session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncBudgetConfig {
+    /// Frames per second refilled into each session's budget
+    pub frames_per_sec: u64,
+    /// Maximum frames a session can accumulate and spend at once
+    pub burst_frames: u64,
+    /// Maximum rooms a session can be paging through at once
+    pub max_concurrent: usize,
+    /// How long a paged sync stays active without its next page requested
+    pub idle_timeout: Duration,
+}
+
+impl Default for SyncBudgetConfig {
+    fn default() -> Self {
+        Self {
+            frames_per_sec: DEFAULT_SYNC_FRAMES_PER_SEC,
+            burst_frames: DEFAULT_SYNC_BURST_FRAMES,
+            max_concurrent: DEFAULT_MAX_CONCURRENT_SYNCS,
+            idle_timeout: DEFAULT_SYNC_IDLE_TIMEOUT,
+        }
+    }
+}
+
+/// Server-wide sync counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncMetrics {
+    /// Sync requests served
+    pub requests: u64,
+    /// Frames returned across all sync responses
+    pub frames_served: u64,
+    /// Encoded bytes returned across all sync responses
+    pub bytes_served: u64,
+    /// Requests shrunk to fit the remaining budget
+    pub clamped: u64,
+    /// Requests rejected for exceeding the budget
+    pub rejected: u64,
+}
+
+impl SyncMetrics {
+    /// Add the counters of `other`, e.g. another shard's, to these.
+    pub fn merge(&mut self, other: &Self) {
+        self.requests = self.requests.saturating_add(other.requests);
+        self.frames_served = self.frames_served.saturating_add(other.frames_served);
+        self.bytes_served = self.bytes_served.saturating_add(other.bytes_served);
+        self.clamped = self.clamped.saturating_add(other.clamped);
+        self.rejected = self.rejected.saturating_add(other.rejected);
+    }
+}
+
+/// Why a sync request was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncDenied {
+    /// Frame budget is exhausted
+    BudgetExhausted {
+        /// Time until at least one frame is available
+        retry_after: Duration,
+    },
+    /// Too many paged syncs in progress
+    TooManyConcurrent {
+        /// The configured limit
+        limit: usize,
+    },
+}
+
+#[derive(Debug)]
+struct SessionBudget {
+    /// Available tokens (`TOKENS_PER_FRAME` per frame)
+    tokens: u64,
+    /// When tokens were last refilled
+    refilled_at: Instant,
+    /// Rooms with a paged sync in progress, and when their last page was
+    /// served
+    active: HashMap<u128, Instant>,
+    /// Frames served to this session
+    frames_served: u64,
+}
+
+/// Sync budgets for every session.
+#[derive(Debug, Default)]
+pub struct SyncBudgets {
+    config: SyncBudgetConfig,
+    sessions: HashMap<u64, SessionBudget>,
+    metrics: SyncMetrics,
+}
+
+impl SyncBudgets {
+    /// Create budgets using `config` for every session.
+    pub fn new(config: SyncBudgetConfig) -> Self {
+        Self { config, ..Self::default() }
+    }
+
+    /// Reserve budget for a sync of up to `limit` frames from `room_id`.
+    ///
+    /// Returns the number of frames the session may be sent, at most `limit`.
+    pub fn acquire(
+        &mut self,
+        session_id: u64,
+        room_id: u128,
+        limit: usize,
+        now: Instant,
+    ) -> Result<usize, SyncDenied> {
+        let config = self.config;
+        let budget = self.sessions.entry(session_id).or_insert_with(|| SessionBudget {
+            tokens: config.burst_frames.saturating_mul(TOKENS_PER_FRAME),
+            refilled_at: now,
+            active: HashMap::new(),
+            frames_served: 0,
+        });
+        refill(budget, &config, now);
+        budget
+            .active
+            .retain(|_, served_at| now.saturating_duration_since(*served_at) < config.idle_timeout);
+
+        if !budget.active.contains_key(&room_id) && budget.active.len() >= config.max_concurrent {
+            self.metrics.rejected = self.metrics.rejected.saturating_add(1);
+            return Err(SyncDenied::TooManyConcurrent { limit: config.max_concurrent });
+        }
+
+        let available = budget.tokens / TOKENS_PER_FRAME;
+        if available == 0 {
+            self.metrics.rejected = self.metrics.rejected.saturating_add(1);
+            let missing = TOKENS_PER_FRAME.saturating_sub(budget.tokens);
+            return Err(SyncDenied::BudgetExhausted {
+                retry_after: refill_time(missing, config.frames_per_sec),
+            });
+        }
+
+        let granted = usize::try_from(available).map_or(limit, |available| limit.min(available));
+        if granted < limit {
+            self.metrics.clamped = self.metrics.clamped.saturating_add(1);
+        }
+        Ok(granted)
+    }
+
+    /// Charge a served sync response against the session's budget.
+    ///
+    /// `has_more` keeps the room's sync active for the concurrency limit,
+    /// until the next page is requested or it idles past the timeout.
+    pub fn charge(
+        &mut self,
+        session_id: u64,
+        room_id: u128,
+        frames: usize,
+        bytes: usize,
+        has_more: bool,
+        now: Instant,
+    ) {
+        let frames = frames as u64;
+
+        self.metrics.requests = self.metrics.requests.saturating_add(1);
+        self.metrics.frames_served = self.metrics.frames_served.saturating_add(frames);
+        self.metrics.bytes_served = self.metrics.bytes_served.saturating_add(bytes as u64);
+
+        let Some(budget) = self.sessions.get_mut(&session_id) else {
+            return;
+        };
+        budget.tokens = budget.tokens.saturating_sub(frames.saturating_mul(TOKENS_PER_FRAME));
+        budget.frames_served = budget.frames_served.saturating_add(frames);
+        if has_more {
+            budget.active.insert(room_id, now);
+        } else {
+            budget.active.remove(&room_id);
+        }
+    }
+
+    /// Release the session's sync of `room_id`, which it will not page
+    /// through any further.
+    pub fn release(&mut self, session_id: u64, room_id: u128) {
+        if let Some(budget) = self.sessions.get_mut(&session_id) {
+            budget.active.remove(&room_id);
+        }
+    }
+
+    /// Charge frames a sync read from the log but did not return, as an
+    /// epoch-change sync skips messages, against the session's budget.
+    pub fn charge_skipped(&mut self, session_id: u64, frames: usize) {
+        if let Some(budget) = self.sessions.get_mut(&session_id) {
+            let tokens = (frames as u64).saturating_mul(TOKENS_PER_FRAME);
+            budget.tokens = budget.tokens.saturating_sub(tokens);
+        }
+    }
+
+    /// Forget a closed session.
+    pub fn remove_session(&mut self, session_id: u64) {
+        self.sessions.remove(&session_id);
+    }
+
+    /// Frames served to a session. `None` if it never synced.
+    pub fn frames_served(&self, session_id: u64) -> Option<u64> {
+        self.sessions.get(&session_id).map(|budget| budget.frames_served)
+    }
+
+    /// Server-wide sync counters.
+    pub fn metrics(&self) -> SyncMetrics {
+        self.metrics
+    }
+}
+
+fn refill(budget: &mut SessionBudget, config: &SyncBudgetConfig, now: Instant) {
+    let elapsed = now.saturating_duration_since(budget.refilled_at);
+    let elapsed_millis = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+
+    // frames/sec * ms = frames * 1000, one token per millisecond-frame
+    let earned = elapsed_millis.saturating_mul(config.frames_per_sec);
+    let cap = config.burst_frames.saturating_mul(TOKENS_PER_FRAME);
+
+    if earned > 0 {
+        budget.tokens = budget.tokens.saturating_add(earned).min(cap);
+        budget.refilled_at = now;
+    }
+}
+
+/// Time to earn `tokens` at `frames_per_sec`, rounded up to the millisecond.
+fn refill_time(tokens: u64, frames_per_sec: u64) -> Duration {
+    if frames_per_sec == 0 {
+        return Duration::MAX;
+    }
+    Duration::from_millis(tokens.div_ceil(frames_per_sec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOM: u128 = 0x1234;
+
+    fn config() -> SyncBudgetConfig {
+        SyncBudgetConfig {
+            frames_per_sec: 10,
+            burst_frames: 20,
+            max_concurrent: 2,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+
+    #[test]
+    fn requests_shrink_then_reject_when_budget_spent() {
+        let now = Instant::now();
+        let mut budgets = SyncBudgets::new(config());
+
+        assert_eq!(budgets.acquire(1, ROOM, 15, now), Ok(15));
+        budgets.charge(1, ROOM, 15, 1500, false, now);
+
+        assert_eq!(budgets.acquire(1, ROOM, 15, now), Ok(5));
+        budgets.charge(1, ROOM, 5, 500, false, now);
+
+        assert_eq!(
+            budgets.acquire(1, ROOM, 15, now),
+            Err(SyncDenied::BudgetExhausted { retry_after: Duration::from_millis(100) })
+        );
+
+        // Other sessions have their own budget
+        assert_eq!(budgets.acquire(2, ROOM, 15, now), Ok(15));
+
+        assert_eq!(budgets.metrics(), SyncMetrics {
+            requests: 2,
+            frames_served: 20,
+            bytes_served: 2000,
+            clamped: 1,
+            rejected: 1,
+        });
+        assert_eq!(budgets.frames_served(1), Some(20));
+    }
+
+    #[test]
+    fn skipped_frames_spend_budget_without_being_served() {
+        let now = Instant::now();
+        let mut budgets = SyncBudgets::new(config());
+
+        assert_eq!(budgets.acquire(1, ROOM, 20, now), Ok(20));
+        budgets.charge(1, ROOM, 2, 200, true, now);
+        budgets.charge_skipped(1, 13);
+
+        assert_eq!(budgets.acquire(1, ROOM, 20, now), Ok(5));
+        assert_eq!(budgets.frames_served(1), Some(2));
+    }
+
+    #[test]
+    fn budget_refills_up_to_burst() {
+        let now = Instant::now();
+        let mut budgets = SyncBudgets::new(config());
+
+        budgets.acquire(1, ROOM, 20, now).expect("within budget");
+        budgets.charge(1, ROOM, 20, 0, false, now);
+
+        assert_eq!(budgets.acquire(1, ROOM, 100, now + Duration::from_millis(500)), Ok(5));
+        assert_eq!(budgets.acquire(1, ROOM, 100, now + Duration::from_secs(60)), Ok(20));
+    }
+
+    #[test]
+    fn concurrent_paged_syncs_limited() {
+        let now = Instant::now();
+        let mut budgets = SyncBudgets::new(config());
+
+        for room in [1, 2] {
+            budgets.acquire(1, room, 1, now).expect("within budget");
+            budgets.charge(1, room, 1, 0, true, now);
+        }
+
+        assert_eq!(budgets.acquire(1, 3, 1, now), Err(SyncDenied::TooManyConcurrent { limit: 2 }));
+
+        // Continuing an active sync is allowed, and finishing it frees a slot
+        budgets.acquire(1, 1, 1, now).expect("active sync continues");
+        budgets.charge(1, 1, 1, 0, false, now);
+        assert_eq!(budgets.acquire(1, 3, 1, now), Ok(1));
+
+        budgets.remove_session(1);
+        assert_eq!(budgets.frames_served(1), None);
+    }
+
+    #[test]
+    fn abandoned_paged_syncs_free_their_slot() {
+        let now = Instant::now();
+        let mut budgets = SyncBudgets::new(config());
+
+        for room in [1, 2] {
+            budgets.acquire(1, room, 1, now).expect("within budget");
+            budgets.charge(1, room, 1, 0, true, now);
+        }
+
+        // Leaving the room releases its sync
+        budgets.release(1, 1);
+        budgets.acquire(1, 3, 1, now).expect("released slot");
+        budgets.charge(1, 3, 1, 0, true, now);
+        assert_eq!(budgets.acquire(1, 4, 1, now), Err(SyncDenied::TooManyConcurrent { limit: 2 }));
+
+        // Syncs whose next page never comes expire
+        let later = now + Duration::from_secs(30);
+        assert_eq!(budgets.acquire(1, 4, 1, later), Ok(1));
+    }
+}
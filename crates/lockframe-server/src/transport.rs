@@ -18,10 +18,20 @@
 //! protocol compatibility. Self-signed certificates are only suitable for local
 //! testing - production deployments MUST use proper TLS certificates from a
 //! trusted CA.
+//!
+//! With a client CA configured, the handshake also requires a client
+//! certificate chaining to it (mutual TLS). The client's identity is then
+//! available from [`QuinnConnection::peer_principal`].
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{fmt::Write as _, net::SocketAddr, sync::Arc};
 
 use quinn::{Endpoint, RecvStream, SendStream, ServerConfig};
+use rustls::{
+    RootCertStore,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+};
+use sha2::{Digest, Sha256};
 
 use crate::error::ServerError;
 
@@ -46,18 +56,22 @@ impl QuinnTransport {
     ///
     /// If `cert_path` and `key_path` are provided, they will be used for TLS.
     /// Otherwise, a self-signed certificate will be generated for testing.
+    /// If `client_ca_path` is provided, clients must present a certificate
+    /// issued by one of the CAs in that PEM file.
     pub async fn bind(
         address: &str,
         cert_path: Option<String>,
         key_path: Option<String>,
+        client_ca_path: Option<String>,
     ) -> Result<Self, ServerError> {
         let addr: SocketAddr = address.parse().map_err(|e| {
             ServerError::Config(format!("invalid bind address '{}': {}", address, e))
         })?;
 
+        let client_roots = client_ca_path.as_deref().map(load_client_roots).transpose()?;
         let server_config = match (cert_path, key_path) {
-            (Some(cert), Some(key)) => load_tls_config(&cert, &key)?,
-            _ => generate_self_signed_config()?,
+            (Some(cert), Some(key)) => load_tls_config(&cert, &key, client_roots)?,
+            _ => generate_self_signed_config(client_roots)?,
         };
 
         let endpoint = Endpoint::server(server_config, addr)
@@ -70,19 +84,23 @@ impl QuinnTransport {
 
     /// Accept a new QUIC connection.
     ///
-    /// This method blocks until a connection is available.
+    /// This method blocks until a connection is available and has completed
+    /// its handshake.
     pub async fn accept(&self) -> Result<QuinnConnection, ServerError> {
+        self.accept_incoming().await?.connect().await
+    }
+
+    /// Accept a new connection attempt, before its handshake.
+    ///
+    /// This method blocks until a connection attempt arrives.
+    pub async fn accept_incoming(&self) -> Result<QuinnIncoming, ServerError> {
         let incoming = self
             .endpoint
             .accept()
             .await
             .ok_or_else(|| ServerError::Transport("endpoint closed".to_string()))?;
 
-        let conn = incoming
-            .await
-            .map_err(|e| ServerError::Transport(format!("connection failed: {}", e)))?;
-
-        Ok(QuinnConnection { connection: conn })
+        Ok(QuinnIncoming { incoming })
     }
 
     /// Local address the transport is bound to.
@@ -93,11 +111,42 @@ impl QuinnTransport {
     }
 }
 
+/// A connection attempt that has not started its handshake.
+///
+/// Only the remote address is known. Refusing here costs the server no TLS
+/// work.
+pub struct QuinnIncoming {
+    incoming: quinn::Incoming,
+}
+
+impl QuinnIncoming {
+    /// Remote peer address.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.incoming.remote_address()
+    }
+
+    /// Complete the handshake.
+    pub async fn connect(self) -> Result<QuinnConnection, ServerError> {
+        let connection = self
+            .incoming
+            .await
+            .map_err(|e| ServerError::Transport(format!("connection failed: {}", e)))?;
+
+        Ok(QuinnConnection { connection })
+    }
+
+    /// Refuse the attempt without a handshake.
+    pub fn refuse(self) {
+        self.incoming.refuse();
+    }
+}
+
 /// A QUIC connection wrapper.
 ///
 /// Wraps Quinn's connection type and provides stream operations. Supports both
-/// bidirectional streams (accept_bi for server-initiated) and unidirectional
-/// streams (open_uni for server-to-client sends).
+/// bidirectional streams (`accept_bi` for client-initiated) and
+/// unidirectional streams (`open_uni` for server-to-client sends, `accept_uni`
+/// for the payloads of streamed frames).
 ///
 /// # Cloning
 ///
@@ -124,6 +173,14 @@ impl QuinnConnection {
             .map_err(|e| ServerError::Transport(format!("accept_bi failed: {}", e)))
     }
 
+    /// Accept a unidirectional stream, carrying a streamed frame's payload.
+    pub async fn accept_uni(&self) -> Result<RecvStream, ServerError> {
+        self.connection
+            .accept_uni()
+            .await
+            .map_err(|e| ServerError::Transport(format!("accept_uni failed: {}", e)))
+    }
+
     /// Open a unidirectional stream for sending.
     pub async fn open_uni(&self) -> Result<SendStream, ServerError> {
         self.connection
@@ -137,14 +194,87 @@ impl QuinnConnection {
         self.connection.remote_address()
     }
 
+    /// Identity of a client that authenticated with a certificate:
+    /// `sha256:` followed by the hex SHA-256 of its leaf certificate.
+    ///
+    /// `None` unless the transport was bound with a client CA.
+    pub fn peer_principal(&self) -> Option<String> {
+        let chain =
+            self.connection.peer_identity()?.downcast::<Vec<CertificateDer<'static>>>().ok()?;
+        chain.first().map(|leaf| certificate_principal(leaf))
+    }
+
     /// Close the connection with an error code and reason.
     pub fn close(&self, error_code: quinn::VarInt, reason: &[u8]) {
         self.connection.close(error_code, reason);
     }
 }
 
+/// Principal of a client certificate.
+fn certificate_principal(cert: &CertificateDer<'_>) -> String {
+    let digest = Sha256::digest(cert.as_ref());
+    digest.iter().fold(String::from("sha256:"), |mut principal, byte| {
+        let _ = write!(principal, "{byte:02x}");
+        principal
+    })
+}
+
+/// Load the CAs client certificates must chain to.
+fn load_client_roots(ca_path: &str) -> Result<RootCertStore, ServerError> {
+    let ca_pem = std::fs::read(ca_path).map_err(|e| {
+        ServerError::Config(format!("failed to read client CA '{}': {}", ca_path, e))
+    })?;
+
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut &ca_pem[..]) {
+        let cert =
+            cert.map_err(|e| ServerError::Config(format!("failed to parse client CA: {}", e)))?;
+        roots
+            .add(cert)
+            .map_err(|e| ServerError::Config(format!("invalid client CA certificate: {}", e)))?;
+    }
+    if roots.is_empty() {
+        return Err(ServerError::Config(format!("no certificates in client CA '{}'", ca_path)));
+    }
+
+    Ok(roots)
+}
+
+/// Build the QUIC server configuration, requiring client certificates
+/// issued by `client_roots` if given.
+fn build_server_config(
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    client_roots: Option<RootCertStore>,
+) -> Result<ServerConfig, ServerError> {
+    let builder = rustls::ServerConfig::builder();
+    let builder = match client_roots {
+        Some(roots) => {
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| ServerError::Config(format!("invalid client CA: {}", e)))?;
+            builder.with_client_cert_verifier(verifier)
+        },
+        None => builder.with_no_client_auth(),
+    };
+    let mut tls_config = builder
+        .with_single_cert(cert_chain, key)
+        .map_err(|e| ServerError::Config(format!("invalid TLS config: {}", e)))?;
+
+    tls_config.alpn_protocols = vec![b"lockframe".to_vec()];
+
+    Ok(ServerConfig::with_crypto(Arc::new(
+        quinn::crypto::rustls::QuicServerConfig::try_from(tls_config)
+            .map_err(|e| ServerError::Config(format!("QUIC config error: {}", e)))?,
+    )))
+}
+
 /// Load TLS configuration from certificate and key files.
-fn load_tls_config(cert_path: &str, key_path: &str) -> Result<ServerConfig, ServerError> {
+fn load_tls_config(
+    cert_path: &str,
+    key_path: &str,
+    client_roots: Option<RootCertStore>,
+) -> Result<ServerConfig, ServerError> {
     use std::fs;
 
     let cert_pem = fs::read(cert_path)
@@ -161,23 +291,13 @@ fn load_tls_config(cert_path: &str, key_path: &str) -> Result<ServerConfig, Serv
         .map_err(|e| ServerError::Config(format!("failed to parse private key: {}", e)))?
         .ok_or_else(|| ServerError::Config("no private key found".to_string()))?;
 
-    let mut tls_config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .map_err(|e| ServerError::Config(format!("invalid TLS config: {}", e)))?;
-
-    tls_config.alpn_protocols = vec![b"lockframe".to_vec()];
-
-    let server_config = ServerConfig::with_crypto(Arc::new(
-        quinn::crypto::rustls::QuicServerConfig::try_from(tls_config)
-            .map_err(|e| ServerError::Config(format!("QUIC config error: {}", e)))?,
-    ));
-
-    Ok(server_config)
+    build_server_config(certs, key, client_roots)
 }
 
 /// Generate a self-signed certificate for testing.
-fn generate_self_signed_config() -> Result<ServerConfig, ServerError> {
+fn generate_self_signed_config(
+    client_roots: Option<RootCertStore>,
+) -> Result<ServerConfig, ServerError> {
     let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
         .map_err(|e| ServerError::Config(format!("failed to generate self-signed cert: {}", e)))?;
 
@@ -187,17 +307,7 @@ fn generate_self_signed_config() -> Result<ServerConfig, ServerError> {
     let cert_chain = vec![cert_der];
     let key = rustls::pki_types::PrivatePkcs8KeyDer::from(key_der);
 
-    let mut tls_config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(cert_chain, key.into())
-        .map_err(|e| ServerError::Config(format!("invalid TLS config: {}", e)))?;
-
-    tls_config.alpn_protocols = vec![b"lockframe".to_vec()];
-
-    let server_config = ServerConfig::with_crypto(Arc::new(
-        quinn::crypto::rustls::QuicServerConfig::try_from(tls_config)
-            .map_err(|e| ServerError::Config(format!("QUIC config error: {}", e)))?,
-    ));
+    let server_config = build_server_config(cert_chain, key.into(), client_roots)?;
 
     tracing::warn!("Using self-signed certificate - not for production use!");
 
@@ -210,7 +320,7 @@ mod tests {
 
     #[tokio::test]
     async fn transport_binds_with_self_signed() {
-        let transport = QuinnTransport::bind("127.0.0.1:0", None, None).await;
+        let transport = QuinnTransport::bind("127.0.0.1:0", None, None, None).await;
         assert!(transport.is_ok(), "Transport should bind with self-signed cert");
 
         let transport = transport.unwrap();
@@ -218,9 +328,73 @@ mod tests {
         assert_ne!(addr.port(), 0, "Should have assigned a port");
     }
 
+    #[tokio::test]
+    async fn client_certificates_are_required_and_identify_the_peer() {
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+        use rustls::pki_types::PrivatePkcs8KeyDer;
+
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: String| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            Some(path.to_string_lossy().into_owned())
+        };
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let issue = |name: &str| {
+            let key = KeyPair::generate().unwrap();
+            let params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            (params.signed_by(&key, &ca, &ca_key).unwrap(), key)
+        };
+        let (server_cert, server_key) = issue("localhost");
+        let (client_cert, client_key) = issue("alice");
+
+        let transport = QuinnTransport::bind(
+            "127.0.0.1:0",
+            write("server.pem", server_cert.pem()),
+            write("server.key", server_key.serialize_pem()),
+            write("ca.pem", ca.pem()),
+        )
+        .await
+        .unwrap();
+        let addr = transport.local_addr().unwrap();
+
+        let connect = |client_auth: Option<(CertificateDer<'static>, PrivateKeyDer<'static>)>| {
+            let mut roots = RootCertStore::empty();
+            roots.add(ca.der().clone()).unwrap();
+            let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+            let mut tls = match client_auth {
+                Some((cert, key)) => builder.with_client_auth_cert(vec![cert], key).unwrap(),
+                None => builder.with_no_client_auth(),
+            };
+            tls.alpn_protocols = vec![b"lockframe".to_vec()];
+            let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(tls).unwrap();
+
+            let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+            endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+            async move {
+                let conn = endpoint.connect(addr, "localhost").unwrap().await?;
+                Ok::<_, quinn::ConnectionError>((endpoint, conn))
+            }
+        };
+
+        let client_der = client_cert.der().clone();
+        let key = PrivatePkcs8KeyDer::from(client_key.serialize_der()).into();
+        let (client, server) =
+            tokio::join!(connect(Some((client_der.clone(), key))), transport.accept());
+        let _client = client.unwrap();
+        assert_eq!(server.unwrap().peer_principal(), Some(certificate_principal(&client_der)));
+
+        let (_client, server) = tokio::join!(connect(None), transport.accept());
+        assert!(server.is_err(), "a client without a certificate must be refused");
+    }
+
     #[tokio::test]
     async fn transport_rejects_invalid_address() {
-        let result = QuinnTransport::bind("invalid:address:format", None, None).await;
+        let result = QuinnTransport::bind("invalid:address:format", None, None, None).await;
         assert!(result.is_err(), "Should reject invalid address");
     }
 }
@@ -0,0 +1,240 @@
+//! Per-room usage for billing.
+//!
+//! The driver counts, per room, the application messages it sequences, the
+//! encoded bytes of every frame it persists, the most members the room had,
+//! and the frame bytes it served to syncing clients. Counts accumulate in an
+//! open [`UsageWindow`] until
+//! [`ServerDriver::close_usage_window`](crate::ServerDriver::close_usage_window)
+//! closes it and opens the next one in the same step, so every frame is
+//! counted in exactly one window. Each room's counts name the last log index
+//! they cover, which lines a report up with the room's log.
+//!
+//! The open window is written to storage on every tick it changed in and
+//! reloaded when the driver starts, so a restart loses at most one tick of
+//! counts. It holds each room's current member count too, so peaks carry
+//! over into the next window across a restart. A window only closes once
+//! the next one is stored; otherwise a restart would count the closed
+//! window's frames again.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Usage of one room within a window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomUsage {
+    /// Application messages sequenced
+    pub messages: u64,
+    /// Encoded bytes of every frame sequenced
+    pub bytes_stored: u64,
+    /// Most members the room had at once
+    pub peak_members: u64,
+    /// Frame bytes sent in sync responses
+    pub sync_bytes_served: u64,
+    /// Last log index counted (`None` if no frame was sequenced)
+    pub last_log_index: Option<u64>,
+}
+
+/// Usage counted since a window opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageWindow {
+    /// Wall clock time the window opened (Unix millis)
+    pub started_at_millis: u64,
+    /// Usage per room with any activity in the window
+    pub rooms: BTreeMap<u128, RoomUsage>,
+    /// Current members per hosted room, carried into the next window's peak
+    #[serde(default)]
+    pub members: BTreeMap<u128, u64>,
+}
+
+/// A closed window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageReport {
+    /// Wall clock time the window opened (Unix millis)
+    pub started_at_millis: u64,
+    /// Wall clock time the window closed (Unix millis)
+    pub ended_at_millis: u64,
+    /// Usage per room with any activity in the window
+    pub rooms: BTreeMap<u128, RoomUsage>,
+}
+
+/// Counts usage into the open window.
+#[derive(Debug, Default)]
+pub struct UsageAccumulator {
+    window: UsageWindow,
+    /// Whether the window changed since it was last stored
+    dirty: bool,
+}
+
+impl UsageAccumulator {
+    /// Open the first window at `started_at_millis`.
+    pub fn new(started_at_millis: u64) -> Self {
+        Self { window: UsageWindow { started_at_millis, ..UsageWindow::default() }, dirty: true }
+    }
+
+    /// Continue a window loaded from storage.
+    pub fn restore(window: UsageWindow) -> Self {
+        Self { window, dirty: false }
+    }
+
+    /// A frame was sequenced at `log_index`. A frame at or below the last
+    /// index counted is the same frame persisted twice and is ignored.
+    pub fn record_frame(&mut self, room_id: u128, log_index: u64, bytes: u64, is_message: bool) {
+        let usage = self.room(room_id);
+        if usage.last_log_index.is_some_and(|last| last >= log_index) {
+            return;
+        }
+        if is_message {
+            usage.messages = usage.messages.saturating_add(1);
+        }
+        usage.bytes_stored = usage.bytes_stored.saturating_add(bytes);
+        usage.last_log_index = usage.last_log_index.max(Some(log_index));
+    }
+
+    /// The room has `members` members now.
+    pub fn record_members(&mut self, room_id: u128, members: u64) {
+        self.window.members.insert(room_id, members);
+        let usage = self.room(room_id);
+        usage.peak_members = usage.peak_members.max(members);
+    }
+
+    /// `bytes` of frames were served to a syncing client.
+    pub fn record_sync(&mut self, room_id: u128, bytes: u64) {
+        let usage = self.room(room_id);
+        usage.sync_bytes_served = usage.sync_bytes_served.saturating_add(bytes);
+    }
+
+    /// The room is no longer hosted here.
+    pub fn remove_room(&mut self, room_id: u128) {
+        self.dirty |= self.window.members.remove(&room_id).is_some();
+    }
+
+    /// The open window.
+    pub fn window(&self) -> &UsageWindow {
+        &self.window
+    }
+
+    /// The open window if it changed since it was last stored.
+    pub fn changed(&self) -> Option<&UsageWindow> {
+        self.dirty.then_some(&self.window)
+    }
+
+    /// The open window was stored.
+    pub fn mark_stored(&mut self) {
+        self.dirty = false;
+    }
+
+    /// Close the open window at `now_millis` and open the next one, once
+    /// `store` stored it. Rooms with members start the next window with
+    /// their current member count as the peak.
+    ///
+    /// If `store` fails the open window stays open and nothing is lost.
+    pub fn close<E>(
+        &mut self,
+        now_millis: u64,
+        store: impl FnOnce(&UsageWindow) -> Result<(), E>,
+    ) -> Result<UsageReport, E> {
+        let rooms = self
+            .window
+            .members
+            .iter()
+            .filter(|(_, members)| **members > 0)
+            .map(|(room_id, members)| {
+                (*room_id, RoomUsage { peak_members: *members, ..RoomUsage::default() })
+            })
+            .collect();
+        let next = UsageWindow {
+            started_at_millis: now_millis,
+            rooms,
+            members: self.window.members.clone(),
+        };
+        store(&next)?;
+        let closed = std::mem::replace(&mut self.window, next);
+        self.dirty = false;
+
+        Ok(UsageReport {
+            started_at_millis: closed.started_at_millis,
+            ended_at_millis: now_millis,
+            rooms: closed.rooms,
+        })
+    }
+
+    fn room(&mut self, room_id: u128) -> &mut RoomUsage {
+        self.dirty = true;
+        self.window.rooms.entry(room_id).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(_: &UsageWindow) -> Result<(), ()> {
+        Ok(())
+    }
+
+    #[test]
+    fn closing_splits_counts_between_windows() {
+        let mut usage = UsageAccumulator::new(1_000);
+        usage.record_members(1, 2);
+        usage.record_frame(1, 0, 200, false);
+        usage.record_frame(1, 1, 300, true);
+        usage.record_frame(1, 1, 300, true);
+        usage.record_members(1, 3);
+        usage.record_members(1, 1);
+        usage.record_sync(1, 500);
+
+        let first = usage.close(2_000, stored).unwrap();
+        assert_eq!((first.started_at_millis, first.ended_at_millis), (1_000, 2_000));
+        assert_eq!(first.rooms[&1], RoomUsage {
+            messages: 1,
+            bytes_stored: 500,
+            peak_members: 3,
+            sync_bytes_served: 500,
+            last_log_index: Some(1),
+        });
+
+        usage.record_frame(1, 2, 100, true);
+        let second = usage.close(3_000, stored).unwrap();
+        assert_eq!(second.started_at_millis, 2_000);
+        assert_eq!(second.rooms[&1], RoomUsage {
+            messages: 1,
+            bytes_stored: 100,
+            peak_members: 1,
+            sync_bytes_served: 0,
+            last_log_index: Some(2),
+        });
+    }
+
+    #[test]
+    fn window_stays_open_if_the_next_is_not_stored() {
+        let mut usage = UsageAccumulator::new(1_000);
+        usage.record_frame(1, 0, 200, true);
+
+        assert_eq!(usage.close(2_000, |_| Err("disk full")), Err("disk full"));
+        assert_eq!(usage.window().started_at_millis, 1_000);
+        assert_eq!(usage.window().rooms[&1].messages, 1);
+    }
+
+    #[test]
+    fn restored_window_carries_peaks_into_the_next() {
+        let mut usage = UsageAccumulator::new(1_000);
+        usage.record_members(1, 4);
+
+        let mut restored = UsageAccumulator::restore(usage.window().clone());
+        let report = restored.close(2_000, stored).unwrap();
+        assert_eq!(report.rooms[&1].peak_members, 4);
+        assert_eq!(restored.window().rooms[&1].peak_members, 4);
+    }
+
+    #[test]
+    fn window_is_changed_until_stored() {
+        let mut usage = UsageAccumulator::restore(UsageWindow::default());
+        assert!(usage.changed().is_none());
+
+        usage.record_sync(7, 10);
+        assert_eq!(usage.changed().map(|window| window.rooms.len()), Some(1));
+        usage.mark_stored();
+        assert!(usage.changed().is_none());
+    }
+}
@@ -0,0 +1,195 @@
+//! Scheduled storage vacuum.
+//!
+//! Retention and archiving compact rooms, which deletes frames but leaves
+//! the space they took inside the database file. A vacuum pass hands that
+//! space back to the file system through [`Storage::vacuum`].
+//!
+//! Passes run on a fixed interval, optionally only inside a daily window
+//! (say, overnight), and each pass rewrites at most a configured number of
+//! bytes so it never competes with frame writes for long. Space left over is
+//! picked up by the next pass.
+
+use std::{str::FromStr, time::Duration};
+
+use crate::storage::{Storage, StorageError};
+
+/// Default time between vacuum passes.
+pub const DEFAULT_VACUUM_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Default most bytes a vacuum pass rewrites.
+pub const DEFAULT_VACUUM_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Milliseconds in a day.
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// Daily window, in UTC, during which vacuum passes may run.
+///
+/// A window whose end is before its start wraps past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VacuumWindow {
+    /// Opening time, as an offset from midnight
+    pub start: Duration,
+    /// Closing time, as an offset from midnight
+    pub end: Duration,
+}
+
+impl VacuumWindow {
+    /// Whether the window is open at `now_millis` (Unix milliseconds).
+    pub fn contains(&self, now_millis: u64) -> bool {
+        let time_of_day = Duration::from_millis(now_millis % DAY_MILLIS);
+        if self.start <= self.end {
+            self.start <= time_of_day && time_of_day < self.end
+        } else {
+            time_of_day >= self.start || time_of_day < self.end
+        }
+    }
+}
+
+impl FromStr for VacuumWindow {
+    type Err = String;
+
+    /// Parse `HH:MM-HH:MM`, e.g. `02:00-05:00` or `22:30-04:00`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid vacuum window {value:?}, expected HH:MM-HH:MM");
+        let time_of_day = |time: &str| -> Option<Duration> {
+            let (hours, minutes) = time.split_once(':')?;
+            let hours: u64 = hours.parse().ok().filter(|h| *h < 24)?;
+            let minutes: u64 = minutes.parse().ok().filter(|m| *m < 60)?;
+            Some(Duration::from_secs(
+                hours.saturating_mul(3600).saturating_add(minutes.saturating_mul(60)),
+            ))
+        };
+
+        let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+        Ok(Self {
+            start: time_of_day(start).ok_or_else(invalid)?,
+            end: time_of_day(end).ok_or_else(invalid)?,
+        })
+    }
+}
+
+/// When and how hard to vacuum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VacuumConfig {
+    /// Time between vacuum passes
+    pub interval: Duration,
+    /// Only vacuum inside this daily window (`None` for any time)
+    pub window: Option<VacuumWindow>,
+    /// Most bytes a single pass rewrites
+    pub max_bytes_per_pass: u64,
+}
+
+impl Default for VacuumConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_VACUUM_INTERVAL,
+            window: None,
+            max_bytes_per_pass: DEFAULT_VACUUM_MAX_BYTES,
+        }
+    }
+}
+
+/// Vacuum counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VacuumMetrics {
+    /// Passes that ran
+    pub passes: u64,
+    /// Passes skipped because the window was closed
+    pub skipped: u64,
+    /// Passes that failed
+    pub failures: u64,
+    /// Bytes returned to the file system across all passes
+    pub bytes_reclaimed: u64,
+}
+
+/// Vacuum schedule and counters.
+#[derive(Debug, Default)]
+pub struct Vacuum {
+    config: VacuumConfig,
+    metrics: VacuumMetrics,
+}
+
+impl Vacuum {
+    /// Create a vacuum schedule.
+    pub fn new(config: VacuumConfig) -> Self {
+        Self { config, metrics: VacuumMetrics::default() }
+    }
+
+    /// Time between vacuum passes.
+    pub fn interval(&self) -> Duration {
+        self.config.interval
+    }
+
+    /// Counters so far.
+    pub fn metrics(&self) -> VacuumMetrics {
+        self.metrics
+    }
+
+    /// Run a pass over `storage` if the window is open at `now_millis`.
+    ///
+    /// Returns the bytes reclaimed, or `None` if the pass was skipped.
+    pub fn run<S: Storage>(
+        &mut self,
+        storage: &S,
+        now_millis: u64,
+    ) -> Result<Option<u64>, StorageError> {
+        if self.config.window.is_some_and(|window| !window.contains(now_millis)) {
+            self.metrics.skipped = self.metrics.skipped.saturating_add(1);
+            return Ok(None);
+        }
+
+        self.metrics.passes = self.metrics.passes.saturating_add(1);
+        match storage.vacuum(self.config.max_bytes_per_pass) {
+            Ok(reclaimed) => {
+                self.metrics.bytes_reclaimed =
+                    self.metrics.bytes_reclaimed.saturating_add(reclaimed);
+                Ok(Some(reclaimed))
+            },
+            Err(e) => {
+                self.metrics.failures = self.metrics.failures.saturating_add(1);
+                Err(e)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    const HOUR_MILLIS: u64 = 60 * 60 * 1000;
+
+    #[test]
+    fn window_parses_and_wraps_midnight() {
+        let night: VacuumWindow = "22:30-04:00".parse().unwrap();
+        assert_eq!(night.start, Duration::from_secs(22 * 3600 + 30 * 60));
+        assert!(night.contains(23 * HOUR_MILLIS));
+        assert!(night.contains(DAY_MILLIS + 3 * HOUR_MILLIS));
+        assert!(!night.contains(4 * HOUR_MILLIS));
+        assert!(!night.contains(12 * HOUR_MILLIS));
+
+        let early: VacuumWindow = "02:00-05:00".parse().unwrap();
+        assert!(early.contains(2 * HOUR_MILLIS));
+        assert!(!early.contains(5 * HOUR_MILLIS));
+
+        assert!("24:00-01:00".parse::<VacuumWindow>().is_err());
+        assert!("02:00".parse::<VacuumWindow>().is_err());
+    }
+
+    #[test]
+    fn passes_outside_window_are_skipped() {
+        let window = "02:00-05:00".parse().ok();
+        let mut vacuum = Vacuum::new(VacuumConfig { window, ..Default::default() });
+        let storage = MemoryStorage::new();
+
+        assert_eq!(vacuum.run(&storage, 12 * HOUR_MILLIS).unwrap(), None);
+        assert_eq!(vacuum.run(&storage, 3 * HOUR_MILLIS).unwrap(), Some(0));
+        assert_eq!(vacuum.metrics(), VacuumMetrics {
+            passes: 1,
+            skipped: 1,
+            failures: 0,
+            bytes_reclaimed: 0
+        });
+    }
+}
@@ -0,0 +1,117 @@
+//! HTTP delivery of archive batches.
+//!
+//! A deliberately small HTTP/1.1 client: one `POST` per connection, success
+//! on any 2xx status. Only plain `http://` endpoints are supported; archivers
+//! reached over untrusted networks belong behind a local TLS-terminating
+//! proxy.
+
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Longest a single delivery attempt may take.
+const POST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most response bytes read while looking for the status line.
+const MAX_STATUS_LINE: usize = 1024;
+
+/// `POST` a CBOR body to `endpoint`.
+pub async fn post(endpoint: &str, body: &[u8]) -> Result<(), String> {
+    tokio::time::timeout(POST_TIMEOUT, post_inner(endpoint, body))
+        .await
+        .map_err(|_| format!("timed out after {}s", POST_TIMEOUT.as_secs()))?
+}
+
+async fn post_inner(endpoint: &str, body: &[u8]) -> Result<(), String> {
+    let (host, path) = parse_endpoint(endpoint)?;
+    let address = if host.contains(':') { host.to_string() } else { format!("{host}:80") };
+
+    let mut stream = TcpStream::connect(&address).await.map_err(|e| e.to_string())?;
+    let head = format!(
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/cbor\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.map_err(|e| e.to_string())?;
+    stream.write_all(body).await.map_err(|e| e.to_string())?;
+
+    let mut response = Vec::new();
+    let mut chunk = [0u8; 256];
+    while !response.contains(&b'\n') && response.len() < MAX_STATUS_LINE {
+        let read = stream.read(&mut chunk).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(chunk.get(..read).unwrap_or_default());
+    }
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if status.starts_with('2') && status.len() == 3 {
+        Ok(())
+    } else {
+        Err(format!("archiver responded {:?}", status_line.lines().next().unwrap_or_default()))
+    }
+}
+
+/// Split an `http://host[:port]/path` URL into host and path.
+fn parse_endpoint(endpoint: &str) -> Result<(&str, &str), String> {
+    let rest = endpoint
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("unsupported archiver endpoint {endpoint:?}, expected http://"))?;
+    let (host, path) = rest.find('/').map_or((rest, "/"), |slash| rest.split_at(slash));
+    if host.is_empty() {
+        return Err(format!("archiver endpoint {endpoint:?} has no host"));
+    }
+    Ok((host, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Accept one request, reply with `status`, and return the request bytes.
+    async fn serve_once(listener: TcpListener, status: &'static str) -> Vec<u8> {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut chunk = [0u8; 1024];
+        while !request.ends_with(b"body") {
+            let read = stream.read(&mut chunk).await.unwrap();
+            request.extend_from_slice(&chunk[..read]);
+        }
+        stream.write_all(format!("HTTP/1.1 {status}\r\n\r\n").as_bytes()).await.unwrap();
+        request
+    }
+
+    #[test]
+    fn endpoints_split_into_host_and_path() {
+        assert_eq!(parse_endpoint("http://archiver:8080/rooms"), Ok(("archiver:8080", "/rooms")));
+        assert_eq!(parse_endpoint("http://archiver"), Ok(("archiver", "/")));
+        assert!(parse_endpoint("https://archiver/").is_err());
+        assert!(parse_endpoint("http:///rooms").is_err());
+    }
+
+    #[tokio::test]
+    async fn post_succeeds_on_2xx_only() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/archive", listener.local_addr().unwrap());
+        let server = tokio::spawn(serve_once(listener, "204 No Content"));
+
+        post(&endpoint, b"body").await.unwrap();
+        let request = String::from_utf8(server.await.unwrap()).unwrap();
+        assert!(request.starts_with("POST /archive HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Length: 4\r\n"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/archive", listener.local_addr().unwrap());
+        let server = tokio::spawn(serve_once(listener, "503 Service Unavailable"));
+
+        assert!(post(&endpoint, b"body").await.is_err());
+        server.await.unwrap();
+    }
+}
@@ -10,7 +10,9 @@
 use bytes::Bytes;
 use lockframe_core::mls::MlsGroupState;
 use lockframe_proto::{Frame, FrameHeader, Opcode};
-use lockframe_server::storage::{ChaoticStorage, MemoryStorage, Storage, StorageError};
+use lockframe_server::storage::{
+    ChaoticStorage, FaultProfile, MemoryStorage, Storage, StorageError,
+};
 use proptest::prelude::*;
 
 /// Create a test frame with specific parameters
@@ -356,3 +358,43 @@ fn prop_storage_chaos_concurrent_rooms() {
         }
     });
 }
+
+#[test]
+fn prop_storage_torn_batches_stay_gap_free() {
+    proptest!(|(
+        partial_rate in 0.0..1.0,
+        failure_rate in 0.0..0.3,
+        seed in any::<u64>(),
+        batch_count in 5usize..30,
+        batch_len in 1usize..8,
+    )| {
+        let profile = FaultProfile::new(seed)
+            .with_write_failures(failure_rate)
+            .with_partial_writes(partial_rate);
+
+        let run = || {
+            let storage = ChaoticStorage::with_profile(MemoryStorage::new(), profile.clone());
+            for _ in 0..batch_count {
+                // Resume after whatever the previous, possibly torn, batch left behind
+                let next = storage.inner().latest_log_index(1).expect("query failed");
+                let start = next.map_or(0, |latest| latest + 1);
+                let frames: Vec<_> = (start..start + batch_len as u64)
+                    .map(|i| create_test_frame(1, i, vec![i as u8]))
+                    .collect();
+                let _ = storage.store_frames_batch(1, &frames);
+            }
+            storage
+        };
+
+        // ORACLE: Torn writes only ever leave a gap-free prefix behind
+        let first = run();
+        verify_frame_sequence(first.inner(), 1).expect("Frame sequence verification failed");
+
+        // ORACLE: The same profile reproduces the same failure schedule
+        let second = run();
+        prop_assert_eq!(
+            first.inner().latest_log_index(1).expect("query failed"),
+            second.inner().latest_log_index(1).expect("query failed")
+        );
+    });
+}
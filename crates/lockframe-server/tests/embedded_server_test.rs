@@ -0,0 +1,316 @@
+//! Embedded server tests
+//!
+//! Runs the full server runtime in process on the in-memory transport.
+
+use lockframe_proto::{FrameHeader, Opcode, Payload, payloads::session::Hello};
+use lockframe_server::{
+    AcceptDecision, AcceptFilter, PeerInfo, Sequencer, Server, ServerRuntimeConfig,
+};
+
+fn hello() -> lockframe_proto::Frame {
+    Payload::Hello(Hello { version: 1, capabilities: vec![], auth_token: None })
+        .into_frame(FrameHeader::new(Opcode::Hello))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn in_memory_server_serves_clients_until_shutdown() {
+    let config = ServerRuntimeConfig { in_memory: true, ..ServerRuntimeConfig::default() };
+    let server = Server::spawn_in_process(config).await.unwrap();
+    assert!(server.local_addr().is_none());
+
+    let mut client = server.connect().unwrap();
+    client.send(&hello()).await.unwrap();
+    let (reply, _) = client.recv().await.unwrap().unwrap();
+    assert_eq!(reply.header.opcode_enum(), Some(Opcode::HelloReply));
+
+    let stats = server.stats().await;
+    assert_eq!((stats.connections, stats.rooms), (1, 0));
+
+    server.shutdown().await.unwrap();
+    assert!(client.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn quic_server_has_no_in_memory_clients() {
+    let config =
+        ServerRuntimeConfig { bind_address: "127.0.0.1:0".to_string(), ..Default::default() };
+    let server = Server::spawn_in_process(config).await.unwrap();
+
+    assert!(server.local_addr().is_some_and(|addr| addr.port() != 0));
+    assert!(server.connect().is_err());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn migrating_unknown_room_fails() {
+    let config = ServerRuntimeConfig { in_memory: true, ..ServerRuntimeConfig::default() };
+    let server = Server::spawn_in_process(config).await.unwrap();
+
+    assert!(server.migrate_room(0x42, "lockframe-2.example:4433").await.is_err());
+    assert_eq!(server.stats().await.rooms, 0);
+    server.shutdown().await.unwrap();
+}
+
+/// Lets the first `remaining` connections in.
+struct Admit {
+    remaining: usize,
+}
+
+impl AcceptFilter for Admit {
+    fn name(&self) -> &'static str {
+        "admit"
+    }
+
+    fn check(&mut self, _peer: &PeerInfo) -> AcceptDecision {
+        match self.remaining.checked_sub(1) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                AcceptDecision::Accept
+            },
+            None => AcceptDecision::Reject { reason: "no more clients".to_string() },
+        }
+    }
+}
+
+#[tokio::test]
+async fn accept_filter_closes_refused_connections_before_a_session_exists() {
+    let config = ServerRuntimeConfig { in_memory: true, ..ServerRuntimeConfig::default() };
+    let mut server = Server::bind(config).await.unwrap();
+    server.add_accept_filter(Admit { remaining: 1 });
+    let server = server.spawn();
+
+    let mut admitted = server.connect().unwrap();
+    admitted.send(&hello()).await.unwrap();
+    assert!(admitted.recv().await.is_some());
+
+    let mut refused = server.connect().unwrap();
+    assert!(refused.recv().await.is_none());
+    assert_eq!(server.stats().await.connections, 1);
+
+    server.shutdown().await.unwrap();
+}
+
+/// Send a bodiless admin API request and return the raw response.
+async fn admin_request(addr: std::net::SocketAddr, method: &str, path: &str) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let request = format!("{method} {path} HTTP/1.1\r\nHost: admin\r\nContent-Length: 0\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn admin_api_lists_and_closes_sessions() {
+    let config = ServerRuntimeConfig {
+        in_memory: true,
+        admin_bind: Some("127.0.0.1:0".to_string()),
+        ..ServerRuntimeConfig::default()
+    };
+    let server = Server::spawn_in_process(config).await.unwrap();
+    let admin = server.admin_addr().unwrap();
+
+    let mut client = server.connect().unwrap();
+    client.send(&hello()).await.unwrap();
+    client.recv().await.unwrap().unwrap();
+
+    let sessions = admin_request(admin, "GET", "/sessions").await;
+    assert!(sessions.starts_with("HTTP/1.1 200 OK"));
+    let session_id: u64 = sessions
+        .split("\"session_id\":")
+        .nth(1)
+        .and_then(|rest| rest.split(',').next())
+        .and_then(|id| id.parse().ok())
+        .unwrap();
+
+    let storage = admin_request(admin, "GET", "/storage").await;
+    assert!(storage.contains("\r\n\r\n{\"rooms\":0,\"frames\":0,"));
+
+    let closed = admin_request(admin, "POST", &format!("/sessions/{session_id}/close")).await;
+    assert!(closed.starts_with("HTTP/1.1 200 OK"));
+    assert!(client.recv().await.is_none());
+
+    // The session's own task reports the close, with the administrator's reason
+    let mut audit = String::new();
+    for _ in 0..100 {
+        audit = admin_request(admin, "GET", "/audit").await;
+        if audit.contains("connection_closed") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert!(audit.contains("\"reason\":\"closed by administrator\""), "{audit}");
+
+    let again = admin_request(admin, "POST", &format!("/sessions/{session_id}/close")).await;
+    assert!(again.starts_with("HTTP/1.1 404"));
+    assert!(admin_request(admin, "GET", "/nope").await.starts_with("HTTP/1.1 404"));
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn maintenance_notifies_and_closes_sessions_and_refuses_new_ones() {
+    let config = ServerRuntimeConfig { in_memory: true, ..ServerRuntimeConfig::default() };
+    let server = Server::spawn_in_process(config).await.unwrap();
+
+    let mut client = server.connect().unwrap();
+    client.send(&hello()).await.unwrap();
+    client.recv().await.unwrap().unwrap();
+
+    let report =
+        server.enter_maintenance(std::time::Duration::from_secs(60), "upgrade").await.unwrap();
+    assert_eq!(report.sessions_closed, 1);
+    assert!(report.drained);
+
+    let (notice, _) = client.recv().await.unwrap().unwrap();
+    let Ok(Payload::Maintenance(notice)) = Payload::from_frame(notice) else {
+        panic!("expected maintenance notice");
+    };
+    assert_eq!((notice.reconnect_after_secs, notice.reason.as_str()), (60, "upgrade"));
+    assert!(client.recv().await.is_none());
+
+    let mut refused = server.connect().unwrap();
+    assert!(refused.recv().await.is_none());
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn sharded_server_sequences_with_the_given_backend() {
+    let config = ServerRuntimeConfig { in_memory: true, shards: 3, ..Default::default() };
+    let mut built = 0;
+    let server = Server::bind_with_sequencer(config, || {
+        built += 1;
+        Sequencer::new()
+    })
+    .await
+    .unwrap();
+    // One for the primary and one per shard of rooms
+    assert_eq!(built, 4);
+
+    let server = server.spawn();
+    let mut client = server.connect().unwrap();
+    client.send(&hello()).await.unwrap();
+    let (reply, _) = client.recv().await.unwrap().unwrap();
+    assert_eq!(reply.header.opcode_enum(), Some(Opcode::HelloReply));
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn sharded_server_serves_sessions_across_shards() {
+    use lockframe_proto::payloads::session::Heartbeat;
+
+    let config = ServerRuntimeConfig {
+        in_memory: true,
+        admin_bind: Some("127.0.0.1:0".to_string()),
+        shards: 3,
+        ..ServerRuntimeConfig::default()
+    };
+    let server = Server::spawn_in_process(config).await.unwrap();
+    let admin = server.admin_addr().unwrap();
+
+    let mut client = server.connect().unwrap();
+    client.send(&hello()).await.unwrap();
+    let (reply, _) = client.recv().await.unwrap().unwrap();
+    assert_eq!(reply.header.opcode_enum(), Some(Opcode::HelloReply));
+
+    // Welcomes for rooms hosted by two different shards
+    for room_id in [1u128, 2] {
+        let mut header = FrameHeader::new(Opcode::Welcome);
+        header.set_room_id(room_id);
+        let welcome = lockframe_proto::Frame::new(header, bytes::Bytes::from("fake welcome"));
+        client.send(&welcome).await.unwrap();
+    }
+
+    // Session frames are still answered by the session's owner
+    let heartbeat = Payload::Heartbeat(Heartbeat { timestamp_micros: 7 })
+        .into_frame(FrameHeader::new(Opcode::Heartbeat))
+        .unwrap();
+    client.send(&heartbeat).await.unwrap();
+    let ack = loop {
+        let (frame, _) = client.recv().await.unwrap().unwrap();
+        if frame.header.opcode_enum() == Some(Opcode::HeartbeatAck) {
+            break frame;
+        }
+    };
+    assert_eq!(
+        Payload::from_frame(ack).unwrap(),
+        Payload::HeartbeatAck(Heartbeat { timestamp_micros: 7 })
+    );
+
+    let sessions = admin_request(admin, "GET", "/sessions").await;
+    let rooms = format!("\"rooms\":[\"{:032x}\",\"{:032x}\"]", 1, 2);
+    assert!(sessions.contains(&rooms), "{sessions}");
+    assert_eq!(server.stats().await.connections, 1);
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn frames_from_one_connection_are_answered_in_order() {
+    use lockframe_proto::payloads::session::Heartbeat;
+
+    let config = ServerRuntimeConfig { in_memory: true, ..ServerRuntimeConfig::default() };
+    let server = Server::spawn_in_process(config).await.unwrap();
+
+    let mut client = server.connect().unwrap();
+    client.send(&hello()).await.unwrap();
+    client.recv().await.unwrap().unwrap();
+
+    // More frames than the connection's mailbox holds, sent without waiting
+    for timestamp_micros in 0..200 {
+        let heartbeat = Payload::Heartbeat(Heartbeat { timestamp_micros })
+            .into_frame(FrameHeader::new(Opcode::Heartbeat))
+            .unwrap();
+        client.send(&heartbeat).await.unwrap();
+    }
+    for timestamp_micros in 0..200 {
+        let (ack, _) = client.recv().await.unwrap().unwrap();
+        assert_eq!(
+            Payload::from_frame(ack).unwrap(),
+            Payload::HeartbeatAck(Heartbeat { timestamp_micros })
+        );
+    }
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn federated_servers_relay_over_their_links() {
+    use lockframe_proto::payloads::session::{SyncMode, SyncRequest};
+    use lockframe_server::{DriverConfig, ServerId};
+
+    const ROOM: u128 = 0x42;
+    let (home_id, edge_id) = (ServerId(1), ServerId(2));
+    let config = |server_id| ServerRuntimeConfig {
+        in_memory: true,
+        driver: DriverConfig { server_id, ..DriverConfig::default() },
+        ..ServerRuntimeConfig::default()
+    };
+
+    let home = Server::spawn_in_process(config(home_id)).await.unwrap();
+    let mut edge = Server::bind(config(edge_id)).await.unwrap();
+    edge.set_room_home(ROOM, home_id);
+    let edge = edge.spawn();
+    home.add_relay_link(edge_id, edge.relay_link(home_id)).await;
+    edge.add_relay_link(home_id, home.relay_link(edge_id)).await;
+
+    let mut client = edge.connect().unwrap();
+    client.send(&hello()).await.unwrap();
+    client.recv().await.unwrap().unwrap();
+
+    // Answered by the home, which knows no such room
+    let mut header = FrameHeader::new(Opcode::SyncRequest);
+    header.set_room_id(ROOM);
+    let request = SyncRequest { from_log_index: 0, limit: 10, mode: SyncMode::Full };
+    client.send(&Payload::SyncRequest(request).into_frame(header).unwrap()).await.unwrap();
+    let (reply, _) = client.recv().await.unwrap().unwrap();
+    assert_eq!(reply.header.room_id(), ROOM);
+    assert_eq!(reply.header.opcode_enum(), Some(Opcode::Error));
+
+    edge.shutdown().await.unwrap();
+    home.shutdown().await.unwrap();
+}
@@ -6,8 +6,17 @@ use std::{collections::HashMap, time::Duration};
 use bytes::Bytes;
 use ed25519_dalek::{Signer, SigningKey};
 use lockframe_core::{env::Environment, mls::MlsGroupState};
-use lockframe_proto::{Frame, FrameHeader, Opcode};
-use lockframe_server::{MemoryStorage, RoomAction, RoomError, RoomManager, Storage};
+use lockframe_proto::{
+    Frame, FrameHeader, Opcode, Payload,
+    payloads::{
+        app::{EncryptedMessage, ReadReceipt},
+        session::{SyncMode, SyncRequest},
+    },
+};
+use lockframe_server::{
+    MAX_PENDING_PROPOSALS, MemoryStorage, RoomAction, RoomError, RoomManager, SqliteStorage,
+    Storage,
+};
 
 // Test environment using system RNG (std::time::Instant)
 #[derive(Clone)]
@@ -30,6 +39,36 @@ impl Environment for TestEnv {
     }
 }
 
+/// Minimal well-formed `AppMessage` envelope for `epoch`.
+fn encrypted_payload(epoch: u64) -> Bytes {
+    encrypted_payload_at(epoch, 0)
+}
+
+/// Minimal well-formed `AppMessage` envelope for `epoch` and `generation`.
+fn encrypted_payload_at(epoch: u64, generation: u32) -> Bytes {
+    let mut nonce = [0u8; 24];
+    nonce[0..8].copy_from_slice(&epoch.to_be_bytes());
+    nonce[12..16].copy_from_slice(&generation.to_be_bytes());
+
+    let message = EncryptedMessage {
+        epoch,
+        sender_index: 0,
+        generation,
+        padding: 0,
+        cipher_suite: 0,
+        nonce,
+        ciphertext: vec![0; EncryptedMessage::TAG_SIZE],
+        push_keys: None,
+    };
+
+    Payload::AppMessage(message).into_frame(FrameHeader::new(Opcode::AppMessage)).unwrap().payload
+}
+
+/// Full sync request from `from_log_index`.
+fn full_sync(from_log_index: u64, limit: u64) -> SyncRequest {
+    SyncRequest { from_log_index, limit, mode: SyncMode::Full }
+}
+
 #[test]
 fn room_manager_new_has_no_rooms() {
     let manager = RoomManager::<TestEnv>::new();
@@ -128,7 +167,7 @@ fn process_frame_succeeds_for_valid_frame() {
     header.set_room_id(room_id);
     header.set_sender_id(creator);
     header.set_epoch(0);
-    let frame = Frame::new(header, Bytes::new());
+    let frame = Frame::new(header, encrypted_payload(0));
 
     let result = manager.process_frame(frame, &env, &storage);
     if let Err(ref e) = result {
@@ -161,7 +200,7 @@ fn process_frame_returns_correct_action_types() {
     header.set_room_id(room_id);
     header.set_sender_id(creator);
     header.set_epoch(0);
-    let frame = Frame::new(header, Bytes::from("test message"));
+    let frame = Frame::new(header, encrypted_payload(0));
 
     let result = manager.process_frame(frame, &env, &storage);
     assert!(result.is_ok());
@@ -218,6 +257,37 @@ fn process_frame_rejects_wrong_epoch() {
     assert!(matches!(result, Err(RoomError::InvalidEpoch { expected: 0, actual: 5 })));
 }
 
+#[test]
+fn process_frame_rejects_malformed_envelope() {
+    let env = TestEnv;
+    let mut manager = RoomManager::new();
+    let storage = MemoryStorage::new();
+
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    let creator = 42;
+    manager.create_room(room_id, creator, &env).unwrap();
+
+    let mut header = FrameHeader::new(Opcode::AppMessage);
+    header.set_room_id(room_id);
+    header.set_sender_id(creator);
+    header.set_epoch(0);
+
+    // Plaintext instead of an encrypted envelope
+    let frame = Frame::new(header, Bytes::from("hello in the clear"));
+    let result = manager.process_frame(frame, &env, &storage);
+    assert!(matches!(result, Err(RoomError::MalformedEnvelope(_))));
+
+    // Envelope claims a different epoch than the header
+    let frame = Frame::new(header, encrypted_payload(3));
+    let result = manager.process_frame(frame, &env, &storage);
+    assert!(matches!(result, Err(RoomError::MalformedEnvelope(_))));
+
+    // Nothing was sequenced
+    let frame = Frame::new(header, encrypted_payload(0));
+    let actions = manager.process_frame(frame, &env, &storage).unwrap();
+    assert!(matches!(actions[0], RoomAction::PersistFrame { log_index: 0, .. }));
+}
+
 /// Test that RoomManager advances epoch after processing a Commit.
 ///
 /// This test exposes a critical wiring bug: RoomManager validates frames
@@ -294,6 +364,30 @@ fn process_commit_advances_epoch() {
         println!("process_frame failed with error: {:?}", e);
     }
     assert!(result.is_ok(), "process_frame should succeed");
+    let actions = result.unwrap();
+
+    // The commit's frame is persisted with the state it produced
+    let commits: Vec<_> = actions
+        .iter()
+        .filter_map(|action| match action {
+            RoomAction::PersistCommit { log_index, state, .. } => Some((*log_index, state.epoch)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(commits, vec![(0, 1)]);
+    assert!(!actions.iter().any(|action| matches!(action, RoomAction::PersistMlsState { .. })));
+
+    // Membership change is reported for external integrations
+    let advanced: Vec<_> = actions
+        .into_iter()
+        .filter_map(|action| match action {
+            RoomAction::EpochAdvanced { room_id, epoch, added, removed, .. } => {
+                Some((room_id, epoch, added, removed))
+            },
+            _ => None,
+        })
+        .collect();
+    assert_eq!(advanced, vec![(room_id, 1, vec![new_member_id], vec![])]);
 
     // CRITICAL ORACLE: Epoch should advance to 1 after processing the commit
     assert_eq!(
@@ -327,7 +421,7 @@ fn process_commit_advances_epoch() {
     header.set_epoch(1); // New epoch after commit
 
     // Create the frame first so payload_size is set correctly
-    let msg_frame = Frame::new(header, Bytes::from("message at epoch 1"));
+    let msg_frame = Frame::new(header, encrypted_payload(1));
 
     // Sign the frame header (application messages require signature validation)
     // Use the centralized signing data method to get the bytes that should be
@@ -349,6 +443,180 @@ fn process_commit_advances_epoch() {
     );
 }
 
+/// Sign `frame`'s header as `signing_key`.
+fn sign(frame: Frame, signing_key: &SigningKey) -> Frame {
+    let signature = signing_key.sign(&frame.header.signing_data());
+    let mut header = frame.header;
+    header.set_signature(signature.to_bytes());
+    Frame::new(header, frame.payload)
+}
+
+#[test]
+fn add_members_rejects_commit_past_member_limit() {
+    let env = TestEnv;
+    let mut manager = RoomManager::with_max_members(Some(1));
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    manager.create_room(room_id, 42, &env).unwrap();
+
+    let (key_package, _hash, _pending) =
+        lockframe_core::mls::MlsGroup::generate_key_package(env.clone(), 100).unwrap();
+
+    let result = manager.add_members(room_id, &[key_package]);
+    assert!(matches!(result, Err(RoomError::RoomFull { max_members: 1, member_count: 2, .. })));
+    assert_eq!(manager.epoch(room_id), Some(0));
+}
+
+/// A member's commit that would overflow the room is rejected and the room
+/// stays at its epoch.
+#[test]
+fn peer_commit_past_member_limit_rejected_before_sequencing() {
+    use lockframe_core::mls::{MlsAction, MlsGroup};
+
+    let env = TestEnv;
+    let mut manager = RoomManager::with_max_members(Some(2));
+    let storage = MemoryStorage::new();
+
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    let creator = 42;
+    let member = 100;
+    manager.create_room(room_id, creator, &env).unwrap();
+
+    let creator_key = SigningKey::generate(&mut rand::thread_rng());
+    let member_key = SigningKey::generate(&mut rand::thread_rng());
+    let keys = HashMap::from([(creator, creator_key.verifying_key().to_bytes())]);
+    let state = MlsGroupState::with_keys(room_id, 0, [0u8; 32], vec![creator], keys, vec![]);
+    storage.store_mls_state(room_id, &state).unwrap();
+
+    // The room fills up to its limit
+    let (key_package, _hash, pending) =
+        MlsGroup::generate_key_package(env.clone(), member).unwrap();
+    let add_actions = manager.add_members(room_id, &[key_package]).unwrap();
+    let mut welcome = None;
+    let mut commit = None;
+    for action in add_actions {
+        match action {
+            MlsAction::SendCommit(frame) => commit = Some(frame),
+            MlsAction::SendWelcome { frame, .. } => welcome = Some(frame),
+            _ => {},
+        }
+    }
+    let commit = commit.expect("add produces a commit");
+    let mut header = commit.header;
+    header.set_room_id(room_id);
+    header.set_sender_id(creator);
+    header.set_epoch(0);
+    let commit = sign(Frame::new(header, commit.payload), &creator_key);
+    manager.process_frame(commit, &env, &storage).unwrap();
+    assert_eq!(manager.epoch(room_id), Some(1));
+
+    let keys = HashMap::from([
+        (creator, creator_key.verifying_key().to_bytes()),
+        (member, member_key.verifying_key().to_bytes()),
+    ]);
+    let state =
+        MlsGroupState::with_keys(room_id, 1, [0u8; 32], vec![creator, member], keys, vec![]);
+    storage.store_mls_state(room_id, &state).unwrap();
+
+    // The new member tries to add a third
+    let welcome = welcome.expect("add produces a welcome");
+    let (mut group, _) =
+        MlsGroup::join_from_welcome(room_id, member, &welcome.payload, pending).unwrap();
+    let (key_package, _hash, _pending) = MlsGroup::generate_key_package(env.clone(), 200).unwrap();
+    let commit = group
+        .add_members_from_bytes(&[key_package])
+        .unwrap()
+        .into_iter()
+        .find_map(|action| match action {
+            MlsAction::SendCommit(frame) => Some(frame),
+            _ => None,
+        })
+        .expect("add produces a commit");
+    let mut header = commit.header;
+    header.set_room_id(room_id);
+    header.set_sender_id(member);
+    header.set_epoch(1);
+    let commit = sign(Frame::new(header, commit.payload), &member_key);
+
+    let result = manager.process_frame(commit, &env, &storage);
+    assert!(
+        matches!(result, Err(RoomError::RoomFull { max_members: 2, member_count: 3, .. })),
+        "got {result:?}"
+    );
+    assert_eq!(manager.epoch(room_id), Some(1));
+}
+
+/// A member's TTL change takes effect with the commit that sets it in the
+/// group context.
+#[test]
+fn message_ttl_changes_with_its_commit() {
+    use lockframe_core::mls::{MlsAction, MlsGroup};
+
+    let env = TestEnv;
+    let mut manager = RoomManager::new();
+    let storage = MemoryStorage::new();
+
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    let creator = 42;
+    let member = 100;
+    manager.create_room(room_id, creator, &env).unwrap();
+
+    let creator_key = SigningKey::generate(&mut rand::thread_rng());
+    let member_key = SigningKey::generate(&mut rand::thread_rng());
+    let keys = HashMap::from([(creator, creator_key.verifying_key().to_bytes())]);
+    let state = MlsGroupState::with_keys(room_id, 0, [0u8; 32], vec![creator], keys, vec![]);
+    storage.store_mls_state(room_id, &state).unwrap();
+
+    let (key_package, _hash, pending) =
+        MlsGroup::generate_key_package(env.clone(), member).unwrap();
+    let mut welcome = None;
+    let mut commit = None;
+    for action in manager.add_members(room_id, &[key_package]).unwrap() {
+        match action {
+            MlsAction::SendCommit(frame) => commit = Some(frame),
+            MlsAction::SendWelcome { frame, .. } => welcome = Some(frame),
+            _ => {},
+        }
+    }
+    let commit = commit.expect("add produces a commit");
+    let mut header = commit.header;
+    header.set_room_id(room_id);
+    header.set_sender_id(creator);
+    header.set_epoch(0);
+    let commit = sign(Frame::new(header, commit.payload), &creator_key);
+    persist(&storage, &manager.process_frame(commit, &env, &storage).unwrap());
+    assert_eq!(manager.message_ttl(room_id), None);
+
+    let keys = HashMap::from([
+        (creator, creator_key.verifying_key().to_bytes()),
+        (member, member_key.verifying_key().to_bytes()),
+    ]);
+    let state =
+        MlsGroupState::with_keys(room_id, 1, [0u8; 32], vec![creator, member], keys, vec![]);
+    storage.store_mls_state(room_id, &state).unwrap();
+
+    let welcome = welcome.expect("add produces a welcome");
+    let (mut group, _) =
+        MlsGroup::join_from_welcome(room_id, member, &welcome.payload, pending).unwrap();
+    let commit = group
+        .set_message_ttl(Some(Duration::from_secs(3600)))
+        .unwrap()
+        .into_iter()
+        .find_map(|action| match action {
+            MlsAction::SendCommit(frame) => Some(frame),
+            _ => None,
+        })
+        .expect("a TTL change produces a commit");
+    let mut header = FrameHeader::new(Opcode::Commit);
+    header.set_room_id(room_id);
+    header.set_sender_id(member);
+    header.set_epoch(1);
+    let commit = sign(Frame::new(header, commit.payload), &member_key);
+
+    persist(&storage, &manager.process_frame(commit, &env, &storage).unwrap());
+    assert_eq!(manager.epoch(room_id), Some(2));
+    assert_eq!(manager.message_ttl(room_id), Some(Duration::from_secs(3600)));
+}
+
 /// Test that handle_sync_request loads frames from storage and returns them.
 #[test]
 fn handle_sync_request_returns_stored_frames() {
@@ -375,7 +643,7 @@ fn handle_sync_request_returns_stored_frames() {
     }
 
     // Request sync from index 0
-    let result = manager.handle_sync_request(room_id, requester, 0, 10, &env, &storage);
+    let result = manager.handle_sync_request(room_id, requester, &full_sync(0, 10), &env, &storage);
     assert!(result.is_ok());
 
     let action = result.unwrap();
@@ -423,7 +691,7 @@ fn handle_sync_request_paginates_with_limit() {
     }
 
     // Request sync with limit of 3
-    let result = manager.handle_sync_request(room_id, 100, 0, 3, &env, &storage);
+    let result = manager.handle_sync_request(room_id, 100, &full_sync(0, 3), &env, &storage);
     assert!(result.is_ok());
 
     let action = result.unwrap();
@@ -436,7 +704,7 @@ fn handle_sync_request_paginates_with_limit() {
     }
 
     // Request next batch starting from index 3
-    let result = manager.handle_sync_request(room_id, 100, 3, 3, &env, &storage);
+    let result = manager.handle_sync_request(room_id, 100, &full_sync(3, 3), &env, &storage);
     assert!(result.is_ok());
 
     let action = result.unwrap();
@@ -449,21 +717,401 @@ fn handle_sync_request_paginates_with_limit() {
     }
 }
 
+/// Test that an epoch-changes sync skips app messages and reports where it
+/// stopped.
+#[test]
+fn handle_sync_request_epoch_changes_skips_app_messages() {
+    let env = TestEnv;
+    let mut manager = RoomManager::new();
+    let storage = MemoryStorage::new();
+
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    let creator = 42;
+
+    manager.create_room(room_id, creator, &env).unwrap();
+
+    // A commit every fourth frame
+    for i in 0..12 {
+        let opcode = if i % 4 == 3 { Opcode::Commit } else { Opcode::AppMessage };
+        let mut header = FrameHeader::new(opcode);
+        header.set_room_id(room_id);
+        header.set_sender_id(creator);
+        header.set_log_index(i);
+        let frame = Frame::new(header, Bytes::from(format!("frame {i}")));
+        storage.store_frame(room_id, i, &frame).unwrap();
+    }
+
+    let request = SyncRequest { from_log_index: 0, limit: 2, mode: SyncMode::EpochChanges };
+    let action = manager.handle_sync_request(room_id, 100, &request, &env, &storage).unwrap();
+    let RoomAction::SendSyncResponse { frames, has_more, mode, next_log_index, .. } = action else {
+        panic!("Expected SendSyncResponse action");
+    };
+    let indices: Vec<u64> =
+        frames.iter().map(|bytes| Frame::decode(bytes).unwrap().header.log_index()).collect();
+    assert_eq!(indices, vec![3, 7]);
+    assert_eq!(mode, SyncMode::EpochChanges);
+    assert_eq!(next_log_index, 8);
+    assert!(has_more);
+
+    // The tail has one more commit, then the scan runs off the end
+    let request = SyncRequest { from_log_index: 8, ..request };
+    let action = manager.handle_sync_request(room_id, 100, &request, &env, &storage).unwrap();
+    let RoomAction::SendSyncResponse { frames, has_more, next_log_index, .. } = action else {
+        panic!("Expected SendSyncResponse action");
+    };
+    assert_eq!(frames.len(), 1);
+    assert_eq!(next_log_index, 12);
+    assert!(!has_more);
+
+    // A scan bounded by the requester's sync budget stops short
+    let request = SyncRequest { from_log_index: 0, ..request };
+    let action =
+        manager.handle_sync_request_within(room_id, 100, &request, 5, &env, &storage).unwrap();
+    let RoomAction::SendSyncResponse { frames, has_more, next_log_index, .. } = action else {
+        panic!("Expected SendSyncResponse action");
+    };
+    assert_eq!(frames.len(), 1);
+    assert_eq!(next_log_index, 5);
+    assert!(has_more);
+}
+
 /// Test that handle_sync_request returns error for unknown room.
 #[test]
 fn handle_sync_request_unknown_room_fails() {
     let env = TestEnv;
-    let manager = RoomManager::<TestEnv>::new();
+    let mut manager = RoomManager::<TestEnv>::new();
     let storage = MemoryStorage::new();
 
     let result = manager.handle_sync_request(
         0x9999_9999_9999_9999_9999_9999_9999_9999,
         100,
-        0,
-        10,
+        &full_sync(0, 10),
         &env,
         &storage,
     );
 
     assert!(matches!(result, Err(RoomError::RoomNotFound(_))));
 }
+
+/// Test that a room whose log fails a checksum is refused until a scrub passes.
+#[test]
+fn corrupted_log_refused_until_scrubbed() {
+    let env = TestEnv;
+    let mut manager = RoomManager::new();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lockframe.db");
+    let storage = SqliteStorage::open(&path).unwrap();
+
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    manager.create_room(room_id, 42, &env).unwrap();
+    for i in 0..3 {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_log_index(i);
+        storage.store_frame(room_id, i, &Frame::new(header, Bytes::from("payload"))).unwrap();
+    }
+    assert_eq!(manager.scrub(room_id, &storage).unwrap(), 3);
+
+    // Damage frame 1 behind the server's back
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let original: Vec<u8> = conn
+        .query_row("SELECT frame FROM frames WHERE log_index = 1", [], |row| row.get(0))
+        .unwrap();
+    let mut damaged = original.clone();
+    *damaged.last_mut().unwrap() ^= 1;
+    conn.execute("UPDATE frames SET frame = ?1 WHERE log_index = 1", [&damaged]).unwrap();
+
+    let result = manager.handle_sync_request(room_id, 100, &full_sync(0, 10), &env, &storage);
+    assert!(matches!(result, Err(RoomError::Corrupted { log_index: 1, .. })));
+    assert_eq!(manager.corrupted_at(room_id), Some(1));
+
+    // Reads that would skip the damaged frame are refused too
+    let result = manager.handle_sync_request(room_id, 100, &full_sync(2, 10), &env, &storage);
+    assert!(matches!(result, Err(RoomError::Corrupted { log_index: 1, .. })));
+
+    // Once repaired, a passing scrub puts the room back in service
+    conn.execute("UPDATE frames SET frame = ?1 WHERE log_index = 1", [&original]).unwrap();
+    assert_eq!(manager.scrub(room_id, &storage).unwrap(), 3);
+    assert_eq!(manager.corrupted_at(room_id), None);
+    let action = manager.handle_sync_request(room_id, 100, &full_sync(0, 10), &env, &storage);
+    assert!(matches!(action, Ok(RoomAction::SendSyncResponse { .. })));
+}
+
+/// Test that proofs from `handle_proof_request` verify against the stored log.
+#[test]
+fn handle_proof_request_proves_stored_frames() {
+    use lockframe_core::merkle::{leaf_hash, verify_consistency, verify_inclusion};
+    use lockframe_proto::payloads::session::ProofRequest;
+
+    let env = TestEnv;
+    let mut manager = RoomManager::new();
+    let storage = MemoryStorage::new();
+
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    let creator = 42;
+
+    manager.create_room(room_id, creator, &env).unwrap();
+
+    let mut frames = Vec::new();
+    for i in 0..5 {
+        let mut header = FrameHeader::new(Opcode::AppMessage);
+        header.set_room_id(room_id);
+        header.set_sender_id(creator);
+        header.set_log_index(i);
+        header.set_epoch(0);
+        let frame = Frame::new(header, Bytes::from(format!("message {i}")));
+        storage.store_frame(room_id, i, &frame).unwrap();
+        frames.push(frame);
+    }
+
+    let request = ProofRequest::Inclusion { leaf_index: 2, tree_size: None };
+    let Ok(RoomAction::SendProofResponse { response: inclusion, .. }) =
+        manager.handle_proof_request(room_id, 100, request, &env, &storage)
+    else {
+        panic!("Expected SendProofResponse action");
+    };
+    assert_eq!(inclusion.tree_size, 5);
+    assert!(verify_inclusion(
+        &leaf_hash(&frames[2]),
+        2,
+        inclusion.tree_size,
+        &inclusion.path,
+        &inclusion.root
+    ));
+
+    let request = ProofRequest::Inclusion { leaf_index: 1, tree_size: Some(3) };
+    let Ok(RoomAction::SendProofResponse { response: old, .. }) =
+        manager.handle_proof_request(room_id, 100, request, &env, &storage)
+    else {
+        panic!("Expected SendProofResponse action");
+    };
+
+    let request = ProofRequest::Consistency { old_size: 3, new_size: None };
+    let Ok(RoomAction::SendProofResponse { response: consistency, .. }) =
+        manager.handle_proof_request(room_id, 100, request, &env, &storage)
+    else {
+        panic!("Expected SendProofResponse action");
+    };
+    assert_eq!(consistency.root, inclusion.root);
+    assert!(verify_consistency(3, 5, &old.root, &consistency.root, &consistency.path));
+
+    let request = ProofRequest::Consistency { old_size: 3, new_size: Some(6) };
+    let result = manager.handle_proof_request(room_id, 100, request, &env, &storage);
+    assert!(matches!(result, Err(RoomError::ProofUnavailable(_))));
+}
+
+/// Write the frames and proposal queues from `actions` to `storage`.
+fn persist(storage: &MemoryStorage, actions: &[RoomAction]) {
+    for action in actions {
+        match action {
+            RoomAction::PersistFrame { room_id, log_index, frame, .. } => {
+                if storage.latest_log_index(*room_id).unwrap() < Some(*log_index) {
+                    storage.store_frame(*room_id, *log_index, frame).unwrap();
+                }
+            },
+            RoomAction::PersistCommit { room_id, log_index, frame, state, .. } => {
+                storage.store_commit(*room_id, *log_index, frame, state).unwrap();
+            },
+            RoomAction::PersistProposal { room_id, proposal, .. } => {
+                storage.store_pending_proposal(*room_id, proposal).unwrap();
+            },
+            RoomAction::ClearProposals { room_id, .. } => {
+                storage.clear_pending_proposals(*room_id).unwrap();
+            },
+            RoomAction::PersistReadMarker { room_id, member_id, up_to_log_index, .. } => {
+                storage.store_read_marker(*room_id, *member_id, *up_to_log_index).unwrap();
+            },
+            _ => {},
+        }
+    }
+}
+
+#[test]
+fn pending_proposals_survive_restart_until_commit() {
+    let env = TestEnv;
+    let storage = MemoryStorage::new();
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    let creator = 42;
+
+    let proposal = |payload: &'static [u8]| {
+        let mut header = FrameHeader::new(Opcode::Proposal);
+        header.set_room_id(room_id);
+        header.set_sender_id(creator);
+        header.set_epoch(0);
+        Frame::new(header, Bytes::from_static(payload))
+    };
+
+    // Two proposals are queued, then the server goes down before a commit
+    {
+        let mut manager = RoomManager::new();
+        manager.create_room(room_id, creator, &env).unwrap();
+        for payload in [&b"first"[..], b"second"] {
+            let actions = manager.process_frame(proposal(payload), &env, &storage).unwrap();
+            persist(&storage, &actions);
+        }
+        assert_eq!(manager.pending_proposals(room_id, &storage).unwrap().len(), 2);
+    }
+
+    // The restarted server picks the queue back up from storage
+    let mut manager = RoomManager::new();
+    manager.create_room(room_id, creator, &env).unwrap();
+    let pending = manager.pending_proposals(room_id, &storage).unwrap();
+    let indices: Vec<_> = pending.iter().map(|frame| frame.header.log_index()).collect();
+    assert_eq!(indices, vec![0, 1]);
+    assert_eq!(pending[1].payload, Bytes::from_static(b"second"));
+
+    // A commit covers the queue and clears it, in memory and in storage
+    let (key_package, _hash, _pending) =
+        lockframe_core::mls::MlsGroup::generate_key_package(env.clone(), 100).unwrap();
+    let add_actions = manager.add_members(room_id, &[key_package]).unwrap();
+    let commit = add_actions
+        .iter()
+        .find_map(|action| match action {
+            lockframe_core::mls::MlsAction::SendCommit(frame) => Some(frame.clone()),
+            _ => None,
+        })
+        .unwrap();
+    let mut header = commit.header;
+    header.set_room_id(room_id);
+    header.set_sender_id(creator);
+    header.set_epoch(0);
+
+    let actions =
+        manager.process_frame(Frame::new(header, commit.payload), &env, &storage).unwrap();
+    assert!(actions.iter().any(|action| matches!(action, RoomAction::ClearProposals { .. })));
+    persist(&storage, &actions);
+
+    assert!(manager.pending_proposals(room_id, &storage).unwrap().is_empty());
+    assert!(storage.load_pending_proposals(room_id).unwrap().is_empty());
+}
+
+#[test]
+fn proposal_queue_is_bounded_and_outlives_compaction() {
+    let env = TestEnv;
+    let storage = MemoryStorage::new();
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    let creator = 42;
+
+    let proposal = || {
+        let mut header = FrameHeader::new(Opcode::Proposal);
+        header.set_room_id(room_id);
+        header.set_sender_id(creator);
+        header.set_epoch(0);
+        Frame::new(header, Bytes::from_static(b"proposal"))
+    };
+
+    let mut manager = RoomManager::new();
+    manager.create_room(room_id, creator, &env).unwrap();
+    for _ in 0..MAX_PENDING_PROPOSALS {
+        let actions = manager.process_frame(proposal(), &env, &storage).unwrap();
+        persist(&storage, &actions);
+    }
+    assert!(matches!(
+        manager.process_frame(proposal(), &env, &storage),
+        Err(RoomError::TooManyProposals { limit: MAX_PENDING_PROPOSALS, .. })
+    ));
+
+    // Compaction drops the proposals from the log, but a sync over the
+    // dropped range still serves them ahead of the retained frames
+    let last = MAX_PENDING_PROPOSALS as u64 - 1;
+    storage.snapshot(room_id, last - 1).unwrap();
+    storage.compact(room_id).unwrap();
+
+    let request = SyncRequest { from_log_index: 0, limit: 10, mode: SyncMode::Full };
+    let action = manager.handle_sync_request(room_id, creator, &request, &env, &storage).unwrap();
+    let RoomAction::SendSyncResponse { frames, .. } = action else {
+        panic!("expected sync response, got {action:?}");
+    };
+    assert_eq!(frames.len(), MAX_PENDING_PROPOSALS);
+    let indices: Vec<u64> =
+        frames.iter().map(|bytes| Frame::decode(bytes).unwrap().header.log_index()).collect();
+    assert_eq!(indices, (0..=last).collect::<Vec<_>>());
+}
+
+#[test]
+fn read_markers_only_advance_and_survive_restart() {
+    let env = TestEnv;
+    let storage = MemoryStorage::new();
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    let creator = 42;
+
+    let frame = |opcode, payload: Bytes| {
+        let mut header = FrameHeader::new(opcode);
+        header.set_room_id(room_id);
+        header.set_sender_id(creator);
+        header.set_epoch(0);
+        Frame::new(header, payload)
+    };
+    let receipt = |room_id, up_to_log_index| {
+        let payload = Payload::ReadReceipt(ReadReceipt { room_id, up_to_log_index });
+        frame(
+            Opcode::ReadReceipt,
+            payload.into_frame(FrameHeader::new(Opcode::ReadReceipt)).unwrap().payload,
+        )
+    };
+
+    {
+        let mut manager = RoomManager::new();
+        manager.create_room(room_id, creator, &env).unwrap();
+        for generation in 0..2 {
+            let message = frame(Opcode::AppMessage, encrypted_payload_at(0, generation));
+            let actions = manager.process_frame(message, &env, &storage).unwrap();
+            persist(&storage, &actions);
+        }
+
+        // Receipts can't point past the log or at another room
+        let result = manager.process_frame(receipt(room_id, 2), &env, &storage);
+        assert!(matches!(result, Err(RoomError::InvalidReadReceipt(_))));
+        let result = manager.process_frame(receipt(room_id + 1, 0), &env, &storage);
+        assert!(matches!(result, Err(RoomError::InvalidReadReceipt(_))));
+
+        let actions = manager.process_frame(receipt(room_id, 1), &env, &storage).unwrap();
+        assert!(
+            actions
+                .iter()
+                .any(|action| matches!(action, RoomAction::PersistFrame { log_index: 2, .. }))
+        );
+        persist(&storage, &actions);
+
+        // An older receipt is sequenced but leaves the marker in place
+        let actions = manager.process_frame(receipt(room_id, 0), &env, &storage).unwrap();
+        assert!(
+            !actions.iter().any(|action| matches!(action, RoomAction::PersistReadMarker { .. }))
+        );
+        persist(&storage, &actions);
+        assert_eq!(manager.read_markers(room_id, &storage).unwrap().get(&creator), Some(&1));
+    }
+
+    let mut manager = RoomManager::new();
+    manager.create_room(room_id, creator, &env).unwrap();
+    let markers = manager.read_markers(room_id, &storage).unwrap();
+    assert_eq!(markers.iter().collect::<Vec<_>>(), vec![(&creator, &1)]);
+}
+
+#[test]
+fn resent_message_is_redelivered_not_sequenced_again() {
+    let env = TestEnv;
+    let storage = MemoryStorage::new();
+    let mut manager = RoomManager::new();
+    let room_id = 0x1234_5678_90ab_cdef_1234_5678_90ab_cdef;
+    let creator = 42;
+    manager.create_room(room_id, creator, &env).unwrap();
+
+    let mut header = FrameHeader::new(Opcode::AppMessage);
+    header.set_room_id(room_id);
+    header.set_sender_id(creator);
+    let message = Frame::new(header, encrypted_payload(0));
+
+    let actions = manager.process_frame(message.clone(), &env, &storage).unwrap();
+    persist(&storage, &actions);
+    assert_eq!(storage.latest_log_index(room_id).unwrap(), Some(0));
+
+    // The sender lost its connection before the broadcast reached it
+    let actions = manager.process_frame(message, &env, &storage).unwrap();
+    let [RoomAction::Redeliver { sender_id, frame, .. }] = actions.as_slice() else {
+        panic!("expected redelivery, got {actions:?}");
+    };
+    assert_eq!(*sender_id, creator);
+    assert_eq!(frame.header.log_index(), 0);
+    assert_eq!(storage.latest_log_index(room_id).unwrap(), Some(0));
+}
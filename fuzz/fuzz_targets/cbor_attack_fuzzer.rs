@@ -81,6 +81,11 @@ fuzz_target!(|attack: CborAttack| {
                 Opcode::HelloReply,
                 Opcode::Ping,
                 Opcode::Pong,
+                Opcode::Heartbeat,
+                Opcode::HeartbeatAck,
+                Opcode::Checkpoint,
+                Opcode::ProofRequest,
+                Opcode::ProofResponse,
                 Opcode::Goodbye,
                 Opcode::Error,
                 Opcode::AppMessage,
@@ -85,6 +85,7 @@ fuzz_target!(|input: FuzzInput| {
         handshake_timeout: Duration::from_secs(5),
         idle_timeout: Duration::from_secs(10),
         heartbeat_interval: Duration::from_secs(3),
+        ..ConnectionConfig::default()
     };
 
     let initial_time = FuzzInstant(Duration::from_secs(input.initial_time_secs as u64));
@@ -214,6 +215,7 @@ fn create_frame_from_fuzzed(fuzzed: &FuzzedFrame) -> Frame {
                 session_id: *session_id,
                 capabilities: vec![],
                 challenge: None,
+                time_sync: None,
             });
             reply
                 .into_frame(FrameHeader::new(Opcode::HelloReply))
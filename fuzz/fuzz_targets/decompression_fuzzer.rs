@@ -0,0 +1,68 @@
+//! Fuzz target for zstd payload decompression
+//!
+//! Compressed payloads come from peers, so the decompressor must hold its
+//! limits against hostile input.
+//!
+//! # Strategy
+//!
+//! - Random bytes: Arbitrary data fed to the decompressor (malformed frames)
+//! - Bombs: Highly compressible data decompressed under a smaller limit
+//! - Roundtrip: Arbitrary data compressed and decompressed under its own size
+//!
+//! # Invariants
+//!
+//! - Output never exceeds the caller's limit
+//! - Data larger than the limit is rejected, not truncated
+//! - Compress/decompress roundtrip returns the input
+//! - NEVER panic on malformed input
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use lockframe_proto::{compression, ProtocolError};
+
+#[derive(Debug, Clone, Arbitrary)]
+enum DecompressionAttack {
+    RandomBytes { bytes: Vec<u8>, max_size: u16 },
+    Bomb { byte: u8, len_exponent: u8, max_size: u16 },
+    Roundtrip { data: Vec<u8> },
+}
+
+fuzz_target!(|attack: DecompressionAttack| {
+    match attack {
+        DecompressionAttack::RandomBytes { bytes, max_size } => {
+            let max_size = usize::from(max_size);
+            if let Ok(output) = compression::decompress(&bytes, max_size) {
+                assert!(output.len() <= max_size, "decompressed past the limit");
+            }
+        }
+
+        DecompressionAttack::Bomb { byte, len_exponent, max_size } => {
+            // Up to 16 MiB of one byte, which compresses to almost nothing
+            let len = 1usize << (len_exponent % 25);
+            let max_size = usize::from(max_size);
+            let Ok(compressed) = compression::compress(&vec![byte; len]) else {
+                return;
+            };
+
+            match compression::decompress(&compressed, max_size) {
+                Ok(output) => {
+                    assert!(len <= max_size, "oversized output accepted");
+                    assert_eq!(output.len(), len);
+                }
+                Err(ProtocolError::PayloadTooLarge { .. }) => {
+                    assert!(len > max_size, "output within the limit rejected");
+                }
+                Err(e) => panic!("valid zstd rejected: {e}"),
+            }
+        }
+
+        DecompressionAttack::Roundtrip { data } => {
+            let compressed = compression::compress(&data).expect("compression failed");
+            let output = compression::decompress(&compressed, data.len())
+                .expect("roundtrip decompression failed");
+            assert_eq!(output, data);
+        }
+    }
+});
@@ -0,0 +1,101 @@
+//! Fuzz target for the ratchet's generation jump bound
+//!
+//! A message names the generation it was encrypted at and the receiver's
+//! ratchet steps forward to it, so the sender picks how much work the receiver
+//! does. The jump bound must keep that work small no matter the generation.
+//!
+//! # Strategy
+//!
+//! - Ratchets resumed anywhere, including next to `u32::MAX`
+//! - Targets anywhere: behind, just ahead, at the bound, far past it
+//! - Bounds changed between messages, including zero
+//!
+//! # Invariants
+//!
+//! - advance_to never panics
+//! - No call skips more than the bound, whatever generation it names
+//! - A target past the bound is rejected with `GenerationJumpTooLarge` and
+//!   leaves the ratchet where it was
+//! - A target behind the ratchet is rejected and leaves it where it was
+//! - A target within the bound returns its key and moves just past it
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use lockframe_crypto::{SenderKeyError, SymmetricRatchet};
+
+#[derive(Debug, Clone, Arbitrary)]
+struct JumpScenario {
+    /// Chain key the ratchet resumes from
+    chain_key: [u8; 32],
+    /// Generation the ratchet resumes at
+    start: u32,
+    /// Initial bound (small, so accepted jumps stay cheap)
+    max_jump: u16,
+    /// Messages arriving, or the bound changing
+    operations: Vec<JumpOperation>,
+}
+
+#[derive(Debug, Clone, Arbitrary)]
+enum JumpOperation {
+    /// Message at an absolute generation, as a forged header would claim
+    Absolute(u32),
+    /// Message a few generations ahead of the ratchet
+    Ahead(u16),
+    /// Change the bound
+    SetMaxJump(u16),
+}
+
+fuzz_target!(|scenario: JumpScenario| {
+    let mut ratchet = SymmetricRatchet::restore(scenario.chain_key, scenario.start);
+    ratchet.set_max_generation_jump(u32::from(scenario.max_jump));
+
+    for op in scenario.operations {
+        let target = match op {
+            JumpOperation::Absolute(target) => target,
+            JumpOperation::Ahead(ahead) => ratchet.generation().saturating_add(u32::from(ahead)),
+            JumpOperation::SetMaxJump(max) => {
+                ratchet.set_max_generation_jump(u32::from(max));
+                continue;
+            },
+        };
+
+        let before = ratchet.generation();
+        let max_jump = ratchet.max_generation_jump();
+        let mut skipped = 0u32;
+
+        // INVARIANT 1: advance_to never panics
+        let result = ratchet.advance_to_with(target, |_| skipped += 1);
+
+        // INVARIANT 2: work is bounded no matter the target
+        assert!(skipped <= max_jump, "skipped {skipped} generations past a bound of {max_jump}");
+
+        if target < before {
+            // INVARIANT 3: stale targets are rejected without moving the ratchet
+            assert!(matches!(result, Err(SenderKeyError::RatchetTooFarBehind { .. })));
+            assert_eq!(ratchet.generation(), before);
+        } else if target - before > max_jump {
+            // INVARIANT 4: targets past the bound are rejected without work
+            match result {
+                Err(SenderKeyError::GenerationJumpTooLarge { current, requested, max_jump: max }) => {
+                    assert_eq!(current, before);
+                    assert_eq!(requested, target);
+                    assert_eq!(max, max_jump);
+                },
+                other => panic!("expected GenerationJumpTooLarge, got {:?}", other.err()),
+            }
+            assert_eq!(skipped, 0);
+            assert_eq!(ratchet.generation(), before);
+        } else if target == u32::MAX {
+            // The last generation has no successor to move to
+            assert!(matches!(result, Err(SenderKeyError::GenerationOverflow { .. })));
+        } else {
+            // INVARIANT 5: targets within the bound are reached exactly
+            let key = result.expect("target within the bound must be reached");
+            assert_eq!(key.generation(), target);
+            assert_eq!(skipped, target - before);
+            assert_eq!(ratchet.generation(), target + 1);
+        }
+    }
+});